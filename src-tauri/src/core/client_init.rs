@@ -0,0 +1,124 @@
+//! Guards creation of the shared MongoDB and R2 clients. The background init in setup()
+//! and the frontend's `init_*_client` calls can overlap; without the guard both would
+//! connect, and the later one would replace the client the earlier one stored.
+
+use std::future::Future;
+use std::sync::atomic::{AtomicU64, Ordering};
+use tokio::sync::Mutex;
+
+/// Held while a client is being created. Keeps the error of the last attempt, so the
+/// callers that waited on it get that error instead of trying again straight away.
+#[derive(Debug)]
+pub struct InitLock<E> {
+    last_error: Mutex<Option<E>>,
+    // Attempts finished so far; a caller that sees it move while waiting shares the result
+    finished: AtomicU64,
+}
+
+impl<E> Default for InitLock<E> {
+    fn default() -> Self {
+        Self { last_error: Mutex::new(None), finished: AtomicU64::new(0) }
+    }
+}
+
+/// Returns the client in `slot`, creating it with `connect` when there is none or when
+/// `force` is set. The caller holds `init_lock` until the outcome is stored, so callers
+/// arriving meanwhile wait for it and share its result: the client it stored, or the
+/// error it failed with. Forced re-initializations take the same lock and so run one at
+/// a time, and always connect.
+pub async fn init_client<T, E, F, Fut>(slot: &Mutex<Option<T>>, init_lock: &InitLock<E>, force: bool, connect: F) -> Result<T, E>
+where
+    T: Clone,
+    E: Clone,
+    F: FnOnce() -> Fut,
+    Fut: Future<Output = Result<T, E>>,
+{
+    let arrived_after = init_lock.finished.load(Ordering::SeqCst);
+    let mut last_error = init_lock.last_error.lock().await;
+    if !force {
+        if let Some(client) = slot.lock().await.as_ref() {
+            return Ok(client.clone());
+        }
+        if init_lock.finished.load(Ordering::SeqCst) != arrived_after {
+            if let Some(e) = last_error.as_ref() {
+                return Err(e.clone());
+            }
+        }
+    }
+    let result = connect().await;
+    match &result {
+        Ok(client) => {
+            *slot.lock().await = Some(client.clone());
+            *last_error = None;
+        }
+        Err(e) => *last_error = Some(e.clone()),
+    }
+    init_lock.finished.fetch_add(1, Ordering::SeqCst);
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    #[derive(Default)]
+    struct MockState {
+        client: Mutex<Option<usize>>,
+        init_lock: InitLock<String>,
+        connects: AtomicUsize,
+        fail: std::sync::atomic::AtomicBool,
+    }
+
+    async fn connect(state: &MockState, force: bool) -> Result<usize, String> {
+        init_client(&state.client, &state.init_lock, force, || async {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            let attempt = state.connects.fetch_add(1, Ordering::SeqCst) + 1;
+            if state.fail.load(Ordering::SeqCst) {
+                Err(format!("attempt {} failed", attempt))
+            } else {
+                Ok(attempt)
+            }
+        }).await
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_inits_connect_once() {
+        let state = Arc::new(MockState::default());
+        let handles: Vec<_> = (0..10)
+            .map(|_| {
+                let state = Arc::clone(&state);
+                tokio::spawn(async move { connect(&state, false).await })
+            })
+            .collect();
+        for handle in handles {
+            assert_eq!(handle.await.unwrap(), Ok(1));
+        }
+        assert_eq!(state.connects.load(Ordering::SeqCst), 1);
+
+        assert_eq!(connect(&state, true).await, Ok(2));
+        assert_eq!(*state.client.lock().await, Some(2));
+    }
+
+    #[tokio::test]
+    async fn test_waiters_share_a_failed_attempt() {
+        let state = Arc::new(MockState::default());
+        state.fail.store(true, Ordering::SeqCst);
+        let handles: Vec<_> = (0..10)
+            .map(|_| {
+                let state = Arc::clone(&state);
+                tokio::spawn(async move { connect(&state, false).await })
+            })
+            .collect();
+        for handle in handles {
+            assert_eq!(handle.await.unwrap(), Err("attempt 1 failed".to_string()));
+        }
+        assert_eq!(state.connects.load(Ordering::SeqCst), 1);
+
+        // A caller arriving after the failure tries again
+        state.fail.store(false, Ordering::SeqCst);
+        assert_eq!(connect(&state, false).await, Ok(2));
+    }
+}
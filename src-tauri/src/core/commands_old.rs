@@ -1,206 +1,33 @@
 use ::mongodb::bson::{self, doc}; // Import bson module and doc macro
 // Remove direct Collection import
-use serde::{Deserialize, Serialize};
 use tauri::{command, State};
-use log::{info, error, warn};
-use futures_util::StreamExt; // Add StreamExt for cursor.next()
+use log::{info, error};
 
 use crate::{MongoState, R2State}; // State structs are now in lib.rs root
 // Removed unused imports related to removed functions
-use crate::core::r2::R2Client; // R2Client is in core::r2
+use crate::core::r2::{R2Client, TRACK_KEY_FIELDS}; // R2Client is in core::r2
 use crate::error::CommandError; // Correct path (from lib.rs) - This is the main error enum
+use crate::features::catalog::changes::{record_deletions, CatalogKind};
+use crate::features::catalog::delete_preview::{check_fingerprint, resolve_tracks};
+use crate::features::catalog::storage::id_to_string;
+use crate::core::db_config::{CatalogCollections, CatalogDatabase};
 
-#[derive(Debug, Serialize, Deserialize)]
-pub struct ClearTestDataResponse {
-    pub success: bool,
-    pub message: String,
-}
-
-// REMOVED fetch_all_tracks function
-
-/// Command to clear test data from the database
-/// This is only available in development/test mode, not in production
-#[command]
-pub async fn clear_test_data(
-    mongo_state: State<'_, MongoState>,
-) -> Result<ClearTestDataResponse, String> { // Keep String error type for this specific command for now
-    info!("Clearing test data from database");
-
-    // Get Mongo client from state
-    let mongo_client_lock = mongo_state.client.lock().await;
-    let mongo_client = mongo_client_lock.as_ref()
-        .ok_or("MongoDB client not initialized. Please configure credentials first.")?;
-
-    // First delete tracks with titles containing 'Test Track' or from test albums
-    let tracks_collection: ::mongodb::Collection<bson::Document> = mongo_client.database("music_library").collection("tracks"); // Use fully qualified type
-
-    // Delete tracks with test data
-    let test_track_filter = doc! { // Keep imported doc! macro
-        "$or": [
-            { "title": { "$regex": "Test Track" } },
-            { "title": { "$regex": "Integration Test Track" } }
-        ]
-    };
-
-    let delete_tracks_result = tracks_collection.delete_many(test_track_filter, None).await;
-    let tracks_deleted = match delete_tracks_result {
-        Ok(result) => result.deleted_count,
-        Err(e) => {
-            error!("Failed to delete test tracks: {}", e);
-            return Ok(ClearTestDataResponse {
-                success: false,
-                message: format!("Failed to delete test tracks: {}", e),
-            });
-        }
-    };
-
-    // Delete test albums
-    let albums_collection: ::mongodb::Collection<bson::Document> = mongo_client.database("music_library").collection("albums"); // Use fully qualified type
-    let test_album_filter = doc! { // Keep imported doc! macro
-        "$or": [
-            { "name": "Test Album" },
-            { "name": "Integration Test Album" }
-        ]
-    };
-
-    let delete_albums_result = albums_collection.delete_many(test_album_filter, None).await;
-    let albums_deleted = match delete_albums_result {
-        Ok(result) => result.deleted_count,
-        Err(e) => {
-            error!("Failed to delete test albums: {}", e);
-            return Ok(ClearTestDataResponse {
-                success: false,
-                message: format!("Failed to delete test albums: {}", e),
-            });
-        }
-    };
-
-    info!("Deleted {} test tracks and {} test albums", tracks_deleted, albums_deleted);
-
-    Ok(ClearTestDataResponse {
-        success: true,
-        message: format!("Successfully cleared test data: {} tracks and {} albums deleted",
-            tracks_deleted, albums_deleted),
-    })
-}
-
-/// Command to test MongoDB connectivity and check collection stats
-#[command]
-pub async fn test_mongodb_collections(
-    mongo_state: State<'_, MongoState>,
-) -> Result<String, String> { // Keep String error type for this specific command for now
-    info!("Testing MongoDB collections");
-
-    // Get Mongo client from state
-    let mongo_client_lock = mongo_state.client.lock().await;
-    let mongo_client = match mongo_client_lock.as_ref() {
-        Some(client) => {
-            info!("MongoDB client found in state");
-            client
-        },
-        None => {
-            error!("MongoDB client not initialized");
-            return Err("MongoDB client not initialized. Please configure credentials first.".to_string());
-        }
-    };
-
-    // Create a database reference
-    info!("Creating database reference to 'music_library'");
-    let db = mongo_client.database("music_library");
-
-    // Get collection names
-    let collection_names = match db.list_collection_names(None).await {
-        Ok(names) => {
-            info!("Found collections: {:?}", names);
-            names
-        },
-        Err(e) => {
-            error!("Failed to list collections: {}", e);
-            return Err(format!("Failed to list collections: {}", e));
-        }
-    };
-
-    let mut result = format!("Found {} collections: {:?}\n", collection_names.len(), collection_names);
-
-    // Check tracks collection
-    if collection_names.contains(&"tracks".to_string()) {
-        let tracks_collection: ::mongodb::Collection<bson::Document> = db.collection("tracks"); // Use fully qualified type
-        match tracks_collection.count_documents(None, None).await {
-            Ok(count) => {
-                info!("Tracks collection has {} documents", count);
-                result.push_str(&format!("Tracks collection: {} documents\n", count));
-
-                // Get a sample track if any exist
-                if count > 0 {
-                    match tracks_collection.find_one(None, None).await {
-                        Ok(Some(doc)) => { // doc here is a variable, not the macro
-                            info!("Sample track document: {:?}", doc);
-                            result.push_str(&format!("Sample track fields: {}\n",
-                                doc.keys().map(|k| k.to_string()).collect::<Vec<String>>().join(", ")));
-                        },
-                        Ok(None) => {
-                            warn!("No track found despite count > 0");
-                            result.push_str("Could not retrieve sample track\n");
-                        },
-                        Err(e) => {
-                            error!("Error fetching sample track: {}", e);
-                            result.push_str(&format!("Error fetching sample track: {}\n", e));
-                        }
-                    }
-                }
-            },
-            Err(e) => {
-                error!("Error counting tracks: {}", e);
-                result.push_str(&format!("Error counting tracks: {}\n", e));
-            }
-        }
-    }
-
-    // Check albums collection
-    if collection_names.contains(&"albums".to_string()) {
-        let albums_collection: ::mongodb::Collection<bson::Document> = db.collection("albums"); // Use fully qualified type
-        match albums_collection.count_documents(None, None).await {
-            Ok(count) => {
-                info!("Albums collection has {} documents", count);
-                result.push_str(&format!("Albums collection: {} documents\n", count));
-
-                // Get a sample album if any exist
-                if count > 0 {
-                    match albums_collection.find_one(None, None).await {
-                        Ok(Some(doc)) => { // doc here is a variable, not the macro
-                            info!("Sample album document: {:?}", doc);
-                            result.push_str(&format!("Sample album fields: {}\n",
-                                doc.keys().map(|k| k.to_string()).collect::<Vec<String>>().join(", ")));
-                        },
-                        Ok(None) => {
-                            warn!("No album found despite count > 0");
-                            result.push_str("Could not retrieve sample album\n");
-                        },
-                        Err(e) => {
-                            error!("Error fetching sample album: {}", e);
-                            result.push_str(&format!("Error fetching sample album: {}\n", e));
-                        }
-                    }
-                }
-            },
-            Err(e) => {
-                error!("Error counting albums: {}", e);
-                result.push_str(&format!("Error counting albums: {}\n", e));
-            }
-        }
-    }
-
-    Ok(result)
-}
+// clear_test_data and test_mongodb_collections moved to features::devtools
 
  // REMOVED update_track_metadata command function
 
 // --- Track Deletion Command ---
 
-/// Command to delete tracks from MongoDB and their audio files from R2
+/// Audio paths written by the original upload flow, before the `r2_*_key` fields.
+const LEGACY_URL_FIELDS: [&str; 3] = ["medium_quality_url", "high_quality_url", "original_quality_url"];
+
+/// Command to delete tracks from MongoDB and their audio files from R2.
+/// With `expected_fingerprint` from `preview_delete`, refuses to run if the selection
+/// changed since the preview.
 #[command]
 pub async fn delete_tracks(
    track_ids: Vec<String>, // Expecting a list of track IDs from the frontend
+   expected_fingerprint: Option<String>,
    mongo_state: State<'_, MongoState>,
    r2_state: State<'_, R2State>, // Add R2State
 ) -> Result<(), CommandError> {
@@ -214,69 +41,52 @@ pub async fn delete_tracks(
     })?;
 
     // Create a database reference
-    let db = mongo_client.database("music_library");
-    let tracks_collection = db.collection::<bson::Document>("tracks");
+    let db = mongo_client.catalog_database();
+    let tracks_collection = db.tracks::<bson::Document>();
 
-    // For each track ID, get the track first to obtain file paths
+    // Resolve the tracks the same way preview_delete does, to obtain file paths
+    let tracks = resolve_tracks(&db, &track_ids).await?;
+    check_fingerprint(&tracks, expected_fingerprint.as_deref())?;
     let filter = doc! {
-        "_id": {
-            "$in": track_ids.iter().filter_map(|id| bson::oid::ObjectId::parse_str(id).ok()).collect::<Vec<_>>()
-        }
+        "_id": { "$in": tracks.iter().filter_map(|doc| doc.get("_id").cloned()).collect::<Vec<_>>() }
     };
 
-    // Fetch the tracks to get their audio file paths
-    let mut cursor = tracks_collection.find(filter.clone(), None).await?;
-
-    // Collect R2 paths to delete
-    let mut r2_paths = Vec::new();
-
-    while let Some(result) = cursor.next().await {
-        match result {
-            Ok(doc) => {
-                // Extract audio file paths
-                if let Some(medium_quality) = doc.get_str("medium_quality_url").ok() {
-                    r2_paths.push(medium_quality.to_string());
-                }
-                if let Some(high_quality) = doc.get_str("high_quality_url").ok() {
-                    r2_paths.push(high_quality.to_string());
-                }
-                if let Some(original_quality) = doc.get_str("original_quality_url").ok() {
-                    r2_paths.push(original_quality.to_string());
-                }
-            },
-            Err(e) => {
-                error!("Error fetching track while preparing for deletion: {}", e);
-                // Continue with the rest of the tracks
-            }
-        }
-    }
-
     // Get R2 client from state
     let r2_client_lock = r2_state.client.lock().await;
     let bucket_name_lock = r2_state.bucket_name.lock().await;
 
-    // Delete the files from R2 if there are any paths
-    if !r2_paths.is_empty() && r2_client_lock.is_some() && bucket_name_lock.is_some() {
-        let r2_client = r2_client_lock.as_ref().unwrap();
-        let bucket_name = bucket_name_lock.as_ref().unwrap();
-
-        // Create R2Client wrapper
+    // Delete each track's files from the bucket they were uploaded to
+    if let (Some(r2_client), Some(bucket_name)) = (r2_client_lock.as_ref(), bucket_name_lock.as_ref()) {
         let r2_client = R2Client::new(r2_client.clone(), bucket_name.clone());
 
-        // Delete files from R2 using the new method
-        match r2_client.delete_objects(&r2_paths).await {
-            Ok(_) => {
-                info!("Successfully deleted {} files from R2", r2_paths.len());
-            },
-            Err(e) => {
-                error!("Failed to delete files from R2: {}", e);
-                // Continue with MongoDB deletion even if R2 deletion failed
+        for doc in &tracks {
+            let mut r2_paths: Vec<String> = LEGACY_URL_FIELDS.iter()
+                .chain(TRACK_KEY_FIELDS.iter())
+                .filter_map(|field| doc.get_str(field).ok().filter(|path| !path.is_empty()))
+                .map(String::from)
+                .collect();
+            r2_paths.sort();
+            r2_paths.dedup();
+            if r2_paths.is_empty() {
+                continue;
+            }
+
+            let bucket_client = r2_client.for_track(doc);
+            match bucket_client.delete_objects(&r2_paths).await {
+                Ok(_) => {
+                    info!("Deleted {} files from R2 bucket {}", r2_paths.len(), bucket_client.bucket_name());
+                },
+                Err(e) => {
+                    error!("Failed to delete files from R2 bucket {}: {}", bucket_client.bucket_name(), e);
+                    // Continue with MongoDB deletion even if R2 deletion failed
+                }
             }
         }
     }
 
     // Delete tracks from MongoDB
     let delete_result = tracks_collection.delete_many(filter, None).await?;
+    record_deletions(&db, CatalogKind::Track, tracks.iter().filter_map(|doc| doc.get("_id").and_then(id_to_string)).collect()).await;
 
     info!("Deleted {} tracks from MongoDB", delete_result.deleted_count);
 
@@ -301,8 +111,8 @@ pub async fn replace_track_audio(
     })?;
 
     // Create a database reference
-    let db = mongo_client.database("music_library");
-    let tracks_collection = db.collection::<bson::Document>("tracks");
+    let db = mongo_client.catalog_database();
+    let tracks_collection = db.tracks::<bson::Document>();
 
     // Get the track to obtain current file paths
     let object_id = bson::oid::ObjectId::parse_str(&track_id)
@@ -0,0 +1,140 @@
+//! Names of the catalog database and its track and album collections, so that staging
+//! and production catalogs can live on one cluster, and the cursor batch size used to
+//! read them. They come from the `database` settings and are read when the MongoDB
+//! client is initialized. A change only applies once the client is re-initialized, so a
+//! running client never switches catalogs.
+//!
+//! Call sites get the database and collections through `CatalogDatabase` and
+//! `CatalogCollections` rather than naming them.
+
+use mongodb::bson::Document;
+use mongodb::{Client, Collection, Database};
+use serde::{Deserialize, Serialize};
+use std::sync::RwLock;
+
+pub const DEFAULT_DATABASE_NAME: &str = "music_library";
+pub const DEFAULT_TRACKS_COLLECTION: &str = "tracks";
+pub const DEFAULT_ALBUMS_COLLECTION: &str = "albums";
+pub const DEFAULT_CURSOR_BATCH_SIZE: u32 = 100;
+
+/// Largest cursor batch size accepted; bigger batches only add memory per round trip.
+const MAX_CURSOR_BATCH_SIZE: u32 = 10_000;
+
+/// Longest database name MongoDB accepts.
+const MAX_DATABASE_NAME_LEN: usize = 63;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DatabaseConfig {
+    pub database_name: String,
+    pub tracks_collection: String,
+    pub albums_collection: String,
+    /// Documents fetched per round trip when reading catalog cursors; larger batches
+    /// mean fewer round trips to distant clusters
+    pub cursor_batch_size: u32,
+}
+
+impl Default for DatabaseConfig {
+    fn default() -> Self {
+        Self {
+            database_name: DEFAULT_DATABASE_NAME.to_string(),
+            tracks_collection: DEFAULT_TRACKS_COLLECTION.to_string(),
+            albums_collection: DEFAULT_ALBUMS_COLLECTION.to_string(),
+            cursor_batch_size: DEFAULT_CURSOR_BATCH_SIZE,
+        }
+    }
+}
+
+impl DatabaseConfig {
+    /// Checks the names against MongoDB's naming rules.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.database_name.is_empty() || self.database_name.len() > MAX_DATABASE_NAME_LEN {
+            return Err(format!("database.database_name must be 1 to {} characters", MAX_DATABASE_NAME_LEN));
+        }
+        if let Some(c) = self.database_name.chars().find(|c| matches!(c, '/' | '\\' | '.' | ' ' | '"' | '$' | '\0')) {
+            return Err(format!("database.database_name must not contain '{}'", c));
+        }
+        for (field, name) in [("tracks_collection", &self.tracks_collection), ("albums_collection", &self.albums_collection)] {
+            if name.is_empty() || name.starts_with("system.") || name.contains('$') || name.contains('\0') {
+                return Err(format!("database.{} must be a non-empty collection name without '$' or a 'system.' prefix", field));
+            }
+        }
+        if self.tracks_collection == self.albums_collection {
+            return Err("database.tracks_collection and database.albums_collection must differ".to_string());
+        }
+        if !(1..=MAX_CURSOR_BATCH_SIZE).contains(&self.cursor_batch_size) {
+            return Err(format!("database.cursor_batch_size must be between 1 and {}", MAX_CURSOR_BATCH_SIZE));
+        }
+        Ok(())
+    }
+}
+
+/// The config the current MongoDB client was initialized with; `None` means the defaults.
+static ACTIVE: RwLock<Option<DatabaseConfig>> = RwLock::new(None);
+
+/// The names in use: those the client was last initialized with, or the defaults.
+pub fn active() -> DatabaseConfig {
+    ACTIVE.read().unwrap_or_else(|e| e.into_inner()).clone().unwrap_or_default()
+}
+
+/// Switches to `config`. Only called while (re)initializing the MongoDB client.
+pub fn activate(config: DatabaseConfig) {
+    *ACTIVE.write().unwrap_or_else(|e| e.into_inner()) = Some(config);
+}
+
+/// Batch size for cursors over the catalog collections.
+pub fn cursor_batch_size() -> u32 {
+    active().cursor_batch_size
+}
+
+/// The configured catalog database of a client.
+pub trait CatalogDatabase {
+    fn catalog_database(&self) -> Database;
+}
+
+impl CatalogDatabase for Client {
+    fn catalog_database(&self) -> Database {
+        self.database(&active().database_name)
+    }
+}
+
+/// The configured track and album collections of the catalog database.
+pub trait CatalogCollections {
+    fn tracks<T>(&self) -> Collection<T>;
+    fn albums<T>(&self) -> Collection<T>;
+
+    /// Both collections as documents, tracks first.
+    fn catalog_collections(&self) -> (Collection<Document>, Collection<Document>) {
+        (self.tracks(), self.albums())
+    }
+}
+
+impl CatalogCollections for Database {
+    fn tracks<T>(&self) -> Collection<T> {
+        self.collection(&active().tracks_collection)
+    }
+
+    fn albums<T>(&self) -> Collection<T> {
+        self.collection(&active().albums_collection)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate_database_config() {
+        assert!(DatabaseConfig::default().validate().is_ok());
+        let staging = DatabaseConfig { database_name: "music_library_staging".to_string(), ..Default::default() };
+        assert!(staging.validate().is_ok());
+
+        assert!(DatabaseConfig { database_name: "music.library".to_string(), ..Default::default() }.validate().is_err());
+        assert!(DatabaseConfig { database_name: String::new(), ..Default::default() }.validate().is_err());
+        assert!(DatabaseConfig { tracks_collection: "$tracks".to_string(), ..Default::default() }.validate().is_err());
+        assert!(DatabaseConfig { albums_collection: "system.albums".to_string(), ..Default::default() }.validate().is_err());
+        assert!(DatabaseConfig { albums_collection: "tracks".to_string(), ..Default::default() }.validate().is_err());
+        assert!(DatabaseConfig { cursor_batch_size: 0, ..Default::default() }.validate().is_err());
+        assert!(DatabaseConfig { cursor_batch_size: 1000, ..Default::default() }.validate().is_ok());
+    }
+}
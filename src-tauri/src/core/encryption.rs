@@ -0,0 +1,382 @@
+//! Client-side encryption of originals before they are uploaded to R2.
+//!
+//! Files are sealed with AES-256-GCM in 64 KiB chunks, so neither side needs the
+//! whole file in memory. Each chunk's nonce is a random 7-byte prefix chosen per file,
+//! a 4-byte chunk counter and a flag marking the final chunk; reordered, dropped or
+//! appended chunks therefore fail authentication, and a file cut off at a chunk
+//! boundary is detected by the missing final chunk.
+//!
+//! Layout: `PCIENC`, a version byte, the 8-byte key fingerprint, the nonce prefix,
+//! then the sealed chunks. The fingerprint doubles as the key id stored on tracks.
+
+use aes_gcm::aead::{Aead, KeyInit};
+use aes_gcm::{Aes256Gcm, Key, Nonce};
+use mongodb::bson::Document;
+use rand::rngs::OsRng;
+use rand::RngCore;
+use sha2::{Digest, Sha256};
+use std::fs::File;
+use std::io::{BufWriter, Read, Write};
+use std::path::Path;
+use thiserror::Error;
+
+use crate::core::r2::ORIGINAL_KEY_FIELDS;
+use crate::features::credentials::load_originals_key;
+use crate::CommandError;
+
+pub const KEY_LEN: usize = 32;
+
+const MAGIC: &[u8; 6] = b"PCIENC";
+const FORMAT_VERSION: u8 = 1;
+const FINGERPRINT_LEN: usize = 8;
+const NONCE_PREFIX_LEN: usize = 7;
+const HEADER_LEN: usize = MAGIC.len() + 1 + FINGERPRINT_LEN + NONCE_PREFIX_LEN;
+const TAG_LEN: usize = 16;
+
+/// Plaintext bytes per chunk.
+pub const CHUNK_SIZE: usize = 64 * 1024;
+const SEALED_CHUNK_LEN: usize = CHUNK_SIZE + TAG_LEN;
+
+#[derive(Debug, Error)]
+pub enum EncryptionError {
+    #[error("File was encrypted with key {file_key_id}, but the configured key is {configured_key_id}")]
+    WrongKey { file_key_id: String, configured_key_id: String },
+    #[error("Encrypted file is truncated: {0}")]
+    Truncated(String),
+    #[error("Encrypted file is corrupted: {0}")]
+    Corrupted(String),
+    #[error("No encryption key is available: {0}")]
+    KeyUnavailable(String),
+    #[error("I/O error: {0}")]
+    Io(#[from] std::io::Error),
+}
+
+impl From<EncryptionError> for CommandError {
+    fn from(err: EncryptionError) -> Self {
+        match err {
+            EncryptionError::WrongKey { .. } | EncryptionError::KeyUnavailable(_) => CommandError::Configuration(err.to_string()),
+            EncryptionError::Io(e) => e.into(),
+            _ => CommandError::Storage(err.to_string()),
+        }
+    }
+}
+
+/// Generates a new random key.
+pub fn generate_key() -> [u8; KEY_LEN] {
+    let mut key = [0u8; KEY_LEN];
+    OsRng.fill_bytes(&mut key);
+    key
+}
+
+fn fingerprint(key: &[u8; KEY_LEN]) -> [u8; FINGERPRINT_LEN] {
+    let digest = Sha256::digest(key);
+    let mut fingerprint = [0u8; FINGERPRINT_LEN];
+    fingerprint.copy_from_slice(&digest[..FINGERPRINT_LEN]);
+    fingerprint
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Identifies a key without revealing it; stored on tracks as `encryption_key_id`.
+pub fn key_id(key: &[u8; KEY_LEN]) -> String {
+    to_hex(&fingerprint(key))
+}
+
+fn cipher(key: &[u8; KEY_LEN]) -> Aes256Gcm {
+    Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(key))
+}
+
+fn chunk_nonce(prefix: &[u8; NONCE_PREFIX_LEN], counter: u32, last: bool) -> [u8; 12] {
+    let mut nonce = [0u8; 12];
+    nonce[..NONCE_PREFIX_LEN].copy_from_slice(prefix);
+    nonce[NONCE_PREFIX_LEN..11].copy_from_slice(&counter.to_be_bytes());
+    nonce[11] = last as u8;
+    nonce
+}
+
+/// Encrypts a stream fed in pieces of any size. Output is appended to `out`.
+pub struct Encryptor {
+    cipher: Aes256Gcm,
+    header: Option<Vec<u8>>,
+    prefix: [u8; NONCE_PREFIX_LEN],
+    counter: u32,
+    buffer: Vec<u8>,
+}
+
+impl Encryptor {
+    pub fn new(key: &[u8; KEY_LEN]) -> Self {
+        let mut prefix = [0u8; NONCE_PREFIX_LEN];
+        OsRng.fill_bytes(&mut prefix);
+        let mut header = Vec::with_capacity(HEADER_LEN);
+        header.extend_from_slice(MAGIC);
+        header.push(FORMAT_VERSION);
+        header.extend_from_slice(&fingerprint(key));
+        header.extend_from_slice(&prefix);
+        Self { cipher: cipher(key), header: Some(header), prefix, counter: 0, buffer: Vec::with_capacity(SEALED_CHUNK_LEN) }
+    }
+
+    fn seal(&mut self, last: bool, out: &mut Vec<u8>) -> Result<(), EncryptionError> {
+        if let Some(header) = self.header.take() {
+            out.extend_from_slice(&header);
+        }
+        let len = self.buffer.len().min(CHUNK_SIZE);
+        let nonce = chunk_nonce(&self.prefix, self.counter, last);
+        let sealed = self.cipher.encrypt(Nonce::from_slice(&nonce), &self.buffer[..len])
+            .map_err(|_| EncryptionError::Corrupted("chunk encryption failed".to_string()))?;
+        out.extend_from_slice(&sealed);
+        self.buffer.drain(..len);
+        self.counter = self.counter.checked_add(1)
+            .ok_or_else(|| EncryptionError::Corrupted("file has too many chunks to encrypt".to_string()))?;
+        Ok(())
+    }
+
+    pub fn update(&mut self, data: &[u8], out: &mut Vec<u8>) -> Result<(), EncryptionError> {
+        self.buffer.extend_from_slice(data);
+        // A full chunk is only sealed once more data follows; the final chunk is sealed by `finish`
+        while self.buffer.len() > CHUNK_SIZE {
+            self.seal(false, out)?;
+        }
+        Ok(())
+    }
+
+    pub fn finish(mut self, out: &mut Vec<u8>) -> Result<(), EncryptionError> {
+        self.seal(true, out)
+    }
+}
+
+/// Decrypts a stream fed in pieces of any size. Output is appended to `out`.
+pub struct Decryptor {
+    cipher: Aes256Gcm,
+    key_id: String,
+    fingerprint: [u8; FINGERPRINT_LEN],
+    prefix: Option<[u8; NONCE_PREFIX_LEN]>,
+    counter: u32,
+    buffer: Vec<u8>,
+}
+
+impl Decryptor {
+    pub fn new(key: &[u8; KEY_LEN]) -> Self {
+        Self {
+            cipher: cipher(key),
+            key_id: key_id(key),
+            fingerprint: fingerprint(key),
+            prefix: None,
+            counter: 0,
+            buffer: Vec::with_capacity(SEALED_CHUNK_LEN + 1),
+        }
+    }
+
+    fn read_header(&mut self) -> Result<[u8; NONCE_PREFIX_LEN], EncryptionError> {
+        let header: Vec<u8> = self.buffer.drain(..HEADER_LEN).collect();
+        if &header[..MAGIC.len()] != MAGIC {
+            return Err(EncryptionError::Corrupted("not an encrypted file".to_string()));
+        }
+        if header[MAGIC.len()] != FORMAT_VERSION {
+            return Err(EncryptionError::Corrupted(format!("unsupported format version {}", header[MAGIC.len()])));
+        }
+        let file_fingerprint = &header[MAGIC.len() + 1..MAGIC.len() + 1 + FINGERPRINT_LEN];
+        if file_fingerprint != self.fingerprint {
+            return Err(EncryptionError::WrongKey { file_key_id: to_hex(file_fingerprint), configured_key_id: self.key_id.clone() });
+        }
+        let mut prefix = [0u8; NONCE_PREFIX_LEN];
+        prefix.copy_from_slice(&header[HEADER_LEN - NONCE_PREFIX_LEN..]);
+        Ok(prefix)
+    }
+
+    fn open(&self, prefix: &[u8; NONCE_PREFIX_LEN], sealed: &[u8], last: bool) -> Option<Vec<u8>> {
+        let nonce = chunk_nonce(prefix, self.counter, last);
+        self.cipher.decrypt(Nonce::from_slice(&nonce), sealed).ok()
+    }
+
+    pub fn update(&mut self, data: &[u8], out: &mut Vec<u8>) -> Result<(), EncryptionError> {
+        self.buffer.extend_from_slice(data);
+        let prefix = match self.prefix {
+            Some(prefix) => prefix,
+            None if self.buffer.len() < HEADER_LEN => return Ok(()),
+            None => {
+                let prefix = self.read_header()?;
+                self.prefix = Some(prefix);
+                prefix
+            }
+        };
+        // Only a chunk followed by more data can be a non-final chunk
+        while self.buffer.len() > SEALED_CHUNK_LEN {
+            let plain = self.open(&prefix, &self.buffer[..SEALED_CHUNK_LEN], false)
+                .ok_or_else(|| EncryptionError::Corrupted(format!("chunk {} failed authentication", self.counter)))?;
+            out.extend_from_slice(&plain);
+            self.buffer.drain(..SEALED_CHUNK_LEN);
+            self.counter += 1;
+        }
+        Ok(())
+    }
+
+    pub fn finish(self, out: &mut Vec<u8>) -> Result<(), EncryptionError> {
+        let prefix = self.prefix
+            .ok_or_else(|| EncryptionError::Truncated("the header is incomplete".to_string()))?;
+        if self.buffer.len() < TAG_LEN {
+            return Err(EncryptionError::Truncated(format!("chunk {} is incomplete", self.counter)));
+        }
+        match self.open(&prefix, &self.buffer, true) {
+            Some(plain) => {
+                out.extend_from_slice(&plain);
+                Ok(())
+            }
+            // A whole chunk that isn't marked final means the chunks after it are missing
+            None if self.open(&prefix, &self.buffer, false).is_some() => {
+                Err(EncryptionError::Truncated(format!("the file ends after chunk {} without its final chunk", self.counter)))
+            }
+            None => Err(EncryptionError::Corrupted(format!("final chunk {} failed authentication; the file may be cut short", self.counter))),
+        }
+    }
+}
+
+/// Encrypts `input` into `output`, returning the number of bytes written.
+/// Blocking; call it off the async runtime.
+pub fn encrypt_file(input: &Path, output: &Path, key: &[u8; KEY_LEN]) -> Result<u64, EncryptionError> {
+    let mut reader = File::open(input)?;
+    let mut writer = BufWriter::new(File::create(output)?);
+    let mut encryptor = Encryptor::new(key);
+    let mut read_buffer = vec![0u8; CHUNK_SIZE];
+    let mut sealed = Vec::with_capacity(SEALED_CHUNK_LEN * 2);
+    let mut written = 0u64;
+    loop {
+        let read = reader.read(&mut read_buffer)?;
+        if read == 0 {
+            break;
+        }
+        encryptor.update(&read_buffer[..read], &mut sealed)?;
+        writer.write_all(&sealed)?;
+        written += sealed.len() as u64;
+        sealed.clear();
+    }
+    encryptor.finish(&mut sealed)?;
+    writer.write_all(&sealed)?;
+    writer.flush()?;
+    Ok(written + sealed.len() as u64)
+}
+
+pub fn decrypt_bytes(data: &[u8], key: &[u8; KEY_LEN]) -> Result<Vec<u8>, EncryptionError> {
+    let mut decryptor = Decryptor::new(key);
+    let mut plain = Vec::with_capacity(data.len());
+    decryptor.update(data, &mut plain)?;
+    decryptor.finish(&mut plain)?;
+    Ok(plain)
+}
+
+// --- Tracks ---
+
+/// Whether `r2_key` is the track's encrypted archive copy.
+pub fn is_encrypted_copy(track_doc: &Document, r2_key: &str) -> bool {
+    track_doc.get_bool("encrypted").unwrap_or(false)
+        && ORIGINAL_KEY_FIELDS.iter().any(|field| track_doc.get_str(field).ok() == Some(r2_key))
+}
+
+/// The configured key, checked against the key id recorded on the track so a rotated
+/// or replaced key is reported before anything is downloaded.
+pub fn key_for_track(track_doc: &Document) -> Result<[u8; KEY_LEN], EncryptionError> {
+    let key = load_originals_key()
+        .map_err(|e| EncryptionError::KeyUnavailable(e.to_string()))?
+        .ok_or_else(|| EncryptionError::KeyUnavailable("the originals encryption key is not stored on this machine".to_string()))?;
+    match track_doc.get_str("encryption_key_id") {
+        Ok(track_key_id) if track_key_id != key_id(&key) => {
+            Err(EncryptionError::WrongKey { file_key_id: track_key_id.to_string(), configured_key_id: key_id(&key) })
+        }
+        _ => Ok(key),
+    }
+}
+
+/// The key to decrypt `r2_key` with, or `None` if that copy is stored in the clear.
+pub fn decryption_key(track_doc: &Document, r2_key: &str) -> Result<Option<[u8; KEY_LEN]>, EncryptionError> {
+    if is_encrypted_copy(track_doc, r2_key) {
+        key_for_track(track_doc).map(Some)
+    } else {
+        Ok(None)
+    }
+}
+
+/// Decrypts bytes downloaded from `r2_key` if they are the track's encrypted copy.
+pub fn readable_bytes(track_doc: &Document, r2_key: &str, bytes: Vec<u8>) -> Result<Vec<u8>, EncryptionError> {
+    match decryption_key(track_doc, r2_key)? {
+        Some(key) => decrypt_bytes(&bytes, &key),
+        None => Ok(bytes),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mongodb::bson::doc;
+
+    fn encrypt_bytes(data: &[u8], key: &[u8; KEY_LEN], piece_len: usize) -> Vec<u8> {
+        let mut encryptor = Encryptor::new(key);
+        let mut sealed = Vec::new();
+        for piece in data.chunks(piece_len) {
+            encryptor.update(piece, &mut sealed).unwrap();
+        }
+        encryptor.finish(&mut sealed).unwrap();
+        sealed
+    }
+
+    #[test]
+    fn test_round_trip_multi_chunk_file() {
+        let key = generate_key();
+        let data: Vec<u8> = (0..CHUNK_SIZE * 3 + 1234).map(|i| (i * 7 % 251) as u8).collect();
+        let dir = tempfile::tempdir().unwrap();
+        let (input, output) = (dir.path().join("take.wav"), dir.path().join("take.wav.enc"));
+        std::fs::write(&input, &data).unwrap();
+
+        let written = encrypt_file(&input, &output, &key).unwrap();
+        let sealed = std::fs::read(&output).unwrap();
+        assert_eq!(written, sealed.len() as u64);
+        assert_eq!(sealed.len(), HEADER_LEN + data.len() + 4 * TAG_LEN);
+        assert_eq!(decrypt_bytes(&sealed, &key).unwrap(), data);
+
+        // Fed in pieces that don't line up with chunks, including an exact multiple
+        let mut decryptor = Decryptor::new(&key);
+        let mut plain = Vec::new();
+        for piece in sealed.chunks(10_000) {
+            decryptor.update(piece, &mut plain).unwrap();
+        }
+        decryptor.finish(&mut plain).unwrap();
+        assert_eq!(plain, data);
+        let exact: Vec<u8> = data[..CHUNK_SIZE * 2].to_vec();
+        assert_eq!(decrypt_bytes(&encrypt_bytes(&exact, &key, 5000), &key).unwrap(), exact);
+        assert!(decrypt_bytes(&encrypt_bytes(&[], &key, 1), &key).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_wrong_key_and_truncation_are_reported() {
+        let key = generate_key();
+        let data = vec![42u8; CHUNK_SIZE * 2 + 10];
+        let sealed = encrypt_bytes(&data, &key, 4096);
+
+        let other_key = generate_key();
+        match decrypt_bytes(&sealed, &other_key) {
+            Err(EncryptionError::WrongKey { file_key_id, configured_key_id }) => {
+                assert_eq!(file_key_id, key_id(&key));
+                assert_eq!(configured_key_id, key_id(&other_key));
+            }
+            other => panic!("expected a wrong key error, got {:?}", other.map(|plain| plain.len())),
+        }
+
+        // Cut at a chunk boundary: every remaining chunk authenticates, but the final one is missing
+        let at_boundary = &sealed[..HEADER_LEN + SEALED_CHUNK_LEN * 2];
+        assert!(matches!(decrypt_bytes(at_boundary, &key), Err(EncryptionError::Truncated(_))));
+        assert!(matches!(decrypt_bytes(&sealed[..HEADER_LEN - 3], &key), Err(EncryptionError::Truncated(_))));
+        assert!(decrypt_bytes(&sealed[..sealed.len() - 5], &key).is_err());
+
+        let mut tampered = sealed.clone();
+        tampered[HEADER_LEN + 100] ^= 1;
+        assert!(matches!(decrypt_bytes(&tampered, &key), Err(EncryptionError::Corrupted(_))));
+    }
+
+    #[test]
+    fn test_is_encrypted_copy() {
+        let track = doc! { "encrypted": true, "r2_archive_key": "tracks/archive/a.wav", "r2_delivery_key": "tracks/delivery/a.mp3" };
+        assert!(is_encrypted_copy(&track, "tracks/archive/a.wav"));
+        assert!(!is_encrypted_copy(&track, "tracks/delivery/a.mp3"));
+        assert!(!is_encrypted_copy(&doc! { "r2_archive_key": "tracks/archive/a.wav" }, "tracks/archive/a.wav"));
+    }
+}
@@ -0,0 +1,294 @@
+//! Background jobs for long-running maintenance work (integrity checks, album exports,
+//! preview re-encodes, ReplayGain backfills).
+//!
+//! `start_job` returns a job id straight away and runs the job on the async runtime.
+//! The `JobManager` keeps the status of running and recently finished jobs, and every
+//! change is emitted as a `job://progress` event carrying the full `JobStatus`. Jobs
+//! check their cancel flag between units of work; some kinds only run one at a time.
+
+use chrono::{DateTime, Utc};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use tauri::{command, AppHandle, Emitter, Manager, State, Wry};
+use ts_rs::TS;
+use uuid::Uuid;
+
+use crate::features::catalog::{export, integrity, reencode, replaygain};
+use crate::CommandError;
+
+/// Finished jobs kept for `list_jobs`; older ones are forgotten.
+const MAX_FINISHED_JOBS: usize = 50;
+
+pub type JobId = String;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, TS)]
+#[serde(rename_all = "snake_case")]
+#[ts(export)]
+pub enum JobKind {
+    IntegrityCheck,
+    AlbumExport,
+    PreviewReencode,
+    ReplayGain,
+}
+
+impl JobKind {
+    /// Kinds that may only have one job running at a time.
+    fn is_exclusive(self) -> bool {
+        match self {
+            // Sampled checks are independent and only read
+            JobKind::IntegrityCheck => false,
+            JobKind::AlbumExport | JobKind::PreviewReencode | JobKind::ReplayGain => true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, TS)]
+#[serde(rename_all = "snake_case")]
+#[ts(export)]
+pub enum JobState {
+    Running,
+    Completed,
+    Failed,
+    Cancelled,
+}
+
+/// Payload of `job://progress` and the result of `get_job`.
+#[derive(Debug, Clone, Serialize, TS)]
+#[ts(export)]
+pub struct JobStatus {
+    pub id: JobId,
+    pub kind: JobKind,
+    pub state: JobState,
+    /// Units of work done out of `total`, e.g. tracks checked or files exported
+    #[ts(type = "number")]
+    pub done: u64,
+    #[ts(type = "number")]
+    pub total: u64,
+    pub message: Option<String>,
+    /// What the job returned, once completed
+    pub result: Option<serde_json::Value>,
+    pub error: Option<String>,
+    pub started_at: DateTime<Utc>,
+    pub finished_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug)]
+struct Job {
+    status: JobStatus,
+    cancel_flag: Arc<AtomicBool>,
+}
+
+/// Managed state tracking running and recently finished jobs.
+#[derive(Debug, Default)]
+pub struct JobManager {
+    jobs: Mutex<HashMap<JobId, Job>>,
+}
+
+impl JobManager {
+    /// Records a new running job, unless `kind` is exclusive and one is already running.
+    fn register(&self, kind: JobKind, now: DateTime<Utc>) -> Result<(JobId, Arc<AtomicBool>), CommandError> {
+        let mut jobs = self.jobs.lock().unwrap_or_else(|e| e.into_inner());
+        if kind.is_exclusive() {
+            if let Some(running) = jobs.values().find(|job| job.status.kind == kind && job.status.state == JobState::Running) {
+                return Err(CommandError::Conflict(format!("A {:?} job is already running ({})", kind, running.status.id)));
+            }
+        }
+        let mut finished: Vec<(DateTime<Utc>, JobId)> = jobs.values()
+            .filter_map(|job| job.status.finished_at.map(|at| (at, job.status.id.clone())))
+            .collect();
+        if finished.len() >= MAX_FINISHED_JOBS {
+            finished.sort();
+            for (_, id) in &finished[..=finished.len() - MAX_FINISHED_JOBS] {
+                jobs.remove(id);
+            }
+        }
+
+        let id = Uuid::new_v4().to_string();
+        let cancel_flag = Arc::new(AtomicBool::new(false));
+        let status = JobStatus {
+            id: id.clone(), kind, state: JobState::Running, done: 0, total: 0, message: None,
+            result: None, error: None, started_at: now, finished_at: None,
+        };
+        jobs.insert(id.clone(), Job { status, cancel_flag: Arc::clone(&cancel_flag) });
+        Ok((id, cancel_flag))
+    }
+
+    fn update(&self, id: &str, change: impl FnOnce(&mut JobStatus)) -> Option<JobStatus> {
+        let mut jobs = self.jobs.lock().unwrap_or_else(|e| e.into_inner());
+        jobs.get_mut(id).map(|job| {
+            change(&mut job.status);
+            job.status.clone()
+        })
+    }
+
+    /// Asks a running job to stop. Returns false if it had already finished.
+    fn cancel(&self, id: &str) -> Result<bool, CommandError> {
+        let jobs = self.jobs.lock().unwrap_or_else(|e| e.into_inner());
+        let job = jobs.get(id).ok_or_else(|| CommandError::NotFound(format!("Job {} not found", id)))?;
+        if job.status.state != JobState::Running {
+            return Ok(false);
+        }
+        job.cancel_flag.store(true, Ordering::SeqCst);
+        Ok(true)
+    }
+
+    fn get(&self, id: &str) -> Option<JobStatus> {
+        self.jobs.lock().unwrap_or_else(|e| e.into_inner()).get(id).map(|job| job.status.clone())
+    }
+
+    /// All known jobs, most recently started first.
+    fn list(&self) -> Vec<JobStatus> {
+        let mut statuses: Vec<JobStatus> = self.jobs.lock().unwrap_or_else(|e| e.into_inner())
+            .values().map(|job| job.status.clone()).collect();
+        statuses.sort_by(|a, b| b.started_at.cmp(&a.started_at));
+        statuses
+    }
+}
+
+/// Handed to a running job to report progress and check for cancellation.
+pub struct JobContext {
+    id: JobId,
+    app_handle: AppHandle<Wry>,
+    cancel_flag: Arc<AtomicBool>,
+}
+
+impl JobContext {
+    pub fn app_handle(&self) -> &AppHandle<Wry> {
+        &self.app_handle
+    }
+
+    pub fn cancel_flag(&self) -> &AtomicBool {
+        &self.cancel_flag
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancel_flag.load(Ordering::SeqCst)
+    }
+
+    pub fn progress(&self, done: u64, total: u64, message: Option<String>) {
+        let status = self.app_handle.state::<JobManager>().update(&self.id, |status| {
+            status.done = done;
+            status.total = total;
+            status.message = message;
+        });
+        if let Some(status) = status {
+            let _ = self.app_handle.emit("job://progress", status);
+        }
+    }
+}
+
+/// Starts `run` as a background job and returns its id. A job that fails after being
+/// cancelled is reported as cancelled.
+pub fn spawn_job<F, Fut, T>(app_handle: &AppHandle<Wry>, kind: JobKind, run: F) -> Result<JobId, CommandError>
+where
+    F: FnOnce(JobContext) -> Fut + Send + 'static,
+    Fut: Future<Output = Result<T, CommandError>> + Send + 'static,
+    T: Serialize + Send + 'static,
+{
+    let (id, cancel_flag) = app_handle.state::<JobManager>().register(kind, Utc::now())?;
+    info!("Started {:?} job {}", kind, id);
+    let context = JobContext { id: id.clone(), app_handle: app_handle.clone(), cancel_flag: Arc::clone(&cancel_flag) };
+    let app_handle = app_handle.clone();
+    let job_id = id.clone();
+    tauri::async_runtime::spawn(async move {
+        let outcome = run(context).await.and_then(|value| serde_json::to_value(value).map_err(CommandError::from));
+        let cancelled = cancel_flag.load(Ordering::SeqCst);
+        let status = app_handle.state::<JobManager>().update(&job_id, |status| {
+            status.finished_at = Some(Utc::now());
+            match outcome {
+                Ok(result) => {
+                    status.state = JobState::Completed;
+                    status.result = Some(result);
+                }
+                Err(_) if cancelled => status.state = JobState::Cancelled,
+                Err(e) => {
+                    warn!("{:?} job {} failed: {}", status.kind, status.id, e);
+                    status.state = JobState::Failed;
+                    status.error = Some(e.to_string());
+                }
+            }
+        });
+        if let Some(status) = status {
+            info!("{:?} job {} finished: {:?}", status.kind, status.id, status.state);
+            let _ = app_handle.emit("job://progress", status);
+        }
+    });
+    Ok(id)
+}
+
+// --- Tauri Commands ---
+
+/// Starts a job of `kind`; `params` are the arguments of the matching command, e.g.
+/// `{"album_id": ..., "destination_zip_path": ...}` for an album export.
+#[command]
+pub async fn start_job(kind: JobKind, params: serde_json::Value, app_handle: AppHandle<Wry>) -> Result<JobId, CommandError> {
+    let params = if params.is_null() { serde_json::json!({}) } else { params };
+    match kind {
+        JobKind::IntegrityCheck => integrity::start_integrity_job(&app_handle, serde_json::from_value(params)?).await,
+        JobKind::AlbumExport => export::start_album_export_job(&app_handle, serde_json::from_value(params)?).await,
+        JobKind::PreviewReencode => reencode::start_reencode_job(&app_handle, serde_json::from_value(params)?).await,
+        JobKind::ReplayGain => replaygain::start_replaygain_job(&app_handle, serde_json::from_value(params)?).await,
+    }
+}
+
+#[command]
+pub fn list_jobs(job_manager: State<'_, JobManager>) -> Vec<JobStatus> {
+    job_manager.list()
+}
+
+#[command]
+pub fn get_job(job_id: String, job_manager: State<'_, JobManager>) -> Result<JobStatus, CommandError> {
+    job_manager.get(&job_id).ok_or_else(|| CommandError::NotFound(format!("Job {} not found", job_id)))
+}
+
+/// Asks a running job to stop at its next checkpoint. Returns false if it had already
+/// finished.
+#[command]
+pub fn cancel_job(job_id: String, job_manager: State<'_, JobManager>) -> Result<bool, CommandError> {
+    let cancelled = job_manager.cancel(&job_id)?;
+    if cancelled {
+        info!("Cancellation requested for job {}", job_id);
+    }
+    Ok(cancelled)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_exclusive_kinds_conflict_while_running() {
+        let manager = JobManager::default();
+        let (export_id, _) = manager.register(JobKind::AlbumExport, Utc::now()).unwrap();
+        assert!(matches!(manager.register(JobKind::AlbumExport, Utc::now()), Err(CommandError::Conflict(_))));
+        assert!(manager.register(JobKind::IntegrityCheck, Utc::now()).is_ok());
+        assert!(manager.register(JobKind::IntegrityCheck, Utc::now()).is_ok());
+
+        assert_eq!(manager.cancel(&export_id).ok(), Some(true));
+        manager.update(&export_id, |status| {
+            status.state = JobState::Cancelled;
+            status.finished_at = Some(Utc::now());
+        });
+        assert_eq!(manager.cancel(&export_id).ok(), Some(false));
+        assert!(manager.register(JobKind::AlbumExport, Utc::now()).is_ok());
+        assert!(matches!(manager.cancel("unknown"), Err(CommandError::NotFound(_))));
+    }
+
+    #[test]
+    fn test_finished_jobs_are_pruned() {
+        let manager = JobManager::default();
+        let start = Utc::now();
+        for i in 0..MAX_FINISHED_JOBS + 5 {
+            let (id, _) = manager.register(JobKind::IntegrityCheck, start).unwrap();
+            manager.update(&id, |status| {
+                status.state = JobState::Completed;
+                status.finished_at = Some(start + chrono::Duration::seconds(i as i64));
+            });
+        }
+        assert_eq!(manager.list().len(), MAX_FINISHED_JOBS);
+    }
+}
@@ -0,0 +1,34 @@
+//! JSON state files in the app data directory. They are written through a temp file
+//! next to them and renamed into place, so a crash never leaves a truncated file.
+
+use serde::Serialize;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// Writes `value` to `path` as pretty JSON, creating the directory if needed. The temp
+/// file is `<path>.json.tmp`, in the same directory so the rename stays atomic.
+pub fn write_json_atomically<T: Serialize + ?Sized>(path: &Path, value: &T) -> io::Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let json = serde_json::to_string_pretty(value)?;
+    let temp_path = path.with_extension("json.tmp");
+    fs::write(&temp_path, json)?;
+    fs::rename(&temp_path, path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_writes_through_a_temp_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("state").join("limits.json");
+        write_json_atomically(&path, &vec![1, 2, 3]).unwrap();
+        write_json_atomically(&path, &vec![4]).unwrap();
+        assert_eq!(serde_json::from_str::<Vec<u32>>(&fs::read_to_string(&path).unwrap()).unwrap(), vec![4]);
+        assert!(!path.with_extension("json.tmp").exists());
+    }
+}
@@ -0,0 +1,480 @@
+//! Live catalog sync: a change stream on the tracks and albums collections, so edits made
+//! by another editor against the same catalog show up without a manual refresh.
+//!
+//! Changes are debounced and emitted as `catalog://changed`, grouped by collection and
+//! operation with the affected ids. Change streams need a replica set or sharded cluster
+//! (Atlas always is one); against a standalone server sync is degraded to a `refresh`
+//! change every 60 seconds. A failed stream is reopened from its resume token, and if the
+//! server no longer has the history for that token a `refresh` is emitted instead.
+//!
+//! The listener is restarted when the MongoDB client is re-initialized and when
+//! `sync.live_sync` is toggled, and stopped when the app exits.
+
+use chrono::{DateTime, Utc};
+use futures_util::StreamExt;
+use log::{info, warn};
+use mongodb::bson::{doc, Bson, Document};
+use mongodb::change_stream::event::{ChangeStreamEvent, OperationType, ResumeToken};
+use mongodb::change_stream::ChangeStream;
+use mongodb::error::ErrorKind;
+use mongodb::options::ChangeStreamOptions;
+use mongodb::Database;
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::Mutex;
+use std::time::Duration;
+use tauri::{command, AppHandle, Emitter, Manager, State, Wry};
+use tokio::sync::watch;
+use tokio::time::{sleep, sleep_until, Instant};
+use ts_rs::TS;
+
+use crate::core::db_config::{self, CatalogDatabase, DatabaseConfig};
+use crate::core::settings::SettingsState;
+use crate::MongoState;
+
+/// How often a degraded listener asks the frontend to refresh.
+const POLL_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Changes arriving within this long of each other are sent together.
+const DEBOUNCE_QUIET: Duration = Duration::from_millis(500);
+
+/// Longest a change is held back while more keep arriving.
+const DEBOUNCE_MAX_DELAY: Duration = Duration::from_secs(2);
+
+/// First wait before reopening a failed stream; doubles up to `MAX_RECONNECT_DELAY`.
+const RECONNECT_DELAY: Duration = Duration::from_secs(2);
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(60);
+
+/// `$changeStream` on a standalone server.
+const CHANGE_STREAMS_NOT_SUPPORTED: i32 = 40573;
+/// The oplog no longer reaches back to the resume token.
+const CHANGE_STREAM_HISTORY_LOST: i32 = 286;
+
+const REFRESH: &str = "refresh";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, TS)]
+#[serde(rename_all = "snake_case")]
+#[ts(export)]
+pub enum SyncMode {
+    /// Changes are streamed as they happen
+    Active,
+    /// Changes can't be streamed right now; `refresh` is sent periodically or once reconnected
+    Degraded,
+    /// Not running: turned off, or no MongoDB client
+    Unavailable,
+}
+
+/// Result of `get_sync_status`.
+#[derive(Debug, Clone, Serialize, TS)]
+#[ts(export)]
+pub struct SyncStatus {
+    pub mode: SyncMode,
+    /// Why sync is degraded or unavailable
+    pub reason: Option<String>,
+    /// When `mode` last changed
+    pub since: DateTime<Utc>,
+}
+
+/// An operation on documents of one collection.
+#[derive(Debug, Clone, PartialEq, Serialize, TS)]
+#[ts(export)]
+pub struct CatalogChange {
+    /// `tracks` or `albums`
+    pub collection: String,
+    /// `insert`, `update`, `replace` or `delete`; `refresh`, without ids, when the
+    /// affected documents aren't known and everything shown should be reloaded
+    pub operation: String,
+    pub ids: Vec<String>,
+}
+
+/// Payload of `catalog://changed`.
+#[derive(Debug, Clone, Serialize, TS)]
+#[ts(export)]
+pub struct CatalogChanged {
+    pub changes: Vec<CatalogChange>,
+}
+
+#[derive(Debug)]
+struct ListenerSlot {
+    // Bumped on every restart, so a replaced listener can't overwrite the status
+    generation: u64,
+    status: SyncStatus,
+    // Dropping the sender stops the listener holding its receiver
+    stop: Option<watch::Sender<()>>,
+}
+
+/// Managed state holding the sync status and the running listener.
+#[derive(Debug)]
+pub struct LiveSyncState {
+    slot: Mutex<ListenerSlot>,
+}
+
+impl Default for LiveSyncState {
+    fn default() -> Self {
+        let status = SyncStatus { mode: SyncMode::Unavailable, reason: Some("MongoDB client not initialized".to_string()), since: Utc::now() };
+        Self { slot: Mutex::new(ListenerSlot { generation: 0, status, stop: None }) }
+    }
+}
+
+impl LiveSyncState {
+    fn lock(&self) -> std::sync::MutexGuard<'_, ListenerSlot> {
+        self.slot.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    pub fn status(&self) -> SyncStatus {
+        self.lock().status.clone()
+    }
+
+    /// Records the status reported by listener `generation`; false once it was replaced.
+    fn report(&self, generation: u64, mode: SyncMode, reason: Option<String>) -> bool {
+        let mut slot = self.lock();
+        if slot.generation != generation {
+            return false;
+        }
+        if slot.status.mode != mode {
+            info!("Live catalog sync is now {:?}{}", mode, reason.as_deref().map(|r| format!(": {}", r)).unwrap_or_default());
+            slot.status.since = Utc::now();
+        }
+        slot.status.mode = mode;
+        slot.status.reason = reason;
+        true
+    }
+
+    fn is_running(&self) -> bool {
+        self.lock().stop.as_ref().is_some_and(|stop| !stop.is_closed())
+    }
+
+    /// Stops the running listener, e.g. on exit.
+    pub fn stop(&self) {
+        let mut slot = self.lock();
+        slot.generation += 1;
+        slot.stop = None;
+    }
+}
+
+/// Replaces the listener with one for the current client and settings, or records why
+/// none can run.
+pub fn restart_live_sync(app_handle: &AppHandle<Wry>) {
+    let enabled = app_handle.state::<SettingsState>().get().sync.live_sync;
+    let state = app_handle.state::<LiveSyncState>();
+    let mut slot = state.lock();
+    slot.generation += 1;
+    let generation = slot.generation;
+    if !enabled {
+        slot.stop = None;
+        drop(slot);
+        state.report(generation, SyncMode::Unavailable, Some("Live sync is turned off in settings".to_string()));
+        return;
+    }
+    let (stop, stopped) = watch::channel(());
+    // Replacing the sender stops the previous listener
+    slot.stop = Some(stop);
+    drop(slot);
+    let listener = Listener { app_handle: app_handle.clone(), generation, stopped };
+    tauri::async_runtime::spawn(run_listener(listener));
+}
+
+/// Starts the listener once the MongoDB client is up. A re-initialized client may point
+/// at another database, so the listener is always replaced then.
+pub fn on_mongo_client_ready(app_handle: &AppHandle<Wry>, reinitialized: bool) {
+    if reinitialized || !app_handle.state::<LiveSyncState>().is_running() {
+        restart_live_sync(app_handle);
+    }
+}
+
+struct Listener {
+    app_handle: AppHandle<Wry>,
+    generation: u64,
+    stopped: watch::Receiver<()>,
+}
+
+impl Listener {
+    fn report(&self, mode: SyncMode, reason: Option<String>) -> bool {
+        self.app_handle.state::<LiveSyncState>().report(self.generation, mode, reason)
+    }
+
+    fn emit(&self, changes: Vec<CatalogChange>) {
+        if changes.is_empty() {
+            return;
+        }
+        if let Err(e) = self.app_handle.emit("catalog://changed", CatalogChanged { changes }) {
+            warn!("Failed to emit catalog change event: {}", e);
+        }
+    }
+
+    /// Resolves once the listener is stopped or replaced.
+    async fn stopped(&mut self) {
+        while self.stopped.changed().await.is_ok() {}
+    }
+}
+
+async fn run_listener(mut listener: Listener) {
+    let client = listener.app_handle.state::<MongoState>().client.lock().await.clone();
+    let Some(client) = client else {
+        listener.report(SyncMode::Unavailable, Some("MongoDB client not initialized".to_string()));
+        return;
+    };
+    // isMaster rather than hello, which servers before 4.4.2 lack
+    let topology = tokio::select! {
+        _ = listener.stopped() => return,
+        reply = client.database("admin").run_command(doc! { "isMaster": 1 }, None) => reply,
+    };
+    match topology {
+        Ok(reply) if supports_change_streams(&reply) => watch_changes(&mut listener, &client.catalog_database()).await,
+        Ok(_) => poll_changes(&mut listener, "The MongoDB server is not a replica set; changes are polled".to_string()).await,
+        Err(e) => poll_changes(&mut listener, format!("Could not check the MongoDB server for change streams: {}", e)).await,
+    }
+    info!("Live catalog sync listener {} stopped.", listener.generation);
+}
+
+/// Whether an `isMaster` reply comes from a replica set member or a mongos router.
+fn supports_change_streams(reply: &Document) -> bool {
+    reply.contains_key("setName") || matches!(reply.get_str("msg"), Ok("isdbgrid"))
+}
+
+/// Asks the frontend to refresh every `POLL_INTERVAL` until stopped.
+async fn poll_changes(listener: &mut Listener, reason: String) {
+    if !listener.report(SyncMode::Degraded, Some(reason)) {
+        return;
+    }
+    let mut interval = tokio::time::interval_at(Instant::now() + POLL_INTERVAL, POLL_INTERVAL);
+    loop {
+        tokio::select! {
+            _ = listener.stopped() => return,
+            _ = interval.tick() => listener.emit(refresh_all()),
+        }
+    }
+}
+
+/// How a stream stopped delivering changes.
+enum StreamEnd {
+    Stopped,
+    /// The server closed the stream, e.g. after the database was dropped
+    Closed,
+    Failed(mongodb::error::Error),
+}
+
+async fn watch_changes(listener: &mut Listener, db: &Database) {
+    let config = db_config::active();
+    let mut resume_token: Option<ResumeToken> = None;
+    let mut retry_delay = RECONNECT_DELAY;
+    loop {
+        let opened = tokio::select! {
+            _ = listener.stopped() => return,
+            opened = open_stream(db, &config, resume_token.clone()) => opened,
+        };
+        let reason = match opened {
+            Ok(mut stream) => {
+                if !listener.report(SyncMode::Active, None) {
+                    return;
+                }
+                retry_delay = RECONNECT_DELAY;
+                let end = forward_changes(listener, &mut stream, &config).await;
+                resume_token = stream.resume_token();
+                match end {
+                    StreamEnd::Stopped => return,
+                    StreamEnd::Closed => {
+                        // A closed stream can't be resumed, so whatever happened is reloaded
+                        resume_token = None;
+                        listener.emit(refresh_all());
+                        continue;
+                    }
+                    StreamEnd::Failed(e) => format!("Change stream failed: {}", e),
+                }
+            }
+            Err(e) if command_error_code(&e) == Some(CHANGE_STREAMS_NOT_SUPPORTED) => {
+                return poll_changes(listener, "The MongoDB server does not support change streams; changes are polled".to_string()).await;
+            }
+            Err(e) if resume_token.is_some() && command_error_code(&e) == Some(CHANGE_STREAM_HISTORY_LOST) => {
+                warn!("Change stream history lost, resuming from now: {}", e);
+                resume_token = None;
+                listener.emit(refresh_all());
+                continue;
+            }
+            Err(e) => format!("Opening the change stream failed: {}", e),
+        };
+        warn!("{}; retrying in {:?}", reason, retry_delay);
+        if !listener.report(SyncMode::Degraded, Some(format!("Reconnecting. {}", reason))) {
+            return;
+        }
+        tokio::select! {
+            _ = listener.stopped() => return,
+            _ = sleep(retry_delay) => {}
+        }
+        retry_delay = (retry_delay * 2).min(MAX_RECONNECT_DELAY);
+    }
+}
+
+async fn open_stream(
+    db: &Database,
+    config: &DatabaseConfig,
+    resume_token: Option<ResumeToken>,
+) -> mongodb::error::Result<ChangeStream<ChangeStreamEvent<Document>>> {
+    let pipeline = [
+        doc! { "$match": { "ns.coll": { "$in": [&config.tracks_collection, &config.albums_collection] } } },
+        // Only the ids are sent on, so full documents of inserts and replaces are left behind
+        doc! { "$project": { "operationType": 1, "ns": 1, "documentKey": 1 } },
+    ];
+    let options = ChangeStreamOptions::builder().resume_after(resume_token).build();
+    db.watch(pipeline, options).await
+}
+
+/// Collects changes from `stream` and emits them in debounced batches.
+async fn forward_changes(
+    listener: &mut Listener,
+    stream: &mut ChangeStream<ChangeStreamEvent<Document>>,
+    config: &DatabaseConfig,
+) -> StreamEnd {
+    let mut pending = PendingChanges::default();
+    loop {
+        let flush_at = pending.flush_at();
+        tokio::select! {
+            _ = listener.stopped() => return StreamEnd::Stopped,
+            _ = sleep_until(flush_at.unwrap_or_else(Instant::now)), if flush_at.is_some() => listener.emit(pending.take()),
+            event = stream.next() => match event {
+                Some(Ok(event)) => {
+                    if let Some((collection, operation, id)) = change_of(&event, config) {
+                        pending.add(collection, operation, id, Instant::now());
+                    }
+                }
+                Some(Err(e)) => {
+                    listener.emit(pending.take());
+                    return StreamEnd::Failed(e);
+                }
+                None => {
+                    listener.emit(pending.take());
+                    return StreamEnd::Closed;
+                }
+            },
+        }
+    }
+}
+
+fn command_error_code(e: &mongodb::error::Error) -> Option<i32> {
+    match e.kind.as_ref() {
+        ErrorKind::Command(command_error) => Some(command_error.code),
+        _ => None,
+    }
+}
+
+/// Collection, operation and document id of an event; the id is None for `refresh`.
+fn change_of(event: &ChangeStreamEvent<Document>, config: &DatabaseConfig) -> Option<(&'static str, &'static str, Option<String>)> {
+    let collection = match event.ns.as_ref().and_then(|ns| ns.coll.as_deref())? {
+        name if name == config.tracks_collection => "tracks",
+        name if name == config.albums_collection => "albums",
+        _ => return None,
+    };
+    let operation = match event.operation_type {
+        OperationType::Insert => "insert",
+        OperationType::Update => "update",
+        OperationType::Replace => "replace",
+        OperationType::Delete => "delete",
+        OperationType::Drop | OperationType::Rename => return Some((collection, REFRESH, None)),
+        _ => return None,
+    };
+    let id = match event.document_key.as_ref()?.get("_id")? {
+        Bson::ObjectId(oid) => oid.to_hex(),
+        Bson::String(id) => id.clone(),
+        other => other.to_string(),
+    };
+    Some((collection, operation, Some(id)))
+}
+
+fn refresh_all() -> Vec<CatalogChange> {
+    ["tracks", "albums"].into_iter()
+        .map(|collection| CatalogChange { collection: collection.to_string(), operation: REFRESH.to_string(), ids: Vec::new() })
+        .collect()
+}
+
+/// Changes waiting to be emitted, with the ids of each collection and operation.
+#[derive(Debug, Default)]
+struct PendingChanges {
+    changes: BTreeMap<(&'static str, &'static str), BTreeSet<String>>,
+    first_at: Option<Instant>,
+    last_at: Option<Instant>,
+}
+
+impl PendingChanges {
+    fn add(&mut self, collection: &'static str, operation: &'static str, id: Option<String>, now: Instant) {
+        let ids = self.changes.entry((collection, operation)).or_default();
+        ids.extend(id);
+        self.first_at.get_or_insert(now);
+        self.last_at = Some(now);
+    }
+
+    /// When the changes are due: after a quiet spell, or `DEBOUNCE_MAX_DELAY` after the first.
+    fn flush_at(&self) -> Option<Instant> {
+        Some((self.last_at? + DEBOUNCE_QUIET).min(self.first_at? + DEBOUNCE_MAX_DELAY))
+    }
+
+    fn take(&mut self) -> Vec<CatalogChange> {
+        self.first_at = None;
+        self.last_at = None;
+        std::mem::take(&mut self.changes).into_iter()
+            .map(|((collection, operation), ids)| CatalogChange {
+                collection: collection.to_string(),
+                operation: operation.to_string(),
+                ids: ids.into_iter().collect(),
+            })
+            .collect()
+    }
+}
+
+// --- Tauri Commands ---
+
+/// Whether catalog changes made elsewhere are being picked up, and if not why.
+#[command]
+pub fn get_sync_status(state: State<'_, LiveSyncState>) -> SyncStatus {
+    state.status()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mongodb::bson::oid::ObjectId;
+
+    #[test]
+    fn test_change_of_event() {
+        let config = DatabaseConfig { albums_collection: "albums_staging".to_string(), ..Default::default() };
+        let oid = ObjectId::new();
+        let event = |operation: &str, coll: &str| -> ChangeStreamEvent<Document> {
+            mongodb::bson::from_document(doc! {
+                "_id": { "_data": "8263" },
+                "operationType": operation,
+                "ns": { "db": "music_library", "coll": coll },
+                "documentKey": { "_id": oid },
+            }).unwrap()
+        };
+        assert_eq!(change_of(&event("update", "tracks"), &config), Some(("tracks", "update", Some(oid.to_hex()))));
+        assert_eq!(change_of(&event("delete", "albums_staging"), &config), Some(("albums", "delete", Some(oid.to_hex()))));
+        assert_eq!(change_of(&event("drop", "tracks"), &config), Some(("tracks", REFRESH, None)));
+        assert_eq!(change_of(&event("insert", "albums"), &config), None);
+
+        assert!(supports_change_streams(&doc! { "ismaster": true, "setName": "atlas-abc-shard-0" }));
+        assert!(supports_change_streams(&doc! { "ismaster": true, "msg": "isdbgrid" }));
+        assert!(!supports_change_streams(&doc! { "ismaster": true }));
+    }
+
+    #[test]
+    fn test_pending_changes_are_debounced() {
+        let start = Instant::now();
+        let mut pending = PendingChanges::default();
+        assert_eq!(pending.flush_at(), None);
+
+        pending.add("tracks", "update", Some("b".to_string()), start);
+        pending.add("tracks", "update", Some("a".to_string()), start + Duration::from_millis(300));
+        pending.add("tracks", "update", Some("a".to_string()), start + Duration::from_millis(400));
+        assert_eq!(pending.flush_at(), Some(start + Duration::from_millis(900)));
+        // A steady trickle of changes still goes out after the maximum delay
+        pending.add("albums", "insert", Some("c".to_string()), start + Duration::from_millis(1800));
+        assert_eq!(pending.flush_at(), Some(start + DEBOUNCE_MAX_DELAY));
+
+        let changes = pending.take();
+        assert_eq!(changes, vec![
+            CatalogChange { collection: "albums".to_string(), operation: "insert".to_string(), ids: vec!["c".to_string()] },
+            CatalogChange { collection: "tracks".to_string(), operation: "update".to_string(), ids: vec!["a".to_string(), "b".to_string()] },
+        ]);
+        assert_eq!(pending.flush_at(), None);
+        assert!(pending.take().is_empty());
+    }
+}
@@ -0,0 +1,158 @@
+//! Log output: every record goes to stderr and to `app.log` in the app data directory,
+//! so a failed batch can still be diagnosed after the app is closed.
+//!
+//! The file is rotated at `MAX_LOG_FILE_BYTES` into `app.1.log`, `app.2.log`, ... with at
+//! most `LOG_FILES_KEPT` files kept, which caps the disk space logs can take.
+
+use log::warn;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use tauri::command;
+
+use crate::CommandError;
+
+/// Bundle identifier from `tauri.conf.json`; Tauri names the app data directory after it.
+const APP_IDENTIFIER: &str = "com.musiclibrarymanager.app";
+const LOG_FILE_NAME: &str = "app.log";
+const MAX_LOG_FILE_BYTES: u64 = 5 * 1024 * 1024;
+/// The current file plus rotated ones
+const LOG_FILES_KEPT: usize = 3;
+
+static LOG_FILE_PATH: OnceLock<PathBuf> = OnceLock::new();
+
+/// Appends to a log file, rotating it once it reaches `max_bytes`. Rotation only happens
+/// between writes, and the logger writes a whole record at a time, so records are never
+/// split across files.
+struct RotatingFile {
+    path: PathBuf,
+    file: File,
+    written: u64,
+    max_bytes: u64,
+    kept: usize,
+}
+
+impl RotatingFile {
+    fn open(path: PathBuf, max_bytes: u64, kept: usize) -> io::Result<Self> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let written = file.metadata()?.len();
+        Ok(Self { path, file, written, max_bytes, kept })
+    }
+
+    /// `app.log` for 0, `app.1.log` for 1, and so on.
+    fn rotated_path(path: &Path, index: usize) -> PathBuf {
+        if index == 0 {
+            return path.to_path_buf();
+        }
+        let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+        let extension = path.extension().map(|e| format!(".{}", e.to_string_lossy())).unwrap_or_default();
+        path.with_file_name(format!("{}.{}{}", stem, index, extension))
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+        // The oldest file is overwritten by the rename
+        for index in (1..self.kept).rev() {
+            let from = Self::rotated_path(&self.path, index - 1);
+            if from.exists() {
+                fs::rename(&from, Self::rotated_path(&self.path, index))?;
+            }
+        }
+        self.file = OpenOptions::new().create(true).truncate(true).write(true).open(&self.path)?;
+        self.written = 0;
+        Ok(())
+    }
+}
+
+impl Write for RotatingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.written > 0 && self.written + buf.len() as u64 > self.max_bytes {
+            self.rotate()?;
+        }
+        let written = self.file.write(buf)?;
+        self.written += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+/// Writes to stderr and the log file. A failing log file never stops stderr output.
+struct Tee {
+    file: RotatingFile,
+}
+
+impl Write for Tee {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let _ = io::stderr().write_all(buf);
+        let _ = self.file.write_all(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        let _ = io::stderr().flush();
+        self.file.flush()
+    }
+}
+
+/// Where the log file is written: `logs/app.log` in the app data directory.
+fn default_log_file_path() -> Option<PathBuf> {
+    dirs::data_dir().map(|dir| dir.join(APP_IDENTIFIER).join("logs").join(LOG_FILE_NAME))
+}
+
+/// Sets up the global logger, with the level taken from `RUST_LOG` (default `info`).
+/// Falls back to stderr only if the log file can't be opened.
+pub fn init_logging() {
+    let mut builder = env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info"));
+    let file = default_log_file_path()
+        .ok_or_else(|| "no app data directory".to_string())
+        .and_then(|path| RotatingFile::open(path.clone(), MAX_LOG_FILE_BYTES, LOG_FILES_KEPT).map_err(|e| format!("{:?}: {}", path, e)));
+    match file {
+        Ok(file) => {
+            let _ = LOG_FILE_PATH.set(file.path.clone());
+            builder.target(env_logger::Target::Pipe(Box::new(Tee { file }))).init();
+        }
+        Err(e) => {
+            builder.init();
+            warn!("Logging to stderr only, the log file could not be opened: {}", e);
+        }
+    }
+}
+
+// --- Tauri Commands ---
+
+/// Path of the current log file, for attaching to bug reports.
+#[command]
+pub fn get_log_file_path() -> Result<String, CommandError> {
+    LOG_FILE_PATH.get()
+        .map(|path| path.to_string_lossy().into_owned())
+        .ok_or_else(|| CommandError::FileSystem("Logs are not being written to a file".to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_rotates_and_keeps_limited_files() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join(LOG_FILE_NAME);
+        let mut file = RotatingFile::open(path.clone(), 10, 3).unwrap();
+        for record in ["aaaaaaaa\n", "bbbbbbbb\n", "cccccccc\n", "dddddddd\n"] {
+            file.write_all(record.as_bytes()).unwrap();
+        }
+        file.flush().unwrap();
+
+        assert_eq!(fs::read_to_string(&path).unwrap(), "dddddddd\n");
+        assert_eq!(fs::read_to_string(dir.path().join("app.1.log")).unwrap(), "cccccccc\n");
+        assert_eq!(fs::read_to_string(dir.path().join("app.2.log")).unwrap(), "bbbbbbbb\n");
+        assert!(!dir.path().join("app.3.log").exists());
+    }
+}
@@ -1,4 +1,15 @@
 // src-tauri/src/core/mod.rs
 pub mod commands_old; // Contains the original commands.rs content, needs refactoring
 pub mod r2; // Add R2 module declaration
+pub mod presign;
+pub mod database;
+pub mod logging;
+pub mod timing;
+pub mod encryption;
+pub mod settings;
+pub mod jobs;
+pub mod client_init;
+pub mod db_config;
+pub mod live_sync;
+pub mod json_file;
 // Add other core modules here if needed, e.g., pub mod database;
\ No newline at end of file
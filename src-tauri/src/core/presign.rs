@@ -0,0 +1,244 @@
+//! Presigned GET URLs for R2 objects, with an in-memory cache so repeated playback
+//! requests (e.g. scrubbing through a track) reuse a URL instead of signing again.
+//! Tracks uploaded as public are linked through the bucket's public domain instead.
+
+use log::info;
+use mongodb::bson::Document;
+use serde::Serialize;
+use serde_json::json;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tauri::{command, AppHandle, State, Wry};
+
+use crate::core::encryption::is_encrypted_copy;
+use crate::core::r2::{ObjectVisibility, R2Client, PLAYBACK_KEY_FIELDS};
+use crate::core::settings::patch_settings;
+use crate::error::CommandError;
+use crate::features::catalog::storage::id_filter;
+use crate::{MongoState, R2State};
+use crate::core::db_config::{CatalogCollections, CatalogDatabase};
+
+/// Lifetime of a URL when the caller doesn't ask for one.
+pub const DEFAULT_URL_EXPIRY_SECS: u64 = 60 * 60;
+/// S3 presigned URLs can live at most seven days.
+pub const MAX_URL_EXPIRY_SECS: u64 = 7 * 24 * 60 * 60;
+
+/// Generated URLs keyed by bucket and object key.
+#[derive(Debug, Default)]
+pub struct PresignedUrlCache {
+    entries: Mutex<HashMap<String, (String, Instant)>>,
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct PresignedUrl {
+    pub url: String,
+    /// Seconds until the URL stops working
+    pub expires_in_secs: u64,
+    /// Whether the URL came from the cache rather than being signed for this request
+    pub cached: bool,
+}
+
+fn cache_key(bucket: &str, key: &str) -> String {
+    format!("{}/{}", bucket, key)
+}
+
+impl PresignedUrlCache {
+    /// Returns a cached URL with at least `min_remaining` of its lifetime left at `now`.
+    /// Expired entries are dropped along the way.
+    fn get(&self, cache_key: &str, min_remaining: Duration, now: Instant) -> Option<(String, Duration)> {
+        let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        entries.retain(|_, (_, expires_at)| *expires_at > now);
+        entries.get(cache_key).and_then(|(url, expires_at)| {
+            let remaining = expires_at.duration_since(now);
+            (remaining >= min_remaining).then(|| (url.clone(), remaining))
+        })
+    }
+
+    fn insert(&self, cache_key: String, url: String, expires_at: Instant) {
+        self.entries.lock().unwrap_or_else(|e| e.into_inner()).insert(cache_key, (url, expires_at));
+    }
+
+    /// Drops all cached URLs, returning how many there were.
+    pub fn clear(&self) -> usize {
+        let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        let count = entries.len();
+        entries.clear();
+        count
+    }
+}
+
+/// Public domain of the bucket (an r2.dev subdomain or a custom domain), used to link
+/// public objects. Without one, public objects get presigned URLs like private ones.
+/// Saved as `storage.public_base_url` in the settings, which keep this up to date.
+#[derive(Debug, Default)]
+pub struct PublicUrlBase(Mutex<Option<String>>);
+
+impl PublicUrlBase {
+    fn get(&self) -> Option<String> {
+        self.0.lock().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    pub(crate) fn set(&self, base_url: Option<String>) {
+        *self.0.lock().unwrap_or_else(|e| e.into_inner()) = base_url;
+    }
+}
+
+/// The public URL of `key`, with each path segment percent-encoded.
+fn public_object_url(base: &str, key: &str) -> Result<String, CommandError> {
+    let mut url = url::Url::parse(base)
+        .map_err(|e| CommandError::Configuration(format!("Invalid public base URL {}: {}", base, e)))?;
+    url.path_segments_mut()
+        .map_err(|_| CommandError::Configuration(format!("Invalid public base URL {}", base)))?
+        .pop_if_empty()
+        .extend(key.split('/'));
+    Ok(url.to_string())
+}
+
+/// Signs a URL for `key`, reusing a cached one with at least half of `expires_in` left.
+async fn presigned_url(
+    r2_client: &R2Client,
+    url_cache: &PresignedUrlCache,
+    key: &str,
+    expires_in: Duration,
+    use_cache: bool,
+) -> Result<PresignedUrl, CommandError> {
+    let cache_key = cache_key(r2_client.bucket_name(), key);
+    if use_cache {
+        if let Some((url, remaining)) = url_cache.get(&cache_key, expires_in / 2, Instant::now()) {
+            return Ok(PresignedUrl { url, expires_in_secs: remaining.as_secs(), cached: true });
+        }
+    }
+
+    let signed_at = Instant::now();
+    let url = r2_client.presign_get(key, expires_in).await
+        .map_err(|e| CommandError::Storage(format!("Failed to presign URL for {}: {}", key, e)))?;
+    if use_cache {
+        url_cache.insert(cache_key, url.clone(), signed_at + expires_in);
+    }
+    Ok(PresignedUrl { url, expires_in_secs: expires_in.as_secs(), cached: false })
+}
+
+fn validate_expiry(expires_in_secs: Option<u64>) -> Result<Duration, CommandError> {
+    let expires_in_secs = expires_in_secs.unwrap_or(DEFAULT_URL_EXPIRY_SECS);
+    if expires_in_secs == 0 || expires_in_secs > MAX_URL_EXPIRY_SECS {
+        return Err(CommandError::Validation(format!(
+            "URL expiry must be between 1 and {} seconds", MAX_URL_EXPIRY_SECS
+        )));
+    }
+    Ok(Duration::from_secs(expires_in_secs))
+}
+
+// --- Tauri Commands ---
+
+/// Returns a presigned GET URL for an R2 object. With `use_cache` (the default), a
+/// previously generated URL is reused while at least half of the requested lifetime remains.
+#[command]
+pub async fn get_presigned_url(
+    key: String,
+    expires_in_secs: Option<u64>,
+    use_cache: Option<bool>,
+    r2_state: State<'_, R2State>,
+    url_cache: State<'_, PresignedUrlCache>,
+) -> Result<PresignedUrl, CommandError> {
+    if key.is_empty() {
+        return Err(CommandError::Validation("Object key must not be empty".to_string()));
+    }
+    let expires_in = validate_expiry(expires_in_secs)?;
+    let r2_client = R2Client::from_state(&r2_state).await?;
+    presigned_url(&r2_client, &url_cache, &key, expires_in, use_cache.unwrap_or(true)).await
+}
+
+/// Returns a URL for a track's delivery copy, or its archive copy if it has none. Public
+/// tracks are linked through the public domain set with `set_public_base_url`, and their
+/// URL never expires (`expires_in_secs` is 0); private tracks get a presigned URL.
+/// Fetching a URL is not a play: plays are counted by the `stream` handler.
+#[command]
+pub async fn get_track_url(
+    track_id: String,
+    expires_in_secs: Option<u64>,
+    mongo_state: State<'_, MongoState>,
+    r2_state: State<'_, R2State>,
+    url_cache: State<'_, PresignedUrlCache>,
+    public_base: State<'_, PublicUrlBase>,
+) -> Result<PresignedUrl, CommandError> {
+    let expires_in = validate_expiry(expires_in_secs)?;
+    let tracks_collection = {
+        let client_lock = mongo_state.client.lock().await;
+        let client = client_lock.as_ref()
+            .ok_or_else(|| CommandError::Configuration("MongoDB client not initialized".to_string()))?;
+        client.catalog_database().tracks::<Document>()
+    };
+    let track_doc = tracks_collection.find_one(id_filter(&track_id), None).await?
+        .ok_or_else(|| CommandError::NotFound(format!("Track {} not found", track_id)))?;
+    // An encrypted original is useless to whoever opens the link
+    let key = PLAYBACK_KEY_FIELDS
+        .iter()
+        .find_map(|field| track_doc.get_str(field).ok().filter(|key| !key.is_empty() && !is_encrypted_copy(&track_doc, key)))
+        .ok_or_else(|| CommandError::NotFound(format!("Track {} has no playable audio in R2", track_id)))?;
+
+    let visibility = ObjectVisibility::from_stored(track_doc.get_str("visibility").ok());
+    if let (ObjectVisibility::Public, Some(base)) = (visibility, public_base.get()) {
+        return Ok(PresignedUrl { url: public_object_url(&base, key)?, expires_in_secs: 0, cached: false });
+    }
+
+    let r2_client = R2Client::from_state(&r2_state).await?;
+    let bucket_client = r2_client.for_track(&track_doc);
+    presigned_url(&bucket_client, &url_cache, key, expires_in, true).await
+}
+
+/// Sets the public domain used to link public tracks, e.g. `https://media.example.com`,
+/// and saves it with the settings. `None` or an empty string clears it.
+#[command]
+pub async fn set_public_base_url(base_url: Option<String>, app_handle: AppHandle<Wry>) -> Result<(), CommandError> {
+    let base_url = base_url.map(|url| url.trim().trim_end_matches('/').to_string()).filter(|url| !url.is_empty());
+    patch_settings(&app_handle, &json!({ "storage": { "public_base_url": base_url } })).await?;
+    info!("Public base URL set to {:?}", base_url);
+    Ok(())
+}
+
+/// Forgets all cached presigned URLs, e.g. after rotating R2 credentials.
+#[command]
+pub async fn clear_url_cache(url_cache: State<'_, PresignedUrlCache>) -> Result<usize, CommandError> {
+    let cleared = url_cache.clear();
+    info!("Cleared {} cached presigned URLs", cleared);
+    Ok(cleared)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cached_url_reused_only_with_enough_ttl() {
+        let cache = PresignedUrlCache::default();
+        let now = Instant::now();
+        cache.insert(cache_key("bucket", "a.m4a"), "https://signed/a".to_string(), now + Duration::from_secs(600));
+
+        let hit = cache.get("bucket/a.m4a", Duration::from_secs(300), now + Duration::from_secs(100));
+        assert_eq!(hit, Some(("https://signed/a".to_string(), Duration::from_secs(500))));
+        // Too little lifetime left: sign a fresh URL instead
+        assert_eq!(cache.get("bucket/a.m4a", Duration::from_secs(300), now + Duration::from_secs(400)), None);
+        assert_eq!(cache.get("other/a.m4a", Duration::ZERO, now), None);
+    }
+
+    #[test]
+    fn test_public_object_url_encodes_segments() {
+        assert_eq!(
+            public_object_url("https://media.example.com/", "tracks/aac/My Song #1.m4a").unwrap(),
+            "https://media.example.com/tracks/aac/My%20Song%20%231.m4a"
+        );
+        assert!(public_object_url("media.example.com", "a.m4a").is_err());
+    }
+
+    #[test]
+    fn test_expired_entries_are_evicted() {
+        let cache = PresignedUrlCache::default();
+        let now = Instant::now();
+        cache.insert("b/old".to_string(), "old".to_string(), now + Duration::from_secs(10));
+        cache.insert("b/new".to_string(), "new".to_string(), now + Duration::from_secs(1000));
+
+        assert!(cache.get("b/new", Duration::ZERO, now + Duration::from_secs(20)).is_some());
+        assert_eq!(cache.clear(), 1);
+    }
+}
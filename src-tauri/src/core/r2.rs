@@ -1,11 +1,19 @@
 use aws_config::meta::region::RegionProviderChain;
-use aws_sdk_s3::{Client, types::{Delete, ObjectIdentifier}};
+use aws_sdk_s3::{Client, types::{Delete, MetadataDirective, ObjectIdentifier}};
 use aws_sdk_s3::config::{Credentials, Region};
+use aws_sdk_s3::error::{DisplayErrorContext, ProvideErrorMetadata, SdkError};
 use aws_sdk_s3::primitives::ByteStream;
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::io::Write;
 use thiserror::Error;
 use futures_util::StreamExt;
+use mongodb::bson::Document;
+use tauri::State;
+
+use crate::error::CommandError;
+use crate::features::upload::throttle::BandwidthLimiter;
+use crate::R2State;
 
 #[derive(Debug, Error)]
 pub enum R2Error {
@@ -23,6 +31,9 @@ pub enum R2Error {
     
     #[error("Bucket does not exist: {0}")]
     BucketNotFound(String),
+
+    #[error("Requested range not satisfiable: {0}")]
+    InvalidRange(String),
     
     #[error("Other error: {0}")]
     Other(String),
@@ -30,6 +41,43 @@ pub enum R2Error {
 
 type R2Result<T> = std::result::Result<T, R2Error>;
 
+/// Track fields that hold R2 keys, legacy ones included, originals first.
+pub const TRACK_KEY_FIELDS: [&str; 4] = ["r2_archive_key", "r2_original_key", "r2_delivery_key", "r2_aac_key"];
+
+/// Track fields that hold the original (archive) copy; the only ones that can be encrypted.
+pub const ORIGINAL_KEY_FIELDS: [&str; 2] = ["r2_archive_key", "r2_original_key"];
+
+/// Track fields in the order playback prefers them: the delivery copy, then the original.
+pub const PLAYBACK_KEY_FIELDS: [&str; 4] = ["r2_delivery_key", "r2_aac_key", "r2_original_key", "r2_archive_key"];
+
+/// Who may read an uploaded object. R2 has no per-object ACLs, so this is recorded on the
+/// track document and decides how its URLs are generated: public objects are linked
+/// through the bucket's public domain, private ones through presigned URLs.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ObjectVisibility {
+    #[default]
+    Private,
+    Public,
+}
+
+impl ObjectVisibility {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ObjectVisibility::Private => "private",
+            ObjectVisibility::Public => "public",
+        }
+    }
+
+    /// Visibility stored on a track; tracks uploaded before it was recorded are private.
+    pub fn from_stored(value: Option<&str>) -> Self {
+        match value {
+            Some("public") => ObjectVisibility::Public,
+            _ => ObjectVisibility::Private,
+        }
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct R2Credentials {
     pub account_id: String,
@@ -65,6 +113,72 @@ pub struct R2DeleteResult {
     pub error: Option<String>,
 }
 
+/// Object count and byte total for one key prefix.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct PrefixUsage {
+    pub prefix: String,
+    pub object_count: u64,
+    pub total_bytes: u64,
+}
+
+/// Storage used by the bucket, broken down by the key prefixes the app writes to.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct StorageUsage {
+    pub total_objects: u64,
+    pub total_bytes: u64,
+    pub by_prefix: Vec<PrefixUsage>,
+    /// Objects that don't fall under any of the known prefixes
+    pub other: PrefixUsage,
+}
+
+/// Key prefixes reported individually by `get_storage_usage`.
+pub const STORAGE_USAGE_PREFIXES: [&str; 5] = ["tracks/original", "tracks/aac", "albums/artwork", "tracks/flac", "tracks/mp3"];
+
+impl StorageUsage {
+    fn new() -> Self {
+        Self {
+            by_prefix: STORAGE_USAGE_PREFIXES.iter()
+                .map(|prefix| PrefixUsage { prefix: prefix.to_string(), ..Default::default() })
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    /// Adds one object to the totals and to the bucket for its prefix.
+    fn record(&mut self, key: &str, size: u64) {
+        self.total_objects += 1;
+        self.total_bytes += size;
+        let bucket = self.by_prefix.iter_mut()
+            .find(|usage| key.starts_with(&format!("{}/", usage.prefix)))
+            .unwrap_or(&mut self.other);
+        bucket.object_count += 1;
+        bucket.total_bytes += size;
+    }
+}
+
+/// User metadata naming the key an object was copied from by `copy_object_verified`.
+const COPIED_FROM_METADATA: &str = "copied-from";
+
+/// What a HEAD request says about an object, to tell copies of it apart from other objects.
+#[derive(Debug, Clone, Default, PartialEq)]
+struct ObjectHead {
+    size: i64,
+    metadata: HashMap<String, String>,
+    content_type: Option<String>,
+    content_disposition: Option<String>,
+    content_encoding: Option<String>,
+    cache_control: Option<String>,
+}
+
+impl ObjectHead {
+    /// Whether this object is a copy of `source` (found at `from`) made by
+    /// `copy_object_verified`: same size, and marked as copied from `from`. ETags can't
+    /// tell, since a copy of a multipart upload gets a different one.
+    fn is_copy_of(&self, source: &ObjectHead, from: &str) -> bool {
+        self.size == source.size && self.metadata.get(COPIED_FROM_METADATA).map(String::as_str) == Some(from)
+    }
+}
+
 #[derive(Clone)]
 pub struct R2Client {
     client: Client,
@@ -77,6 +191,16 @@ impl R2Client {
         Self { client, bucket_name }
     }
 
+    /// Builds a client from the configured `R2State`, failing if credentials haven't been set up.
+    pub async fn from_state(r2_state: &R2State) -> Result<Self, CommandError> {
+        let client_lock = r2_state.client.lock().await;
+        let bucket_lock = r2_state.bucket_name.lock().await;
+        match (client_lock.as_ref(), bucket_lock.as_ref()) {
+            (Some(client), Some(bucket_name)) => Ok(Self::new(client.clone(), bucket_name.clone())),
+            _ => Err(CommandError::Configuration("R2 client not initialized".to_string())),
+        }
+    }
+
     pub async fn test_connection(&self) -> R2ConnectionResult {
         match self.client.list_objects_v2().bucket(&self.bucket_name).send().await {
             Ok(_) => R2ConnectionResult {
@@ -90,27 +214,67 @@ impl R2Client {
         }
     }
     
-    /// List all objects in the bucket
-    pub async fn list_objects(&self) -> R2Result<Vec<String>> {
-        let resp = self.client.list_objects_v2()
-            .bucket(&self.bucket_name)
-            .send()
-            .await
-            .map_err(|e| R2Error::AwsError(e.to_string()))?;
-            
+    /// Checks that the bucket exists and the credentials can list it.
+    pub async fn check_bucket_access(&self) -> R2Result<()> {
+        self.client.list_objects_v2().bucket(&self.bucket_name).max_keys(1).send().await
+            .map(|_| ())
+            .map_err(|e| R2Error::BucketNotFound(format!("{}: {}", self.bucket_name, e)))
+    }
+
+    /// List all object keys in the bucket, optionally limited to those under `prefix`.
+    /// Follows continuation tokens so buckets with more than 1000 objects are listed in full.
+    pub async fn list_objects(&self, prefix: Option<&str>) -> R2Result<Vec<String>> {
         let mut keys = Vec::new();
-        
-        if let Some(contents) = resp.contents {
-            for object in contents {
-                if let Some(key) = object.key {
-                    keys.push(key);
-                }
+        let mut continuation_token: Option<String> = None;
+
+        loop {
+            let resp = self.client.list_objects_v2()
+                .bucket(&self.bucket_name)
+                .set_prefix(prefix.map(String::from))
+                .set_continuation_token(continuation_token.take())
+                .send()
+                .await
+                .map_err(|e| R2Error::AwsError(e.to_string()))?;
+
+            keys.extend(resp.contents().iter().filter_map(|object| object.key().map(String::from)));
+
+            match resp.next_continuation_token() {
+                Some(token) if resp.is_truncated().unwrap_or(false) => continuation_token = Some(token.to_string()),
+                _ => break,
             }
         }
-        
+
         Ok(keys)
     }
     
+    /// Sum object sizes across the whole bucket, following continuation tokens
+    pub async fn storage_usage(&self) -> R2Result<StorageUsage> {
+        let mut usage = StorageUsage::new();
+        let mut continuation_token: Option<String> = None;
+
+        loop {
+            let resp = self.client.list_objects_v2()
+                .bucket(&self.bucket_name)
+                .set_continuation_token(continuation_token.take())
+                .send()
+                .await
+                .map_err(|e| R2Error::AwsError(e.to_string()))?;
+
+            for object in resp.contents() {
+                if let Some(key) = object.key() {
+                    usage.record(key, object.size().unwrap_or(0).max(0) as u64);
+                }
+            }
+
+            match resp.next_continuation_token() {
+                Some(token) if resp.is_truncated().unwrap_or(false) => continuation_token = Some(token.to_string()),
+                _ => break,
+            }
+        }
+
+        Ok(usage)
+    }
+
     /// Upload data to the bucket
     pub async fn upload_object(&self, key: &str, data: Vec<u8>, content_type: &str) -> R2Result<()> {
         let stream = ByteStream::from(data);
@@ -142,6 +306,73 @@ impl R2Client {
         Ok(bytes.to_vec())
     }
     
+    /// Opens an object for reading chunk by chunk, so large files are never held in
+    /// memory. `None` if the object doesn't exist.
+    pub async fn get_object_stream(&self, key: &str) -> R2Result<Option<ByteStream>> {
+        match self.client.get_object().bucket(&self.bucket_name).key(key).send().await {
+            Ok(resp) => Ok(Some(resp.body)),
+            Err(err) if err.as_service_error().is_some_and(|e| e.is_no_such_key()) => Ok(None),
+            Err(err) => Err(R2Error::AwsError(err.to_string())),
+        }
+    }
+
+    /// Streams an object to a file at `path`, writing to a `.part` file that is renamed
+    /// once complete. Encrypted originals are decrypted on the way when `decrypt_with`
+    /// is given, and the transfer is paced by `limiter` when one is given. Returns the
+    /// bytes written, or `None` if the object doesn't exist.
+    pub async fn download_to_path(
+        &self,
+        key: &str,
+        path: &std::path::Path,
+        decrypt_with: Option<&[u8; crate::core::encryption::KEY_LEN]>,
+        limiter: Option<&BandwidthLimiter>,
+    ) -> R2Result<Option<u64>> {
+        use tokio::io::AsyncWriteExt;
+
+        let Some(mut body) = self.get_object_stream(key).await? else {
+            return Ok(None);
+        };
+        let part_path = path.with_extension(match path.extension() {
+            Some(extension) => format!("{}.part", extension.to_string_lossy()),
+            None => "part".to_string(),
+        });
+        let write_error = |e: std::io::Error| R2Error::FileWriteError(format!("{}: {}", path.display(), e));
+        let result: R2Result<u64> = async {
+            let mut file = tokio::fs::File::create(&part_path).await.map_err(write_error)?;
+            let mut decryptor = decrypt_with.map(crate::core::encryption::Decryptor::new);
+            let mut plain = Vec::new();
+            let mut written = 0u64;
+            while let Some(chunk) = body.try_next().await.map_err(|e| R2Error::Other(format!("Download interrupted: {}", e)))? {
+                if let Some(wait) = limiter.map(|limiter| limiter.reserve(chunk.len())).filter(|wait| !wait.is_zero()) {
+                    tokio::time::sleep(wait).await;
+                }
+                let data = match decryptor.as_mut() {
+                    Some(decryptor) => {
+                        plain.clear();
+                        decryptor.update(&chunk, &mut plain).map_err(|e| R2Error::Other(e.to_string()))?;
+                        &plain[..]
+                    }
+                    None => &chunk[..],
+                };
+                file.write_all(data).await.map_err(write_error)?;
+                written += data.len() as u64;
+            }
+            if let Some(decryptor) = decryptor {
+                plain.clear();
+                decryptor.finish(&mut plain).map_err(|e| R2Error::Other(e.to_string()))?;
+                file.write_all(&plain).await.map_err(write_error)?;
+                written += plain.len() as u64;
+            }
+            file.flush().await.map_err(write_error)?;
+            tokio::fs::rename(&part_path, path).await.map_err(write_error)?;
+            Ok(written)
+        }.await;
+        if result.is_err() {
+            let _ = tokio::fs::remove_file(&part_path).await;
+        }
+        result.map(Some)
+    }
+
     /// Delete an object from the bucket
     pub async fn delete_object(&self, key: &str) -> R2Result<()> {
         self.client.delete_object()
@@ -184,25 +415,255 @@ impl R2Client {
         Ok(())
     }
     
+    /// Moves an object to a new key in the same bucket with `copy_object_verified`. The
+    /// source is only deleted once the copy is confirmed.
+    pub async fn move_object(&self, from: &str, to: &str) -> R2Result<()> {
+        if from == to {
+            return Ok(());
+        }
+        self.copy_object_verified(from, to, false).await?;
+        self.delete_object(from).await
+    }
+
+    /// Copies an object to a new key in the same bucket with a server-side copy, then
+    /// checks that the copy exists with the source's size. The copy keeps the source's
+    /// headers and metadata and is marked with the key it came from, so an earlier copy
+    /// left by an interrupted run is recognised and reused. Any other object at `to` is
+    /// only replaced with `overwrite`.
+    pub async fn copy_object_verified(&self, from: &str, to: &str, overwrite: bool) -> R2Result<()> {
+        let source = self.object_head(from).await?
+            .ok_or_else(|| R2Error::Other(format!("Object {} does not exist", from)))?;
+        match self.object_head(to).await? {
+            Some(existing) if existing.is_copy_of(&source, from) => return Ok(()),
+            Some(_) if !overwrite => return Err(R2Error::Other(format!("An object already exists at {}", to))),
+            _ => {}
+        }
+
+        let mut metadata = source.metadata.clone();
+        metadata.insert(COPIED_FROM_METADATA.to_string(), from.to_string());
+        self.client.copy_object()
+            .bucket(&self.bucket_name)
+            .copy_source(copy_source(&self.bucket_name, from))
+            .key(to)
+            .metadata_directive(MetadataDirective::Replace)
+            .set_metadata(Some(metadata))
+            .set_content_type(source.content_type.clone())
+            .set_content_disposition(source.content_disposition.clone())
+            .set_content_encoding(source.content_encoding.clone())
+            .set_cache_control(source.cache_control.clone())
+            .send()
+            .await
+            .map_err(|e| R2Error::AwsError(e.to_string()))?;
+
+        match self.object_size(to).await? {
+            Some(copied) if copied == source.size => Ok(()),
+            copied => Err(R2Error::Other(format!(
+                "Copy of {} to {} could not be verified ({} of {} bytes); the source was kept",
+                from, to, copied.unwrap_or(0), source.size
+            ))),
+        }
+    }
+
+    /// Sets and removes user-defined metadata on an object by copying it onto itself with
+    /// the metadata replaced. Its content type and other headers are kept, and metadata
+    /// keys not named are left as they are. Costs a HEAD and a COPY; returns false if the
+    /// object doesn't exist.
+    pub async fn update_object_metadata(&self, key: &str, set: &HashMap<String, String>, remove: &[&str]) -> R2Result<bool> {
+        let head = match self.client.head_object().bucket(&self.bucket_name).key(key).send().await {
+            Ok(head) => head,
+            Err(err) if err.raw_response().map(|resp| resp.status().as_u16()) == Some(404) => return Ok(false),
+            Err(err) => return Err(R2Error::AwsError(err.to_string())),
+        };
+        let mut metadata = head.metadata().cloned().unwrap_or_default();
+        metadata.retain(|name, _| !remove.contains(&name.as_str()));
+        metadata.extend(set.iter().map(|(name, value)| (name.clone(), value.clone())));
+
+        self.client.copy_object()
+            .bucket(&self.bucket_name)
+            .copy_source(copy_source(&self.bucket_name, key))
+            .key(key)
+            .metadata_directive(MetadataDirective::Replace)
+            .set_metadata(Some(metadata))
+            .set_content_type(head.content_type().map(String::from))
+            .set_content_disposition(head.content_disposition().map(String::from))
+            .set_content_encoding(head.content_encoding().map(String::from))
+            .set_cache_control(head.cache_control().map(String::from))
+            .send()
+            .await
+            .map_err(|e| R2Error::AwsError(e.to_string()))?;
+        Ok(true)
+    }
+
     /// Check if an object exists
     pub async fn object_exists(&self, key: &str) -> R2Result<bool> {
-        match self.client.head_object()
+        Ok(self.object_size(key).await?.is_some())
+    }
+
+    /// Whether `to` holds an object other than an earlier `copy_object_verified` copy of
+    /// `from`, i.e. whether copying `from` there would replace something.
+    pub async fn holds_other_object(&self, from: &str, to: &str) -> R2Result<bool> {
+        let Some(existing) = self.object_head(to).await? else { return Ok(false) };
+        Ok(match self.object_head(from).await? {
+            Some(source) => !existing.is_copy_of(&source, from),
+            None => true,
+        })
+    }
+
+    /// Presigned GET URL for an object, valid for `expires_in`.
+    pub async fn presign_get(&self, key: &str, expires_in: std::time::Duration) -> R2Result<String> {
+        let config = aws_sdk_s3::presigning::PresigningConfig::expires_in(expires_in)
+            .map_err(|e| R2Error::Other(format!("Invalid presigning expiry: {}", e)))?;
+        let request = self.client.get_object()
+            .bucket(&self.bucket_name)
+            .key(key)
+            .presigned(config)
+            .await
+            .map_err(|e| R2Error::AwsError(e.to_string()))?;
+        Ok(request.uri().to_string())
+    }
+
+    /// The underlying S3 client, for uploads that need more than `upload_object`.
+    pub fn s3_client(&self) -> &Client {
+        &self.client
+    }
+
+    pub fn bucket_name(&self) -> &str {
+        &self.bucket_name
+    }
+
+    /// A client with the same credentials for another bucket.
+    pub fn with_bucket(&self, bucket_name: &str) -> Self {
+        Self::new(self.client.clone(), bucket_name.to_string())
+    }
+
+    /// The client for the bucket a track's objects are in: its `r2_bucket`, or this
+    /// client's bucket for tracks stored before bucket overrides.
+    pub fn for_track(&self, track_doc: &Document) -> Self {
+        match track_doc.get_str("r2_bucket") {
+            Ok(bucket) if !bucket.is_empty() && bucket != self.bucket_name => self.with_bucket(bucket),
+            _ => self.clone(),
+        }
+    }
+
+    /// Part of an object for an HTTP `Range` value such as `bytes=0-1023`, or the whole
+    /// object without one. `None` if the object doesn't exist.
+    pub async fn download_range(&self, key: &str, range: Option<&str>) -> R2Result<Option<ObjectRange>> {
+        let resp = match self.client.get_object()
             .bucket(&self.bucket_name)
             .key(key)
+            .set_range(range.map(String::from))
             .send()
             .await
         {
-            Ok(_) => Ok(true),
-            Err(err) => {
-                // Check if it's a "not found" error
-                if err.to_string().contains("404") {
-                    Ok(false)
-                } else {
-                    Err(R2Error::AwsError(err.to_string()))
-                }
+            Ok(resp) => resp,
+            Err(err) if err.as_service_error().is_some_and(|e| e.is_no_such_key()) => return Ok(None),
+            Err(err) if err.raw_response().map(|resp| resp.status().as_u16()) == Some(416) => {
+                return Err(R2Error::InvalidRange(range.unwrap_or_default().to_string()));
+            }
+            Err(err) => return Err(R2Error::AwsError(err.to_string())),
+        };
+        let content_range = resp.content_range().map(String::from);
+        let content_type = resp.content_type().map(String::from);
+        let bytes = resp.body.collect().await
+            .map_err(|e| R2Error::Other(format!("Failed to read object body: {}", e)))?
+            .to_vec();
+        Ok(Some(ObjectRange { bytes, content_range, content_type }))
+    }
+
+    /// Downloads an object unless its ETag still matches `etag`. Without an ETag this is
+    /// a plain download that also reports the object's ETag.
+    pub async fn get_object_if_modified(&self, key: &str, etag: Option<&str>) -> R2Result<ConditionalObject> {
+        let resp = match self.client.get_object()
+            .bucket(&self.bucket_name)
+            .key(key)
+            .set_if_none_match(etag.map(String::from))
+            .send()
+            .await
+        {
+            Ok(resp) => resp,
+            // The SDK reports 304 Not Modified as an error
+            Err(err) if err.raw_response().map(|resp| resp.status().as_u16()) == Some(304) => {
+                return Ok(ConditionalObject::NotModified);
             }
+            Err(err) => return Err(R2Error::AwsError(err.to_string())),
+        };
+        let etag = resp.e_tag().map(String::from);
+        let bytes = resp.body.collect().await
+            .map_err(|e| R2Error::Other(format!("Failed to read object body: {}", e)))?
+            .to_vec();
+        Ok(ConditionalObject::Modified { bytes, etag })
+    }
+
+    /// Size in bytes of an object, or `None` if it doesn't exist
+    pub async fn object_size(&self, key: &str) -> R2Result<Option<i64>> {
+        Ok(self.object_head(key).await?.map(|head| head.size))
+    }
+
+    async fn object_head(&self, key: &str) -> R2Result<Option<ObjectHead>> {
+        match self.client.head_object()
+            .bucket(&self.bucket_name)
+            .key(key)
+            .send()
+            .await
+        {
+            Ok(resp) => Ok(Some(ObjectHead {
+                size: resp.content_length().unwrap_or(0),
+                metadata: resp.metadata().cloned().unwrap_or_default(),
+                content_type: resp.content_type().map(String::from),
+                content_disposition: resp.content_disposition().map(String::from),
+                content_encoding: resp.content_encoding().map(String::from),
+                cache_control: resp.cache_control().map(String::from),
+            })),
+            // HEAD responses have no body, so a missing key surfaces as a bare 404 status
+            Err(err) if err.raw_response().map(|resp| resp.status().as_u16()) == Some(404) => Ok(None),
+            Err(err) => Err(R2Error::AwsError(err.to_string())),
+        }
+    }
+}
+
+/// The `x-amz-copy-source` value for an object: bucket and key, URL-encoded except for
+/// the slashes between key segments.
+fn copy_source(bucket: &str, key: &str) -> String {
+    let mut encoded = String::with_capacity(bucket.len() + key.len() + 1);
+    encoded.push_str(bucket);
+    encoded.push('/');
+    for byte in key.bytes() {
+        if byte.is_ascii_alphanumeric() || b"-_.~/".contains(&byte) {
+            encoded.push(byte as char);
+        } else {
+            encoded.push_str(&format!("%{:02X}", byte));
         }
     }
+    encoded
+}
+
+/// Result of `R2Client::get_object_if_modified`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConditionalObject {
+    /// The object still has the given ETag
+    NotModified,
+    Modified { bytes: Vec<u8>, etag: Option<String> },
+}
+
+/// Bytes returned by `R2Client::download_range`.
+#[derive(Debug, Clone)]
+pub struct ObjectRange {
+    pub bytes: Vec<u8>,
+    /// `bytes start-end/total`, present when a range was requested
+    pub content_range: Option<String>,
+    pub content_type: Option<String>,
+}
+
+/// Summarize how much R2 storage the library is using
+#[tauri::command]
+pub async fn get_storage_usage(r2_state: State<'_, R2State>) -> Result<StorageUsage, CommandError> {
+    let r2_client = R2Client::from_state(&r2_state).await?;
+
+    log::info!("Calculating storage usage for bucket '{}'", r2_client.bucket_name);
+    let usage = r2_client.storage_usage().await
+        .map_err(|e| CommandError::Storage(format!("Failed to list bucket objects: {}", e)))?;
+    log::info!("Bucket holds {} objects totalling {} bytes", usage.total_objects, usage.total_bytes);
+    Ok(usage)
 }
 
 #[tauri::command]
@@ -461,4 +922,128 @@ pub async fn delete_files(r2_client: &R2Client, file_keys: &[String]) -> Result<
             Err(R2Error::AwsError(e.to_string()))
         }
     }
-}
\ No newline at end of file
+}
+
+/// Endpoint of the account's R2 API, unless another one is given.
+pub fn r2_endpoint(account_id: &str, endpoint: &str) -> String {
+    if endpoint.trim().is_empty() {
+        format!("https://{}.r2.cloudflarestorage.com", account_id.trim())
+    } else {
+        endpoint.trim().to_string()
+    }
+}
+
+/// What an R2 error code usually means for credentials entered by hand.
+fn credential_error_hint(code: &str) -> Option<&'static str> {
+    match code {
+        "InvalidAccessKeyId" => Some("the access key ID is not recognised"),
+        "SignatureDoesNotMatch" => Some("the secret access key does not belong to this access key ID"),
+        "AccessDenied" | "Unauthorized" => Some("the API token lacks permission for this"),
+        "NoSuchBucket" => Some("the bucket does not exist in this account"),
+        _ => None,
+    }
+}
+
+/// The error code and message R2 returned, or why it could not be reached.
+fn describe_sdk_error<E, R>(err: &SdkError<E, R>) -> String
+where
+    E: ProvideErrorMetadata + std::error::Error + 'static,
+    R: std::fmt::Debug,
+{
+    if matches!(err, SdkError::DispatchFailure(_) | SdkError::TimeoutError(_)) {
+        return format!("the endpoint could not be reached ({})", DisplayErrorContext(err));
+    }
+    match err.code() {
+        Some(code) => {
+            let detail = err.message().map(|message| format!("{}: {}", code, message)).unwrap_or_else(|| code.to_string());
+            match credential_error_hint(code) {
+                Some(hint) => format!("{} ({})", hint, detail),
+                None => detail,
+            }
+        }
+        None => DisplayErrorContext(err).to_string(),
+    }
+}
+
+/// Builds an S3 client for R2 and checks it: `list_buckets` for the key pair, then a
+/// one-key `list_objects_v2` for access to the bucket. Stores nothing.
+pub async fn connect_and_verify(credentials: &R2Credentials) -> Result<Client, CommandError> {
+    let endpoint = r2_endpoint(&credentials.account_id, &credentials.endpoint);
+    let aws_creds = Credentials::new(&credentials.access_key_id, &credentials.secret_access_key, None, None, "r2-credentials");
+    let config = aws_config::defaults(aws_config::BehaviorVersion::latest())
+        .region(Region::new("auto"))
+        .endpoint_url(&endpoint)
+        .credentials_provider(aws_creds)
+        .load().await;
+    let s3_config = aws_sdk_s3::config::Builder::from(&config).force_path_style(true).build();
+    let client = Client::from_conf(s3_config);
+
+    log::info!("Testing R2 connection (list_buckets) against {}", endpoint);
+    client.list_buckets().send().await.map_err(|e| {
+        log::error!("R2 connection test failed (list_buckets): {}", DisplayErrorContext(&e));
+        CommandError::Storage(format!("R2 connection test failed: {}", describe_sdk_error(&e)))
+    })?;
+
+    log::info!("Testing R2 bucket access: {}", credentials.bucket_name);
+    client.list_objects_v2().bucket(&credentials.bucket_name).max_keys(1).send().await.map_err(|e| {
+        log::error!("R2 bucket access test failed (list_objects_v2): {}", DisplayErrorContext(&e));
+        CommandError::Storage(format!(
+            "R2 credentials seem valid but couldn't access bucket '{}': {}",
+            credentials.bucket_name, describe_sdk_error(&e)
+        ))
+    })?;
+    Ok(client)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_storage_usage_groups_by_prefix() {
+        let mut usage = StorageUsage::new();
+        usage.record("tracks/original/a.wav", 100);
+        usage.record("tracks/original/b.wav", 50);
+        usage.record("tracks/aac/a.m4a", 10);
+        usage.record("tracks/aacx/stray.bin", 1);
+        usage.record("notes.txt", 5);
+
+        assert_eq!(usage.total_objects, 5);
+        assert_eq!(usage.total_bytes, 166);
+        assert_eq!(usage.by_prefix[0].object_count, 2);
+        assert_eq!(usage.by_prefix[0].total_bytes, 150);
+        assert_eq!(usage.by_prefix[1].total_bytes, 10);
+        assert_eq!(usage.by_prefix[2].object_count, 0);
+        assert_eq!(usage.other.object_count, 2);
+        assert_eq!(usage.other.total_bytes, 6);
+    }
+
+    #[test]
+    fn test_copies_are_recognised_by_size_and_marker() {
+        let source = ObjectHead { size: 1_000, ..Default::default() };
+        let marked = |from: &str, size| ObjectHead {
+            size,
+            metadata: HashMap::from([(COPIED_FROM_METADATA.to_string(), from.to_string())]),
+            ..Default::default()
+        };
+        assert!(marked("staging/a.wav", 1_000).is_copy_of(&source, "staging/a.wav"));
+        // Cut short, copied from another key, or an unrelated object of the same size
+        assert!(!marked("staging/a.wav", 999).is_copy_of(&source, "staging/a.wav"));
+        assert!(!marked("staging/b.wav", 1_000).is_copy_of(&source, "staging/a.wav"));
+        assert!(!ObjectHead { size: 1_000, ..Default::default() }.is_copy_of(&source, "staging/a.wav"));
+    }
+
+    #[test]
+    fn test_copy_source_encodes_key() {
+        assert_eq!(copy_source("masters", "tracks/original/a.wav"), "masters/tracks/original/a.wav");
+        assert_eq!(copy_source("masters", "old/Été & co #1.wav"), "masters/old/%C3%89t%C3%A9%20%26%20co%20%231.wav");
+    }
+
+    #[test]
+    fn test_r2_endpoint() {
+        assert_eq!(r2_endpoint("acct", ""), "https://acct.r2.cloudflarestorage.com");
+        assert_eq!(r2_endpoint("acct", " https://eu.example.com "), "https://eu.example.com");
+        assert_eq!(credential_error_hint("SignatureDoesNotMatch"), Some("the secret access key does not belong to this access key ID"));
+        assert_eq!(credential_error_hint("SlowDown"), None);
+    }
+}
@@ -0,0 +1,527 @@
+//! Persistent app settings: one versioned `AppSettings` document kept in `settings.json`
+//! in the app config directory and held in the managed `SettingsState`.
+//!
+//! `update_settings` takes a partial JSON object that is merged into the current
+//! settings (RFC 7386 merge patch: objects merge key by key, `null` resets a field to its
+//! default). The result is validated as a whole before anything is saved or applied, and
+//! a `settings://changed` event lists the fields that changed. Files written by older
+//! versions are migrated on load. The settings are loaded during setup, before the
+//! frontend can run a command.
+
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, RwLock, RwLockWriteGuard};
+use tauri::{command, AppHandle, Emitter, Manager, State, Wry};
+use tokio::sync::Notify;
+
+use crate::core::db_config::DatabaseConfig;
+use crate::core::json_file::write_json_atomically;
+use crate::core::live_sync;
+use crate::core::presign::PublicUrlBase;
+use crate::core::timing::{CommandTimeouts, TimeoutSettings};
+use crate::features::upload::encryption::EncryptionSettings;
+use crate::features::upload::limits::UploadLimits;
+use crate::features::upload::{UploadState, DEFAULT_ITEM_TIMEOUT_SECS, DEFAULT_UPLOAD_CONCURRENCY, MAX_UPLOAD_CONCURRENCY};
+use crate::CommandError;
+
+/// Version of the settings schema written by this build.
+pub const SETTINGS_VERSION: u32 = 2;
+
+const SETTINGS_FILE_NAME: &str = "settings.json";
+
+/// Files next to `settings.json` that held settings before version 2, by the field that
+/// took them over. They are removed once imported.
+const LEGACY_FILES: [(&str, &str); 3] = [
+    ("limits", "upload_limits.json"),
+    ("encryption", "encryption.json"),
+    ("timeouts", "command_timeouts.json"),
+];
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AppSettings {
+    pub version: u32,
+    pub upload: UploadSettings,
+    /// Checked for every item as it is queued
+    pub limits: UploadLimits,
+    pub encryption: EncryptionSettings,
+    /// Deadlines of the operations wrapped in `with_timeout`
+    pub timeouts: CommandTimeouts,
+    /// Applied when the MongoDB client is next initialized
+    pub database: DatabaseConfig,
+    pub sync: SyncSettings,
+    pub storage: StorageSettings,
+}
+
+impl Default for AppSettings {
+    fn default() -> Self {
+        Self {
+            version: SETTINGS_VERSION,
+            upload: UploadSettings::default(),
+            limits: UploadLimits::default(),
+            encryption: EncryptionSettings::default(),
+            timeouts: CommandTimeouts::default(),
+            database: DatabaseConfig::default(),
+            sync: SyncSettings::default(),
+            storage: StorageSettings::default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct UploadSettings {
+    /// Items processed at once, from 1 to `MAX_UPLOAD_CONCURRENCY`
+    pub concurrency: usize,
+    /// Per-item timeout for transcoding and R2 uploads
+    pub item_timeout_secs: u64,
+    /// Whether originals go to R2 when a queue doesn't say; off keeps them on local storage
+    pub upload_originals: bool,
+    /// Whether cover images next to the audio files become album artwork when the tags
+    /// have none, see `features::upload::folder_art`
+    pub folder_art: bool,
+}
+
+impl Default for UploadSettings {
+    fn default() -> Self {
+        Self { concurrency: DEFAULT_UPLOAD_CONCURRENCY, item_timeout_secs: DEFAULT_ITEM_TIMEOUT_SECS, upload_originals: true, folder_art: true }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SyncSettings {
+    /// Whether catalog changes made by other editors are picked up, see `core::live_sync`
+    pub live_sync: bool,
+}
+
+impl Default for SyncSettings {
+    fn default() -> Self {
+        Self { live_sync: true }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct StorageSettings {
+    /// Public domain of the bucket that public tracks are linked through, see
+    /// `core::presign`; without one they get presigned URLs
+    pub public_base_url: Option<String>,
+}
+
+impl AppSettings {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.version != SETTINGS_VERSION {
+            return Err(format!("Settings version {} is not supported (expected {})", self.version, SETTINGS_VERSION));
+        }
+        if !(1..=MAX_UPLOAD_CONCURRENCY).contains(&self.upload.concurrency) {
+            return Err(format!("upload.concurrency must be between 1 and {}", MAX_UPLOAD_CONCURRENCY));
+        }
+        if self.upload.item_timeout_secs == 0 {
+            return Err("upload.item_timeout_secs must be at least 1 second".to_string());
+        }
+        self.limits.validate()?;
+        self.timeouts.validate()?;
+        if let Some(url) = &self.storage.public_base_url {
+            if !(url.starts_with("https://") || url.starts_with("http://")) {
+                return Err(format!("storage.public_base_url must start with https:// or http://: {}", url));
+            }
+        }
+        self.database.validate()
+    }
+}
+
+/// Managed state holding the current settings; loaded from disk at startup.
+#[derive(Debug, Default)]
+pub struct SettingsState {
+    current: RwLock<AppSettings>,
+    // Set once `init_settings` has replaced the defaults
+    loaded: AtomicBool,
+    // Wakes the callers waiting in `loaded`
+    loaded_notify: Notify,
+}
+
+impl SettingsState {
+    pub fn new(settings: AppSettings) -> Self {
+        Self { current: RwLock::new(settings), ..Default::default() }
+    }
+
+    pub fn get(&self) -> AppSettings {
+        self.current.read().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    fn write(&self) -> RwLockWriteGuard<'_, AppSettings> {
+        self.current.write().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// The settings once they have been loaded from disk. Callers that act on them once,
+    /// like the MongoDB client picking its database, use this rather than `get`.
+    pub async fn loaded(&self) -> AppSettings {
+        let loaded = self.loaded_notify.notified();
+        if !self.loaded.load(Ordering::SeqCst) {
+            loaded.await;
+        }
+        self.get()
+    }
+
+    fn set_loaded(&self, settings: AppSettings) {
+        *self.write() = settings;
+        self.loaded.store(true, Ordering::SeqCst);
+        self.loaded_notify.notify_waiters();
+    }
+}
+
+/// Old and new value of one changed setting, keyed by its dotted path in the event.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SettingChange {
+    pub old: Value,
+    pub new: Value,
+}
+
+/// Payload of `settings://changed`.
+#[derive(Debug, Clone, Serialize)]
+pub struct SettingsChanged {
+    pub changes: BTreeMap<String, SettingChange>,
+    pub settings: AppSettings,
+}
+
+// --- Patching ---
+
+/// Applies an RFC 7386 merge patch to `target` in place.
+fn merge_patch(target: &mut Value, patch: &Value) {
+    let Value::Object(patch) = patch else {
+        *target = patch.clone();
+        return;
+    };
+    if !target.is_object() {
+        *target = Value::Object(Map::new());
+    }
+    let Value::Object(target) = target else { unreachable!() };
+    for (key, value) in patch {
+        if value.is_null() {
+            target.remove(key);
+        } else {
+            merge_patch(target.entry(key.clone()).or_insert(Value::Null), value);
+        }
+    }
+}
+
+/// The settings that result from applying `patch` to `current`, validated.
+fn apply_patch(current: &AppSettings, patch: &Value) -> Result<AppSettings, String> {
+    if !patch.is_object() {
+        return Err("Settings patch must be a JSON object".to_string());
+    }
+    if patch.get("version").is_some() {
+        return Err("The settings version can't be changed".to_string());
+    }
+    let mut merged = serde_json::to_value(current).map_err(|e| e.to_string())?;
+    merge_patch(&mut merged, patch);
+    let settings: AppSettings = serde_json::from_value(merged).map_err(|e| format!("Invalid settings: {}", e))?;
+    settings.validate()?;
+    Ok(settings)
+}
+
+/// Leaf values that differ between `old` and `new`, by dotted path.
+fn diff(old: &Value, new: &Value, path: &str, changes: &mut BTreeMap<String, SettingChange>) {
+    match (old, new) {
+        (Value::Object(old), Value::Object(new)) => {
+            for key in old.keys().chain(new.keys().filter(|key| !old.contains_key(*key))) {
+                let child = if path.is_empty() { key.clone() } else { format!("{}.{}", path, key) };
+                diff(old.get(key).unwrap_or(&Value::Null), new.get(key).unwrap_or(&Value::Null), &child, changes);
+            }
+        }
+        _ if old != new => {
+            changes.insert(path.to_string(), SettingChange { old: old.clone(), new: new.clone() });
+        }
+        _ => {}
+    }
+}
+
+// --- Migration ---
+
+/// Upgrades settings written by an older version to `SETTINGS_VERSION`, one version at
+/// a time. Fails on files from a newer version rather than dropping their fields.
+/// `legacy` reads one of the `LEGACY_FILES` by name.
+fn migrate(mut value: Value, legacy: &dyn Fn(&str) -> Option<Value>) -> Result<Value, String> {
+    let mut version = value.get("version").and_then(Value::as_u64).unwrap_or(0) as u32;
+    if version > SETTINGS_VERSION {
+        return Err(format!("settings were written by a newer version of the app (version {})", version));
+    }
+    while version < SETTINGS_VERSION {
+        match version {
+            // Unversioned files predate nothing; they only gain the version field
+            0 => {}
+            // Limits, encryption and timeouts move in from their own files
+            1 => {
+                if let Value::Object(fields) = &mut value {
+                    for (field, file_name) in LEGACY_FILES {
+                        if let Some(legacy_value) = legacy(file_name) {
+                            fields.entry(field).or_insert(legacy_value);
+                        }
+                    }
+                }
+            }
+            _ => unreachable!("no migration from settings version {}", version),
+        }
+        version += 1;
+        if let Value::Object(fields) = &mut value {
+            fields.insert("version".to_string(), Value::from(version));
+        }
+    }
+    Ok(value)
+}
+
+// --- Persistence ---
+
+fn settings_path(app_handle: &AppHandle<Wry>) -> Result<PathBuf, CommandError> {
+    let dir = app_handle.path().app_config_dir()
+        .map_err(|e| CommandError::FileSystem(format!("Failed to resolve app config directory: {}", e)))?;
+    Ok(dir.join(SETTINGS_FILE_NAME))
+}
+
+fn parse_settings(json: &str, legacy: &dyn Fn(&str) -> Option<Value>) -> Result<(AppSettings, bool), String> {
+    let value: Value = serde_json::from_str(json).map_err(|e| e.to_string())?;
+    let saved_version = value.get("version").and_then(Value::as_u64);
+    let settings: AppSettings = serde_json::from_value(migrate(value, legacy)?).map_err(|e| e.to_string())?;
+    settings.validate()?;
+    Ok((settings, saved_version != Some(u64::from(SETTINGS_VERSION))))
+}
+
+fn read_legacy_file(path: &Path) -> Option<Value> {
+    let json = fs::read_to_string(path).ok()?;
+    serde_json::from_str(&json)
+        .map_err(|e| warn!("Invalid settings in {:?}, using defaults: {}", path, e))
+        .ok()
+}
+
+/// Reads the saved settings, migrating older files, and falls back to the defaults if
+/// there are none or the file can't be read.
+pub fn load_settings(app_handle: &AppHandle<Wry>) -> AppSettings {
+    let Ok(path) = settings_path(app_handle) else {
+        return AppSettings::default();
+    };
+    let json = match fs::read_to_string(&path) {
+        Ok(json) => json,
+        // Never saved, but an older version may have left its separate files
+        Err(_) if LEGACY_FILES.iter().any(|(_, file_name)| path.with_file_name(file_name).exists()) => "{}".to_string(),
+        Err(_) => return AppSettings::default(),
+    };
+    match parse_settings(&json, &|file_name| read_legacy_file(&path.with_file_name(file_name))) {
+        Ok((settings, migrated)) => {
+            if migrated {
+                info!("Migrated {:?} to settings version {}", path, SETTINGS_VERSION);
+                match save_settings(app_handle, &settings) {
+                    Ok(()) => {
+                        for (_, file_name) in LEGACY_FILES {
+                            let _ = fs::remove_file(path.with_file_name(file_name));
+                        }
+                    }
+                    Err(e) => warn!("Failed to save migrated settings: {}", e),
+                }
+            }
+            settings
+        }
+        Err(e) => {
+            warn!("Invalid settings in {:?}, using defaults: {}", path, e);
+            AppSettings::default()
+        }
+    }
+}
+
+fn save_settings(app_handle: &AppHandle<Wry>, settings: &AppSettings) -> Result<(), CommandError> {
+    let path = settings_path(app_handle)?;
+    write_json_atomically(&path, settings)?;
+    Ok(())
+}
+
+/// Pushes the settings into the state of the features that use them.
+pub async fn apply_settings(app_handle: &AppHandle<Wry>, settings: &AppSettings) {
+    let upload_state = app_handle.state::<Arc<UploadState>>();
+    upload_state.concurrency.store(settings.upload.concurrency, Ordering::SeqCst);
+    upload_state.item_timeout_secs.store(settings.upload.item_timeout_secs, Ordering::SeqCst);
+    upload_state.upload_originals.store(settings.upload.upload_originals, Ordering::SeqCst);
+    upload_state.folder_art.store(settings.upload.folder_art, Ordering::SeqCst);
+    *upload_state.limits.lock().await = settings.limits;
+    upload_state.bandwidth.set_limit_mb_per_sec(settings.limits.max_upload_mb_per_sec);
+    *upload_state.encryption.lock().await = settings.encryption;
+    app_handle.state::<TimeoutSettings>().set(settings.timeouts);
+    app_handle.state::<PublicUrlBase>().set(settings.storage.public_base_url.clone());
+}
+
+/// Loads the saved settings into `SettingsState` and the features' state. Called from
+/// setup, so no command runs with the defaults; it blocks on the state locks, which
+/// nothing holds yet.
+pub fn init_settings(app_handle: &AppHandle<Wry>) {
+    let settings = load_settings(app_handle);
+    tauri::async_runtime::block_on(apply_settings(app_handle, &settings));
+    app_handle.state::<SettingsState>().set_loaded(settings);
+}
+
+/// Merges `patch` into the settings, then saves and applies the result and emits
+/// `settings://changed`. Nothing changes if the patched settings are invalid. The
+/// commands that change a single setting go through here too, so every change is saved.
+pub async fn patch_settings(app_handle: &AppHandle<Wry>, patch: &Value) -> Result<AppSettings, CommandError> {
+    let settings_state = app_handle.state::<SettingsState>();
+    let (updated, changes) = {
+        // Held across the save so concurrent updates can't interleave on disk
+        let mut current = settings_state.write();
+        let updated = apply_patch(&current, patch).map_err(CommandError::Validation)?;
+        let mut changes = BTreeMap::new();
+        diff(
+            &serde_json::to_value(&*current).unwrap_or_default(),
+            &serde_json::to_value(&updated).unwrap_or_default(),
+            "",
+            &mut changes,
+        );
+        if changes.is_empty() {
+            return Ok(updated);
+        }
+        save_settings(app_handle, &updated)?;
+        *current = updated.clone();
+        (updated, changes)
+    };
+
+    apply_settings(app_handle, &updated).await;
+    if changes.contains_key("sync.live_sync") {
+        live_sync::restart_live_sync(app_handle);
+    }
+    info!("Settings changed: {:?}", changes.keys().collect::<Vec<_>>());
+    let _ = app_handle.emit("settings://changed", SettingsChanged { changes, settings: updated.clone() });
+    Ok(updated)
+}
+
+// --- Tauri Commands ---
+
+#[command]
+pub fn get_settings(settings_state: State<'_, SettingsState>) -> AppSettings {
+    settings_state.get()
+}
+
+/// Merges `patch` into the settings, e.g. `{"upload": {"concurrency": 2}}`, then saves
+/// and applies the result. Nothing changes if the patched settings are invalid.
+#[command]
+pub async fn update_settings(patch: Value, app_handle: AppHandle<Wry>) -> Result<AppSettings, CommandError> {
+    patch_settings(&app_handle, &patch).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::features::upload::limits::UploadLimitsUpdate;
+    use serde_json::json;
+    use std::thread;
+
+    #[test]
+    fn test_partial_update_keeps_other_fields() {
+        let current = AppSettings { upload: UploadSettings { concurrency: 3, item_timeout_secs: 120, upload_originals: false, folder_art: true }, ..Default::default() };
+        let updated = apply_patch(&current, &json!({ "upload": { "item_timeout_secs": 600 } })).unwrap();
+        assert_eq!(updated.upload, UploadSettings { concurrency: 3, item_timeout_secs: 600, upload_originals: false, folder_art: true });
+
+        // null resets a field to its default
+        let reset = apply_patch(&current, &json!({ "upload": { "concurrency": null } })).unwrap();
+        assert_eq!(reset.upload.concurrency, DEFAULT_UPLOAD_CONCURRENCY);
+
+        // set_upload_limits leaves out the limits it isn't changing
+        let limited = AppSettings { limits: UploadLimits { max_file_size_bytes: 1_000, max_upload_mb_per_sec: 2.5 }, ..Default::default() };
+        let update = UploadLimitsUpdate { max_file_size_bytes: Some(2_000), ..Default::default() };
+        let updated_limits = apply_patch(&limited, &json!({ "limits": update })).unwrap();
+        assert_eq!(updated_limits.limits, UploadLimits { max_file_size_bytes: 2_000, max_upload_mb_per_sec: 2.5 });
+
+        let mut changes = BTreeMap::new();
+        diff(&serde_json::to_value(&current).unwrap(), &serde_json::to_value(&updated).unwrap(), "", &mut changes);
+        assert_eq!(changes.keys().collect::<Vec<_>>(), vec!["upload.item_timeout_secs"]);
+        assert_eq!(changes["upload.item_timeout_secs"], SettingChange { old: json!(120), new: json!(600) });
+    }
+
+    #[test]
+    fn test_invalid_updates_are_rejected() {
+        let current = AppSettings::default();
+        assert!(apply_patch(&current, &json!({ "upload": { "concurrency": 0 } })).is_err());
+        assert!(apply_patch(&current, &json!({ "upload": { "concurrency": "two" } })).is_err());
+        assert!(apply_patch(&current, &json!({ "upload": { "retries": 3 } })).is_err());
+        assert!(apply_patch(&current, &json!({ "version": 2 })).is_err());
+        assert!(apply_patch(&current, &json!([1, 2])).is_err());
+        assert!(apply_patch(&current, &json!({ "database": { "database_name": "music.library" } })).is_err());
+        assert!(apply_patch(&current, &json!({ "database": { "database_name": "music_library_staging" } })).is_ok());
+        assert!(apply_patch(&current, &json!({ "storage": { "public_base_url": "media.example.com" } })).is_err());
+        let public = apply_patch(&current, &json!({ "storage": { "public_base_url": "https://media.example.com" } })).unwrap();
+        assert_eq!(public.storage.public_base_url.as_deref(), Some("https://media.example.com"));
+        assert_eq!(apply_patch(&public, &json!({ "storage": { "public_base_url": null } })).unwrap().storage, StorageSettings::default());
+    }
+
+    #[test]
+    fn test_migration_from_unversioned_and_newer_files() {
+        let (settings, migrated) = parse_settings(r#"{ "upload": { "concurrency": 4 } }"#, &|_| None).unwrap();
+        assert!(migrated);
+        assert_eq!((settings.version, settings.upload.concurrency), (SETTINGS_VERSION, 4));
+
+        let (_, migrated) = parse_settings(&serde_json::to_string(&AppSettings::default()).unwrap(), &|_| None).unwrap();
+        assert!(!migrated);
+        assert!(parse_settings(r#"{ "version": 99 }"#, &|_| None).is_err());
+    }
+
+    #[test]
+    fn test_version_1_imports_the_separate_files() {
+        let legacy = |file_name: &str| match file_name {
+            "upload_limits.json" => Some(json!({ "max_file_size_bytes": 1024, "max_upload_mb_per_sec": 2.5 })),
+            "encryption.json" => Some(json!({ "encrypt_originals": true })),
+            _ => None,
+        };
+        let (settings, migrated) = parse_settings(r#"{ "version": 1, "upload": { "concurrency": 2 } }"#, &legacy).unwrap();
+        assert!(migrated);
+        assert_eq!(settings.upload.concurrency, 2);
+        assert_eq!(settings.limits, UploadLimits { max_file_size_bytes: 1024, max_upload_mb_per_sec: 2.5 });
+        assert!(settings.encryption.encrypt_originals);
+        assert_eq!(settings.timeouts, CommandTimeouts::default());
+
+        // Invalid values in an old file fail the load like any invalid setting
+        let zero_limit = |file_name: &str| (file_name == "upload_limits.json").then(|| json!({ "max_file_size_bytes": 0 }));
+        assert!(parse_settings(r#"{ "version": 1 }"#, &zero_limit).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_loaded_waits_for_the_saved_settings() {
+        let state = Arc::new(SettingsState::default());
+        let waiter = tokio::spawn({
+            let state = Arc::clone(&state);
+            async move { state.loaded().await }
+        });
+        tokio::task::yield_now().await;
+        assert!(!waiter.is_finished());
+
+        let saved = AppSettings { upload: UploadSettings { concurrency: 1, ..Default::default() }, ..Default::default() };
+        state.set_loaded(saved);
+        assert_eq!(waiter.await.unwrap().upload.concurrency, 1);
+        assert_eq!(state.loaded().await.upload.concurrency, 1);
+    }
+
+    #[test]
+    fn test_readers_never_see_a_partial_write() {
+        let initial = AppSettings { upload: UploadSettings { concurrency: 1, item_timeout_secs: 100, ..Default::default() }, ..Default::default() };
+        let state = Arc::new(SettingsState::new(initial));
+        let readers: Vec<_> = (0..4).map(|_| {
+            let state = Arc::clone(&state);
+            thread::spawn(move || {
+                for _ in 0..1_000 {
+                    let upload = state.get().upload;
+                    // Writers always change both fields together
+                    assert_eq!(upload.item_timeout_secs, upload.concurrency as u64 * 100);
+                }
+            })
+        }).collect();
+
+        for concurrency in (1..=MAX_UPLOAD_CONCURRENCY).cycle().take(500) {
+            let mut current = state.write();
+            let patch = json!({ "upload": { "concurrency": concurrency, "item_timeout_secs": concurrency * 100 } });
+            *current = apply_patch(&current, &patch).unwrap();
+        }
+        for reader in readers {
+            reader.join().unwrap();
+        }
+    }
+}
@@ -0,0 +1,223 @@
+//! Timeouts and timing for long-running commands.
+//!
+//! `with_timeout` races an operation against a deadline and records how long it took in
+//! a ring buffer of recent timings, which `get_command_timings` summarizes per label so
+//! slow paths can be found from real use. The deadlines are part of the app settings,
+//! under `timeouts` (see `core::settings`).
+
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, VecDeque};
+use std::fmt;
+use std::future::Future;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use serde_json::json;
+use tauri::{command, AppHandle, State, Wry};
+
+use crate::core::settings::patch_settings;
+use crate::CommandError;
+
+/// Timings kept for `get_command_timings`; older ones are dropped.
+const MAX_TIMING_SAMPLES: usize = 1000;
+
+static TIMINGS: Mutex<VecDeque<TimingSample>> = Mutex::new(VecDeque::new());
+
+#[derive(Debug, Clone)]
+struct TimingSample {
+    label: String,
+    elapsed: Duration,
+    timed_out: bool,
+}
+
+/// Deadlines, in seconds, for the kinds of operations wrapped in `with_timeout`.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CommandTimeouts {
+    /// Catalog listing and search
+    pub query_secs: u64,
+    /// Whole-catalog checks such as the integrity check
+    pub audit_secs: u64,
+    /// Creating and testing the R2 client
+    pub connect_secs: u64,
+}
+
+impl Default for CommandTimeouts {
+    fn default() -> Self {
+        Self { query_secs: 60, audit_secs: 30 * 60, connect_secs: 30 }
+    }
+}
+
+impl CommandTimeouts {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.query_secs == 0 || self.audit_secs == 0 || self.connect_secs == 0 {
+            return Err("Timeouts must be at least 1 second".to_string());
+        }
+        Ok(())
+    }
+
+    pub fn query(&self) -> Duration {
+        Duration::from_secs(self.query_secs)
+    }
+
+    pub fn audit(&self) -> Duration {
+        Duration::from_secs(self.audit_secs)
+    }
+
+    pub fn connect(&self) -> Duration {
+        Duration::from_secs(self.connect_secs)
+    }
+}
+
+/// Managed state holding the current deadlines; loaded from disk at startup.
+#[derive(Debug, Default)]
+pub struct TimeoutSettings(Mutex<CommandTimeouts>);
+
+impl TimeoutSettings {
+    pub fn get(&self) -> CommandTimeouts {
+        *self.0.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    pub fn set(&self, timeouts: CommandTimeouts) {
+        *self.0.lock().unwrap_or_else(|e| e.into_inner()) = timeouts;
+    }
+}
+
+/// Returned by `with_timeout` when the operation missed its deadline.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OperationTimedOut {
+    pub label: String,
+    pub after: Duration,
+}
+
+impl fmt::Display for OperationTimedOut {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "operation timed out: {} after {}s", self.label, self.after.as_secs())
+    }
+}
+
+impl From<OperationTimedOut> for CommandError {
+    fn from(timed_out: OperationTimedOut) -> Self {
+        CommandError::Unexpected(timed_out.to_string())
+    }
+}
+
+fn record_timing(label: &str, elapsed: Duration, timed_out: bool) {
+    let mut timings = TIMINGS.lock().unwrap_or_else(|e| e.into_inner());
+    if timings.len() == MAX_TIMING_SAMPLES {
+        timings.pop_front();
+    }
+    timings.push_back(TimingSample { label: label.to_string(), elapsed, timed_out });
+}
+
+/// Runs `operation`, failing with a timeout error if it takes longer than `timeout`.
+/// The time taken is recorded under `label` whether it succeeds, fails, or times out.
+pub async fn with_timeout<T, E, F>(label: &str, timeout: Duration, operation: F) -> Result<T, E>
+where
+    F: Future<Output = Result<T, E>>,
+    E: From<OperationTimedOut>,
+{
+    let started = Instant::now();
+    let result = tokio::time::timeout(timeout, operation).await;
+    let elapsed = started.elapsed();
+    record_timing(label, elapsed, result.is_err());
+    match result {
+        Ok(result) => {
+            if elapsed > timeout / 2 {
+                info!("{} took {:?}, over half its {}s timeout", label, elapsed, timeout.as_secs());
+            }
+            result
+        }
+        Err(_) => {
+            warn!("{} timed out after {}s", label, timeout.as_secs());
+            Err(OperationTimedOut { label: label.to_string(), after: timeout }.into())
+        }
+    }
+}
+
+/// Timings recorded for one label.
+#[derive(Debug, Serialize, Clone, PartialEq)]
+pub struct TimingSummary {
+    pub label: String,
+    pub count: usize,
+    pub timed_out: usize,
+    pub p50_ms: f64,
+    pub p95_ms: f64,
+    pub max_ms: f64,
+}
+
+/// Nearest-rank percentile of sorted samples.
+fn percentile(sorted: &[Duration], percent: usize) -> Duration {
+    let rank = (sorted.len() * percent).div_ceil(100).max(1);
+    sorted[rank.min(sorted.len()) - 1]
+}
+
+fn summarize(samples: &VecDeque<TimingSample>) -> Vec<TimingSummary> {
+    let mut by_label: BTreeMap<&str, (Vec<Duration>, usize)> = BTreeMap::new();
+    for sample in samples {
+        let (durations, timed_out) = by_label.entry(&sample.label).or_default();
+        durations.push(sample.elapsed);
+        *timed_out += sample.timed_out as usize;
+    }
+    let millis = |duration: Duration| duration.as_secs_f64() * 1000.0;
+    by_label.into_iter().map(|(label, (mut durations, timed_out))| {
+        durations.sort();
+        TimingSummary {
+            label: label.to_string(),
+            count: durations.len(),
+            timed_out,
+            p50_ms: millis(percentile(&durations, 50)),
+            p95_ms: millis(percentile(&durations, 95)),
+            max_ms: millis(durations[durations.len() - 1]),
+        }
+    }).collect()
+}
+
+// --- Tauri Commands ---
+
+/// Debug command: count and p50/p95/max latency of recent timed operations, by label.
+#[command]
+pub fn get_command_timings() -> Vec<TimingSummary> {
+    summarize(&TIMINGS.lock().unwrap_or_else(|e| e.into_inner()))
+}
+
+#[command]
+pub fn get_command_timeouts(timeout_settings: State<'_, TimeoutSettings>) -> CommandTimeouts {
+    timeout_settings.get()
+}
+
+/// Saves new deadlines to the settings; they apply to operations started from now on.
+#[command]
+pub async fn set_command_timeouts(timeouts: CommandTimeouts, app_handle: AppHandle<Wry>) -> Result<CommandTimeouts, CommandError> {
+    timeouts.validate().map_err(CommandError::Validation)?;
+    patch_settings(&app_handle, &json!({ "timeouts": timeouts })).await?;
+    info!("Command timeouts set to {:?}", timeouts);
+    Ok(timeouts)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_with_timeout_expires() {
+        let result: Result<(), CommandError> = with_timeout("test_sleep", Duration::from_millis(10), async {
+            tokio::time::sleep(Duration::from_secs(5)).await;
+            Ok(())
+        }).await;
+        match result {
+            Err(CommandError::Unexpected(message)) => assert_eq!(message, "operation timed out: test_sleep after 0s"),
+            other => panic!("expected a timeout, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_summarize_percentiles() {
+        let samples: VecDeque<TimingSample> = (1..=20)
+            .map(|ms| TimingSample { label: "search".into(), elapsed: Duration::from_millis(ms), timed_out: ms == 20 })
+            .collect();
+        let summary = &summarize(&samples)[0];
+        assert_eq!((summary.count, summary.timed_out), (20, 1));
+        assert_eq!((summary.p50_ms, summary.p95_ms, summary.max_ms), (10.0, 19.0, 20.0));
+    }
+}
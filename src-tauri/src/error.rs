@@ -1,8 +1,12 @@
 use serde::{Deserialize, Serialize};
 use thiserror::Error; // Using thiserror for cleaner error definitions
+use ts_rs::TS;
 
-/// Standard error structure for Tauri command results.
-#[derive(Debug, Serialize, Deserialize, Clone, Error)]
+/// Standard error structure for Tauri command results. Serialized as
+/// `{ "code": "Validation", "message": "..." }`, the shape `safeInvoke` reads.
+#[derive(Debug, Serialize, Deserialize, Clone, Error, TS)]
+#[serde(tag = "code", content = "message")]
+#[ts(export)]
 pub enum CommandError {
     #[error("Database Error: {0}")]
     Database(String),
@@ -28,9 +32,18 @@ pub enum CommandError {
     #[error("Keychain Error: {0}")]
     Keychain(String),
 
+    #[error("Keychain Access Denied: {0}")]
+    KeychainAccessDenied(String), // The user denied the keychain prompt; retry_keychain_access asks again
+
     #[error("Not Found: {0}")]
     NotFound(String),
 
+    #[error("Original Not Uploaded: {0}")]
+    OriginalNotUploaded(String), // The original was kept on local storage; the message says where
+
+    #[error("Conflict: {0}")]
+    Conflict(String), // Clashes with an operation already in progress
+
     #[error("Operation Failed: {0}")]
     OperationFailed(String), // Generic failure
 
@@ -118,4 +131,15 @@ impl From<CommandError> for String {
     fn from(err: CommandError) -> Self {
         err.to_string() // Use the message generated by thiserror::Error
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_serializes_as_code_and_message() {
+        let json = serde_json::to_value(CommandError::NotFound("Track abc".to_string())).unwrap();
+        assert_eq!(json, serde_json::json!({ "code": "NotFound", "message": "Track abc" }));
+    }
+}
@@ -0,0 +1,241 @@
+//! Activity feed: structured records of catalog changes ("uploaded 14 tracks",
+//! "deleted album X") shown to admins in the app.
+//!
+//! Entries go into a capped `activity` collection in MongoDB, or into a local JSON lines
+//! file when MongoDB isn't available. Recording is always best-effort: failures are
+//! logged and never surface to the operation being recorded.
+
+use futures_util::stream::TryStreamExt;
+use log::warn;
+use mongodb::bson::{doc, from_document, to_document, Document};
+use mongodb::error::ErrorKind;
+use mongodb::options::{CreateCollectionOptions, FindOptions};
+use mongodb::Database;
+use serde::{Deserialize, Serialize};
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use tauri::{command, AppHandle, Emitter, Manager, State, Wry};
+
+use crate::CommandError;
+use crate::MongoState;
+use crate::core::db_config::CatalogDatabase;
+
+const ACTIVITY_COLLECTION: &str = "activity";
+const ACTIVITY_CAP_BYTES: u64 = 16 * 1024 * 1024;
+const ACTIVITY_CAP_ENTRIES: u64 = 20_000;
+
+/// Entries kept in the local fallback file before the oldest are dropped.
+const FALLBACK_CAP_ENTRIES: usize = 1_000;
+
+const DEFAULT_PAGE_SIZE: u32 = 50;
+const MAX_PAGE_SIZE: u32 = 500;
+
+/// MongoDB error code returned when creating a collection that already exists.
+const NAMESPACE_EXISTS: i32 = 48;
+
+/// Set once the capped collection is known to exist.
+static COLLECTION_READY: AtomicBool = AtomicBool::new(false);
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ActivityAction {
+    TracksUploaded,
+    MetadataUpdated,
+    TracksDeleted,
+    AlbumCreated,
+    AlbumUpdated,
+    AlbumDeleted,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ActivityEntry {
+    pub action: ActivityAction,
+    pub entity_ids: Vec<String>,
+    pub summary: String,
+    /// Milliseconds since the Unix epoch
+    pub timestamp_ms: i64,
+}
+
+impl ActivityEntry {
+    pub fn new(action: ActivityAction, entity_ids: Vec<String>, summary: impl Into<String>) -> Self {
+        Self {
+            action,
+            entity_ids,
+            summary: summary.into(),
+            timestamp_ms: chrono::Utc::now().timestamp_millis(),
+        }
+    }
+}
+
+// --- MongoDB Store ---
+
+/// Creates the capped collection on first use. Safe to call repeatedly.
+async fn ensure_collection(db: &Database) {
+    if COLLECTION_READY.load(Ordering::SeqCst) {
+        return;
+    }
+    let options = CreateCollectionOptions::builder()
+        .capped(true)
+        .size(ACTIVITY_CAP_BYTES)
+        .max(ACTIVITY_CAP_ENTRIES)
+        .build();
+    match db.create_collection(ACTIVITY_COLLECTION, options).await {
+        Ok(()) => COLLECTION_READY.store(true, Ordering::SeqCst),
+        Err(e) if matches!(*e.kind, ErrorKind::Command(ref err) if err.code == NAMESPACE_EXISTS) => {
+            COLLECTION_READY.store(true, Ordering::SeqCst)
+        }
+        // Inserting still works against an uncapped collection; try again next time
+        Err(e) => warn!("Could not create capped activity collection: {}", e),
+    }
+}
+
+async fn insert_entry(db: &Database, entry: &ActivityEntry) -> Result<(), String> {
+    ensure_collection(db).await;
+    let entry_doc = to_document(entry).map_err(|e| e.to_string())?;
+    db.collection::<Document>(ACTIVITY_COLLECTION)
+        .insert_one(entry_doc, None)
+        .await
+        .map(|_| ())
+        .map_err(|e| e.to_string())
+}
+
+// --- Local Fallback Store ---
+
+fn fallback_path(app_handle: &AppHandle<Wry>) -> Option<PathBuf> {
+    app_handle.path().app_data_dir().ok().map(|dir| dir.join("activity_log.jsonl"))
+}
+
+/// Appends an entry, trimming the file to the newest `FALLBACK_CAP_ENTRIES` entries.
+fn append_fallback(path: &Path, entry: &ActivityEntry) -> std::io::Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let line = serde_json::to_string(entry)?;
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    writeln!(file, "{}", line)?;
+
+    let entries = read_fallback(path);
+    if entries.len() > FALLBACK_CAP_ENTRIES {
+        let kept: Vec<String> = entries[entries.len() - FALLBACK_CAP_ENTRIES..]
+            .iter()
+            .filter_map(|entry| serde_json::to_string(entry).ok())
+            .collect();
+        fs::write(path, kept.join("\n") + "\n")?;
+    }
+    Ok(())
+}
+
+/// Reads all fallback entries, oldest first, skipping unreadable lines.
+fn read_fallback(path: &Path) -> Vec<ActivityEntry> {
+    fs::read_to_string(path)
+        .map(|contents| contents.lines().filter_map(|line| serde_json::from_str(line).ok()).collect())
+        .unwrap_or_default()
+}
+
+/// Newest-first page of entries older than `before_timestamp`.
+fn page_entries(mut entries: Vec<ActivityEntry>, limit: usize, before_timestamp: Option<i64>) -> Vec<ActivityEntry> {
+    if let Some(before) = before_timestamp {
+        entries.retain(|entry| entry.timestamp_ms < before);
+    }
+    entries.sort_by_key(|entry| std::cmp::Reverse(entry.timestamp_ms));
+    entries.truncate(limit);
+    entries
+}
+
+// --- Recording ---
+
+/// Records an entry and notifies the frontend with an `activity://new` event.
+/// Writes to `db` when given, falling back to the local file otherwise or on failure.
+pub async fn record_activity(app_handle: &AppHandle<Wry>, db: Option<&Database>, entry: ActivityEntry) {
+    let stored_in_db = match db {
+        Some(db) => match insert_entry(db, &entry).await {
+            Ok(()) => true,
+            Err(e) => {
+                warn!("Failed to record activity in MongoDB, using local log: {}", e);
+                false
+            }
+        },
+        None => false,
+    };
+    if !stored_in_db {
+        match fallback_path(app_handle) {
+            Some(path) => {
+                if let Err(e) = append_fallback(&path, &entry) {
+                    warn!("Failed to record activity locally: {}", e);
+                }
+            }
+            None => warn!("Failed to record activity: no app data directory"),
+        }
+    }
+    if let Err(e) = app_handle.emit("activity://new", &entry) {
+        warn!("Failed to emit activity event: {}", e);
+    }
+}
+
+// --- Tauri Commands ---
+
+/// Returns up to `limit` activity entries, newest first. Pass the `timestamp_ms` of the
+/// last entry received as `before_timestamp` to fetch the next page.
+#[command]
+pub async fn get_activity_log(
+    limit: Option<u32>,
+    before_timestamp: Option<i64>,
+    app_handle: AppHandle<Wry>,
+    mongo_state: State<'_, MongoState>,
+) -> Result<Vec<ActivityEntry>, CommandError> {
+    let limit = limit.unwrap_or(DEFAULT_PAGE_SIZE).clamp(1, MAX_PAGE_SIZE);
+
+    // Entries recorded while MongoDB was unavailable
+    let mut entries = fallback_path(&app_handle).map(|path| read_fallback(&path)).unwrap_or_default();
+
+    let client_lock = mongo_state.client.lock().await;
+    if let Some(client) = client_lock.as_ref() {
+        let filter = match before_timestamp {
+            Some(before) => doc! { "timestamp_ms": { "$lt": before } },
+            None => doc! {},
+        };
+        let options = FindOptions::builder().sort(doc! { "timestamp_ms": -1 }).limit(limit as i64).build();
+        let docs: Vec<Document> = client.catalog_database()
+            .collection::<Document>(ACTIVITY_COLLECTION)
+            .find(filter, options)
+            .await?
+            .try_collect()
+            .await?;
+        entries.extend(docs.into_iter().filter_map(|entry_doc| from_document(entry_doc).ok()));
+    }
+
+    Ok(page_entries(entries, limit as usize, before_timestamp))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    fn entry_at(timestamp_ms: i64) -> ActivityEntry {
+        ActivityEntry { timestamp_ms, ..ActivityEntry::new(ActivityAction::TracksUploaded, vec![], "test") }
+    }
+
+    #[test]
+    fn test_fallback_round_trip_and_cap() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("activity_log.jsonl");
+        for i in 0..(FALLBACK_CAP_ENTRIES as i64 + 5) {
+            append_fallback(&path, &entry_at(i)).unwrap();
+        }
+
+        let entries = read_fallback(&path);
+        assert_eq!(entries.len(), FALLBACK_CAP_ENTRIES);
+        assert_eq!(entries[0].timestamp_ms, 5);
+    }
+
+    #[test]
+    fn test_page_entries_newest_first_before_timestamp() {
+        let entries = vec![entry_at(10), entry_at(30), entry_at(20), entry_at(40)];
+        let page = page_entries(entries, 2, Some(40));
+        let timestamps: Vec<i64> = page.iter().map(|e| e.timestamp_ms).collect();
+        assert_eq!(timestamps, vec![30, 20]);
+    }
+}
@@ -0,0 +1,214 @@
+//! Repairs albums that were created more than once for the same release, e.g. "Night
+//! Drive" and "night drive " by the same artist. The startup migration only merges exact
+//! name and artist matches; this compares them case- and whitespace-insensitively.
+//!
+//! Like deleting tracks, it runs in two steps: a dry run reports the merges and a
+//! fingerprint of them, and passing that fingerprint back refuses to merge if the
+//! duplicates have changed since.
+
+use futures_util::stream::TryStreamExt;
+use log::info;
+use mongodb::bson::{doc, Bson, Document};
+use mongodb::options::FindOptions;
+use mongodb::Database;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use tauri::{command, AppHandle, State, Wry};
+
+use super::album_names::AlbumNameCache;
+use super::changes::{record_deletions, touched, CatalogKind};
+use super::migrations::{album_id_references, oldest_album};
+use super::storage::id_to_string;
+use crate::features::activity::{record_activity, ActivityAction, ActivityEntry};
+use crate::CommandError;
+use crate::MongoState;
+use crate::core::db_config::{CatalogCollections, CatalogDatabase, cursor_batch_size};
+
+/// One group of duplicates and the album they are merged into.
+#[derive(Debug, Serialize, Clone, PartialEq)]
+pub struct AlbumMerge {
+    pub canonical_id: String,
+    pub name: String,
+    pub artist: Option<String>,
+    /// Albums merged into the canonical one and deleted
+    pub merged_ids: Vec<String>,
+    /// Tracks whose `album_id` points at a merged album
+    pub tracks_repointed: u64,
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct AlbumMergeReport {
+    pub dry_run: bool,
+    /// Pass to `merge_duplicate_albums` as `expected_fingerprint` to apply this plan
+    pub fingerprint: String,
+    pub merges: Vec<AlbumMerge>,
+    pub albums_removed: u64,
+    pub tracks_repointed: u64,
+}
+
+/// Lower-cased with surrounding and repeated whitespace removed.
+fn normalize(value: &str) -> String {
+    value.split_whitespace().collect::<Vec<_>>().join(" ").to_lowercase()
+}
+
+/// Groups albums by normalized name and artist, keeping groups of two or more with the
+/// canonical (oldest) album first. Albums created for an explicit id carry a
+/// `grouping_key` and are meant to stay separate, so they are left out.
+fn duplicate_groups(albums: Vec<Document>) -> Vec<Vec<Document>> {
+    let mut groups: BTreeMap<(String, String), Vec<Document>> = BTreeMap::new();
+    for album in albums {
+        if album.get("grouping_key").is_some_and(|key| !matches!(key, Bson::Null)) {
+            continue;
+        }
+        let key = (normalize(album.get_str("name").unwrap_or_default()), normalize(album.get_str("artist").unwrap_or_default()));
+        groups.entry(key).or_default().push(album);
+    }
+    groups.into_values()
+        .filter(|group| group.len() > 1)
+        .filter_map(|mut group| {
+            let canonical_id = oldest_album(&group)?.get("_id").cloned()?;
+            let position = group.iter().position(|album| album.get("_id") == Some(&canonical_id))?;
+            group.swap(0, position);
+            Some(group)
+        })
+        .collect()
+}
+
+/// Hash of the planned merges, independent of the order albums were read in.
+fn plan_fingerprint(groups: &[Vec<Document>]) -> String {
+    let mut lines: Vec<String> = groups.iter()
+        .map(|group| {
+            let mut ids: Vec<String> = group.iter().filter_map(|album| album.get("_id").and_then(id_to_string)).collect();
+            ids[1..].sort();
+            ids.join(",")
+        })
+        .collect();
+    lines.sort();
+    let digest = Sha256::digest(lines.join("\n").as_bytes());
+    digest.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Track ids listed on the duplicates, for adding to the canonical album's `track_ids`.
+pub(super) fn listed_track_ids(duplicates: &[Document]) -> Vec<Bson> {
+    let mut track_ids: Vec<Bson> = Vec::new();
+    for id in duplicates.iter().flat_map(|album| album.get_array("track_ids").map(|ids| ids.as_slice()).unwrap_or_default()) {
+        if !track_ids.contains(id) {
+            track_ids.push(id.clone());
+        }
+    }
+    track_ids
+}
+
+async fn merge_group(db: &Database, group: &[Document], dry_run: bool) -> Result<AlbumMerge, CommandError> {
+    let (canonical, duplicates) = group.split_first()
+        .ok_or_else(|| CommandError::Unexpected("Empty album group".to_string()))?;
+    let canonical_id = canonical.get("_id").cloned().unwrap_or(Bson::Null);
+    let duplicate_ids: Vec<Bson> = duplicates.iter().filter_map(|album| album.get("_id").cloned()).collect();
+    let references: Vec<Bson> = duplicate_ids.iter().flat_map(album_id_references).collect();
+    let tracks_filter = doc! { "album_id": { "$in": references } };
+    let tracks_collection = db.tracks::<Document>();
+
+    let tracks_repointed = if dry_run {
+        tracks_collection.count_documents(tracks_filter, None).await?
+    } else {
+        let repointed = tracks_collection
+            .update_many(tracks_filter, touched(doc! { "$set": { "album_id": canonical_id.clone() } }), None)
+            .await?
+            .modified_count;
+        let albums_collection = db.albums::<Document>();
+        let track_ids = listed_track_ids(duplicates);
+        if !track_ids.is_empty() {
+            albums_collection
+                .update_one(doc! { "_id": canonical_id.clone() }, touched(doc! { "$addToSet": { "track_ids": { "$each": track_ids } } }), None)
+                .await?;
+        }
+        albums_collection.delete_many(doc! { "_id": { "$in": &duplicate_ids } }, None).await?;
+        record_deletions(db, CatalogKind::Album, duplicate_ids.iter().filter_map(id_to_string).collect()).await;
+        repointed
+    };
+
+    Ok(AlbumMerge {
+        canonical_id: id_to_string(&canonical_id).unwrap_or_default(),
+        name: canonical.get_str("name").unwrap_or_default().to_string(),
+        artist: canonical.get_str("artist").ok().map(String::from),
+        merged_ids: duplicate_ids.iter().filter_map(id_to_string).collect(),
+        tracks_repointed,
+    })
+}
+
+// --- Tauri Commands ---
+
+/// Finds albums whose name and artist differ only in case or whitespace and merges each
+/// group into its oldest album: tracks are repointed, `track_ids` merged and the other
+/// albums deleted. With `dry_run` nothing changes and the report shows what would be
+/// merged; `expected_fingerprint` from that report makes the real run refuse if the
+/// duplicates changed in between.
+#[command]
+pub async fn merge_duplicate_albums(
+    dry_run: bool,
+    expected_fingerprint: Option<String>,
+    app_handle: AppHandle<Wry>,
+    mongo_state: State<'_, MongoState>,
+    album_cache: State<'_, AlbumNameCache>,
+) -> Result<AlbumMergeReport, CommandError> {
+    let db = {
+        let client_lock = mongo_state.client.lock().await;
+        let client = client_lock.as_ref()
+            .ok_or_else(|| CommandError::Configuration("MongoDB client not initialized".to_string()))?;
+        client.catalog_database()
+    };
+    let options = FindOptions::builder()
+        .batch_size(cursor_batch_size())
+        .projection(doc! { "name": 1, "artist": 1, "date_added": 1, "track_ids": 1, "grouping_key": 1 })
+        .build();
+    let albums: Vec<Document> = db.albums::<Document>().find(None, options).await?.try_collect().await?;
+    let groups = duplicate_groups(albums);
+    let fingerprint = plan_fingerprint(&groups);
+    if !dry_run {
+        if let Some(expected) = expected_fingerprint.filter(|expected| *expected != fingerprint) {
+            return Err(CommandError::Validation(format!(
+                "The duplicate albums changed since the dry run (expected plan {}); review the merges again", expected
+            )));
+        }
+    }
+
+    let mut merges = Vec::with_capacity(groups.len());
+    for group in &groups {
+        merges.push(merge_group(&db, group, dry_run).await?);
+    }
+    let albums_removed = merges.iter().map(|merge| merge.merged_ids.len() as u64).sum();
+    let tracks_repointed = merges.iter().map(|merge| merge.tracks_repointed).sum();
+
+    if !dry_run && albums_removed > 0 {
+        let merged_ids: Vec<String> = merges.iter().flat_map(|merge| merge.merged_ids.iter().cloned()).collect();
+        for id in &merged_ids {
+            album_cache.invalidate(id);
+        }
+        info!("Merged {} duplicate albums into {} and repointed {} tracks", albums_removed, merges.len(), tracks_repointed);
+        let summary = format!("Merged {} duplicate album{}", albums_removed, if albums_removed == 1 { "" } else { "s" });
+        record_activity(&app_handle, Some(&db), ActivityEntry::new(ActivityAction::AlbumDeleted, merged_ids, summary)).await;
+    }
+    Ok(AlbumMergeReport { dry_run, fingerprint, merges, albums_removed, tracks_repointed })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mongodb::bson::DateTime;
+
+    #[test]
+    fn test_duplicate_groups_normalize_and_put_oldest_first() {
+        let albums = vec![
+            doc! { "_id": "b", "name": "Night  Drive ", "artist": "The Band", "date_added": DateTime::from_millis(2_000) },
+            doc! { "_id": "a", "name": "night drive", "artist": "the band", "date_added": DateTime::from_millis(1_000) },
+            doc! { "_id": "c", "name": "Night Drive", "artist": "The Band", "grouping_key": "explicit" },
+            doc! { "_id": "d", "name": "Night Drive", "artist": "Someone Else" },
+        ];
+        let groups = duplicate_groups(albums);
+        assert_eq!(groups.len(), 1);
+        let ids: Vec<&str> = groups[0].iter().filter_map(|album| album.get_str("_id").ok()).collect();
+        assert_eq!(ids, vec!["a", "b"]);
+        assert_ne!(plan_fingerprint(&groups), plan_fingerprint(&[]));
+    }
+}
@@ -0,0 +1,196 @@
+//! Shared cache of album id -> album name for track listings, so listing N tracks
+//! costs at most one batched `$in` query instead of N `find_one` calls.
+
+use futures_util::stream::TryStreamExt;
+use log::info;
+use mongodb::bson::{doc, oid::ObjectId, Bson, Document};
+use mongodb::options::FindOptions;
+use mongodb::Database;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tauri::{command, State};
+
+use super::storage::id_to_string;
+use crate::CommandError;
+use crate::core::db_config::{CatalogCollections, cursor_batch_size};
+
+/// How long a cached name is trusted before it is fetched again.
+pub const ALBUM_NAME_TTL: Duration = Duration::from_secs(10 * 60);
+
+/// Album names keyed by album id, with hit/miss counters for the debug command.
+#[derive(Debug)]
+pub struct AlbumNameCache {
+    entries: Mutex<HashMap<String, (String, Instant)>>,
+    ttl: Duration,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl Default for AlbumNameCache {
+    fn default() -> Self {
+        Self::with_ttl(ALBUM_NAME_TTL)
+    }
+}
+
+/// Snapshot of the cache counters.
+#[derive(Debug, Serialize, Clone, PartialEq)]
+pub struct AlbumNameCacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub entries: usize,
+    /// Fraction of lookups served from the cache, `0.0` before any lookup
+    pub hit_rate: f64,
+}
+
+impl AlbumNameCache {
+    pub fn with_ttl(ttl: Duration) -> Self {
+        Self {
+            entries: Mutex::new(HashMap::new()),
+            ttl,
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    /// Splits `ids` into names still fresh at `now` and ids that must be fetched.
+    fn lookup(&self, ids: &[String], now: Instant) -> (HashMap<String, String>, Vec<String>) {
+        let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        let mut found = HashMap::new();
+        let mut missing = Vec::new();
+        for id in ids {
+            if found.contains_key(id) || missing.contains(id) {
+                continue;
+            }
+            match entries.get(id) {
+                Some((name, cached_at)) if now.duration_since(*cached_at) < self.ttl => {
+                    found.insert(id.clone(), name.clone());
+                }
+                Some(_) => {
+                    entries.remove(id);
+                    missing.push(id.clone());
+                }
+                None => missing.push(id.clone()),
+            }
+        }
+        self.hits.fetch_add(found.len() as u64, Ordering::Relaxed);
+        self.misses.fetch_add(missing.len() as u64, Ordering::Relaxed);
+        (found, missing)
+    }
+
+    fn insert(&self, names: &HashMap<String, String>, now: Instant) {
+        let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        for (id, name) in names {
+            entries.insert(id.clone(), (name.clone(), now));
+        }
+    }
+
+    /// Drops a cached name, e.g. after the album is renamed or deleted.
+    pub fn invalidate(&self, album_id: &str) {
+        self.entries.lock().unwrap_or_else(|e| e.into_inner()).remove(album_id);
+    }
+
+    pub fn stats(&self) -> AlbumNameCacheStats {
+        let hits = self.hits.load(Ordering::Relaxed);
+        let misses = self.misses.load(Ordering::Relaxed);
+        let lookups = hits + misses;
+        AlbumNameCacheStats {
+            hits,
+            misses,
+            entries: self.entries.lock().unwrap_or_else(|e| e.into_inner()).len(),
+            hit_rate: if lookups == 0 { 0.0 } else { hits as f64 / lookups as f64 },
+        }
+    }
+}
+
+/// Returns the names of the given albums, fetching all uncached ids in a single query.
+/// Ids with no matching album are left out of the result.
+pub async fn resolve_album_names(
+    db: &Database,
+    cache: &AlbumNameCache,
+    ids: &[String],
+) -> Result<HashMap<String, String>, mongodb::error::Error> {
+    let ids: Vec<String> = ids.iter().filter(|id| !id.is_empty()).cloned().collect();
+    let (mut names, missing) = cache.lookup(&ids, Instant::now());
+    if missing.is_empty() {
+        return Ok(names);
+    }
+
+    // Albums may be keyed by ObjectId or by a plain string id
+    let id_values: Vec<Bson> = missing.iter()
+        .flat_map(|id| {
+            let oid = ObjectId::parse_str(id).ok().map(Bson::ObjectId);
+            std::iter::once(Bson::String(id.clone())).chain(oid)
+        })
+        .collect();
+    let options = FindOptions::builder().batch_size(cursor_batch_size()).projection(doc! { "_id": 1, "name": 1 }).build();
+    let album_docs: Vec<Document> = db.albums::<Document>()
+        .find(doc! { "_id": { "$in": id_values } }, options)
+        .await?
+        .try_collect()
+        .await?;
+
+    let fetched: HashMap<String, String> = album_docs.iter()
+        .filter_map(|album_doc| {
+            let id = album_doc.get("_id").and_then(id_to_string)?;
+            let name = album_doc.get_str("name").unwrap_or("Unknown Album").to_string();
+            Some((id, name))
+        })
+        .collect();
+    info!("Resolved {} of {} uncached album names", fetched.len(), missing.len());
+    cache.insert(&fetched, Instant::now());
+    names.extend(fetched);
+    Ok(names)
+}
+
+// --- Tauri Commands ---
+
+/// Returns album name cache hit/miss counters, for checking cache effectiveness.
+#[command]
+pub async fn debug_album_name_cache(cache: State<'_, AlbumNameCache>) -> Result<AlbumNameCacheStats, CommandError> {
+    Ok(cache.stats())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs.iter().map(|(id, name)| (id.to_string(), name.to_string())).collect()
+    }
+
+    #[test]
+    fn test_lookup_counts_hits_and_misses() {
+        let cache = AlbumNameCache::default();
+        let now = Instant::now();
+        cache.insert(&names(&[("a1", "First")]), now);
+
+        let ids = vec!["a1".to_string(), "a2".to_string(), "a1".to_string()];
+        let (found, missing) = cache.lookup(&ids, now);
+
+        assert_eq!(found, names(&[("a1", "First")]));
+        assert_eq!(missing, vec!["a2".to_string()]);
+        let stats = cache.stats();
+        assert_eq!((stats.hits, stats.misses, stats.entries), (1, 1, 1));
+        assert_eq!(stats.hit_rate, 0.5);
+    }
+
+    #[test]
+    fn test_expired_and_invalidated_entries_are_refetched() {
+        let cache = AlbumNameCache::with_ttl(Duration::from_secs(60));
+        let start = Instant::now();
+        cache.insert(&names(&[("a1", "First"), ("a2", "Second")]), start);
+
+        cache.invalidate("a2");
+        let (found, missing) = cache.lookup(&["a1".to_string(), "a2".to_string()], start);
+        assert!(found.contains_key("a1"));
+        assert_eq!(missing, vec!["a2".to_string()]);
+
+        let (found, missing) = cache.lookup(&["a1".to_string()], start + Duration::from_secs(61));
+        assert!(found.is_empty());
+        assert_eq!(missing, vec!["a1".to_string()]);
+        assert_eq!(cache.stats().entries, 0);
+    }
+}
@@ -0,0 +1,663 @@
+//! Album artwork upload, plus download and an on-disk thumbnail cache for the catalog
+//! grid.
+//!
+//! The ETag of the R2 object each cached file was made from is kept in `manifest.json`
+//! in the cache directory, so `prefetch_album_art` can revalidate cached artwork with
+//! conditional GETs instead of downloading it again on every start.
+
+use base64::Engine;
+use futures_util::stream::{self, StreamExt, TryStreamExt};
+use image::imageops::FilterType;
+use image::{DynamicImage, ImageFormat};
+use log::{info, warn};
+use mongodb::bson::{doc, oid::ObjectId, Bson, Document};
+use mongodb::Database;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::io::Cursor;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::SystemTime;
+use tauri::{command, AppHandle, Emitter, Manager, State, Wry};
+
+use super::changes::touched;
+use super::storage::{id_filter, id_to_string};
+use crate::core::r2::{ConditionalObject, R2Client};
+use crate::features::activity::{record_activity, ActivityAction, ActivityEntry};
+use crate::CommandError;
+use crate::{MongoState, R2State};
+use crate::core::db_config::{CatalogCollections, CatalogDatabase};
+
+/// Upper bound on the total size of the artwork cache directory.
+pub const ALBUM_ART_CACHE_MAX_BYTES: u64 = 200 * 1024 * 1024;
+
+/// Thumbnails at or below this size are also returned inline as a data URL.
+const DATA_URL_MAX_SIZE: u32 = 128;
+
+/// Largest square size the frontend may request.
+const MAX_ART_SIZE: u32 = 2048;
+
+/// Largest artwork file accepted by `upload_album_artwork`.
+pub const MAX_UPLOAD_BYTES: u64 = 10 * 1024 * 1024;
+
+/// Uploaded artwork is downscaled to fit within this many pixels on its longer side.
+const MAX_UPLOAD_DIMENSION: u32 = 1500;
+
+/// Conditional GETs `prefetch_album_art` runs at once.
+const PREFETCH_CONCURRENCY: usize = 6;
+
+const MANIFEST_FILE_NAME: &str = "manifest.json";
+
+/// Cached album artwork returned to the frontend.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct AlbumArt {
+    pub album_id: String,
+    pub local_path: String,
+    pub data_url: Option<String>,
+}
+
+/// Outcome of `upload_album_artwork`.
+#[derive(Debug, Serialize, Clone)]
+pub struct ArtworkUploadResult {
+    pub album_id: String,
+    /// R2 key stored as the album's `art_path`
+    pub key: String,
+    pub width: u32,
+    pub height: u32,
+    pub resized: bool,
+}
+
+/// Outcome of `prefetch_album_art`.
+#[derive(Debug, Serialize, Clone, Default, PartialEq)]
+pub struct ArtPrefetchResult {
+    /// Cached artwork confirmed current by its ETag
+    pub fresh: usize,
+    pub downloaded: usize,
+    /// Albums without artwork
+    pub skipped: usize,
+    pub failed: usize,
+}
+
+/// The R2 object a cached file was made from.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+struct ManifestEntry {
+    key: String,
+    etag: String,
+}
+
+/// Cache file name to the R2 object and ETag it was made from, loaded from the cache
+/// directory on first use.
+#[derive(Debug, Default)]
+pub struct AlbumArtManifest {
+    entries: Mutex<Option<HashMap<String, ManifestEntry>>>,
+}
+
+impl AlbumArtManifest {
+    fn get(&self, dir: &Path, file_name: &str) -> Option<ManifestEntry> {
+        let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        entries.get_or_insert_with(|| load_manifest(dir)).get(file_name).cloned()
+    }
+
+    /// Records the ETag of a cache file (or forgets it, without one) and saves the manifest.
+    fn record(&self, dir: &Path, file_name: &str, key: &str, etag: Option<String>) {
+        let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        let entries = entries.get_or_insert_with(|| load_manifest(dir));
+        match etag {
+            Some(etag) => entries.insert(file_name.to_string(), ManifestEntry { key: key.to_string(), etag }),
+            None => entries.remove(file_name),
+        };
+        if let Err(e) = save_manifest(dir, entries) {
+            warn!("Failed to save the artwork cache manifest: {}", e);
+        }
+    }
+}
+
+/// Artwork ready for upload.
+#[derive(Debug)]
+struct PreparedArtwork {
+    bytes: Vec<u8>,
+    width: u32,
+    height: u32,
+    resized: bool,
+}
+
+// --- Upload Helpers ---
+
+/// Image format and key extension of a supported artwork file (jpg, png, webp).
+fn artwork_format(path: &Path) -> Result<(ImageFormat, &'static str), CommandError> {
+    let extension = path.extension().and_then(|e| e.to_str()).unwrap_or_default().to_lowercase();
+    match extension.as_str() {
+        "jpg" | "jpeg" => Ok((ImageFormat::Jpeg, "jpg")),
+        "png" => Ok((ImageFormat::Png, "png")),
+        "webp" => Ok((ImageFormat::WebP, "webp")),
+        _ => Err(CommandError::Validation(format!("Unsupported artwork type '{}': use jpg, png, or webp", extension))),
+    }
+}
+
+fn artwork_key(album_id: &str, extension: &str) -> String {
+    format!("albums/artwork/{}.{}", album_id, extension)
+}
+
+/// Decodes the artwork (rejecting files whose content doesn't match their type) and,
+/// with `downscale`, shrinks it to fit within `MAX_UPLOAD_DIMENSION` in the same format.
+/// Artwork that already fits is uploaded byte for byte.
+fn prepare_artwork(bytes: Vec<u8>, format: ImageFormat, downscale: bool) -> Result<PreparedArtwork, CommandError> {
+    let image = image::load_from_memory_with_format(&bytes, format)
+        .map_err(|e| CommandError::Validation(format!("Artwork is not a valid {:?} image: {}", format, e)))?;
+    if !downscale || image.width().max(image.height()) <= MAX_UPLOAD_DIMENSION {
+        return Ok(PreparedArtwork { width: image.width(), height: image.height(), bytes, resized: false });
+    }
+
+    let mut resized = image.resize(MAX_UPLOAD_DIMENSION, MAX_UPLOAD_DIMENSION, FilterType::Lanczos3);
+    if format == ImageFormat::Jpeg {
+        // JPEG has no alpha channel
+        resized = DynamicImage::ImageRgb8(resized.to_rgb8());
+    }
+    let mut output = Cursor::new(Vec::new());
+    resized.write_to(&mut output, format)
+        .map_err(|e| CommandError::Metadata(format!("Failed to encode album artwork: {}", e)))?;
+    Ok(PreparedArtwork { width: resized.width(), height: resized.height(), bytes: output.into_inner(), resized: true })
+}
+
+// --- Cache Helpers ---
+
+/// Directory holding cached artwork files.
+pub(crate) fn cache_dir(app_handle: &AppHandle<Wry>) -> Result<PathBuf, CommandError> {
+    let base = app_handle.path().app_cache_dir()
+        .map_err(|e| CommandError::FileSystem(format!("Failed to resolve app cache directory: {}", e)))?;
+    Ok(base.join("album_art"))
+}
+
+/// Keeps album ids safe to use in file names.
+fn sanitize_id(album_id: &str) -> String {
+    album_id.chars().map(|c| if c.is_ascii_alphanumeric() { c } else { '_' }).collect()
+}
+
+/// File name for a cache entry. Resized entries are always JPEG; originals keep the
+/// extension of their R2 key.
+fn cache_file_name(album_id: &str, size: Option<u32>, r2_key: &str) -> String {
+    match size {
+        Some(size) => format!("{}_{}.jpg", sanitize_id(album_id), size),
+        None => {
+            let extension = Path::new(r2_key).extension().and_then(|e| e.to_str()).unwrap_or("img");
+            format!("{}_orig.{}", sanitize_id(album_id), extension)
+        }
+    }
+}
+
+/// Removes every cached size of an album's artwork. Call this whenever an album's
+/// artwork changes.
+pub fn invalidate_album_art_cache(cache_dir: &Path, album_id: &str) {
+    let prefix = format!("{}_", sanitize_id(album_id));
+    let entries = match fs::read_dir(cache_dir) {
+        Ok(entries) => entries,
+        Err(_) => return, // Nothing cached yet
+    };
+    for entry in entries.flatten() {
+        if entry.file_name().to_string_lossy().starts_with(&prefix) {
+            if let Err(e) = fs::remove_file(entry.path()) {
+                warn!("Failed to remove cached artwork {:?}: {}", entry.path(), e);
+            }
+        }
+    }
+    info!("Invalidated cached artwork for album {}", album_id);
+}
+
+fn load_manifest(dir: &Path) -> HashMap<String, ManifestEntry> {
+    match fs::read_to_string(dir.join(MANIFEST_FILE_NAME)) {
+        Ok(json) => serde_json::from_str(&json).unwrap_or_else(|e| {
+            warn!("Ignoring invalid artwork cache manifest: {}", e);
+            HashMap::new()
+        }),
+        Err(_) => HashMap::new(), // Nothing cached yet
+    }
+}
+
+/// Writes the manifest through a temp file so a crash never leaves a truncated file.
+fn save_manifest(dir: &Path, entries: &HashMap<String, ManifestEntry>) -> std::io::Result<()> {
+    fs::create_dir_all(dir)?;
+    let json = serde_json::to_string(entries)?;
+    let temp_path = dir.join(format!("{}.tmp", MANIFEST_FILE_NAME));
+    fs::write(&temp_path, json)?;
+    fs::rename(temp_path, dir.join(MANIFEST_FILE_NAME))
+}
+
+/// Marks a cache entry as recently used so LRU eviction keeps it.
+fn touch(path: &Path) {
+    if let Ok(file) = fs::File::options().write(true).open(path) {
+        let _ = file.set_modified(SystemTime::now());
+    }
+}
+
+/// Deletes least-recently-used files until the directory fits within `max_bytes`.
+fn evict_to_cap(dir: &Path, max_bytes: u64) -> std::io::Result<()> {
+    let mut entries: Vec<(PathBuf, u64, SystemTime)> = fs::read_dir(dir)?
+        .flatten()
+        .filter_map(|entry| {
+            let metadata = entry.metadata().ok()?;
+            if !metadata.is_file() || entry.file_name() == MANIFEST_FILE_NAME { return None; }
+            Some((entry.path(), metadata.len(), metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH)))
+        })
+        .collect();
+
+    let mut total: u64 = entries.iter().map(|(_, len, _)| len).sum();
+    if total <= max_bytes {
+        return Ok(());
+    }
+
+    entries.sort_by_key(|(_, _, modified)| *modified);
+    for (path, len, _) in entries {
+        if total <= max_bytes { break; }
+        fs::remove_file(&path)?;
+        total -= len;
+        info!("Evicted cached artwork {:?}", path);
+    }
+    Ok(())
+}
+
+/// Scales artwork to a `size` x `size` JPEG, cropping to fill the square.
+fn resize_artwork(bytes: &[u8], size: u32) -> Result<Vec<u8>, CommandError> {
+    let image = image::load_from_memory(bytes)
+        .map_err(|e| CommandError::Metadata(format!("Failed to decode album artwork: {}", e)))?;
+    let resized = DynamicImage::ImageRgb8(image.resize_to_fill(size, size, FilterType::Lanczos3).to_rgb8());
+    let mut output = Cursor::new(Vec::new());
+    resized.write_to(&mut output, ImageFormat::Jpeg)
+        .map_err(|e| CommandError::Metadata(format!("Failed to encode album artwork: {}", e)))?;
+    Ok(output.into_inner())
+}
+
+fn to_data_url(bytes: &[u8], path: &Path) -> String {
+    let mime = mime_guess::from_path(path).first_or_octet_stream();
+    format!("data:{};base64,{}", mime, base64::engine::general_purpose::STANDARD.encode(bytes))
+}
+
+/// Downloads artwork from R2 unless the cached file is still current, writes the (resized)
+/// file, and records its ETag. Returns the cached bytes and whether they were downloaded.
+async fn refresh_cache_entry(
+    r2_client: &R2Client,
+    manifest: &AlbumArtManifest,
+    dir: &Path,
+    album_id: &str,
+    size: Option<u32>,
+    art_key: &str,
+) -> Result<(PathBuf, Vec<u8>, bool), CommandError> {
+    let file_name = cache_file_name(album_id, size, art_key);
+    let cached_path = dir.join(&file_name);
+    // An ETag is only worth sending if the file it describes is still on disk
+    let known_etag = manifest.get(dir, &file_name)
+        .filter(|entry| entry.key == art_key && cached_path.is_file())
+        .map(|entry| entry.etag);
+
+    let (original, etag) = match r2_client.get_object_if_modified(art_key, known_etag.as_deref()).await
+        .map_err(|e| CommandError::Storage(format!("Failed to download album artwork: {}", e)))?
+    {
+        ConditionalObject::NotModified => {
+            touch(&cached_path);
+            return Ok((cached_path.clone(), fs::read(&cached_path)?, false));
+        }
+        ConditionalObject::Modified { bytes, etag } => (bytes, etag),
+    };
+    let bytes = match size {
+        Some(size) => tokio::task::spawn_blocking(move || resize_artwork(&original, size))
+            .await
+            .map_err(|e| CommandError::Unexpected(format!("Artwork task join error: {}", e)))??,
+        None => original,
+    };
+
+    fs::create_dir_all(dir)?;
+    fs::write(&cached_path, &bytes)?;
+    manifest.record(dir, &file_name, art_key, etag);
+    if let Err(e) = evict_to_cap(dir, ALBUM_ART_CACHE_MAX_BYTES) {
+        warn!("Failed to evict old artwork cache entries: {}", e);
+    }
+    Ok((cached_path, bytes, true))
+}
+
+fn album_art_response(album_id: &str, path: &Path, size: Option<u32>, bytes: &[u8]) -> AlbumArt {
+    let data_url = match size {
+        Some(size) if size <= DATA_URL_MAX_SIZE => Some(to_data_url(bytes, path)),
+        _ => None,
+    };
+    AlbumArt { album_id: album_id.to_string(), local_path: path.to_string_lossy().into_owned(), data_url }
+}
+
+// --- Tauri Commands ---
+
+/// Returns album artwork from the local cache, downloading (and optionally resizing)
+/// it from R2 on a miss.
+#[command]
+pub async fn get_album_art(
+    album_id: String,
+    size: Option<u32>,
+    app_handle: AppHandle<Wry>,
+    mongo_state: State<'_, MongoState>,
+    r2_state: State<'_, R2State>,
+    manifest: State<'_, AlbumArtManifest>,
+) -> Result<AlbumArt, CommandError> {
+    if let Some(size) = size {
+        if size == 0 || size > MAX_ART_SIZE {
+            return Err(CommandError::Validation(format!("Artwork size must be between 1 and {}", MAX_ART_SIZE)));
+        }
+    }
+
+    // --- Look up the artwork key on the album ---
+    let art_key = {
+        let client_lock = mongo_state.client.lock().await;
+        let client = client_lock.as_ref()
+            .ok_or_else(|| CommandError::Configuration("MongoDB client not initialized".to_string()))?;
+        let albums_collection = client.catalog_database().albums::<Document>();
+        let album_doc = albums_collection.find_one(id_filter(&album_id), None).await?
+            .ok_or_else(|| CommandError::NotFound(format!("Album not found: {}", album_id)))?;
+        match album_doc.get_str("art_path") {
+            Ok(key) if !key.is_empty() => key.to_string(),
+            _ => return Err(CommandError::NotFound(format!("Album {} has no artwork", album_id))),
+        }
+    };
+
+    // --- Serve from cache if present ---
+    let dir = cache_dir(&app_handle)?;
+    let cached_path = dir.join(cache_file_name(&album_id, size, &art_key));
+    if let Ok(bytes) = fs::read(&cached_path) {
+        touch(&cached_path);
+        return Ok(album_art_response(&album_id, &cached_path, size, &bytes));
+    }
+
+    // --- Download from R2 ---
+    let r2_client = R2Client::from_state(&r2_state).await?;
+    info!("Artwork cache miss for album {} (size {:?}), downloading {}", album_id, size, art_key);
+    let (cached_path, bytes, _) = refresh_cache_entry(&r2_client, &manifest, &dir, &album_id, size, &art_key).await?;
+    Ok(album_art_response(&album_id, &cached_path, size, &bytes))
+}
+
+/// Brings the cached artwork of many albums up to date, e.g. when the catalog grid
+/// loads. Cached files are revalidated by ETag and only downloaded again if the artwork
+/// changed in R2. An `art://ready` event carrying the `AlbumArt` is emitted as each
+/// album's file is ready; failures are logged and counted.
+#[command]
+pub async fn prefetch_album_art(
+    album_ids: Vec<String>,
+    size: Option<u32>,
+    app_handle: AppHandle<Wry>,
+    mongo_state: State<'_, MongoState>,
+    r2_state: State<'_, R2State>,
+    manifest: State<'_, AlbumArtManifest>,
+) -> Result<ArtPrefetchResult, CommandError> {
+    if let Some(size) = size {
+        if size == 0 || size > MAX_ART_SIZE {
+            return Err(CommandError::Validation(format!("Artwork size must be between 1 and {}", MAX_ART_SIZE)));
+        }
+    }
+    if album_ids.is_empty() {
+        return Ok(ArtPrefetchResult::default());
+    }
+
+    let albums_collection = {
+        let client_lock = mongo_state.client.lock().await;
+        let client = client_lock.as_ref()
+            .ok_or_else(|| CommandError::Configuration("MongoDB client not initialized".to_string()))?;
+        client.catalog_database().albums::<Document>()
+    };
+    let ids: Vec<Bson> = album_ids.iter()
+        .map(|id| ObjectId::parse_str(id).map(Bson::ObjectId).unwrap_or_else(|_| Bson::String(id.clone())))
+        .collect();
+    let albums: Vec<Document> = albums_collection
+        .find(doc! { "_id": { "$in": ids } }, None)
+        .await?
+        .try_collect()
+        .await?;
+    let art_keys: HashMap<String, String> = albums.iter()
+        .filter_map(|album| {
+            let album_id = album.get("_id").and_then(id_to_string)?;
+            let key = album.get_str("art_path").ok().filter(|key| !key.is_empty())?;
+            Some((album_id, key.to_string()))
+        })
+        .collect();
+
+    let dir = cache_dir(&app_handle)?;
+    let r2_client = R2Client::from_state(&r2_state).await?;
+    let mut result = ArtPrefetchResult { skipped: album_ids.iter().filter(|id| !art_keys.contains_key(*id)).count(), ..Default::default() };
+
+    let outcomes: Vec<Option<bool>> = stream::iter(album_ids.iter().filter_map(|id| art_keys.get(id).map(|key| (id, key))))
+        .map(|(album_id, art_key)| {
+            let (r2_client, manifest, dir, app_handle) = (&r2_client, &manifest, &dir, &app_handle);
+            async move {
+                match refresh_cache_entry(r2_client, manifest, dir, album_id, size, art_key).await {
+                    Ok((path, bytes, downloaded)) => {
+                        let _ = app_handle.emit("art://ready", album_art_response(album_id, &path, size, &bytes));
+                        Some(downloaded)
+                    }
+                    Err(e) => {
+                        warn!("Failed to prefetch artwork of album {}: {}", album_id, e);
+                        None
+                    }
+                }
+            }
+        })
+        .buffer_unordered(PREFETCH_CONCURRENCY)
+        .collect()
+        .await;
+
+    for outcome in outcomes {
+        match outcome {
+            Some(true) => result.downloaded += 1,
+            Some(false) => result.fresh += 1,
+            None => result.failed += 1,
+        }
+    }
+    info!("Prefetched album artwork: {:?}", result);
+    Ok(result)
+}
+
+/// Uploads an image file as an album's artwork to `albums/artwork/{album_id}.{ext}` and
+/// points the album's `art_path` at it, deleting artwork previously stored under another
+/// key. With `only_if_missing`, albums that already have artwork, or get it from another
+/// writer meanwhile, are left alone with a `Conflict` error. Callers drop the album's
+/// cached artwork.
+pub async fn store_album_artwork(
+    db: &Database,
+    r2_client: &R2Client,
+    album_id: &str,
+    path: &Path,
+    downscale: bool,
+    only_if_missing: bool,
+) -> Result<ArtworkUploadResult, CommandError> {
+    let (format, extension) = artwork_format(path)?;
+    let file_size = fs::metadata(path)
+        .map_err(|e| CommandError::FileSystem(format!("Cannot read artwork file {}: {}", path.display(), e)))?
+        .len();
+    if file_size > MAX_UPLOAD_BYTES {
+        return Err(CommandError::Validation(format!("Artwork is {} bytes, the limit is {} bytes", file_size, MAX_UPLOAD_BYTES)));
+    }
+
+    let albums_collection = db.albums::<Document>();
+    let album_doc = albums_collection.find_one(id_filter(album_id), None).await?
+        .ok_or_else(|| CommandError::NotFound(format!("Album not found: {}", album_id)))?;
+    let album_key_id = album_doc.get("_id").and_then(id_to_string).unwrap_or_else(|| album_id.to_string());
+    let previous_key = album_doc.get_str("art_path").ok().filter(|key| !key.is_empty()).map(String::from);
+    if only_if_missing && previous_key.is_some() {
+        return Err(CommandError::Conflict(format!("Album {} already has artwork", album_id)));
+    }
+
+    let bytes = fs::read(path)?;
+    let prepared = tokio::task::spawn_blocking(move || prepare_artwork(bytes, format, downscale))
+        .await
+        .map_err(|e| CommandError::Unexpected(format!("Artwork task join error: {}", e)))??;
+
+    let key = artwork_key(&album_key_id, extension);
+    let content_type = mime_guess::from_path(&key).first_or_octet_stream().to_string();
+    r2_client.upload_object(&key, prepared.bytes, &content_type).await
+        .map_err(|e| CommandError::Storage(format!("Failed to upload album artwork: {}", e)))?;
+
+    let mut filter = id_filter(album_id);
+    if only_if_missing {
+        filter.insert("art_path", doc! { "$in": [null, ""] });
+    }
+    let update = albums_collection
+        .update_one(filter, touched(doc! { "$set": { "art_path": &key } }), None)
+        .await?;
+    if only_if_missing && update.matched_count == 0 {
+        // Another writer stored artwork since the album was read; drop ours unless it
+        // landed on the same key
+        let current = albums_collection.find_one(id_filter(album_id), None).await?;
+        if current.as_ref().and_then(|album| album.get_str("art_path").ok()) != Some(key.as_str()) {
+            if let Err(e) = r2_client.delete_object(&key).await {
+                warn!("Failed to delete unused artwork {} of album {}: {}", key, album_id, e);
+            }
+        }
+        return Err(CommandError::Conflict(format!("Album {} got artwork from another upload", album_id)));
+    }
+    if let Some(previous_key) = previous_key.filter(|previous| *previous != key) {
+        if let Err(e) = r2_client.delete_object(&previous_key).await {
+            warn!("Failed to delete previous artwork {} of album {}: {}", previous_key, album_id, e);
+        }
+    }
+
+    info!("Uploaded artwork for album {} to {} ({}x{})", album_id, key, prepared.width, prepared.height);
+    Ok(ArtworkUploadResult { album_id: album_id.to_string(), key, width: prepared.width, height: prepared.height, resized: prepared.resized })
+}
+
+/// Uploads an image file as an album's artwork, see `store_album_artwork`. Artwork
+/// larger than 1500px is downscaled unless `downscale` is false. The local cache for the
+/// album is dropped.
+#[command]
+pub async fn upload_album_artwork(
+    album_id: String,
+    image_path: String,
+    downscale: Option<bool>,
+    app_handle: AppHandle<Wry>,
+    mongo_state: State<'_, MongoState>,
+    r2_state: State<'_, R2State>,
+) -> Result<ArtworkUploadResult, CommandError> {
+    let db = {
+        let client_lock = mongo_state.client.lock().await;
+        let client = client_lock.as_ref()
+            .ok_or_else(|| CommandError::Configuration("MongoDB client not initialized".to_string()))?;
+        client.catalog_database()
+    };
+    let r2_client = R2Client::from_state(&r2_state).await?;
+    let result = store_album_artwork(&db, &r2_client, &album_id, Path::new(&image_path), downscale.unwrap_or(true), false).await?;
+    invalidate_album_art_cache(&cache_dir(&app_handle)?, &album_id);
+
+    record_activity(&app_handle, Some(&db), ActivityEntry::new(ActivityAction::AlbumUpdated, vec![album_id], "Updated album artwork")).await;
+    Ok(result)
+}
+
+/// Drops every cached size of an album's artwork so the next `get_album_art` call
+/// downloads it again. Must be called whenever an album's artwork is replaced.
+#[command]
+pub async fn invalidate_album_art(album_id: String, app_handle: AppHandle<Wry>) -> Result<(), CommandError> {
+    let dir = cache_dir(&app_handle)?;
+    invalidate_album_art_cache(&dir, &album_id);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use tempfile::tempdir;
+
+    fn write_with_age(path: &Path, len: usize, age_secs: u64) {
+        fs::write(path, vec![0u8; len]).unwrap();
+        let file = fs::File::options().write(true).open(path).unwrap();
+        file.set_modified(SystemTime::now() - Duration::from_secs(age_secs)).unwrap();
+    }
+
+    #[test]
+    fn test_cache_file_name() {
+        assert_eq!(cache_file_name("abc123", Some(64), "albums/artwork/x.png"), "abc123_64.jpg");
+        assert_eq!(cache_file_name("abc123", None, "albums/artwork/x.png"), "abc123_orig.png");
+        assert_eq!(cache_file_name("../evil", None, "art"), "___evil_orig.img");
+    }
+
+    #[test]
+    fn test_evict_to_cap_removes_least_recently_used() {
+        let dir = tempdir().unwrap();
+        write_with_age(&dir.path().join("old.jpg"), 100, 300);
+        write_with_age(&dir.path().join("mid.jpg"), 100, 200);
+        write_with_age(&dir.path().join("new.jpg"), 100, 100);
+
+        evict_to_cap(dir.path(), 200).unwrap();
+
+        assert!(!dir.path().join("old.jpg").exists());
+        assert!(dir.path().join("mid.jpg").exists());
+        assert!(dir.path().join("new.jpg").exists());
+    }
+
+    #[test]
+    fn test_manifest_persists_etags_and_survives_eviction() {
+        let dir = tempdir().unwrap();
+        let manifest = AlbumArtManifest::default();
+        manifest.record(dir.path(), "a1_64.jpg", "albums/artwork/a1.png", Some("\"etag-1\"".to_string()));
+        manifest.record(dir.path(), "a2_64.jpg", "albums/artwork/a2.png", Some("\"etag-2\"".to_string()));
+        manifest.record(dir.path(), "a2_64.jpg", "albums/artwork/a2.png", None);
+
+        // A fresh instance reads what the first one saved
+        let reloaded = AlbumArtManifest::default();
+        assert_eq!(
+            reloaded.get(dir.path(), "a1_64.jpg"),
+            Some(ManifestEntry { key: "albums/artwork/a1.png".into(), etag: "\"etag-1\"".into() })
+        );
+        assert_eq!(reloaded.get(dir.path(), "a2_64.jpg"), None);
+
+        evict_to_cap(dir.path(), 0).unwrap();
+        assert!(dir.path().join(MANIFEST_FILE_NAME).exists());
+    }
+
+    #[test]
+    fn test_invalidate_album_art_cache_removes_all_sizes() {
+        let dir = tempdir().unwrap();
+        for name in ["a1_64.jpg", "a1_orig.png", "a10_64.jpg"] {
+            fs::write(dir.path().join(name), b"x").unwrap();
+        }
+
+        invalidate_album_art_cache(dir.path(), "a1");
+
+        assert!(!dir.path().join("a1_64.jpg").exists());
+        assert!(!dir.path().join("a1_orig.png").exists());
+        assert!(dir.path().join("a10_64.jpg").exists());
+    }
+
+    #[test]
+    fn test_artwork_format_and_key() {
+        assert_eq!(artwork_format(Path::new("/tmp/Cover.JPEG")).unwrap(), (ImageFormat::Jpeg, "jpg"));
+        assert_eq!(artwork_format(Path::new("cover.webp")).unwrap().1, "webp");
+        assert!(artwork_format(Path::new("cover.gif")).is_err());
+        assert_eq!(artwork_key("abc123", "png"), "albums/artwork/abc123.png");
+    }
+
+    #[test]
+    fn test_prepare_artwork_downscales_large_png() {
+        let fixture = |width, height| {
+            let mut png = Cursor::new(Vec::new());
+            DynamicImage::new_rgba8(width, height).write_to(&mut png, ImageFormat::Png).unwrap();
+            png.into_inner()
+        };
+
+        let prepared = prepare_artwork(fixture(3000, 1500), ImageFormat::Png, true).unwrap();
+        assert!(prepared.resized);
+        assert_eq!((prepared.width, prepared.height), (1500, 750));
+        let decoded = image::load_from_memory_with_format(&prepared.bytes, ImageFormat::Png).unwrap();
+        assert_eq!((decoded.width(), decoded.height()), (1500, 750));
+
+        // Small artwork and disabled downscaling keep the original bytes
+        let small = fixture(64, 64);
+        assert_eq!(prepare_artwork(small.clone(), ImageFormat::Png, true).unwrap().bytes, small);
+        assert!(!prepare_artwork(fixture(2000, 2000), ImageFormat::Png, false).unwrap().resized);
+        // Content must match the declared type
+        assert!(prepare_artwork(small, ImageFormat::Jpeg, true).is_err());
+    }
+
+    #[test]
+    fn test_resize_artwork_produces_square_jpeg() {
+        let source = DynamicImage::new_rgba8(40, 20);
+        let mut png = Cursor::new(Vec::new());
+        source.write_to(&mut png, ImageFormat::Png).unwrap();
+
+        let resized = resize_artwork(png.get_ref(), 16).unwrap();
+
+        let decoded = image::load_from_memory_with_format(&resized, ImageFormat::Jpeg).unwrap();
+        assert_eq!((decoded.width(), decoded.height()), (16, 16));
+    }
+}
@@ -0,0 +1,510 @@
+//! Offline snapshots of the catalog metadata for disaster recovery. `backup_catalog`
+//! writes every album and track document, R2 keys included, to one JSON file:
+//!
+//! `{ "schema_version": 1, "created_at": "...", "albums": [...], "tracks": [...] }`
+//!
+//! Documents are canonical Extended JSON so ObjectIds and dates survive the round trip.
+//! Both directions stream: documents are written as the cursor yields them, and
+//! `restore_catalog` inserts them in batches as the file is parsed. Audio in R2 is not
+//! backed up.
+
+use chrono::Utc;
+use futures_util::stream::StreamExt;
+use log::{info, warn};
+use mongodb::bson::{doc, Bson, Document};
+use mongodb::error::{BulkWriteError, BulkWriteFailure, ErrorKind};
+use mongodb::options::{FindOneOptions, FindOptions, InsertManyOptions};
+use mongodb::Collection;
+use serde::de::{self, DeserializeSeed, IgnoredAny, MapAccess, SeqAccess, Visitor};
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use tauri::{command, State};
+use tokio::sync::mpsc;
+
+use super::album_names::AlbumNameCache;
+use super::changes::{stamp, CatalogKind};
+use super::storage::id_to_string;
+use crate::core::db_config::{CatalogCollections, cursor_batch_size};
+use crate::CommandError;
+use crate::MongoState;
+
+/// Version of the backup file layout; files with another version are refused.
+pub const BACKUP_SCHEMA_VERSION: u32 = 1;
+
+/// Documents parsed ahead of the inserts during a restore.
+const RESTORE_QUEUE_CAPACITY: usize = 256;
+
+/// Documents inserted per `insert_many` during a restore.
+const RESTORE_BATCH_SIZE: usize = 500;
+
+/// Result of `backup_catalog`.
+#[derive(Debug, Serialize, Clone, PartialEq)]
+pub struct BackupSummary {
+    pub path: String,
+    pub albums: u64,
+    pub tracks: u64,
+    pub bytes: u64,
+}
+
+/// Documents of one collection handled by `restore_catalog`.
+#[derive(Debug, Serialize, Clone, Default, PartialEq)]
+pub struct RestoreCounts {
+    pub restored: u64,
+    /// Already in the catalog under the same `_id`
+    pub skipped: u64,
+    /// Rejected by another unique index: a slug or ISRC taken by another document, or an
+    /// album with the same name and artist, which the backup's tracks are filed under
+    pub conflicts: u64,
+    /// Not documents, and not inserted
+    pub invalid: u64,
+}
+
+/// Result of `restore_catalog`.
+#[derive(Debug, Serialize, Clone, Default, PartialEq)]
+pub struct RestoreSummary {
+    pub albums: RestoreCounts,
+    pub tracks: RestoreCounts,
+}
+
+impl RestoreSummary {
+    fn counts_mut(&mut self, kind: CatalogKind) -> &mut RestoreCounts {
+        match kind {
+            CatalogKind::Album => &mut self.albums,
+            CatalogKind::Track => &mut self.tracks,
+        }
+    }
+}
+
+/// Writes a backup file section by section.
+struct BackupWriter<W: Write> {
+    out: W,
+    bytes: u64,
+    in_section: bool,
+    first_in_section: bool,
+}
+
+impl<W: Write> BackupWriter<W> {
+    fn begin(out: W) -> std::io::Result<Self> {
+        let mut writer = Self { out, bytes: 0, in_section: false, first_in_section: true };
+        let created_at = serde_json::to_string(&Utc::now())?;
+        writer.write(&format!("{{\"schema_version\":{},\"created_at\":{}", BACKUP_SCHEMA_VERSION, created_at))?;
+        Ok(writer)
+    }
+
+    fn write(&mut self, text: &str) -> std::io::Result<()> {
+        self.out.write_all(text.as_bytes())?;
+        self.bytes += text.len() as u64;
+        Ok(())
+    }
+
+    /// Starts the array of `kind`'s documents, closing the previous one.
+    fn section(&mut self, kind: CatalogKind) -> std::io::Result<()> {
+        if self.in_section {
+            self.write("]")?;
+        }
+        self.in_section = true;
+        self.first_in_section = true;
+        self.write(&format!(",\"{}\":[", section_name(kind)))
+    }
+
+    fn document(&mut self, document: Document) -> std::io::Result<()> {
+        if !self.first_in_section {
+            self.write(",")?;
+        }
+        self.first_in_section = false;
+        let json = serde_json::to_string(&Bson::Document(document).into_canonical_extjson())?;
+        self.write(&json)
+    }
+
+    /// Closes the last section and the file; returns the bytes written.
+    fn finish(mut self) -> std::io::Result<u64> {
+        if self.in_section {
+            self.write("]")?;
+        }
+        self.write("}")?;
+        self.out.flush()?;
+        Ok(self.bytes)
+    }
+}
+
+fn section_name(kind: CatalogKind) -> &'static str {
+    match kind {
+        CatalogKind::Album => "albums",
+        CatalogKind::Track => "tracks",
+    }
+}
+
+/// Streams a document array into the restore queue.
+struct DocumentsSeed<'a> {
+    kind: CatalogKind,
+    sender: &'a mpsc::Sender<(CatalogKind, serde_json::Value)>,
+}
+
+impl<'de> DeserializeSeed<'de> for DocumentsSeed<'_> {
+    type Value = ();
+
+    fn deserialize<D: de::Deserializer<'de>>(self, deserializer: D) -> Result<(), D::Error> {
+        deserializer.deserialize_seq(self)
+    }
+}
+
+impl<'de> Visitor<'de> for DocumentsSeed<'_> {
+    type Value = ();
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("an array of documents")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<(), A::Error> {
+        while let Some(value) = seq.next_element::<serde_json::Value>()? {
+            self.sender.blocking_send((self.kind, value)).map_err(|_| de::Error::custom("the restore was stopped"))?;
+        }
+        Ok(())
+    }
+}
+
+/// Reads the top level of a backup file, checking the version before any documents.
+struct BackupVisitor {
+    sender: mpsc::Sender<(CatalogKind, serde_json::Value)>,
+}
+
+impl<'de> Visitor<'de> for BackupVisitor {
+    type Value = ();
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a catalog backup")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<(), A::Error> {
+        let mut version_checked = false;
+        while let Some(key) = map.next_key::<String>()? {
+            let kind = match key.as_str() {
+                "schema_version" => {
+                    let version: u32 = map.next_value()?;
+                    if version != BACKUP_SCHEMA_VERSION {
+                        return Err(de::Error::custom(format!(
+                            "schema version {} is not supported (expected {})", version, BACKUP_SCHEMA_VERSION
+                        )));
+                    }
+                    version_checked = true;
+                    continue;
+                }
+                "albums" => CatalogKind::Album,
+                "tracks" => CatalogKind::Track,
+                _ => {
+                    map.next_value::<IgnoredAny>()?;
+                    continue;
+                }
+            };
+            if !version_checked {
+                return Err(de::Error::custom("schema_version must come before the documents"));
+            }
+            map.next_value_seed(DocumentsSeed { kind, sender: &self.sender })?;
+        }
+        if !version_checked {
+            return Err(de::Error::missing_field("schema_version"));
+        }
+        Ok(())
+    }
+}
+
+/// Parses a backup file, sending its documents to `sender` as they are read. Blocking.
+fn parse_backup(path: &Path, sender: mpsc::Sender<(CatalogKind, serde_json::Value)>) -> Result<(), String> {
+    let file = File::open(path).map_err(|e| format!("Cannot open {}: {}", path.display(), e))?;
+    let mut deserializer = serde_json::Deserializer::from_reader(BufReader::new(file));
+    de::Deserializer::deserialize_map(&mut deserializer, BackupVisitor { sender }).map_err(|e| e.to_string())?;
+    deserializer.end().map_err(|e| e.to_string())
+}
+
+async fn write_collection<W: Write>(
+    writer: &mut BackupWriter<W>,
+    collection: Collection<Document>,
+    kind: CatalogKind,
+) -> Result<u64, CommandError> {
+    writer.section(kind)?;
+    let options = FindOptions::builder().batch_size(cursor_batch_size()).sort(doc! { "_id": 1 }).build();
+    let mut cursor = collection.find(None, options).await?;
+    let mut count = 0;
+    while let Some(document) = cursor.next().await {
+        writer.document(document?)?;
+        count += 1;
+    }
+    Ok(count)
+}
+
+// --- Tauri Commands ---
+
+/// Writes every album and track document to `path`, replacing the file. The backup is
+/// written next to it first, so an interrupted backup never leaves a partial file at `path`.
+#[command]
+pub async fn backup_catalog(path: String, mongo_state: State<'_, MongoState>) -> Result<BackupSummary, CommandError> {
+    let path = PathBuf::from(path.trim());
+    if path.as_os_str().is_empty() || path.is_dir() {
+        return Err(CommandError::Validation("Choose a file to write the backup to".to_string()));
+    }
+    let db = mongo_state.database().await?;
+    let partial_path = path.with_extension("json.part");
+
+    let result = async {
+        let mut writer = BackupWriter::begin(BufWriter::new(File::create(&partial_path)?))?;
+        let albums = write_collection(&mut writer, db.albums::<Document>(), CatalogKind::Album).await?;
+        let tracks = write_collection(&mut writer, db.tracks::<Document>(), CatalogKind::Track).await?;
+        let bytes = writer.finish()?;
+        fs::rename(&partial_path, &path)?;
+        Ok::<_, CommandError>(BackupSummary { path: path.display().to_string(), albums, tracks, bytes })
+    }.await;
+
+    match result {
+        Ok(summary) => {
+            info!("Backed up {} albums and {} tracks to {} ({} bytes)", summary.albums, summary.tracks, summary.path, summary.bytes);
+            Ok(summary)
+        }
+        Err(e) => {
+            let _ = fs::remove_file(&partial_path);
+            Err(e)
+        }
+    }
+}
+
+/// Whether a duplicate key error came from the `_id` index, i.e. the document itself is
+/// already in the catalog rather than clashing with another one.
+fn is_id_conflict(error: &BulkWriteError) -> bool {
+    error.message.contains("index: _id_ ")
+}
+
+/// Points a backup track at the existing album that took its album's place.
+fn remap_album_id(track: &mut Document, album_ids: &HashMap<String, Bson>) {
+    let existing = track.get("album_id").and_then(id_to_string).and_then(|id| album_ids.get(&id));
+    if let Some(existing) = existing {
+        // Tracks store the id as a string, older ones as an ObjectId; keep the same type
+        let album_id = match (track.get("album_id"), existing) {
+            (Some(Bson::String(_)), Bson::ObjectId(oid)) => Bson::String(oid.to_hex()),
+            _ => existing.clone(),
+        };
+        track.insert("album_id", album_id);
+    }
+}
+
+/// Inserts `batch` without stopping at the first failure. Returns the documents rejected
+/// by a unique index; any other error fails the batch.
+async fn insert_batch(collection: &Collection<Document>, batch: &[Document]) -> mongodb::error::Result<Vec<BulkWriteError>> {
+    let options = InsertManyOptions::builder().ordered(false).build();
+    match collection.insert_many(batch, options).await {
+        Ok(_) => Ok(Vec::new()),
+        Err(e) => match e.kind.as_ref() {
+            ErrorKind::BulkWrite(BulkWriteFailure { write_errors: Some(errors), write_concern_error: None, .. })
+                if errors.iter().all(|error| error.code == 11000) => Ok(errors.clone()),
+            _ => Err(e),
+        },
+    }
+}
+
+/// The album already in the catalog under the name, artist and grouping key of `album`.
+async fn existing_album_id(albums: &Collection<Document>, album: &Document) -> mongodb::error::Result<Option<Bson>> {
+    let (Some(name), Some(artist)) = (album.get("name"), album.get("artist")) else {
+        return Ok(None);
+    };
+    let grouping_key = album.get("grouping_key").cloned().unwrap_or(Bson::Null);
+    let filter = doc! { "name": name, "artist": artist, "grouping_key": grouping_key };
+    let options = FindOneOptions::builder().projection(doc! { "_id": 1 }).build();
+    Ok(albums.find_one(filter, options).await?.and_then(|found| found.get("_id").cloned()))
+}
+
+/// State of a running `restore_catalog`.
+#[derive(Default)]
+struct Restore {
+    summary: RestoreSummary,
+    // Backup album ids whose album was rejected, mapped to the album that already exists
+    album_ids: HashMap<String, Bson>,
+    // Albums inserted, to drop from the album name cache
+    restored_album_ids: Vec<String>,
+}
+
+impl Restore {
+    async fn insert(&mut self, collection: &Collection<Document>, albums: &Collection<Document>, kind: CatalogKind, batch: Vec<Document>) -> mongodb::error::Result<()> {
+        if batch.is_empty() {
+            return Ok(());
+        }
+        let errors = insert_batch(collection, &batch).await?;
+        let rejected: HashSet<usize> = errors.iter().map(|error| error.index).collect();
+        let counts = self.summary.counts_mut(kind);
+        counts.restored += (batch.len() - rejected.len()) as u64;
+        for error in &errors {
+            if is_id_conflict(error) {
+                counts.skipped += 1;
+                continue;
+            }
+            counts.conflicts += 1;
+            if kind != CatalogKind::Album {
+                continue;
+            }
+            let album = &batch[error.index];
+            let backup_id = album.get("_id").and_then(id_to_string);
+            if let (Some(backup_id), Some(existing_id)) = (backup_id, existing_album_id(albums, album).await?) {
+                self.album_ids.insert(backup_id, existing_id);
+            }
+        }
+        if kind == CatalogKind::Album {
+            let restored = batch.iter().enumerate().filter(|(index, _)| !rejected.contains(index));
+            self.restored_album_ids.extend(restored.filter_map(|(_, album)| album.get("_id").and_then(id_to_string)));
+        }
+        Ok(())
+    }
+}
+
+/// Inserts the documents of a `backup_catalog` file, in unordered batches. Documents
+/// already in the catalog are skipped, never overwritten, so restoring into a partly
+/// intact catalog fills the gaps. An album rejected because one with the same name and
+/// artist exists hands its tracks over to that album; backups list albums before tracks,
+/// so the mapping is known by the time the tracks are inserted. Restored documents get a
+/// fresh `updated_at` so polling clients pick them up.
+#[command]
+pub async fn restore_catalog(
+    path: String,
+    mongo_state: State<'_, MongoState>,
+    album_cache: State<'_, AlbumNameCache>,
+) -> Result<RestoreSummary, CommandError> {
+    let path = PathBuf::from(path.trim());
+    if !path.is_file() {
+        return Err(CommandError::NotFound(format!("Backup file {} not found", path.display())));
+    }
+    let db = mongo_state.database().await?;
+    let (albums, tracks) = (db.albums::<Document>(), db.tracks::<Document>());
+
+    let (sender, mut receiver) = mpsc::channel(RESTORE_QUEUE_CAPACITY);
+    let parser = tokio::task::spawn_blocking(move || parse_backup(&path, sender));
+    let mut restore = Restore::default();
+    let mut batch = Vec::with_capacity(RESTORE_BATCH_SIZE);
+    let mut batch_kind = CatalogKind::Album;
+    let result = async {
+        while let Some((kind, value)) = receiver.recv().await {
+            if kind != batch_kind {
+                let collection = if batch_kind == CatalogKind::Album { &albums } else { &tracks };
+                restore.insert(collection, &albums, batch_kind, std::mem::take(&mut batch)).await?;
+                batch_kind = kind;
+            }
+            let mut document = match Bson::try_from(value) {
+                Ok(Bson::Document(document)) if document.contains_key("_id") => document,
+                _ => {
+                    restore.summary.counts_mut(kind).invalid += 1;
+                    continue;
+                }
+            };
+            if kind == CatalogKind::Track {
+                remap_album_id(&mut document, &restore.album_ids);
+            }
+            stamp(&mut document);
+            batch.push(document);
+            if batch.len() >= RESTORE_BATCH_SIZE {
+                let collection = if kind == CatalogKind::Album { &albums } else { &tracks };
+                restore.insert(collection, &albums, kind, std::mem::take(&mut batch)).await?;
+            }
+        }
+        let collection = if batch_kind == CatalogKind::Album { &albums } else { &tracks };
+        restore.insert(collection, &albums, batch_kind, std::mem::take(&mut batch)).await
+    }.await;
+    for id in &restore.restored_album_ids {
+        album_cache.invalidate(id);
+    }
+    if let Err(e) = result {
+        // Dropping the receiver stops the parser
+        warn!("Restore stopped after {:?}: {}", restore.summary, e);
+        return Err(e.into());
+    }
+    parser.await
+        .map_err(|e| CommandError::Unexpected(format!("Backup parser failed: {}", e)))?
+        .map_err(|e| CommandError::Validation(format!("Invalid backup file (documents before the error were restored): {}", e)))?;
+
+    info!("Restored catalog backup: {:?}", restore.summary);
+    Ok(restore.summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mongodb::bson::{oid::ObjectId, DateTime};
+
+    fn parse(path: &Path) -> (Result<(), String>, Vec<(CatalogKind, serde_json::Value)>) {
+        let (sender, mut receiver) = mpsc::channel(RESTORE_QUEUE_CAPACITY);
+        let result = parse_backup(path, sender);
+        let mut documents = Vec::new();
+        while let Ok(document) = receiver.try_recv() {
+            documents.push(document);
+        }
+        (result, documents)
+    }
+
+    #[test]
+    fn test_backup_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("catalog.json");
+        let album_id = ObjectId::new();
+        let mut writer = BackupWriter::begin(File::create(&path).unwrap()).unwrap();
+        writer.section(CatalogKind::Album).unwrap();
+        writer.document(doc! { "_id": album_id, "name": "Tide", "date_added": DateTime::from_millis(1_000) }).unwrap();
+        writer.section(CatalogKind::Track).unwrap();
+        for n in 1..=2 {
+            writer.document(doc! { "_id": ObjectId::new(), "album_id": album_id.to_hex(), "track_number": n, "r2_archive_key": format!("tracks/{}.wav", n) }).unwrap();
+        }
+        let bytes = writer.finish().unwrap();
+        assert_eq!(bytes, fs::metadata(&path).unwrap().len());
+
+        let (result, documents) = parse(&path);
+        result.unwrap();
+        assert_eq!(documents.iter().map(|(kind, _)| *kind).collect::<Vec<_>>(), vec![CatalogKind::Album, CatalogKind::Track, CatalogKind::Track]);
+        let Ok(Bson::Document(album)) = Bson::try_from(documents[0].1.clone()) else { panic!("album is not a document") };
+        assert_eq!(album.get_object_id("_id"), Ok(album_id));
+        assert_eq!(album.get_datetime("date_added"), Ok(&DateTime::from_millis(1_000)));
+    }
+
+    #[test]
+    fn test_empty_sections_and_unsupported_versions() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("empty.json");
+        let mut writer = BackupWriter::begin(File::create(&path).unwrap()).unwrap();
+        writer.section(CatalogKind::Album).unwrap();
+        writer.section(CatalogKind::Track).unwrap();
+        writer.finish().unwrap();
+        let (result, documents) = parse(&path);
+        assert_eq!((result, documents.len()), (Ok(()), 0));
+
+        fs::write(&path, r#"{"schema_version":2,"albums":[{"_id":"a"}]}"#).unwrap();
+        let (result, documents) = parse(&path);
+        assert!(result.unwrap_err().contains("schema version 2"));
+        assert!(documents.is_empty());
+
+        fs::write(&path, r#"{"albums":[{"_id":"a"}],"schema_version":1}"#).unwrap();
+        assert!(parse(&path).1.is_empty(), "nothing is restored before the version is known");
+    }
+
+    #[test]
+    fn test_id_conflicts_are_told_from_other_unique_indexes() {
+        let error = |message: &str| mongodb::bson::from_document::<BulkWriteError>(doc! { "index": 0, "code": 11000, "errmsg": message }).unwrap();
+        assert!(is_id_conflict(&error("E11000 duplicate key error collection: catalog.albums index: _id_ dup key: { _id: ObjectId('65f0c1') }")));
+        assert!(!is_id_conflict(&error("E11000 duplicate key error collection: catalog.albums index: name_1_artist_1_grouping_key_1 dup key: { name: \"Tide\" }")));
+        assert!(!is_id_conflict(&error("E11000 duplicate key error collection: catalog.tracks index: isrc_1 dup key: { isrc: \"USRC17607839\" }")));
+    }
+
+    #[test]
+    fn test_tracks_follow_albums_that_were_matched() {
+        let (backup_album, existing_album) = (ObjectId::new(), ObjectId::new());
+        let album_ids = HashMap::from([(backup_album.to_hex(), Bson::ObjectId(existing_album))]);
+
+        let mut track = doc! { "_id": ObjectId::new(), "album_id": backup_album.to_hex() };
+        remap_album_id(&mut track, &album_ids);
+        assert_eq!(track.get_str("album_id"), Ok(existing_album.to_hex().as_str()));
+
+        let mut old_track = doc! { "_id": ObjectId::new(), "album_id": backup_album };
+        remap_album_id(&mut old_track, &album_ids);
+        assert_eq!(old_track.get_object_id("album_id"), Ok(existing_album));
+
+        let other_album = ObjectId::new().to_hex();
+        let mut untouched = doc! { "_id": ObjectId::new(), "album_id": &other_album };
+        remap_album_id(&mut untouched, &album_ids);
+        assert_eq!(untouched.get_str("album_id"), Ok(other_album.as_str()));
+    }
+}
@@ -0,0 +1,265 @@
+//! Incremental catalog refresh for clients that can't use change streams, e.g. against a
+//! standalone server (see `core::live_sync`). Writes to tracks and albums set `updated_at`
+//! through `touched` and `stamp`, and deletes leave a tombstone in `catalog_tombstones`,
+//! so `fetch_tracks_updated_since` returns only what changed since the last poll.
+//!
+//! Tombstones expire after `TOMBSTONE_RETENTION_DAYS` through a TTL index. A client whose
+//! last poll is older than that is told to reload everything, as deletions may be gone.
+//! Play and download counters don't touch `updated_at`.
+
+use chrono::Utc;
+use futures_util::stream::TryStreamExt;
+use log::warn;
+use mongodb::bson::{doc, oid::ObjectId, Bson, DateTime, Document};
+use mongodb::options::{FindOptions, IndexOptions};
+use mongodb::{Database, IndexModel};
+use serde::Serialize;
+use std::collections::BTreeSet;
+use std::time::Duration;
+use tauri::{command, State};
+use ts_rs::TS;
+
+use super::album_names::AlbumNameCache;
+use super::staging::STAGED_STATUS;
+use super::storage::id_to_string;
+use super::storage::mongodb::{fetch_tracks_by_ids, TrackWithAlbum};
+use crate::core::db_config::{CatalogCollections, cursor_batch_size};
+use crate::CommandError;
+use crate::MongoState;
+
+pub const UPDATED_AT: &str = "updated_at";
+
+const TOMBSTONE_COLLECTION: &str = "catalog_tombstones";
+
+/// How long deletions are remembered for incremental refreshes.
+pub const TOMBSTONE_RETENTION_DAYS: i64 = 30;
+
+/// Writers stamp `updated_at` with their own clocks, so queries reach back this far before
+/// `since`. A change may then be returned twice, but isn't missed.
+const CLOCK_SKEW_ALLOWANCE_MS: i64 = 60_000;
+
+/// Which collection a tombstone belongs to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CatalogKind {
+    Track,
+    Album,
+}
+
+impl CatalogKind {
+    fn name(self) -> &'static str {
+        match self {
+            CatalogKind::Track => "tracks",
+            CatalogKind::Album => "albums",
+        }
+    }
+}
+
+/// `update` with `updated_at` set to now alongside its other `$set` fields.
+pub fn touched(mut update: Document) -> Document {
+    let now = Bson::DateTime(DateTime::now());
+    match update.get_mut("$set") {
+        Some(Bson::Document(set)) => {
+            set.insert(UPDATED_AT, now);
+        }
+        _ => {
+            update.insert("$set", doc! { UPDATED_AT: now });
+        }
+    }
+    update
+}
+
+/// Sets `updated_at` on a document about to be inserted.
+pub fn stamp(document: &mut Document) {
+    document.insert(UPDATED_AT, DateTime::now());
+}
+
+/// Leaves tombstones for deleted documents. Best effort: if the write fails, polling
+/// clients keep showing the documents until they next reload everything.
+pub async fn record_deletions(db: &Database, kind: CatalogKind, ids: Vec<String>) {
+    if ids.is_empty() {
+        return;
+    }
+    let deleted_at = DateTime::now();
+    let tombstones = ids.into_iter().map(|id| doc! { "collection": kind.name(), "doc_id": id, "deleted_at": deleted_at });
+    if let Err(e) = db.collection::<Document>(TOMBSTONE_COLLECTION).insert_many(tombstones, None).await {
+        warn!("Failed to record deleted {}: {}", kind.name(), e);
+    }
+}
+
+/// Indexes for the `updated_at` queries and the tombstones, whose TTL index prunes them.
+pub async fn create_change_indexes(db: &Database) -> Result<(), mongodb::error::Error> {
+    for collection in [db.tracks::<Document>(), db.albums::<Document>()] {
+        collection.create_index(IndexModel::builder().keys(doc! { UPDATED_AT: 1 }).build(), None).await?;
+    }
+    let tombstones = db.collection::<Document>(TOMBSTONE_COLLECTION);
+    let retention = Duration::from_secs(TOMBSTONE_RETENTION_DAYS as u64 * 24 * 60 * 60);
+    let ttl_index = IndexModel::builder()
+        .keys(doc! { "deleted_at": 1 })
+        .options(IndexOptions::builder().expire_after(retention).build())
+        .build();
+    tombstones.create_index(ttl_index, None).await?;
+    tombstones.create_index(IndexModel::builder().keys(doc! { "collection": 1, "deleted_at": 1 }).build(), None).await?;
+    Ok(())
+}
+
+/// Result of `fetch_tracks_updated_since`.
+#[derive(Debug, Serialize, TS)]
+#[ts(export)]
+pub struct TrackDelta {
+    /// Tracks added or changed since `since`, as track listings return them
+    pub tracks: Vec<TrackWithAlbum>,
+    /// Tracks deleted, moved to the trash or staged for review since `since`, all of
+    /// which track listings leave out
+    pub deleted_ids: Vec<String>,
+    /// Pass as `since` on the next call
+    pub as_of: chrono::DateTime<Utc>,
+    /// `since` is older than the tombstones are kept, so nothing else is filled in and the
+    /// client should reload everything
+    pub full_refresh: bool,
+}
+
+fn needs_full_refresh(since: chrono::DateTime<Utc>, now: chrono::DateTime<Utc>) -> bool {
+    now - since > chrono::Duration::days(TOMBSTONE_RETENTION_DAYS)
+}
+
+/// Splits the tracks changed in the window into live ones, to be fetched, and trashed or
+/// staged ones, which are reported as deleted along with the tombstoned ids.
+fn split_changes(changed: &[Document], tombstoned: Vec<String>) -> (Vec<ObjectId>, Vec<String>) {
+    let mut live = Vec::new();
+    let mut deleted: BTreeSet<String> = tombstoned.into_iter().collect();
+    for track_doc in changed {
+        let Some(id) = track_doc.get("_id") else { continue };
+        if track_doc.contains_key("deleted_at") || track_doc.get_str("status") == Ok(STAGED_STATUS) {
+            deleted.extend(id_to_string(id));
+        } else if let Some(oid) = id_to_string(id).and_then(|id| ObjectId::parse_str(id).ok()) {
+            live.push(oid);
+        }
+    }
+    (live, deleted.into_iter().collect())
+}
+
+// --- Tauri Commands ---
+
+/// Tracks changed and deleted since `since`, the `as_of` of the previous call, for clients
+/// polling instead of reloading the catalog. When `full_refresh` is set the client should
+/// reload everything and poll from the returned `as_of`.
+#[command]
+pub async fn fetch_tracks_updated_since(
+    since: chrono::DateTime<Utc>,
+    mongo_state: State<'_, MongoState>,
+    album_cache: State<'_, AlbumNameCache>,
+) -> Result<TrackDelta, CommandError> {
+    let as_of = Utc::now();
+    if needs_full_refresh(since, as_of) {
+        return Ok(TrackDelta { tracks: Vec::new(), deleted_ids: Vec::new(), as_of, full_refresh: true });
+    }
+    let db = mongo_state.database().await?;
+    tracks_updated_since(&db, &album_cache, since, as_of).await
+}
+
+/// The delta of `fetch_tracks_updated_since` from `db`, once `since` is known to be recent
+/// enough for the tombstones.
+async fn tracks_updated_since(db: &Database, album_cache: &AlbumNameCache, since: chrono::DateTime<Utc>, as_of: chrono::DateTime<Utc>) -> Result<TrackDelta, CommandError> {
+    let window_start = DateTime::from_millis(since.timestamp_millis() - CLOCK_SKEW_ALLOWANCE_MS);
+
+    let options = FindOptions::builder().batch_size(cursor_batch_size()).projection(doc! { "_id": 1, "deleted_at": 1, "status": 1 }).build();
+    let changed: Vec<Document> = db.tracks::<Document>()
+        .find(doc! { UPDATED_AT: { "$gte": window_start } }, options)
+        .await?
+        .try_collect()
+        .await?;
+    let options = FindOptions::builder().batch_size(cursor_batch_size()).projection(doc! { "doc_id": 1 }).build();
+    let tombstoned: Vec<String> = db.collection::<Document>(TOMBSTONE_COLLECTION)
+        .find(doc! { "collection": CatalogKind::Track.name(), "deleted_at": { "$gte": window_start } }, options)
+        .await?
+        .try_collect::<Vec<Document>>()
+        .await?
+        .iter()
+        .filter_map(|tombstone| tombstone.get_str("doc_id").ok().map(String::from))
+        .collect();
+
+    let (live_ids, deleted_ids) = split_changes(&changed, tombstoned);
+    let tracks = fetch_tracks_by_ids(db, album_cache, &live_ids).await?;
+    Ok(TrackDelta { tracks, deleted_ids, as_of, full_refresh: false })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_touched_adds_updated_at() {
+        let update = touched(doc! { "$set": { "title": "Tide" }, "$unset": { "isrc": "" } });
+        let set = update.get_document("$set").unwrap();
+        assert_eq!(set.get_str("title"), Ok("Tide"));
+        assert!(set.get_datetime(UPDATED_AT).is_ok());
+        assert!(update.contains_key("$unset"));
+
+        let update = touched(doc! { "$pull": { "track_ids": "a1" } });
+        assert!(update.get_document("$set").unwrap().get_datetime(UPDATED_AT).is_ok());
+    }
+
+    #[test]
+    fn test_split_changes() {
+        let (edited, added, trashed, staged) = (ObjectId::new(), ObjectId::new(), ObjectId::new(), ObjectId::new());
+        let changed = [
+            doc! { "_id": edited },
+            doc! { "_id": added.to_hex() },
+            doc! { "_id": trashed, "deleted_at": DateTime::now() },
+            doc! { "_id": staged, "status": STAGED_STATUS },
+        ];
+        let (live, deleted) = split_changes(&changed, vec!["gone".to_string(), trashed.to_hex()]);
+        assert_eq!(live, vec![edited, added]);
+        let mut expected = vec!["gone".to_string(), trashed.to_hex(), staged.to_hex()];
+        expected.sort();
+        assert_eq!(deleted, expected);
+
+        // Nothing changed
+        assert_eq!(split_changes(&[], Vec::new()), (Vec::new(), Vec::new()));
+    }
+
+    // Run with `MONGODB_TEST_URI=mongodb://... cargo test -- --ignored`
+    #[tokio::test]
+    #[ignore = "requires a MongoDB server (MONGODB_TEST_URI)"]
+    async fn test_delta_reports_updates_and_deletes() {
+        let uri = std::env::var("MONGODB_TEST_URI").expect("MONGODB_TEST_URI is not set");
+        let client = mongodb::Client::with_uri_str(&uri).await.unwrap();
+        let db = client.database(&format!("track_delta_test_{}", uuid::Uuid::new_v4().simple()));
+        let album_cache = AlbumNameCache::default();
+
+        // Written well before the window, clock skew allowance included
+        let long_ago = DateTime::from_millis(Utc::now().timestamp_millis() - 10 * 60 * 1000);
+        let album_id = ObjectId::new();
+        db.albums::<Document>().insert_one(doc! { "_id": album_id, "name": "Tides", UPDATED_AT: long_ago }, None).await.unwrap();
+        let (edited, deleted, untouched) = (ObjectId::new(), ObjectId::new(), ObjectId::new());
+        let tracks = [edited, deleted, untouched].map(|id| doc! {
+            "_id": id, "title": "Tide", "album_id": album_id, "filename": "tide.wav", "duration": 180,
+            "writers": [], "publishers": [], "path": "", UPDATED_AT: long_ago,
+        });
+        db.tracks::<Document>().insert_many(tracks, None).await.unwrap();
+        let since = Utc::now() - chrono::Duration::minutes(5);
+
+        let empty = tracks_updated_since(&db, &album_cache, since, Utc::now()).await.unwrap();
+        assert!(empty.tracks.is_empty() && empty.deleted_ids.is_empty() && !empty.full_refresh);
+
+        let tracks = db.tracks::<Document>();
+        tracks.update_one(doc! { "_id": edited }, touched(doc! { "$set": { "title": "Tide (Edit)" } }), None).await.unwrap();
+        tracks.delete_one(doc! { "_id": deleted }, None).await.unwrap();
+        record_deletions(&db, CatalogKind::Track, vec![deleted.to_hex()]).await;
+
+        let delta = tracks_updated_since(&db, &album_cache, since, Utc::now()).await.unwrap();
+        db.drop(None).await.unwrap();
+        let edited_id = edited.to_hex();
+        let changed: Vec<_> = delta.tracks.iter().map(|track| (track.id.as_str(), track.title.as_str(), track.album_name.as_str())).collect();
+        assert_eq!(changed, vec![(edited_id.as_str(), "Tide (Edit)", "Tides")]);
+        assert_eq!(delta.deleted_ids, vec![deleted.to_hex()]);
+    }
+
+    #[test]
+    fn test_old_since_needs_full_refresh() {
+        let now = Utc::now();
+        assert!(!needs_full_refresh(now - chrono::Duration::minutes(1), now));
+        assert!(!needs_full_refresh(now - chrono::Duration::days(TOMBSTONE_RETENTION_DAYS), now));
+        assert!(needs_full_refresh(now - chrono::Duration::days(TOMBSTONE_RETENTION_DAYS + 1), now));
+    }
+}
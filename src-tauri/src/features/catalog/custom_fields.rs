@@ -0,0 +1,70 @@
+//! Free-form key/value tags on tracks (`custom_fields`), e.g. `"sync_cleared": "yes"`.
+//! Keys become BSON field names under `custom_fields`, so they are restricted to avoid
+//! dotted paths and operator-looking names.
+
+use mongodb::bson::Document;
+use std::collections::HashMap;
+
+pub const MAX_CUSTOM_FIELDS: usize = 50;
+pub const MAX_CUSTOM_FIELD_KEY_LEN: usize = 64;
+
+/// Checks a single key: non-empty, at most 64 characters, no `.` or `$`.
+pub fn validate_custom_field_key(key: &str) -> Result<(), String> {
+    if key.trim().is_empty() {
+        return Err("Custom field keys must not be empty".to_string());
+    }
+    if key.chars().count() > MAX_CUSTOM_FIELD_KEY_LEN {
+        return Err(format!("Custom field key '{}' is longer than {} characters", key, MAX_CUSTOM_FIELD_KEY_LEN));
+    }
+    if key.contains('.') || key.contains('$') {
+        return Err(format!("Custom field key '{}' must not contain '.' or '$'", key));
+    }
+    Ok(())
+}
+
+/// Checks every key and the number of fields on one track.
+pub fn validate_custom_fields(fields: &HashMap<String, String>) -> Result<(), String> {
+    if fields.len() > MAX_CUSTOM_FIELDS {
+        return Err(format!("A track can have at most {} custom fields ({} given)", MAX_CUSTOM_FIELDS, fields.len()));
+    }
+    fields.keys().try_for_each(|key| validate_custom_field_key(key))
+}
+
+/// Builds a filter matching tracks whose custom fields equal all of the given values.
+pub fn custom_fields_filter(matches: &HashMap<String, String>) -> Result<Document, String> {
+    let mut filter = Document::new();
+    for (key, value) in matches {
+        validate_custom_field_key(key)?;
+        filter.insert(format!("custom_fields.{}", key), value.clone());
+    }
+    Ok(filter)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mongodb::bson::doc;
+
+    fn fields(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()
+    }
+
+    #[test]
+    fn test_validate_custom_fields() {
+        assert!(validate_custom_fields(&fields(&[("sync_cleared", "yes"), ("client", "AcmeAds")])).is_ok());
+        assert!(validate_custom_fields(&fields(&[("", "x")])).is_err());
+        assert!(validate_custom_fields(&fields(&[("a.b", "x")])).is_err());
+        assert!(validate_custom_fields(&fields(&[("$where", "x")])).is_err());
+        assert!(validate_custom_fields(&fields(&[(&"k".repeat(65), "x")])).is_err());
+        assert!(validate_custom_fields(&fields(&[(&"k".repeat(64), "x")])).is_ok());
+
+        let too_many: HashMap<String, String> = (0..=MAX_CUSTOM_FIELDS).map(|n| (format!("key{}", n), String::new())).collect();
+        assert!(validate_custom_fields(&too_many).is_err());
+    }
+
+    #[test]
+    fn test_custom_fields_filter() {
+        assert_eq!(custom_fields_filter(&fields(&[("bpm", "124")])).unwrap(), doc! { "custom_fields.bpm": "124" });
+        assert!(custom_fields_filter(&fields(&[("bpm.x", "124")])).is_err());
+    }
+}
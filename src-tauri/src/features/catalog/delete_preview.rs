@@ -0,0 +1,209 @@
+//! What deleting a selection of tracks would affect, for the confirmation dialog:
+//! bytes freed, albums left empty, and tracks other features still point at.
+//!
+//! The preview returns a fingerprint of the tracks it resolved. Deleting with
+//! `expected_fingerprint` refuses to run if the same ids now resolve differently, so a
+//! stale dialog can't delete a set the user never saw.
+
+use futures_util::stream::TryStreamExt;
+use mongodb::bson::{doc, Bson, Document};
+use mongodb::options::FindOptions;
+use mongodb::Database;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap};
+use tauri::{command, State};
+
+use super::storage::id_to_string;
+use super::trash::{exclude_trashed, ids_filter};
+use crate::CommandError;
+use crate::MongoState;
+use crate::core::db_config::{CatalogCollections, CatalogDatabase, cursor_batch_size};
+
+/// Activity newer than this counts as recent.
+const RECENT_ACTIVITY_MS: i64 = 7 * 24 * 60 * 60 * 1000;
+
+#[derive(Debug, Serialize, Clone, PartialEq)]
+pub struct AlbumImpact {
+    pub album_id: String,
+    pub name: Option<String>,
+    pub deleted_tracks: u64,
+    /// Tracks left in the album (trashed ones not counted)
+    pub remaining_tracks: u64,
+    pub becomes_empty: bool,
+}
+
+#[derive(Debug, Serialize, Clone, PartialEq)]
+pub struct TrackReference {
+    pub track_id: String,
+    pub title: Option<String>,
+    /// e.g. "preview clip", "playlist 'Demos'", "recent activity"
+    pub references: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct DeletePreview {
+    /// Pass to `delete_tracks` / `trash_tracks` as `expected_fingerprint`
+    pub fingerprint: String,
+    pub track_count: usize,
+    /// Requested ids with no track document
+    pub missing_ids: Vec<String>,
+    /// Sum of `file_size` of the original uploads
+    pub total_bytes: i64,
+    pub albums: Vec<AlbumImpact>,
+    pub referenced_tracks: Vec<TrackReference>,
+}
+
+/// Looks up the tracks a deletion of `track_ids` would remove. Shared by the preview
+/// and the delete commands so both see the same set.
+pub async fn resolve_tracks(db: &Database, track_ids: &[String]) -> Result<Vec<Document>, CommandError> {
+    if track_ids.is_empty() {
+        return Err(CommandError::Validation("No tracks selected".to_string()));
+    }
+    Ok(db.tracks::<Document>().find(ids_filter(track_ids), None).await?.try_collect().await?)
+}
+
+/// Hash of the resolved track ids, independent of order and duplicates.
+pub fn fingerprint(tracks: &[Document]) -> String {
+    let mut ids: Vec<String> = tracks.iter().filter_map(|track| track.get("_id").and_then(id_to_string)).collect();
+    ids.sort();
+    ids.dedup();
+    let digest = Sha256::digest(ids.join("\n").as_bytes());
+    digest.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Fails if the tracks no longer match the previewed set.
+pub fn check_fingerprint(tracks: &[Document], expected: Option<&str>) -> Result<(), CommandError> {
+    match expected {
+        Some(expected) if expected != fingerprint(tracks) => Err(CommandError::Validation(
+            "The selected tracks changed since the delete was previewed; review the selection again".to_string(),
+        )),
+        _ => Ok(()),
+    }
+}
+
+fn file_size(track_doc: &Document) -> i64 {
+    match track_doc.get("file_size") {
+        Some(Bson::Int64(n)) => *n,
+        Some(Bson::Int32(n)) => *n as i64,
+        _ => 0,
+    }
+}
+
+async fn album_impacts(db: &Database, tracks: &[Document]) -> Result<Vec<AlbumImpact>, CommandError> {
+    // Keyed by string id so ObjectId and string references group together
+    let mut by_album: BTreeMap<String, (Bson, u64)> = BTreeMap::new();
+    for track in tracks {
+        if let Some(album_ref) = track.get("album_id").filter(|id| !matches!(id, Bson::Null)) {
+            if let Some(album_id) = id_to_string(album_ref) {
+                by_album.entry(album_id).or_insert_with(|| (album_ref.clone(), 0)).1 += 1;
+            }
+        }
+    }
+    let deleted_ids: Vec<Bson> = tracks.iter().filter_map(|track| track.get("_id").cloned()).collect();
+
+    let mut impacts = Vec::with_capacity(by_album.len());
+    for (album_id, (album_ref, deleted_tracks)) in by_album {
+        let album_refs = vec![album_ref.clone(), Bson::String(album_id.clone())];
+        let mut filter = doc! { "album_id": { "$in": album_refs }, "_id": { "$nin": &deleted_ids } };
+        exclude_trashed(&mut filter);
+        let remaining_tracks = db.tracks::<Document>().count_documents(filter, None).await?;
+        let name = db.albums::<Document>().find_one(doc! { "_id": album_ref }, None).await?
+            .and_then(|album| album.get_str("name").ok().map(String::from));
+        impacts.push(AlbumImpact { album_id, name, deleted_tracks, remaining_tracks, becomes_empty: remaining_tracks == 0 });
+    }
+    Ok(impacts)
+}
+
+async fn track_references(db: &Database, tracks: &[Document]) -> Result<Vec<TrackReference>, CommandError> {
+    let track_ids: Vec<String> = tracks.iter().filter_map(|track| track.get("_id").and_then(id_to_string)).collect();
+    let mut references: HashMap<String, Vec<String>> = HashMap::new();
+
+    for track in tracks {
+        if track.get_str("preview_key").is_ok_and(|key| !key.is_empty()) {
+            if let Some(id) = track.get("_id").and_then(id_to_string) {
+                references.entry(id).or_default().push("preview clip".to_string());
+            }
+        }
+    }
+
+    let playlist_options = FindOptions::builder().batch_size(cursor_batch_size()).projection(doc! { "name": 1, "track_ids": 1 }).build();
+    let playlists: Vec<Document> = db.collection::<Document>("playlists")
+        .find(doc! { "track_ids": { "$in": &track_ids } }, playlist_options).await?.try_collect().await?;
+    for playlist in &playlists {
+        let name = playlist.get_str("name").unwrap_or_default();
+        for id in playlist.get_array("track_ids").map(|ids| ids.as_slice()).unwrap_or_default() {
+            if let Some(id) = id.as_str().filter(|id| track_ids.iter().any(|track_id| track_id == id)) {
+                references.entry(id.to_string()).or_default().push(format!("playlist '{}'", name));
+            }
+        }
+    }
+
+    let since = chrono::Utc::now().timestamp_millis() - RECENT_ACTIVITY_MS;
+    let activity: Vec<Document> = db.collection::<Document>("activity")
+        .find(doc! { "entity_ids": { "$in": &track_ids }, "timestamp_ms": { "$gte": since } }, None).await?.try_collect().await?;
+    for entry in &activity {
+        for id in entry.get_array("entity_ids").map(|ids| ids.as_slice()).unwrap_or_default() {
+            if let Some(id) = id.as_str().filter(|id| track_ids.iter().any(|track_id| track_id == id)) {
+                let reasons = references.entry(id.to_string()).or_default();
+                if !reasons.iter().any(|reason| reason == "recent activity") {
+                    reasons.push("recent activity".to_string());
+                }
+            }
+        }
+    }
+
+    Ok(tracks.iter()
+        .filter_map(|track| {
+            let track_id = track.get("_id").and_then(id_to_string)?;
+            let references = references.remove(&track_id)?;
+            Some(TrackReference { track_id, title: track.get_str("title").ok().map(String::from), references })
+        })
+        .collect())
+}
+
+// --- Tauri Commands ---
+
+/// Describes what deleting `track_ids` would affect, without changing anything.
+#[command]
+pub async fn preview_delete(track_ids: Vec<String>, mongo_state: State<'_, MongoState>) -> Result<DeletePreview, CommandError> {
+    let db = {
+        let client_lock = mongo_state.client.lock().await;
+        let client = client_lock.as_ref()
+            .ok_or_else(|| CommandError::Configuration("MongoDB client not initialized".to_string()))?;
+        client.catalog_database()
+    };
+    let tracks = resolve_tracks(&db, &track_ids).await?;
+    let found: Vec<String> = tracks.iter().filter_map(|track| track.get("_id").and_then(id_to_string)).collect();
+    let mut missing_ids: Vec<String> = track_ids.into_iter().filter(|id| !found.contains(id)).collect();
+    missing_ids.dedup();
+
+    Ok(DeletePreview {
+        fingerprint: fingerprint(&tracks),
+        track_count: tracks.len(),
+        missing_ids,
+        total_bytes: tracks.iter().map(file_size).sum(),
+        albums: album_impacts(&db, &tracks).await?,
+        referenced_tracks: track_references(&db, &tracks).await?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mongodb::bson::oid::ObjectId;
+
+    #[test]
+    fn test_fingerprint_ignores_order() {
+        let (a, b) = (ObjectId::new(), ObjectId::new());
+        let tracks = vec![doc! { "_id": a }, doc! { "_id": b }];
+        let reversed = vec![doc! { "_id": b }, doc! { "_id": a }];
+        assert_eq!(fingerprint(&tracks), fingerprint(&reversed));
+        assert_ne!(fingerprint(&tracks), fingerprint(&tracks[..1]));
+
+        let expected = fingerprint(&tracks);
+        assert!(check_fingerprint(&reversed, Some(&expected)).is_ok());
+        assert!(check_fingerprint(&tracks[..1], Some(&expected)).is_err());
+        assert!(check_fingerprint(&tracks[..1], None).is_ok());
+    }
+}
@@ -0,0 +1,139 @@
+//! Distinct values of a catalog field, for filter sidebars. Array fields such as `genre`
+//! are flattened by MongoDB's `distinct`, so each entry is one value, not one array.
+//! Only the fields in `DISTINCT_FIELDS` can be queried.
+
+use mongodb::bson::{Bson, Document};
+use serde::Serialize;
+use std::cmp::Ordering;
+use tauri::{command, State};
+use ts_rs::TS;
+
+use super::staging::exclude_staged;
+use super::trash::exclude_trashed;
+use crate::core::db_config::CatalogCollections;
+use crate::CommandError;
+use crate::MongoState;
+
+const DEFAULT_DISTINCT_LIMIT: u32 = 200;
+const MAX_DISTINCT_LIMIT: u32 = 1000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Source {
+    Tracks,
+    Albums,
+}
+
+/// Fields `get_distinct_values` accepts, and the collection each is read from.
+const DISTINCT_FIELDS: [(&str, Source); 9] = [
+    ("genre", Source::Tracks),
+    ("writers", Source::Tracks),
+    ("publishers", Source::Tracks),
+    ("composers", Source::Tracks),
+    ("instruments", Source::Tracks),
+    ("mood", Source::Tracks),
+    ("artists", Source::Tracks),
+    ("musical_key", Source::Tracks),
+    ("year", Source::Albums),
+];
+
+/// Result of `get_distinct_values`.
+#[derive(Debug, Serialize, PartialEq, TS)]
+#[ts(export)]
+pub struct DistinctValues {
+    /// Numbers ascending, then text ignoring case
+    pub values: Vec<serde_json::Value>,
+    /// More values exist than were returned
+    pub truncated: bool,
+}
+
+fn source_of(field: &str) -> Result<Source, CommandError> {
+    DISTINCT_FIELDS.iter()
+        .find(|(name, _)| *name == field)
+        .map(|(_, source)| *source)
+        .ok_or_else(|| {
+            let fields: Vec<&str> = DISTINCT_FIELDS.iter().map(|(name, _)| *name).collect();
+            CommandError::Validation(format!("Unknown field '{}' (expected one of {})", field, fields.join(", ")))
+        })
+}
+
+/// Drops nulls, blank text, and anything that isn't text or a number, then sorts and
+/// keeps the first `limit` values.
+fn sorted_values(values: Vec<Bson>, limit: usize) -> DistinctValues {
+    let mut numbers = Vec::new();
+    let mut texts = Vec::new();
+    for value in values {
+        match value {
+            Bson::Int32(n) => numbers.push(f64::from(n)),
+            Bson::Int64(n) => numbers.push(n as f64),
+            Bson::Double(n) if n.is_finite() => numbers.push(n),
+            Bson::String(text) if !text.trim().is_empty() => texts.push(text.trim().to_string()),
+            _ => {}
+        }
+    }
+    numbers.sort_by(|a, b| a.partial_cmp(b).unwrap_or(Ordering::Equal));
+    numbers.dedup();
+    texts.sort_by(|a, b| a.to_lowercase().cmp(&b.to_lowercase()).then_with(|| a.cmp(b)));
+    texts.dedup();
+
+    let all = numbers.into_iter()
+        .map(|n| if n.fract() == 0.0 { serde_json::json!(n as i64) } else { serde_json::json!(n) })
+        .chain(texts.into_iter().map(serde_json::Value::String));
+    let mut values: Vec<serde_json::Value> = all.take(limit + 1).collect();
+    let truncated = values.len() > limit;
+    values.truncate(limit);
+    DistinctValues { values, truncated }
+}
+
+// --- Tauri Commands ---
+
+/// Distinct values of `field` across the catalog, trashed tracks left out. At most
+/// `limit` values are returned (default 200, at most 1000).
+#[command]
+pub async fn get_distinct_values(
+    field: String,
+    limit: Option<u32>,
+    mongo_state: State<'_, MongoState>,
+) -> Result<DistinctValues, CommandError> {
+    let source = source_of(&field)?;
+    let limit = limit.unwrap_or(DEFAULT_DISTINCT_LIMIT).clamp(1, MAX_DISTINCT_LIMIT) as usize;
+    let db = mongo_state.database().await?;
+    let values = match source {
+        Source::Tracks => {
+            let mut filter = Document::new();
+            exclude_trashed(&mut filter);
+            exclude_staged(&mut filter);
+            db.tracks::<Document>().distinct(&field, filter, None).await?
+        }
+        Source::Albums => db.albums::<Document>().distinct(&field, None, None).await?,
+    };
+    Ok(sorted_values(values, limit))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_only_listed_fields_are_accepted() {
+        assert_eq!(source_of("genre").unwrap(), Source::Tracks);
+        assert_eq!(source_of("year").unwrap(), Source::Albums);
+        assert!(source_of("title").is_err());
+        assert!(source_of("$where").is_err());
+    }
+
+    #[test]
+    fn test_values_are_cleaned_sorted_and_capped() {
+        let values = vec![
+            Bson::String("rock".into()), Bson::String("Ambient".into()), Bson::String("  ".into()),
+            Bson::Null, Bson::Int32(2001), Bson::Int64(1999), Bson::String("Rock ".into()),
+        ];
+        let distinct = sorted_values(values.clone(), 10);
+        assert_eq!(distinct.values, vec![json!(1999), json!(2001), json!("Ambient"), json!("Rock"), json!("rock")]);
+        assert!(!distinct.truncated);
+
+        let capped = sorted_values(values, 2);
+        assert_eq!(capped.values, vec![json!(1999), json!(2001)]);
+        assert!(capped.truncated);
+    }
+}
@@ -0,0 +1,442 @@
+//! Exporting original files for clients: a single track's original to a folder, or all
+//! originals of an album as a ZIP archive. Encrypted originals are decrypted on export.
+//! Album exports run as background jobs, one at a time.
+//!
+//! Album files are streamed from R2 into the archive chunk by chunk, so memory use doesn't grow
+//! with file size. Entries are stored uncompressed (audio doesn't compress further) with
+//! ZIP64 enabled for files over 4 GB. A `metadata.json` describing the album and the
+//! exported tracks is added last.
+
+use futures_util::stream::TryStreamExt;
+use log::{info, warn};
+use mongodb::bson::{doc, oid::ObjectId, Bson, Document};
+use mongodb::options::FindOptions;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use tauri::{command, AppHandle, Emitter, Manager, State, Wry};
+use zip::write::SimpleFileOptions;
+use zip::{CompressionMethod, ZipWriter};
+
+use super::staging::exclude_staged;
+use super::stats::{record_access, AccessKind};
+use super::storage::{id_filter, id_to_string};
+use super::trash::exclude_trashed;
+use crate::core::encryption::{decryption_key, Decryptor, KEY_LEN};
+use crate::core::jobs::{spawn_job, JobContext, JobId, JobKind};
+use crate::core::r2::{R2Client, ORIGINAL_KEY_FIELDS};
+use crate::features::upload::originals::{is_local_original, local_original_description};
+use crate::CommandError;
+use crate::{MongoState, R2State};
+use crate::core::db_config::{CatalogCollections, CatalogDatabase, cursor_batch_size};
+
+const METADATA_FILE_NAME: &str = "metadata.json";
+
+/// Payload of `export://progress` events, sent when each file starts and finishes.
+#[derive(Debug, Serialize, Clone)]
+pub struct AlbumExportProgress {
+    pub album_id: String,
+    /// One-based position of the file in the export
+    pub file_index: usize,
+    pub total_files: usize,
+    pub file_name: String,
+    pub bytes_written: u64,
+    pub done: bool,
+}
+
+/// A track left out of the export.
+#[derive(Debug, Serialize, Clone, PartialEq)]
+pub struct SkippedTrack {
+    pub track_id: String,
+    pub title: Option<String>,
+    pub reason: String,
+}
+
+/// Arguments of an album export job.
+#[derive(Debug, Deserialize, Clone)]
+pub struct AlbumExportParams {
+    pub album_id: String,
+    pub destination_zip_path: String,
+}
+
+/// Result of an album export job.
+#[derive(Debug, Serialize, Clone)]
+pub struct AlbumExportSummary {
+    pub zip_path: String,
+    pub exported: usize,
+    pub total_bytes: u64,
+    pub skipped: Vec<SkippedTrack>,
+}
+
+/// Turns a title into something every file system accepts.
+fn sanitize_file_name(name: &str) -> String {
+    let cleaned: String = name.chars()
+        .map(|c| if matches!(c, '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|') || c.is_control() { '_' } else { c })
+        .collect();
+    let cleaned = cleaned.trim().trim_end_matches('.').trim();
+    if cleaned.is_empty() { "Untitled".to_string() } else { cleaned.to_string() }
+}
+
+/// `{track_number} - {title}.{ext}`, with ` (2)`, ` (3)`, ... added to repeated names.
+fn archive_file_name(track_number: Option<i64>, title: &str, key: &str, used: &mut HashSet<String>) -> String {
+    let stem = match track_number {
+        Some(number) => format!("{:02} - {}", number, sanitize_file_name(title)),
+        None => sanitize_file_name(title),
+    };
+    let extension = Path::new(key).extension().map(|e| format!(".{}", e.to_string_lossy())).unwrap_or_default();
+    let mut name = format!("{}{}", stem, extension);
+    let mut copy = 2;
+    while !used.insert(name.to_lowercase()) {
+        name = format!("{} ({}){}", stem, copy, extension);
+        copy += 1;
+    }
+    name
+}
+
+fn track_number(track_doc: &Document) -> Option<i64> {
+    match track_doc.get("track_number") {
+        Some(Bson::Int32(n)) => Some(*n as i64),
+        Some(Bson::Int64(n)) => Some(*n),
+        Some(Bson::Double(n)) => Some(*n as i64),
+        _ => None,
+    }.filter(|n| *n > 0)
+}
+
+/// Track fields written to `metadata.json`.
+fn track_metadata(track_doc: &Document, file_name: &str) -> serde_json::Value {
+    let field = |name: &str| track_doc.get(name).cloned().unwrap_or(Bson::Null).into_relaxed_extjson();
+    serde_json::json!({
+        "file": file_name,
+        "track_id": track_doc.get("_id").and_then(id_to_string),
+        "title": field("title"),
+        "track_number": field("track_number"),
+        "artists": field("artists"),
+        "duration": field("duration"),
+        "genre": field("genre"),
+        "composers": field("composers"),
+        "writers": field("writers"),
+        "publishers": field("publishers"),
+        "isrc": field("isrc"),
+        "catalog_number": field("catalog_number"),
+        "bpm": field("bpm"),
+        "musical_key": field("musical_key"),
+        "stats": field("stats"),
+    })
+}
+
+fn album_metadata(album_doc: &Document) -> serde_json::Value {
+    let field = |name: &str| album_doc.get(name).cloned().unwrap_or(Bson::Null).into_relaxed_extjson();
+    serde_json::json!({
+        "album_id": album_doc.get("_id").and_then(id_to_string),
+        "name": field("name"),
+        "artist": field("artist"),
+        "year": field("year"),
+        "genres": field("genres"),
+        "publisher": field("publisher"),
+    })
+}
+
+/// Why a single file couldn't be exported. `Cancelled` stops the whole export.
+enum FileError {
+    Missing,
+    Failed(String),
+    Cancelled,
+}
+
+/// Streams one object into a new archive entry, decrypting encrypted originals on the
+/// way. A failed or cancelled entry is removed from the archive again.
+async fn write_entry(
+    zip: &mut ZipWriter<BufWriter<File>>,
+    r2_client: &R2Client,
+    key: &str,
+    decrypt_with: Option<&[u8; KEY_LEN]>,
+    file_name: &str,
+    cancel_flag: &AtomicBool,
+) -> Result<u64, FileError> {
+    let mut body = match r2_client.get_object_stream(key).await {
+        Ok(Some(body)) => body,
+        Ok(None) => return Err(FileError::Missing),
+        Err(e) => return Err(FileError::Failed(e.to_string())),
+    };
+    let options = SimpleFileOptions::default().compression_method(CompressionMethod::Stored).large_file(true);
+    zip.start_file(file_name, options).map_err(|e| FileError::Failed(e.to_string()))?;
+
+    let mut decryptor = decrypt_with.map(Decryptor::new);
+    let mut plain = Vec::new();
+    let mut written = 0u64;
+    let result = loop {
+        if cancel_flag.load(Ordering::SeqCst) {
+            break Err(FileError::Cancelled);
+        }
+        let chunk = match body.try_next().await {
+            Ok(Some(chunk)) => chunk,
+            Ok(None) => {
+                // The final chunk of an encrypted file is only released once the stream ends
+                if let Some(decryptor) = decryptor.take() {
+                    plain.clear();
+                    if let Err(e) = decryptor.finish(&mut plain) {
+                        break Err(FileError::Failed(e.to_string()));
+                    }
+                    if let Err(e) = zip.write_all(&plain) {
+                        break Err(FileError::Failed(format!("Failed to write archive: {}", e)));
+                    }
+                    written += plain.len() as u64;
+                }
+                break Ok(written);
+            }
+            Err(e) => break Err(FileError::Failed(format!("Download interrupted: {}", e))),
+        };
+        let data: &[u8] = match decryptor.as_mut() {
+            Some(decryptor) => {
+                plain.clear();
+                if let Err(e) = decryptor.update(&chunk, &mut plain) {
+                    break Err(FileError::Failed(e.to_string()));
+                }
+                &plain
+            }
+            None => &chunk,
+        };
+        match zip.write_all(data) {
+            Ok(()) => written += data.len() as u64,
+            Err(e) => break Err(FileError::Failed(format!("Failed to write archive: {}", e))),
+        }
+    };
+    if result.is_err() {
+        if let Err(e) = zip.abort_file() {
+            warn!("Failed to remove partial entry {} from the export: {}", file_name, e);
+        }
+    }
+    result
+}
+
+async fn export_album(
+    job: &JobContext,
+    db: &mongodb::Database,
+    r2_client: &R2Client,
+    album_id: &str,
+    destination: &Path,
+) -> Result<AlbumExportSummary, CommandError> {
+    let (app_handle, cancel_flag) = (job.app_handle(), job.cancel_flag());
+    let album_doc = db.albums::<Document>().find_one(id_filter(album_id), None).await?
+        .ok_or_else(|| CommandError::NotFound(format!("Album {} not found", album_id)))?;
+    let album_ids: Vec<Bson> = match ObjectId::parse_str(album_id) {
+        Ok(oid) => vec![Bson::ObjectId(oid), Bson::String(album_id.to_string())],
+        Err(_) => vec![Bson::String(album_id.to_string())],
+    };
+    let mut filter = doc! { "album_id": { "$in": album_ids } };
+    exclude_trashed(&mut filter);
+    exclude_staged(&mut filter);
+    let options = FindOptions::builder().batch_size(cursor_batch_size()).sort(doc! { "track_number": 1, "title": 1 }).build();
+    let tracks: Vec<Document> = db.tracks::<Document>().find(filter, options).await?.try_collect().await?;
+    if tracks.is_empty() {
+        return Err(CommandError::Validation(format!("Album {} has no tracks to export", album_id)));
+    }
+
+    let file = File::create(destination)?;
+    let mut zip = ZipWriter::new(BufWriter::new(file));
+    let mut summary = AlbumExportSummary {
+        zip_path: destination.to_string_lossy().into_owned(),
+        exported: 0,
+        total_bytes: 0,
+        skipped: Vec::new(),
+    };
+    let mut used_names = HashSet::new();
+    let mut exported_tracks = Vec::new();
+    let total_files = tracks.len();
+
+    for (index, track_doc) in tracks.iter().enumerate() {
+        job.progress(index as u64, total_files as u64, None);
+        let track_id = track_doc.get("_id").and_then(id_to_string).unwrap_or_default();
+        let title = track_doc.get_str("title").ok().map(String::from);
+        let skip = |reason: &str| SkippedTrack { track_id: track_id.clone(), title: title.clone(), reason: reason.to_string() };
+        let Some(key) = ORIGINAL_KEY_FIELDS.iter().find_map(|field| track_doc.get_str(field).ok().filter(|key| !key.is_empty())) else {
+            let reason = if is_local_original(track_doc) {
+                format!("Original was not uploaded; {}", local_original_description(track_doc))
+            } else {
+                "No original file stored".to_string()
+            };
+            summary.skipped.push(skip(&reason));
+            continue;
+        };
+        let file_name = archive_file_name(track_number(track_doc), title.as_deref().unwrap_or("Untitled"), key, &mut used_names);
+        let progress = |bytes_written, done| AlbumExportProgress {
+            album_id: album_id.to_string(), file_index: index + 1, total_files, file_name: file_name.clone(), bytes_written, done,
+        };
+        let decrypt_with = match decryption_key(track_doc, key) {
+            Ok(decrypt_with) => decrypt_with,
+            Err(e) => {
+                summary.skipped.push(skip(&e.to_string()));
+                continue;
+            }
+        };
+        let _ = app_handle.emit("export://progress", progress(0, false));
+
+        match write_entry(&mut zip, &r2_client.for_track(track_doc), key, decrypt_with.as_ref(), &file_name, cancel_flag).await {
+            Ok(bytes) => {
+                summary.exported += 1;
+                summary.total_bytes += bytes;
+                exported_tracks.push(track_metadata(track_doc, &file_name));
+                record_access(db.tracks(), &track_id, AccessKind::Download);
+                let _ = app_handle.emit("export://progress", progress(bytes, true));
+            }
+            Err(FileError::Missing) => summary.skipped.push(skip(&format!("Object {} not found in R2", key))),
+            Err(FileError::Failed(e)) => {
+                warn!("Skipping track {} in album export: {}", track_id, e);
+                summary.skipped.push(skip(&e));
+            }
+            Err(FileError::Cancelled) => return Err(CommandError::OperationFailed("Album export was cancelled".to_string())),
+        }
+    }
+    job.progress(total_files as u64, total_files as u64, None);
+
+    let metadata = serde_json::json!({
+        "album": album_metadata(&album_doc),
+        "tracks": exported_tracks,
+        "skipped": summary.skipped,
+        "exported_at": chrono::Utc::now().to_rfc3339(),
+    });
+    let metadata_options = SimpleFileOptions::default().compression_method(CompressionMethod::Deflated);
+    let archive_error = |e: zip::result::ZipError| CommandError::FileSystem(format!("Failed to write archive: {}", e));
+    zip.start_file(METADATA_FILE_NAME, metadata_options).map_err(archive_error)?;
+    serde_json::to_writer_pretty(&mut zip, &metadata)
+        .map_err(|e| CommandError::FileSystem(format!("Failed to write {}: {}", METADATA_FILE_NAME, e)))?;
+    zip.finish().map_err(archive_error)?.flush()?;
+    Ok(summary)
+}
+
+/// A file name in `dir` based on `file_name` that isn't taken yet: `take.wav`,
+/// `take (2).wav`, ... Only the final path component of `file_name` is used.
+fn unused_destination(dir: &Path, file_name: &str) -> std::path::PathBuf {
+    let file_name = Path::new(file_name).file_name().map(|name| sanitize_file_name(&name.to_string_lossy()))
+        .unwrap_or_else(|| "Untitled".to_string());
+    let path = dir.join(&file_name);
+    if !path.exists() {
+        return path;
+    }
+    let stem = Path::new(&file_name).file_stem().unwrap_or_default().to_string_lossy().into_owned();
+    let extension = Path::new(&file_name).extension().map(|e| format!(".{}", e.to_string_lossy())).unwrap_or_default();
+    (2..).map(|copy| dir.join(format!("{} ({}){}", stem, copy, extension)))
+        .find(|path| !path.exists())
+        .unwrap_or(path)
+}
+
+// --- Tauri Commands ---
+
+/// Downloads a track's original file into `destination_dir`, named after the uploaded
+/// file, and returns the path written. Existing files are never overwritten. Tracks
+/// whose original was kept locally fail with `OriginalNotUploaded`.
+#[command]
+pub async fn export_original(
+    track_id: String,
+    destination_dir: String,
+    mongo_state: State<'_, MongoState>,
+    r2_state: State<'_, R2State>,
+) -> Result<String, CommandError> {
+    let dir = Path::new(&destination_dir);
+    if !dir.is_dir() {
+        return Err(CommandError::Validation(format!("{} is not a folder", destination_dir)));
+    }
+    let tracks_collection = {
+        let client_lock = mongo_state.client.lock().await;
+        let client = client_lock.as_ref()
+            .ok_or_else(|| CommandError::Configuration("MongoDB client not initialized".to_string()))?;
+        client.catalog_database().tracks::<Document>()
+    };
+    let track_doc = tracks_collection.find_one(id_filter(&track_id), None).await?
+        .ok_or_else(|| CommandError::NotFound(format!("Track {} not found", track_id)))?;
+    if is_local_original(&track_doc) {
+        return Err(CommandError::OriginalNotUploaded(format!(
+            "The original of track {} was never uploaded to R2; it was {}", track_id, local_original_description(&track_doc)
+        )));
+    }
+    let key = track_doc.get_str("r2_original_key").ok().filter(|key| !key.is_empty())
+        .ok_or_else(|| CommandError::NotFound(format!("Track {} has no original file stored", track_id)))?;
+    let r2_client = R2Client::from_state(&r2_state).await?.for_track(&track_doc);
+
+    let file_name = track_doc.get_str("filename").ok().filter(|name| !name.trim().is_empty())
+        .or_else(|| Path::new(key).file_name().and_then(|name| name.to_str()))
+        .unwrap_or("Untitled");
+    let destination = unused_destination(dir, file_name);
+    let decrypt_with = decryption_key(&track_doc, key)?;
+    let bytes = r2_client.download_to_path(key, &destination, decrypt_with.as_ref(), None).await
+        .map_err(|e| CommandError::Storage(format!("Failed to download {}: {}", key, e)))?
+        .ok_or_else(|| CommandError::NotFound(format!("Original file {} of track {} is missing from R2", key, track_id)))?;
+    info!("Exported original of track {} to {:?} ({} bytes)", track_id, destination, bytes);
+    record_access(tracks_collection, &track_id, AccessKind::Download);
+    Ok(destination.to_string_lossy().into_owned())
+}
+
+/// Starts a job writing the originals of an album's tracks to a ZIP archive. Fails
+/// straight away if the clients aren't ready or an export is already running.
+pub async fn start_album_export_job(app_handle: &AppHandle<Wry>, params: AlbumExportParams) -> Result<JobId, CommandError> {
+    let AlbumExportParams { album_id, destination_zip_path } = params;
+    if Path::new(&destination_zip_path).is_dir() {
+        return Err(CommandError::Validation(format!("{} is a directory", destination_zip_path)));
+    }
+    let db = {
+        let mongo_state = app_handle.state::<MongoState>();
+        let client_lock = mongo_state.client.lock().await;
+        let client = client_lock.as_ref()
+            .ok_or_else(|| CommandError::Configuration("MongoDB client not initialized".to_string()))?;
+        client.catalog_database()
+    };
+    let r2_client = R2Client::from_state(&app_handle.state::<R2State>()).await?;
+
+    spawn_job(app_handle, JobKind::AlbumExport, move |job| async move {
+        let destination = Path::new(&destination_zip_path);
+        info!("Exporting originals of album {} to {}", album_id, destination_zip_path);
+        let result = export_album(&job, &db, &r2_client, &album_id, destination).await;
+        match &result {
+            Ok(summary) => info!(
+                "Exported {} files ({} bytes) of album {}, skipped {}",
+                summary.exported, summary.total_bytes, album_id, summary.skipped.len()
+            ),
+            Err(e) => {
+                warn!("Album export of {} failed: {}", album_id, e);
+                if destination.exists() {
+                    let _ = fs::remove_file(destination);
+                }
+            }
+        }
+        result
+    })
+}
+
+/// Starts a job writing the originals of an album's tracks to a ZIP archive at
+/// `destination_zip_path` and returns its id; cancel it with `cancel_job`. Tracks whose
+/// original is missing are listed in the job's summary instead of failing the export.
+/// Emits `export://progress` per file; a cancelled or failed export leaves no archive
+/// behind.
+#[command]
+pub async fn export_album_originals(
+    album_id: String,
+    destination_zip_path: String,
+    app_handle: AppHandle<Wry>,
+) -> Result<JobId, CommandError> {
+    start_album_export_job(&app_handle, AlbumExportParams { album_id, destination_zip_path }).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unused_destination_never_overwrites() {
+        let dir = tempfile::tempdir().unwrap();
+        assert_eq!(unused_destination(dir.path(), "../take.wav"), dir.path().join("take.wav"));
+        fs::write(dir.path().join("take.wav"), b"").unwrap();
+        fs::write(dir.path().join("take (2).wav"), b"").unwrap();
+        assert_eq!(unused_destination(dir.path(), "take.wav"), dir.path().join("take (3).wav"));
+    }
+
+    #[test]
+    fn test_archive_file_name() {
+        let mut used = HashSet::new();
+        assert_eq!(archive_file_name(Some(3), "Intro: Part 1/2", "tracks/original/a.wav", &mut used), "03 - Intro_ Part 1_2.wav");
+        assert_eq!(archive_file_name(Some(3), "intro: part 1/2", "tracks/original/b.WAV", &mut used), "03 - intro_ part 1_2 (2).WAV");
+        assert_eq!(archive_file_name(None, "  ...  ", "tracks/original/c", &mut used), "Untitled");
+    }
+}
@@ -0,0 +1,282 @@
+//! Genre taxonomy: canonical genre names stored in the `genres` collection, plus the
+//! normalization applied whenever track genres are written.
+
+use futures_util::stream::TryStreamExt;
+use log::info;
+use mongodb::bson::{doc, Bson, Document};
+use mongodb::options::{FindOptions, UpdateOptions};
+use mongodb::{Collection, Database};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use tauri::{command, State};
+
+use super::changes::touched;
+use crate::CommandError;
+use crate::MongoState;
+use crate::core::db_config::{CatalogCollections, cursor_batch_size};
+
+/// A taxonomy genre and how many tracks use it.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct GenreUsage {
+    pub name: String,
+    pub track_count: u64,
+}
+
+/// Number of documents rewritten by a rename or merge.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct GenreRewriteResult {
+    pub tracks_updated: u64,
+    pub albums_updated: u64,
+}
+
+// --- Normalization ---
+
+/// Trims and collapses internal whitespace. Returns `None` for blank input.
+pub fn clean_genre(raw: &str) -> Option<String> {
+    let cleaned = raw.split_whitespace().collect::<Vec<_>>().join(" ");
+    if cleaned.is_empty() { None } else { Some(cleaned) }
+}
+
+/// Lookup key used to match spelling variants ("Hip Hop", "hip-hop", "HipHop").
+pub fn genre_key(name: &str) -> String {
+    name.chars().filter(|c| c.is_alphanumeric()).flat_map(char::to_lowercase).collect()
+}
+
+fn genres_collection(db: &Database) -> Collection<Document> {
+    db.collection::<Document>("genres")
+}
+
+/// Maps raw genre values onto their canonical taxonomy names, adding unknown genres
+/// to the taxonomy as they are first seen. Duplicates and blanks are dropped.
+pub async fn normalize_genres(db: &Database, raw: &[String]) -> Result<Vec<String>, mongodb::error::Error> {
+    let genres = genres_collection(db);
+    let mut normalized: Vec<String> = Vec::with_capacity(raw.len());
+
+    for value in raw {
+        let cleaned = match clean_genre(value) {
+            Some(cleaned) => cleaned,
+            None => continue,
+        };
+        let key = genre_key(&cleaned);
+        if key.is_empty() {
+            continue;
+        }
+
+        let canonical = match genres.find_one(doc! { "key": &key }, None).await? {
+            Some(existing) => existing.get_str("name").map(String::from).unwrap_or(cleaned),
+            None => {
+                let options = UpdateOptions::builder().upsert(true).build();
+                genres
+                    .update_one(doc! { "key": &key }, doc! { "$setOnInsert": { "name": &cleaned, "key": &key } }, options)
+                    .await?;
+                info!("Added genre '{}' to taxonomy", cleaned);
+                cleaned
+            }
+        };
+
+        if !normalized.contains(&canonical) {
+            normalized.push(canonical);
+        }
+    }
+
+    Ok(normalized)
+}
+
+/// Replaces every spelling of the genres keyed `source_keys` in the string array `field`
+/// with `target`, keeping order and removing duplicates. Returns the new array if
+/// anything changed.
+fn rewrite_genre_field(document: &Document, field: &str, source_keys: &[String], target: &str) -> Option<Vec<String>> {
+    let values = document.get_array(field).ok()?;
+    let mut changed = false;
+    let mut rewritten: Vec<String> = Vec::with_capacity(values.len());
+
+    for value in values {
+        let name = match value {
+            Bson::String(name) => name.as_str(),
+            _ => continue,
+        };
+        let name = if source_keys.contains(&genre_key(name)) {
+            changed |= name != target;
+            target
+        } else {
+            name
+        };
+        if rewritten.iter().any(|existing| existing == name) {
+            changed = true;
+        } else {
+            rewritten.push(name.to_string());
+        }
+    }
+
+    if changed { Some(rewritten) } else { None }
+}
+
+/// Rewrites `field` on every document in `collection` that references a spelling of one
+/// of the genres keyed `source_keys`, matched the same way as the taxonomy.
+async fn rewrite_collection(collection: &Collection<Document>, field: &str, source_keys: &[String], target: &str) -> Result<u64, CommandError> {
+    let spellings: Vec<String> = collection.distinct(field, None, None).await?
+        .into_iter()
+        .filter_map(|value| match value {
+            Bson::String(name) => Some(name),
+            _ => None,
+        })
+        .filter(|name| name != target && source_keys.contains(&genre_key(name)))
+        .collect();
+    if spellings.is_empty() {
+        return Ok(0);
+    }
+    let options = FindOptions::builder().batch_size(cursor_batch_size()).projection(doc! { "_id": 1, field: 1 }).build();
+    let mut cursor = collection.find(doc! { field: { "$in": spellings } }, options).await?;
+
+    let mut updated = 0;
+    while let Some(document) = cursor.try_next().await? {
+        if let (Some(id), Some(rewritten)) = (document.get("_id"), rewrite_genre_field(&document, field, source_keys, target)) {
+            collection.update_one(doc! { "_id": id.clone() }, touched(doc! { "$set": { field: rewritten } }), None).await?;
+            updated += 1;
+        }
+    }
+    Ok(updated)
+}
+
+/// Folds `sources` into `target` in the taxonomy and on all tracks and albums.
+async fn merge_into(db: &Database, sources: &[String], target: &str) -> Result<GenreRewriteResult, CommandError> {
+    let target = clean_genre(target)
+        .ok_or_else(|| CommandError::Validation("Target genre must not be empty".to_string()))?;
+    let target_key = genre_key(&target);
+
+    let genres = genres_collection(db);
+    let options = UpdateOptions::builder().upsert(true).build();
+    genres
+        .update_one(doc! { "key": &target_key }, doc! { "$set": { "name": &target, "key": &target_key } }, options)
+        .await?;
+
+    // Spellings of the target itself are rewritten on tracks too, but its entry stays
+    let mut source_keys: Vec<String> = sources.iter().map(|s| genre_key(s)).filter(|k| !k.is_empty()).collect();
+    source_keys.sort();
+    source_keys.dedup();
+    let removed_keys: Vec<String> = source_keys.iter().filter(|k| **k != target_key).cloned().collect();
+    if !removed_keys.is_empty() {
+        genres.delete_many(doc! { "key": { "$in": removed_keys } }, None).await?;
+    }
+
+    let result = GenreRewriteResult {
+        tracks_updated: rewrite_collection(&db.tracks::<Document>(), "genre", &source_keys, &target).await?,
+        albums_updated: rewrite_collection(&db.albums::<Document>(), "genres", &source_keys, &target).await?,
+    };
+    info!(
+        "Merged genres {:?} into '{}' ({} tracks, {} albums updated)",
+        sources, target, result.tracks_updated, result.albums_updated
+    );
+    Ok(result)
+}
+
+
+// --- Tauri Commands ---
+
+/// Lists every known genre with the number of tracks using it. Genres found on tracks
+/// but missing from the taxonomy are included too.
+#[command]
+pub async fn list_genres(mongo_state: State<'_, MongoState>) -> Result<Vec<GenreUsage>, CommandError> {
+    let db = mongo_state.database().await?;
+
+    let mut counts: BTreeMap<String, u64> = BTreeMap::new();
+    let mut taxonomy = genres_collection(&db).find(None, None).await?;
+    while let Some(genre_doc) = taxonomy.try_next().await? {
+        if let Ok(name) = genre_doc.get_str("name") {
+            counts.entry(name.to_string()).or_insert(0);
+        }
+    }
+
+    let pipeline = vec![
+        doc! { "$unwind": "$genre" },
+        doc! { "$group": { "_id": "$genre", "count": { "$sum": 1 } } },
+    ];
+    let mut usage = db.tracks::<Document>().aggregate(pipeline, None).await?;
+    while let Some(group) = usage.try_next().await? {
+        if let Ok(name) = group.get_str("_id") {
+            let count = match group.get("count") {
+                Some(Bson::Int32(n)) => *n as u64,
+                Some(Bson::Int64(n)) => *n as u64,
+                _ => 0,
+            };
+            *counts.entry(name.to_string()).or_insert(0) += count;
+        }
+    }
+
+    Ok(counts.into_iter().map(|(name, track_count)| GenreUsage { name, track_count }).collect())
+}
+
+/// Renames a genre everywhere it is used.
+#[command]
+pub async fn rename_genre(old: String, new: String, mongo_state: State<'_, MongoState>) -> Result<GenreRewriteResult, CommandError> {
+    let db = mongo_state.database().await?;
+    merge_into(&db, &[old], &new).await
+}
+
+/// Merges several genres (e.g. spelling variants) into a single target genre.
+#[command]
+pub async fn merge_genres(sources: Vec<String>, target: String, mongo_state: State<'_, MongoState>) -> Result<GenreRewriteResult, CommandError> {
+    if sources.is_empty() {
+        return Err(CommandError::Validation("No source genres given to merge".to_string()));
+    }
+    let db = mongo_state.database().await?;
+    merge_into(&db, &sources, &target).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sources(names: &[&str]) -> Vec<String> {
+        names.iter().map(|s| s.to_string()).collect()
+    }
+
+    fn keys(names: &[&str]) -> Vec<String> {
+        names.iter().map(|s| genre_key(s)).collect()
+    }
+
+    #[test]
+    fn test_clean_genre_and_key() {
+        assert_eq!(clean_genre("  Hip   Hop \t"), Some("Hip Hop".to_string()));
+        assert_eq!(clean_genre("   "), None);
+        assert_eq!(genre_key("Hip Hop"), "hiphop");
+        assert_eq!(genre_key("hip-hop"), "hiphop");
+        assert_eq!(genre_key("HipHop"), "hiphop");
+    }
+
+    #[test]
+    fn test_rename_rewrites_track_genre_array() {
+        let track = doc! { "_id": "t1", "genre": ["Jazz", "hip-hop", "Soul"] };
+        let rewritten = rewrite_genre_field(&track, "genre", &keys(&["hip-hop"]), "Hip Hop");
+        assert_eq!(rewritten, Some(sources(&["Jazz", "Hip Hop", "Soul"])));
+    }
+
+    #[test]
+    fn test_rename_rewrites_album_genres_array() {
+        let album = doc! { "_id": "a1", "genres": ["HipHop"] };
+        let rewritten = rewrite_genre_field(&album, "genres", &keys(&["HipHop"]), "Hip Hop");
+        assert_eq!(rewritten, Some(sources(&["Hip Hop"])));
+    }
+
+    #[test]
+    fn test_merge_deduplicates_and_skips_unaffected_documents() {
+        let track = doc! { "genre": ["Hip Hop", "hip-hop", "HipHop"] };
+        let rewritten = rewrite_genre_field(&track, "genre", &keys(&["hip-hop", "HipHop"]), "Hip Hop");
+        assert_eq!(rewritten, Some(sources(&["Hip Hop"])));
+
+        let untouched = doc! { "genre": ["Jazz"] };
+        assert_eq!(rewrite_genre_field(&untouched, "genre", &keys(&["hip-hop"]), "Hip Hop"), None);
+        // Documents without the field are left alone
+        assert_eq!(rewrite_genre_field(&doc! {}, "genres", &keys(&["hip-hop"]), "Hip Hop"), None);
+    }
+
+    #[test]
+    fn test_rewrite_matches_other_spellings_of_a_source() {
+        let track = doc! { "genre": ["HIP-HOP", "Jazz"] };
+        let rewritten = rewrite_genre_field(&track, "genre", &keys(&["Hip Hop"]), "Rap");
+        assert_eq!(rewritten, Some(sources(&["Rap", "Jazz"])));
+
+        let canonical = doc! { "genre": ["Rap"] };
+        assert_eq!(rewrite_genre_field(&canonical, "genre", &keys(&["rap"]), "Rap"), None);
+    }
+}
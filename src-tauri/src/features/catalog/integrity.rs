@@ -0,0 +1,287 @@
+//! Catalog integrity check: confirms that the R2 objects referenced by track documents
+//! still exist (e.g. after objects were deleted directly in the Cloudflare dashboard).
+//! Runs as a background job; progress is reported through `job://progress`.
+
+use futures_util::stream::{self, StreamExt, TryStreamExt};
+use log::{info, warn};
+use mongodb::bson::{doc, Document};
+use mongodb::options::FindOptions;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use tauri::{command, AppHandle, Manager, Wry};
+
+use super::storage::id_to_string;
+use crate::features::upload::originals::is_local_original;
+use crate::core::jobs::{spawn_job, JobContext, JobId, JobKind};
+use crate::core::r2::{R2Client, TRACK_KEY_FIELDS};
+use crate::core::timing::{with_timeout, TimeoutSettings};
+use crate::CommandError;
+use crate::{MongoState, R2State};
+use crate::core::db_config::{CatalogCollections, CatalogDatabase, cursor_batch_size};
+
+/// Number of `head_object` requests kept in flight at once.
+const MAX_CONCURRENT_CHECKS: usize = 16;
+
+/// Emit a progress event every this many tracks.
+const PROGRESS_EVENT_INTERVAL: usize = 100;
+
+/// An R2 key referenced by a track that no longer exists in the bucket.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct MissingObject {
+    pub track_id: String,
+    pub title: Option<String>,
+    /// Which track field holds the key (one of `TRACK_KEY_FIELDS`)
+    pub field: String,
+    pub key: String,
+}
+
+/// An original upload whose R2 size differs from the `file_size` recorded on the track.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SizeMismatch {
+    pub track_id: String,
+    pub title: Option<String>,
+    pub key: String,
+    pub expected_bytes: i64,
+    pub actual_bytes: i64,
+}
+
+/// A track with no R2 keys at all.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct TrackWithoutKeys {
+    pub track_id: String,
+    pub title: Option<String>,
+}
+
+/// Findings of an integrity check.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct IntegrityReport {
+    pub checked_tracks: usize,
+    pub missing_objects: Vec<MissingObject>,
+    pub size_mismatches: Vec<SizeMismatch>,
+    pub tracks_without_keys: Vec<TrackWithoutKeys>,
+    /// Tracks uploaded without their original, which is kept on local storage by design
+    pub local_originals: usize,
+    /// R2 requests that failed for reasons other than the object being missing
+    pub errors: Vec<String>,
+    /// Where the JSON copy of this report was written
+    pub report_path: Option<String>,
+}
+
+/// Arguments of an integrity check job.
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct IntegrityJobParams {
+    /// Check a random subset of this many tracks instead of the whole catalog
+    #[serde(default)]
+    pub sample_size: Option<u32>,
+}
+
+/// Result of checking one track, merged into the report as checks finish.
+#[derive(Default)]
+struct TrackCheck {
+    missing: Vec<MissingObject>,
+    mismatch: Option<SizeMismatch>,
+    without_keys: Option<TrackWithoutKeys>,
+    local_original: bool,
+    errors: Vec<String>,
+}
+
+impl IntegrityReport {
+    fn merge(&mut self, check: TrackCheck) {
+        self.checked_tracks += 1;
+        self.missing_objects.extend(check.missing);
+        self.size_mismatches.extend(check.mismatch);
+        self.tracks_without_keys.extend(check.without_keys);
+        self.local_originals += usize::from(check.local_original);
+        self.errors.extend(check.errors);
+    }
+}
+
+fn non_empty_key(track_doc: &Document, field: &str) -> Option<String> {
+    track_doc.get_str(field).ok().filter(|key| !key.is_empty()).map(String::from)
+}
+
+/// Heads every R2 object the track references.
+async fn check_track(r2_client: &R2Client, track_doc: Document) -> TrackCheck {
+    let mut check = TrackCheck::default();
+    let track_id = track_doc.get("_id").and_then(id_to_string).unwrap_or_default();
+    let title = track_doc.get_str("title").ok().map(String::from);
+    // No original key is expected; the delivery copy is still checked
+    check.local_original = is_local_original(&track_doc);
+    let r2_client = r2_client.for_track(&track_doc);
+
+    let mut keys: Vec<(&str, String)> = Vec::new();
+    for field in TRACK_KEY_FIELDS {
+        if let Some(key) = non_empty_key(&track_doc, field) {
+            if !keys.iter().any(|(_, existing)| *existing == key) {
+                keys.push((field, key));
+            }
+        }
+    }
+    if keys.is_empty() {
+        check.without_keys = Some(TrackWithoutKeys { track_id, title });
+        return check;
+    }
+
+    for (field, key) in keys {
+        match r2_client.object_size(&key).await {
+            Ok(None) => check.missing.push(MissingObject {
+                track_id: track_id.clone(),
+                title: title.clone(),
+                field: field.to_string(),
+                key,
+            }),
+            Ok(Some(actual_bytes)) => {
+                // `file_size` is the size of the original upload, so only an unencrypted original can be compared
+                let expected = track_doc.get_i64("file_size").ok().filter(|size| *size > 0)
+                    .filter(|_| !track_doc.get_bool("encrypted").unwrap_or(false));
+                if let (true, Some(expected_bytes)) = (field == "r2_original_key", expected) {
+                    if expected_bytes != actual_bytes {
+                        check.mismatch = Some(SizeMismatch {
+                            track_id: track_id.clone(),
+                            title: title.clone(),
+                            key,
+                            expected_bytes,
+                            actual_bytes,
+                        });
+                    }
+                }
+            }
+            Err(e) => check.errors.push(format!("Track {}: failed to check {}: {}", track_id, key, e)),
+        }
+    }
+    check
+}
+
+/// Loads the track documents to check, optionally as a random sample.
+async fn load_tracks(tracks_collection: &mongodb::Collection<Document>, sample_size: Option<u32>) -> Result<Vec<Document>, CommandError> {
+    let mut projection = doc! { "_id": 1, "title": 1, "file_size": 1, "encrypted": 1, "original_location": 1, "r2_bucket": 1 };
+    for field in TRACK_KEY_FIELDS {
+        projection.insert(field, 1);
+    }
+    let docs = match sample_size {
+        Some(size) => {
+            let pipeline = vec![doc! { "$sample": { "size": size as i64 } }, doc! { "$project": projection }];
+            tracks_collection.aggregate(pipeline, None).await?.try_collect().await?
+        }
+        None => {
+            let options = FindOptions::builder().batch_size(cursor_batch_size()).projection(projection).build();
+            tracks_collection.find(None, options).await?.try_collect().await?
+        }
+    };
+    Ok(docs)
+}
+
+/// Writes the report under `<app data>/integrity_reports/` and returns the file path.
+fn write_report_file(app_handle: &AppHandle<Wry>, report: &IntegrityReport) -> Result<String, CommandError> {
+    let dir = app_handle.path().app_data_dir()
+        .map_err(|e| CommandError::FileSystem(format!("Failed to resolve app data directory: {}", e)))?
+        .join("integrity_reports");
+    fs::create_dir_all(&dir)?;
+    let path = dir.join(format!("integrity_{}.json", chrono::Local::now().format("%Y%m%d_%H%M%S")));
+    fs::write(&path, serde_json::to_vec_pretty(report)?)?;
+    Ok(path.to_string_lossy().into_owned())
+}
+
+/// Starts an integrity check job. Fails straight away if the clients aren't ready.
+pub async fn start_integrity_job(app_handle: &AppHandle<Wry>, params: IntegrityJobParams) -> Result<JobId, CommandError> {
+    if params.sample_size == Some(0) {
+        return Err(CommandError::Validation("Sample size must be greater than zero".to_string()));
+    }
+
+    let tracks_collection = {
+        let mongo_state = app_handle.state::<MongoState>();
+        let client_lock = mongo_state.client.lock().await;
+        let client = client_lock.as_ref()
+            .ok_or_else(|| CommandError::Configuration("MongoDB client not initialized".to_string()))?;
+        client.catalog_database().tracks::<Document>()
+    };
+    let r2_client = R2Client::from_state(&app_handle.state::<R2State>()).await?;
+    let timeout = app_handle.state::<TimeoutSettings>().get().audit();
+
+    spawn_job(app_handle, JobKind::IntegrityCheck, move |job| async move {
+        run_integrity_check(&job, &tracks_collection, &r2_client, timeout, params.sample_size).await
+    })
+}
+
+async fn run_integrity_check(
+    job: &JobContext,
+    tracks_collection: &mongodb::Collection<Document>,
+    r2_client: &R2Client,
+    timeout: std::time::Duration,
+    sample_size: Option<u32>,
+) -> Result<IntegrityReport, CommandError> {
+    let mut report = with_timeout("verify_catalog_integrity", timeout, async {
+        let tracks = load_tracks(tracks_collection, sample_size).await?;
+        let total = tracks.len();
+        info!("Verifying R2 objects for {} tracks", total);
+        job.progress(0, total as u64, None);
+
+        let mut report = IntegrityReport::default();
+        let mut checks = stream::iter(tracks)
+            .map(|track_doc| check_track(r2_client, track_doc))
+            .buffer_unordered(MAX_CONCURRENT_CHECKS);
+        while let Some(check) = checks.next().await {
+            if job.is_cancelled() {
+                return Err(CommandError::OperationFailed("Integrity check was cancelled".to_string()));
+            }
+            report.merge(check);
+            if report.checked_tracks % PROGRESS_EVENT_INTERVAL == 0 || report.checked_tracks == total {
+                job.progress(report.checked_tracks as u64, total as u64, None);
+            }
+        }
+        Ok::<_, CommandError>(report)
+    }).await?;
+
+    info!(
+        "Integrity check finished: {} missing objects, {} size mismatches, {} tracks without keys, {} local originals, {} errors",
+        report.missing_objects.len(), report.size_mismatches.len(), report.tracks_without_keys.len(), report.local_originals, report.errors.len()
+    );
+
+    match write_report_file(job.app_handle(), &report) {
+        Ok(path) => report.report_path = Some(path),
+        Err(e) => warn!("Failed to write integrity report file: {}", e),
+    }
+    Ok(report)
+}
+
+// --- Tauri Commands ---
+
+/// Starts a job checking that every track's R2 objects exist and that originals match
+/// the recorded `file_size`, and returns its id. Pass `sample_size` to check a random
+/// subset instead of the whole catalog. The finished job's result is the report, which
+/// is also saved as JSON.
+#[command]
+pub async fn verify_catalog_integrity(sample_size: Option<u32>, app_handle: AppHandle<Wry>) -> Result<JobId, CommandError> {
+    start_integrity_job(&app_handle, IntegrityJobParams { sample_size }).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_report_merge_accumulates_findings() {
+        let mut report = IntegrityReport::default();
+        report.merge(TrackCheck {
+            missing: vec![MissingObject { track_id: "1".into(), title: None, field: "r2_aac_key".into(), key: "tracks/aac/a.m4a".into() }],
+            ..Default::default()
+        });
+        report.merge(TrackCheck { without_keys: Some(TrackWithoutKeys { track_id: "2".into(), title: None }), ..Default::default() });
+        report.merge(TrackCheck::default());
+        report.merge(TrackCheck { local_original: true, ..Default::default() });
+
+        assert_eq!(report.checked_tracks, 4);
+        assert_eq!(report.missing_objects.len(), 1);
+        assert_eq!(report.tracks_without_keys.len(), 1);
+        assert_eq!(report.local_originals, 1);
+        assert!(report.size_mismatches.is_empty());
+    }
+
+    #[test]
+    fn test_non_empty_key() {
+        let track = doc! { "r2_original_key": "tracks/original/a.wav", "r2_aac_key": "", "other": null };
+        assert_eq!(non_empty_key(&track, "r2_original_key"), Some("tracks/original/a.wav".to_string()));
+        assert_eq!(non_empty_key(&track, "r2_aac_key"), None);
+        assert_eq!(non_empty_key(&track, "other"), None);
+    }
+}
@@ -0,0 +1,229 @@
+//! One-off data fixes run whenever a MongoDB connection is established, followed by
+//! index creation. Every migration is idempotent, so running it against an already
+//! migrated database does nothing. Migrations that scan whole collections are also
+//! versioned: the version applied is recorded in the `migrations` collection, and they
+//! only run again when their version is bumped.
+
+use futures_util::stream::TryStreamExt;
+use log::info;
+use mongodb::bson::{doc, oid::ObjectId, Bson, DateTime, Document};
+use mongodb::options::UpdateOptions;
+use mongodb::Database;
+
+use super::album_merge::listed_track_ids;
+use super::album_names::AlbumNameCache;
+use super::changes::{record_deletions, touched, CatalogKind};
+use super::notes::migrate_comments_to_notes;
+use super::slugs::backfill_slugs;
+use super::storage::id_to_string;
+use super::storage::mongodb::create_indexes;
+use crate::CommandError;
+use crate::core::db_config::CatalogCollections;
+
+const MIGRATIONS_COLLECTION: &str = "migrations";
+
+/// Bump to run `dedupe_albums` again on databases that already ran it.
+const DEDUPE_ALBUMS_VERSION: i32 = 1;
+
+/// Version of a migration recorded for this database, 0 if it never ran.
+async fn applied_version(db: &Database, name: &str) -> Result<i32, CommandError> {
+    let record = db.collection::<Document>(MIGRATIONS_COLLECTION).find_one(doc! { "_id": name }, None).await?;
+    Ok(record.and_then(|record| record.get_i32("version").ok()).unwrap_or(0))
+}
+
+async fn record_version(db: &Database, name: &str, version: i32) -> Result<(), CommandError> {
+    db.collection::<Document>(MIGRATIONS_COLLECTION)
+        .update_one(
+            doc! { "_id": name },
+            doc! { "$set": { "version": version, "applied_at": DateTime::now() } },
+            UpdateOptions::builder().upsert(true).build(),
+        )
+        .await?;
+    Ok(())
+}
+
+/// Runs all migrations, then creates the indexes. Migrations come first because some
+/// indexes (e.g. the unique album index) cannot be built while the data violates them.
+/// Album names cached for merged albums are dropped from `album_cache`.
+pub async fn run_migrations(db: &Database, album_cache: &AlbumNameCache) -> Result<(), CommandError> {
+    if applied_version(db, "dedupe_albums").await? < DEDUPE_ALBUMS_VERSION {
+        let merged = dedupe_albums(db, album_cache).await?;
+        if merged > 0 {
+            info!("Merged {} duplicate album documents", merged);
+        }
+        record_version(db, "dedupe_albums", DEDUPE_ALBUMS_VERSION).await?;
+    }
+    let migrated = migrate_comments_to_notes(db).await?;
+    if migrated > 0 {
+        info!("Moved the comments of {} tracks into notes", migrated);
+    }
+    let slugged = backfill_slugs(db).await?;
+    if slugged > 0 {
+        info!("Generated slugs for {} tracks and albums", slugged);
+    }
+    create_indexes(db)
+        .await
+        .map_err(|e| CommandError::Database(format!("Failed to create indexes: {}", e)))
+}
+
+/// Creation time of an album: `date_added`, falling back to the ObjectId timestamp.
+fn album_created_at(album: &Document) -> Option<DateTime> {
+    album.get_datetime("date_added").ok().copied()
+        .or_else(|| album.get_object_id("_id").ok().map(|id| id.timestamp()))
+}
+
+/// Picks the album to keep from a group of duplicates: the oldest one, with albums of
+/// unknown age last and ties broken by id so the choice is stable.
+pub(super) fn oldest_album(albums: &[Document]) -> Option<&Document> {
+    albums.iter().min_by_key(|album| {
+        let created_at = album_created_at(album);
+        (created_at.is_none(), created_at, album.get("_id").and_then(id_to_string))
+    })
+}
+
+/// `album_id` values that refer to the given album: older tracks store the id as a hex
+/// string rather than an ObjectId.
+pub(super) fn album_id_references(id: &Bson) -> Vec<Bson> {
+    match id {
+        Bson::ObjectId(oid) => vec![id.clone(), Bson::String(oid.to_hex())],
+        Bson::String(s) => match ObjectId::parse_str(s) {
+            Ok(oid) => vec![id.clone(), Bson::ObjectId(oid)],
+            Err(_) => vec![id.clone()],
+        },
+        other => vec![other.clone()],
+    }
+}
+
+/// Album fields that identify the album or are maintained separately, never copied from
+/// a duplicate.
+const UNMERGED_ALBUM_FIELDS: [&str; 8] = ["_id", "name", "artist", "grouping_key", "track_ids", "slug", "date_added", "updated_at"];
+
+/// Whether a field holds no information: null, blank text or an empty array or document.
+fn is_blank(value: &Bson) -> bool {
+    match value {
+        Bson::Null => true,
+        Bson::String(s) => s.trim().is_empty(),
+        Bson::Array(items) => items.is_empty(),
+        Bson::Document(fields) => fields.is_empty(),
+        _ => false,
+    }
+}
+
+/// Fields the kept album is missing (or has blank) that a duplicate has, e.g. `art_path`
+/// or `release_year`. Duplicates are read in the given order, so the first one with a
+/// value wins.
+pub(super) fn merged_album_fields(keeper: &Document, duplicates: &[Document]) -> Document {
+    let mut fields = Document::new();
+    for duplicate in duplicates {
+        for (field, value) in duplicate {
+            if UNMERGED_ALBUM_FIELDS.contains(&field.as_str()) || is_blank(value) || fields.contains_key(field) {
+                continue;
+            }
+            if keeper.get(field).map_or(true, is_blank) {
+                fields.insert(field.clone(), value.clone());
+            }
+        }
+    }
+    fields
+}
+
+/// Merges albums sharing a name, artist and `grouping_key` (unset unless the album was
+/// created for an explicit id) into the oldest of them: fields the oldest album lacks
+/// (art, year, ...) are taken from the duplicates, their tracks are repointed to it and
+/// the duplicates deleted. Returns the number of album documents removed.
+pub async fn dedupe_albums(db: &Database, album_cache: &AlbumNameCache) -> Result<u64, CommandError> {
+    let albums_collection = db.albums::<Document>();
+    let tracks_collection = db.tracks::<Document>();
+
+    let pipeline = vec![
+        doc! { "$group": {
+            "_id": { "name": "$name", "artist": "$artist", "grouping_key": "$grouping_key" },
+            "albums": { "$push": "$$ROOT" },
+            "count": { "$sum": 1 },
+        } },
+        doc! { "$match": { "count": { "$gt": 1 } } },
+    ];
+    let groups: Vec<Document> = albums_collection.aggregate(pipeline, None).await?.try_collect().await?;
+
+    let mut removed = 0;
+    for group in groups {
+        let albums: Vec<Document> = group.get_array("albums").map(|albums| {
+            albums.iter().filter_map(|album| album.as_document().cloned()).collect()
+        }).unwrap_or_default();
+        let Some(keeper) = oldest_album(&albums).cloned() else { continue };
+        let Some(keeper_id) = keeper.get("_id").cloned() else { continue };
+        let mut duplicates: Vec<Document> = albums.into_iter().filter(|album| album.get("_id") != Some(&keeper_id)).collect();
+        duplicates.sort_by_key(|album| {
+            let created_at = album_created_at(album);
+            (created_at.is_none(), created_at)
+        });
+        let duplicate_ids: Vec<Bson> = duplicates.iter().filter_map(|album| album.get("_id").cloned()).collect();
+        let references: Vec<Bson> = duplicate_ids.iter().flat_map(album_id_references).collect();
+
+        let mut update = doc! {};
+        let fields = merged_album_fields(&keeper, &duplicates);
+        if !fields.is_empty() {
+            update.insert("$set", fields);
+        }
+        let track_ids = listed_track_ids(&duplicates);
+        if !track_ids.is_empty() {
+            update.insert("$addToSet", doc! { "track_ids": { "$each": track_ids } });
+        }
+        if !update.is_empty() {
+            albums_collection.update_one(doc! { "_id": keeper_id.clone() }, touched(update), None).await?;
+        }
+        tracks_collection
+            .update_many(doc! { "album_id": { "$in": references } }, touched(doc! { "$set": { "album_id": keeper_id.clone() } }), None)
+            .await?;
+        let deleted = albums_collection.delete_many(doc! { "_id": { "$in": &duplicate_ids } }, None).await?;
+        let deleted_ids: Vec<String> = duplicate_ids.iter().filter_map(id_to_string).collect();
+        for id in &deleted_ids {
+            album_cache.invalidate(id);
+        }
+        record_deletions(db, CatalogKind::Album, deleted_ids).await;
+        removed += deleted.deleted_count;
+    }
+    Ok(removed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_oldest_album_prefers_date_added_then_id_timestamp() {
+        let early = DateTime::from_millis(1_000);
+        let late = DateTime::from_millis(2_000);
+        let albums = vec![
+            doc! { "_id": "b", "date_added": late },
+            doc! { "_id": "a" }, // Unknown age: never preferred
+            doc! { "_id": "c", "date_added": early },
+        ];
+        assert_eq!(oldest_album(&albums).and_then(|a| a.get_str("_id").ok()), Some("c"));
+
+        let oid = ObjectId::new();
+        let albums = vec![doc! { "_id": "x" }, doc! { "_id": oid }];
+        assert_eq!(oldest_album(&albums).and_then(|a| a.get_object_id("_id").ok()), Some(oid));
+    }
+
+    #[test]
+    fn test_merged_album_fields_fill_only_missing_fields() {
+        let keeper = doc! { "_id": "keep", "name": "Night Drive", "artist": "Lumen", "art_path": "", "release_year": 2019, "label": Bson::Null };
+        let duplicates = vec![
+            doc! { "_id": "dup1", "name": "Night Drive", "artist": "Lumen", "release_year": 2020, "art_path": "albums/dup1/cover.jpg", "track_ids": ["t1"] },
+            doc! { "_id": "dup2", "name": "Night Drive", "art_path": "albums/dup2/cover.jpg", "label": "Nightshift", "genres": [] },
+        ];
+        assert_eq!(
+            merged_album_fields(&keeper, &duplicates),
+            doc! { "art_path": "albums/dup1/cover.jpg", "label": "Nightshift" },
+        );
+        assert!(merged_album_fields(&keeper, &[]).is_empty());
+    }
+
+    #[test]
+    fn test_album_id_references_cover_both_id_forms() {
+        let oid = ObjectId::new();
+        assert_eq!(album_id_references(&Bson::ObjectId(oid)), vec![Bson::ObjectId(oid), Bson::String(oid.to_hex())]);
+        assert_eq!(album_id_references(&Bson::String("legacy".into())), vec![Bson::String("legacy".into())]);
+    }
+}
@@ -1,4 +1,34 @@
 // src-tauri/src/features/catalog/mod.rs
 pub mod storage;
+pub mod relink;
+pub mod artwork;
+pub mod genres;
+pub mod preview;
+pub mod integrity;
+pub mod vocabulary;
+pub mod waveforms;
+pub mod album_names;
+pub mod playlists;
+pub mod custom_fields;
+pub mod transcoded;
+pub mod migrations;
+pub mod reextract;
+pub mod reencode;
+pub mod replaygain;
+pub mod trash;
+pub mod stream;
+pub mod suggestions;
+pub mod export;
+pub mod backup;
+pub mod notes;
+pub mod object_metadata;
+pub mod r2_keys;
+pub mod stats;
+pub mod delete_preview;
+pub mod album_merge;
+pub mod changes;
+pub mod distinct;
+pub mod slugs;
+pub mod staging;
 // pub mod commands; // Add later when commands are refactored
 // pub mod types;   // Add later if needed
\ No newline at end of file
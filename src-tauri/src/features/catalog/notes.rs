@@ -0,0 +1,225 @@
+//! Track notes: timestamped, attributed comments kept in a `notes` array on the track,
+//! replacing the single `comments` string that each editor overwrote.
+//!
+//! Notes are changed with `$push`, `$pull`, and array filters, never by rewriting the
+//! array, so concurrent editors don't lose each other's notes. They are not part of
+//! `TrackWithAlbum`, and track listings leave them out; load them with `get_track_notes`.
+
+use log::info;
+use mongodb::bson::{self, doc, oid::ObjectId, DateTime, Document};
+use mongodb::options::{FindOneAndUpdateOptions, FindOneOptions, ReturnDocument};
+use mongodb::{Collection, Database};
+use serde::{Deserialize, Serialize};
+use tauri::{command, State};
+
+use super::changes::touched;
+use super::storage::id_filter;
+use crate::CommandError;
+use crate::MongoState;
+use crate::core::db_config::CatalogCollections;
+
+/// Longest note text accepted, in characters.
+pub const MAX_NOTE_LENGTH: usize = 2000;
+const MAX_AUTHOR_LENGTH: usize = 100;
+
+/// Author given to notes created from the legacy `comments` field.
+pub const LEGACY_COMMENTS_AUTHOR: &str = "Imported comment";
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct TrackNote {
+    pub id: String,
+    pub author: String,
+    pub text: String,
+    pub created_at: DateTime,
+    pub edited_at: Option<DateTime>,
+}
+
+impl TrackNote {
+    pub fn new(author: &str, text: &str, created_at: DateTime) -> Self {
+        Self { id: ObjectId::new().to_hex(), author: author.to_string(), text: text.to_string(), created_at, edited_at: None }
+    }
+}
+
+/// Trims a note and checks it is neither empty nor too long.
+fn validate_note_text(text: &str) -> Result<String, CommandError> {
+    let text = text.trim();
+    if text.is_empty() {
+        return Err(CommandError::Validation("Note text must not be empty".to_string()));
+    }
+    let length = text.chars().count();
+    if length > MAX_NOTE_LENGTH {
+        return Err(CommandError::Validation(format!("Notes are limited to {} characters, this one has {}", MAX_NOTE_LENGTH, length)));
+    }
+    Ok(text.to_string())
+}
+
+fn validate_author(author: &str) -> Result<String, CommandError> {
+    let author = author.trim();
+    if author.is_empty() {
+        return Err(CommandError::Validation("Note author must not be empty".to_string()));
+    }
+    if author.chars().count() > MAX_AUTHOR_LENGTH {
+        return Err(CommandError::Validation(format!("Note author is limited to {} characters", MAX_AUTHOR_LENGTH)));
+    }
+    Ok(author.to_string())
+}
+
+/// The notes of a track document, oldest first. Unreadable entries are skipped.
+fn notes_from_document(track_doc: &Document) -> Vec<TrackNote> {
+    track_doc.get_array("notes").map(|notes| {
+        notes.iter()
+            .filter_map(|note| note.as_document().cloned())
+            .filter_map(|note| bson::from_document(note).ok())
+            .collect()
+    }).unwrap_or_default()
+}
+
+fn tracks_collection(db: &Database) -> Collection<Document> {
+    db.tracks::<Document>()
+}
+
+
+/// Applies `update` to the track and returns its notes afterwards, or `not_found` if no
+/// document matched `filter`.
+async fn update_notes(
+    db: &Database,
+    filter: Document,
+    update: Document,
+    array_filters: Option<Vec<Document>>,
+    not_found: String,
+) -> Result<Vec<TrackNote>, CommandError> {
+    let options = FindOneAndUpdateOptions::builder()
+        .projection(doc! { "notes": 1 })
+        .array_filters(array_filters)
+        .return_document(ReturnDocument::After)
+        .build();
+    let track_doc = tracks_collection(db).find_one_and_update(filter, touched(update), options).await?
+        .ok_or(CommandError::NotFound(not_found))?;
+    Ok(notes_from_document(&track_doc))
+}
+
+/// Creates a first note from the legacy `comments` field of tracks that have no notes
+/// yet. The `comments` field itself is left in place. Returns the number of tracks
+/// migrated.
+pub async fn migrate_comments_to_notes(db: &Database) -> Result<u64, CommandError> {
+    let tracks = tracks_collection(db);
+    let filter = doc! { "comments": { "$type": "string", "$regex": r"\S" }, "notes": { "$exists": false } };
+    let options = FindOneOptions::builder().projection(doc! { "comments": 1, "date_added": 1 }).build();
+    let mut migrated = 0;
+    // One track at a time: every note needs its own id
+    while let Some(track_doc) = tracks.find_one(filter.clone(), options.clone()).await? {
+        let Some(id) = track_doc.get("_id").cloned() else { break };
+        let created_at = track_doc.get_datetime("date_added").ok().copied().unwrap_or_else(DateTime::now);
+        let note = TrackNote::new(LEGACY_COMMENTS_AUTHOR, track_doc.get_str("comments").unwrap_or_default().trim(), created_at);
+        let note = bson::to_bson(&note).map_err(|e| CommandError::Database(format!("Failed to convert note to BSON: {}", e)))?;
+        let result = tracks.update_one(doc! { "_id": id, "notes": { "$exists": false } }, touched(doc! { "$set": { "notes": [note] } }), None).await?;
+        if result.modified_count == 0 {
+            break; // Not migrated and still matched: stop rather than loop forever
+        }
+        migrated += 1;
+    }
+    Ok(migrated)
+}
+
+// --- Tauri Commands ---
+
+/// Notes of a track, oldest first.
+#[command]
+pub async fn get_track_notes(track_id: String, mongo_state: State<'_, MongoState>) -> Result<Vec<TrackNote>, CommandError> {
+    let db = mongo_state.database().await?;
+    let options = FindOneOptions::builder().projection(doc! { "notes": 1 }).build();
+    let track_doc = tracks_collection(&db).find_one(id_filter(&track_id), options).await?
+        .ok_or_else(|| CommandError::NotFound(format!("Track {} not found", track_id)))?;
+    Ok(notes_from_document(&track_doc))
+}
+
+/// Adds a note to a track. Returns the track's notes.
+#[command]
+pub async fn add_track_note(
+    track_id: String,
+    author: String,
+    text: String,
+    mongo_state: State<'_, MongoState>,
+) -> Result<Vec<TrackNote>, CommandError> {
+    let note = TrackNote::new(&validate_author(&author)?, &validate_note_text(&text)?, DateTime::now());
+    let note_bson = bson::to_bson(&note).map_err(|e| CommandError::Database(format!("Failed to convert note to BSON: {}", e)))?;
+    let db = mongo_state.database().await?;
+
+    let notes = update_notes(
+        &db,
+        id_filter(&track_id),
+        doc! { "$push": { "notes": note_bson } },
+        None,
+        format!("Track {} not found", track_id),
+    ).await?;
+    info!("Added note {} to track {}", note.id, track_id);
+    Ok(notes)
+}
+
+/// Replaces the text of a note and marks it edited. Returns the track's notes.
+#[command]
+pub async fn edit_track_note(
+    track_id: String,
+    note_id: String,
+    text: String,
+    mongo_state: State<'_, MongoState>,
+) -> Result<Vec<TrackNote>, CommandError> {
+    let text = validate_note_text(&text)?;
+    let db = mongo_state.database().await?;
+
+    let mut filter = id_filter(&track_id);
+    filter.insert("notes.id", &note_id);
+    let notes = update_notes(
+        &db,
+        filter,
+        doc! { "$set": { "notes.$[note].text": text, "notes.$[note].edited_at": DateTime::now() } },
+        Some(vec![doc! { "note.id": &note_id }]),
+        format!("Note {} not found on track {}", note_id, track_id),
+    ).await?;
+    info!("Edited note {} on track {}", note_id, track_id);
+    Ok(notes)
+}
+
+/// Deletes a note. Returns the track's remaining notes.
+#[command]
+pub async fn delete_track_note(
+    track_id: String,
+    note_id: String,
+    mongo_state: State<'_, MongoState>,
+) -> Result<Vec<TrackNote>, CommandError> {
+    let db = mongo_state.database().await?;
+
+    let mut filter = id_filter(&track_id);
+    filter.insert("notes.id", &note_id);
+    let notes = update_notes(
+        &db,
+        filter,
+        doc! { "$pull": { "notes": { "id": &note_id } } },
+        None,
+        format!("Note {} not found on track {}", note_id, track_id),
+    ).await?;
+    info!("Deleted note {} from track {}", note_id, track_id);
+    Ok(notes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mongodb::bson::Bson;
+
+    #[test]
+    fn test_validate_note_text() {
+        assert_eq!(validate_note_text("  Needs a new mix  ").unwrap(), "Needs a new mix");
+        assert!(validate_note_text(" \n ").is_err());
+        assert!(validate_note_text(&"é".repeat(MAX_NOTE_LENGTH)).is_ok());
+        assert!(validate_note_text(&"a".repeat(MAX_NOTE_LENGTH + 1)).is_err());
+    }
+
+    #[test]
+    fn test_notes_from_document_skips_malformed_entries() {
+        let note = TrackNote::new("Sam", "Cleared for sync", DateTime::from_millis(1_000));
+        let track_doc = doc! { "notes": [bson::to_bson(&note).unwrap(), { "text": "no id" }, Bson::Null] };
+        assert_eq!(notes_from_document(&track_doc), vec![note]);
+        assert!(notes_from_document(&doc! { "comments": "old" }).is_empty());
+    }
+}
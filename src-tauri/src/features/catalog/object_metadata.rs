@@ -0,0 +1,101 @@
+//! Track fields mirrored onto the user-defined metadata (`x-amz-meta-*`) of the track's R2
+//! objects, for clients that read tags from the object rather than the catalog. Opt-in
+//! per update with `sync_object_metadata`, since each object costs a HEAD and a COPY.
+
+use log::warn;
+use mongodb::bson::{Bson, Document};
+use std::collections::HashMap;
+
+use super::storage::UpdateTrackPayload;
+use crate::core::r2::{R2Client, TRACK_KEY_FIELDS};
+
+/// Track fields mirrored onto the objects.
+pub const SYNCED_FIELDS: [&str; 5] = ["title", "genre", "composers", "isrc", "catalog_number"];
+
+/// Whether an update changes a field that is mirrored onto the objects.
+pub fn touches_synced_fields(payload: &UpdateTrackPayload) -> bool {
+    payload.title.is_some()
+        || payload.genre.is_some()
+        || payload.composers.is_some()
+        || payload.isrc.is_some()
+        || payload.catalog_number.is_some()
+}
+
+/// Metadata name of a field; hyphens, as some proxies drop headers with underscores.
+fn metadata_name(field: &str) -> String {
+    field.replace('_', "-")
+}
+
+/// Header values must be printable ASCII; other bytes, and `%`, are percent-encoded.
+fn header_value(value: &str) -> String {
+    let mut encoded = String::with_capacity(value.len());
+    for byte in value.bytes() {
+        if (0x20..0x7f).contains(&byte) && byte != b'%' {
+            encoded.push(byte as char);
+        } else {
+            encoded.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    encoded
+}
+
+/// Object metadata for the synced fields the track has; lists are joined with ", ".
+fn object_metadata(track_doc: &Document) -> HashMap<String, String> {
+    let mut metadata = HashMap::new();
+    for field in SYNCED_FIELDS {
+        let value = match track_doc.get(field) {
+            Some(Bson::String(value)) => value.trim().to_string(),
+            Some(Bson::Array(values)) => values.iter().filter_map(Bson::as_str).map(str::trim).collect::<Vec<_>>().join(", "),
+            _ => continue,
+        };
+        if !value.is_empty() {
+            metadata.insert(metadata_name(field), header_value(&value));
+        }
+    }
+    metadata
+}
+
+/// Writes the track's synced fields onto each of its audio objects, removing those the
+/// track no longer has. Returns how many objects were updated; missing objects are skipped.
+pub async fn sync_object_metadata(r2_client: &R2Client, track_doc: &Document) -> Result<usize, String> {
+    let metadata = object_metadata(track_doc);
+    let removed_names: Vec<String> = SYNCED_FIELDS.iter().map(|field| metadata_name(field)).filter(|name| !metadata.contains_key(name)).collect();
+    let removed: Vec<&str> = removed_names.iter().map(String::as_str).collect();
+    let bucket_client = r2_client.for_track(track_doc);
+
+    let mut keys: Vec<&str> = TRACK_KEY_FIELDS.iter().filter_map(|field| track_doc.get_str(field).ok().filter(|key| !key.is_empty())).collect();
+    keys.sort_unstable();
+    keys.dedup();
+    let mut updated = 0;
+    for key in keys {
+        match bucket_client.update_object_metadata(key, &metadata, &removed).await {
+            Ok(true) => updated += 1,
+            Ok(false) => warn!("Skipped metadata sync for missing object {}", key),
+            Err(e) => return Err(format!("Updating the metadata of {} failed: {}", key, e)),
+        }
+    }
+    Ok(updated)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mongodb::bson::doc;
+
+    #[test]
+    fn test_object_metadata() {
+        let track = doc! {
+            "title": " Café Nights ",
+            "genre": ["Jazz", "Lounge"],
+            "composers": [],
+            "catalog_number": "ABC-100",
+            "isrc": null,
+        };
+        let metadata = object_metadata(&track);
+        assert_eq!(metadata.len(), 3);
+        assert_eq!(metadata["title"], "Caf%C3%A9 Nights");
+        assert_eq!(metadata["genre"], "Jazz, Lounge");
+        assert_eq!(metadata["catalog-number"], "ABC-100");
+        assert_eq!(header_value("100%"), "100%25");
+    }
+}
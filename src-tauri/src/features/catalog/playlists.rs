@@ -0,0 +1,278 @@
+//! Playlists: named, ordered lists of tracks stored in the `playlists` collection.
+//! Each playlist document keeps its tracks as an ordered `track_ids` array of hex ids.
+
+use futures_util::stream::TryStreamExt;
+use log::info;
+use mongodb::bson::{self, doc, oid::ObjectId, Document};
+use mongodb::options::{FindOneAndUpdateOptions, FindOptions, ReturnDocument};
+use mongodb::{Collection, Database};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use tauri::{command, State};
+
+use super::album_names::AlbumNameCache;
+use super::storage::mongodb::{fetch_tracks_by_ids, TrackWithAlbum};
+use crate::CommandError;
+use crate::MongoState;
+use crate::core::db_config::{CatalogCollections, cursor_batch_size};
+
+const PLAYLISTS_COLLECTION: &str = "playlists";
+
+/// A playlist without its track records, as returned by list and edit commands.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Playlist {
+    pub id: String,
+    pub name: String,
+    pub track_ids: Vec<String>,
+}
+
+/// A playlist with its tracks resolved, in playlist order.
+#[derive(Debug, Serialize, Clone)]
+pub struct PlaylistWithTracks {
+    pub id: String,
+    pub name: String,
+    pub tracks: Vec<TrackWithAlbum>,
+}
+
+// --- Helpers ---
+
+fn playlists_collection(db: &Database) -> Collection<Document> {
+    db.collection::<Document>(PLAYLISTS_COLLECTION)
+}
+
+fn parse_object_id(id: &str, what: &str) -> Result<ObjectId, CommandError> {
+    ObjectId::parse_str(id).map_err(|e| CommandError::Validation(format!("Invalid {} ID format '{}': {}", what, id, e)))
+}
+
+fn playlist_from_document(playlist_doc: &Document) -> Result<Playlist, CommandError> {
+    let id = playlist_doc.get_object_id("_id")
+        .map_err(|e| CommandError::Database(format!("Playlist document has an invalid _id: {}", e)))?;
+    let track_ids = playlist_doc.get_array("track_ids")
+        .map(|ids| ids.iter().filter_map(|id| id.as_str().map(String::from)).collect())
+        .unwrap_or_default();
+    Ok(Playlist {
+        id: id.to_hex(),
+        name: playlist_doc.get_str("name").unwrap_or_default().to_string(),
+        track_ids,
+    })
+}
+
+/// Whether `reordered` holds exactly the same ids as `current`, counting repeats.
+fn is_permutation(current: &[String], reordered: &[String]) -> bool {
+    if current.len() != reordered.len() {
+        return false;
+    }
+    let mut counts: HashMap<&str, i64> = HashMap::new();
+    for id in current {
+        *counts.entry(id.as_str()).or_insert(0) += 1;
+    }
+    for id in reordered {
+        *counts.entry(id.as_str()).or_insert(0) -= 1;
+    }
+    counts.values().all(|count| *count == 0)
+}
+
+/// Fails with the ids among `track_ids` that have no track document.
+async fn ensure_tracks_exist(db: &Database, track_ids: &[ObjectId]) -> Result<(), CommandError> {
+    let options = FindOptions::builder().batch_size(cursor_batch_size()).projection(doc! { "_id": 1 }).build();
+    let found: HashSet<ObjectId> = db.tracks::<Document>()
+        .find(doc! { "_id": { "$in": track_ids } }, options)
+        .await?
+        .try_collect::<Vec<Document>>()
+        .await?
+        .iter()
+        .filter_map(|track_doc| track_doc.get_object_id("_id").ok())
+        .collect();
+
+    let missing: Vec<String> = track_ids.iter().filter(|id| !found.contains(id)).map(|id| id.to_hex()).collect();
+    if missing.is_empty() {
+        Ok(())
+    } else {
+        Err(CommandError::NotFound(format!("Tracks not found: {}", missing.join(", "))))
+    }
+}
+
+/// Applies `update` to a playlist and returns the updated playlist.
+async fn update_playlist(db: &Database, playlist_id: ObjectId, update: Document) -> Result<Playlist, CommandError> {
+    let options = FindOneAndUpdateOptions::builder().return_document(ReturnDocument::After).build();
+    let updated = playlists_collection(db)
+        .find_one_and_update(doc! { "_id": playlist_id }, update, options)
+        .await?
+        .ok_or_else(|| CommandError::NotFound(format!("Playlist not found: {}", playlist_id.to_hex())))?;
+    playlist_from_document(&updated)
+}
+
+async fn find_playlist(db: &Database, playlist_id: ObjectId) -> Result<Playlist, CommandError> {
+    let playlist_doc = playlists_collection(db)
+        .find_one(doc! { "_id": playlist_id }, None)
+        .await?
+        .ok_or_else(|| CommandError::NotFound(format!("Playlist not found: {}", playlist_id.to_hex())))?;
+    playlist_from_document(&playlist_doc)
+}
+
+
+// --- Tauri Commands ---
+
+/// Creates an empty playlist.
+#[command]
+pub async fn create_playlist(name: String, mongo_state: State<'_, MongoState>) -> Result<Playlist, CommandError> {
+    let name = name.trim().to_string();
+    if name.is_empty() {
+        return Err(CommandError::Validation("Playlist name must not be empty".to_string()));
+    }
+    let db = mongo_state.database().await?;
+
+    let playlist_id = ObjectId::new();
+    let now = bson::DateTime::now();
+    playlists_collection(&db)
+        .insert_one(doc! {
+            "_id": playlist_id,
+            "name": &name,
+            "track_ids": Vec::<String>::new(),
+            "date_added": now,
+            "updated_at": now,
+        }, None)
+        .await?;
+    info!("Created playlist '{}' ({})", name, playlist_id);
+    Ok(Playlist { id: playlist_id.to_hex(), name, track_ids: Vec::new() })
+}
+
+/// Lists all playlists by name, without resolving their tracks.
+#[command]
+pub async fn list_playlists(mongo_state: State<'_, MongoState>) -> Result<Vec<Playlist>, CommandError> {
+    let db = mongo_state.database().await?;
+
+    let options = FindOptions::builder().batch_size(cursor_batch_size()).sort(doc! { "name": 1 }).build();
+    let playlist_docs: Vec<Document> = playlists_collection(&db).find(None, options).await?.try_collect().await?;
+    playlist_docs.iter().map(playlist_from_document).collect()
+}
+
+/// Returns a playlist with its tracks in playlist order. Tracks deleted since they were
+/// added are left out.
+#[command]
+pub async fn get_playlist(
+    playlist_id: String,
+    mongo_state: State<'_, MongoState>,
+    album_cache: State<'_, AlbumNameCache>,
+) -> Result<PlaylistWithTracks, CommandError> {
+    let playlist_oid = parse_object_id(&playlist_id, "playlist")?;
+    let db = mongo_state.database().await?;
+
+    let playlist = find_playlist(&db, playlist_oid).await?;
+    let track_oids: Vec<ObjectId> = playlist.track_ids.iter().filter_map(|id| ObjectId::parse_str(id).ok()).collect();
+    let tracks = fetch_tracks_by_ids(&db, &album_cache, &track_oids).await?;
+    Ok(PlaylistWithTracks { id: playlist.id, name: playlist.name, tracks })
+}
+
+/// Appends tracks to the end of a playlist. Every track must exist.
+#[command]
+pub async fn add_tracks_to_playlist(
+    playlist_id: String,
+    track_ids: Vec<String>,
+    mongo_state: State<'_, MongoState>,
+) -> Result<Playlist, CommandError> {
+    let playlist_oid = parse_object_id(&playlist_id, "playlist")?;
+    let track_oids = track_ids.iter().map(|id| parse_object_id(id, "track")).collect::<Result<Vec<_>, _>>()?;
+    if track_oids.is_empty() {
+        return Err(CommandError::Validation("No tracks given to add".to_string()));
+    }
+    let db = mongo_state.database().await?;
+
+    ensure_tracks_exist(&db, &track_oids).await?;
+    let hex_ids: Vec<String> = track_oids.iter().map(|id| id.to_hex()).collect();
+    let playlist = update_playlist(&db, playlist_oid, doc! {
+        "$push": { "track_ids": { "$each": &hex_ids } },
+        "$set": { "updated_at": bson::DateTime::now() },
+    }).await?;
+    info!("Added {} tracks to playlist {}", hex_ids.len(), playlist.id);
+    Ok(playlist)
+}
+
+/// Removes every occurrence of the given tracks from a playlist.
+#[command]
+pub async fn remove_tracks_from_playlist(
+    playlist_id: String,
+    track_ids: Vec<String>,
+    mongo_state: State<'_, MongoState>,
+) -> Result<Playlist, CommandError> {
+    let playlist_oid = parse_object_id(&playlist_id, "playlist")?;
+    let db = mongo_state.database().await?;
+
+    let playlist = update_playlist(&db, playlist_oid, doc! {
+        "$pull": { "track_ids": { "$in": &track_ids } },
+        "$set": { "updated_at": bson::DateTime::now() },
+    }).await?;
+    info!("Removed tracks {:?} from playlist {}", track_ids, playlist.id);
+    Ok(playlist)
+}
+
+/// Replaces the playlist order. `track_ids` must contain exactly the playlist's current tracks.
+#[command]
+pub async fn reorder_playlist(
+    playlist_id: String,
+    track_ids: Vec<String>,
+    mongo_state: State<'_, MongoState>,
+) -> Result<Playlist, CommandError> {
+    let playlist_oid = parse_object_id(&playlist_id, "playlist")?;
+    let db = mongo_state.database().await?;
+
+    let current = find_playlist(&db, playlist_oid).await?;
+    if !is_permutation(&current.track_ids, &track_ids) {
+        return Err(CommandError::Validation(
+            "New order must contain exactly the playlist's current tracks".to_string(),
+        ));
+    }
+    // Match on the old order so a concurrent edit is not silently overwritten
+    let options = FindOneAndUpdateOptions::builder().return_document(ReturnDocument::After).build();
+    let updated = playlists_collection(&db)
+        .find_one_and_update(
+            doc! { "_id": playlist_oid, "track_ids": &current.track_ids },
+            doc! { "$set": { "track_ids": &track_ids, "updated_at": bson::DateTime::now() } },
+            options,
+        )
+        .await?
+        .ok_or_else(|| CommandError::OperationFailed("Playlist changed while reordering; reload and try again".to_string()))?;
+    playlist_from_document(&updated)
+}
+
+#[command]
+pub async fn delete_playlist(playlist_id: String, mongo_state: State<'_, MongoState>) -> Result<(), CommandError> {
+    let playlist_oid = parse_object_id(&playlist_id, "playlist")?;
+    let db = mongo_state.database().await?;
+
+    let result = playlists_collection(&db).delete_one(doc! { "_id": playlist_oid }, None).await?;
+    if result.deleted_count == 0 {
+        return Err(CommandError::NotFound(format!("Playlist not found: {}", playlist_id)));
+    }
+    info!("Deleted playlist {}", playlist_id);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ids(values: &[&str]) -> Vec<String> {
+        values.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn test_is_permutation() {
+        assert!(is_permutation(&ids(&["a", "b", "a"]), &ids(&["a", "a", "b"])));
+        assert!(is_permutation(&[], &[]));
+        assert!(!is_permutation(&ids(&["a", "b"]), &ids(&["a"])));
+        assert!(!is_permutation(&ids(&["a", "b", "a"]), &ids(&["a", "b", "b"])));
+        assert!(!is_permutation(&ids(&["a", "b"]), &ids(&["a", "c"])));
+    }
+
+    #[test]
+    fn test_playlist_from_document() {
+        let oid = ObjectId::new();
+        let playlist = playlist_from_document(&doc! { "_id": oid, "name": "Road Trip", "track_ids": ["t2", "t1"] }).unwrap();
+        assert_eq!(playlist.id, oid.to_hex());
+        assert_eq!(playlist.name, "Road Trip");
+        assert_eq!(playlist.track_ids, ids(&["t2", "t1"]));
+
+        assert!(playlist_from_document(&doc! { "_id": "not-an-oid", "name": "x" }).is_err());
+    }
+}
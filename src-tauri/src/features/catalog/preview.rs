@@ -0,0 +1,136 @@
+//! Short preview clips for licensing clients, cut from a track's source audio.
+
+use log::{info, warn};
+use mongodb::bson::{doc, Document};
+use std::path::Path;
+use std::time::Duration;
+use tauri::{command, State};
+use tempfile::Builder as TempFileBuilder;
+
+use super::changes::touched;
+use super::storage::id_to_string;
+use crate::core::encryption::readable_bytes;
+use crate::core::r2::R2Client;
+use crate::features::upload::audio::transcode::cut_preview_clip;
+use crate::features::upload::DEFAULT_ITEM_TIMEOUT_SECS;
+use crate::CommandError;
+use crate::{MongoState, R2State};
+use crate::core::db_config::{CatalogCollections, CatalogDatabase};
+
+/// R2 prefix preview clips are uploaded under.
+const PREVIEW_PREFIX: &str = "tracks/previews";
+
+/// Longest preview clip we will generate, in seconds.
+const MAX_PREVIEW_SECS: f64 = 300.0;
+
+/// R2 key for a preview clip. Start and length are part of the key so different
+/// cuts of the same track don't overwrite each other on the CDN.
+fn preview_key(track_id: &str, start_secs: f64, duration_secs: f64) -> String {
+    format!("{}/{}_{}ms_{}ms.m4a", PREVIEW_PREFIX, track_id, (start_secs * 1000.0).round() as u64, (duration_secs * 1000.0).round() as u64)
+}
+
+fn validate_clip_range(start_secs: f64, duration_secs: f64) -> Result<(), CommandError> {
+    if !start_secs.is_finite() || start_secs < 0.0 {
+        return Err(CommandError::Validation("Preview start time must be zero or positive".to_string()));
+    }
+    if !duration_secs.is_finite() || duration_secs <= 0.0 || duration_secs > MAX_PREVIEW_SECS {
+        return Err(CommandError::Validation(format!("Preview duration must be between 0 and {} seconds", MAX_PREVIEW_SECS)));
+    }
+    Ok(())
+}
+
+// --- Tauri Commands ---
+
+/// Cuts a preview clip from the track stored at `r2_key`, uploads it under
+/// `tracks/previews/`, records it as the track's `preview_key`, and returns the key.
+#[command]
+pub async fn generate_preview_clip(
+    r2_key: String,
+    start_secs: f64,
+    duration_secs: f64,
+    mongo_state: State<'_, MongoState>,
+    r2_state: State<'_, R2State>,
+) -> Result<String, CommandError> {
+    validate_clip_range(start_secs, duration_secs)?;
+
+    // --- Find the track that owns this object ---
+    let tracks_collection = {
+        let client_lock = mongo_state.client.lock().await;
+        let client = client_lock.as_ref()
+            .ok_or_else(|| CommandError::Configuration("MongoDB client not initialized".to_string()))?;
+        client.catalog_database().tracks::<Document>()
+    };
+    let track_doc = tracks_collection
+        .find_one(doc! { "$or": [
+            { "r2_original_key": &r2_key }, { "r2_aac_key": &r2_key },
+            { "r2_archive_key": &r2_key }, { "r2_delivery_key": &r2_key },
+        ] }, None)
+        .await?
+        .ok_or_else(|| CommandError::NotFound(format!("No track references R2 key {}", r2_key)))?;
+    let track_id = track_doc.get("_id").and_then(id_to_string)
+        .ok_or_else(|| CommandError::Database("Track has an invalid _id".to_string()))?;
+    let previous_key = track_doc.get_str("preview_key").ok().map(String::from);
+
+    // --- Download the source to a temp file ---
+    let r2_client = R2Client::from_state(&r2_state).await?.for_track(&track_doc);
+    info!("Generating {}s preview from {} at {}s for track {}", duration_secs, r2_key, start_secs, track_id);
+    let source_bytes = r2_client.download_object(&r2_key).await
+        .map_err(|e| CommandError::Storage(format!("Failed to download source audio: {}", e)))?;
+    let source_bytes = readable_bytes(&track_doc, &r2_key, source_bytes)?;
+
+    // Keep the source extension so ffmpeg can detect the container
+    let source_suffix = Path::new(&r2_key).extension()
+        .map(|ext| format!(".{}", ext.to_string_lossy()))
+        .unwrap_or_default();
+    let source_file = TempFileBuilder::new().prefix("preview_source_").suffix(&source_suffix).tempfile()?;
+    std::fs::write(source_file.path(), source_bytes)?;
+    let clip_file = TempFileBuilder::new().prefix("preview_clip_").suffix(".m4a").tempfile()?;
+
+    // --- Cut and encode the clip ---
+    let source_path = source_file.path().to_path_buf();
+    let clip_path = clip_file.path().to_path_buf();
+    tokio::task::spawn_blocking(move || {
+        cut_preview_clip(&source_path, &clip_path, start_secs, duration_secs, Some(Duration::from_secs(DEFAULT_ITEM_TIMEOUT_SECS)))
+    })
+    .await
+    .map_err(|e| CommandError::Unexpected(format!("Preview task join error: {}", e)))?
+    .map_err(|e| CommandError::Transcoding(e.to_string()))?;
+
+    // --- Upload and record the preview ---
+    let key = preview_key(&track_id, start_secs, duration_secs);
+    let clip_bytes = std::fs::read(clip_file.path())?;
+    r2_client.upload_object(&key, clip_bytes, "audio/mp4").await
+        .map_err(|e| CommandError::Storage(format!("Failed to upload preview clip: {}", e)))?;
+
+    tracks_collection
+        .update_one(doc! { "_id": track_doc.get("_id").cloned() }, touched(doc! { "$set": { "preview_key": &key } }), None)
+        .await?;
+
+    if let Some(previous_key) = previous_key.filter(|previous| *previous != key) {
+        if let Err(e) = r2_client.delete_object(&previous_key).await {
+            warn!("Failed to delete previous preview {}: {}", previous_key, e);
+        }
+    }
+
+    info!("Stored preview clip {} for track {}", key, track_id);
+    Ok(key)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_preview_key() {
+        assert_eq!(preview_key("abc", 30.0, 15.5), "tracks/previews/abc_30000ms_15500ms.m4a");
+    }
+
+    #[test]
+    fn test_validate_clip_range() {
+        assert!(validate_clip_range(0.0, 30.0).is_ok());
+        assert!(validate_clip_range(-1.0, 30.0).is_err());
+        assert!(validate_clip_range(10.0, 0.0).is_err());
+        assert!(validate_clip_range(10.0, MAX_PREVIEW_SECS + 1.0).is_err());
+        assert!(validate_clip_range(f64::NAN, 30.0).is_err());
+    }
+}
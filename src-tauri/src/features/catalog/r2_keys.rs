@@ -0,0 +1,104 @@
+//! Renaming R2 objects in place, to reorganize the bucket without downloading and
+//! re-uploading. Tracks that reference the old key are pointed at the new one.
+
+use log::{error, info};
+use mongodb::bson::{doc, Document};
+use serde::Serialize;
+use tauri::{command, State};
+
+use super::changes::touched;
+use crate::core::r2::{R2Client, TRACK_KEY_FIELDS};
+use crate::CommandError;
+use crate::{MongoState, R2State};
+use crate::core::db_config::{CatalogCollections, CatalogDatabase};
+
+/// Outcome of `move_r2_object`.
+#[derive(Debug, Serialize, Clone, PartialEq)]
+pub struct MoveResult {
+    pub bucket: String,
+    pub from_key: String,
+    pub to_key: String,
+    /// Key fields changed across all tracks
+    pub tracks_updated: u64,
+}
+
+fn validate_key(key: &str) -> Result<(), CommandError> {
+    if key.is_empty() || key.ends_with('/') {
+        return Err(CommandError::Validation(format!("'{}' is not an object key", key)));
+    }
+    Ok(())
+}
+
+/// Matches tracks stored in `bucket`. Tracks without `r2_bucket` live in the configured bucket.
+fn bucket_filter(bucket: &str, default_bucket: &str) -> Document {
+    if bucket == default_bucket {
+        doc! { "r2_bucket": { "$in": [null, "", bucket] } }
+    } else {
+        doc! { "r2_bucket": bucket }
+    }
+}
+
+// --- Tauri Commands ---
+
+/// Moves an object to a new key with a server-side copy, then updates every track key
+/// field that referenced it. `bucket` defaults to the configured bucket.
+#[command]
+pub async fn move_r2_object(
+    from_key: String,
+    to_key: String,
+    bucket: Option<String>,
+    mongo_state: State<'_, MongoState>,
+    r2_state: State<'_, R2State>,
+) -> Result<MoveResult, CommandError> {
+    let (from_key, to_key) = (from_key.trim(), to_key.trim());
+    validate_key(from_key)?;
+    validate_key(to_key)?;
+    if from_key == to_key {
+        return Err(CommandError::Validation("The new key is the same as the old one".to_string()));
+    }
+
+    let tracks_collection = {
+        let client_lock = mongo_state.client.lock().await;
+        let client = client_lock.as_ref()
+            .ok_or_else(|| CommandError::Configuration("MongoDB client not initialized".to_string()))?;
+        client.catalog_database().tracks::<Document>()
+    };
+    let r2_client = R2Client::from_state(&r2_state).await?;
+    let default_bucket = r2_client.bucket_name().to_string();
+    let bucket = bucket.map(|b| b.trim().to_string()).filter(|b| !b.is_empty()).unwrap_or_else(|| default_bucket.clone());
+    let bucket_client = if bucket == default_bucket { r2_client } else { r2_client.with_bucket(&bucket) };
+
+    bucket_client.move_object(from_key, to_key).await
+        .map_err(|e| CommandError::Storage(format!("Failed to move {} to {}: {}", from_key, to_key, e)))?;
+
+    let mut tracks_updated = 0;
+    for field in TRACK_KEY_FIELDS {
+        let mut filter = bucket_filter(&bucket, &default_bucket);
+        filter.insert(field, from_key);
+        match tracks_collection.update_many(filter, touched(doc! { "$set": { field: to_key } }), None).await {
+            Ok(result) => tracks_updated += result.modified_count,
+            Err(e) => {
+                error!("Moved {} to {} but failed to update {}: {}", from_key, to_key, field, e);
+                return Err(CommandError::Database(format!(
+                    "Moved {} to {}, but tracks still reference the old key: {}", from_key, to_key, e
+                )));
+            }
+        }
+    }
+
+    info!("Moved {} to {} in bucket '{}' ({} track fields updated)", from_key, to_key, bucket, tracks_updated);
+    Ok(MoveResult { bucket, from_key: from_key.to_string(), to_key: to_key.to_string(), tracks_updated })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bucket_filter_includes_unset_bucket_for_default() {
+        assert_eq!(bucket_filter("masters", "masters"), doc! { "r2_bucket": { "$in": [null, "", "masters"] } });
+        assert_eq!(bucket_filter("archive", "masters"), doc! { "r2_bucket": "archive" });
+        assert!(validate_key("tracks/").is_err());
+        assert!(validate_key("tracks/a.wav").is_ok());
+    }
+}
@@ -0,0 +1,296 @@
+//! Re-encoding every track's AAC preview from its original, e.g. after the delivery
+//! transcode settings changed. Runs as a `PreviewReencode` job, one at a time.
+//!
+//! Each preview is transcoded with the current settings and uploaded over the existing
+//! `r2_aac_key`, so links and the track document stay valid. Finished and skipped tracks
+//! are recorded in a checkpoint document as the job goes; a job started after an
+//! interruption skips them and carries on. The checkpoint is removed once a run gets
+//! through the whole catalog, so the next job starts over.
+//!
+//! Work runs with the upload queue's concurrency and per-item timeout, and the queue's
+//! bandwidth limit paces both the download of each original and the upload of its preview.
+
+use chrono::Utc;
+use futures_util::stream::{self, StreamExt, TryStreamExt};
+use log::{info, warn};
+use mongodb::bson::{doc, Bson, Document};
+use mongodb::options::{FindOptions, UpdateOptions};
+use mongodb::Collection;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tauri::{command, AppHandle, Manager, Wry};
+use ts_rs::TS;
+
+use super::storage::id_to_string;
+use crate::core::db_config::{CatalogCollections, CatalogDatabase, cursor_batch_size};
+use crate::core::encryption::decryption_key;
+use crate::core::jobs::{spawn_job, JobContext, JobId, JobKind};
+use crate::core::r2::{R2Client, ORIGINAL_KEY_FIELDS};
+use crate::features::upload::audio::transcode::{transcode_with_timeout, TranscodeFormat, TranscodingOptions};
+use crate::features::upload::{upload_file_to_r2, UploadState, MAX_UPLOAD_CONCURRENCY};
+use crate::CommandError;
+use crate::{MongoState, R2State};
+
+/// Collection holding the checkpoints of resumable jobs.
+const CHECKPOINT_COLLECTION: &str = "job_checkpoints";
+const CHECKPOINT_ID: &str = "preview_reencode";
+
+/// Progress is reported every this many tracks, and after the last one.
+const PROGRESS_EVENT_INTERVAL: usize = 10;
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct ReencodeJobParams {
+    /// Ignore the checkpoint of an interrupted run and re-encode every track
+    pub restart: bool,
+}
+
+/// A track that could not be re-encoded.
+#[derive(Debug, Clone, Serialize, PartialEq, TS)]
+#[ts(export)]
+pub struct ReencodeFailure {
+    pub track_id: String,
+    pub error: String,
+}
+
+/// Result of a finished `PreviewReencode` job.
+#[derive(Debug, Clone, Default, Serialize, TS)]
+#[ts(export)]
+pub struct ReencodeReport {
+    /// Tracks with an original and a separate preview to re-encode from it
+    #[ts(type = "number")]
+    pub total_tracks: usize,
+    #[ts(type = "number")]
+    pub reencoded: usize,
+    /// Tracks finished by an earlier, interrupted run
+    #[ts(type = "number")]
+    pub resumed: usize,
+    /// Tracks whose original is missing from R2
+    pub skipped_missing_original: Vec<String>,
+    pub failed: Vec<ReencodeFailure>,
+}
+
+/// How one track went.
+enum TrackOutcome {
+    Reencoded,
+    MissingOriginal,
+    Failed(String),
+}
+
+/// The original to transcode from and the preview to overwrite, unless the track lacks
+/// either or they are the same object.
+fn reencode_keys(track_doc: &Document) -> Option<(String, String)> {
+    let non_empty = |field: &str| track_doc.get_str(field).ok().filter(|key| !key.is_empty());
+    let original = ORIGINAL_KEY_FIELDS.iter().find_map(|field| non_empty(field))?;
+    let preview = non_empty("r2_aac_key").filter(|preview| *preview != original)?;
+    Some((original.to_string(), preview.to_string()))
+}
+
+/// Track ids the checkpoint lists as done, finished or skipped.
+async fn load_checkpoint(checkpoints: &Collection<Document>) -> Result<HashSet<String>, CommandError> {
+    let checkpoint = checkpoints.find_one(doc! { "_id": CHECKPOINT_ID }, None).await?;
+    let ids = |field: &str| -> Vec<String> {
+        checkpoint.as_ref()
+            .and_then(|checkpoint| checkpoint.get_array(field).ok())
+            .map(|ids| ids.iter().filter_map(Bson::as_str).map(String::from).collect())
+            .unwrap_or_default()
+    };
+    Ok(ids("completed").into_iter().chain(ids("skipped")).collect())
+}
+
+/// Adds a track to the checkpoint's `completed` or `skipped` list.
+async fn record_checkpoint(checkpoints: &Collection<Document>, field: &str, track_id: &str) -> Result<(), CommandError> {
+    let options = UpdateOptions::builder().upsert(true).build();
+    checkpoints.update_one(
+        doc! { "_id": CHECKPOINT_ID },
+        doc! { "$addToSet": { field: track_id }, "$set": { "updated_at": Utc::now().to_rfc3339() } },
+        options,
+    ).await?;
+    Ok(())
+}
+
+/// What every track of a re-encode run shares.
+struct ReencodeContext {
+    r2_client: R2Client,
+    upload_state: Arc<UploadState>,
+    timeout: Duration,
+}
+
+/// Downloads the original, transcodes it and uploads the result over the preview.
+async fn reencode_track(ctx: &ReencodeContext, track_doc: &Document, original_key: &str, preview_key: &str) -> TrackOutcome {
+    let bucket_client = ctx.r2_client.for_track(track_doc);
+    match bucket_client.object_size(original_key).await {
+        Ok(Some(_)) => {}
+        Ok(None) => return TrackOutcome::MissingOriginal,
+        Err(e) => return TrackOutcome::Failed(format!("Checking {} failed: {}", original_key, e)),
+    }
+    match transcode_and_upload(ctx, &bucket_client, track_doc, original_key, preview_key).await {
+        Ok(()) => TrackOutcome::Reencoded,
+        Err(e) => TrackOutcome::Failed(e),
+    }
+}
+
+async fn transcode_and_upload(
+    ctx: &ReencodeContext,
+    bucket_client: &R2Client,
+    track_doc: &Document,
+    original_key: &str,
+    preview_key: &str,
+) -> Result<(), String> {
+    let decrypt_with = decryption_key(track_doc, original_key).map_err(|e| e.to_string())?;
+    let work_dir = tempfile::Builder::new().prefix("reencode_").tempdir().map_err(|e| e.to_string())?;
+    let extension = Path::new(original_key).extension().map(|ext| format!(".{}", ext.to_string_lossy())).unwrap_or_default();
+    let input_path = work_dir.path().join(format!("original{}", extension));
+    let output_path = work_dir.path().join(format!("preview.{}", TranscodeFormat::Aac.extension()));
+    bucket_client.download_to_path(original_key, &input_path, decrypt_with.as_ref(), Some(&ctx.upload_state.bandwidth)).await
+        .map_err(|e| format!("Download of {} failed: {}", original_key, e))?
+        .ok_or_else(|| format!("{} disappeared before it could be downloaded", original_key))?;
+
+    let (input, output, timeout) = (input_path.clone(), output_path.clone(), ctx.timeout);
+    tokio::task::spawn_blocking(move || {
+        transcode_with_timeout(&input, &output, TranscodeFormat::Aac, &TranscodingOptions::default(), Some(timeout))
+    })
+        .await
+        .map_err(|e| format!("Transcoding task failed: {}", e))?
+        .map_err(|e| e.to_string())?;
+
+    let sent = Arc::new(AtomicU64::new(0));
+    let upload = upload_file_to_r2(
+        bucket_client.s3_client(), &ctx.upload_state.bandwidth, &sent, &output_path,
+        bucket_client.bucket_name(), preview_key, TranscodeFormat::Aac.mime_type(), false,
+    );
+    tokio::time::timeout(ctx.timeout, upload).await
+        .map_err(|_| format!("Upload of {} timed out after {}s", preview_key, ctx.timeout.as_secs()))?
+        .map_err(|e| e.to_string())
+}
+
+/// Starts a preview re-encode job. Fails straight away if the clients aren't ready or
+/// another re-encode is running.
+pub async fn start_reencode_job(app_handle: &AppHandle<Wry>, params: ReencodeJobParams) -> Result<JobId, CommandError> {
+    let (tracks_collection, checkpoints) = {
+        let mongo_state = app_handle.state::<MongoState>();
+        let client_lock = mongo_state.client.lock().await;
+        let client = client_lock.as_ref()
+            .ok_or_else(|| CommandError::Configuration("MongoDB client not initialized".to_string()))?;
+        let db = client.catalog_database();
+        (db.tracks::<Document>(), db.collection::<Document>(CHECKPOINT_COLLECTION))
+    };
+    let r2_client = R2Client::from_state(&app_handle.state::<R2State>()).await?;
+    let upload_state = Arc::clone(&app_handle.state::<Arc<UploadState>>());
+    let ctx = ReencodeContext {
+        timeout: Duration::from_secs(upload_state.item_timeout_secs.load(Ordering::SeqCst)),
+        r2_client,
+        upload_state,
+    };
+
+    spawn_job(app_handle, JobKind::PreviewReencode, move |job| async move {
+        run_reencode(&job, &ctx, &tracks_collection, &checkpoints, params.restart).await
+    })
+}
+
+async fn run_reencode(
+    job: &JobContext,
+    ctx: &ReencodeContext,
+    tracks_collection: &Collection<Document>,
+    checkpoints: &Collection<Document>,
+    restart: bool,
+) -> Result<ReencodeReport, CommandError> {
+    if restart {
+        checkpoints.delete_one(doc! { "_id": CHECKPOINT_ID }, None).await?;
+    }
+    let done = load_checkpoint(checkpoints).await?;
+
+    let filter = doc! {
+        "r2_aac_key": { "$nin": [null, ""] },
+        "$or": ORIGINAL_KEY_FIELDS.iter().map(|&field| doc! { field: { "$nin": [null, ""] } }).collect::<Vec<_>>(),
+    };
+    let mut projection = doc! { "r2_aac_key": 1, "r2_bucket": 1, "encrypted": 1, "encryption_key_id": 1 };
+    projection.extend(ORIGINAL_KEY_FIELDS.iter().map(|field| (field.to_string(), Bson::Int32(1))));
+    let options = FindOptions::builder().batch_size(cursor_batch_size()).projection(projection).build();
+    let tracks: Vec<Document> = tracks_collection.find(filter, options).await?.try_collect().await?;
+
+    let (finished, pending): (Vec<_>, Vec<_>) = tracks.into_iter()
+        .filter_map(|track_doc| {
+            let track_id = track_doc.get("_id").and_then(id_to_string)?;
+            let (original_key, preview_key) = reencode_keys(&track_doc)?;
+            Some((track_id, track_doc, original_key, preview_key))
+        })
+        .partition(|(track_id, ..)| done.contains(track_id));
+    let mut report = ReencodeReport { total_tracks: finished.len() + pending.len(), resumed: finished.len(), ..Default::default() };
+    info!("Re-encoding {} previews ({} done by an earlier run)", pending.len(), report.resumed);
+    job.progress(report.resumed as u64, report.total_tracks as u64, None);
+
+    let concurrency = ctx.upload_state.concurrency.load(Ordering::SeqCst).clamp(1, MAX_UPLOAD_CONCURRENCY);
+    let mut outcomes = stream::iter(pending)
+        .map(|(track_id, track_doc, original_key, preview_key)| async move {
+            let outcome = reencode_track(ctx, &track_doc, &original_key, &preview_key).await;
+            (track_id, outcome)
+        })
+        .buffer_unordered(concurrency);
+    let mut processed = report.resumed;
+    while let Some((track_id, outcome)) = outcomes.next().await {
+        match outcome {
+            TrackOutcome::Reencoded => {
+                record_checkpoint(checkpoints, "completed", &track_id).await?;
+                report.reencoded += 1;
+            }
+            TrackOutcome::MissingOriginal => {
+                warn!("Original of track {} is missing from R2; preview not re-encoded", track_id);
+                record_checkpoint(checkpoints, "skipped", &track_id).await?;
+                report.skipped_missing_original.push(track_id);
+            }
+            // Failed tracks stay out of the checkpoint so a resumed run retries them
+            TrackOutcome::Failed(error) => {
+                warn!("Re-encoding the preview of track {} failed: {}", track_id, error);
+                report.failed.push(ReencodeFailure { track_id, error });
+            }
+        }
+        processed += 1;
+        if job.is_cancelled() {
+            return Err(CommandError::OperationFailed("Preview re-encode was cancelled; start it again to resume".to_string()));
+        }
+        if processed % PROGRESS_EVENT_INTERVAL == 0 || processed == report.total_tracks {
+            job.progress(processed as u64, report.total_tracks as u64, None);
+        }
+    }
+
+    if report.failed.is_empty() {
+        checkpoints.delete_one(doc! { "_id": CHECKPOINT_ID }, None).await?;
+    }
+    info!(
+        "Preview re-encode finished: {} re-encoded, {} resumed, {} missing originals, {} failed",
+        report.reencoded, report.resumed, report.skipped_missing_original.len(), report.failed.len()
+    );
+    Ok(report)
+}
+
+// --- Tauri Commands ---
+
+/// Starts a job re-encoding every track's AAC preview from its original with the current
+/// transcode settings, and returns its id. An interrupted run is resumed unless `restart`
+/// is set. The finished job's result is a `ReencodeReport`.
+#[command]
+pub async fn reencode_catalog_previews(restart: Option<bool>, app_handle: AppHandle<Wry>) -> Result<JobId, CommandError> {
+    start_reencode_job(&app_handle, ReencodeJobParams { restart: restart.unwrap_or(false) }).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reencode_keys() {
+        let track = doc! { "r2_archive_key": "tracks/a.wav", "r2_original_key": "old/a.wav", "r2_aac_key": "tracks/a.m4a" };
+        assert_eq!(reencode_keys(&track), Some(("tracks/a.wav".to_string(), "tracks/a.m4a".to_string())));
+        let legacy = doc! { "r2_original_key": "old/a.wav", "r2_aac_key": "old/a.m4a" };
+        assert_eq!(reencode_keys(&legacy), Some(("old/a.wav".to_string(), "old/a.m4a".to_string())));
+
+        assert_eq!(reencode_keys(&doc! { "r2_original_key": "a.wav" }), None);
+        assert_eq!(reencode_keys(&doc! { "r2_original_key": "", "r2_aac_key": "a.m4a" }), None);
+        assert_eq!(reencode_keys(&doc! { "r2_archive_key": "a.m4a", "r2_aac_key": "a.m4a" }), None);
+    }
+}
@@ -0,0 +1,240 @@
+//! Re-running metadata extraction on uploaded tracks, so extractor improvements can be
+//! backfilled without uploading the audio again.
+//!
+//! Only track fields are updated. Album name, album artist, and year live on the album
+//! document and are left alone.
+
+use log::{info, warn};
+use mongodb::bson::{doc, Bson, Document};
+use mongodb::{Collection, Database};
+use serde::Serialize;
+use std::path::Path;
+use tauri::{command, State};
+use tempfile::Builder as TempFileBuilder;
+
+use super::changes::touched;
+use super::genres::normalize_genres;
+use super::storage::id_filter;
+use crate::core::encryption::readable_bytes;
+use crate::core::r2::{R2Client, TRACK_KEY_FIELDS};
+use crate::features::upload::audio::metadata::extract_metadata;
+use crate::features::upload::UploadItemMetadata;
+use crate::CommandError;
+use crate::{MongoState, R2State};
+use crate::core::db_config::{CatalogCollections, CatalogDatabase};
+
+/// Artist stored by the upload pipeline when the file had none.
+const UNKNOWN_ARTIST: &str = "Unknown Artist";
+
+/// A field updated by `reextract_metadata`.
+#[derive(Debug, Serialize, Clone, PartialEq)]
+pub struct FieldChange {
+    pub field: String,
+    pub old: serde_json::Value,
+    pub new: serde_json::Value,
+}
+
+/// What `reextract_metadata` did to one track.
+#[derive(Debug, Serialize, Clone)]
+pub struct ReextractSummary {
+    pub track_id: String,
+    /// Empty if the extracted metadata added nothing
+    pub changes: Vec<FieldChange>,
+    pub error: Option<String>,
+}
+
+/// Track fields filled from the extracted metadata. Empty extracted values are dropped so
+/// re-extraction never clears a field.
+fn extracted_fields(metadata: &UploadItemMetadata, genres: Vec<String>) -> Vec<(&'static str, Bson)> {
+    let text = |value: &Option<String>| value.as_deref().map(str::trim).filter(|v| !v.is_empty()).map(Bson::from);
+    // `composers` holds every credited composer; `composer` only the first
+    let composers: Vec<Bson> = match &metadata.composers {
+        Some(composers) => composers.iter().collect::<Vec<_>>(),
+        None => metadata.composer.iter().collect(),
+    }.into_iter().map(|c| c.trim()).filter(|c| !c.is_empty()).map(Bson::from).collect();
+    let mut fields = vec![
+        ("title", text(&metadata.title)),
+        ("artists", text(&metadata.artist).map(|artist| Bson::Array(vec![artist]))),
+        ("duration", metadata.duration_sec.filter(|d| *d > 0.0).map(Bson::from)),
+        ("track_number", metadata.track_number.filter(|n| *n > 0).map(|n| Bson::from(n as i64))),
+        ("genre", Some(genres).filter(|g| !g.is_empty()).map(Bson::from)),
+        ("composers", Some(composers).filter(|c| !c.is_empty()).map(Bson::Array)),
+        ("catalog_number", text(&metadata.catalog_number)),
+        ("comments", text(&metadata.comments)),
+    ];
+    fields.retain(|(_, value)| value.is_some());
+    fields.into_iter().map(|(field, value)| (field, value.unwrap_or(Bson::Null))).collect()
+}
+
+/// Whether a stored value is empty or a placeholder the upload pipeline filled in (the
+/// file name as title, "Unknown Artist"), as opposed to something a person entered.
+fn is_default_value(track_doc: &Document, field: &str, value: Option<&Bson>) -> bool {
+    match value {
+        None | Some(Bson::Null) => true,
+        Some(Bson::String(s)) if s.trim().is_empty() => true,
+        Some(Bson::String(title)) if field == "title" => track_doc.get_str("filename").ok()
+            .and_then(|filename| Path::new(filename).file_stem())
+            .is_some_and(|stem| stem.to_string_lossy() == title.as_str()),
+        Some(Bson::Array(values)) => values.is_empty() || (field == "artists" && values == &[Bson::from(UNKNOWN_ARTIST)]),
+        Some(Bson::Int32(n)) => *n == 0,
+        Some(Bson::Int64(n)) => *n == 0,
+        Some(Bson::Double(n)) => *n == 0.0,
+        Some(_) => false,
+    }
+}
+
+/// The `$set` document and change list for one track. Without `overwrite`, only fields
+/// holding empty or default values are replaced.
+fn plan_updates(track_doc: &Document, extracted: Vec<(&'static str, Bson)>, overwrite: bool) -> (Document, Vec<FieldChange>) {
+    let mut set = Document::new();
+    let mut changes = Vec::new();
+    for (field, new_value) in extracted {
+        let old_value = track_doc.get(field);
+        if old_value == Some(&new_value) || !(overwrite || is_default_value(track_doc, field, old_value)) {
+            continue;
+        }
+        changes.push(FieldChange {
+            field: field.to_string(),
+            old: old_value.cloned().unwrap_or(Bson::Null).into_relaxed_extjson(),
+            new: new_value.clone().into_relaxed_extjson(),
+        });
+        set.insert(field, new_value);
+    }
+    (set, changes)
+}
+
+/// Downloads the track's archive copy (tags are most complete there), falling back to
+/// the delivery copy, and extracts its metadata.
+async fn extract_from_r2(r2_client: &R2Client, track_doc: &Document) -> Result<UploadItemMetadata, String> {
+    let key = TRACK_KEY_FIELDS
+        .iter()
+        .find_map(|field| track_doc.get_str(field).ok().filter(|key| !key.is_empty()))
+        .ok_or_else(|| "Track has no R2 audio key".to_string())?;
+    let bucket_client = r2_client.for_track(track_doc);
+
+    let bytes = bucket_client.download_object(key).await.map_err(|e| format!("Download of {} failed: {}", key, e))?;
+    let bytes = readable_bytes(track_doc, key, bytes).map_err(|e| e.to_string())?;
+    let suffix = Path::new(key).extension().map(|ext| format!(".{}", ext.to_string_lossy())).unwrap_or_default();
+    let temp_file = TempFileBuilder::new().prefix("reextract_").suffix(&suffix).tempfile().map_err(|e| e.to_string())?;
+    std::fs::write(temp_file.path(), bytes).map_err(|e| e.to_string())?;
+
+    let temp_path = temp_file.path().to_string_lossy().into_owned();
+    let mut metadata = tokio::task::spawn_blocking(move || extract_metadata(temp_path))
+        .await
+        .map_err(|e| format!("Metadata task join error: {}", e))??;
+    // The extractor falls back to the file name for the title; the temp file's name is meaningless
+    if metadata.title.as_deref().is_some_and(|title| temp_file.path().file_stem().is_some_and(|stem| stem.to_string_lossy() == title)) {
+        metadata.title = None;
+    }
+    Ok(metadata)
+}
+
+async fn reextract_one(
+    db: &Database,
+    tracks_collection: &Collection<Document>,
+    r2_client: &R2Client,
+    track_id: &str,
+    overwrite: bool,
+) -> Result<Vec<FieldChange>, String> {
+    let filter = id_filter(track_id);
+    let track_doc = tracks_collection
+        .find_one(filter.clone(), None)
+        .await
+        .map_err(|e| format!("Track lookup failed: {}", e))?
+        .ok_or_else(|| "Track not found".to_string())?;
+
+    let metadata = extract_from_r2(r2_client, &track_doc).await?;
+    let genres = match &metadata.genre {
+        Some(genre) => normalize_genres(db, std::slice::from_ref(genre)).await.map_err(|e| format!("Genre normalization failed: {}", e))?,
+        None => Vec::new(),
+    };
+
+    let (set, changes) = plan_updates(&track_doc, extracted_fields(&metadata, genres), overwrite);
+    if !set.is_empty() {
+        tracks_collection
+            .update_one(filter, touched(doc! { "$set": set }), None)
+            .await
+            .map_err(|e| format!("Failed to update track: {}", e))?;
+    }
+    Ok(changes)
+}
+
+// --- Tauri Commands ---
+
+/// Downloads each track's audio from R2, re-runs metadata extraction, and fills in the
+/// fields that are still empty or default. With `overwrite`, every extracted field
+/// replaces the stored value. Failures are reported per track.
+#[command]
+pub async fn reextract_metadata(
+    track_ids: Vec<String>,
+    overwrite: Option<bool>,
+    mongo_state: State<'_, MongoState>,
+    r2_state: State<'_, R2State>,
+) -> Result<Vec<ReextractSummary>, CommandError> {
+    if track_ids.is_empty() {
+        return Err(CommandError::Validation("No tracks selected".to_string()));
+    }
+    let overwrite = overwrite.unwrap_or(false);
+    let db = {
+        let client_lock = mongo_state.client.lock().await;
+        let client = client_lock.as_ref()
+            .ok_or_else(|| CommandError::Configuration("MongoDB client not initialized".to_string()))?;
+        client.catalog_database()
+    };
+    let tracks_collection = db.tracks::<Document>();
+    let r2_client = R2Client::from_state(&r2_state).await?;
+
+    let mut summaries = Vec::with_capacity(track_ids.len());
+    for track_id in track_ids {
+        let summary = match reextract_one(&db, &tracks_collection, &r2_client, &track_id, overwrite).await {
+            Ok(changes) => ReextractSummary { track_id, changes, error: None },
+            Err(e) => {
+                warn!("Metadata re-extraction failed for track {}: {}", track_id, e);
+                ReextractSummary { track_id, changes: Vec::new(), error: Some(e) }
+            }
+        };
+        summaries.push(summary);
+    }
+    let changed = summaries.iter().filter(|s| !s.changes.is_empty()).count();
+    info!("Re-extracted metadata for {} tracks, {} changed", summaries.len(), changed);
+    Ok(summaries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn extracted() -> Vec<(&'static str, Bson)> {
+        let metadata = UploadItemMetadata {
+            title: Some("Real Title".into()),
+            artist: Some("Real Artist".into()),
+            track_number: Some(3),
+            composer: Some("  ".into()),
+            ..Default::default()
+        };
+        extracted_fields(&metadata, vec!["Jazz".to_string()])
+    }
+
+    #[test]
+    fn test_only_default_fields_are_filled() {
+        let track_doc = doc! {
+            "filename": "01 real title.mp3",
+            "title": "01 real title", // Derived from the file name
+            "artists": ["Unknown Artist"],
+            "track_number": 7, // Entered by hand
+            "genre": [],
+            "composers": ["Someone"],
+        };
+        let (set, changes) = plan_updates(&track_doc, extracted(), false);
+        assert_eq!(set, doc! { "title": "Real Title", "artists": ["Real Artist"], "genre": ["Jazz"] });
+        assert_eq!(changes[0], FieldChange { field: "title".into(), old: "01 real title".into(), new: "Real Title".into() });
+    }
+
+    #[test]
+    fn test_overwrite_replaces_set_fields_but_never_clears() {
+        let track_doc = doc! { "title": "Edited", "artists": ["Edited"], "track_number": 7_i64, "genre": ["Jazz"], "composers": ["Someone"] };
+        let (set, _) = plan_updates(&track_doc, extracted(), true);
+        // Unchanged genre is skipped, the blank composer is not written
+        assert_eq!(set, doc! { "title": "Real Title", "artists": ["Real Artist"], "track_number": 3_i64 });
+    }
+}
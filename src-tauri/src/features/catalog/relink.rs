@@ -0,0 +1,306 @@
+//! Relinking of tracks whose local source files (`original_path`) have moved.
+
+use futures_util::stream::TryStreamExt;
+use log::{error, info, warn};
+use mongodb::bson::{doc, Document};
+use mongodb::options::FindOptions;
+use mongodb::Collection;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use tauri::{command, State};
+
+use super::changes::touched;
+use super::storage::{id_filter, id_to_string};
+use crate::CommandError;
+use crate::MongoState;
+use crate::core::db_config::{CatalogCollections, CatalogDatabase, cursor_batch_size};
+
+/// A single track → new file location mapping sent by the frontend.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct RelinkRequest {
+    pub track_id: String,
+    pub new_path: String,
+}
+
+/// Outcome of relinking one track.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct RelinkResult {
+    pub track_id: String,
+    pub new_path: String,
+    pub success: bool,
+    pub error: Option<String>,
+}
+
+/// A track whose `original_path` no longer exists on disk.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct MissingSource {
+    pub track_id: String,
+    pub title: Option<String>,
+    pub original_path: String,
+}
+
+/// Missing sources sharing the same parent directory, so the UI can offer a
+/// single "folder moved here" relink for all of them.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct MissingSourceGroup {
+    pub directory: String,
+    pub tracks: Vec<MissingSource>,
+}
+
+// --- Helpers ---
+
+/// Checks that `new_path` is an existing file and, unless `force` is set, that its
+/// size matches the size recorded when the track was uploaded.
+fn verify_relink_target(new_path: &Path, expected_size: Option<i64>, force: bool) -> Result<(), String> {
+    let metadata = fs::metadata(new_path)
+        .map_err(|e| format!("File not found at {}: {}", new_path.display(), e))?;
+    if !metadata.is_file() {
+        return Err(format!("{} is not a file", new_path.display()));
+    }
+    if force {
+        return Ok(());
+    }
+    match expected_size {
+        Some(expected) if expected > 0 && metadata.len() != expected as u64 => Err(format!(
+            "File size mismatch for {}: expected {} bytes, found {} (use force to relink anyway)",
+            new_path.display(), expected, metadata.len()
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// Replaces `old_prefix` with `new_prefix` on whole path components.
+/// Returns `None` if `path` does not live under `old_prefix`.
+fn rewrite_path_prefix(path: &str, old_prefix: &str, new_prefix: &str) -> Option<String> {
+    Path::new(path)
+        .strip_prefix(old_prefix)
+        .ok()
+        .map(|rest| Path::new(new_prefix).join(rest).to_string_lossy().into_owned())
+}
+
+/// Groups missing sources by parent directory, ordered by directory.
+fn group_by_parent_dir(missing: Vec<MissingSource>) -> Vec<MissingSourceGroup> {
+    let mut groups: BTreeMap<String, Vec<MissingSource>> = BTreeMap::new();
+    for source in missing {
+        let directory = Path::new(&source.original_path)
+            .parent()
+            .map(|p| p.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        groups.entry(directory).or_default().push(source);
+    }
+    groups
+        .into_iter()
+        .map(|(directory, tracks)| MissingSourceGroup { directory, tracks })
+        .collect()
+}
+
+/// Verifies and applies a single relink, returning a per-track result.
+async fn relink_one(tracks_collection: &Collection<Document>, request: RelinkRequest, force: bool) -> RelinkResult {
+    let failed = |request: &RelinkRequest, message: String| RelinkResult {
+        track_id: request.track_id.clone(),
+        new_path: request.new_path.clone(),
+        success: false,
+        error: Some(message),
+    };
+
+    let filter = id_filter(&request.track_id);
+    let track_doc = match tracks_collection.find_one(filter.clone(), None).await {
+        Ok(Some(doc)) => doc,
+        Ok(None) => return failed(&request, format!("Track not found: {}", request.track_id)),
+        Err(e) => return failed(&request, format!("Failed to fetch track: {}", e)),
+    };
+
+    let expected_size = track_doc.get_i64("file_size").ok();
+    if let Err(message) = verify_relink_target(Path::new(&request.new_path), expected_size, force) {
+        warn!("Refusing to relink track {}: {}", request.track_id, message);
+        return failed(&request, message);
+    }
+
+    match tracks_collection
+        .update_one(filter, touched(doc! { "$set": { "original_path": &request.new_path } }), None)
+        .await
+    {
+        Ok(_) => {
+            info!("Relinked track {} to {}", request.track_id, request.new_path);
+            RelinkResult { track_id: request.track_id, new_path: request.new_path, success: true, error: None }
+        }
+        Err(e) => {
+            error!("Failed to update original_path for track {}: {}", request.track_id, e);
+            failed(&request, format!("Failed to update track: {}", e))
+        }
+    }
+}
+
+/// Returns the tracks collection from the client held in `MongoState`.
+fn tracks_collection(client: &Option<mongodb::Client>) -> Result<Collection<Document>, CommandError> {
+    let client = client.as_ref().ok_or_else(|| {
+        CommandError::Configuration("MongoDB client not initialized".to_string())
+    })?;
+    Ok(client.catalog_database().tracks::<Document>())
+}
+
+// --- Tauri Commands ---
+
+/// Points tracks at new local source files after the user moved them on disk.
+#[command]
+pub async fn relink_tracks(
+    mapping: Vec<RelinkRequest>,
+    force: Option<bool>,
+    mongo_state: State<'_, MongoState>,
+) -> Result<Vec<RelinkResult>, CommandError> {
+    info!("Relinking {} tracks", mapping.len());
+    let client_lock = mongo_state.client.lock().await;
+    let tracks_collection = tracks_collection(&client_lock)?;
+
+    let force = force.unwrap_or(false);
+    let mut results = Vec::with_capacity(mapping.len());
+    for request in mapping {
+        results.push(relink_one(&tracks_collection, request, force).await);
+    }
+    Ok(results)
+}
+
+/// Relinks every track whose `original_path` lives under `old_prefix` to the same
+/// relative location under `new_prefix` ("folder moved here").
+#[command]
+pub async fn relink_tracks_by_prefix(
+    old_prefix: String,
+    new_prefix: String,
+    force: Option<bool>,
+    mongo_state: State<'_, MongoState>,
+) -> Result<Vec<RelinkResult>, CommandError> {
+    if old_prefix.is_empty() {
+        return Err(CommandError::Validation("Old folder path must not be empty".to_string()));
+    }
+    info!("Relinking tracks under {} to {}", old_prefix, new_prefix);
+    let client_lock = mongo_state.client.lock().await;
+    let tracks_collection = tracks_collection(&client_lock)?;
+
+    let options = FindOptions::builder().batch_size(cursor_batch_size()).projection(doc! { "_id": 1, "original_path": 1 }).build();
+    let docs: Vec<Document> = tracks_collection
+        .find(doc! { "original_path": { "$type": "string" } }, options)
+        .await?
+        .try_collect()
+        .await?;
+
+    let force = force.unwrap_or(false);
+    let mut results = Vec::new();
+    for track_doc in docs {
+        if let (Some(track_id), Ok(original_path)) = (track_doc.get("_id").and_then(id_to_string), track_doc.get_str("original_path")) {
+            if let Some(new_path) = rewrite_path_prefix(original_path, &old_prefix, &new_prefix) {
+                results.push(relink_one(&tracks_collection, RelinkRequest { track_id, new_path }, force).await);
+            }
+        }
+    }
+    info!("Relink by prefix processed {} tracks", results.len());
+    Ok(results)
+}
+
+/// Scans all tracks and returns those whose `original_path` no longer exists,
+/// grouped by parent directory.
+#[command]
+pub async fn find_missing_sources(mongo_state: State<'_, MongoState>) -> Result<Vec<MissingSourceGroup>, CommandError> {
+    info!("Scanning tracks for missing source files");
+    let client_lock = mongo_state.client.lock().await;
+    let tracks_collection = tracks_collection(&client_lock)?;
+
+    let options = FindOptions::builder().batch_size(cursor_batch_size()).projection(doc! { "_id": 1, "title": 1, "original_path": 1 }).build();
+    let mut cursor = tracks_collection.find(doc! { "original_path": { "$type": "string" } }, options).await?;
+
+    let mut missing = Vec::new();
+    while let Some(track_doc) = cursor.try_next().await? {
+        if let (Some(track_id), Ok(original_path)) = (track_doc.get("_id").and_then(id_to_string), track_doc.get_str("original_path")) {
+            if !Path::new(original_path).exists() {
+                missing.push(MissingSource {
+                    track_id,
+                    title: track_doc.get_str("title").ok().map(String::from),
+                    original_path: original_path.to_string(),
+                });
+            }
+        }
+    }
+    info!("Found {} tracks with missing source files", missing.len());
+    Ok(group_by_parent_dir(missing))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::File;
+    use std::io::Write;
+    use tempfile::tempdir;
+
+    fn write_file(path: &Path, len: usize) {
+        let mut file = File::create(path).unwrap();
+        file.write_all(&vec![0u8; len]).unwrap();
+    }
+
+    #[test]
+    fn test_verify_relink_target_missing_file() {
+        let temp_dir = tempdir().unwrap();
+        let result = verify_relink_target(&temp_dir.path().join("gone.wav"), None, false);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_verify_relink_target_size_mismatch_and_force() {
+        let temp_dir = tempdir().unwrap();
+        let path = temp_dir.path().join("track.wav");
+        write_file(&path, 16);
+
+        assert!(verify_relink_target(&path, Some(16), false).is_ok());
+        assert!(verify_relink_target(&path, Some(32), false).is_err());
+        assert!(verify_relink_target(&path, Some(32), true).is_ok());
+        // No recorded size means there is nothing to compare against
+        assert!(verify_relink_target(&path, None, false).is_ok());
+    }
+
+    #[test]
+    fn test_verify_relink_target_rejects_directory() {
+        let temp_dir = tempdir().unwrap();
+        assert!(verify_relink_target(temp_dir.path(), None, true).is_err());
+    }
+
+    #[test]
+    fn test_rewrite_path_prefix() {
+        let old_dir = tempdir().unwrap();
+        let new_dir = tempdir().unwrap();
+        let old_path = old_dir.path().join("album").join("01.wav");
+
+        let rewritten = rewrite_path_prefix(
+            &old_path.to_string_lossy(),
+            &old_dir.path().to_string_lossy(),
+            &new_dir.path().to_string_lossy(),
+        );
+        assert_eq!(rewritten, Some(new_dir.path().join("album").join("01.wav").to_string_lossy().into_owned()));
+
+        // Prefixes only match whole path components
+        assert_eq!(rewrite_path_prefix("/music/albums2/a.wav", "/music/albums", "/new"), None);
+    }
+
+    #[test]
+    fn test_group_by_parent_dir() {
+        let temp_dir = tempdir().unwrap();
+        let dir_a = temp_dir.path().join("a");
+        let dir_b = temp_dir.path().join("b");
+        let source = |id: &str, path: std::path::PathBuf| MissingSource {
+            track_id: id.to_string(),
+            title: None,
+            original_path: path.to_string_lossy().into_owned(),
+        };
+
+        let groups = group_by_parent_dir(vec![
+            source("1", dir_b.join("x.wav")),
+            source("2", dir_a.join("y.wav")),
+            source("3", dir_b.join("z.wav")),
+        ]);
+
+        assert_eq!(groups.len(), 2);
+        assert_eq!(groups[0].directory, dir_a.to_string_lossy());
+        assert_eq!(groups[0].tracks.len(), 1);
+        assert_eq!(groups[1].directory, dir_b.to_string_lossy());
+        assert_eq!(groups[1].tracks.len(), 2);
+    }
+}
@@ -0,0 +1,214 @@
+//! Backfill of `loudness_lufs` and `replaygain_db` for tracks uploaded before loudness was
+//! measured, or for chosen tracks again. Runs as a `ReplayGain` job, one at a time.
+//!
+//! Each track's original is downloaded and measured with `ebur128` (see
+//! `upload::audio::loudness`), like uploads measure the source file, so the stored values
+//! don't depend on which path wrote them. Tracks whose original was never uploaded fall
+//! back to the delivery rendition. The values are set on the document.
+
+use futures_util::stream::{self, StreamExt, TryStreamExt};
+use log::{info, warn};
+use mongodb::bson::{doc, Bson, Document};
+use mongodb::options::FindOptions;
+use mongodb::Collection;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::Duration;
+use tauri::{command, AppHandle, Manager, Wry};
+use ts_rs::TS;
+
+use super::changes::touched;
+use super::storage::id_to_string;
+use super::trash::ids_filter;
+use crate::core::db_config::{CatalogCollections, CatalogDatabase, cursor_batch_size};
+use crate::core::encryption::readable_bytes;
+use crate::core::jobs::{spawn_job, JobContext, JobId, JobKind};
+use crate::core::r2::{R2Client, ORIGINAL_KEY_FIELDS, TRACK_KEY_FIELDS};
+use crate::features::upload::audio::loudness::{measure_loudness, Loudness};
+use crate::features::upload::originals::is_local_original;
+use crate::features::upload::{UploadState, MAX_UPLOAD_CONCURRENCY};
+use crate::CommandError;
+use crate::{MongoState, R2State};
+
+
+/// Progress is reported every this many tracks, and after the last one.
+const PROGRESS_EVENT_INTERVAL: usize = 10;
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct ReplayGainJobParams {
+    /// Tracks to measure; every track without a measurement when empty
+    pub track_ids: Vec<String>,
+    /// Measure tracks that already have values again
+    pub overwrite: bool,
+}
+
+/// A track that could not be measured.
+#[derive(Debug, Clone, Serialize, PartialEq, TS)]
+#[ts(export)]
+pub struct ReplayGainFailure {
+    pub track_id: String,
+    pub error: String,
+}
+
+/// Result of a finished `ReplayGain` job.
+#[derive(Debug, Clone, Default, Serialize, TS)]
+#[ts(export)]
+pub struct ReplayGainReport {
+    #[ts(type = "number")]
+    pub total_tracks: usize,
+    #[ts(type = "number")]
+    pub measured: usize,
+    /// Tracks that are silent or had no audio ffmpeg could measure; left unchanged
+    pub unmeasurable: Vec<String>,
+    pub failed: Vec<ReplayGainFailure>,
+}
+
+/// The R2 key to measure: the original if it was uploaded, else the delivery rendition.
+fn audio_key(track_doc: &Document) -> Option<&str> {
+    let local_original = is_local_original(track_doc);
+    TRACK_KEY_FIELDS.iter()
+        .filter(|field| !(local_original && ORIGINAL_KEY_FIELDS.contains(*field)))
+        .find_map(|field| track_doc.get_str(field).ok().filter(|key| !key.is_empty()))
+}
+
+/// Which tracks a job measures.
+fn track_filter(params: &ReplayGainJobParams) -> Document {
+    let mut filter = if params.track_ids.is_empty() { Document::new() } else { ids_filter(&params.track_ids) };
+    if !params.overwrite {
+        // Matches a missing field as well as null
+        filter.insert("replaygain_db", Bson::Null);
+    }
+    filter
+}
+
+/// Downloads the track's audio and measures it. `Ok(None)` if there was nothing to measure.
+async fn measure_track(r2_client: &R2Client, track_doc: &Document, timeout: Duration) -> Result<Option<Loudness>, String> {
+    let key = audio_key(track_doc).ok_or_else(|| "Track has no R2 audio key".to_string())?;
+    let bucket_client = r2_client.for_track(track_doc);
+    let bytes = bucket_client.download_object(key).await.map_err(|e| format!("Download of {} failed: {}", key, e))?;
+    let bytes = readable_bytes(track_doc, key, bytes).map_err(|e| e.to_string())?;
+    let suffix = Path::new(key).extension().map(|ext| format!(".{}", ext.to_string_lossy())).unwrap_or_default();
+    let temp_file = tempfile::Builder::new().prefix("loudness_").suffix(&suffix).tempfile().map_err(|e| e.to_string())?;
+    tokio::fs::write(temp_file.path(), bytes).await.map_err(|e| e.to_string())?;
+
+    let temp_path = temp_file.path().to_path_buf();
+    tokio::task::spawn_blocking(move || measure_loudness(&temp_path, Some(timeout)))
+        .await
+        .map_err(|e| format!("Loudness task failed: {}", e))?
+        .map_err(|e| e.to_string())
+}
+
+/// Starts a ReplayGain job. Fails straight away if the clients aren't ready or another
+/// ReplayGain job is running.
+pub async fn start_replaygain_job(app_handle: &AppHandle<Wry>, params: ReplayGainJobParams) -> Result<JobId, CommandError> {
+    let tracks_collection = {
+        let mongo_state = app_handle.state::<MongoState>();
+        let client_lock = mongo_state.client.lock().await;
+        let client = client_lock.as_ref()
+            .ok_or_else(|| CommandError::Configuration("MongoDB client not initialized".to_string()))?;
+        client.catalog_database().tracks::<Document>()
+    };
+    let r2_client = R2Client::from_state(&app_handle.state::<R2State>()).await?;
+    let upload_state = Arc::clone(&app_handle.state::<Arc<UploadState>>());
+    let timeout = Duration::from_secs(upload_state.item_timeout_secs.load(Ordering::SeqCst));
+    let concurrency = upload_state.concurrency.load(Ordering::SeqCst).clamp(1, MAX_UPLOAD_CONCURRENCY);
+
+    spawn_job(app_handle, JobKind::ReplayGain, move |job| async move {
+        run_replaygain(&job, &tracks_collection, &r2_client, &params, timeout, concurrency).await
+    })
+}
+
+async fn run_replaygain(
+    job: &JobContext,
+    tracks_collection: &Collection<Document>,
+    r2_client: &R2Client,
+    params: &ReplayGainJobParams,
+    timeout: Duration,
+    concurrency: usize,
+) -> Result<ReplayGainReport, CommandError> {
+    let mut projection = doc! { "r2_bucket": 1, "original_location": 1, "encrypted": 1, "encryption_key_id": 1 };
+    projection.extend(TRACK_KEY_FIELDS.iter().map(|field| (field.to_string(), Bson::Int32(1))));
+    let options = FindOptions::builder().batch_size(cursor_batch_size()).projection(projection).build();
+    let tracks: Vec<Document> = tracks_collection.find(track_filter(params), options).await?.try_collect().await?;
+
+    let mut report = ReplayGainReport { total_tracks: tracks.len(), ..Default::default() };
+    info!("Measuring loudness of {} tracks ({} at a time)", report.total_tracks, concurrency);
+    job.progress(0, report.total_tracks as u64, None);
+
+    let mut outcomes = stream::iter(tracks)
+        .map(|track_doc| async move {
+            let outcome = measure_track(r2_client, &track_doc, timeout).await;
+            (track_doc, outcome)
+        })
+        .buffer_unordered(concurrency);
+    let mut processed = 0;
+    while let Some((track_doc, outcome)) = outcomes.next().await {
+        let track_id = track_doc.get("_id").and_then(id_to_string).unwrap_or_default();
+        match outcome {
+            Ok(Some(loudness)) => {
+                let update = touched(doc! { "$set": { "loudness_lufs": loudness.loudness_lufs, "replaygain_db": loudness.replaygain_db } });
+                match tracks_collection.update_one(doc! { "_id": track_doc.get("_id").cloned() }, update, None).await {
+                    Ok(_) => report.measured += 1,
+                    Err(e) => report.failed.push(ReplayGainFailure { track_id, error: format!("Failed to store loudness: {}", e) }),
+                }
+            }
+            Ok(None) => report.unmeasurable.push(track_id),
+            Err(error) => {
+                warn!("Measuring the loudness of track {} failed: {}", track_id, error);
+                report.failed.push(ReplayGainFailure { track_id, error });
+            }
+        }
+        processed += 1;
+        if job.is_cancelled() {
+            return Err(CommandError::OperationFailed("ReplayGain backfill was cancelled".to_string()));
+        }
+        if processed % PROGRESS_EVENT_INTERVAL == 0 || processed == report.total_tracks {
+            job.progress(processed as u64, report.total_tracks as u64, None);
+        }
+    }
+
+    info!(
+        "ReplayGain backfill finished: {} measured, {} unmeasurable, {} failed",
+        report.measured, report.unmeasurable.len(), report.failed.len()
+    );
+    Ok(report)
+}
+
+// --- Tauri Commands ---
+
+/// Starts a job measuring the integrated loudness of `track_ids`, or of every track
+/// without a measurement, and storing it with the ReplayGain gain. Tracks that already
+/// have values are only measured again with `overwrite`. Returns the job id; the finished
+/// job's result is a `ReplayGainReport`.
+#[command]
+pub async fn compute_replaygain(
+    track_ids: Option<Vec<String>>,
+    overwrite: Option<bool>,
+    app_handle: AppHandle<Wry>,
+) -> Result<JobId, CommandError> {
+    let params = ReplayGainJobParams { track_ids: track_ids.unwrap_or_default(), overwrite: overwrite.unwrap_or(false) };
+    start_replaygain_job(&app_handle, params).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_audio_key_and_filter() {
+        let track = doc! { "r2_delivery_key": "delivery/a.m4a", "r2_archive_key": "tracks/a.flac" };
+        assert_eq!(audio_key(&track), Some("tracks/a.flac"));
+        let legacy = doc! { "r2_archive_key": "", "r2_original_key": "", "r2_aac_key": "old/a.m4a" };
+        assert_eq!(audio_key(&legacy), Some("old/a.m4a"));
+        let local = doc! { "original_location": "local", "r2_archive_key": "tracks/a.wav", "r2_delivery_key": "delivery/a.m4a" };
+        assert_eq!(audio_key(&local), Some("delivery/a.m4a"));
+        assert_eq!(audio_key(&doc! { "original_location": "local", "r2_archive_key": "tracks/a.wav" }), None);
+
+        assert_eq!(track_filter(&ReplayGainJobParams::default()), doc! { "replaygain_db": null });
+        let chosen = ReplayGainJobParams { track_ids: vec!["a1".to_string()], overwrite: true };
+        assert_eq!(track_filter(&chosen), doc! { "_id": { "$in": ["a1"] } });
+    }
+}
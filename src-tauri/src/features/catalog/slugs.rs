@@ -0,0 +1,229 @@
+//! URL-safe `slug`s for tracks and albums, used by the public catalog site instead of
+//! ObjectIds. A slug is built from the title (or album name) and artist and gets a
+//! numeric suffix (`-2`, `-3`, ...) when it is taken.
+//!
+//! Renaming can regenerate a slug; the old one moves to `previous_slugs` and stays
+//! reserved, so links to it keep resolving to the same document.
+
+use futures_util::stream::TryStreamExt;
+use mongodb::bson::{doc, Bson, Document};
+use mongodb::options::{FindOneOptions, FindOptions};
+use mongodb::{Collection, Database};
+use std::collections::HashSet;
+use tauri::{command, State};
+
+use super::changes::touched;
+use super::album_names::AlbumNameCache;
+use super::storage::is_duplicate_key_error;
+use super::storage::mongodb::{album_summary_from_document, album_summary_pipeline, fetch_tracks_by_ids, AlbumSummary, TrackWithAlbum};
+use super::staging::exclude_staged;
+use super::trash::exclude_trashed;
+use crate::features::upload::keygen::slugify;
+use crate::CommandError;
+use crate::MongoState;
+use crate::core::db_config::{CatalogCollections, CatalogDatabase, cursor_batch_size};
+
+/// Cap on the slug before any numeric suffix.
+const MAX_SLUG_LEN: usize = 96;
+
+/// Slug for a title or album name and its artist, e.g. "night-drive-the-band".
+/// Non-ASCII characters are transliterated and runs of punctuation become one dash.
+pub fn slug_base(name: &str, artist: &str) -> String {
+    slugify(&format!("{} {}", name, artist), MAX_SLUG_LEN)
+}
+
+/// The first of `base`, `base-2`, `base-3`, ... not in `taken`.
+fn first_free(base: &str, taken: &HashSet<String>) -> String {
+    let mut slug = base.to_string();
+    let mut suffix = 2;
+    while taken.contains(&slug) {
+        slug = format!("{}-{}", base, suffix);
+        suffix += 1;
+    }
+    slug
+}
+
+/// Adds the current and previous slugs of a document to `taken`.
+fn collect_slugs(slug_doc: &Document, taken: &mut HashSet<String>) {
+    if let Ok(slug) = slug_doc.get_str("slug") {
+        taken.insert(slug.to_string());
+    }
+    let previous = slug_doc.get_array("previous_slugs").map(|slugs| slugs.as_slice()).unwrap_or_default();
+    taken.extend(previous.iter().filter_map(|slug| slug.as_str().map(String::from)));
+}
+
+/// Slugs in `collection` that a slug made from `base` could collide with, leaving out
+/// those of the document `exclude`.
+async fn taken_slugs(collection: &Collection<Document>, base: &str, exclude: Option<&Bson>) -> mongodb::error::Result<HashSet<String>> {
+    let pattern = format!("^{}(-[0-9]+)?$", regex::escape(base));
+    let mut filter = doc! { "$or": [
+        { "slug": { "$regex": &pattern } },
+        { "previous_slugs": { "$regex": &pattern } },
+    ] };
+    if let Some(id) = exclude {
+        filter.insert("_id", doc! { "$ne": id });
+    }
+    let options = FindOptions::builder().batch_size(cursor_batch_size()).projection(doc! { "slug": 1, "previous_slugs": 1 }).build();
+    let docs: Vec<Document> = collection.find(filter, options).await?.try_collect().await?;
+    let mut taken = HashSet::new();
+    for slug_doc in &docs {
+        collect_slugs(slug_doc, &mut taken);
+    }
+    Ok(taken)
+}
+
+/// A slug from `base` that no other document in `collection` uses now or used before.
+/// The unique index still rejects a concurrent writer that picked the same one.
+pub async fn unique_slug(collection: &Collection<Document>, base: &str, exclude: Option<&Bson>) -> mongodb::error::Result<String> {
+    Ok(first_free(base, &taken_slugs(collection, base, exclude).await?))
+}
+
+/// Whether a write failed because another document took the same slug first.
+pub fn is_slug_conflict(e: &mongodb::error::Error) -> bool {
+    is_duplicate_key_error(e) && e.to_string().contains("slug_1")
+}
+
+/// `$set` and `$addToSet` fields that give the document `id` a slug made from `name` and
+/// `artist`, keeping its current slug in `previous_slugs`. Empty when the slug would not
+/// change.
+pub async fn regenerated_slug_fields(
+    collection: &Collection<Document>,
+    id: &Bson,
+    name: &str,
+    artist: &str,
+) -> mongodb::error::Result<(Document, Document)> {
+    let options = FindOneOptions::builder().projection(doc! { "slug": 1 }).build();
+    let current = collection.find_one(doc! { "_id": id }, options).await?
+        .and_then(|slug_doc| slug_doc.get_str("slug").ok().map(String::from));
+    let slug = unique_slug(collection, &slug_base(name, artist), Some(id)).await?;
+    if current.as_deref() == Some(slug.as_str()) {
+        return Ok((Document::new(), Document::new()));
+    }
+    let mut previous = Document::new();
+    if let Some(current) = current {
+        previous.insert("previous_slugs", current);
+    }
+    Ok((doc! { "slug": slug }, previous))
+}
+
+/// The artist to slug a document by: `artist` on albums, the first of `artists` on tracks.
+fn artist_of(slug_doc: &Document) -> &str {
+    slug_doc.get_str("artist").ok()
+        .or_else(|| slug_doc.get_array("artists").ok().and_then(|artists| artists.first()).and_then(Bson::as_str))
+        .unwrap_or_default()
+}
+
+async fn backfill_collection(collection: &Collection<Document>, name_field: &str) -> Result<u64, CommandError> {
+    let options = FindOptions::builder().batch_size(cursor_batch_size()).projection(doc! { name_field: 1, "artist": 1, "artists": 1 }).build();
+    let missing: Vec<Document> = collection.find(doc! { "slug": { "$exists": false } }, options).await?.try_collect().await?;
+    if missing.is_empty() {
+        return Ok(0);
+    }
+
+    let options = FindOptions::builder().batch_size(cursor_batch_size()).projection(doc! { "slug": 1, "previous_slugs": 1 }).build();
+    let existing: Vec<Document> = collection
+        .find(doc! { "$or": [{ "slug": { "$exists": true } }, { "previous_slugs": { "$exists": true } }] }, options)
+        .await?
+        .try_collect()
+        .await?;
+    let mut taken = HashSet::new();
+    for slug_doc in &existing {
+        collect_slugs(slug_doc, &mut taken);
+    }
+
+    let mut backfilled = 0;
+    for slug_doc in &missing {
+        let Some(id) = slug_doc.get("_id") else { continue };
+        let slug = first_free(&slug_base(slug_doc.get_str(name_field).unwrap_or_default(), artist_of(slug_doc)), &taken);
+        collection.update_one(doc! { "_id": id }, touched(doc! { "$set": { "slug": &slug } }), None).await?;
+        taken.insert(slug);
+        backfilled += 1;
+    }
+    Ok(backfilled)
+}
+
+/// Gives every track and album created before slugs existed one. Returns the number of
+/// documents updated.
+pub async fn backfill_slugs(db: &Database) -> Result<u64, CommandError> {
+    let tracks = backfill_collection(&db.tracks::<Document>(), "title").await?;
+    let albums = backfill_collection(&db.albums::<Document>(), "name").await?;
+    Ok(tracks + albums)
+}
+
+/// Finds the document with `slug`, falling back to one that used to have it.
+async fn find_by_slug(collection: &Collection<Document>, slug: &str, mut filter: Document) -> mongodb::error::Result<Option<Document>> {
+    let options = FindOneOptions::builder().projection(doc! { "_id": 1 }).build();
+    filter.insert("slug", slug);
+    if let Some(found) = collection.find_one(filter.clone(), options.clone()).await? {
+        return Ok(Some(found));
+    }
+    filter.remove("slug");
+    filter.insert("previous_slugs", slug);
+    collection.find_one(filter, options).await
+}
+
+// --- Tauri Commands ---
+
+/// Looks up a track by its current or a previous slug. Trashed tracks are not found.
+#[command]
+pub async fn find_track_by_slug(
+    slug: String,
+    mongo_state: State<'_, MongoState>,
+    album_cache: State<'_, AlbumNameCache>,
+) -> Result<TrackWithAlbum, CommandError> {
+    let db = {
+        let client_lock = mongo_state.client.lock().await;
+        let client = client_lock.as_ref()
+            .ok_or_else(|| CommandError::Configuration("MongoDB client not initialized".to_string()))?;
+        client.catalog_database()
+    };
+    let not_found = || CommandError::NotFound(format!("No track with slug '{}'", slug));
+    let mut filter = Document::new();
+    exclude_trashed(&mut filter);
+    exclude_staged(&mut filter);
+    let track_id = find_by_slug(&db.tracks::<Document>(), &slug, filter).await?
+        .and_then(|track| track.get_object_id("_id").ok())
+        .ok_or_else(not_found)?;
+    fetch_tracks_by_ids(&db, &album_cache, &[track_id]).await?.into_iter().next().ok_or_else(not_found)
+}
+
+/// Looks up an album summary by its current or a previous slug.
+#[command]
+pub async fn find_album_by_slug(slug: String, mongo_state: State<'_, MongoState>) -> Result<AlbumSummary, CommandError> {
+    let db = {
+        let client_lock = mongo_state.client.lock().await;
+        let client = client_lock.as_ref()
+            .ok_or_else(|| CommandError::Configuration("MongoDB client not initialized".to_string()))?;
+        client.catalog_database()
+    };
+    let not_found = || CommandError::NotFound(format!("No album with slug '{}'", slug));
+    let albums_collection = db.albums::<Document>();
+    let album_id = find_by_slug(&albums_collection, &slug, Document::new()).await?
+        .and_then(|album| album.get("_id").cloned())
+        .ok_or_else(not_found)?;
+
+    let mut pipeline = album_summary_pipeline(None, 0, 1).map_err(CommandError::Unexpected)?;
+    pipeline.insert(0, doc! { "$match": { "_id": album_id } });
+    let album_docs: Vec<Document> = albums_collection.aggregate(pipeline, None).await?.try_collect().await?;
+    album_docs.first().and_then(album_summary_from_document).ok_or_else(not_found)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_slugs_fold_unicode_and_take_suffixes() {
+        assert_eq!(slug_base("Café  del Mar!!", "Sigur Rós"), "cafe-del-mar-sigur-ros");
+        assert_eq!(slug_base("--Night -- Drive--", ""), "night-drive");
+        assert_eq!(slug_base("", ""), "unknown");
+
+        let taken: HashSet<String> = ["song-artist", "song-artist-2"].iter().map(|s| s.to_string()).collect();
+        assert_eq!(first_free("song-artist", &taken), "song-artist-3");
+        assert_eq!(first_free("other-artist", &taken), "other-artist");
+
+        let mut collected = HashSet::new();
+        collect_slugs(&doc! { "slug": "new", "previous_slugs": ["old", "older"] }, &mut collected);
+        assert_eq!(collected.len(), 3);
+    }
+}
@@ -0,0 +1,366 @@
+//! Review of new uploads before they go live. Uploads queued with `staging` store their
+//! objects under `staging/` and carry `status: "staged"`, which keeps them out of track
+//! listings. `promote_tracks` moves them to the keys they would have had and clears the
+//! status; `reject_staged_tracks` deletes them.
+//!
+//! Promotion copies each object and checks the copy before the document is pointed at
+//! it, and only then deletes the staged object, so a failure at any step leaves the
+//! track staged and complete. Copies made before a failure are reused on the next try.
+//! A final key taken in the meantime is resolved with the upload's `on_key_collision`
+//! policy, recorded on the staged track.
+
+use futures_util::stream::TryStreamExt;
+use log::{info, warn};
+use mongodb::bson::{doc, oid::ObjectId, Document};
+use mongodb::options::FindOptions;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::future::Future;
+use tauri::{command, AppHandle, State, Wry};
+
+use super::album_names::AlbumNameCache;
+use super::changes::{record_deletions, touched, CatalogKind};
+use super::storage::id_to_string;
+use super::storage::mongodb::{fetch_tracks_by_ids, TrackWithAlbum};
+use super::trash::ids_filter;
+use crate::core::db_config::{CatalogCollections, cursor_batch_size};
+use crate::core::r2::{R2Client, TRACK_KEY_FIELDS};
+use crate::features::activity::{record_activity, ActivityAction, ActivityEntry};
+use crate::features::upload::keygen::{resolve_collision, KeyCollisionPolicy};
+use crate::CommandError;
+use crate::{MongoState, R2State};
+
+pub const STAGING_PREFIX: &str = "staging/";
+
+/// `status` of tracks waiting for review.
+pub const STAGED_STATUS: &str = "staged";
+
+/// Field of a staged track holding the `KeyCollisionPolicy` it was uploaded with.
+pub const COLLISION_POLICY_FIELD: &str = "key_collision_policy";
+
+/// A track that could not be promoted or rejected.
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct StagingFailure {
+    pub track_id: String,
+    pub error: String,
+}
+
+/// Outcome of `promote_tracks` and `reject_staged_tracks`.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct StagingResult {
+    pub succeeded: Vec<String>,
+    /// Tracks left staged, with the reason
+    pub failed: Vec<StagingFailure>,
+    /// Staged objects of promoted tracks that could not be deleted; nothing references them
+    pub leftover_keys: Vec<String>,
+}
+
+/// The key an object is uploaded to when its upload is staged.
+pub fn staged_key(key: &str) -> String {
+    format!("{}{}", STAGING_PREFIX, key)
+}
+
+/// Adds the condition that leaves staged tracks out of a track query.
+pub fn exclude_staged(filter: &mut Document) {
+    filter.insert("status", doc! { "$ne": STAGED_STATUS });
+}
+
+fn staged_filter(track_ids: &[String]) -> Document {
+    let mut filter = ids_filter(track_ids);
+    filter.insert("status", STAGED_STATUS);
+    filter
+}
+
+/// Staged keys of a track and the keys they are promoted to, by staged key. Legacy
+/// fields usually repeat the current ones, so a key can appear in several fields.
+fn promotion_moves(track_doc: &Document) -> BTreeMap<String, String> {
+    TRACK_KEY_FIELDS.iter()
+        .filter_map(|field| track_doc.get_str(field).ok())
+        .filter_map(|key| key.strip_prefix(STAGING_PREFIX).map(|promoted| (key.to_string(), promoted.to_string())))
+        .collect()
+}
+
+/// The `$set` pointing every key field of the track at its promoted key.
+fn promotion_update(track_doc: &Document, moves: &BTreeMap<String, String>) -> Document {
+    let mut set = Document::new();
+    for field in TRACK_KEY_FIELDS {
+        if let Some(promoted) = track_doc.get_str(field).ok().and_then(|key| moves.get(key)) {
+            set.insert(field, promoted);
+        }
+    }
+    touched(doc! { "$set": set, "$unset": { "status": "", COLLISION_POLICY_FIELD: "" } })
+}
+
+/// The collision policy a staged track was uploaded with; tracks staged before it was
+/// recorded get the default.
+fn collision_policy(track_doc: &Document) -> KeyCollisionPolicy {
+    track_doc.get(COLLISION_POLICY_FIELD)
+        .and_then(|policy| mongodb::bson::from_bson(policy.clone()).ok())
+        .unwrap_or_default()
+}
+
+/// Copies each staged object to its final key and returns the moves as made. A final key
+/// that `taken` reports holding another object is resolved with `policy` first; an
+/// earlier copy of the same object doesn't count, so a retry reuses it. Stops at the
+/// first failure.
+async fn copy_staged_objects<T, TFut, C, CFut>(
+    moves: BTreeMap<String, String>,
+    policy: KeyCollisionPolicy,
+    taken: T,
+    copy: C,
+) -> Result<BTreeMap<String, String>, String>
+where
+    T: Fn(String, String) -> TFut,
+    TFut: Future<Output = Result<bool, String>>,
+    C: Fn(String, String) -> CFut,
+    CFut: Future<Output = Result<(), String>>,
+{
+    let mut made = BTreeMap::new();
+    for (staged, promoted) in moves {
+        let target = resolve_collision(promoted, policy, |key| taken(staged.clone(), key)).await
+            .map_err(|e| e.to_string())?;
+        copy(staged.clone(), target.clone()).await
+            .map_err(|e| format!("Copy of {} to {} failed: {}", staged, target, e))?;
+        made.insert(staged, target);
+    }
+    Ok(made)
+}
+
+async fn staged_tracks(db: &mongodb::Database, track_ids: &[String]) -> Result<Vec<Document>, CommandError> {
+    if track_ids.is_empty() {
+        return Err(CommandError::Validation("No tracks selected".to_string()));
+    }
+    Ok(db.tracks::<Document>().find(staged_filter(track_ids), None).await?.try_collect().await?)
+}
+
+/// Adds a failure for every requested track that isn't staged.
+fn not_staged(track_ids: &[String], found: &[Document], result: &mut StagingResult) {
+    let found: Vec<String> = found.iter().filter_map(|track| track.get("_id").and_then(id_to_string)).collect();
+    for track_id in track_ids.iter().filter(|id| !found.contains(id)) {
+        result.failed.push(StagingFailure { track_id: track_id.clone(), error: "Track is not staged".to_string() });
+    }
+}
+
+/// Copies the staged objects and points the track at the copies. Returns the staged keys
+/// to delete afterwards.
+async fn promote_track(db: &mongodb::Database, bucket_client: &R2Client, track_doc: &Document) -> Result<Vec<String>, String> {
+    let policy = collision_policy(track_doc);
+    let overwrite = policy == KeyCollisionPolicy::Overwrite;
+    let moves = copy_staged_objects(
+        promotion_moves(track_doc),
+        policy,
+        |staged, key| async move { bucket_client.holds_other_object(&staged, &key).await.map_err(|e| e.to_string()) },
+        |staged, key| async move { bucket_client.copy_object_verified(&staged, &key, overwrite).await.map_err(|e| e.to_string()) },
+    ).await?;
+    let filter = doc! { "_id": track_doc.get("_id").cloned(), "status": STAGED_STATUS };
+    let updated = db.tracks::<Document>().update_one(filter, promotion_update(track_doc, &moves), None).await
+        .map_err(|e| format!("Failed to update the track: {}", e))?;
+    if updated.matched_count == 0 {
+        return Err("Track is no longer staged".to_string());
+    }
+    Ok(moves.into_keys().collect())
+}
+
+// --- Tauri Commands ---
+
+/// Lists tracks waiting for review, newest first.
+#[command]
+pub async fn list_staged_tracks(
+    mongo_state: State<'_, MongoState>,
+    album_cache: State<'_, AlbumNameCache>,
+) -> Result<Vec<TrackWithAlbum>, CommandError> {
+    let db = mongo_state.database().await?;
+    let options = FindOptions::builder().batch_size(cursor_batch_size()).projection(doc! { "_id": 1 }).sort(doc! { "date_added": -1 }).build();
+    let staged: Vec<Document> = db.tracks::<Document>()
+        .find(doc! { "status": STAGED_STATUS }, options)
+        .await?
+        .try_collect()
+        .await?;
+    let object_ids: Vec<ObjectId> = staged.iter().filter_map(|track| track.get_object_id("_id").ok()).collect();
+    Ok(fetch_tracks_by_ids(&db, &album_cache, &object_ids).await?)
+}
+
+/// Moves staged tracks to their final keys and makes them visible. Each object is copied
+/// and checked, then the track is updated, then the staged objects are deleted; a track
+/// that fails before its update stays staged.
+#[command]
+pub async fn promote_tracks(
+    track_ids: Vec<String>,
+    mongo_state: State<'_, MongoState>,
+    r2_state: State<'_, R2State>,
+) -> Result<StagingResult, CommandError> {
+    let db = mongo_state.database().await?;
+    let r2_client = R2Client::from_state(&r2_state).await?;
+    let tracks = staged_tracks(&db, &track_ids).await?;
+
+    let mut result = StagingResult::default();
+    not_staged(&track_ids, &tracks, &mut result);
+    for track_doc in &tracks {
+        let track_id = track_doc.get("_id").and_then(id_to_string).unwrap_or_default();
+        let bucket_client = r2_client.for_track(track_doc);
+        match promote_track(&db, &bucket_client, track_doc).await {
+            Ok(staged_keys) => {
+                if let Err(e) = bucket_client.delete_objects(&staged_keys).await {
+                    warn!("Promoted track {} but failed to delete its staged objects: {}", track_id, e);
+                    result.leftover_keys.extend(staged_keys);
+                }
+                result.succeeded.push(track_id);
+            }
+            Err(error) => {
+                warn!("Failed to promote track {}: {}", track_id, error);
+                result.failed.push(StagingFailure { track_id, error });
+            }
+        }
+    }
+    info!("Promoted {} staged tracks; {} failed", result.succeeded.len(), result.failed.len());
+    Ok(result)
+}
+
+/// Deletes staged tracks: their R2 objects first, then their documents. A track whose
+/// objects cannot all be deleted stays staged.
+#[command]
+pub async fn reject_staged_tracks(
+    track_ids: Vec<String>,
+    app_handle: AppHandle<Wry>,
+    mongo_state: State<'_, MongoState>,
+    r2_state: State<'_, R2State>,
+) -> Result<StagingResult, CommandError> {
+    let db = mongo_state.database().await?;
+    let r2_client = R2Client::from_state(&r2_state).await?;
+    let tracks = staged_tracks(&db, &track_ids).await?;
+
+    let mut result = StagingResult::default();
+    not_staged(&track_ids, &tracks, &mut result);
+    let mut deleted_ids = Vec::new();
+    for track_doc in &tracks {
+        let track_id = track_doc.get("_id").and_then(id_to_string).unwrap_or_default();
+        let keys: Vec<String> = promotion_moves(track_doc).into_keys().collect();
+        if let Err(e) = r2_client.for_track(track_doc).delete_objects(&keys).await {
+            warn!("Failed to delete R2 objects of staged track {}: {}", track_id, e);
+            result.failed.push(StagingFailure { track_id, error: e.to_string() });
+            continue;
+        }
+        if let Some(id) = track_doc.get("_id") {
+            deleted_ids.push(id.clone());
+            result.succeeded.push(track_id);
+        }
+    }
+
+    if !deleted_ids.is_empty() {
+        db.tracks::<Document>().delete_many(doc! { "_id": { "$in": &deleted_ids }, "status": STAGED_STATUS }, None).await?;
+        let track_ids = result.succeeded.clone();
+        record_deletions(&db, CatalogKind::Track, track_ids.clone()).await;
+        db.albums::<Document>()
+            .update_many(doc! { "track_ids": { "$in": &track_ids } }, touched(doc! { "$pull": { "track_ids": { "$in": &track_ids } } }), None)
+            .await?;
+        let summary = format!("Rejected {} staged track{}", track_ids.len(), if track_ids.len() == 1 { "" } else { "s" });
+        record_activity(&app_handle, Some(&db), ActivityEntry::new(ActivityAction::TracksDeleted, track_ids, summary)).await;
+    }
+    info!("Rejected {} staged tracks; {} failed", result.succeeded.len(), result.failed.len());
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_promotion_strips_the_staging_prefix() {
+        let track = doc! {
+            "r2_archive_key": "staging/tracks/a1/song.wav",
+            "r2_original_key": "staging/tracks/a1/song.wav",
+            "r2_delivery_key": "staging/delivery/a1/song.m4a",
+            "r2_aac_key": null,
+        };
+        let moves = promotion_moves(&track);
+        assert_eq!(moves.len(), 2);
+        assert_eq!(moves["staging/tracks/a1/song.wav"], "tracks/a1/song.wav");
+
+        let update = promotion_update(&track, &moves);
+        let set = update.get_document("$set").unwrap();
+        assert_eq!(set.get_str("r2_archive_key"), Ok("tracks/a1/song.wav"));
+        assert_eq!(set.get_str("r2_original_key"), Ok("tracks/a1/song.wav"));
+        assert_eq!(set.get_str("r2_delivery_key"), Ok("delivery/a1/song.m4a"));
+        assert!(!set.contains_key("r2_aac_key"));
+        assert_eq!(update.get_document("$unset").unwrap(), &doc! { "status": "", COLLISION_POLICY_FIELD: "" });
+
+        // Already promoted keys are left alone
+        assert!(promotion_moves(&doc! { "r2_archive_key": "tracks/a1/song.wav" }).is_empty());
+        assert_eq!(staged_key("tracks/a1/song.wav"), "staging/tracks/a1/song.wav");
+    }
+
+    /// Objects by key, each with the staged key it was copied from ("" for other
+    /// objects), and keys whose copy fails.
+    #[derive(Default)]
+    struct FakeBucket {
+        objects: std::sync::Mutex<BTreeMap<String, String>>,
+        failing: Vec<String>,
+    }
+
+    impl FakeBucket {
+        fn taken(&self, staged: String, key: String) -> impl Future<Output = Result<bool, String>> {
+            let taken = self.objects.lock().unwrap().get(&key).is_some_and(|from| *from != staged);
+            async move { Ok(taken) }
+        }
+
+        fn copy(&self, staged: String, key: String) -> impl Future<Output = Result<(), String>> {
+            let result = if self.failing.contains(&key) {
+                Err("connection reset".to_string())
+            } else {
+                self.objects.lock().unwrap().insert(key, staged);
+                Ok(())
+            };
+            async move { result }
+        }
+
+        async fn promote(&self, moves: &BTreeMap<String, String>, policy: KeyCollisionPolicy) -> Result<BTreeMap<String, String>, String> {
+            copy_staged_objects(moves.clone(), policy, |staged, key| self.taken(staged, key), |staged, key| self.copy(staged, key)).await
+        }
+    }
+
+    fn staged_track_moves() -> BTreeMap<String, String> {
+        promotion_moves(&doc! {
+            "r2_archive_key": "staging/tracks/a1/song.wav",
+            "r2_delivery_key": "staging/delivery/a1/song.m4a",
+        })
+    }
+
+    #[tokio::test]
+    async fn test_interrupted_promotion_reuses_earlier_copies() {
+        let moves = staged_track_moves();
+        let mut bucket = FakeBucket { failing: vec!["tracks/a1/song.wav".to_string()], ..Default::default() };
+        let error = bucket.promote(&moves, KeyCollisionPolicy::Fail).await.unwrap_err();
+        assert!(error.contains("connection reset"), "{}", error);
+        // The delivery copy was made before the failure
+        assert!(bucket.objects.lock().unwrap().contains_key("delivery/a1/song.m4a"));
+
+        // The retry takes the earlier copy as its own rather than as a collision
+        bucket.failing.clear();
+        let made = bucket.promote(&moves, KeyCollisionPolicy::Fail).await.unwrap();
+        assert_eq!(made, moves);
+    }
+
+    #[tokio::test]
+    async fn test_taken_final_keys_follow_the_collision_policy() {
+        let moves = staged_track_moves();
+        let bucket = || FakeBucket {
+            objects: std::sync::Mutex::new(BTreeMap::from([("tracks/a1/song.wav".to_string(), String::new())])),
+            ..Default::default()
+        };
+
+        let error = bucket().promote(&moves, KeyCollisionPolicy::Fail).await.unwrap_err();
+        assert!(error.contains("already exists at tracks/a1/song.wav"), "{}", error);
+
+        let renamed = bucket().promote(&moves, KeyCollisionPolicy::Rename).await.unwrap();
+        let archive = &renamed["staging/tracks/a1/song.wav"];
+        assert!(archive.starts_with("tracks/a1/song-") && archive.ends_with(".wav"), "{}", archive);
+        assert_eq!(renamed["staging/delivery/a1/song.m4a"], "delivery/a1/song.m4a");
+
+        let overwritten = bucket().promote(&moves, KeyCollisionPolicy::Overwrite).await.unwrap();
+        assert_eq!(overwritten, moves);
+
+        let track = doc! { COLLISION_POLICY_FIELD: "overwrite" };
+        assert_eq!(collision_policy(&track), KeyCollisionPolicy::Overwrite);
+        assert_eq!(collision_policy(&doc! {}), KeyCollisionPolicy::Rename);
+    }
+}
@@ -0,0 +1,147 @@
+//! Play and download counters kept on each track under `stats`. Serving a track
+//! bumps them with an atomic update in the background, so a slow or failed write
+//! never holds up playback or an export.
+
+use futures_util::stream::TryStreamExt;
+use log::warn;
+use mongodb::bson::{doc, Bson, DateTime, Document};
+use mongodb::options::FindOptions;
+use mongodb::Collection;
+use serde::Serialize;
+use tauri::{command, State};
+
+use super::staging::exclude_staged;
+use super::storage::{id_filter, id_to_string};
+use super::trash::exclude_trashed;
+use crate::CommandError;
+use crate::MongoState;
+
+const MAX_TOP_TRACKS: i64 = 500;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AccessKind {
+    Play,
+    Download,
+}
+
+impl AccessKind {
+    fn counter_field(self) -> &'static str {
+        match self {
+            AccessKind::Play => "stats.play_count",
+            AccessKind::Download => "stats.download_count",
+        }
+    }
+}
+
+/// Counters of a track. Tracks never served have all zeroes.
+#[derive(Debug, Serialize, Clone, PartialEq)]
+pub struct TrackStats {
+    pub play_count: i64,
+    pub download_count: i64,
+    /// RFC 3339
+    pub last_accessed: Option<String>,
+}
+
+impl TrackStats {
+    fn from_track(track_doc: &Document) -> Self {
+        let stats = track_doc.get_document("stats").ok();
+        let count = |name: &str| match stats.and_then(|stats| stats.get(name)) {
+            Some(Bson::Int32(n)) => *n as i64,
+            Some(Bson::Int64(n)) => *n,
+            _ => 0,
+        };
+        TrackStats {
+            play_count: count("play_count"),
+            download_count: count("download_count"),
+            last_accessed: stats.and_then(|stats| stats.get_datetime("last_accessed").ok())
+                .and_then(|at| at.try_to_rfc3339_string().ok()),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct TopTrack {
+    pub track_id: String,
+    pub title: Option<String>,
+    pub stats: TrackStats,
+}
+
+fn access_update(kind: AccessKind, now: DateTime) -> Document {
+    doc! {
+        "$inc": { kind.counter_field(): 1_i64 },
+        "$set": { "stats.last_accessed": now },
+    }
+}
+
+/// Counts one play or download of a track without waiting for the write.
+pub fn record_access(tracks_collection: Collection<Document>, track_id: &str, kind: AccessKind) {
+    let track_id = track_id.to_string();
+    tauri::async_runtime::spawn(async move {
+        let update = access_update(kind, DateTime::now());
+        if let Err(e) = tracks_collection.update_one(id_filter(&track_id), update, None).await {
+            warn!("Failed to record {:?} of track {}: {}", kind, track_id, e);
+        }
+    });
+}
+
+async fn tracks_collection(mongo_state: &MongoState) -> Result<Collection<Document>, CommandError> {
+    Ok(mongo_state.collections().await?.0)
+}
+
+// --- Tauri Commands ---
+
+#[command]
+pub async fn get_track_stats(track_id: String, mongo_state: State<'_, MongoState>) -> Result<TrackStats, CommandError> {
+    let options = mongodb::options::FindOneOptions::builder().projection(doc! { "stats": 1 }).build();
+    let track_doc = tracks_collection(&mongo_state).await?.find_one(id_filter(&track_id), options).await?
+        .ok_or_else(|| CommandError::NotFound(format!("Track {} not found", track_id)))?;
+    Ok(TrackStats::from_track(&track_doc))
+}
+
+/// Most played (`metric` = "plays") or most downloaded ("downloads") tracks, highest
+/// first. Tracks never served are left out.
+#[command]
+pub async fn top_tracks(metric: String, limit: Option<i64>, mongo_state: State<'_, MongoState>) -> Result<Vec<TopTrack>, CommandError> {
+    let field = match metric.as_str() {
+        "plays" => AccessKind::Play.counter_field(),
+        "downloads" => AccessKind::Download.counter_field(),
+        other => return Err(CommandError::Validation(format!("Unknown metric '{}', expected 'plays' or 'downloads'", other))),
+    };
+    let limit = limit.unwrap_or(50).clamp(1, MAX_TOP_TRACKS);
+    let mut filter = doc! { field: { "$gt": 0 } };
+    exclude_trashed(&mut filter);
+    exclude_staged(&mut filter);
+    let options = FindOptions::builder()
+        .sort(doc! { field: -1, "_id": 1 })
+        .limit(limit)
+        .projection(doc! { "title": 1, "stats": 1 })
+        .build();
+    let tracks: Vec<Document> = tracks_collection(&mongo_state).await?.find(filter, options).await?.try_collect().await?;
+    Ok(tracks.iter()
+        .filter_map(|track_doc| Some(TopTrack {
+            track_id: track_doc.get("_id").and_then(id_to_string)?,
+            title: track_doc.get_str("title").ok().map(String::from),
+            stats: TrackStats::from_track(track_doc),
+        }))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_access_update_and_stats() {
+        let now = DateTime::from_millis(1_700_000_000_000);
+        assert_eq!(
+            access_update(AccessKind::Download, now),
+            doc! { "$inc": { "stats.download_count": 1_i64 }, "$set": { "stats.last_accessed": now } }
+        );
+
+        let track_doc = doc! { "stats": { "play_count": 3_i32, "last_accessed": now } };
+        let stats = TrackStats::from_track(&track_doc);
+        assert_eq!((stats.play_count, stats.download_count), (3, 0));
+        assert_eq!(stats.last_accessed.as_deref(), Some("2023-11-14T22:13:20Z"));
+        assert_eq!(TrackStats::from_track(&doc! {}).play_count, 0);
+    }
+}
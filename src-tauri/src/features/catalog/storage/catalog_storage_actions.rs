@@ -6,6 +6,13 @@ use futures_util::stream::TryStreamExt;
 use log::{info, warn, error};
 use std::collections::HashMap;
 use anyhow::{Result, anyhow}; // Use anyhow for error handling
+use tauri::{AppHandle, Wry};
+
+use super::id_to_string;
+use crate::features::activity::{record_activity, ActivityAction, ActivityEntry};
+use crate::features::catalog::changes::{record_deletions, touched, CatalogKind};
+use crate::core::db_config::CatalogCollections;
+use crate::core::r2::R2Client;
 
 // Import AWS S3 SDK directly
 use aws_sdk_s3;
@@ -28,7 +35,7 @@ mod r2_operations {
     }
     
     // Placeholder for R2 delete files function
-    pub async fn delete_files(r2_client: &MyR2Client, file_paths: &[String]) -> Result<()> {
+    pub async fn delete_files(r2_client: &R2Client, file_paths: &[String]) -> Result<()> {
         // Implementation would go here
         info!("Placeholder: Would delete {} files from R2", file_paths.len());
         Ok(())
@@ -52,9 +59,9 @@ mod r2_operations {
 }
 
 /// Deletes multiple tracks from the database and corresponding files from R2.
-pub async fn delete_tracks_by_ids(db: &Database, r2_client: &MyR2Client, track_ids: &[String]) -> Result<()> {
+pub async fn delete_tracks_by_ids(app_handle: &AppHandle<Wry>, db: &Database, r2_client: &MyR2Client, track_ids: &[String]) -> Result<()> {
     info!("Attempting to delete tracks with IDs: {:?}", track_ids);
-    let collection: Collection<mongodb::bson::Document> = db.collection("tracks");
+    let collection: Collection<mongodb::bson::Document> = db.tracks();
 
     // Ensure IDs are not empty before proceeding
     if track_ids.is_empty() {
@@ -84,54 +91,63 @@ pub async fn delete_tracks_by_ids(db: &Database, r2_client: &MyR2Client, track_i
         }
     };
 
-    // Extract file paths and album IDs
+    // Extract file paths, grouped by the bucket holding them, and album IDs
     let mut album_updates: HashMap<String, Vec<String>> = HashMap::new(); // album_id -> [track_id_to_remove]
-    let file_paths_to_delete: Vec<String> = tracks_to_delete.iter()
-        .filter_map(|doc| {
-            let path = doc.get_str("path").ok().map(String::from);
-            // Use track_id (which is _id in the doc)
-            if let (Ok(track_id), Ok(album_id)) = (doc.get_str("_id"), doc.get_str("album_id")) {
-                 if !album_id.is_empty() { // Only update if album_id is present
-                    album_updates.entry(album_id.to_string()).or_default().push(track_id.to_string());
-                 }
-            }
-            path
-        })
-        .collect();
+    let mut file_paths_to_delete: HashMap<String, (R2Client, Vec<String>)> = HashMap::new(); // bucket -> (client, paths)
+    let default_client = R2Client::new(r2_client.client.clone(), r2_client.bucket_name.clone());
+    for doc in &tracks_to_delete {
+        if let Ok(path) = doc.get_str("path") {
+            let bucket_client = default_client.for_track(doc);
+            file_paths_to_delete.entry(bucket_client.bucket_name().to_string())
+                .or_insert_with(|| (bucket_client, Vec::new()))
+                .1.push(path.to_string());
+        }
+        // Use track_id (which is _id in the doc)
+        if let (Ok(track_id), Ok(album_id)) = (doc.get_str("_id"), doc.get_str("album_id")) {
+             if !album_id.is_empty() { // Only update if album_id is present
+                album_updates.entry(album_id.to_string()).or_default().push(track_id.to_string());
+             }
+        }
+    }
 
-    info!("File paths identified for R2 deletion: {:?}", file_paths_to_delete);
+    for (bucket, (_, paths)) in &file_paths_to_delete {
+        info!("File paths identified for R2 deletion in bucket {}: {:?}", bucket, paths);
+    }
     info!("Album updates needed: {:?}", album_updates);
 
     // 2. Now, delete the documents from MongoDB
     match collection.delete_many(filter, None).await {
         Ok(delete_result) => {
             info!("Successfully deleted {} tracks from MongoDB.", delete_result.deleted_count);
+            let summary = format!("Deleted {} track{}", delete_result.deleted_count, if delete_result.deleted_count == 1 { "" } else { "s" });
+            record_activity(app_handle, Some(db), ActivityEntry::new(ActivityAction::TracksDeleted, track_ids.to_vec(), summary)).await;
+            let deleted_ids = tracks_to_delete.iter().filter_map(|doc| doc.get("_id").and_then(id_to_string)).collect();
+            record_deletions(db, CatalogKind::Track, deleted_ids).await;
             if delete_result.deleted_count != tracks_to_delete.len() as u64 {
                 warn!("Mismatch between found documents ({}) and deleted count ({}).", tracks_to_delete.len(), delete_result.deleted_count);
             }
 
-            // Delete corresponding files from R2
-            if !file_paths_to_delete.is_empty() {
-                info!("Attempting to delete {} files from R2.", file_paths_to_delete.len());
-                // Assuming a function `delete_files` exists in the r2 module
-                match r2_operations::delete_files(r2_client, &file_paths_to_delete).await { // Use the imported r2 module
-                    Ok(_) => info!("Successfully requested deletion of files from R2."),
+            // Delete corresponding files from R2, from each bucket the tracks used
+            for (bucket, (bucket_client, paths)) in &file_paths_to_delete {
+                info!("Attempting to delete {} files from R2 bucket {}.", paths.len(), bucket);
+                match r2_operations::delete_files(bucket_client, paths).await {
+                    Ok(_) => info!("Successfully requested deletion of files from R2 bucket {}.", bucket),
                     Err(e) => {
                         // Log the error but don't necessarily fail the whole operation,
                         // as the DB deletion might have succeeded.
-                        error!("Failed to delete files from R2: {:?}", e);
+                        error!("Failed to delete files from R2 bucket {}: {:?}", bucket, e);
                         // Optionally, return an error or partial success indicator here
                     }
                 }
             }
 
             // 3. Update affected albums
-            let albums_collection: Collection<mongodb::bson::Document> = db.collection("albums");
+            let albums_collection: Collection<mongodb::bson::Document> = db.albums();
             for (album_id, track_ids_to_remove) in album_updates {
                 info!("Updating album {} to remove tracks {:?}", album_id, track_ids_to_remove);
                 let update_result = albums_collection.update_one(
                     doc! { "_id": &album_id },
-                    doc! { "$pull": { "track_ids": { "$in": track_ids_to_remove } } },
+                    touched(doc! { "$pull": { "track_ids": { "$in": track_ids_to_remove } } }),
                     None
                 ).await;
 
@@ -167,7 +183,7 @@ pub async fn replace_track_audio(
     new_medium_quality_local_path: &str, // Path of the newly transcoded file on local disk
 ) -> Result<()> {
     info!("Starting audio replacement for track_id: {}", track_id);
-    let tracks_collection: Collection<mongodb::bson::Document> = db.collection("tracks");
+    let tracks_collection: Collection<mongodb::bson::Document> = db.tracks();
 
     // 1. Fetch the existing track document
     let filter = doc! { "_id": track_id };
@@ -213,7 +229,7 @@ pub async fn replace_track_audio(
 
     // 4. Update the track document in MongoDB
     info!("Updating MongoDB document for track {} with new path {}", track_id, new_r2_medium_key);
-    let update_doc = doc! { "$set": { "path": &new_r2_medium_key } }; // Adjust field name if needed
+    let update_doc = touched(doc! { "$set": { "path": &new_r2_medium_key } }); // Adjust field name if needed
     match tracks_collection.update_one(filter, update_doc, None).await {
         Ok(update_result) => {
             if update_result.matched_count == 0 {
@@ -239,7 +255,8 @@ pub async fn replace_track_audio(
         if !old_path.is_empty() && old_path != new_r2_medium_key {
             info!("Deleting old R2 file: {}", old_path);
             // Assuming delete_files exists and takes R2Client and a slice of keys
-            match r2_operations::delete_files(r2_client, &[old_path.clone()]).await { // Use the imported r2 module
+            let bucket_client = R2Client::new(r2_client.client.clone(), r2_client.bucket_name.clone()).for_track(&track_doc);
+            match r2_operations::delete_files(&bucket_client, &[old_path.clone()]).await { // Use the imported r2 module
                 Ok(_) => info!("Successfully deleted old file {} from R2.", old_path),
                 Err(e) => {
                     // Log error but don't fail the overall operation, as the main goal (replacement) succeeded.
@@ -4,11 +4,84 @@ pub mod catalog_storage_actions; // Declare the new module
 // Removed re-exports, will use full paths in commands.rs
 // pub use r2::R2Client; // Remove R2 re-export
 
+use ::mongodb::bson::{doc, oid::ObjectId, Bson, Document};
+use ::mongodb::error::{ErrorKind, WriteFailure};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use ts_rs::TS;
+
+/// Renders a document `_id` as a string regardless of whether it is stored as an ObjectId or a string.
+pub fn id_to_string(id: &Bson) -> Option<String> {
+    match id {
+        Bson::ObjectId(oid) => Some(oid.to_hex()),
+        Bson::String(s) => Some(s.clone()),
+        _ => None,
+    }
+}
+
+/// Builds an `_id` filter that matches both ObjectId and string ids.
+pub fn id_filter(id: &str) -> Document {
+    match ObjectId::parse_str(id) {
+        Ok(oid) => doc! { "_id": oid },
+        Err(_) => doc! { "_id": id },
+    }
+}
+
+/// Whether a write failed on a unique index.
+pub fn is_duplicate_key_error(e: &::mongodb::error::Error) -> bool {
+    match e.kind.as_ref() {
+        ErrorKind::Command(command_error) => command_error.code == 11000,
+        ErrorKind::Write(WriteFailure::WriteError(write_error)) => write_error.code == 11000,
+        _ => false,
+    }
+}
+
+/// Normalizes an ISRC to its 12-character form (`CCXXXYYNNNNN`: country code, registrant,
+/// year, designation), accepting lowercase and the hyphenated display form.
+pub fn normalize_isrc(isrc: &str) -> Result<String, String> {
+    let compact: String = isrc.trim().chars().filter(|c| *c != '-').collect::<String>().to_ascii_uppercase();
+    let bytes = compact.as_bytes();
+    let valid = bytes.len() == 12
+        && bytes[..2].iter().all(u8::is_ascii_uppercase)
+        && bytes[2..5].iter().all(u8::is_ascii_alphanumeric)
+        && bytes[5..].iter().all(u8::is_ascii_digit);
+    if valid {
+        Ok(compact)
+    } else {
+        Err(format!("'{}' is not a valid ISRC (expected 2 letters, 3 letters or digits, then 7 digits)", isrc.trim()))
+    }
+}
+
+/// Allowed difference between a percentage split and 100.
+pub const PERCENTAGE_TOLERANCE: f32 = 0.1;
+
+/// Checks a writer or publisher split: every share is between 0 and 100, the shares sum
+/// to 100, and every name is credited in `names`. An empty split means none is recorded.
+pub fn validate_percentages(percentages: &HashMap<String, f32>, names: &[String], role: &str) -> Result<(), String> {
+    if percentages.is_empty() {
+        return Ok(());
+    }
+    let mut uncredited: Vec<&str> = percentages.keys()
+        .filter(|name| !names.iter().any(|credited| credited.trim() == name.trim()))
+        .map(String::as_str)
+        .collect();
+    if !uncredited.is_empty() {
+        uncredited.sort_unstable();
+        return Err(format!("{} percentages given for names not in the {} list: {}", role, role.to_lowercase(), uncredited.join(", ")));
+    }
+    if let Some((name, share)) = percentages.iter().find(|(_, share)| !(share.is_finite() && (0.0..=100.0).contains(*share))) {
+        return Err(format!("{} percentage for {} must be between 0 and 100, got {}", role, name, share));
+    }
+    let total: f32 = percentages.values().sum();
+    if (total - 100.0).abs() > PERCENTAGE_TOLERANCE {
+        return Err(format!("{} percentages must add up to 100, got {}", role, total));
+    }
+    Ok(())
+}
 
 // Payload for updating track metadata selectively
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, TS)]
+#[ts(export, optional_fields = nullable)] // Unset fields are left as they are
 pub struct UpdateTrackPayload {
     pub title: Option<String>,
     pub genre: Option<Vec<String>>,
@@ -19,5 +92,47 @@ pub struct UpdateTrackPayload {
     pub instruments: Option<Vec<String>>, // Assuming Vec<String> based on usage pattern
     pub mood: Option<Vec<String>>, // Assuming Vec<String> based on usage pattern
     pub comments: Option<String>,
+    pub custom_fields: Option<HashMap<String, String>>, // Replaces all custom fields when set
+    pub bpm: Option<f32>, // Manual correction of the estimate made during upload
+    pub musical_key: Option<String>, // Manual correction, e.g. "A minor"
+    pub composers: Option<Vec<String>>,
+    pub isrc: Option<String>, // Empty string removes the ISRC
+    pub catalog_number: Option<String>, // Empty string removes the catalog number
+    #[serde(default)]
+    pub regenerate_slug: bool, // New slug from the (new) title; the old one keeps resolving
+    #[serde(default)]
+    pub sync_object_metadata: bool, // Also rewrite the R2 objects' metadata; an extra copy per object
     // Add other optional fields if needed for updates
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize_isrc() {
+        assert_eq!(normalize_isrc("usrc17607839").unwrap(), "USRC17607839");
+        assert_eq!(normalize_isrc(" US-RC1-76-07839 ").unwrap(), "USRC17607839");
+        assert!(normalize_isrc("US1234567890").is_err()); // Country code must be letters
+        assert!(normalize_isrc("USRC1760783").is_err());
+        assert!(normalize_isrc("USRC176078AB").is_err());
+    }
+
+    #[test]
+    fn test_validate_percentages() {
+        let names = vec!["Ann".to_string(), "Bo".to_string(), "Cy".to_string()];
+        let split = |shares: &[(&str, f32)]| shares.iter().map(|(name, share)| (name.to_string(), *share)).collect::<HashMap<_, _>>();
+
+        assert!(validate_percentages(&split(&[("Ann", 33.33), ("Bo", 33.33), ("Cy", 33.34)]), &names, "Writer").is_ok());
+        assert!(validate_percentages(&split(&[("Ann", 50.0), ("Bo", 49.95)]), &names, "Writer").is_ok());
+        assert!(validate_percentages(&HashMap::new(), &[], "Writer").is_ok());
+        assert_eq!(
+            validate_percentages(&split(&[("Ann", 50.0), ("Bo", 40.0)]), &names, "Writer").unwrap_err(),
+            "Writer percentages must add up to 100, got 90"
+        );
+        assert_eq!(
+            validate_percentages(&split(&[("Ann", 50.0), ("Dee", 50.0)]), &names, "Publisher").unwrap_err(),
+            "Publisher percentages given for names not in the publisher list: Dee"
+        );
+        assert!(validate_percentages(&split(&[("Ann", 150.0), ("Bo", -50.0)]), &names, "Writer").is_err());
+    }
+}
@@ -1,19 +1,34 @@
 use mongodb::{
     bson::{self, doc, Document, to_bson}, // Add bson module import
-    options::{ClientOptions, IndexOptions, FindOptions},
+    options::{AggregateOptions, ClientOptions, IndexOptions, FindOptions},
     IndexModel,
     Client, Collection, Database,
 };
 use futures_util::stream::TryStreamExt;
 use serde::{Deserialize, Serialize}; // Serialize is needed here for derive macros
+use ts_rs::TS;
 use std::error::Error;
 use std::sync::Arc;
 use log::{info, warn, error}; // Ensure error is imported
 use std::collections::HashMap;
-use tauri::State; // Import State for command arguments
-use crate::MongoState; // Import MongoState from lib.rs
+use tauri::{AppHandle, State, Wry}; // Import State for command arguments
+use crate::{MongoState, R2State}; // Import MongoState from lib.rs
 
 use super::UpdateTrackPayload; // Import from parent module (storage/mod.rs)
+use super::{id_filter, is_duplicate_key_error, validate_percentages};
+use crate::features::catalog::genres::normalize_genres;
+use crate::features::catalog::vocabulary::{apply_vocabulary, load_vocabulary};
+use crate::features::activity::{record_activity, ActivityAction, ActivityEntry};
+use crate::features::catalog::album_names::{resolve_album_names, AlbumNameCache};
+use crate::features::catalog::custom_fields::{custom_fields_filter, validate_custom_fields};
+use crate::features::catalog::object_metadata::{sync_object_metadata, touches_synced_fields};
+use crate::core::r2::R2Client;
+use crate::features::catalog::slugs::{is_slug_conflict, regenerated_slug_fields};
+use crate::features::catalog::trash::exclude_trashed;
+use crate::features::catalog::staging::exclude_staged;
+use crate::features::catalog::changes::{create_change_indexes, record_deletions, stamp, touched, CatalogKind};
+use crate::core::timing::{with_timeout, TimeoutSettings};
+use crate::core::db_config::{self, cursor_batch_size, CatalogCollections, CatalogDatabase};
 
 use self::error::CommandError;
 
@@ -23,12 +38,16 @@ mod error {
     // Ensure no 'use serde::Serialize;' is present here
     use serde::Serialize; // THIS LINE SHOULD BE REMOVED
 
+    /// Serialized like `crate::CommandError`, so the frontend reads both as its
+    /// generated `CommandError` type.
     #[derive(Debug, Serialize)] // This will use the top-level `use serde::Serialize`
+    #[serde(tag = "code", content = "message")]
     pub enum CommandError {
         Validation(String),
         Database(String),
         NotFound(String),
         Configuration(String), // Added for consistency
+        Unexpected(String),
     }
 
     // Implement Display for CommandError
@@ -39,6 +58,7 @@ mod error {
                 CommandError::Database(msg) => write!(f, "Database Error: {}", msg),
                 CommandError::NotFound(msg) => write!(f, "Not Found Error: {}", msg),
                 CommandError::Configuration(msg) => write!(f, "Configuration Error: {}", msg),
+                CommandError::Unexpected(msg) => write!(f, "Unexpected Error: {}", msg),
             }
         }
     }
@@ -46,6 +66,12 @@ mod error {
     // Implement Error for CommandError
     impl std::error::Error for CommandError {}
 
+    impl From<crate::core::timing::OperationTimedOut> for CommandError {
+        fn from(timed_out: crate::core::timing::OperationTimedOut) -> Self {
+            CommandError::Unexpected(timed_out.to_string())
+        }
+    }
+
 }
 
 
@@ -89,7 +115,8 @@ pub struct Track {
 }
 
 // Track list response structure for returning track data with album details
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, TS)]
+#[ts(export)]
 pub struct TrackWithAlbum {
     pub id: String, // Use 'id' consistent with frontend expectations
     pub title: String,
@@ -107,15 +134,25 @@ pub struct TrackWithAlbum {
     pub path: String, // Keep path as string (R2 key)
     pub waveform_data: Option<Vec<f32>>,
     pub comments: Option<String>, // Added comments field
+    pub custom_fields: Option<HashMap<String, String>>,
+    pub bpm: Option<f32>,
+    pub musical_key: Option<String>,
+    pub loudness_lufs: Option<f64>, // Integrated loudness, see compute_replaygain
+    pub replaygain_db: Option<f64>, // Gain to the ReplayGain reference level
+    pub isrc: Option<String>,
+    pub catalog_number: Option<String>,
+    pub slug: Option<String>, // URL-safe id for the public catalog site
 }
 
 
 // Track list response
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, TS)]
+#[ts(export)]
 pub struct TrackListResponse {
     pub success: bool,
     pub message: Option<String>,
     pub tracks: Vec<TrackWithAlbum>,
+    #[ts(type = "number")]
     pub total_count: usize,
 }
 
@@ -137,6 +174,14 @@ pub struct TrackDocument {
     pub path: String, // Path to medium quality file in R2
     pub waveform_data: Option<Vec<f32>>,
     pub comments: Option<String>, // Added comments field
+    pub custom_fields: Option<HashMap<String, String>>,
+    pub bpm: Option<f32>,
+    pub musical_key: Option<String>,
+    pub loudness_lufs: Option<f64>, // Unset until measured at upload or by the backfill
+    pub replaygain_db: Option<f64>,
+    pub isrc: Option<String>,
+    pub catalog_number: Option<String>,
+    pub slug: Option<String>, // Unset until the slug backfill has run
 }
 
 // MongoDB Client wrapper (No longer needed directly in commands)
@@ -159,10 +204,16 @@ pub struct DbResponse<T> {
 // Initialize the MongoDB client (This logic is likely handled in main.rs now)
 // pub async fn initialize_mongo_client(...) -> Result<Arc<MongoClient>, Box<dyn Error + Send + Sync>> { ... }
 
-// Create necessary indexes for efficient searching
-async fn create_indexes(db: &Database) -> Result<(), Box<dyn Error + Send + Sync>> {
+// Create necessary indexes for efficient searching. Run through `run_migrations`, which
+// merges duplicate albums first so the unique album index can be built.
+/// Creates the catalog indexes. A failing index (e.g. a unique one over existing
+/// duplicates) is logged and skipped so the ones after it are still created; the error
+/// counts the failures.
+pub async fn create_indexes(db: &Database) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let mut failed = 0;
+
     // Create text index on track title
-    let tracks_collection: Collection<Document> = db.collection("tracks");
+    let tracks_collection: Collection<Document> = db.tracks();
     let track_index_options = IndexOptions::builder()
         .build();
 
@@ -179,10 +230,10 @@ async fn create_indexes(db: &Database) -> Result<(), Box<dyn Error + Send + Sync
         .options(track_index_options)
         .build();
 
-    tracks_collection.create_index(track_index_model, None).await?;
+    create_index_logged(&tracks_collection, track_index_model, &mut failed).await;
 
     // Create text index on album name
-    let albums_collection: Collection<Document> = db.collection("albums");
+    let albums_collection: Collection<Document> = db.albums();
     let album_index_options = IndexOptions::builder()
         .build();
 
@@ -194,35 +245,125 @@ async fn create_indexes(db: &Database) -> Result<(), Box<dyn Error + Send + Sync
         .options(album_index_options)
         .build();
 
-    albums_collection.create_index(album_index_model, None).await?;
+    create_index_logged(&albums_collection, album_index_model, &mut failed).await;
+
+    // One album per name and artist, so concurrent uploads of a new album resolve to the
+    // same document. Albums created for an explicit id carry their own `grouping_key` and
+    // may share a name; albums matched by name leave it unset.
+    // The earlier {name, artist} index would reject those; dropping it fails harmlessly once gone
+    let _ = albums_collection.drop_index("name_1_artist_1", None).await;
+    let album_identity_index = IndexModel::builder()
+        .keys(doc! { "name": 1, "artist": 1, "grouping_key": 1 })
+        .options(IndexOptions::builder().unique(true).build())
+        .build();
+
+    create_index_logged(&albums_collection, album_identity_index, &mut failed).await;
 
     // Create index for album_id in tracks
     let album_track_relation_index = IndexModel::builder()
         .keys(doc! { "album_id": 1 })
         .build();
 
-    tracks_collection.create_index(album_track_relation_index, None).await?;
+    create_index_logged(&tracks_collection, album_track_relation_index, &mut failed).await;
 
+    // Wildcard index so filters on any custom field key can use an index
+    let custom_fields_index = IndexModel::builder()
+        .keys(doc! { "custom_fields.$**": 1 })
+        .build();
+
+    create_index_logged(&tracks_collection, custom_fields_index, &mut failed).await;
+
+    // Indexes for the common `search_catalog` filter combinations. Still unindexed:
+    // - range filters combined with a text query (only the text index is used, the
+    //   ranges are applied to the text matches)
+    // - `has_writers` and custom field filters combined with other filters (the
+    //   wildcard index serves one custom field on its own)
+    // - genre facet counts, which scan every track matching the filter
+    let search_indexes = [
+        doc! { "genre": 1, "bpm": 1 },
+        doc! { "genre": 1, "duration": 1 },
+        doc! { "bpm": 1, "duration": 1 },
+        doc! { "duration": 1 },
+        doc! { "album_id": 1, "track_number": 1 },
+        // fetch_all_tracks sorts; duration and album grouping are covered above
+        doc! { "title": 1 },
+        doc! { "track_number": 1 },
+        doc! { "date_added": 1 },
+        // Trash listing and expiry
+        doc! { "deleted_at": 1 },
+        doc! { "catalog_number": 1 },
+        // top_tracks
+        doc! { "stats.play_count": -1 },
+        doc! { "stats.download_count": -1 },
+    ];
+    for keys in search_indexes {
+        create_index_logged(&tracks_collection, IndexModel::builder().keys(keys).build(), &mut failed).await;
+    }
+
+    // An ISRC identifies one recording. Sparse, so tracks without one don't collide;
+    // writers must leave the field out rather than store null.
+    let isrc_index = IndexModel::builder()
+        .keys(doc! { "isrc": 1 })
+        .options(IndexOptions::builder().unique(true).sparse(true).build())
+        .build();
+    create_index_logged(&tracks_collection, isrc_index, &mut failed).await;
+
+    // Slugs are public URLs, so each names one track or album. Sparse so documents the
+    // backfill hasn't reached yet don't collide; old slugs are looked up in `previous_slugs`.
+    for collection in [&tracks_collection, &albums_collection] {
+        let slug_index = IndexModel::builder()
+            .keys(doc! { "slug": 1 })
+            .options(IndexOptions::builder().unique(true).sparse(true).build())
+            .build();
+        create_index_logged(collection, slug_index, &mut failed).await;
+        create_index_logged(collection, IndexModel::builder().keys(doc! { "previous_slugs": 1 }).build(), &mut failed).await;
+    }
+
+    // `updated_at` queries and tombstones for incremental refreshes
+    if let Err(e) = create_change_indexes(db).await {
+        warn!("Failed to create change tracking indexes: {}", e);
+        failed += 1;
+    }
+
+    if failed > 0 {
+        return Err(format!("{} index(es) could not be created", failed).into());
+    }
     Ok(())
 }
 
+/// Creates one index, logging a failure and counting it in `failed` instead of
+/// returning it.
+async fn create_index_logged(collection: &Collection<Document>, index: IndexModel, failed: &mut usize) {
+    let keys = index.keys.clone();
+    if let Err(e) = collection.create_index(index, None).await {
+        warn!("Failed to create index {} on {}: {}", keys, collection.name(), e);
+        *failed += 1;
+    }
+}
+
 // Album CRUD operations (These are not commands, keep as helper functions if needed elsewhere)
 pub async fn create_album(
+    app_handle: &AppHandle<Wry>,
     db: &Database, // Accept &Database directly
     album_id: &str,
     album_data: Album,
 ) -> DbResponse<()> {
-    let collection = db.collection::<Document>("albums");
+    let collection = db.albums::<Document>();
     let mut doc = to_bson(&album_data).unwrap().as_document().unwrap().clone();
     doc.insert("_id", album_id);
+    stamp(&mut doc);
 
     match collection.insert_one(doc, None).await {
-        Ok(_) => DbResponse {
-            success: true,
-            message: Some("Album created successfully".to_string()),
-            id: Some(album_id.to_string()),
-            data: None,
-        },
+        Ok(_) => {
+            let summary = format!("Created album {}", album_data.name);
+            record_activity(app_handle, Some(db), ActivityEntry::new(ActivityAction::AlbumCreated, vec![album_id.to_string()], summary)).await;
+            DbResponse {
+                success: true,
+                message: Some("Album created successfully".to_string()),
+                id: Some(album_id.to_string()),
+                data: None,
+            }
+        }
         Err(e) => DbResponse {
             success: false,
             message: Some(format!("Failed to create album: {}", e)),
@@ -233,7 +374,7 @@ pub async fn create_album(
 }
 
 pub async fn get_album(db: &Database, album_id: &str) -> DbResponse<Album> {
-    let collection = db.collection::<Document>("albums");
+    let collection = db.albums::<Document>();
     match collection.find_one(doc! { "_id": album_id }, None).await {
         Ok(Some(album_doc)) => {
             match mongodb::bson::from_document::<Album>(album_doc) {
@@ -268,25 +409,50 @@ pub async fn get_album(db: &Database, album_id: &str) -> DbResponse<Album> {
 
 
 pub async fn update_album(
+    app_handle: &AppHandle<Wry>,
     db: &Database,
+    album_cache: &AlbumNameCache,
     album_id: &str,
     album_data: Album,
+    regenerate_slug: bool, // New slug from the (new) name; the old one keeps resolving
 ) -> DbResponse<()> {
-    let collection = db.collection::<Document>("albums");
-    let update_doc = to_bson(&album_data).unwrap();
+    let collection = db.albums::<Document>();
+    let mut update_doc = to_bson(&album_data).unwrap().as_document().unwrap().clone();
+    let mut update = Document::new();
+
+    let filter = id_filter(album_id);
+
+    if regenerate_slug {
+        let artist = match collection.find_one(filter.clone(), None).await {
+            Ok(album_doc) => album_doc.and_then(|album_doc| album_doc.get_str("artist").ok().map(String::from)).unwrap_or_default(),
+            Err(e) => return DbResponse { success: false, message: Some(format!("Failed to load album: {}", e)), id: None, data: None },
+        };
+        let id = filter.get("_id").cloned().unwrap_or(bson::Bson::Null);
+        match regenerated_slug_fields(&collection, &id, &album_data.name, &artist).await {
+            Ok((slug_fields, previous_slug)) => {
+                update_doc.extend(slug_fields);
+                if !previous_slug.is_empty() {
+                    update.insert("$addToSet", previous_slug);
+                }
+            }
+            Err(e) => return DbResponse { success: false, message: Some(format!("Failed to generate slug: {}", e)), id: None, data: None },
+        }
+    }
+    update.insert("$set", update_doc);
 
     match collection
         .update_one(
-            doc! { "_id": album_id },
-            doc! {
-                "$set": update_doc
-            },
+            filter,
+            touched(update),
             None,
         )
         .await
     {
         Ok(result) => {
             if result.matched_count > 0 {
+                album_cache.invalidate(album_id); // The name may have changed
+                let summary = format!("Updated album {}", album_data.name);
+                record_activity(app_handle, Some(db), ActivityEntry::new(ActivityAction::AlbumUpdated, vec![album_id.to_string()], summary)).await;
                 DbResponse {
                     success: true,
                     message: Some("Album updated successfully".to_string()),
@@ -311,11 +477,15 @@ pub async fn update_album(
     }
 }
 
-pub async fn delete_album(db: &Database, album_id: &str) -> DbResponse<()> {
-    let collection = db.collection::<Document>("albums");
+pub async fn delete_album(app_handle: &AppHandle<Wry>, db: &Database, album_cache: &AlbumNameCache, album_id: &str) -> DbResponse<()> {
+    let collection = db.albums::<Document>();
     match collection.delete_one(doc! { "_id": album_id }, None).await {
         Ok(result) => {
             if result.deleted_count > 0 {
+                album_cache.invalidate(album_id);
+                record_deletions(db, CatalogKind::Album, vec![album_id.to_string()]).await;
+                let summary = format!("Deleted album {}", album_id);
+                record_activity(app_handle, Some(db), ActivityEntry::new(ActivityAction::AlbumDeleted, vec![album_id.to_string()], summary)).await;
                 DbResponse {
                     success: true,
                     message: Some("Album deleted successfully".to_string()),
@@ -346,9 +516,10 @@ pub async fn create_track(
     track_id: &str,
     track_data: Track,
 ) -> DbResponse<()> {
-    let collection = db.collection::<Document>("tracks");
+    let collection = db.tracks::<Document>();
     let mut doc = to_bson(&track_data).unwrap().as_document().unwrap().clone();
     doc.insert("_id", track_id);
+    stamp(&mut doc);
 
     match collection.insert_one(doc, None).await {
         Ok(_) => DbResponse {
@@ -367,7 +538,7 @@ pub async fn create_track(
 }
 
 pub async fn get_track(db: &Database, track_id: &str) -> DbResponse<Track> {
-    let collection = db.collection::<Document>("tracks");
+    let collection = db.tracks::<Document>();
     match collection.find_one(doc! { "_id": track_id }, None).await {
         Ok(Some(track_doc)) => {
              match mongodb::bson::from_document::<Track>(track_doc) {
@@ -405,15 +576,15 @@ pub async fn update_track(
     track_id: &str,
     track_data: Track,
 ) -> DbResponse<()> {
-    let collection = db.collection::<Document>("tracks");
+    let collection = db.tracks::<Document>();
     let update_doc = to_bson(&track_data).unwrap();
 
     match collection
         .update_one(
             doc! { "_id": track_id },
-            doc! {
+            touched(doc! {
                 "$set": update_doc
-            },
+            }),
             None,
         )
         .await
@@ -445,10 +616,11 @@ pub async fn update_track(
 }
 
 pub async fn delete_track(db: &Database, track_id: &str) -> DbResponse<()> {
-    let collection = db.collection::<Document>("tracks");
+    let collection = db.tracks::<Document>();
     match collection.delete_one(doc! { "_id": track_id }, None).await {
         Ok(result) => {
             if result.deleted_count > 0 {
+                record_deletions(db, CatalogKind::Track, vec![track_id.to_string()]).await;
                 DbResponse {
                     success: true,
                     message: Some("Track deleted successfully".to_string()),
@@ -476,23 +648,43 @@ pub async fn delete_track(db: &Database, track_id: &str) -> DbResponse<()> {
 // --- Functions `delete_tracks_by_ids` and `replace_track_audio` moved to `catalog_storage_actions.rs` ---
 
 
+/// Album name to show for a track, given names resolved through the album name cache.
+fn album_name_for(album_names: &HashMap<String, String>, album_id: &str) -> String {
+    if album_id.is_empty() {
+        return "No Album ID".to_string();
+    }
+    album_names.get(album_id).cloned().unwrap_or_else(|| "Unknown Album".to_string())
+}
+
+/// Resolves album names for a page of tracks, logging (not failing) on database errors.
+async fn album_names_for_tracks(db: &Database, album_cache: &AlbumNameCache, tracks: &[TrackDocument]) -> HashMap<String, String> {
+    let album_ids: Vec<String> = tracks.iter().map(|track| track.album_id.clone()).collect();
+    resolve_album_names(db, album_cache, &album_ids).await.unwrap_or_else(|e| {
+        error!("Failed to resolve album names: {}", e);
+        HashMap::new()
+    })
+}
+
 // Search tracks based on a query string (Not a command, keep as helper)
 pub async fn search_tracks(
     db: &Database,
+    album_cache: &AlbumNameCache,
     query: &str,
     limit: Option<i64>,
     skip: Option<i64>,
 ) -> TrackListResponse {
     info!("Searching tracks with query: {}", query);
-    let tracks_collection: Collection<Document> = db.collection("tracks");
-    let albums_collection: Collection<Document> = db.collection("albums");
+    let tracks_collection: Collection<Document> = db.tracks();
 
     // Basic text search filter
-    let filter = doc! { "$text": { "$search": query } };
+    let mut filter = doc! { "$text": { "$search": query } };
+    exclude_trashed(&mut filter);
+    exclude_staged(&mut filter);
 
     let find_options = FindOptions::builder()
         .limit(limit)
         .skip(skip.map(|s| s as u64))
+        .batch_size(cursor_batch_size())
         .build();
 
     // Get total count matching the search query
@@ -512,44 +704,19 @@ pub async fn search_tracks(
         }
     };
 
-    let mut tracks_with_album: Vec<TrackWithAlbum> = Vec::new();
+    let mut track_docs: Vec<TrackDocument> = Vec::new();
     while let Ok(Some(track_doc)) = cursor.try_next().await {
-        let track_data = match mongodb::bson::from_document::<TrackDocument>(track_doc.clone()) {
-             Ok(data) => data,
-             Err(e) => {
-                 warn!("Failed to deserialize track document during search: {}. Doc: {:?}", e, track_doc);
-                 continue;
-             }
-         };
-
-        let album_name = if !track_data.album_id.is_empty() {
-            let album_filter = doc! { "_id": &track_data.album_id };
-            match albums_collection.find_one(album_filter, None).await {
-                Ok(Some(album_doc)) => album_doc.get_str("name").unwrap_or("Unknown Album").to_string(),
-                _ => "Unknown Album".to_string(),
-            }
-        } else {
-            "No Album ID".to_string()
-        };
+        match mongodb::bson::from_document::<TrackDocument>(track_doc.clone()) {
+             Ok(data) => track_docs.push(data),
+             Err(e) => warn!("Failed to deserialize track document during search: {}. Doc: {:?}", e, track_doc),
+         }
+    }
+    let album_names = album_names_for_tracks(db, album_cache, &track_docs).await;
 
-        tracks_with_album.push(TrackWithAlbum {
-            id: track_data._id,
-            title: track_data.title,
-            album_id: track_data.album_id,
-            album_name,
-            track_number: track_data.track_number,
-            filename: track_data.filename,
-            duration: Some(track_data.duration),
-            writers: track_data.writers,
-            writer_percentages: track_data.writer_percentages,
-            publishers: track_data.publishers,
-            publisher_percentages: track_data.publisher_percentages,
-            composers: track_data.composers,
-            genre: track_data.genre,
-            path: track_data.path,
-            waveform_data: track_data.waveform_data,
-            comments: track_data.comments,
-        });
+    let mut tracks_with_album: Vec<TrackWithAlbum> = Vec::new();
+    for track_data in track_docs {
+        let album_name = album_name_for(&album_names, &track_data.album_id);
+        tracks_with_album.push(track_data.into_track_with_album(album_name));
     }
 
     TrackListResponse { success: true, message: None, tracks: tracks_with_album, total_count }
@@ -563,13 +730,14 @@ pub async fn search_albums(
     skip: Option<i64>,
 ) -> DbResponse<Vec<Album>> {
     info!("Searching albums with query: {}", query);
-    let albums_collection: Collection<Document> = db.collection("albums");
+    let albums_collection: Collection<Document> = db.albums();
 
     let filter = doc! { "$text": { "$search": query } };
 
     let find_options = FindOptions::builder()
         .limit(limit)
         .skip(skip.map(|s| s as u64))
+        .batch_size(cursor_batch_size())
         .build();
 
     match albums_collection.find(filter, find_options).await {
@@ -600,18 +768,243 @@ pub async fn search_albums(
     }
 }
 
+/// Filters for `search_catalog`. Every field is optional and set fields are combined with AND.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+#[serde(default)]
+pub struct SearchFilter {
+    /// Full-text query over title, genre, writers, publishers, instruments, and mood
+    pub text: Option<String>,
+    /// Matches tracks with any of these genres
+    pub genres: Vec<String>,
+    /// Duration bounds in seconds, inclusive
+    pub duration_min: Option<f64>,
+    pub duration_max: Option<f64>,
+    pub bpm_min: Option<f64>,
+    pub bpm_max: Option<f64>,
+    /// `Some(false)` finds tracks with no writers credited
+    pub has_writers: Option<bool>,
+    pub album_id: Option<String>,
+    /// Exact matches on `custom_fields.<key>`
+    pub custom_fields: HashMap<String, String>,
+    /// Exact match, in any accepted ISRC form
+    pub isrc: Option<String>,
+    pub catalog_number: Option<String>,
+}
+
+/// Sort order for `search_catalog` and `fetch_album_summaries`. Without one, text
+/// searches sort by relevance, other track searches by title, and albums by name.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SearchSort {
+    pub field: String,
+    #[serde(default)]
+    pub descending: bool,
+}
+
+/// Fields `search_catalog` can sort by.
+const SEARCH_SORT_FIELDS: [&str; 6] = ["title", "duration", "bpm", "date_added", "track_number", "musical_key"];
+const DEFAULT_SEARCH_PAGE_SIZE: u64 = 50;
+const MAX_SEARCH_PAGE_SIZE: u64 = 500;
+
+/// Number of matching tracks with a given facet value.
+#[derive(Debug, Serialize, Clone, PartialEq)]
+pub struct FacetCount {
+    pub value: String,
+    pub count: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SearchResult {
+    pub tracks: Vec<TrackWithAlbum>,
+    pub total_count: u64,
+    /// Per-genre counts over all tracks matching the filter, most common first
+    pub genre_facets: Vec<FacetCount>,
+}
+
+/// Adds `{ field: { $gte: min, $lte: max } }` for whichever bounds are set.
+fn insert_range(filter: &mut Document, field: &str, min: Option<f64>, max: Option<f64>) -> Result<(), String> {
+    if let (Some(min), Some(max)) = (min, max) {
+        if min > max {
+            return Err(format!("{} minimum ({}) is greater than its maximum ({})", field, min, max));
+        }
+    }
+    let mut range = Document::new();
+    if let Some(min) = min { range.insert("$gte", min); }
+    if let Some(max) = max { range.insert("$lte", max); }
+    if !range.is_empty() {
+        filter.insert(field, range);
+    }
+    Ok(())
+}
+
+/// Compiles a `SearchFilter` into a `tracks` query.
+fn build_search_filter(search: &SearchFilter) -> Result<Document, String> {
+    let mut filter = custom_fields_filter(&search.custom_fields)?;
+    if let Some(text) = search.text.as_deref().map(str::trim).filter(|text| !text.is_empty()) {
+        filter.insert("$text", doc! { "$search": text });
+    }
+    if !search.genres.is_empty() {
+        filter.insert("genre", doc! { "$in": &search.genres });
+    }
+    insert_range(&mut filter, "duration", search.duration_min, search.duration_max)?;
+    insert_range(&mut filter, "bpm", search.bpm_min, search.bpm_max)?;
+    if let Some(has_writers) = search.has_writers {
+        filter.insert("writers.0", doc! { "$exists": has_writers });
+    }
+    if let Some(album_id) = &search.album_id {
+        // Tracks store album_id as an ObjectId, older ones as a string
+        let mut ids = vec![bson::Bson::String(album_id.clone())];
+        if let Ok(oid) = bson::oid::ObjectId::parse_str(album_id) {
+            ids.push(bson::Bson::ObjectId(oid));
+        }
+        filter.insert("album_id", doc! { "$in": ids });
+    }
+    if let Some(isrc) = search.isrc.as_deref().map(str::trim).filter(|isrc| !isrc.is_empty()) {
+        filter.insert("isrc", super::normalize_isrc(isrc)?);
+    }
+    if let Some(catalog_number) = search.catalog_number.as_deref().map(str::trim).filter(|number| !number.is_empty()) {
+        filter.insert("catalog_number", catalog_number);
+    }
+    Ok(filter)
+}
+
+fn search_sort_doc(sort: Option<&SearchSort>, has_text: bool) -> Result<Document, String> {
+    match sort {
+        Some(sort) if SEARCH_SORT_FIELDS.contains(&sort.field.as_str()) => {
+            let field = sort.field.as_str();
+            let direction = if sort.descending { -1 } else { 1 };
+            // _id keeps pages stable when many tracks share a value
+            Ok(doc! { field: direction, "_id": 1 })
+        }
+        Some(sort) => Err(format!("Cannot sort by '{}'; expected one of {}", sort.field, SEARCH_SORT_FIELDS.join(", "))),
+        None if has_text => Ok(doc! { "_score": -1, "_id": 1 }),
+        None => Ok(doc! { "title": 1, "_id": 1 }),
+    }
+}
+
+/// Builds the aggregation returning one page of tracks, the total count, and genre facet
+/// counts in a single round trip.
+fn search_pipeline(filter: Document, sort: Document, skip: u64, limit: u64) -> Vec<Document> {
+    let has_text = filter.contains_key("$text");
+    let (skip, limit) = (skip as i64, limit as i64);
+    let mut pipeline = vec![doc! { "$match": filter }];
+    if has_text {
+        pipeline.push(doc! { "$addFields": { "_score": { "$meta": "textScore" } } });
+    }
+    pipeline.push(doc! { "$facet": {
+        "tracks": [
+            { "$sort": sort },
+            { "$skip": skip },
+            { "$limit": limit },
+        ],
+        "total": [{ "$count": "count" }],
+        "genres": [
+            { "$unwind": "$genre" },
+            { "$group": { "_id": "$genre", "count": { "$sum": 1 } } },
+            { "$sort": { "count": -1, "_id": 1 } },
+        ],
+    } });
+    pipeline
+}
+
+/// Reads a `$count`/`$sum` result, which the server returns as either int32 or int64.
+fn count_value(count_doc: &Document, field: &str) -> u64 {
+    match count_doc.get(field) {
+        Some(bson::Bson::Int32(n)) => *n as u64,
+        Some(bson::Bson::Int64(n)) => *n as u64,
+        _ => 0,
+    }
+}
+
+/// Searches tracks with structured filters and returns one page plus genre facet counts - TAURI COMMAND
+/// `page` is zero-based; `page_size` defaults to 50 and is capped at 500.
+#[tauri::command]
+pub async fn search_catalog(
+    mongo_state: State<'_, MongoState>,
+    timeout_settings: State<'_, TimeoutSettings>,
+    album_cache: State<'_, AlbumNameCache>,
+    filter: SearchFilter,
+    sort: Option<SearchSort>,
+    page: Option<u64>,
+    page_size: Option<u64>,
+) -> Result<SearchResult, CommandError> {
+    let timeout = timeout_settings.get().query();
+    with_timeout("search_catalog", timeout, async {
+        let mut query = build_search_filter(&filter).map_err(CommandError::Validation)?;
+        exclude_trashed(&mut query);
+        exclude_staged(&mut query);
+        let sort_doc = search_sort_doc(sort.as_ref(), query.contains_key("$text")).map_err(CommandError::Validation)?;
+        let page_size = page_size.unwrap_or(DEFAULT_SEARCH_PAGE_SIZE).clamp(1, MAX_SEARCH_PAGE_SIZE);
+        let skip = page.unwrap_or(0).saturating_mul(page_size);
+        info!("search_catalog command: filter={:?}, sort={:?}, skip={}, limit={}", query, sort_doc, skip, page_size);
+
+        let client_lock = mongo_state.client.lock().await;
+        let client = match client_lock.as_ref() {
+            Some(c) => c,
+            None => {
+                error!("search_catalog command: MongoDB client not initialized");
+                return Err(CommandError::Configuration("MongoDB client not initialized".to_string()));
+            }
+        };
+        let db = client.catalog_database();
+
+        let pipeline = search_pipeline(query, sort_doc, skip, page_size);
+        // Facet stages over large catalogs can exceed the in-memory sort limit
+        let options = AggregateOptions::builder().allow_disk_use(true).batch_size(cursor_batch_size()).build();
+        let results: Vec<Document> = db.tracks::<Document>()
+            .aggregate(pipeline, options)
+            .await
+            .map_err(|e| CommandError::Database(format!("Search failed: {}", e)))?
+            .try_collect()
+            .await
+            .map_err(|e| CommandError::Database(format!("Search failed: {}", e)))?;
+        let facets = results.into_iter().next().unwrap_or_default();
+
+        let total_count = facets.get_array("total").ok()
+            .and_then(|total| total.first())
+            .and_then(|count| count.as_document())
+            .map_or(0, |count_doc| count_value(count_doc, "count"));
+        let genre_facets = facets.get_array("genres").map(|genres| {
+            genres.iter()
+                .filter_map(|genre| genre.as_document())
+                .filter_map(|genre_doc| Some(FacetCount {
+                    value: genre_doc.get_str("_id").ok()?.to_string(),
+                    count: count_value(genre_doc, "count"),
+                }))
+                .collect()
+        }).unwrap_or_default();
+
+        let mut track_docs: Vec<TrackDocument> = Vec::new();
+        for track_doc in facets.get_array("tracks").map(|tracks| tracks.as_slice()).unwrap_or_default() {
+            let Some(track_doc) = track_doc.as_document() else { continue };
+            match parse_track_document(track_doc.clone()) {
+                Ok(track) => track_docs.push(track),
+                Err(e) => warn!("search_catalog command: Failed to deserialize track doc: {}. Doc: {:?}", e, track_doc),
+            }
+        }
+        let album_names = album_names_for_tracks(&db, &album_cache, &track_docs).await;
+        let tracks = track_docs.into_iter()
+            .map(|track| {
+                let album_name = album_name_for(&album_names, &track.album_id);
+                track.into_track_with_album(album_name)
+            })
+            .collect();
+
+        Ok(SearchResult { tracks, total_count, genre_facets })
+    }).await
+}
+
 // Get all tracks associated with a specific album ID (Not a command, keep as helper)
 pub async fn get_tracks_by_album(
     db: &Database,
+    album_cache: &AlbumNameCache,
     album_id: &str,
 ) -> TrackListResponse {
     info!("Fetching tracks for album_id: {}", album_id);
-    let tracks_collection: Collection<Document> = db.collection("tracks");
-    let albums_collection: Collection<Document> = db.collection("albums");
+    let tracks_collection: Collection<Document> = db.tracks();
 
     // Fetch album name first
-    let album_name = match albums_collection.find_one(doc! { "_id": album_id }, None).await {
-        Ok(Some(album_doc)) => album_doc.get_str("name").unwrap_or("Unknown Album").to_string(),
+    let album_name = match resolve_album_names(db, album_cache, &[album_id.to_string()]).await {
+        Ok(mut names) if names.contains_key(album_id) => names.remove(album_id).unwrap_or_default(),
         _ => {
             warn!("Album {} not found when fetching tracks by album", album_id);
             "Unknown Album".to_string()
@@ -619,7 +1012,7 @@ pub async fn get_tracks_by_album(
     };
 
     let filter = doc! { "album_id": album_id };
-    let find_options = FindOptions::builder().sort(doc! { "track_number": 1 }).build(); // Sort by track number
+    let find_options = FindOptions::builder().sort(doc! { "track_number": 1 }).batch_size(cursor_batch_size()).build(); // Sort by track number
 
     // Get total count for this album
     let total_count = match tracks_collection.count_documents(filter.clone(), None).await {
@@ -648,24 +1041,7 @@ pub async fn get_tracks_by_album(
              }
          };
 
-        tracks_with_album.push(TrackWithAlbum {
-            id: track_data._id,
-            title: track_data.title,
-            album_id: track_data.album_id,
-            album_name: album_name.clone(), // Use fetched album name
-            track_number: track_data.track_number,
-            filename: track_data.filename,
-            duration: Some(track_data.duration),
-            writers: track_data.writers,
-            writer_percentages: track_data.writer_percentages,
-            publishers: track_data.publishers,
-            publisher_percentages: track_data.publisher_percentages,
-            composers: track_data.composers,
-            genre: track_data.genre,
-            path: track_data.path,
-            waveform_data: track_data.waveform_data,
-            comments: track_data.comments,
-        });
+        tracks_with_album.push(track_data.into_track_with_album(album_name.clone())); // Use fetched album name
     }
 
     TrackListResponse { success: true, message: None, tracks: tracks_with_album, total_count }
@@ -673,8 +1049,9 @@ pub async fn get_tracks_by_album(
 
 // Get all albums (Not a command, keep as helper)
 pub async fn get_all_albums(db: &Database) -> DbResponse<Vec<Album>> {
-    let collection = db.collection::<Document>("albums");
-    match collection.find(None, None).await {
+    let collection = db.albums::<Document>();
+    let find_options = FindOptions::builder().batch_size(cursor_batch_size()).build();
+    match collection.find(None, find_options).await {
         Ok(mut cursor) => {
             let mut albums: Vec<Album> = Vec::new();
             while let Ok(Some(album_doc)) = cursor.try_next().await {
@@ -702,139 +1079,464 @@ pub async fn get_all_albums(db: &Database) -> DbResponse<Vec<Album>> {
     }
 }
 
-// Fetch all tracks with pagination and sorting - TAURI COMMAND
+/// An album with counts computed from the tracks that point at it.
+#[derive(Debug, Serialize, Clone, PartialEq)]
+pub struct AlbumSummary {
+    pub id: String,
+    pub name: String,
+    pub artist: Option<String>,
+    pub year: Option<i32>,
+    pub genres: Vec<String>,
+    pub track_count: u64,
+    pub total_duration_secs: f64,
+    /// R2 key of the album art (`art_path` on the album document)
+    pub art_key: Option<String>,
+    /// The album's `track_ids` array lists different tracks than those whose
+    /// `album_id` points at it. Albums without a `track_ids` array are never stale.
+    pub track_ids_stale: bool,
+    /// URL-safe id for the public catalog site
+    pub slug: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct AlbumSummaryPage {
+    pub albums: Vec<AlbumSummary>,
+    pub total_count: u64,
+}
+
+/// Album fields `fetch_album_summaries` can sort by. The first three are stored on the
+/// album; the rest are computed from its tracks.
+const ALBUM_SORT_FIELDS: [&str; 5] = ["name", "artist", "year", "track_count", "total_duration_secs"];
+const STORED_ALBUM_SORT_FIELDS: [&str; 3] = ["name", "artist", "year"];
+
+/// Builds the album summary aggregation. Sorting by a stored field pages before the
+/// `$lookup`, so only one page of albums is joined; sorting by a computed field has to
+/// join every album first.
+pub(crate) fn album_summary_pipeline(sort: Option<&SearchSort>, skip: u64, limit: u64) -> Result<Vec<Document>, String> {
+    let (field, descending) = match sort {
+        Some(sort) if ALBUM_SORT_FIELDS.contains(&sort.field.as_str()) => (sort.field.as_str(), sort.descending),
+        Some(sort) => return Err(format!("Cannot sort albums by '{}'; expected one of {}", sort.field, ALBUM_SORT_FIELDS.join(", "))),
+        None => ("name", false),
+    };
+    let direction = if descending { -1 } else { 1 };
+    let (skip, limit) = (skip as i64, limit as i64);
+    let page = [
+        doc! { "$sort": { field: direction, "_id": 1 } },
+        doc! { "$skip": skip },
+        doc! { "$limit": limit },
+    ];
+
+    let mut pipeline = Vec::new();
+    let sort_before_join = STORED_ALBUM_SORT_FIELDS.contains(&field);
+    if sort_before_join {
+        pipeline.extend(page.iter().cloned());
+    }
+    let tracks_collection_name = db_config::active().tracks_collection;
+    // Tracks reference albums by ObjectId, older ones by the hex string. One equality
+    // join per form, so both use the `album_id` index. Trashed and staged tracks don't
+    // count towards the album.
+    let mut listed = Document::new();
+    exclude_trashed(&mut listed);
+    exclude_staged(&mut listed);
+    pipeline.push(doc! { "$addFields": { "album_hex": { "$toString": "$_id" } } });
+    for (local_field, joined) in [("_id", "tracks_by_oid"), ("album_hex", "tracks_by_hex")] {
+        pipeline.push(doc! { "$lookup": {
+            "from": tracks_collection_name.as_str(),
+            "localField": local_field,
+            "foreignField": "album_id",
+            "pipeline": [{ "$match": listed.clone() }, { "$project": { "_id": 1, "duration": 1 } }],
+            "as": joined,
+        } });
+    }
+    pipeline.push(doc! { "$addFields": { "joined_tracks": { "$concatArrays": ["$tracks_by_oid", "$tracks_by_hex"] } } });
+    pipeline.push(doc! { "$project": {
+        "name": 1,
+        "artist": 1,
+        "year": 1,
+        "genres": 1,
+        "art_path": 1,
+        "slug": 1,
+        "track_count": { "$size": "$joined_tracks" },
+        "total_duration_secs": { "$sum": "$joined_tracks.duration" },
+        "track_ids_stale": { "$cond": {
+            "if": { "$isArray": "$track_ids" },
+            "then": { "$not": [{ "$setEquals": [
+                { "$map": { "input": "$track_ids", "in": { "$toString": "$$this" } } },
+                { "$map": { "input": "$joined_tracks", "in": { "$toString": "$$this._id" } } },
+            ] }] },
+            "else": false,
+        } },
+    } });
+    if !sort_before_join {
+        pipeline.extend(page.iter().cloned());
+    }
+    Ok(pipeline)
+}
+
+pub(crate) fn album_summary_from_document(album_doc: &Document) -> Option<AlbumSummary> {
+    let number = |field: &str| match album_doc.get(field) {
+        Some(bson::Bson::Int32(n)) => Some(*n as f64),
+        Some(bson::Bson::Int64(n)) => Some(*n as f64),
+        Some(bson::Bson::Double(n)) => Some(*n),
+        _ => None,
+    };
+    Some(AlbumSummary {
+        id: album_doc.get("_id").and_then(super::id_to_string)?,
+        name: album_doc.get_str("name").unwrap_or_default().to_string(),
+        artist: album_doc.get_str("artist").ok().map(String::from),
+        year: number("year").map(|year| year as i32),
+        genres: album_doc.get_array("genres")
+            .map(|genres| genres.iter().filter_map(|genre| genre.as_str().map(String::from)).collect())
+            .unwrap_or_default(),
+        track_count: number("track_count").unwrap_or(0.0) as u64,
+        total_duration_secs: number("total_duration_secs").unwrap_or(0.0),
+        art_key: album_doc.get_str("art_path").ok().filter(|key| !key.is_empty()).map(String::from),
+        track_ids_stale: album_doc.get_bool("track_ids_stale").unwrap_or(false),
+        slug: album_doc.get_str("slug").ok().map(String::from),
+    })
+}
+
+/// Lists albums with track counts and total durations from one aggregation - TAURI COMMAND
+/// `page` is zero-based; `page_size` defaults to 50 and is capped at 500.
 #[tauri::command]
-pub async fn fetch_all_tracks(
-    mongo_state: State<'_, MongoState>, // <-- Use State
-    sort_field: String, // Pass simple types directly
-    sort_direction: String,
-    limit: Option<i64>,
-    skip: Option<i64>,
-) -> Result<TrackListResponse, CommandError> { // <-- Return local CommandError
-    info!("fetch_all_tracks command: Starting with sort_field={}, sort_direction={}", sort_field, sort_direction);
+pub async fn fetch_album_summaries(
+    mongo_state: State<'_, MongoState>,
+    sort: Option<SearchSort>,
+    page: Option<u64>,
+    page_size: Option<u64>,
+) -> Result<AlbumSummaryPage, CommandError> {
+    let page_size = page_size.unwrap_or(DEFAULT_SEARCH_PAGE_SIZE).clamp(1, MAX_SEARCH_PAGE_SIZE);
+    let skip = page.unwrap_or(0).saturating_mul(page_size);
+    let pipeline = album_summary_pipeline(sort.as_ref(), skip, page_size).map_err(CommandError::Validation)?;
 
-    // Get Mongo client from state
     let client_lock = mongo_state.client.lock().await;
     let client = match client_lock.as_ref() {
         Some(c) => c,
         None => {
-            error!("fetch_all_tracks command: MongoDB client not initialized");
+            error!("fetch_album_summaries command: MongoDB client not initialized");
             return Err(CommandError::Configuration("MongoDB client not initialized".to_string()));
         }
     };
-    let db = client.database("music_library"); // Get Database instance
+    let albums_collection = client.catalog_database().albums::<Document>();
 
-    let tracks_collection: Collection<Document> = db.collection("tracks");
-    let albums_collection: Collection<Document> = db.collection("albums"); // Needed for album names
+    let total_count = albums_collection.count_documents(None, None).await
+        .map_err(|e| CommandError::Database(format!("Failed to count albums: {}", e)))?;
+    let options = AggregateOptions::builder().allow_disk_use(true).batch_size(cursor_batch_size()).build();
+    let album_docs: Vec<Document> = albums_collection.aggregate(pipeline, options)
+        .await
+        .map_err(|e| CommandError::Database(format!("Failed to summarize albums: {}", e)))?
+        .try_collect()
+        .await
+        .map_err(|e| CommandError::Database(format!("Failed to summarize albums: {}", e)))?;
 
-    // Determine sort order
+    let albums: Vec<AlbumSummary> = album_docs.iter().filter_map(album_summary_from_document).collect();
+    let stale = albums.iter().filter(|album| album.track_ids_stale).count();
+    if stale > 0 {
+        warn!("fetch_album_summaries command: {} albums on this page have stale track_ids", stale);
+    }
+    Ok(AlbumSummaryPage { albums, total_count })
+}
+
+/// Sort key ordering tracks by the name of their album, then by track number.
+const ALBUM_NAME_SORT_KEY: &str = "album_name";
+
+/// Sort keys `fetch_all_tracks` accepts, and the fields each sorts on. All but
+/// `album_name` are indexed track fields; album names aren't stored on tracks, so that
+/// key sorts on the name joined in by `album_name_sort_pipeline`.
+const TRACK_LIST_SORT_FIELDS: [(&str, &[&str]); 5] = [
+    ("title", &["title"]),
+    ("duration", &["duration"]),
+    ("track_number", &["track_number"]),
+    ("date_added", &["date_added"]),
+    (ALBUM_NAME_SORT_KEY, &["album_name", "track_number"]),
+];
+
+fn track_list_sort_doc(sort_field: &str, sort_direction: &str) -> Result<Document, CommandError> {
+    let (_, fields) = TRACK_LIST_SORT_FIELDS.iter().find(|(key, _)| *key == sort_field).ok_or_else(|| {
+        let keys: Vec<&str> = TRACK_LIST_SORT_FIELDS.iter().map(|(key, _)| *key).collect();
+        CommandError::Validation(format!("Cannot sort by '{}'; expected one of {}", sort_field, keys.join(", ")))
+    })?;
     let sort_order = if sort_direction == "desc" { -1 } else { 1 };
-    let sort_doc = doc! { sort_field: sort_order };
-    info!("fetch_all_tracks command: Using sort document: {:?}", sort_doc);
+    Ok(fields.iter().map(|field| (field.to_string(), bson::Bson::Int32(sort_order))).collect())
+}
 
-    let find_options = FindOptions::builder()
-        .sort(sort_doc)
-        .limit(limit)
-        .skip(skip.map(|s| s as u64))
-        .build();
+/// Pages the tracks matching `filter` by album name. Every matching track is joined to
+/// its album before sorting, so this is slower than the indexed sorts. Tracks reference
+/// albums by ObjectId, older ones by the hex string; one equality join covers each form.
+fn album_name_sort_pipeline(filter: Document, sort_doc: Document, skip: Option<i64>, limit: Option<i64>) -> Vec<Document> {
+    let albums_collection_name = db_config::active().albums_collection;
+    let mut pipeline = vec![
+        doc! { "$match": filter },
+        doc! { "$addFields": { "album_oid": { "$convert": { "input": "$album_id", "to": "objectId", "onError": null, "onNull": null } } } },
+    ];
+    for (local_field, joined) in [("album_id", "albums_by_id"), ("album_oid", "albums_by_oid")] {
+        pipeline.push(doc! { "$lookup": {
+            "from": albums_collection_name.as_str(),
+            "localField": local_field,
+            "foreignField": "_id",
+            "pipeline": [{ "$project": { "_id": 0, "name": 1 } }],
+            "as": joined,
+        } });
+    }
+    pipeline.push(doc! { "$addFields": { "album_name": {
+        "$arrayElemAt": [{ "$concatArrays": ["$albums_by_id.name", "$albums_by_oid.name"] }, 0],
+    } } });
+    let mut sort_doc = sort_doc;
+    sort_doc.insert("_id", 1);
+    pipeline.push(doc! { "$sort": sort_doc });
+    if let Some(skip) = skip.filter(|skip| *skip > 0) {
+        pipeline.push(doc! { "$skip": skip });
+    }
+    if let Some(limit) = limit.filter(|limit| *limit > 0) {
+        pipeline.push(doc! { "$limit": limit });
+    }
+    // Notes are loaded per track with get_track_notes
+    pipeline.push(doc! { "$project": { "notes": 0, "album_oid": 0, "albums_by_id": 0, "albums_by_oid": 0, "album_name": 0 } });
+    pipeline
+}
 
-    // Get total count first for pagination
-    let total_count = match tracks_collection.count_documents(None, None).await {
-        Ok(count) => {
-            info!("fetch_all_tracks command: Total track count: {}", count);
-            count as usize
-        },
-        Err(e) => {
-            error!("fetch_all_tracks command: Failed to count documents: {}", e);
-            return Err(CommandError::Database(format!("Failed to count documents: {}", e)));
+// Fetch all tracks with pagination and sorting - TAURI COMMAND
+#[tauri::command]
+pub async fn fetch_all_tracks(
+    mongo_state: State<'_, MongoState>, // <-- Use State
+    timeout_settings: State<'_, TimeoutSettings>,
+    album_cache: State<'_, AlbumNameCache>,
+    sort_field: String, // Pass simple types directly
+    sort_direction: String,
+    limit: Option<i64>,
+    skip: Option<i64>,
+    custom_field_filters: Option<HashMap<String, String>>, // Exact matches on custom_fields.<key>
+    include_trashed: Option<bool>, // Trashed tracks are left out unless true
+    include_staged: Option<bool>, // Tracks awaiting review are left out unless true
+) -> Result<TrackListResponse, CommandError> { // <-- Return local CommandError
+    let timeout = timeout_settings.get().query();
+    with_timeout("fetch_all_tracks", timeout, async {
+        info!("fetch_all_tracks command: Starting with sort_field={}, sort_direction={}", sort_field, sort_direction);
+        let sort_doc = track_list_sort_doc(&sort_field, &sort_direction)?;
+
+        let mut filter = match &custom_field_filters {
+            Some(matches) => custom_fields_filter(matches).map_err(CommandError::Validation)?,
+            None => Document::new(),
+        };
+        if !include_trashed.unwrap_or(false) {
+            exclude_trashed(&mut filter);
         }
-    };
-
-    info!("fetch_all_tracks command: Executing find() with options: {:?}", find_options);
-    let cursor_result = tracks_collection.find(None, find_options).await;
-
-    let mut cursor = match cursor_result {
-        Ok(cursor) => {
-            info!("fetch_all_tracks command: Cursor obtained successfully");
-            cursor
-        },
-        Err(e) => {
-            error!("fetch_all_tracks command: Failed to execute find query: {}", e);
-            return Err(CommandError::Database(format!("Failed to fetch tracks: {:?}", e)));
+        if !include_staged.unwrap_or(false) {
+            exclude_staged(&mut filter);
         }
-    };
 
-    let mut tracks_with_album: Vec<TrackWithAlbum> = Vec::new();
-    let mut processed_count = 0;
+        // Get Mongo client from state
+        let client_lock = mongo_state.client.lock().await;
+        let client = match client_lock.as_ref() {
+            Some(c) => c,
+            None => {
+                error!("fetch_all_tracks command: MongoDB client not initialized");
+                return Err(CommandError::Configuration("MongoDB client not initialized".to_string()));
+            }
+        };
+        let db = client.catalog_database(); // Get Database instance
 
-    while let Ok(Some(track_doc)) = cursor.try_next().await {
-        processed_count += 1;
-        // info!("fetch_all_tracks command: Processing track {}/{}", processed_count, total_count); // Less verbose logging
+        let tracks_collection: Collection<Document> = db.tracks();
 
-        let track_data = match mongodb::bson::from_document::<TrackDocument>(track_doc.clone()) {
-             Ok(data) => data,
-             Err(e) => {
-                 warn!("fetch_all_tracks command: Failed to deserialize track doc: {}. Doc: {:?}", e, track_doc);
-                 continue;
-             }
-         };
+        info!("fetch_all_tracks command: Using sort document: {:?}", sort_doc);
 
-        // Fetch album name
-        let album_name = if !track_data.album_id.is_empty() {
-            let album_filter = doc! { "_id": &track_data.album_id };
-            match albums_collection.find_one(album_filter, None).await {
-                Ok(Some(album_doc)) => {
-                    album_doc.get_str("name").unwrap_or("Unknown Album").to_string()
-                },
-                Ok(None) => {
-                    warn!("fetch_all_tracks command: Album not found for ID: {}", track_data.album_id);
-                    "Unknown Album".to_string()
-                },
-                Err(e) => {
-                    error!("fetch_all_tracks command: Error fetching album {}: {}", track_data.album_id, e);
-                    "Error Fetching Album".to_string()
-                }
+        // Get total count first for pagination
+        let total_count = match tracks_collection.count_documents(filter.clone(), None).await {
+            Ok(count) => {
+                info!("fetch_all_tracks command: Total track count: {}", count);
+                count as usize
+            },
+            Err(e) => {
+                error!("fetch_all_tracks command: Failed to count documents: {}", e);
+                return Err(CommandError::Database(format!("Failed to count documents: {}", e)));
             }
+        };
+
+        let cursor_result = if sort_field == ALBUM_NAME_SORT_KEY {
+            let pipeline = album_name_sort_pipeline(filter, sort_doc, skip, limit);
+            let options = AggregateOptions::builder().allow_disk_use(true).batch_size(cursor_batch_size()).build();
+            info!("fetch_all_tracks command: Executing aggregate() sorted by album name");
+            tracks_collection.aggregate(pipeline, options).await
         } else {
-            warn!("fetch_all_tracks command: Track {} has empty album_id", track_data._id);
-            "No Album ID".to_string()
+            // Notes are loaded per track with get_track_notes
+            let find_options = FindOptions::builder()
+                .projection(doc! { "notes": 0 })
+                .sort(sort_doc)
+                .limit(limit)
+                .skip(skip.map(|s| s as u64))
+                .batch_size(cursor_batch_size())
+                .build();
+            info!("fetch_all_tracks command: Executing find() with options: {:?}", find_options);
+            tracks_collection.find(filter, find_options).await
         };
 
-        // Convert TrackDocument to TrackWithAlbum
-        let track_with_album = TrackWithAlbum {
-            id: track_data._id,
-            title: track_data.title,
-            album_id: track_data.album_id,
-            album_name,
-            track_number: track_data.track_number,
-            filename: track_data.filename,
-            duration: Some(track_data.duration),
-            writers: track_data.writers,
-            writer_percentages: track_data.writer_percentages,
-            publishers: track_data.publishers,
-            publisher_percentages: track_data.publisher_percentages,
-            composers: track_data.composers,
-            genre: track_data.genre,
-            path: track_data.path,
-            waveform_data: track_data.waveform_data,
-            comments: track_data.comments,
+        let mut cursor = match cursor_result {
+            Ok(cursor) => {
+                info!("fetch_all_tracks command: Cursor obtained successfully");
+                cursor
+            },
+            Err(e) => {
+                error!("fetch_all_tracks command: Failed to execute find query: {}", e);
+                return Err(CommandError::Database(format!("Failed to fetch tracks: {:?}", e)));
+            }
         };
-        tracks_with_album.push(track_with_album);
+
+        let mut track_docs: Vec<TrackDocument> = Vec::new();
+        while let Ok(Some(track_doc)) = cursor.try_next().await {
+            match mongodb::bson::from_document::<TrackDocument>(track_doc.clone()) {
+                 Ok(data) => track_docs.push(data),
+                 Err(e) => warn!("fetch_all_tracks command: Failed to deserialize track doc: {}. Doc: {:?}", e, track_doc),
+             }
+        }
+
+        // Fetch album names for the whole page at once
+        let album_names = album_names_for_tracks(&db, &album_cache, &track_docs).await;
+
+        let mut tracks_with_album: Vec<TrackWithAlbum> = Vec::new();
+        for track_data in track_docs {
+            if track_data.album_id.is_empty() {
+                warn!("fetch_all_tracks command: Track {} has empty album_id", track_data._id);
+            } else if !album_names.contains_key(&track_data.album_id) {
+                warn!("fetch_all_tracks command: Album not found for ID: {}", track_data.album_id);
+            }
+            let album_name = album_name_for(&album_names, &track_data.album_id);
+
+            // Convert TrackDocument to TrackWithAlbum
+            tracks_with_album.push(track_data.into_track_with_album(album_name));
+        }
+         info!("fetch_all_tracks command: Processed {} tracks successfully", tracks_with_album.len());
+
+        Ok(TrackListResponse {
+            success: true,
+            message: None,
+            tracks: tracks_with_album,
+            total_count,
+        })
+    }).await
+}
+
+/// Deserializes a track document, accepting ObjectId as well as string `_id`/`album_id` values.
+fn parse_track_document(mut track_doc: Document) -> Result<TrackDocument, bson::de::Error> {
+    for field in ["_id", "album_id"] {
+        if let Some(id) = track_doc.get(field).and_then(super::id_to_string) {
+            track_doc.insert(field, id);
+        }
     }
-     info!("fetch_all_tracks command: Processed {} tracks successfully", tracks_with_album.len());
+    bson::from_document::<TrackDocument>(track_doc)
+}
 
-    Ok(TrackListResponse {
-        success: true,
-        message: None,
-        tracks: tracks_with_album,
-        total_count,
-    })
+impl TrackDocument {
+    fn into_track_with_album(self, album_name: String) -> TrackWithAlbum {
+        TrackWithAlbum {
+            id: self._id,
+            title: self.title,
+            album_id: self.album_id,
+            album_name,
+            track_number: self.track_number,
+            filename: self.filename,
+            duration: Some(self.duration),
+            writers: self.writers,
+            writer_percentages: self.writer_percentages,
+            publishers: self.publishers,
+            publisher_percentages: self.publisher_percentages,
+            composers: self.composers,
+            genre: self.genre,
+            path: self.path,
+            waveform_data: self.waveform_data,
+            comments: self.comments,
+            custom_fields: self.custom_fields,
+            bpm: self.bpm,
+            musical_key: self.musical_key,
+            loudness_lufs: self.loudness_lufs,
+            replaygain_db: self.replaygain_db,
+            isrc: self.isrc,
+            catalog_number: self.catalog_number,
+            slug: self.slug,
+        }
+    }
+}
+
+/// Puts fetched tracks into the order of `ids`, skipping ids that were not found.
+/// A repeated id is returned at each position it was requested.
+fn order_by_ids(ids: &[String], tracks: &HashMap<String, TrackDocument>) -> Vec<TrackDocument> {
+    ids.iter().filter_map(|id| tracks.get(id).cloned()).collect()
+}
+
+/// Fetches the given tracks with album names in one query, in the order of `object_ids`.
+/// Ids with no matching track are skipped.
+pub async fn fetch_tracks_by_ids(
+    db: &Database,
+    album_cache: &AlbumNameCache,
+    object_ids: &[bson::oid::ObjectId],
+) -> Result<Vec<TrackWithAlbum>, mongodb::error::Error> {
+    if object_ids.is_empty() {
+        return Ok(Vec::new());
+    }
+    let track_docs: Vec<Document> = db.tracks::<Document>()
+        .find(doc! { "_id": { "$in": object_ids } }, FindOptions::builder().batch_size(cursor_batch_size()).build())
+        .await?
+        .try_collect()
+        .await?;
+
+    let mut tracks_by_id: HashMap<String, TrackDocument> = HashMap::new();
+    for track_doc in track_docs {
+        match parse_track_document(track_doc.clone()) {
+            Ok(track) => { tracks_by_id.insert(track._id.clone(), track); }
+            Err(e) => warn!("Failed to deserialize track doc: {}. Doc: {:?}", e, track_doc),
+        }
+    }
+    let ids: Vec<String> = object_ids.iter().map(|oid| oid.to_hex()).collect();
+    if tracks_by_id.len() < ids.len() {
+        warn!("Found {} of {} requested tracks", tracks_by_id.len(), ids.len());
+    }
+
+    let tracks = order_by_ids(&ids, &tracks_by_id);
+    let album_names = album_names_for_tracks(db, album_cache, &tracks).await;
+    Ok(tracks.into_iter()
+        .map(|track| {
+            let album_name = album_name_for(&album_names, &track.album_id);
+            track.into_track_with_album(album_name)
+        })
+        .collect())
+}
+
+/// Fetches full records for the given track ids in one query - TAURI COMMAND
+/// Tracks are returned in the requested order; ids with no matching track are skipped.
+#[tauri::command]
+pub async fn get_tracks_by_ids(
+    mongo_state: State<'_, MongoState>,
+    album_cache: State<'_, AlbumNameCache>,
+    track_ids: Vec<String>,
+) -> Result<Vec<TrackWithAlbum>, CommandError> {
+    info!("get_tracks_by_ids command: Fetching {} tracks", track_ids.len());
+
+    let object_ids = track_ids.iter()
+        .map(|id| bson::oid::ObjectId::parse_str(id)
+            .map_err(|e| CommandError::Validation(format!("Invalid track ID format '{}': {}", id, e))))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let client_lock = mongo_state.client.lock().await;
+    let client = match client_lock.as_ref() {
+        Some(c) => c,
+        None => {
+            error!("get_tracks_by_ids command: MongoDB client not initialized");
+            return Err(CommandError::Configuration("MongoDB client not initialized".to_string()));
+        }
+    };
+    let db = client.catalog_database();
+
+    fetch_tracks_by_ids(&db, &album_cache, &object_ids).await
+        .map_err(|e| CommandError::Database(format!("Failed to fetch tracks: {}", e)))
 }
 
 /// Updates the metadata for a track in the database - TAURI COMMAND
+/// With `sync_object_metadata`, edits to the fields in `object_metadata::SYNCED_FIELDS`
+/// are also written to the track's R2 objects.
 #[tauri::command]
 pub async fn update_track_metadata(
+    app_handle: AppHandle<Wry>,
     mongo_state: State<'_, MongoState>, // <-- Use State
+    r2_state: State<'_, R2State>,
     track_id: String, // Pass simple types
     payload: UpdateTrackPayload, // Pass payload struct
 ) -> Result<(), CommandError> { // <-- Return local CommandError
@@ -849,7 +1551,7 @@ pub async fn update_track_metadata(
             return Err(CommandError::Configuration("MongoDB client not initialized".to_string()));
         }
     };
-    let db = client.database("music_library"); // Get Database instance
+    let db = client.catalog_database(); // Get Database instance
 
     // Convert string ID to ObjectId
     let object_id = match bson::oid::ObjectId::parse_str(&track_id) {
@@ -860,7 +1562,33 @@ pub async fn update_track_metadata(
         }
     };
 
-    let tracks_collection = db.collection::<Document>("tracks");
+    let tracks_collection = db.tracks::<Document>();
+
+    // Splits are checked against the credited names, taken from the stored track when the
+    // payload doesn't replace them
+    let needs_stored_names = (payload.writer_percentages.is_some() && payload.writers.is_none())
+        || (payload.publisher_percentages.is_some() && payload.publishers.is_none());
+    let stored_names = if needs_stored_names {
+        let options = mongodb::options::FindOneOptions::builder().projection(doc! { "writers": 1, "publishers": 1 }).build();
+        tracks_collection.find_one(doc! { "_id": object_id }, options).await
+            .map_err(|e| CommandError::Database(format!("Failed to load track: {}", e)))?
+            .ok_or_else(|| CommandError::NotFound(format!("Track with ID {} not found", track_id)))?
+    } else {
+        Document::new()
+    };
+    let names = |payload_names: &Option<Vec<String>>, field: &str| -> Vec<String> {
+        payload_names.clone().unwrap_or_else(|| {
+            stored_names.get_array(field).map(|names| {
+                names.iter().filter_map(|name| name.as_str().map(String::from)).collect()
+            }).unwrap_or_default()
+        })
+    };
+    if let Some(writer_percentages) = &payload.writer_percentages {
+        validate_percentages(writer_percentages, &names(&payload.writers, "writers"), "Writer").map_err(CommandError::Validation)?;
+    }
+    if let Some(publisher_percentages) = &payload.publisher_percentages {
+        validate_percentages(publisher_percentages, &names(&payload.publishers, "publishers"), "Publisher").map_err(CommandError::Validation)?;
+    }
 
     // Build update document based on provided fields in payload
     let mut update_doc = Document::new();
@@ -897,41 +1625,317 @@ pub async fn update_track_metadata(
         })?);
     }
 
-    // REMOVED composers block - Field does not exist on UpdateTrackPayload
+    if let Some(composers) = &payload.composers {
+        let composers: Vec<&str> = composers.iter().map(|c| c.trim()).filter(|c| !c.is_empty()).collect();
+        update_doc.insert("composers", composers);
+    }
+
+    // Empty values are removed rather than stored, as the ISRC index is unique and sparse
+    let mut unset_doc = Document::new();
+    if let Some(isrc) = payload.isrc.as_deref().map(str::trim) {
+        if isrc.is_empty() {
+            unset_doc.insert("isrc", "");
+        } else {
+            update_doc.insert("isrc", super::normalize_isrc(isrc).map_err(CommandError::Validation)?);
+        }
+    }
+    if let Some(catalog_number) = payload.catalog_number.as_deref().map(str::trim) {
+        if catalog_number.is_empty() {
+            unset_doc.insert("catalog_number", "");
+        } else {
+            update_doc.insert("catalog_number", catalog_number);
+        }
+    }
+
+    // Controlled vocabulary is opt-in; when disabled, genres go through the taxonomy instead
+    let vocabulary = load_vocabulary(&db).await.map_err(|e| {
+        error!("Failed to load vocabulary settings: {}", e);
+        CommandError::Database(format!("Failed to load vocabulary settings: {}", e))
+    })?;
 
     if let Some(genre) = &payload.genre {
-        update_doc.insert("genre", to_bson(genre).map_err(|e| {
+        let genre = if vocabulary.enabled {
+            apply_vocabulary(genre, &vocabulary.genres, "Genre").map_err(CommandError::Validation)?
+        } else {
+            normalize_genres(&db, genre).await.map_err(|e| {
+                error!("Failed to normalize genres: {}", e);
+                CommandError::Database(format!("Failed to normalize genres: {}", e))
+            })?
+        };
+        update_doc.insert("genre", to_bson(&genre).map_err(|e| {
             error!("Failed to convert genre to BSON: {}", e);
             CommandError::Database(format!("Failed to convert genre to BSON: {}", e))
         })?);
     }
 
+    if let Some(mood) = &payload.mood {
+        let mood = if vocabulary.enabled {
+            apply_vocabulary(mood, &vocabulary.moods, "Mood").map_err(CommandError::Validation)?
+        } else {
+            mood.clone()
+        };
+        update_doc.insert("mood", to_bson(&mood).map_err(|e| {
+            error!("Failed to convert mood to BSON: {}", e);
+            CommandError::Database(format!("Failed to convert mood to BSON: {}", e))
+        })?);
+    }
+
      if let Some(comments) = &payload.comments {
         update_doc.insert("comments", comments);
     }
 
+    // Replaces the whole map; send an empty map to clear all custom fields
+    if let Some(custom_fields) = &payload.custom_fields {
+        validate_custom_fields(custom_fields).map_err(CommandError::Validation)?;
+        update_doc.insert("custom_fields", to_bson(custom_fields).map_err(|e| {
+            error!("Failed to convert custom_fields to BSON: {}", e);
+            CommandError::Database(format!("Failed to convert custom_fields to BSON: {}", e))
+        })?);
+    }
+
+    if let Some(bpm) = payload.bpm {
+        if !(bpm.is_finite() && bpm > 0.0 && bpm <= 400.0) {
+            return Err(CommandError::Validation(format!("BPM must be between 0 and 400, got {}", bpm)));
+        }
+        update_doc.insert("bpm", bpm);
+    }
+
+    if let Some(musical_key) = &payload.musical_key {
+        let musical_key = musical_key.trim();
+        if musical_key.is_empty() {
+            return Err(CommandError::Validation("Musical key must not be empty".to_string()));
+        }
+        update_doc.insert("musical_key", musical_key);
+    }
+
+    // The old slug moves to `previous_slugs`, so links to it keep working
+    let mut add_to_set_doc = Document::new();
+    if payload.regenerate_slug {
+        let options = mongodb::options::FindOneOptions::builder().projection(doc! { "title": 1, "artists": 1 }).build();
+        let stored = tracks_collection.find_one(doc! { "_id": object_id }, options).await
+            .map_err(|e| CommandError::Database(format!("Failed to load track: {}", e)))?
+            .ok_or_else(|| CommandError::NotFound(format!("Track with ID {} not found", track_id)))?;
+        let title = payload.title.as_deref().unwrap_or_else(|| stored.get_str("title").unwrap_or_default());
+        let artist = stored.get_array("artists").ok()
+            .and_then(|artists| artists.first())
+            .and_then(bson::Bson::as_str)
+            .unwrap_or_default();
+        let (slug_fields, previous_slug) = regenerated_slug_fields(&tracks_collection, &bson::Bson::ObjectId(object_id), title, artist).await
+            .map_err(|e| CommandError::Database(format!("Failed to generate slug: {}", e)))?;
+        update_doc.extend(slug_fields);
+        add_to_set_doc = previous_slug;
+    }
+
     // REMOVED track_number block - Field does not exist on UpdateTrackPayload
 
 
     // Only update if there are fields to change
-    if !update_doc.is_empty() {
-        let update = doc! { "$set": update_doc };
-        match tracks_collection.update_one(doc! { "_id": object_id }, update, None).await {
+    if !update_doc.is_empty() || !unset_doc.is_empty() {
+        let edited_fields: Vec<String> = update_doc.keys().chain(unset_doc.keys()).cloned().collect();
+        let mut update = Document::new();
+        if !update_doc.is_empty() {
+            update.insert("$set", update_doc);
+        }
+        if !unset_doc.is_empty() {
+            update.insert("$unset", unset_doc);
+        }
+        if !add_to_set_doc.is_empty() {
+            update.insert("$addToSet", add_to_set_doc);
+        }
+        match tracks_collection.update_one(doc! { "_id": object_id }, touched(update), None).await {
             Ok(result) => {
                 if result.matched_count == 0 {
                     error!("Track not found for update: {}", track_id);
                     return Err(CommandError::NotFound(format!("Track not found: {}", track_id)));
                 }
                 info!("Successfully updated metadata for track: {}", track_id);
+                let summary = format!("Edited {} on 1 track", edited_fields.join(", "));
+                record_activity(&app_handle, Some(&db), ActivityEntry::new(ActivityAction::MetadataUpdated, vec![track_id.clone()], summary)).await;
+            }
+            Err(e) if is_slug_conflict(&e) => {
+                return Err(CommandError::Validation("Another track took the new slug at the same time; try again".to_string()));
+            }
+            Err(e) if is_duplicate_key_error(&e) => {
+                return Err(CommandError::Validation(format!(
+                    "ISRC {} is already assigned to another track", payload.isrc.as_deref().unwrap_or_default().trim()
+                )));
             }
             Err(e) => {
                 error!("Failed to update track metadata in MongoDB: {}", e);
                 return Err(CommandError::Database(format!("Failed to update track: {}", e)));
             }
         }
+
+        if payload.sync_object_metadata && touches_synced_fields(&payload) {
+            let track_doc = tracks_collection.find_one(doc! { "_id": object_id }, None).await
+                .map_err(|e| CommandError::Database(format!("Failed to load track: {}", e)))?
+                .ok_or_else(|| CommandError::NotFound(format!("Track not found: {}", track_id)))?;
+            let r2_client = R2Client::from_state(&r2_state).await.map_err(|e| CommandError::Configuration(e.to_string()))?;
+            let updated = sync_object_metadata(&r2_client, &track_doc).await.map_err(|e| {
+                error!("Object metadata sync failed for track {}: {}", track_id, e);
+                CommandError::Unexpected(format!("Track updated, but its R2 object metadata was not: {}", e))
+            })?;
+            info!("Synced object metadata of {} R2 objects for track {}", updated, track_id);
+        }
     } else {
         info!("No metadata fields provided to update for track: {}", track_id);
     }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn track(id: &str) -> TrackDocument {
+        TrackDocument {
+            _id: id.to_string(),
+            title: format!("Track {}", id),
+            album_id: String::new(),
+            track_number: None,
+            filename: String::new(),
+            duration: 0,
+            writers: vec![],
+            writer_percentages: None,
+            publishers: vec![],
+            publisher_percentages: None,
+            composers: None,
+            genre: None,
+            path: String::new(),
+            waveform_data: None,
+            comments: None,
+            custom_fields: None,
+            bpm: None,
+            musical_key: None,
+            loudness_lufs: None,
+            replaygain_db: None,
+            isrc: None,
+            catalog_number: None,
+            slug: None,
+        }
+    }
+
+    #[test]
+    fn test_order_by_ids_follows_request_order() {
+        let fetched: HashMap<String, TrackDocument> = ["a", "b", "c"].iter().map(|id| (id.to_string(), track(id))).collect();
+        let ids: Vec<String> = ["c", "missing", "a", "c"].iter().map(|id| id.to_string()).collect();
+
+        let ordered: Vec<String> = order_by_ids(&ids, &fetched).into_iter().map(|t| t._id).collect();
+        assert_eq!(ordered, vec!["c", "a", "c"]);
+    }
+
+    #[test]
+    fn test_build_search_filter() {
+        assert_eq!(build_search_filter(&SearchFilter::default()).unwrap(), Document::new());
+
+        let search = SearchFilter {
+            text: Some("  upbeat  ".to_string()),
+            genres: vec!["Rock".to_string(), "Pop".to_string()],
+            duration_min: Some(90.0),
+            duration_max: Some(120.0),
+            bpm_min: Some(100.0),
+            has_writers: Some(true),
+            custom_fields: HashMap::from([("sync_cleared".to_string(), "yes".to_string())]),
+            ..Default::default()
+        };
+        assert_eq!(build_search_filter(&search).unwrap(), doc! {
+            "custom_fields.sync_cleared": "yes",
+            "$text": { "$search": "upbeat" },
+            "genre": { "$in": ["Rock", "Pop"] },
+            "duration": { "$gte": 90.0, "$lte": 120.0 },
+            "bpm": { "$gte": 100.0 },
+            "writers.0": { "$exists": true },
+        });
+
+        let inverted = SearchFilter { bpm_min: Some(140.0), bpm_max: Some(120.0), ..Default::default() };
+        assert!(build_search_filter(&inverted).is_err());
+    }
+
+    #[test]
+    fn test_search_sort_doc() {
+        assert_eq!(search_sort_doc(None, true).unwrap(), doc! { "_score": -1, "_id": 1 });
+        assert_eq!(search_sort_doc(None, false).unwrap(), doc! { "title": 1, "_id": 1 });
+        let by_bpm = SearchSort { field: "bpm".to_string(), descending: true };
+        assert_eq!(search_sort_doc(Some(&by_bpm), true).unwrap(), doc! { "bpm": -1, "_id": 1 });
+        let unknown = SearchSort { field: "$where".to_string(), descending: false };
+        assert!(search_sort_doc(Some(&unknown), false).is_err());
+    }
+
+    #[test]
+    fn test_track_list_sort_doc() {
+        assert_eq!(track_list_sort_doc("title", "asc").unwrap(), doc! { "title": 1 });
+        assert_eq!(track_list_sort_doc("date_added", "desc").unwrap(), doc! { "date_added": -1 });
+        assert_eq!(track_list_sort_doc("album_name", "desc").unwrap(), doc! { "album_name": -1, "track_number": -1 });
+        for field in ["bpm", "$where", "title.0", ""] {
+            assert!(matches!(track_list_sort_doc(field, "asc"), Err(CommandError::Validation(_))), "{}", field);
+        }
+    }
+
+    #[test]
+    fn test_album_name_sort_pipeline_sorts_after_the_join() {
+        let sort_doc = track_list_sort_doc("album_name", "asc").unwrap();
+        let pipeline = album_name_sort_pipeline(doc! { "genre": "Jazz" }, sort_doc, Some(50), Some(25));
+        let stages: Vec<&String> = pipeline.iter().map(|stage| stage.keys().next().unwrap()).collect();
+        assert_eq!(stages, ["$match", "$addFields", "$lookup", "$lookup", "$addFields", "$sort", "$skip", "$limit", "$project"]);
+        assert_eq!(pipeline[5], doc! { "$sort": { "album_name": 1, "track_number": 1, "_id": 1 } });
+        assert_eq!(pipeline[8].get_document("$project").unwrap().get_i32("album_name"), Ok(0));
+
+        let first_page = album_name_sort_pipeline(Document::new(), doc! { "album_name": 1 }, None, None);
+        assert!(first_page.iter().all(|stage| !stage.contains_key("$skip") && !stage.contains_key("$limit")));
+    }
+
+    #[test]
+    fn test_album_summary_pipeline_pages_before_join_for_stored_fields() {
+        let stage_names = |pipeline: &[Document]| -> Vec<String> {
+            pipeline.iter().map(|stage| stage.keys().next().unwrap().clone()).collect()
+        };
+        let by_name = album_summary_pipeline(None, 0, 50).unwrap();
+        let join = ["$addFields", "$lookup", "$lookup", "$addFields", "$project"];
+        assert_eq!(stage_names(&by_name), [["$sort", "$skip", "$limit"].as_slice(), &join].concat());
+        // Plain equality joins, which can use the album_id index
+        for lookup in by_name.iter().filter_map(|stage| stage.get_document("$lookup").ok()) {
+            assert_eq!(lookup.get_str("foreignField"), Ok("album_id"));
+            assert!(!lookup.contains_key("let"));
+            let lookup_pipeline = lookup.get_array("pipeline").unwrap();
+            let listed = lookup_pipeline[0].as_document().unwrap().get_document("$match").unwrap();
+            assert!(listed.contains_key("deleted_at") && listed.contains_key("status"));
+        }
+
+        let by_count = SearchSort { field: "track_count".to_string(), descending: true };
+        let pipeline = album_summary_pipeline(Some(&by_count), 50, 50).unwrap();
+        assert_eq!(stage_names(&pipeline), [join.as_slice(), &["$sort", "$skip", "$limit"]].concat());
+        assert_eq!(pipeline[5], doc! { "$sort": { "track_count": -1, "_id": 1 } });
+
+        let unknown = SearchSort { field: "track_ids".to_string(), descending: false };
+        assert!(album_summary_pipeline(Some(&unknown), 0, 50).is_err());
+    }
+
+    #[test]
+    fn test_album_summary_from_document() {
+        let oid = bson::oid::ObjectId::new();
+        let summary = album_summary_from_document(&doc! {
+            "_id": oid, "name": "Blue", "artist": "Joni", "year": 1971, "genres": ["Folk"], "art_path": "",
+            "track_count": 10, "total_duration_secs": 2160.5, "track_ids_stale": true,
+        }).unwrap();
+        assert_eq!(summary.id, oid.to_hex());
+        assert_eq!(summary.year, Some(1971));
+        assert_eq!(summary.track_count, 10);
+        assert_eq!(summary.total_duration_secs, 2160.5);
+        assert_eq!(summary.art_key, None);
+        assert!(summary.track_ids_stale);
+    }
+
+    #[test]
+    fn test_parse_track_document_accepts_object_ids() {
+        let oid = bson::oid::ObjectId::new();
+        let album_oid = bson::oid::ObjectId::new();
+        let track_doc = doc! {
+            "_id": oid, "title": "Song", "album_id": album_oid, "filename": "song.wav", "duration": 120,
+            "writers": [], "publishers": [], "path": "tracks/song.m4a",
+        };
+        let parsed = parse_track_document(track_doc).unwrap();
+        assert_eq!(parsed._id, oid.to_hex());
+        assert_eq!(parsed.album_id, album_oid.to_hex());
+    }
+}
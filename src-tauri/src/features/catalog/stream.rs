@@ -0,0 +1,282 @@
+//! Local streaming endpoint for the built-in player.
+//!
+//! The webview's audio element can't send R2 credentials, and presigned URLs keep
+//! working wherever they are pasted. Instead the player loads `stream://localhost/track/{id}`:
+//! the handler looks up the track's object, fetches the requested byte range from R2 with
+//! the app's own client, and answers with the headers media elements need to seek.
+//! Every request is handled on its own task with its own responder, so concurrent
+//! playback of several tracks never mixes response bodies.
+
+use log::{info, warn};
+use mongodb::bson::Document;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use tauri::http::{header, Request, Response, StatusCode};
+use tauri::{command, AppHandle, Manager, UriSchemeContext, UriSchemeResponder, Wry};
+
+use super::stats::{record_access, AccessKind};
+use super::storage::id_filter;
+use crate::core::encryption::is_encrypted_copy;
+use crate::core::r2::{R2Client, R2Error, PLAYBACK_KEY_FIELDS};
+use crate::CommandError;
+use crate::{MongoState, R2State};
+use crate::core::db_config::{CatalogCollections, CatalogDatabase};
+
+/// URI scheme registered for streaming in `main.rs`.
+pub const STREAM_SCHEME: &str = "stream";
+
+/// Open-ended ranges (`bytes=N-`), and requests without a range, are answered with at
+/// most this many bytes; media elements request the rest as playback continues.
+const MAX_CHUNK_BYTES: u64 = 2 * 1024 * 1024;
+
+/// The R2 object streamed for a track.
+#[derive(Debug, Clone, PartialEq)]
+struct StreamSource {
+    /// Bucket recorded on the track, if it differs from the configured one
+    bucket: Option<String>,
+    key: String,
+}
+
+/// Track id to R2 object, so seeking doesn't query MongoDB for every range request.
+#[derive(Debug, Default)]
+pub struct StreamSourceCache {
+    sources: Mutex<HashMap<String, StreamSource>>,
+}
+
+impl StreamSourceCache {
+    fn get(&self, track_id: &str) -> Option<StreamSource> {
+        self.sources.lock().unwrap_or_else(|e| e.into_inner()).get(track_id).cloned()
+    }
+
+    fn insert(&self, track_id: &str, source: StreamSource) {
+        self.sources.lock().unwrap_or_else(|e| e.into_inner()).insert(track_id.to_string(), source);
+    }
+
+    fn remove(&self, track_id: &str) {
+        self.sources.lock().unwrap_or_else(|e| e.into_inner()).remove(track_id);
+    }
+}
+
+/// The delivery copy, falling back to the original for tracks without one. Encrypted
+/// originals can't be streamed.
+fn stream_source(track_doc: &Document) -> Option<StreamSource> {
+    let key = PLAYBACK_KEY_FIELDS
+        .iter()
+        .find_map(|field| track_doc.get_str(field).ok().filter(|key| !key.is_empty() && !is_encrypted_copy(track_doc, key)))?;
+    let bucket = track_doc.get_str("r2_bucket").ok().filter(|bucket| !bucket.is_empty()).map(String::from);
+    Some(StreamSource { bucket, key: key.to_string() })
+}
+
+/// URI to hand to `<audio src>`. Windows and Android serve custom schemes over
+/// `http://<scheme>.localhost`.
+pub fn track_stream_uri(track_id: &str) -> String {
+    if cfg!(any(windows, target_os = "android")) {
+        format!("http://{}.localhost/track/{}", STREAM_SCHEME, track_id)
+    } else {
+        format!("{}://localhost/track/{}", STREAM_SCHEME, track_id)
+    }
+}
+
+fn track_id_from_path(path: &str) -> Option<&str> {
+    path.strip_prefix("/track/").filter(|id| !id.is_empty() && !id.contains('/'))
+}
+
+/// The range to request from R2 for a `Range` header, with open-ended ranges capped
+/// at `MAX_CHUNK_BYTES`. `None` for malformed or multi-part ranges.
+fn normalize_range(header_value: &str) -> Option<String> {
+    let spec = header_value.trim().strip_prefix("bytes=")?;
+    if spec.contains(',') {
+        return None;
+    }
+    let (start, end) = spec.split_once('-')?;
+    match (start.trim(), end.trim()) {
+        ("", suffix) => suffix.parse::<u64>().ok().filter(|len| *len > 0).map(|len| format!("bytes=-{}", len)),
+        (start, "") => {
+            let start = start.parse::<u64>().ok()?;
+            Some(format!("bytes={}-{}", start, start.saturating_add(MAX_CHUNK_BYTES - 1)))
+        }
+        (start, end) => {
+            let (start, end) = (start.parse::<u64>().ok()?, end.parse::<u64>().ok()?);
+            (end >= start).then(|| format!("bytes={}-{}", start, end))
+        }
+    }
+}
+
+/// The range to fetch for a request's `Range` header. The scheme responder takes the
+/// whole body at once and can't stream it, so a request without a range is answered
+/// with the first chunk as a 206 rather than the whole object read into memory.
+/// `None` for a header `normalize_range` rejects.
+fn requested_range(header_value: Option<&str>) -> Option<String> {
+    normalize_range(header_value.unwrap_or("bytes=0-"))
+}
+
+/// Players fetch a track in many range requests; only the one from the start counts as a play.
+fn starts_playback(range: &str) -> bool {
+    range.starts_with("bytes=0-")
+}
+
+fn status_response(status: StatusCode) -> Response<Vec<u8>> {
+    Response::builder().status(status).body(Vec::new()).unwrap_or_default()
+}
+
+/// Looks up the track's stream source, using the cache unless `refresh` is set.
+async fn resolve_source(app_handle: &AppHandle<Wry>, track_id: &str, refresh: bool) -> Result<Option<StreamSource>, CommandError> {
+    let cache = app_handle.state::<StreamSourceCache>();
+    if !refresh {
+        if let Some(source) = cache.get(track_id) {
+            return Ok(Some(source));
+        }
+    }
+    let tracks_collection = {
+        let mongo_state = app_handle.state::<MongoState>();
+        let client_lock = mongo_state.client.lock().await;
+        let client = client_lock.as_ref()
+            .ok_or_else(|| CommandError::Configuration("MongoDB client not initialized".to_string()))?;
+        client.catalog_database().tracks::<Document>()
+    };
+    let source = tracks_collection.find_one(id_filter(track_id), None).await?.as_ref().and_then(stream_source);
+    match &source {
+        Some(source) => cache.insert(track_id, source.clone()),
+        None => cache.remove(track_id),
+    }
+    Ok(source)
+}
+
+async fn serve(app_handle: AppHandle<Wry>, request: Request<Vec<u8>>) -> Response<Vec<u8>> {
+    let Some(track_id) = track_id_from_path(request.uri().path()) else {
+        return status_response(StatusCode::NOT_FOUND);
+    };
+    let range_header = match request.headers().get(header::RANGE).map(|value| value.to_str()) {
+        Some(Ok(value)) => Some(value),
+        Some(Err(_)) => return status_response(StatusCode::RANGE_NOT_SATISFIABLE),
+        None => None,
+    };
+    let Some(range) = requested_range(range_header) else {
+        return status_response(StatusCode::RANGE_NOT_SATISFIABLE);
+    };
+    let r2_client = match R2Client::from_state(&app_handle.state::<R2State>()).await {
+        Ok(client) => client,
+        Err(e) => {
+            warn!("Stream request for track {} before R2 is ready: {}", track_id, e);
+            return status_response(StatusCode::SERVICE_UNAVAILABLE);
+        }
+    };
+
+    // A cached source may point at an object deleted since, so a miss is retried fresh
+    for refresh in [false, true] {
+        let source = match resolve_source(&app_handle, track_id, refresh).await {
+            Ok(Some(source)) => source,
+            Ok(None) => return status_response(StatusCode::NOT_FOUND),
+            Err(e) => {
+                warn!("Failed to look up stream source of track {}: {}", track_id, e);
+                return status_response(StatusCode::SERVICE_UNAVAILABLE);
+            }
+        };
+        let bucket_client = match &source.bucket {
+            Some(bucket) if bucket != r2_client.bucket_name() => r2_client.with_bucket(bucket),
+            _ => r2_client.clone(),
+        };
+        let object = match bucket_client.download_range(&source.key, Some(&range)).await {
+            Ok(Some(object)) => object,
+            Ok(None) => continue,
+            Err(R2Error::InvalidRange(_)) => return status_response(StatusCode::RANGE_NOT_SATISFIABLE),
+            Err(e) => {
+                warn!("Failed to stream {} for track {}: {}", source.key, track_id, e);
+                return status_response(StatusCode::BAD_GATEWAY);
+            }
+        };
+
+        let content_type = object.content_type
+            .unwrap_or_else(|| mime_guess::from_path(&source.key).first_or_octet_stream().to_string());
+        let mut response = Response::builder()
+            .header(header::CONTENT_TYPE, content_type)
+            .header(header::ACCEPT_RANGES, "bytes")
+            .header(header::CONTENT_LENGTH, object.bytes.len())
+            .header(header::ACCESS_CONTROL_ALLOW_ORIGIN, "*");
+        response = match &object.content_range {
+            Some(content_range) => response.status(StatusCode::PARTIAL_CONTENT).header(header::CONTENT_RANGE, content_range),
+            None => response.status(StatusCode::OK),
+        };
+        if starts_playback(&range) {
+            count_play(&app_handle, track_id).await;
+        }
+        return response.body(object.bytes).unwrap_or_else(|_| status_response(StatusCode::INTERNAL_SERVER_ERROR));
+    }
+    status_response(StatusCode::NOT_FOUND)
+}
+
+async fn count_play(app_handle: &AppHandle<Wry>, track_id: &str) {
+    let mongo_state = app_handle.state::<MongoState>();
+    let client_lock = mongo_state.client.lock().await;
+    if let Some(client) = client_lock.as_ref() {
+        record_access(client.catalog_database().tracks::<Document>(), track_id, AccessKind::Play);
+    }
+}
+
+/// Handler for the `stream://` URI scheme, registered in `main.rs`.
+pub fn handle_stream_request(ctx: UriSchemeContext<'_, Wry>, request: Request<Vec<u8>>, responder: UriSchemeResponder) {
+    let app_handle = ctx.app_handle().clone();
+    tauri::async_runtime::spawn(async move {
+        responder.respond(serve(app_handle, request).await);
+    });
+}
+
+// --- Tauri Commands ---
+
+/// Returns the local URI that streams a track for the built-in player. Fails if the
+/// track doesn't exist or has no audio stored.
+#[command]
+pub async fn get_track_stream_uri(track_id: String, app_handle: AppHandle<Wry>) -> Result<String, CommandError> {
+    match resolve_source(&app_handle, &track_id, true).await? {
+        Some(source) => {
+            info!("Streaming track {} from {}", track_id, source.key);
+            Ok(track_stream_uri(&track_id))
+        }
+        None => Err(CommandError::NotFound(format!("Track {} not found or has no audio", track_id))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mongodb::bson::doc;
+
+    #[test]
+    fn test_normalize_range() {
+        assert_eq!(normalize_range("bytes=0-1023").as_deref(), Some("bytes=0-1023"));
+        assert_eq!(normalize_range("bytes=100-").as_deref(), Some("bytes=100-2097251"));
+        assert_eq!(normalize_range("bytes=-500").as_deref(), Some("bytes=-500"));
+        assert_eq!(normalize_range("bytes=10-5"), None);
+        assert_eq!(normalize_range("bytes=0-1,5-9"), None);
+        assert_eq!(normalize_range("items=0-1"), None);
+
+        // Without a header only the first chunk is fetched
+        assert_eq!(requested_range(None).as_deref(), Some("bytes=0-2097151"));
+        assert_eq!(requested_range(Some("bytes=5-9")).as_deref(), Some("bytes=5-9"));
+        assert_eq!(requested_range(Some("bytes=9-5")), None);
+    }
+
+    #[test]
+    fn test_stream_source_falls_back_to_original() {
+        let with_delivery = doc! { "r2_aac_key": "tracks/aac/a.m4a", "r2_original_key": "tracks/original/a.wav", "r2_bucket": "" };
+        assert_eq!(stream_source(&with_delivery), Some(StreamSource { bucket: None, key: "tracks/aac/a.m4a".into() }));
+        let original_only = doc! { "r2_aac_key": null, "r2_original_key": "tracks/original/b.wav", "r2_bucket": "archive" };
+        assert_eq!(stream_source(&original_only), Some(StreamSource { bucket: Some("archive".into()), key: "tracks/original/b.wav".into() }));
+        assert_eq!(stream_source(&doc! { "title": "No audio" }), None);
+    }
+
+    #[test]
+    fn test_starts_playback() {
+        assert!(starts_playback("bytes=0-1023"));
+        assert!(!starts_playback("bytes=1024-2047"));
+        assert!(!starts_playback("bytes=-500"));
+    }
+
+    #[test]
+    fn test_track_id_from_path() {
+        assert_eq!(track_id_from_path("/track/65f0c0ffee"), Some("65f0c0ffee"));
+        assert_eq!(track_id_from_path("/track/"), None);
+        assert_eq!(track_id_from_path("/track/a/b"), None);
+        assert!(track_stream_uri("abc").ends_with("localhost/track/abc"));
+    }
+}
@@ -0,0 +1,316 @@
+//! Type-ahead suggestions for the tagging UI: values already used on tracks that start
+//! with what the user has typed, most used first, so the vocabulary stays consistent.
+//! Also whole metadata sets from earlier versions of a song, so a new mix can copy the
+//! splits instead of retyping them.
+
+use futures_util::stream::TryStreamExt;
+use mongodb::bson::{doc, Bson, Document};
+use mongodb::options::FindOptions;
+use mongodb::Database;
+use serde::Serialize;
+use std::collections::{BTreeSet, HashMap};
+use tauri::{command, State};
+
+use super::staging::exclude_staged;
+use super::storage::id_to_string;
+use super::trash::exclude_trashed;
+use crate::CommandError;
+use crate::MongoState;
+use crate::core::db_config::CatalogCollections;
+
+const DEFAULT_SUGGESTION_LIMIT: u32 = 10;
+const MAX_SUGGESTION_LIMIT: u32 = 50;
+
+/// Metadata sets `suggest_metadata` returns.
+const METADATA_SUGGESTION_LIMIT: usize = 5;
+
+/// Text search hits scored by `suggest_metadata`, best text score first.
+const METADATA_CANDIDATE_LIMIT: i64 = 50;
+
+/// Words marking a version of a song rather than the song, in a trailing `(...)`,
+/// `[...]` or `- ...` part of a title.
+const VERSION_WORDS: [&str; 20] = [
+    "instrumental", "inst", "clean", "explicit", "dirty", "remix", "mix", "edit", "version", "radio",
+    "extended", "acapella", "cappella", "vocal", "dub", "remaster", "remastered", "live", "demo", "stems",
+];
+
+/// A value used on tracks and how many tracks use it.
+#[derive(Debug, Serialize, Clone, PartialEq)]
+pub struct Suggestion {
+    pub value: String,
+    pub track_count: u64,
+}
+
+/// Metadata of an existing track whose title is close to the one being uploaded.
+#[derive(Debug, Serialize, Clone, PartialEq)]
+pub struct MetadataSuggestion {
+    pub track_id: String,
+    pub title: String,
+    pub artists: Vec<String>,
+    pub writers: Vec<String>,
+    pub writer_percentages: Option<HashMap<String, f32>>,
+    pub publishers: Vec<String>,
+    pub publisher_percentages: Option<HashMap<String, f32>>,
+    pub genre: Vec<String>,
+    pub composers: Vec<String>,
+    /// Similarity of the normalized titles, from 0 to 1; 1 means the same song
+    pub score: f64,
+}
+
+/// Strings in the array `field` of a track. Anything else reads as empty: uploads
+/// store `{}` placeholders for writers and publishers, and legacy tracks a string genre.
+fn string_list(hit: &Document, field: &str) -> Vec<String> {
+    match hit.get(field) {
+        Some(Bson::Array(values)) => values.iter().filter_map(|value| value.as_str().map(str::to_string)).collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// Numeric shares in the split document `field`, skipping entries that aren't numbers.
+fn percentages(hit: &Document, field: &str) -> Option<HashMap<String, f32>> {
+    let shares = hit.get_document(field).ok()?;
+    Some(shares.iter()
+        .filter_map(|(name, share)| {
+            let share = match share {
+                Bson::Double(n) => *n,
+                Bson::Int32(n) => *n as f64,
+                Bson::Int64(n) => *n as f64,
+                _ => return None,
+            };
+            Some((name.clone(), share as f32))
+        })
+        .collect())
+}
+
+fn is_version_tag(part: &str) -> bool {
+    part.split(|c: char| !c.is_alphanumeric()).any(|word| VERSION_WORDS.contains(&word))
+}
+
+/// Lowercased title without trailing version tags ("(Instrumental)", "[Clean]",
+/// "- Remix"), with punctuation collapsed to single spaces.
+pub fn normalize_title(title: &str) -> String {
+    let mut title = title.trim().to_lowercase();
+    loop {
+        let stripped = if let Some(inner) = title.strip_suffix(')').and_then(|rest| rest.rfind('(').map(|start| (start, &rest[start + 1..]))) {
+            is_version_tag(inner.1).then_some(inner.0)
+        } else if let Some(inner) = title.strip_suffix(']').and_then(|rest| rest.rfind('[').map(|start| (start, &rest[start + 1..]))) {
+            is_version_tag(inner.1).then_some(inner.0)
+        } else {
+            title.rfind(" - ").filter(|start| is_version_tag(&title[start + 3..]))
+        };
+        match stripped {
+            // A title that is nothing but a tag is kept as it is
+            Some(end) if !title[..end].trim().is_empty() => title = title[..end].trim_end().to_string(),
+            _ => break,
+        }
+    }
+    title.split(|c: char| !c.is_alphanumeric()).filter(|word| !word.is_empty()).collect::<Vec<_>>().join(" ")
+}
+
+/// Word overlap of two normalized titles (Jaccard index).
+fn title_similarity(a: &str, b: &str) -> f64 {
+    let a: BTreeSet<&str> = a.split(' ').filter(|word| !word.is_empty()).collect();
+    let b: BTreeSet<&str> = b.split(' ').filter(|word| !word.is_empty()).collect();
+    let union = a.union(&b).count();
+    if union == 0 {
+        return 0.0;
+    }
+    a.intersection(&b).count() as f64 / union as f64
+}
+
+/// Text search for tracks sharing words with the normalized title, optionally by an
+/// artist (whole name, ignoring case). Trashed and staged tracks are left out.
+fn metadata_filter(normalized_title: &str, artist: Option<&str>) -> Document {
+    let mut filter = doc! { "$text": { "$search": normalized_title } };
+    if let Some(artist) = artist.map(str::trim).filter(|artist| !artist.is_empty()) {
+        filter.insert("artists", doc! { "$regex": format!("^{}$", regex::escape(artist)), "$options": "i" });
+    }
+    exclude_trashed(&mut filter);
+    exclude_staged(&mut filter);
+    filter
+}
+
+/// Scores text search hits against the normalized title and keeps the best ones. Hits
+/// arrive best text score first, which the stable sort keeps for equal similarity.
+fn rank_candidates(normalized_title: &str, hits: Vec<Document>) -> Vec<MetadataSuggestion> {
+    let mut suggestions: Vec<MetadataSuggestion> = hits.into_iter()
+        .filter_map(|hit| {
+            let track_id = hit.get("_id").and_then(id_to_string)?;
+            let title = hit.get_str("title").unwrap_or_default().to_string();
+            let score = title_similarity(normalized_title, &normalize_title(&title));
+            (score > 0.0).then(|| MetadataSuggestion {
+                track_id,
+                title,
+                artists: string_list(&hit, "artists"),
+                writers: string_list(&hit, "writers"),
+                writer_percentages: percentages(&hit, "writer_percentages"),
+                publishers: string_list(&hit, "publishers"),
+                publisher_percentages: percentages(&hit, "publisher_percentages"),
+                genre: string_list(&hit, "genre"),
+                composers: string_list(&hit, "composers"),
+                score,
+            })
+        })
+        .collect();
+    suggestions.sort_by(|a, b| b.score.total_cmp(&a.score));
+    suggestions.truncate(METADATA_SUGGESTION_LIMIT);
+    suggestions
+}
+
+async fn suggest_metadata_sets(db: &Database, title: &str, artist: Option<&str>) -> Result<Vec<MetadataSuggestion>, CommandError> {
+    let normalized_title = normalize_title(title);
+    if normalized_title.is_empty() {
+        return Ok(Vec::new());
+    }
+    let options = FindOptions::builder()
+        .projection(doc! {
+            "title": 1, "artists": 1, "writers": 1, "writer_percentages": 1, "publishers": 1,
+            "publisher_percentages": 1, "genre": 1, "composers": 1, "text_score": { "$meta": "textScore" },
+        })
+        .sort(doc! { "text_score": { "$meta": "textScore" } })
+        .limit(METADATA_CANDIDATE_LIMIT)
+        .build();
+    let hits: Vec<Document> = db.tracks::<Document>()
+        .find(metadata_filter(&normalized_title, artist), options)
+        .await?
+        .try_collect()
+        .await?;
+    Ok(rank_candidates(&normalized_title, hits))
+}
+
+/// Aggregation returning the `limit` most used values of an array field starting with
+/// `prefix`, ignoring case. Ties are ordered alphabetically. Trashed and staged tracks
+/// are left out.
+fn suggestion_pipeline(field: &str, prefix: &str, limit: u32) -> Vec<Document> {
+    let matches_prefix = doc! { "$regex": format!("^{}", regex::escape(prefix.trim())), "$options": "i" };
+    let path = format!("${}", field);
+    let mut tracks = doc! { field: matches_prefix.clone() };
+    exclude_trashed(&mut tracks);
+    exclude_staged(&mut tracks);
+    vec![
+        // Narrows to tracks with a matching value; the unwound values are filtered again
+        // because other entries of the same array may not match
+        doc! { "$match": tracks },
+        doc! { "$unwind": &path },
+        doc! { "$match": { field: matches_prefix } },
+        doc! { "$group": { "_id": &path, "count": { "$sum": 1 } } },
+        doc! { "$sort": { "count": -1, "_id": 1 } },
+        doc! { "$limit": limit as i64 },
+    ]
+}
+
+async fn suggest(db: &Database, field: &str, prefix: &str, limit: Option<u32>) -> Result<Vec<Suggestion>, CommandError> {
+    let limit = limit.unwrap_or(DEFAULT_SUGGESTION_LIMIT).clamp(1, MAX_SUGGESTION_LIMIT);
+    let mut groups = db.tracks::<Document>()
+        .aggregate(suggestion_pipeline(field, prefix, limit), None)
+        .await?;
+    let mut suggestions = Vec::new();
+    while let Some(group) = groups.try_next().await? {
+        let Ok(value) = group.get_str("_id") else { continue };
+        if value.trim().is_empty() {
+            continue;
+        }
+        let track_count = match group.get("count") {
+            Some(Bson::Int32(n)) => *n as u64,
+            Some(Bson::Int64(n)) => *n as u64,
+            _ => 0,
+        };
+        suggestions.push(Suggestion { value: value.to_string(), track_count });
+    }
+    Ok(suggestions)
+}
+
+
+// --- Tauri Commands ---
+
+/// Writers used on tracks that start with `prefix`, most used first.
+#[command]
+pub async fn suggest_writers(prefix: String, limit: Option<u32>, mongo_state: State<'_, MongoState>) -> Result<Vec<Suggestion>, CommandError> {
+    suggest(&mongo_state.database().await?, "writers", &prefix, limit).await
+}
+
+/// Publishers used on tracks that start with `prefix`, most used first.
+#[command]
+pub async fn suggest_publishers(prefix: String, limit: Option<u32>, mongo_state: State<'_, MongoState>) -> Result<Vec<Suggestion>, CommandError> {
+    suggest(&mongo_state.database().await?, "publishers", &prefix, limit).await
+}
+
+/// Genres used on tracks that start with `prefix`, most used first.
+#[command]
+pub async fn suggest_genres(prefix: String, limit: Option<u32>, mongo_state: State<'_, MongoState>) -> Result<Vec<Suggestion>, CommandError> {
+    suggest(&mongo_state.database().await?, "genre", &prefix, limit).await
+}
+
+/// Metadata of up to 5 existing tracks with a title close to `title`, ignoring version
+/// tags such as "(Instrumental)", best match first. `artist` limits the search to tracks
+/// by that artist.
+#[command]
+pub async fn suggest_metadata(title: String, artist: Option<String>, mongo_state: State<'_, MongoState>) -> Result<Vec<MetadataSuggestion>, CommandError> {
+    suggest_metadata_sets(&mongo_state.database().await?, &title, artist.as_deref()).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_suggestion_pipeline_escapes_prefix() {
+        let pipeline = suggestion_pipeline("writers", " J. Smith (", 5);
+        let expected = doc! { "writers": { "$regex": r"^J\. Smith \(", "$options": "i" } };
+        let mut tracks = expected.clone();
+        exclude_trashed(&mut tracks);
+        exclude_staged(&mut tracks);
+        assert_eq!(pipeline[0], doc! { "$match": tracks });
+        assert_eq!(pipeline[2], doc! { "$match": expected });
+        assert_eq!(pipeline[5], doc! { "$limit": 5_i64 });
+    }
+
+    #[test]
+    fn test_normalize_title_strips_version_tags() {
+        assert_eq!(normalize_title("Summer Nights (Instrumental)"), "summer nights");
+        assert_eq!(normalize_title("Summer Nights [Clean] (Radio Edit)"), "summer nights");
+        assert_eq!(normalize_title("Summer Nights - DJ Sol Remix"), "summer nights");
+        assert_eq!(normalize_title("  SUMMER   nights! "), "summer nights");
+        // Only version tags go; other brackets and dashes are part of the title
+        assert_eq!(normalize_title("Nights (Part II)"), "nights part ii");
+        assert_eq!(normalize_title("Up - Down"), "up down");
+        assert_eq!(normalize_title("(Instrumental)"), "instrumental");
+    }
+
+    #[test]
+    fn test_candidates_are_ranked_by_title_similarity() {
+        let hits = vec![
+            doc! { "_id": "t1", "title": "Summer Nights Forever", "writers": ["A"] },
+            doc! { "_id": "t2", "title": "Summer Nights (Clean)", "writers": ["B"], "writer_percentages": { "B": 100 }, "genre": ["Pop"] },
+            doc! { "_id": "t3", "title": "Winter Mornings", "writers": ["C"] },
+        ];
+        let ranked = rank_candidates(&normalize_title("Summer Nights - Remix"), hits);
+        assert_eq!(ranked.iter().map(|s| s.track_id.as_str()).collect::<Vec<_>>(), vec!["t2", "t1"]);
+        assert_eq!(ranked[0].score, 1.0);
+        assert_eq!(ranked[0].writer_percentages.as_ref().unwrap()["B"], 100.0);
+        assert_eq!(ranked[0].genre, vec!["Pop".to_string()]);
+        assert!(ranked[0].composers.is_empty());
+
+        let filter = metadata_filter("summer nights", Some(" The Band. "));
+        assert_eq!(filter.get_document("artists").unwrap(), &doc! { "$regex": r"^The Band\.$", "$options": "i" });
+        assert!(!metadata_filter("summer nights", Some(" ")).contains_key("artists"));
+    }
+
+    #[test]
+    fn test_candidates_with_placeholder_fields_are_kept() {
+        // As stored by the upload pipeline, and a legacy track with a string genre
+        let hits = vec![
+            doc! { "_id": "t1", "title": "Summer Nights", "writers": {}, "publishers": ["Pub"], "publisher_percentages": { "Pub": 100.0 } },
+            doc! { "_id": "t2", "title": "Summer Nights (Clean)", "writers": ["B"], "publishers": {}, "genre": "Pop", "composers": Bson::Null },
+        ];
+        let ranked = rank_candidates(&normalize_title("Summer Nights"), hits);
+        assert_eq!(ranked.len(), 2);
+        assert!(ranked[0].writers.is_empty());
+        assert_eq!(ranked[0].publishers, vec!["Pub".to_string()]);
+        assert_eq!(ranked[0].publisher_percentages.as_ref().unwrap()["Pub"], 100.0);
+        assert_eq!(ranked[1].writers, vec!["B".to_string()]);
+        assert!(ranked[1].publishers.is_empty());
+        assert!(ranked[1].genre.is_empty());
+        assert!(ranked[1].composers.is_empty());
+    }
+}
@@ -0,0 +1,156 @@
+//! Deleting delivery (transcoded) copies from R2 to reclaim space. Archive copies are
+//! never touched, so a purged delivery copy can be transcoded again from the archive.
+
+use futures_util::stream::TryStreamExt;
+use log::{info, warn};
+use mongodb::bson::{doc, oid::ObjectId, Bson, Document};
+use mongodb::options::FindOptions;
+use serde::{Deserialize, Serialize};
+use tauri::{command, State};
+
+use super::changes::touched;
+use super::storage::id_to_string;
+use crate::core::r2::R2Client;
+use crate::CommandError;
+use crate::{MongoState, R2State};
+use crate::core::db_config::{CatalogCollections, CatalogDatabase, cursor_batch_size};
+
+/// Which tracks a bulk storage command applies to: `"all"` or `{ "ids": [...] }`.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TrackSelection {
+    All,
+    Ids(Vec<String>),
+}
+
+/// Outcome of `purge_transcoded_copies`.
+#[derive(Debug, Serialize, Clone, Default, PartialEq)]
+pub struct PurgeResult {
+    /// Tracks whose delivery copy was deleted
+    pub purged: usize,
+    pub bytes_freed: u64,
+    /// Selected tracks without a separate delivery copy
+    pub skipped: usize,
+    pub failed: usize,
+}
+
+/// The track's delivery key, unless it has none or it is also the archive key.
+fn delivery_key_to_purge(track_doc: &Document) -> Option<String> {
+    let non_empty = |field: &str| track_doc.get_str(field).ok().filter(|key| !key.is_empty());
+    let delivery_key = non_empty("r2_delivery_key").or_else(|| non_empty("r2_aac_key"))?;
+    if [non_empty("r2_archive_key"), non_empty("r2_original_key")].contains(&Some(delivery_key)) {
+        return None;
+    }
+    Some(delivery_key.to_string())
+}
+
+fn selection_filter(selection: &TrackSelection) -> Document {
+    match selection {
+        TrackSelection::All => doc! { "$or": [
+            { "r2_delivery_key": { "$nin": [null, ""] } },
+            { "r2_aac_key": { "$nin": [null, ""] } },
+        ] },
+        // Match both ObjectId and string ids
+        TrackSelection::Ids(ids) => {
+            let ids: Vec<Bson> = ids.iter()
+                .map(|id| ObjectId::parse_str(id).map(Bson::ObjectId).unwrap_or_else(|_| Bson::String(id.clone())))
+                .collect();
+            doc! { "_id": { "$in": ids } }
+        }
+    }
+}
+
+// --- Tauri Commands ---
+
+/// Deletes the delivery copy of the selected tracks from R2 and clears `r2_delivery_key`
+/// (and the legacy `r2_aac_key`). Each object is deleted from the bucket recorded on the
+/// track, falling back to the configured bucket.
+#[command]
+pub async fn purge_transcoded_copies(
+    tracks: TrackSelection,
+    mongo_state: State<'_, MongoState>,
+    r2_state: State<'_, R2State>,
+) -> Result<PurgeResult, CommandError> {
+    if tracks == TrackSelection::Ids(Vec::new()) {
+        return Err(CommandError::Validation("No tracks selected".to_string()));
+    }
+    let tracks_collection = {
+        let client_lock = mongo_state.client.lock().await;
+        let client = client_lock.as_ref()
+            .ok_or_else(|| CommandError::Configuration("MongoDB client not initialized".to_string()))?;
+        client.catalog_database().tracks::<Document>()
+    };
+    let r2_client = R2Client::from_state(&r2_state).await?;
+
+    let projection = doc! { "_id": 1, "r2_bucket": 1, "r2_delivery_key": 1, "r2_aac_key": 1, "r2_archive_key": 1, "r2_original_key": 1 };
+    let options = FindOptions::builder().batch_size(cursor_batch_size()).projection(projection).build();
+    let track_docs: Vec<Document> = tracks_collection.find(selection_filter(&tracks), options).await?.try_collect().await?;
+
+    let mut result = PurgeResult::default();
+    if let TrackSelection::Ids(ids) = &tracks {
+        // Ids with no track document count as skipped
+        result.skipped = ids.len().saturating_sub(track_docs.len());
+    }
+    for track_doc in &track_docs {
+        let track_id = track_doc.get("_id").and_then(id_to_string).unwrap_or_default();
+        let Some(key) = delivery_key_to_purge(track_doc) else {
+            result.skipped += 1;
+            continue;
+        };
+        let bucket_client = r2_client.for_track(track_doc);
+
+        // A missing object is already gone; the field is still cleared
+        let size = match bucket_client.object_size(&key).await {
+            Ok(size) => size.unwrap_or(0),
+            Err(e) => {
+                warn!("Could not check delivery copy {} of track {}: {}", key, track_id, e);
+                result.failed += 1;
+                continue;
+            }
+        };
+        if let Err(e) = bucket_client.delete_object(&key).await {
+            warn!("Failed to delete delivery copy {} of track {}: {}", key, track_id, e);
+            result.failed += 1;
+            continue;
+        }
+        tracks_collection
+            .update_one(
+                doc! { "_id": track_doc.get("_id").cloned().unwrap_or(Bson::Null) },
+                touched(doc! { "$set": { "r2_delivery_key": null, "r2_aac_key": null } }),
+                None,
+            )
+            .await?;
+        result.purged += 1;
+        result.bytes_freed += size.max(0) as u64;
+    }
+
+    info!("Purged transcoded copies: {:?}", result);
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_delivery_key_to_purge() {
+        assert_eq!(
+            delivery_key_to_purge(&doc! { "r2_delivery_key": "tracks/aac/a.m4a", "r2_archive_key": "tracks/original/a.wav" }),
+            Some("tracks/aac/a.m4a".to_string())
+        );
+        // Legacy tracks only have r2_aac_key
+        assert_eq!(delivery_key_to_purge(&doc! { "r2_aac_key": "tracks/aac/b.m4a", "r2_delivery_key": null }), Some("tracks/aac/b.m4a".to_string()));
+        assert_eq!(delivery_key_to_purge(&doc! { "r2_delivery_key": "", "r2_original_key": "tracks/original/c.wav" }), None);
+        // Never delete an object that is also the archive copy
+        assert_eq!(delivery_key_to_purge(&doc! { "r2_delivery_key": "same.wav", "r2_original_key": "same.wav" }), None);
+    }
+
+    #[test]
+    fn test_track_selection_deserializes() {
+        assert_eq!(serde_json::from_str::<TrackSelection>(r#""all""#).unwrap(), TrackSelection::All);
+        assert_eq!(
+            serde_json::from_str::<TrackSelection>(r#"{"ids":["a","b"]}"#).unwrap(),
+            TrackSelection::Ids(vec!["a".to_string(), "b".to_string()])
+        );
+    }
+}
@@ -0,0 +1,202 @@
+//! Soft deletion of tracks. Trashed tracks keep their documents and R2 objects, carry a
+//! `deleted_at` timestamp, and are left out of track listings and searches until they
+//! are restored or the trash is emptied.
+
+use futures_util::stream::TryStreamExt;
+use log::{info, warn};
+use mongodb::bson::{doc, oid::ObjectId, Bson, DateTime, Document};
+use mongodb::options::FindOptions;
+use serde::Serialize;
+use std::collections::BTreeSet;
+use std::time::{Duration, SystemTime};
+use tauri::{command, AppHandle, State, Wry};
+
+use super::album_names::AlbumNameCache;
+use super::changes::{record_deletions, touched, CatalogKind};
+use super::delete_preview::{check_fingerprint, resolve_tracks};
+use super::storage::id_to_string;
+use super::storage::mongodb::{fetch_tracks_by_ids, TrackWithAlbum};
+use crate::core::r2::{R2Client, TRACK_KEY_FIELDS};
+use crate::features::activity::{record_activity, ActivityAction, ActivityEntry};
+use crate::CommandError;
+use crate::{MongoState, R2State};
+use crate::core::db_config::{CatalogCollections, cursor_batch_size};
+
+/// How long tracks stay in the trash before `empty_trash` deletes them by default.
+pub const DEFAULT_TRASH_RETENTION_DAYS: u32 = 30;
+
+/// Outcome of `empty_trash`.
+#[derive(Debug, Serialize, Clone, Default, PartialEq)]
+pub struct EmptyTrashResult {
+    pub deleted: usize,
+    /// Tracks kept because one of their R2 objects could not be deleted
+    pub failed: usize,
+}
+
+/// Adds the condition that leaves trashed tracks out of a track query.
+pub fn exclude_trashed(filter: &mut Document) {
+    filter.insert("deleted_at", doc! { "$exists": false });
+}
+
+/// Matches both ObjectId and string ids.
+pub fn ids_filter(track_ids: &[String]) -> Document {
+    let ids: Vec<Bson> = track_ids.iter()
+        .map(|id| ObjectId::parse_str(id).map(Bson::ObjectId).unwrap_or_else(|_| Bson::String(id.clone())))
+        .collect();
+    doc! { "_id": { "$in": ids } }
+}
+
+/// The distinct R2 objects referenced by a track.
+fn audio_keys(track_doc: &Document) -> BTreeSet<String> {
+    TRACK_KEY_FIELDS.iter()
+        .filter_map(|field| track_doc.get_str(field).ok().filter(|key| !key.is_empty()))
+        .map(String::from)
+        .collect()
+}
+
+/// Tracks trashed before this time are due for deletion.
+fn retention_cutoff(now: SystemTime, retention_days: u32) -> DateTime {
+    let retention = Duration::from_secs(u64::from(retention_days) * 24 * 60 * 60);
+    DateTime::from_system_time(now.checked_sub(retention).unwrap_or(SystemTime::UNIX_EPOCH))
+}
+
+
+// --- Tauri Commands ---
+
+/// Moves tracks to the trash. Returns the number of tracks newly trashed; tracks already
+/// in the trash keep their original `deleted_at`. With `expected_fingerprint` from
+/// `preview_delete`, refuses to run if the selection changed since the preview.
+#[command]
+pub async fn trash_tracks(
+    track_ids: Vec<String>,
+    expected_fingerprint: Option<String>,
+    app_handle: AppHandle<Wry>,
+    mongo_state: State<'_, MongoState>,
+) -> Result<u64, CommandError> {
+    if track_ids.is_empty() {
+        return Err(CommandError::Validation("No tracks selected".to_string()));
+    }
+    let db = mongo_state.database().await?;
+    if expected_fingerprint.is_some() {
+        check_fingerprint(&resolve_tracks(&db, &track_ids).await?, expected_fingerprint.as_deref())?;
+    }
+    let mut filter = ids_filter(&track_ids);
+    exclude_trashed(&mut filter);
+    let result = db.tracks::<Document>()
+        .update_many(filter, touched(doc! { "$set": { "deleted_at": DateTime::now() } }), None)
+        .await?;
+
+    info!("Moved {} tracks to the trash", result.modified_count);
+    let summary = format!("Moved {} track{} to the trash", result.modified_count, if result.modified_count == 1 { "" } else { "s" });
+    record_activity(&app_handle, Some(&db), ActivityEntry::new(ActivityAction::TracksDeleted, track_ids, summary)).await;
+    Ok(result.modified_count)
+}
+
+/// Takes tracks back out of the trash. Returns the number of tracks restored.
+#[command]
+pub async fn restore_tracks(track_ids: Vec<String>, mongo_state: State<'_, MongoState>) -> Result<u64, CommandError> {
+    if track_ids.is_empty() {
+        return Err(CommandError::Validation("No tracks selected".to_string()));
+    }
+    let db = mongo_state.database().await?;
+    let result = db.tracks::<Document>()
+        .update_many(ids_filter(&track_ids), touched(doc! { "$unset": { "deleted_at": "" } }), None)
+        .await?;
+    info!("Restored {} tracks from the trash", result.modified_count);
+    Ok(result.modified_count)
+}
+
+/// Lists trashed tracks, most recently trashed first.
+#[command]
+pub async fn list_trashed_tracks(
+    mongo_state: State<'_, MongoState>,
+    album_cache: State<'_, AlbumNameCache>,
+) -> Result<Vec<TrackWithAlbum>, CommandError> {
+    let db = mongo_state.database().await?;
+    let options = FindOptions::builder().batch_size(cursor_batch_size()).projection(doc! { "_id": 1 }).sort(doc! { "deleted_at": -1 }).build();
+    let trashed: Vec<Document> = db.tracks::<Document>()
+        .find(doc! { "deleted_at": { "$exists": true } }, options)
+        .await?
+        .try_collect()
+        .await?;
+    let object_ids: Vec<ObjectId> = trashed.iter().filter_map(|track| track.get_object_id("_id").ok()).collect();
+    Ok(fetch_tracks_by_ids(&db, &album_cache, &object_ids).await?)
+}
+
+/// Permanently deletes tracks that have been in the trash for longer than
+/// `retention_days` (default 30; 0 empties the whole trash): their R2 objects first,
+/// then their documents. A track whose objects cannot all be deleted stays in the trash.
+#[command]
+pub async fn empty_trash(
+    retention_days: Option<u32>,
+    app_handle: AppHandle<Wry>,
+    mongo_state: State<'_, MongoState>,
+    r2_state: State<'_, R2State>,
+) -> Result<EmptyTrashResult, CommandError> {
+    let cutoff = retention_cutoff(SystemTime::now(), retention_days.unwrap_or(DEFAULT_TRASH_RETENTION_DAYS));
+    let db = mongo_state.database().await?;
+    let tracks_collection = db.tracks::<Document>();
+    let r2_client = R2Client::from_state(&r2_state).await?;
+
+    let expired: Vec<Document> = tracks_collection
+        .find(doc! { "deleted_at": { "$lte": cutoff } }, None)
+        .await?
+        .try_collect()
+        .await?;
+
+    let mut result = EmptyTrashResult::default();
+    let mut deleted_ids = Vec::new();
+    for track_doc in &expired {
+        let track_id = track_doc.get("_id").and_then(id_to_string).unwrap_or_default();
+        let bucket_client = r2_client.for_track(track_doc);
+        let keys: Vec<String> = audio_keys(track_doc).into_iter().collect();
+        if let Err(e) = bucket_client.delete_objects(&keys).await {
+            warn!("Failed to delete R2 objects of trashed track {}: {}", track_id, e);
+            result.failed += 1;
+            continue;
+        }
+        if let Some(id) = track_doc.get("_id") {
+            deleted_ids.push(id.clone());
+        }
+    }
+
+    if !deleted_ids.is_empty() {
+        let deleted = tracks_collection.delete_many(doc! { "_id": { "$in": &deleted_ids } }, None).await?;
+        result.deleted = deleted.deleted_count as usize;
+        // Albums created by the old pipeline list their tracks by string id
+        let track_ids: Vec<String> = deleted_ids.iter().filter_map(id_to_string).collect();
+        record_deletions(&db, CatalogKind::Track, track_ids.clone()).await;
+        db.albums::<Document>()
+            .update_many(doc! { "track_ids": { "$in": &track_ids } }, touched(doc! { "$pull": { "track_ids": { "$in": &track_ids } } }), None)
+            .await?;
+        let summary = format!("Permanently deleted {} track{} from the trash", result.deleted, if result.deleted == 1 { "" } else { "s" });
+        record_activity(&app_handle, Some(&db), ActivityEntry::new(ActivityAction::TracksDeleted, track_ids, summary)).await;
+    }
+
+    info!("Emptied trash: {:?}", result);
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_audio_keys_are_deduplicated() {
+        let track_doc = doc! {
+            "r2_archive_key": "tracks/original/a.wav",
+            "r2_original_key": "tracks/original/a.wav",
+            "r2_delivery_key": "tracks/aac/a.m4a",
+            "r2_aac_key": "",
+        };
+        assert_eq!(audio_keys(&track_doc).into_iter().collect::<Vec<_>>(), vec!["tracks/aac/a.m4a", "tracks/original/a.wav"]);
+    }
+
+    #[test]
+    fn test_retention_cutoff() {
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(40 * 24 * 60 * 60);
+        assert_eq!(retention_cutoff(now, 30), DateTime::from_millis(10 * 24 * 60 * 60 * 1000));
+        assert_eq!(retention_cutoff(now, 0), DateTime::from_system_time(now));
+        assert_eq!(retention_cutoff(now, 100), DateTime::from_millis(0));
+    }
+}
@@ -0,0 +1,134 @@
+//! Optional controlled vocabulary for genre and mood values, stored in the `settings`
+//! collection. When enabled, metadata edits may only use values from these lists.
+
+use log::info;
+use mongodb::bson::{doc, from_document, to_document};
+use mongodb::options::ReplaceOptions;
+use mongodb::Database;
+use serde::{Deserialize, Serialize};
+use tauri::{command, State};
+
+use super::genres::{clean_genre, genre_key};
+use crate::CommandError;
+use crate::MongoState;
+
+/// `_id` of the settings document holding the vocabulary.
+const VOCABULARY_SETTINGS_ID: &str = "vocabulary";
+
+/// Allowed genre and mood values. Enforcement is off unless `enabled` is set.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct Vocabulary {
+    #[serde(default)]
+    pub enabled: bool,
+    #[serde(default)]
+    pub genres: Vec<String>,
+    #[serde(default)]
+    pub moods: Vec<String>,
+}
+
+/// Cleans and de-duplicates a vocabulary list, treating spelling variants as duplicates.
+fn clean_list(values: &[String]) -> Vec<String> {
+    let mut cleaned: Vec<String> = Vec::new();
+    for value in values.iter().filter_map(|v| clean_genre(v)) {
+        if !cleaned.iter().any(|existing| genre_key(existing) == genre_key(&value)) {
+            cleaned.push(value);
+        }
+    }
+    cleaned
+}
+
+/// Maps each value onto its vocabulary entry, matching spelling variants
+/// ("hip-hop" -> "Hip Hop"). Values with no matching entry are rejected.
+pub fn apply_vocabulary(values: &[String], allowed: &[String], field: &str) -> Result<Vec<String>, String> {
+    let mut normalized: Vec<String> = Vec::with_capacity(values.len());
+    let mut rejected: Vec<String> = Vec::new();
+
+    for value in values.iter().filter_map(|v| clean_genre(v)) {
+        match allowed.iter().find(|entry| genre_key(entry) == genre_key(&value)) {
+            Some(entry) if !normalized.contains(entry) => normalized.push(entry.clone()),
+            Some(_) => {}
+            None => rejected.push(value),
+        }
+    }
+
+    if rejected.is_empty() {
+        Ok(normalized)
+    } else {
+        Err(format!("{} not in the allowed vocabulary: {}", field, rejected.join(", ")))
+    }
+}
+
+/// Loads the vocabulary settings, returning a disabled, empty vocabulary if none are stored.
+pub async fn load_vocabulary(db: &Database) -> Result<Vocabulary, mongodb::error::Error> {
+    let settings = db.collection::<mongodb::bson::Document>("settings");
+    match settings.find_one(doc! { "_id": VOCABULARY_SETTINGS_ID }, None).await? {
+        Some(settings_doc) => Ok(from_document(settings_doc).unwrap_or_default()),
+        None => Ok(Vocabulary::default()),
+    }
+}
+
+
+// --- Tauri Commands ---
+
+/// Returns the current genre/mood vocabulary settings.
+#[command]
+pub async fn get_vocabulary(mongo_state: State<'_, MongoState>) -> Result<Vocabulary, CommandError> {
+    let db = mongo_state.database().await?;
+    Ok(load_vocabulary(&db).await?)
+}
+
+/// Replaces the genre/mood vocabulary settings and returns the cleaned lists as stored.
+#[command]
+pub async fn set_vocabulary(vocabulary: Vocabulary, mongo_state: State<'_, MongoState>) -> Result<Vocabulary, CommandError> {
+    let vocabulary = Vocabulary {
+        enabled: vocabulary.enabled,
+        genres: clean_list(&vocabulary.genres),
+        moods: clean_list(&vocabulary.moods),
+    };
+    if vocabulary.enabled && vocabulary.genres.is_empty() && vocabulary.moods.is_empty() {
+        return Err(CommandError::Validation("Cannot enable an empty vocabulary".to_string()));
+    }
+
+    let db = mongo_state.database().await?;
+    let mut settings_doc = to_document(&vocabulary)
+        .map_err(|e| CommandError::Database(format!("Failed to serialize vocabulary: {}", e)))?;
+    settings_doc.insert("_id", VOCABULARY_SETTINGS_ID);
+
+    let options = ReplaceOptions::builder().upsert(true).build();
+    db.collection::<mongodb::bson::Document>("settings")
+        .replace_one(doc! { "_id": VOCABULARY_SETTINGS_ID }, settings_doc, options)
+        .await?;
+    info!(
+        "Saved vocabulary ({} genres, {} moods, enforcement {})",
+        vocabulary.genres.len(), vocabulary.moods.len(), if vocabulary.enabled { "on" } else { "off" }
+    );
+    Ok(vocabulary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn list(values: &[&str]) -> Vec<String> {
+        values.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn test_apply_vocabulary_normalizes_variants() {
+        let allowed = list(&["Hip Hop", "Jazz"]);
+        let result = apply_vocabulary(&list(&["hip-hop", " HipHop ", "jazz"]), &allowed, "Genre");
+        assert_eq!(result, Ok(list(&["Hip Hop", "Jazz"])));
+    }
+
+    #[test]
+    fn test_apply_vocabulary_rejects_unknown_values() {
+        let allowed = list(&["Uplifting"]);
+        let err = apply_vocabulary(&list(&["Uplifting", "Dark", "Tense"]), &allowed, "Mood").unwrap_err();
+        assert_eq!(err, "Mood not in the allowed vocabulary: Dark, Tense");
+    }
+
+    #[test]
+    fn test_clean_list_drops_blanks_and_variants() {
+        assert_eq!(clean_list(&list(&["Hip Hop", "hip-hop", "  ", "Soul"])), list(&["Hip Hop", "Soul"]));
+    }
+}
@@ -0,0 +1,178 @@
+//! Bulk backfill of `waveform_data` for tracks uploaded before waveforms were generated.
+
+use futures_util::stream::{self, StreamExt, TryStreamExt};
+use log::{info, warn};
+use mongodb::bson::{doc, Document};
+use mongodb::options::FindOptions;
+use mongodb::Collection;
+use serde::Serialize;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use tauri::{command, AppHandle, Emitter, State, Wry};
+use tempfile::Builder as TempFileBuilder;
+
+use super::changes::touched;
+use super::storage::id_to_string;
+use crate::core::encryption::readable_bytes;
+use crate::core::r2::{R2Client, PLAYBACK_KEY_FIELDS};
+use crate::features::upload::audio::waveform::{compute_peaks, DEFAULT_WAVEFORM_PEAKS};
+use crate::CommandError;
+use crate::{MongoState, R2State};
+use crate::core::db_config::{CatalogCollections, CatalogDatabase, cursor_batch_size};
+
+const DEFAULT_CONCURRENCY: usize = 4;
+const MAX_CONCURRENCY: usize = 16;
+
+/// Tracks whether a regeneration run is active and lets the frontend cancel it.
+#[derive(Debug, Default)]
+pub struct WaveformState {
+    pub is_running: Arc<AtomicBool>,
+    pub cancel_flag: Arc<AtomicBool>,
+}
+
+/// Payload of `waveform://progress` events.
+#[derive(Debug, Serialize, Clone)]
+pub struct WaveformProgress {
+    pub processed: usize,
+    pub total: usize,
+    pub updated: usize,
+    pub failed: usize,
+}
+
+/// Outcome of a regeneration run.
+#[derive(Debug, Serialize, Clone)]
+pub struct WaveformRegenerationResult {
+    pub updated: usize,
+    pub failed: usize,
+    pub cancelled: bool,
+}
+
+/// Downloads one track's audio, computes its peaks, and stores them on the document.
+async fn regenerate_one(tracks_collection: &Collection<Document>, r2_client: &R2Client, track_doc: &Document) -> Result<(), String> {
+    let track_id = track_doc.get("_id").cloned().ok_or_else(|| "Track has no _id".to_string())?;
+    // The delivery rendition is much smaller than the archive and good enough for peaks
+    let key = PLAYBACK_KEY_FIELDS
+        .iter()
+        .find_map(|field| track_doc.get_str(field).ok().filter(|key| !key.is_empty()))
+        .ok_or_else(|| "Track has no R2 audio key".to_string())?;
+
+    let bytes = r2_client.for_track(track_doc).download_object(key).await.map_err(|e| format!("Download of {} failed: {}", key, e))?;
+    let bytes = readable_bytes(track_doc, key, bytes).map_err(|e| e.to_string())?;
+    let suffix = Path::new(key).extension().map(|ext| format!(".{}", ext.to_string_lossy())).unwrap_or_default();
+    let temp_file = TempFileBuilder::new().prefix("waveform_").suffix(&suffix).tempfile().map_err(|e| e.to_string())?;
+    std::fs::write(temp_file.path(), bytes).map_err(|e| e.to_string())?;
+
+    let temp_path = temp_file.path().to_path_buf();
+    let peaks = tokio::task::spawn_blocking(move || compute_peaks(&temp_path, DEFAULT_WAVEFORM_PEAKS))
+        .await
+        .map_err(|e| format!("Waveform task join error: {}", e))??;
+
+    tracks_collection
+        .update_one(doc! { "_id": track_id }, touched(doc! { "$set": { "waveform_data": peaks } }), None)
+        .await
+        .map_err(|e| format!("Failed to store waveform: {}", e))?;
+    Ok(())
+}
+
+async fn run_regeneration(
+    concurrency: usize,
+    app_handle: &AppHandle<Wry>,
+    cancel_flag: &AtomicBool,
+    mongo_state: &MongoState,
+    r2_state: &R2State,
+) -> Result<WaveformRegenerationResult, CommandError> {
+    let tracks_collection = {
+        let client_lock = mongo_state.client.lock().await;
+        let client = client_lock.as_ref()
+            .ok_or_else(|| CommandError::Configuration("MongoDB client not initialized".to_string()))?;
+        client.catalog_database().tracks::<Document>()
+    };
+    let r2_client = R2Client::from_state(r2_state).await?;
+
+    // Missing, null, and empty waveforms all need regenerating
+    let projection = doc! {
+        "_id": 1, "r2_delivery_key": 1, "r2_aac_key": 1, "r2_original_key": 1, "r2_archive_key": 1,
+        "encrypted": 1, "encryption_key_id": 1, "r2_bucket": 1,
+    };
+    let options = FindOptions::builder().batch_size(cursor_batch_size()).projection(projection).build();
+    let tracks: Vec<Document> = tracks_collection
+        .find(doc! { "waveform_data": { "$in": [null, []] } }, options)
+        .await?
+        .try_collect()
+        .await?;
+    let total = tracks.len();
+    info!("Regenerating waveforms for {} tracks ({} at a time)", total, concurrency);
+
+    let processed = AtomicUsize::new(0);
+    let updated = AtomicUsize::new(0);
+    let failed = AtomicUsize::new(0);
+
+    stream::iter(tracks)
+        .map(|track_doc| {
+            let (tracks_collection, r2_client) = (&tracks_collection, &r2_client);
+            let (processed, updated, failed) = (&processed, &updated, &failed);
+            async move {
+                if cancel_flag.load(Ordering::SeqCst) {
+                    return;
+                }
+                match regenerate_one(tracks_collection, r2_client, &track_doc).await {
+                    Ok(()) => { updated.fetch_add(1, Ordering::SeqCst); }
+                    Err(e) => {
+                        let track_id = track_doc.get("_id").and_then(id_to_string).unwrap_or_default();
+                        warn!("Waveform regeneration failed for track {}: {}", track_id, e);
+                        failed.fetch_add(1, Ordering::SeqCst);
+                    }
+                }
+                let progress = WaveformProgress {
+                    processed: processed.fetch_add(1, Ordering::SeqCst) + 1,
+                    total,
+                    updated: updated.load(Ordering::SeqCst),
+                    failed: failed.load(Ordering::SeqCst),
+                };
+                let _ = app_handle.emit("waveform://progress", progress);
+            }
+        })
+        .buffer_unordered(concurrency)
+        .collect::<Vec<()>>()
+        .await;
+
+    let result = WaveformRegenerationResult {
+        updated: updated.load(Ordering::SeqCst),
+        failed: failed.load(Ordering::SeqCst),
+        cancelled: cancel_flag.load(Ordering::SeqCst),
+    };
+    info!("Waveform regeneration finished: {:?}", result);
+    Ok(result)
+}
+
+// --- Tauri Commands ---
+
+/// Computes and stores waveforms for every track missing them, `concurrency` tracks
+/// at a time. Emits `waveform://progress` after each track.
+#[command]
+pub async fn regenerate_waveforms(
+    concurrency: Option<usize>,
+    app_handle: AppHandle<Wry>,
+    waveform_state: State<'_, WaveformState>,
+    mongo_state: State<'_, MongoState>,
+    r2_state: State<'_, R2State>,
+) -> Result<WaveformRegenerationResult, CommandError> {
+    let concurrency = concurrency.unwrap_or(DEFAULT_CONCURRENCY).clamp(1, MAX_CONCURRENCY);
+    if waveform_state.is_running.compare_exchange(false, true, Ordering::SeqCst, Ordering::SeqCst).is_err() {
+        return Err(CommandError::OperationFailed("Waveform regeneration is already running".to_string()));
+    }
+    waveform_state.cancel_flag.store(false, Ordering::SeqCst);
+
+    let result = run_regeneration(concurrency, &app_handle, &waveform_state.cancel_flag, &mongo_state, &r2_state).await;
+    waveform_state.is_running.store(false, Ordering::SeqCst);
+    result
+}
+
+/// Stops a running regeneration after the tracks already in flight finish.
+#[command]
+pub async fn cancel_waveform_regeneration(waveform_state: State<'_, WaveformState>) -> Result<(), CommandError> {
+    info!("Received request to cancel waveform regeneration.");
+    waveform_state.cancel_flag.store(true, Ordering::SeqCst);
+    Ok(())
+}
@@ -0,0 +1,475 @@
+//! Where credentials are kept: the OS keychain when one is usable, otherwise an
+//! encrypted file in the user's config directory.
+//!
+//! The file is sealed with ChaCha20-Poly1305 under a key derived (Argon2id) from a
+//! user passphrase and the machine identifier, so a copied file is useless on another
+//! machine or without the passphrase. The passphrase is supplied at runtime through
+//! `unlock_credential_store`, or via `CREDENTIALS_PASSPHRASE_ENV` for headless use.
+//!
+//! When the user denies a keychain prompt (macOS), the keychain isn't asked again for
+//! `ACCESS_DENIED_COOLDOWN` or until `clear_access_denial`; credential reads and writes
+//! fail with `CredentialsError::AccessDenied` in the meantime instead of re-prompting.
+
+use argon2::Argon2;
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use chacha20poly1305::aead::{Aead, KeyInit};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use keyring::Entry;
+use log::{info, warn};
+use rand::rngs::OsRng;
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+use ts_rs::TS;
+
+use super::CredentialsError;
+
+/// Environment variable read as the file passphrase when none was entered in the app.
+pub const CREDENTIALS_PASSPHRASE_ENV: &str = "MUSIC_LIBRARY_CREDENTIALS_PASSPHRASE";
+
+const CREDENTIALS_DIR: &str = "com.musiclibrarymanager.app";
+const CREDENTIALS_FILE: &str = "credentials.enc";
+const FILE_FORMAT_VERSION: u32 = 1;
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 12;
+
+/// Keychain entry used to check whether the OS keychain works at all.
+const PROBE_SERVICE: &str = "com.musiclibrarymanager.probe";
+const PROBE_ACCOUNT: &str = "availability_probe";
+
+/// How long the keychain is left alone after the user denied access.
+pub const ACCESS_DENIED_COOLDOWN: Duration = Duration::from_secs(5 * 60);
+
+/// macOS Security framework statuses for a denied or dismissed prompt: errSecUserCanceled,
+/// errSecAuthFailed and errSecInteractionNotAllowed.
+const ACCESS_DENIED_CODES: [i32; 3] = [-128, -25293, -25308];
+
+/// When the user last denied keychain access, cleared by `clear_access_denial`.
+static ACCESS_DENIED_AT: Mutex<Option<Instant>> = Mutex::new(None);
+
+/// Passphrase for the encrypted file, set by `unlock_credential_store`.
+static FILE_PASSPHRASE: Mutex<Option<String>> = Mutex::new(None);
+
+/// Whether the OS keychain is usable, probed once per process.
+static KEYCHAIN_AVAILABLE: OnceLock<bool> = OnceLock::new();
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, TS)]
+#[serde(rename_all = "snake_case")]
+#[ts(export)]
+pub enum BackendKind {
+    Keychain,
+    EncryptedFile,
+    /// The plaintext `dev_credentials.json` of older debug builds, only read to migrate it
+    DevFile,
+}
+
+/// Storage for secrets addressed by service and account, like the OS keychain.
+pub trait CredentialBackend: Send + Sync {
+    fn kind(&self) -> BackendKind;
+    /// Returns `Ok(None)` when nothing is stored for the entry.
+    fn get(&self, service: &str, account: &str) -> Result<Option<String>, CredentialsError>;
+    fn set(&self, service: &str, account: &str, secret: &str) -> Result<(), CredentialsError>;
+    /// Deleting a missing entry succeeds.
+    fn delete(&self, service: &str, account: &str) -> Result<(), CredentialsError>;
+}
+
+// --- Keychain Backend ---
+
+/// Whether a keyring error means the user denied the keychain prompt. keyring only
+/// exposes the platform error boxed, so the OSStatus is read from its debug output
+/// (`Error { code: -128, .. }`).
+fn is_access_denial(err: &keyring::Error) -> bool {
+    let platform_error = match err {
+        keyring::Error::PlatformFailure(e) | keyring::Error::NoStorageAccess(e) => format!("{:?}", e),
+        _ => return false,
+    };
+    platform_error.split("code: ").skip(1)
+        .filter_map(|rest| {
+            let end = rest.find(|c: char| c != '-' && !c.is_ascii_digit()).unwrap_or(rest.len());
+            rest[..end].parse::<i32>().ok()
+        })
+        .any(|code| ACCESS_DENIED_CODES.contains(&code))
+}
+
+fn cooldown_remaining(denied_at: Option<Instant>, now: Instant) -> Option<Duration> {
+    denied_at.and_then(|at| ACCESS_DENIED_COOLDOWN.checked_sub(now.duration_since(at))).filter(|left| !left.is_zero())
+}
+
+/// Time left before the keychain is asked again, if the user recently denied access.
+pub fn access_denied_remaining() -> Option<Duration> {
+    cooldown_remaining(*ACCESS_DENIED_AT.lock().unwrap_or_else(|e| e.into_inner()), Instant::now())
+}
+
+/// Lets the next credential access prompt again, e.g. after the user chose to retry.
+pub fn clear_access_denial() {
+    *ACCESS_DENIED_AT.lock().unwrap_or_else(|e| e.into_inner()) = None;
+}
+
+fn access_denied_error(remaining: Duration) -> CredentialsError {
+    CredentialsError::AccessDenied(format!(
+        "Keychain access was denied; not asking again for {} seconds unless you retry",
+        remaining.as_secs()
+    ))
+}
+
+/// Fails fast while the cooldown after a denied prompt is running.
+fn check_access() -> Result<(), CredentialsError> {
+    match access_denied_remaining() {
+        Some(remaining) => Err(access_denied_error(remaining)),
+        None => Ok(()),
+    }
+}
+
+/// Converts a keyring error, starting the cooldown if it was a denied prompt.
+fn keychain_error(err: keyring::Error) -> CredentialsError {
+    if !is_access_denial(&err) {
+        return err.into();
+    }
+    warn!("Keychain access was denied: {}", err);
+    *ACCESS_DENIED_AT.lock().unwrap_or_else(|e| e.into_inner()) = Some(Instant::now());
+    access_denied_error(ACCESS_DENIED_COOLDOWN)
+}
+
+pub struct KeychainBackend;
+
+impl CredentialBackend for KeychainBackend {
+    fn kind(&self) -> BackendKind {
+        BackendKind::Keychain
+    }
+
+    fn get(&self, service: &str, account: &str) -> Result<Option<String>, CredentialsError> {
+        check_access()?;
+        match Entry::new(service, account)?.get_password() {
+            Ok(secret) if secret.is_empty() => Ok(None),
+            Ok(secret) => Ok(Some(secret)),
+            Err(keyring::Error::NoEntry) => Ok(None),
+            Err(e) => Err(keychain_error(e)),
+        }
+    }
+
+    fn set(&self, service: &str, account: &str, secret: &str) -> Result<(), CredentialsError> {
+        check_access()?;
+        let entry = Entry::new(service, account)?;
+        let _ = entry.delete_credential(); // Attempt to delete existing before setting
+        entry.set_password(secret).map_err(keychain_error)
+    }
+
+    fn delete(&self, service: &str, account: &str) -> Result<(), CredentialsError> {
+        check_access()?;
+        match Entry::new(service, account)?.delete_credential() {
+            Ok(()) | Err(keyring::Error::NoEntry) => Ok(()),
+            Err(e) => Err(keychain_error(e)),
+        }
+    }
+}
+
+// --- Encrypted File Backend ---
+
+/// On-disk layout of the encrypted credentials file.
+#[derive(Serialize, Deserialize)]
+struct SealedFile {
+    version: u32,
+    salt: String,
+    nonce: String,
+    ciphertext: String,
+}
+
+pub struct EncryptedFileBackend {
+    path: PathBuf,
+    passphrase: String,
+    machine_id: String,
+}
+
+impl EncryptedFileBackend {
+    pub fn new(path: PathBuf, passphrase: String) -> Self {
+        Self::with_machine_id(path, passphrase, machine_id())
+    }
+
+    fn with_machine_id(path: PathBuf, passphrase: String, machine_id: String) -> Self {
+        Self { path, passphrase, machine_id }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn derive_key(&self, salt: &[u8]) -> Result<[u8; 32], CredentialsError> {
+        let mut secret = self.passphrase.as_bytes().to_vec();
+        secret.push(0);
+        secret.extend_from_slice(self.machine_id.as_bytes());
+        let mut key = [0u8; 32];
+        Argon2::default()
+            .hash_password_into(&secret, salt, &mut key)
+            .map_err(|e| CredentialsError::Unexpected(format!("Key derivation failed: {}", e)))?;
+        Ok(key)
+    }
+
+    /// Decrypts all entries. A missing file is an empty store.
+    fn load(&self) -> Result<HashMap<String, String>, CredentialsError> {
+        let contents = match fs::read_to_string(&self.path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(HashMap::new()),
+            Err(e) => return Err(CredentialsError::FileSystem(format!("Failed to read credentials file: {}", e))),
+        };
+        let sealed: SealedFile = serde_json::from_str(&contents)
+            .map_err(|e| CredentialsError::Unexpected(format!("Credentials file is corrupted: {}", e)))?;
+        if sealed.version != FILE_FORMAT_VERSION {
+            return Err(CredentialsError::Configuration(format!("Unsupported credentials file version {}", sealed.version)));
+        }
+        let decode = |field: &str| BASE64.decode(field)
+            .map_err(|e| CredentialsError::Unexpected(format!("Credentials file is corrupted: {}", e)));
+        let (salt, nonce, ciphertext) = (decode(&sealed.salt)?, decode(&sealed.nonce)?, decode(&sealed.ciphertext)?);
+        if nonce.len() != NONCE_LEN {
+            return Err(CredentialsError::Unexpected("Credentials file is corrupted: bad nonce".to_string()));
+        }
+
+        let key = self.derive_key(&salt)?;
+        let plaintext = ChaCha20Poly1305::new(Key::from_slice(&key))
+            .decrypt(Nonce::from_slice(&nonce), ciphertext.as_ref())
+            // Authentication fails for a wrong passphrase, another machine, or tampering alike
+            .map_err(|_| CredentialsError::Validation("Incorrect passphrase for the credentials file".to_string()))?;
+        serde_json::from_slice(&plaintext)
+            .map_err(|e| CredentialsError::Unexpected(format!("Credentials file is corrupted: {}", e)))
+    }
+
+    /// Encrypts and writes all entries with a fresh salt and nonce.
+    fn save(&self, entries: &HashMap<String, String>) -> Result<(), CredentialsError> {
+        let mut salt = [0u8; SALT_LEN];
+        let mut nonce = [0u8; NONCE_LEN];
+        OsRng.fill_bytes(&mut salt);
+        OsRng.fill_bytes(&mut nonce);
+
+        let key = self.derive_key(&salt)?;
+        let plaintext = serde_json::to_vec(entries)
+            .map_err(|e| CredentialsError::Unexpected(format!("Failed to serialize credentials: {}", e)))?;
+        let ciphertext = ChaCha20Poly1305::new(Key::from_slice(&key))
+            .encrypt(Nonce::from_slice(&nonce), plaintext.as_ref())
+            .map_err(|e| CredentialsError::Unexpected(format!("Failed to encrypt credentials: {}", e)))?;
+        let sealed = SealedFile {
+            version: FILE_FORMAT_VERSION,
+            salt: BASE64.encode(salt),
+            nonce: BASE64.encode(nonce),
+            ciphertext: BASE64.encode(ciphertext),
+        };
+        let json = serde_json::to_string_pretty(&sealed)
+            .map_err(|e| CredentialsError::Unexpected(format!("Failed to serialize credentials file: {}", e)))?;
+
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)
+                .map_err(|e| CredentialsError::FileSystem(format!("Failed to create credentials directory: {}", e)))?;
+        }
+        // Write to a sibling file first so a crash never leaves a truncated store
+        let temp_path = self.path.with_extension("enc.tmp");
+        fs::write(&temp_path, json)
+            .map_err(|e| CredentialsError::FileSystem(format!("Failed to write credentials file: {}", e)))?;
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let _ = fs::set_permissions(&temp_path, fs::Permissions::from_mode(0o600));
+        }
+        fs::rename(&temp_path, &self.path)
+            .map_err(|e| CredentialsError::FileSystem(format!("Failed to replace credentials file: {}", e)))
+    }
+
+    /// Checks that the passphrase opens the existing file, if there is one.
+    pub fn verify(&self) -> Result<(), CredentialsError> {
+        self.load().map(|_| ())
+    }
+}
+
+fn entry_key(service: &str, account: &str) -> String {
+    format!("{}/{}", service, account)
+}
+
+impl CredentialBackend for EncryptedFileBackend {
+    fn kind(&self) -> BackendKind {
+        BackendKind::EncryptedFile
+    }
+
+    fn get(&self, service: &str, account: &str) -> Result<Option<String>, CredentialsError> {
+        Ok(self.load()?.remove(&entry_key(service, account)).filter(|secret| !secret.is_empty()))
+    }
+
+    fn set(&self, service: &str, account: &str, secret: &str) -> Result<(), CredentialsError> {
+        let mut entries = self.load()?;
+        entries.insert(entry_key(service, account), secret.to_string());
+        self.save(&entries)
+    }
+
+    fn delete(&self, service: &str, account: &str) -> Result<(), CredentialsError> {
+        let mut entries = self.load()?;
+        if entries.remove(&entry_key(service, account)).is_some() {
+            self.save(&entries)?;
+        }
+        Ok(())
+    }
+}
+
+// --- Backend Selection ---
+
+/// Stable per-machine identifier mixed into the file key. Falls back to the host
+/// name where no machine id file exists (e.g. Windows).
+fn machine_id() -> String {
+    ["/etc/machine-id", "/var/lib/dbus/machine-id"]
+        .iter()
+        .find_map(|path| fs::read_to_string(path).ok().map(|id| id.trim().to_string()).filter(|id| !id.is_empty()))
+        .or_else(|| std::env::var("COMPUTERNAME").ok())
+        .or_else(|| std::env::var("HOSTNAME").ok())
+        .unwrap_or_else(|| "unknown-machine".to_string())
+}
+
+pub fn encrypted_file_path() -> PathBuf {
+    dirs::config_dir()
+        .unwrap_or_else(|| PathBuf::from("."))
+        .join(CREDENTIALS_DIR)
+        .join(CREDENTIALS_FILE)
+}
+
+/// Whether the OS keychain can be used. Probed once; a missing entry counts as usable,
+/// and so does a denied prompt, which means the keychain is there but locked to us.
+pub fn keychain_available() -> bool {
+    *KEYCHAIN_AVAILABLE.get_or_init(|| {
+        let available = match Entry::new(PROBE_SERVICE, PROBE_ACCOUNT) {
+            Ok(entry) => match entry.get_password() {
+                Ok(_) | Err(keyring::Error::NoEntry) => true,
+                Err(e) if is_access_denial(&e) => {
+                    let _ = keychain_error(e);
+                    true
+                }
+                Err(e) => {
+                    warn!("OS keychain unavailable: {}", e);
+                    false
+                }
+            },
+            Err(e) => {
+                warn!("OS keychain unavailable: {}", e);
+                false
+            }
+        };
+        info!("Credential backend: {}", if available { "OS keychain" } else { "encrypted file" });
+        available
+    })
+}
+
+fn file_passphrase() -> Option<String> {
+    FILE_PASSPHRASE.lock().unwrap_or_else(|e| e.into_inner()).clone()
+        .or_else(|| std::env::var(CREDENTIALS_PASSPHRASE_ENV).ok())
+        .filter(|passphrase| !passphrase.is_empty())
+}
+
+/// Sets the passphrase for the encrypted file after checking it opens the existing file.
+pub fn unlock(passphrase: String) -> Result<(), CredentialsError> {
+    if passphrase.is_empty() {
+        return Err(CredentialsError::Validation("Passphrase must not be empty".to_string()));
+    }
+    EncryptedFileBackend::new(encrypted_file_path(), passphrase.clone()).verify()?;
+    *FILE_PASSPHRASE.lock().unwrap_or_else(|e| e.into_inner()) = Some(passphrase);
+    Ok(())
+}
+
+/// Whether the encrypted file backend is in use but has no passphrase yet.
+pub fn is_locked() -> bool {
+    !keychain_available() && file_passphrase().is_none()
+}
+
+/// The encrypted file, opened with `passphrase` or else the one already entered or set
+/// in the environment. Used to read the file while the keychain is the active backend.
+pub fn encrypted_file_backend(passphrase: Option<String>) -> Result<EncryptedFileBackend, CredentialsError> {
+    let passphrase = passphrase.filter(|passphrase| !passphrase.is_empty()).or_else(file_passphrase).ok_or_else(|| {
+        CredentialsError::Validation("Enter the passphrase of the credentials file to read it".to_string())
+    })?;
+    let file = EncryptedFileBackend::new(encrypted_file_path(), passphrase);
+    file.verify()?;
+    Ok(file)
+}
+
+/// The backend credentials are read from and written to.
+pub fn active_backend() -> Result<Box<dyn CredentialBackend>, CredentialsError> {
+    if keychain_available() {
+        return Ok(Box::new(KeychainBackend));
+    }
+    let passphrase = file_passphrase().ok_or_else(|| {
+        CredentialsError::Configuration("Credential store is locked. Enter the credentials passphrase in Settings.".to_string())
+    })?;
+    Ok(Box::new(EncryptedFileBackend::new(encrypted_file_path(), passphrase)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    fn backend(path: &Path, passphrase: &str) -> EncryptedFileBackend {
+        EncryptedFileBackend::with_machine_id(path.to_path_buf(), passphrase.to_string(), "test-machine".to_string())
+    }
+
+    #[test]
+    fn test_encrypted_file_round_trip() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join(CREDENTIALS_FILE);
+        let store = backend(&path, "correct horse");
+
+        assert_eq!(store.get("svc", "acct").unwrap(), None);
+        store.set("svc", "acct", "s3cret").unwrap();
+        store.set("svc", "other", "value").unwrap();
+
+        let reopened = backend(&path, "correct horse");
+        assert_eq!(reopened.get("svc", "acct").unwrap(), Some("s3cret".to_string()));
+        reopened.delete("svc", "acct").unwrap();
+        reopened.delete("svc", "acct").unwrap(); // Deleting twice is fine
+        assert_eq!(reopened.get("svc", "acct").unwrap(), None);
+        assert_eq!(reopened.get("svc", "other").unwrap(), Some("value".to_string()));
+
+        // Secrets are not stored in plain text
+        assert!(!fs::read_to_string(&path).unwrap().contains("value"));
+    }
+
+    #[derive(Debug)]
+    struct Error {
+        code: i32,
+    }
+
+    impl std::fmt::Display for Error {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            write!(f, "error code {}", self.code)
+        }
+    }
+
+    impl std::error::Error for Error {}
+
+    #[test]
+    fn test_access_denial_detection_and_cooldown() {
+        let platform = |code| keyring::Error::PlatformFailure(Box::new(Error { code }));
+        assert!(is_access_denial(&platform(-128)));
+        assert!(is_access_denial(&platform(-25293)));
+        assert!(!is_access_denial(&platform(-25291)));
+        assert!(!is_access_denial(&keyring::Error::NoEntry));
+
+        let denied_at = Instant::now();
+        assert_eq!(cooldown_remaining(None, denied_at), None);
+        assert_eq!(cooldown_remaining(Some(denied_at), denied_at), Some(ACCESS_DENIED_COOLDOWN));
+        assert!(cooldown_remaining(Some(denied_at), denied_at + ACCESS_DENIED_COOLDOWN).is_none());
+    }
+
+    #[test]
+    fn test_encrypted_file_wrong_passphrase_or_machine() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join(CREDENTIALS_FILE);
+        backend(&path, "correct horse").set("svc", "acct", "s3cret").unwrap();
+
+        let wrong = backend(&path, "battery staple");
+        assert!(matches!(wrong.get("svc", "acct"), Err(CredentialsError::Validation(_))));
+        assert!(wrong.verify().is_err());
+        // A failed write must not clobber the existing store
+        assert!(wrong.set("svc", "acct", "overwritten").is_err());
+
+        let other_machine = EncryptedFileBackend::with_machine_id(path.clone(), "correct horse".to_string(), "other".to_string());
+        assert!(other_machine.get("svc", "acct").is_err());
+
+        assert_eq!(backend(&path, "correct horse").get("svc", "acct").unwrap(), Some("s3cret".to_string()));
+    }
+}
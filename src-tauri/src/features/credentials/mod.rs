@@ -1,16 +1,16 @@
-//! Handles storage and retrieval of credentials using the system keychain
-//! with a fallback to a local file for development environments.
+//! Handles storage and retrieval of credentials using the system keychain, with a
+//! fallback to an encrypted local file when no keychain is available (see `backend`).
 
+pub mod backend;
+
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
 use serde::{Deserialize, Serialize};
-use log::{info, error, warn};
-use std::fs;
-use std::path::{Path, PathBuf};
-use std::env;
-use keyring::Entry;
+use log::{info, error};
 use tauri::command;
 use anyhow::{self, Result};
 use std::fmt;
 use thiserror::Error;
+use ts_rs::TS;
 use std::error::Error as StdError;
 
 // Define a custom error type for credentials operations
@@ -24,6 +24,8 @@ pub enum CredentialsError {
     NotFound(String),
     Unexpected(String),
     Keychain(String),
+    /// The user denied a keychain prompt; the keychain isn't asked again until the cooldown ends
+    AccessDenied(String),
 }
 
 impl fmt::Display for CredentialsError {
@@ -37,6 +39,7 @@ impl fmt::Display for CredentialsError {
             CredentialsError::NotFound(s) => write!(f, "Not found: {}", s),
             CredentialsError::Unexpected(s) => write!(f, "Unexpected error: {}", s),
             CredentialsError::Keychain(s) => write!(f, "Keychain error: {}", s),
+            CredentialsError::AccessDenied(s) => write!(f, "Keychain access denied: {}", s),
         }
     }
 }
@@ -61,10 +64,15 @@ const KEYCHAIN_SERVICE_MONGO: &str = "com.musiclibrarymanager.mongo";
 const KEYCHAIN_ACCOUNT_MONGO: &str = "mongo_credentials";
 const KEYCHAIN_SERVICE_R2: &str = "com.musiclibrarymanager.r2";
 const KEYCHAIN_ACCOUNT_R2: &str = "r2_credentials";
+const KEYCHAIN_SERVICE_ENCRYPTION: &str = "com.musiclibrarymanager.encryption";
+const KEYCHAIN_ACCOUNT_ORIGINALS_KEY: &str = "originals_key";
 
-// Dev-mode fallback config file path for credentials (only used if keychain fails)
-#[cfg(debug_assertions)]
-const DEV_CREDENTIALS_FILE: &str = "dev_credentials.json";
+/// Every stored credential, by the name used in reports.
+const STORED_ENTRIES: [(&str, &str, &str); 3] = [
+    ("mongo", KEYCHAIN_SERVICE_MONGO, KEYCHAIN_ACCOUNT_MONGO),
+    ("r2", KEYCHAIN_SERVICE_R2, KEYCHAIN_ACCOUNT_R2),
+    ("originals_key", KEYCHAIN_SERVICE_ENCRYPTION, KEYCHAIN_ACCOUNT_ORIGINALS_KEY),
+];
 
 // --- Data Structures ---
 
@@ -81,7 +89,8 @@ pub struct MongoCredentials {
 }
 
 /// R2 credentials structure
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, TS)]
+#[ts(export)]
 pub struct R2Credentials {
     pub account_id: String,
     pub bucket_name: String,
@@ -90,52 +99,227 @@ pub struct R2Credentials {
     pub endpoint: String,
 }
 
-/// Development credentials storage structure (used only in debug builds as fallback)
-#[cfg(debug_assertions)]
-#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+/// What is configured, without the R2 secret key or the Mongo connection string, so
+/// the settings screen can show the current setup.
+#[derive(Serialize, Debug, Clone, Default, TS)]
+#[ts(export)]
+pub struct CredentialsStatus {
+    pub mongo_configured: bool,
+    pub r2_configured: bool,
+    pub r2_account_id: Option<String>,
+    pub r2_bucket_name: Option<String>,
+    pub r2_endpoint: Option<String>,
+}
+
+impl CredentialsStatus {
+    fn new(mongo_configured: bool, r2: Option<R2Credentials>) -> Self {
+        match r2 {
+            Some(creds) => Self {
+                mongo_configured,
+                r2_configured: true,
+                r2_account_id: Some(creds.account_id),
+                r2_bucket_name: Some(creds.bucket_name),
+                r2_endpoint: Some(creds.endpoint),
+            },
+            None => Self { mongo_configured, ..Self::default() },
+        }
+    }
+}
+
+/// Which backend holds credentials, for display in Settings.
+#[derive(Serialize, Debug, Clone, TS)]
+#[ts(export)]
+pub struct CredentialBackendInfo {
+    pub backend: backend::BackendKind,
+    /// Human-readable description of where secrets are stored
+    pub location: String,
+    /// True when the encrypted file is in use and no passphrase has been entered yet
+    pub locked: bool,
+}
+
+/// What `migrate_file_credentials_to_keychain` did, by credential name (`mongo`, `r2`,
+/// `originals_key`).
+#[derive(Serialize, Debug, Clone, Default, PartialEq, TS)]
+#[ts(export)]
+pub struct CredentialMigrationReport {
+    /// Whether there was an encrypted credentials file
+    pub file_found: bool,
+    /// Whether there was a plaintext `dev_credentials.json`
+    pub dev_file_found: bool,
+    /// Copied into the keychain
+    pub migrated: Vec<String>,
+    /// Already in the keychain with the same value
+    pub already_in_keychain: Vec<String>,
+    /// In the keychain with a different value, which was kept
+    pub conflicts: Vec<String>,
+    pub file_deleted: bool,
+    pub dev_file_deleted: bool,
+}
+
+/// Payload of `credentials://access-denied`, sent once when startup finds keychain
+/// access denied so the UI can explain how to allow it.
+#[derive(Serialize, Debug, Clone)]
+pub struct KeychainAccessDenied {
+    /// Seconds until the keychain is asked again on its own; `retry_keychain_access` asks now
+    pub retry_after_secs: u64,
+    pub message: String,
+}
+
+impl KeychainAccessDenied {
+    /// The event payload while access is denied, or None once it may be asked again.
+    pub fn current() -> Option<Self> {
+        backend::access_denied_remaining().map(|remaining| Self {
+            retry_after_secs: remaining.as_secs(),
+            message: "Access to the keychain was denied, so saved credentials can't be read. \
+                Choose Retry and click \"Always Allow\" when macOS asks for the keychain password.".to_string(),
+        })
+    }
+}
+
+/// Maps a credential type from the frontend onto its keychain service and account.
+fn credential_entry(credential_type: &str) -> Result<(&'static str, &'static str), CredentialsError> {
+    match credential_type {
+        "mongo" => Ok((KEYCHAIN_SERVICE_MONGO, KEYCHAIN_ACCOUNT_MONGO)),
+        "r2" => Ok((KEYCHAIN_SERVICE_R2, KEYCHAIN_ACCOUNT_R2)),
+        _ => Err(CredentialsError::Validation("Invalid credential type provided".to_string())),
+    }
+}
+
+/// Plaintext file debug builds fell back to when the keychain failed, read from the
+/// working directory.
+const DEV_CREDENTIALS_FILE: &str = "dev_credentials.json";
+
+/// Layout of `dev_credentials.json`.
+#[derive(Deserialize, Debug, Default)]
+#[serde(default)]
 struct DevCredentials {
     mongo_connection_string: Option<String>,
     r2_credentials: Option<R2Credentials>,
 }
 
-// --- Development Fallback Helpers (Debug Only) ---
-
-#[cfg(debug_assertions)]
-async fn load_dev_credentials() -> DevCredentials {
-    let path = PathBuf::from(DEV_CREDENTIALS_FILE);
-    if path.exists() {
-        match fs::read_to_string(&path) {
-            Ok(json_str) => {
-                match serde_json::from_str::<DevCredentials>(&json_str) {
-                    Ok(creds) => {
-                        info!("Loaded development credentials from file");
-                        return creds;
-                    },
-                    Err(e) => {
-                        warn!("Failed to parse development credentials file: {}", e);
-                    }
+impl DevCredentials {
+    fn load(path: &std::path::Path) -> Result<Self, CredentialsError> {
+        let contents = std::fs::read_to_string(path)
+            .map_err(|e| CredentialsError::FileSystem(format!("Failed to read {}: {}", path.display(), e)))?;
+        serde_json::from_str(&contents)
+            .map_err(|e| CredentialsError::Validation(format!("{} is not a valid credentials file: {}", path.display(), e)))
+    }
+}
+
+/// Read-only, so the dev file can be migrated like any other backend.
+impl backend::CredentialBackend for DevCredentials {
+    fn kind(&self) -> backend::BackendKind {
+        backend::BackendKind::DevFile
+    }
+
+    fn get(&self, service: &str, account: &str) -> Result<Option<String>, CredentialsError> {
+        match (service, account) {
+            (KEYCHAIN_SERVICE_MONGO, KEYCHAIN_ACCOUNT_MONGO) => Ok(self.mongo_connection_string.clone().filter(|uri| !uri.is_empty())),
+            (KEYCHAIN_SERVICE_R2, KEYCHAIN_ACCOUNT_R2) => self.r2_credentials.as_ref()
+                .map(|creds| serde_json::to_string(creds)
+                    .map_err(|e| CredentialsError::Unexpected(format!("Failed to serialize R2 credentials: {}", e))))
+                .transpose(),
+            _ => Ok(None),
+        }
+    }
+
+    fn set(&self, _service: &str, _account: &str, _secret: &str) -> Result<(), CredentialsError> {
+        Err(CredentialsError::Validation(format!("{} is read-only", DEV_CREDENTIALS_FILE)))
+    }
+
+    fn delete(&self, _service: &str, _account: &str) -> Result<(), CredentialsError> {
+        Err(CredentialsError::Validation(format!("{} is read-only", DEV_CREDENTIALS_FILE)))
+    }
+}
+
+/// Copies every credential in `source` into `target` unless `target` already has one,
+/// reading each copy back before counting it as migrated. Returns how many of the
+/// source's entries conflicted with what `target` holds.
+fn migrate_entries(
+    source: &dyn backend::CredentialBackend,
+    target: &dyn backend::CredentialBackend,
+    report: &mut CredentialMigrationReport,
+) -> Result<usize, CredentialsError> {
+    let mut conflicts = 0;
+    for (name, service, account) in STORED_ENTRIES {
+        let Some(secret) = source.get(service, account)? else { continue };
+        match target.get(service, account)? {
+            Some(existing) if existing == secret => {
+                if !report.migrated.iter().any(|migrated| migrated == name) {
+                    report.already_in_keychain.push(name.to_string());
                 }
-            },
-            Err(e) => {
-                warn!("Failed to read development credentials file: {}", e);
             }
+            Some(_) => {
+                report.conflicts.push(name.to_string());
+                conflicts += 1;
+            }
+            None => {
+                target.set(service, account, &secret)?;
+                if target.get(service, account)?.as_deref() != Some(secret.as_str()) {
+                    return Err(CredentialsError::Keychain(format!("The {} credentials could not be read back after copying them", name)));
+                }
+                report.migrated.push(name.to_string());
+            }
+        }
+    }
+    Ok(conflicts)
+}
+
+/// Removes a migrated credentials file, unless some of its entries conflicted.
+fn delete_migrated_file(path: &std::path::Path, conflicts: usize) -> Result<bool, CredentialsError> {
+    if conflicts > 0 {
+        info!("Kept {}; the keychain holds different values for {} of its entries", path.display(), conflicts);
+        return Ok(false);
+    }
+    std::fs::remove_file(path)
+        .map_err(|e| CredentialsError::FileSystem(format!("Credentials were migrated, but deleting {} failed: {}", path.display(), e)))?;
+    info!("Deleted credentials file {}", path.display());
+    Ok(true)
+}
+
+fn backend_info() -> CredentialBackendInfo {
+    if backend::keychain_available() {
+        CredentialBackendInfo {
+            backend: backend::BackendKind::Keychain,
+            location: "System keychain".to_string(),
+            locked: false,
+        }
+    } else {
+        CredentialBackendInfo {
+            backend: backend::BackendKind::EncryptedFile,
+            location: backend::encrypted_file_path().display().to_string(),
+            locked: backend::is_locked(),
         }
     }
-    DevCredentials::default()
 }
 
-#[cfg(debug_assertions)]
-async fn save_dev_credentials(creds: &DevCredentials) -> Result<(), CredentialsError> {
-    let creds_json = serde_json::to_string_pretty(creds)
-        .map_err(|e| CredentialsError::Unexpected(format!("Failed to serialize dev credentials: {}", e)))?;
-    
-    std::fs::write(DEV_CREDENTIALS_FILE, creds_json)
-        .map_err(|e| CredentialsError::FileSystem(format!("Failed to write dev credentials file: {}", e)))
+/// The key originals are encrypted with before upload, if one has been created.
+pub fn load_originals_key() -> Result<Option<[u8; crate::core::encryption::KEY_LEN]>, CredentialsError> {
+    let Some(encoded) = backend::active_backend()?.get(KEYCHAIN_SERVICE_ENCRYPTION, KEYCHAIN_ACCOUNT_ORIGINALS_KEY)? else {
+        return Ok(None);
+    };
+    let key = BASE64.decode(encoded.trim()).ok()
+        .and_then(|bytes| <[u8; crate::core::encryption::KEY_LEN]>::try_from(bytes).ok())
+        .ok_or_else(|| CredentialsError::Unexpected("Stored originals encryption key is malformed".to_string()))?;
+    Ok(Some(key))
+}
+
+/// Returns the originals encryption key, generating and storing one on first use.
+pub fn get_or_create_originals_key() -> Result<[u8; crate::core::encryption::KEY_LEN], CredentialsError> {
+    if let Some(key) = load_originals_key()? {
+        return Ok(key);
+    }
+    let key = crate::core::encryption::generate_key();
+    backend::active_backend()?
+        .set(KEYCHAIN_SERVICE_ENCRYPTION, KEYCHAIN_ACCOUNT_ORIGINALS_KEY, &BASE64.encode(key))
+        .map_err(|e| { error!("Failed to store originals encryption key: {}", e); e })?;
+    info!("Created originals encryption key {}", crate::core::encryption::key_id(&key));
+    Ok(key)
 }
 
 // --- Tauri Commands ---
 
-/// Stores R2 credentials in Keychain using keyring
+/// Stores R2 credentials in the active credential backend
 #[command]
 pub async fn store_r2_credentials(
     account_id: String,
@@ -144,275 +328,206 @@ pub async fn store_r2_credentials(
     secret_access_key: String,
     endpoint: String,
 ) -> Result<bool, CredentialsError> {
-    info!("Storing R2 credentials in keychain");
+    info!("Storing R2 credentials");
     let creds = R2Credentials { account_id, bucket_name, access_key_id, secret_access_key, endpoint };
-    let entry_result = Entry::new(KEYCHAIN_SERVICE_R2, KEYCHAIN_ACCOUNT_R2);
-
-    let entry = match entry_result {
-        Ok(entry) => entry,
-        Err(keyring_error) => {
-            error!("Failed to create keyring entry for R2 credentials: {}", keyring_error);
-            #[cfg(debug_assertions)] {
-                info!("Using development fallback for storing R2 credentials");
-                let mut dev_creds = load_dev_credentials().await;
-                dev_creds.r2_credentials = Some(creds);
-                return save_dev_credentials(&dev_creds).await.map(|_| true);
-            }
-            #[cfg(not(debug_assertions))] return Err(keyring_error.into());
-        }
-    };
-
     let json_str = serde_json::to_string(&creds)
         .map_err(|e| CredentialsError::Unexpected(format!("Failed to serialize R2 credentials: {}", e)))?;
-    let _ = entry.delete_credential(); // Attempt to delete existing before setting
-
-    match entry.set_password(&json_str) {
-        Ok(_) => {
-            info!("Successfully stored R2 credentials");
-            #[cfg(debug_assertions)] {
-                let mut dev_creds = load_dev_credentials().await;
-                dev_creds.r2_credentials = Some(creds);
-                let _ = save_dev_credentials(&dev_creds).await; // Save to dev file as well
-            }
-            Ok(true)
-        },
-        Err(keyring_error) => {
-            error!("Failed to store R2 credentials in keychain: {}", keyring_error);
-            #[cfg(debug_assertions)] {
-                info!("Using development fallback for storing R2 credentials after keychain failure");
-                let mut dev_creds = load_dev_credentials().await;
-                dev_creds.r2_credentials = Some(creds);
-                return save_dev_credentials(&dev_creds).await.map(|_| true);
-            }
-            #[cfg(not(debug_assertions))] Err(keyring_error.into())
-        }
-    }
+
+    backend::active_backend()?
+        .set(KEYCHAIN_SERVICE_R2, KEYCHAIN_ACCOUNT_R2, &json_str)
+        .map_err(|e| { error!("Failed to store R2 credentials: {}", e); e })?;
+    info!("Successfully stored R2 credentials");
+    Ok(true)
 }
 
-/// Retrieves R2 credentials from Keychain using keyring
+/// Retrieves R2 credentials from the active credential backend
 #[command]
 pub async fn get_r2_credentials() -> Result<R2Credentials, CredentialsError> {
-    info!("Retrieving R2 credentials from keychain");
-    let entry_result = Entry::new(KEYCHAIN_SERVICE_R2, KEYCHAIN_ACCOUNT_R2);
-
-    let entry = match entry_result {
-        Ok(entry) => entry,
-        Err(keyring_error) => {
-            error!("Failed to create keyring entry for R2 credentials: {}", keyring_error);
-            #[cfg(debug_assertions)] {
-                info!("Using development fallback for retrieving R2 credentials");
-                let dev_creds = load_dev_credentials().await;
-                if let Some(creds) = dev_creds.r2_credentials { return Ok(creds); }
-            }
-            return Err(keyring_error.into());
-        }
-    };
-
-    match entry.get_password() {
-        Ok(json_str) => {
-            if json_str.is_empty() {
-                // Keychain entry exists but is empty, treat as not found
-                 info!("R2 credentials entry found but empty in keychain");
-                 #[cfg(debug_assertions)] {
-                     info!("Using development fallback for retrieving R2 credentials");
-                     let dev_creds = load_dev_credentials().await;
-                     if let Some(creds) = dev_creds.r2_credentials { return Ok(creds); }
-                 }
-                return Err(CredentialsError::NotFound("R2 credentials not set".to_string()));
-            }
-            serde_json::from_str::<R2Credentials>(&json_str)
-                .map_err(|e| CredentialsError::Unexpected(format!("Failed to parse R2 credentials: {}", e)))
-        },
-        Err(keyring_error) => {
-            if matches!(keyring_error, keyring::Error::NoEntry) {
-                info!("R2 credentials not found in keychain");
-                #[cfg(debug_assertions)] {
-                    info!("Using development fallback for retrieving R2 credentials");
-                    let dev_creds = load_dev_credentials().await;
-                    if let Some(creds) = dev_creds.r2_credentials { return Ok(creds); }
-                }
-                Err(CredentialsError::NotFound("R2 credentials not found".to_string()))
-            } else {
-                error!("Failed to get R2 credentials from keychain: {}", keyring_error);
-                #[cfg(debug_assertions)] {
-                    info!("Using development fallback for retrieving R2 credentials after keychain error");
-                    let dev_creds = load_dev_credentials().await;
-                    if let Some(creds) = dev_creds.r2_credentials { return Ok(creds); }
-                }
-                Err(keyring_error.into())
-            }
+    info!("Retrieving R2 credentials");
+    match backend::active_backend()?.get(KEYCHAIN_SERVICE_R2, KEYCHAIN_ACCOUNT_R2)? {
+        Some(json_str) => serde_json::from_str::<R2Credentials>(&json_str)
+            .map_err(|e| CredentialsError::Unexpected(format!("Failed to parse R2 credentials: {}", e))),
+        None => {
+            info!("R2 credentials not found");
+            Err(CredentialsError::NotFound("R2 credentials not found".to_string()))
         }
     }
 }
 
-/// Stores MongoDB connection string in Keychain using keyring
+/// Stores MongoDB connection string in the active credential backend
 #[command]
 pub async fn store_mongo_credentials(connection_string: String) -> Result<bool, CredentialsError> {
-    info!("Storing MongoDB credentials (connection string) in keychain");
-    let entry_result = Entry::new(KEYCHAIN_SERVICE_MONGO, KEYCHAIN_ACCOUNT_MONGO);
-
-    let entry = match entry_result {
-        Ok(entry) => entry,
-        Err(keyring_error) => {
-            error!("Failed to create keyring entry for MongoDB credentials: {}", keyring_error);
-            #[cfg(debug_assertions)] {
-                info!("Using development fallback for storing MongoDB credentials");
-                let mut dev_creds = load_dev_credentials().await;
-                dev_creds.mongo_connection_string = Some(connection_string.clone());
-                 return save_dev_credentials(&dev_creds).await.map(|_| true);
-            }
-             #[cfg(not(debug_assertions))] return Err(keyring_error.into());
-        }
-    };
-
-    let _ = entry.delete_credential(); // Attempt to delete existing before setting
-
-    match entry.set_password(&connection_string) {
-        Ok(_) => {
-            info!("Successfully stored MongoDB credentials");
-            #[cfg(debug_assertions)] {
-                let mut dev_creds = load_dev_credentials().await;
-                dev_creds.mongo_connection_string = Some(connection_string);
-                let _ = save_dev_credentials(&dev_creds).await; // Save to dev file as well
-            }
-            Ok(true)
-        },
-        Err(keyring_error) => {
-            error!("Failed to store MongoDB credentials in keychain: {}", keyring_error);
-            #[cfg(debug_assertions)] {
-                info!("Using development fallback for storing MongoDB credentials after keychain failure");
-                let mut dev_creds = load_dev_credentials().await;
-                dev_creds.mongo_connection_string = Some(connection_string);
-                 return save_dev_credentials(&dev_creds).await.map(|_| true);
-            }
-             #[cfg(not(debug_assertions))] Err(keyring_error.into())
-        }
-    }
+    info!("Storing MongoDB credentials (connection string)");
+    backend::active_backend()?
+        .set(KEYCHAIN_SERVICE_MONGO, KEYCHAIN_ACCOUNT_MONGO, &connection_string)
+        .map_err(|e| { error!("Failed to store MongoDB credentials: {}", e); e })?;
+    info!("Successfully stored MongoDB credentials");
+    Ok(true)
 }
 
-/// Retrieves MongoDB connection string from Keychain using keyring
+/// Retrieves MongoDB connection string from the active credential backend
 #[command]
 pub async fn get_mongo_credentials() -> Result<String, CredentialsError> {
-    info!("Retrieving MongoDB credentials (connection string) from keychain");
-    let entry_result = Entry::new(KEYCHAIN_SERVICE_MONGO, KEYCHAIN_ACCOUNT_MONGO);
-
-    let entry = match entry_result {
-         Ok(entry) => entry,
-         Err(keyring_error) => {
-             error!("Failed to create keyring entry for MongoDB credentials: {}", keyring_error);
-             #[cfg(debug_assertions)] {
-                 info!("Using development fallback for retrieving MongoDB credentials");
-                 let dev_creds = load_dev_credentials().await;
-                 if let Some(conn_string) = dev_creds.mongo_connection_string { return Ok(conn_string); }
-             }
-             return Err(keyring_error.into());
-         }
-     };
-
-    match entry.get_password() {
-        Ok(connection_string) => {
-            if connection_string.is_empty() {
-                 info!("MongoDB credentials entry found but empty in keychain");
-                 #[cfg(debug_assertions)] {
-                     info!("Using development fallback for retrieving MongoDB credentials");
-                     let dev_creds = load_dev_credentials().await;
-                     if let Some(conn_string) = dev_creds.mongo_connection_string { return Ok(conn_string); }
-                 }
-                Err(CredentialsError::NotFound("MongoDB connection string not set".to_string()))
-            } else {
-                info!("Successfully retrieved MongoDB credentials");
-                Ok(connection_string)
-            }
-        },
-        Err(keyring_error) => {
-            if matches!(keyring_error, keyring::Error::NoEntry) {
-                info!("MongoDB credentials not found in keychain");
-                #[cfg(debug_assertions)] {
-                    info!("Using development fallback for retrieving MongoDB credentials");
-                    let dev_creds = load_dev_credentials().await;
-                    if let Some(conn_string) = dev_creds.mongo_connection_string { return Ok(conn_string); }
-                }
-                Err(CredentialsError::NotFound("MongoDB credentials not found".to_string()))
-            } else {
-                error!("Failed to get MongoDB credentials from keychain: {}", keyring_error);
-                #[cfg(debug_assertions)] {
-                    info!("Using development fallback for retrieving MongoDB credentials after keychain error");
-                    let dev_creds = load_dev_credentials().await;
-                    if let Some(conn_string) = dev_creds.mongo_connection_string { return Ok(conn_string); }
-                }
-                Err(keyring_error.into())
-            }
+    info!("Retrieving MongoDB credentials (connection string)");
+    match backend::active_backend()?.get(KEYCHAIN_SERVICE_MONGO, KEYCHAIN_ACCOUNT_MONGO)? {
+        Some(connection_string) => {
+            info!("Successfully retrieved MongoDB credentials");
+            Ok(connection_string)
+        }
+        None => {
+            info!("MongoDB credentials not found");
+            Err(CredentialsError::NotFound("MongoDB credentials not found".to_string()))
         }
     }
 }
 
-/// Check if credentials exist in the keychain
+/// Check if credentials exist in the active credential backend
 #[command]
 pub async fn has_credentials(credential_type: String) -> Result<bool, CredentialsError> {
-    let (service, account) = match credential_type.as_str() {
-         "mongo" => (KEYCHAIN_SERVICE_MONGO, KEYCHAIN_ACCOUNT_MONGO),
-         "r2" => (KEYCHAIN_SERVICE_R2, KEYCHAIN_ACCOUNT_R2),
-         _ => return Err(CredentialsError::Validation("Invalid credential type provided".to_string())),
-     };
-
-     let entry = Entry::new(service, account)
-        .map_err(|e| CredentialsError::Keychain(format!("Failed to create Keychain entry for checking {}: {}", credential_type, e)))?;
-
-     match entry.get_password() {
-         Ok(pass) => Ok(!pass.is_empty()), // True if entry exists and is not empty
-         Err(keyring_error) => {
-             if matches!(keyring_error, keyring::Error::NoEntry) {
-                  info!("{} credentials not found in keychain", credential_type);
-                 Ok(false) // Not found is not an error in this context
-             } else {
-                 error!("Failed to check if {} credentials exist: {}", credential_type, keyring_error);
-                 Err(keyring_error.into()) // Other keychain errors are propagated
-             }
-         }
-     }
+    let (service, account) = credential_entry(&credential_type)?;
+    let found = backend::active_backend()?.get(service, account)
+        .map_err(|e| { error!("Failed to check if {} credentials exist: {}", credential_type, e); e })?
+        .is_some();
+    if !found {
+        info!("{} credentials not found", credential_type);
+    }
+    Ok(found)
 }
 
-/// Delete credentials from the keychain
+/// Reports which credentials are configured, leaving out the secrets
+#[command]
+pub async fn get_credentials_status() -> Result<CredentialsStatus, CredentialsError> {
+    let backend = backend::active_backend()?;
+    let mongo_configured = backend.get(KEYCHAIN_SERVICE_MONGO, KEYCHAIN_ACCOUNT_MONGO)?.is_some();
+    let r2 = match backend.get(KEYCHAIN_SERVICE_R2, KEYCHAIN_ACCOUNT_R2)? {
+        Some(json_str) => Some(serde_json::from_str::<R2Credentials>(&json_str)
+            .map_err(|e| CredentialsError::Unexpected(format!("Failed to parse R2 credentials: {}", e)))?),
+        None => None,
+    };
+    Ok(CredentialsStatus::new(mongo_configured, r2))
+}
+
+/// Delete credentials from the active credential backend
 #[command]
 pub async fn delete_credentials(credential_type: String) -> Result<(), CredentialsError> {
-     let (service, account) = match credential_type.as_str() {
-         "mongo" => (KEYCHAIN_SERVICE_MONGO, KEYCHAIN_ACCOUNT_MONGO),
-         "r2" => (KEYCHAIN_SERVICE_R2, KEYCHAIN_ACCOUNT_R2),
-         _ => return Err(CredentialsError::Validation("Invalid credential type provided".to_string())),
-     };
-
-     let entry = Entry::new(service, account)
-         .map_err(|e| CredentialsError::Keychain(format!("Failed to create Keychain entry for deleting {}: {}", credential_type, e)))?;
-
-     match entry.delete_credential() {
-         Ok(_) => {
-             info!("Successfully deleted {} credentials", credential_type);
-             #[cfg(debug_assertions)] {
-                 // Also clear from dev file if it exists
-                 if credential_type == "mongo" {
-                     let mut dev_creds = load_dev_credentials().await;
-                     if dev_creds.mongo_connection_string.is_some() {
-                         dev_creds.mongo_connection_string = None;
-                         let _ = save_dev_credentials(&dev_creds).await;
-                     }
-                 } else if credential_type == "r2" {
-                      let mut dev_creds = load_dev_credentials().await;
-                     if dev_creds.r2_credentials.is_some() {
-                         dev_creds.r2_credentials = None;
-                         let _ = save_dev_credentials(&dev_creds).await;
-                     }
-                 }
-             }
-             Ok(())
-         },
-         Err(keyring_error) => {
-             if matches!(keyring_error, keyring::Error::NoEntry) {
-                 info!("{} credentials not found during deletion attempt, considered successful.", credential_type);
-                 Ok(()) // Not finding it is okay for deletion
-             } else {
-                 error!("Failed to delete {} credentials: {}", credential_type, keyring_error);
-                 Err(keyring_error.into())
-             }
-         }
-     }
+    let (service, account) = credential_entry(&credential_type)?;
+    backend::active_backend()?.delete(service, account)
+        .map_err(|e| { error!("Failed to delete {} credentials: {}", credential_type, e); e })?;
+    info!("Successfully deleted {} credentials", credential_type);
+    Ok(())
+}
+
+/// Reports which backend holds credentials and whether it still needs a passphrase
+#[command]
+pub async fn get_credential_backend_info() -> Result<CredentialBackendInfo, CredentialsError> {
+    Ok(backend_info())
+}
+
+/// Unlocks the encrypted credentials file. The passphrase is kept in memory only.
+#[command]
+pub async fn unlock_credential_store(passphrase: String) -> Result<CredentialBackendInfo, CredentialsError> {
+    if backend::keychain_available() {
+        return Err(CredentialsError::Validation("Credentials are stored in the system keychain; no passphrase is needed".to_string()));
+    }
+    backend::unlock(passphrase)?;
+    info!("Unlocked encrypted credentials file");
+    Ok(backend_info())
+}
+
+/// Moves credentials stranded outside the keychain into it: those in the encrypted file,
+/// saved while the keychain was unavailable, and those in the plaintext
+/// `dev_credentials.json` of debug builds. Entries the keychain already has are left
+/// alone; where both files hold one, the encrypted file's is used. With `delete_file`,
+/// each file is removed afterwards unless one of its entries conflicted.
+#[command]
+pub async fn migrate_file_credentials_to_keychain(passphrase: Option<String>, delete_file: bool) -> Result<CredentialMigrationReport, CredentialsError> {
+    if !backend::keychain_available() {
+        return Err(CredentialsError::Configuration("No system keychain is available to move credentials into".to_string()));
+    }
+    let keychain = backend::KeychainBackend;
+    let mut report = CredentialMigrationReport::default();
+
+    let file_path = backend::encrypted_file_path();
+    if file_path.exists() {
+        report.file_found = true;
+        let file = backend::encrypted_file_backend(passphrase)?;
+        let conflicts = migrate_entries(&file, &keychain, &mut report)?;
+        if delete_file {
+            report.file_deleted = delete_migrated_file(&file_path, conflicts)?;
+        }
+    }
+
+    let dev_path = std::path::PathBuf::from(DEV_CREDENTIALS_FILE);
+    if dev_path.exists() {
+        report.dev_file_found = true;
+        let conflicts = migrate_entries(&DevCredentials::load(&dev_path)?, &keychain, &mut report)?;
+        if delete_file {
+            report.dev_file_deleted = delete_migrated_file(&dev_path, conflicts)?;
+        }
+    }
+
+    info!(
+        "Migrated credentials to the keychain: {:?} copied, {:?} already present, {:?} conflicting",
+        report.migrated, report.already_in_keychain, report.conflicts
+    );
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_status_leaves_out_secret_key() {
+        let creds = R2Credentials {
+            account_id: "acct".to_string(),
+            bucket_name: "masters".to_string(),
+            access_key_id: "AKID".to_string(),
+            secret_access_key: "s3cr3t".to_string(),
+            endpoint: "https://acct.r2.cloudflarestorage.com".to_string(),
+        };
+        let status = CredentialsStatus::new(true, Some(creds));
+        let json = serde_json::to_string(&status).unwrap();
+        assert!(status.r2_configured && status.mongo_configured);
+        assert_eq!(status.r2_bucket_name.as_deref(), Some("masters"));
+        assert!(!json.contains("s3cr3t"));
+
+        let status = CredentialsStatus::new(false, None);
+        assert!(!status.r2_configured && status.r2_account_id.is_none());
+    }
+
+    #[test]
+    fn test_migrate_entries_keeps_conflicting_target_values() {
+        use backend::{CredentialBackend, EncryptedFileBackend};
+        let dir = tempfile::tempdir().unwrap();
+        let source = EncryptedFileBackend::new(dir.path().join("source.enc"), "pass".to_string());
+        let target = EncryptedFileBackend::new(dir.path().join("target.enc"), "pass".to_string());
+        source.set(KEYCHAIN_SERVICE_MONGO, KEYCHAIN_ACCOUNT_MONGO, "mongodb://old").unwrap();
+        source.set(KEYCHAIN_SERVICE_R2, KEYCHAIN_ACCOUNT_R2, "{}").unwrap();
+        source.set(KEYCHAIN_SERVICE_ENCRYPTION, KEYCHAIN_ACCOUNT_ORIGINALS_KEY, "a2V5").unwrap();
+        target.set(KEYCHAIN_SERVICE_MONGO, KEYCHAIN_ACCOUNT_MONGO, "mongodb://new").unwrap();
+        target.set(KEYCHAIN_SERVICE_R2, KEYCHAIN_ACCOUNT_R2, "{}").unwrap();
+
+        let mut report = CredentialMigrationReport::default();
+        assert_eq!(migrate_entries(&source, &target, &mut report).unwrap(), 1);
+        assert_eq!(report.migrated, vec!["originals_key"]);
+        assert_eq!(report.already_in_keychain, vec!["r2"]);
+        assert_eq!(report.conflicts, vec!["mongo"]);
+        assert_eq!(target.get(KEYCHAIN_SERVICE_MONGO, KEYCHAIN_ACCOUNT_MONGO).unwrap().as_deref(), Some("mongodb://new"));
+        assert_eq!(target.get(KEYCHAIN_SERVICE_ENCRYPTION, KEYCHAIN_ACCOUNT_ORIGINALS_KEY).unwrap().as_deref(), Some("a2V5"));
+    }
+
+    #[test]
+    fn test_dev_credentials_file() {
+        use backend::CredentialBackend;
+        let dev: DevCredentials = serde_json::from_str(r#"{
+            "mongo_connection_string": "mongodb://localhost",
+            "r2_credentials": { "account_id": "acct", "bucket_name": "b", "access_key_id": "AKID", "secret_access_key": "s", "endpoint": "https://e" }
+        }"#).unwrap();
+        assert_eq!(dev.get(KEYCHAIN_SERVICE_MONGO, KEYCHAIN_ACCOUNT_MONGO).unwrap().as_deref(), Some("mongodb://localhost"));
+        let r2: R2Credentials = serde_json::from_str(&dev.get(KEYCHAIN_SERVICE_R2, KEYCHAIN_ACCOUNT_R2).unwrap().unwrap()).unwrap();
+        assert_eq!(r2.bucket_name, "b");
+        assert_eq!(dev.get(KEYCHAIN_SERVICE_ENCRYPTION, KEYCHAIN_ACCOUNT_ORIGINALS_KEY).unwrap(), None);
+        assert!(dev.set(KEYCHAIN_SERVICE_MONGO, KEYCHAIN_ACCOUNT_MONGO, "x").is_err());
+    }
 }
@@ -0,0 +1,349 @@
+//! QA helpers for dev builds. The commands are only registered with
+//! `#[cfg(debug_assertions)]`, and destructive ones also need a short-lived
+//! confirmation token from `request_dangerous_operation`.
+
+use ::mongodb::bson::{self, doc, oid::ObjectId, DateTime, Document};
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tauri::{command, State};
+use uuid::Uuid;
+
+use crate::MongoState;
+use crate::features::catalog::changes::{record_deletions, stamp, CatalogKind};
+use crate::features::catalog::storage::id_to_string;
+use crate::core::db_config::{self, CatalogCollections, CatalogDatabase};
+
+/// How long a confirmation token can be redeemed after it was issued.
+const TOKEN_TTL: Duration = Duration::from_secs(60);
+
+/// Operations that need a confirmation token.
+const DANGEROUS_OPERATIONS: &[&str] = &["clear_test_data"];
+
+/// Flag on every document `seed_test_data` writes; `clear_test_data` deletes by it.
+const TEST_DATA_FLAG: &str = "is_test_data";
+
+const DEFAULT_FIXTURE_TRACKS: u32 = 5;
+const MAX_FIXTURE_TRACKS: u32 = 100;
+
+/// Confirmation tokens issued by `request_dangerous_operation`, each good for one call.
+#[derive(Debug, Default)]
+pub struct DangerousOperationTokens {
+    // Token -> (operation, issued at)
+    tokens: Mutex<HashMap<String, (String, Instant)>>,
+}
+
+impl DangerousOperationTokens {
+    fn issue(&self, operation: &str, now: Instant) -> String {
+        let token = Uuid::new_v4().to_string();
+        let mut tokens = self.tokens.lock().unwrap_or_else(|e| e.into_inner());
+        tokens.retain(|_, (_, issued)| now.saturating_duration_since(*issued) < TOKEN_TTL);
+        tokens.insert(token.clone(), (operation.to_string(), now));
+        token
+    }
+
+    /// Consumes the token if it was issued for `operation` less than `TOKEN_TTL` ago.
+    fn redeem(&self, operation: &str, token: &str, now: Instant) -> Result<(), String> {
+        let mut tokens = self.tokens.lock().unwrap_or_else(|e| e.into_inner());
+        match tokens.remove(token) {
+            Some((issued_for, _)) if issued_for != operation => {
+                Err(format!("Confirmation token was issued for '{}', not '{}'", issued_for, operation))
+            }
+            Some((_, issued)) if now.saturating_duration_since(issued) >= TOKEN_TTL => {
+                Err("Confirmation token has expired; request a new one".to_string())
+            }
+            Some(_) => Ok(()),
+            None => Err("Unknown or already used confirmation token".to_string()),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ClearTestDataResponse {
+    pub success: bool,
+    pub message: String,
+}
+
+/// A fixture album and `track_count` tracks on it, all flagged `is_test_data: true`.
+/// `run` keeps the album apart from those of earlier seeds in the unique
+/// {name, artist, grouping_key} index.
+fn fixture_documents(run: &str, track_count: u32) -> (Document, Vec<Document>) {
+    let album_id = ObjectId::new();
+    let artist = "QA Fixture Artist";
+    let tracks: Vec<Document> = (1..=track_count)
+        .map(|n| {
+            let mut track = doc! {
+                "_id": ObjectId::new(),
+                "title": format!("QA Fixture Track {}", n),
+                "album_id": album_id,
+                "track_number": n as i32,
+                "filename": format!("qa_fixture_{}.wav", n),
+                "duration": 180,
+                "artists": [artist],
+                "writers": Document::new(),
+                "publishers": Document::new(),
+                "genre": ["Test"],
+                "date_added": DateTime::now(),
+                TEST_DATA_FLAG: true,
+            };
+            stamp(&mut track);
+            track
+        })
+        .collect();
+    let mut album = doc! {
+        "_id": album_id,
+        "name": format!("QA Fixture Album {}", run),
+        "artist": artist,
+        "compilation": false,
+        "art_path": null,
+        "date_added": DateTime::now(),
+        TEST_DATA_FLAG: true,
+    };
+    stamp(&mut album);
+    (album, tracks)
+}
+
+/// Issues a confirmation token for a destructive dev command, valid for one call
+/// within 60 seconds.
+#[command]
+pub async fn request_dangerous_operation(
+    operation: String,
+    tokens: State<'_, DangerousOperationTokens>,
+) -> Result<String, String> {
+    if !DANGEROUS_OPERATIONS.contains(&operation.as_str()) {
+        return Err(format!("'{}' is not a dangerous operation", operation));
+    }
+    warn!("Issued a confirmation token for '{}'", operation);
+    Ok(tokens.issue(&operation, Instant::now()))
+}
+
+/// Writes a fixture album with `track_count` tracks (5 by default, at most 100) for QA.
+/// Everything it writes is flagged `is_test_data: true`, so `clear_test_data` removes it.
+#[command]
+pub async fn seed_test_data(
+    track_count: Option<u32>,
+    mongo_state: State<'_, MongoState>,
+) -> Result<String, String> {
+    let track_count = track_count.unwrap_or(DEFAULT_FIXTURE_TRACKS).clamp(1, MAX_FIXTURE_TRACKS);
+    let mongo_client_lock = mongo_state.client.lock().await;
+    let mongo_client = mongo_client_lock.as_ref()
+        .ok_or("MongoDB client not initialized. Please configure credentials first.")?;
+    let db = mongo_client.catalog_database();
+
+    let run = Uuid::new_v4().simple().to_string();
+    let (album, tracks) = fixture_documents(&run[..8], track_count);
+    let album_name = album.get_str("name").unwrap_or_default().to_string();
+    db.albums::<Document>().insert_one(album, None).await
+        .map_err(|e| format!("Failed to insert test album: {}", e))?;
+    db.tracks::<Document>().insert_many(tracks, None).await
+        .map_err(|e| format!("Failed to insert test tracks: {}", e))?;
+
+    info!("Seeded test album '{}' with {} tracks", album_name, track_count);
+    Ok(format!("Seeded test album '{}' with {} tracks", album_name, track_count))
+}
+
+/// Deletes tracks and albums written by `seed_test_data`, i.e. those flagged
+/// `is_test_data: true`. Titles are never matched, so real tracks are safe.
+#[command]
+pub async fn clear_test_data(
+    confirmation_token: String,
+    tokens: State<'_, DangerousOperationTokens>,
+    mongo_state: State<'_, MongoState>,
+) -> Result<ClearTestDataResponse, String> {
+    tokens.redeem("clear_test_data", &confirmation_token, Instant::now())?;
+    info!("Clearing test data from database");
+
+    // Get Mongo client from state
+    let mongo_client_lock = mongo_state.client.lock().await;
+    let mongo_client = mongo_client_lock.as_ref()
+        .ok_or("MongoDB client not initialized. Please configure credentials first.")?;
+    let db = mongo_client.catalog_database();
+    let test_data_filter = doc! { TEST_DATA_FLAG: true };
+
+    let tracks_collection: ::mongodb::Collection<bson::Document> = db.tracks();
+    // Tombstones so other editors drop the fixtures too
+    let test_track_ids = tracks_collection.distinct("_id", test_data_filter.clone(), None).await.unwrap_or_default();
+    let tracks_deleted = match tracks_collection.delete_many(test_data_filter.clone(), None).await {
+        Ok(result) => result.deleted_count,
+        Err(e) => {
+            error!("Failed to delete test tracks: {}", e);
+            return Ok(ClearTestDataResponse {
+                success: false,
+                message: format!("Failed to delete test tracks: {}", e),
+            });
+        }
+    };
+
+    record_deletions(&db, CatalogKind::Track, test_track_ids.iter().filter_map(id_to_string).collect()).await;
+
+    let albums_collection: ::mongodb::Collection<bson::Document> = db.albums();
+    let test_album_ids = albums_collection.distinct("_id", test_data_filter.clone(), None).await.unwrap_or_default();
+    let albums_deleted = match albums_collection.delete_many(test_data_filter, None).await {
+        Ok(result) => result.deleted_count,
+        Err(e) => {
+            error!("Failed to delete test albums: {}", e);
+            return Ok(ClearTestDataResponse {
+                success: false,
+                message: format!("Failed to delete test albums: {}", e),
+            });
+        }
+    };
+    record_deletions(&db, CatalogKind::Album, test_album_ids.iter().filter_map(id_to_string).collect()).await;
+
+    info!("Deleted {} test tracks and {} test albums", tracks_deleted, albums_deleted);
+
+    Ok(ClearTestDataResponse {
+        success: true,
+        message: format!("Successfully cleared test data: {} tracks and {} albums deleted",
+            tracks_deleted, albums_deleted),
+    })
+}
+
+/// Command to test MongoDB connectivity and check collection stats
+#[command]
+pub async fn test_mongodb_collections(
+    mongo_state: State<'_, MongoState>,
+) -> Result<String, String> {
+    info!("Testing MongoDB collections");
+
+    // Get Mongo client from state
+    let mongo_client_lock = mongo_state.client.lock().await;
+    let mongo_client = match mongo_client_lock.as_ref() {
+        Some(client) => {
+            info!("MongoDB client found in state");
+            client
+        },
+        None => {
+            error!("MongoDB client not initialized");
+            return Err("MongoDB client not initialized. Please configure credentials first.".to_string());
+        }
+    };
+
+    // Create a database reference
+    let db_config = db_config::active();
+    info!("Creating database reference to '{}'", db_config.database_name);
+    let db = mongo_client.catalog_database();
+
+    // Get collection names
+    let collection_names = match db.list_collection_names(None).await {
+        Ok(names) => {
+            info!("Found collections: {:?}", names);
+            names
+        },
+        Err(e) => {
+            error!("Failed to list collections: {}", e);
+            return Err(format!("Failed to list collections: {}", e));
+        }
+    };
+
+    let mut result = format!("Found {} collections: {:?}\n", collection_names.len(), collection_names);
+
+    // Check tracks collection
+    if collection_names.contains(&db_config.tracks_collection) {
+        let tracks_collection: ::mongodb::Collection<bson::Document> = db.tracks(); // Use fully qualified type
+        match tracks_collection.count_documents(None, None).await {
+            Ok(count) => {
+                info!("Tracks collection has {} documents", count);
+                result.push_str(&format!("Tracks collection: {} documents\n", count));
+
+                // Get a sample track if any exist
+                if count > 0 {
+                    match tracks_collection.find_one(None, None).await {
+                        Ok(Some(doc)) => { // doc here is a variable, not the macro
+                            info!("Sample track document: {:?}", doc);
+                            result.push_str(&format!("Sample track fields: {}\n",
+                                doc.keys().map(|k| k.to_string()).collect::<Vec<String>>().join(", ")));
+                        },
+                        Ok(None) => {
+                            warn!("No track found despite count > 0");
+                            result.push_str("Could not retrieve sample track\n");
+                        },
+                        Err(e) => {
+                            error!("Error fetching sample track: {}", e);
+                            result.push_str(&format!("Error fetching sample track: {}\n", e));
+                        }
+                    }
+                }
+            },
+            Err(e) => {
+                error!("Error counting tracks: {}", e);
+                result.push_str(&format!("Error counting tracks: {}\n", e));
+            }
+        }
+    }
+
+    // Check albums collection
+    if collection_names.contains(&db_config.albums_collection) {
+        let albums_collection: ::mongodb::Collection<bson::Document> = db.albums(); // Use fully qualified type
+        match albums_collection.count_documents(None, None).await {
+            Ok(count) => {
+                info!("Albums collection has {} documents", count);
+                result.push_str(&format!("Albums collection: {} documents\n", count));
+
+                // Get a sample album if any exist
+                if count > 0 {
+                    match albums_collection.find_one(None, None).await {
+                        Ok(Some(doc)) => { // doc here is a variable, not the macro
+                            info!("Sample album document: {:?}", doc);
+                            result.push_str(&format!("Sample album fields: {}\n",
+                                doc.keys().map(|k| k.to_string()).collect::<Vec<String>>().join(", ")));
+                        },
+                        Ok(None) => {
+                            warn!("No album found despite count > 0");
+                            result.push_str("Could not retrieve sample album\n");
+                        },
+                        Err(e) => {
+                            error!("Error fetching sample album: {}", e);
+                            result.push_str(&format!("Error fetching sample album: {}\n", e));
+                        }
+                    }
+                }
+            },
+            Err(e) => {
+                error!("Error counting albums: {}", e);
+                result.push_str(&format!("Error counting albums: {}\n", e));
+            }
+        }
+    }
+
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fixtures_are_flagged_as_test_data() {
+        let (album, tracks) = fixture_documents("run1", 3);
+        assert_eq!(tracks.len(), 3);
+        assert_eq!(album.get_bool(TEST_DATA_FLAG), Ok(true));
+        let album_id = album.get_object_id("_id").unwrap();
+        for track in &tracks {
+            assert_eq!(track.get_bool(TEST_DATA_FLAG), Ok(true));
+            assert_eq!(track.get_object_id("album_id"), Ok(album_id));
+        }
+        // A second seed gets an album of its own
+        let (other, _) = fixture_documents("run2", 1);
+        assert_ne!(album.get_str("name"), other.get_str("name"));
+    }
+
+    #[test]
+    fn test_tokens_are_single_use_and_expire() {
+        let tokens = DangerousOperationTokens::default();
+        let now = Instant::now();
+
+        let token = tokens.issue("clear_test_data", now);
+        assert!(tokens.redeem("clear_test_data", &token, now + Duration::from_secs(59)).is_ok());
+        assert!(tokens.redeem("clear_test_data", &token, now + Duration::from_secs(59)).is_err());
+
+        let token = tokens.issue("clear_test_data", now);
+        assert!(tokens.redeem("clear_test_data", &token, now + TOKEN_TTL).is_err());
+
+        let token = tokens.issue("clear_test_data", now);
+        assert!(tokens.redeem("drop_everything", &token, now).is_err());
+        assert!(tokens.redeem("clear_test_data", "not-a-token", now).is_err());
+    }
+}
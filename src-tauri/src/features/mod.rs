@@ -2,6 +2,9 @@
 pub mod catalog;
 pub mod upload;
 pub mod credentials;
+pub mod activity;
+pub mod onboarding;
+pub mod devtools;
 
 // Import the CommandError type directly from the crate root
 use crate::core::r2; // This is just to demonstrate that `crate` refers to app_lib
\ No newline at end of file
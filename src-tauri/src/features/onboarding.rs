@@ -0,0 +1,154 @@
+//! First-run setup: which prerequisites are in place, and which steps of the setup
+//! wizard the user has completed. Until setup has been completed once, failures of the
+//! background client initialization are reported as a single `setup-required` event
+//! rather than as errors.
+//!
+//! Progress is kept in `onboarding.json` in the app config directory.
+
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+use tauri::{command, AppHandle, Manager, State, Wry};
+
+use crate::core::json_file::write_json_atomically;
+use crate::features::credentials::has_credentials;
+use crate::CommandError;
+use crate::{MongoState, R2State};
+
+/// Steps of the setup wizard, in order. Setup is complete once all are done.
+pub const SETUP_STEPS: [&str; 4] = ["r2_credentials", "mongo_credentials", "ffmpeg", "clients"];
+
+const ONBOARDING_FILE_NAME: &str = "onboarding.json";
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct OnboardingState {
+    pub onboarding_completed: bool,
+    pub completed_steps: BTreeSet<String>,
+}
+
+/// Returned by `get_setup_status`.
+#[derive(Debug, Clone, Serialize)]
+pub struct SetupStatus {
+    pub r2_credentials_stored: bool,
+    pub mongo_credentials_stored: bool,
+    pub ffmpeg_found: bool,
+    /// First line of `ffmpeg -version`
+    pub ffmpeg_version: Option<String>,
+    pub mongo_client_initialized: bool,
+    pub r2_client_initialized: bool,
+    pub onboarding_completed: bool,
+    pub completed_steps: Vec<String>,
+}
+
+fn onboarding_path(app_handle: &AppHandle<Wry>) -> Result<PathBuf, CommandError> {
+    let dir = app_handle.path().app_config_dir()
+        .map_err(|e| CommandError::FileSystem(format!("Failed to resolve app config directory: {}", e)))?;
+    Ok(dir.join(ONBOARDING_FILE_NAME))
+}
+
+/// Reads the saved onboarding progress; a missing or unreadable file means nothing has
+/// been completed.
+pub fn load_onboarding_state(app_handle: &AppHandle<Wry>) -> OnboardingState {
+    let Ok(path) = onboarding_path(app_handle) else {
+        return OnboardingState::default();
+    };
+    match fs::read_to_string(&path) {
+        Ok(json) => serde_json::from_str(&json).unwrap_or_else(|e| {
+            warn!("Invalid onboarding state in {:?}, starting over: {}", path, e);
+            OnboardingState::default()
+        }),
+        Err(_) => OnboardingState::default(), // First run
+    }
+}
+
+pub fn save_onboarding_state(app_handle: &AppHandle<Wry>, state: &OnboardingState) -> Result<(), CommandError> {
+    let path = onboarding_path(app_handle)?;
+    write_json_atomically(&path, state)?;
+    Ok(())
+}
+
+/// Marks a step done, and setup complete once every step is.
+fn apply_step(state: &mut OnboardingState, step: &str) -> Result<(), CommandError> {
+    if !SETUP_STEPS.contains(&step) {
+        return Err(CommandError::Validation(format!("Unknown setup step '{}', expected one of {}", step, SETUP_STEPS.join(", "))));
+    }
+    state.completed_steps.insert(step.to_string());
+    if SETUP_STEPS.iter().all(|step| state.completed_steps.contains(*step)) {
+        state.onboarding_completed = true;
+    }
+    Ok(())
+}
+
+/// First line of `ffmpeg -version`, or `None` if ffmpeg isn't on the PATH. Blocks while
+/// ffmpeg runs.
+pub fn ffmpeg_version() -> Option<String> {
+    let output = Command::new("ffmpeg")
+        .arg("-version")
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .output()
+        .ok()
+        .filter(|output| output.status.success())?;
+    String::from_utf8_lossy(&output.stdout).lines().next().map(|line| line.trim().to_string())
+}
+
+// --- Tauri Commands ---
+
+/// Reports which setup prerequisites are satisfied, for the setup wizard.
+#[command]
+pub async fn get_setup_status(
+    app_handle: AppHandle<Wry>,
+    mongo_state: State<'_, MongoState>,
+    r2_state: State<'_, R2State>,
+) -> Result<SetupStatus, CommandError> {
+    let stored = |credential_type: &str| {
+        let credential_type = credential_type.to_string();
+        async move { has_credentials(credential_type).await.unwrap_or(false) }
+    };
+    let ffmpeg_version = tokio::task::spawn_blocking(ffmpeg_version)
+        .await
+        .map_err(|e| CommandError::Unexpected(format!("ffmpeg check task join error: {}", e)))?;
+    let state = load_onboarding_state(&app_handle);
+
+    Ok(SetupStatus {
+        r2_credentials_stored: stored("r2").await,
+        mongo_credentials_stored: stored("mongo").await,
+        ffmpeg_found: ffmpeg_version.is_some(),
+        ffmpeg_version,
+        mongo_client_initialized: mongo_state.client.lock().await.is_some(),
+        r2_client_initialized: r2_state.client.lock().await.is_some(),
+        onboarding_completed: state.onboarding_completed,
+        completed_steps: state.completed_steps.into_iter().collect(),
+    })
+}
+
+/// Records that a setup step passed. Returns the updated progress.
+#[command]
+pub async fn complete_setup_step(step: String, app_handle: AppHandle<Wry>) -> Result<OnboardingState, CommandError> {
+    let mut state = load_onboarding_state(&app_handle);
+    apply_step(&mut state, &step)?;
+    save_onboarding_state(&app_handle, &state)?;
+    info!("Setup step '{}' completed (setup complete: {})", step, state.onboarding_completed);
+    Ok(state)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_setup_completes_after_every_step() {
+        let mut state = OnboardingState::default();
+        for step in &SETUP_STEPS[..3] {
+            apply_step(&mut state, step).unwrap();
+        }
+        assert!(!state.onboarding_completed);
+        apply_step(&mut state, "clients").unwrap();
+        assert!(state.onboarding_completed);
+        assert!(apply_step(&mut state, "dns").is_err());
+    }
+}
@@ -0,0 +1,345 @@
+//! Tempo (BPM) and musical key estimation, stored on tracks for sync licensing searches.
+//!
+//! Both estimates use a mono mix of at most the middle 60 seconds of a track. BPM is the
+//! strongest period in the autocorrelation of a spectral-flux onset envelope; the key is
+//! the Krumhansl-Kessler profile that best correlates with the track's chroma vector.
+//! The results are estimates and can be corrected through `update_track_metadata`.
+
+use rustfft::num_complex::Complex;
+use rustfft::FftPlanner;
+use serde::{Deserialize, Serialize};
+use std::f32::consts::PI;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+
+use symphonia::core::audio::SampleBuffer;
+use symphonia::core::codecs::DecoderOptions;
+use symphonia::core::errors::Error as SymphoniaError;
+use symphonia::core::formats::{FormatOptions, SeekMode, SeekTo};
+use symphonia::core::io::MediaSourceStream;
+use symphonia::core::meta::MetadataOptions;
+use symphonia::core::probe::Hint;
+use symphonia::core::units::Time;
+use symphonia::default::{get_codecs, get_probe};
+use tauri::command;
+
+use crate::CommandError;
+
+/// Longest stretch of audio analyzed, taken from the middle of the track.
+pub const ANALYSIS_WINDOW_SECS: f64 = 60.0;
+
+/// Tempo range searched by `estimate_bpm`.
+const MIN_BPM: f32 = 60.0;
+const MAX_BPM: f32 = 200.0;
+/// Tempo the octave weighting is centred on, so 120 wins over 60 and 240 for the same beat.
+const CENTRE_BPM: f32 = 120.0;
+
+const ONSET_FRAME_LEN: usize = 1024;
+const ONSET_HOP: usize = 512;
+const CHROMA_FRAME_LEN: usize = 8192;
+/// Frequencies outside this range are mostly percussion or overtones and blur the chroma.
+const CHROMA_MIN_HZ: f32 = 80.0;
+const CHROMA_MAX_HZ: f32 = 2000.0;
+
+const PITCH_CLASSES: [&str; 12] = ["C", "C#", "D", "D#", "E", "F", "F#", "G", "G#", "A", "A#", "B"];
+const MAJOR_PROFILE: [f32; 12] = [6.35, 2.23, 3.48, 2.33, 4.38, 4.09, 2.52, 5.19, 2.39, 3.66, 2.29, 2.88];
+const MINOR_PROFILE: [f32; 12] = [6.33, 2.68, 3.52, 5.38, 2.60, 3.53, 2.54, 4.75, 3.98, 2.69, 3.34, 3.17];
+
+/// Estimated tempo and key. Either is `None` if the audio gives no usable estimate.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct TrackFeatures {
+    pub bpm: Option<f32>,
+    /// Tonic and mode, e.g. "A minor"
+    pub musical_key: Option<String>,
+}
+
+/// Decodes an audio file and estimates its tempo and key.
+pub fn analyze_file(path: &Path) -> Result<TrackFeatures, String> {
+    let (samples, sample_rate) = decode_mono_window(path, ANALYSIS_WINDOW_SECS)?;
+    Ok(analyze_samples(&samples, sample_rate))
+}
+
+/// Estimates tempo and key from mono samples.
+pub fn analyze_samples(samples: &[f32], sample_rate: u32) -> TrackFeatures {
+    TrackFeatures {
+        bpm: estimate_bpm(samples, sample_rate),
+        musical_key: estimate_key(samples, sample_rate),
+    }
+}
+
+/// Decodes up to `window_secs` of audio from the middle of the file, downmixed to mono.
+/// Files whose length isn't known up front are read from the start.
+fn decode_mono_window(path: &Path, window_secs: f64) -> Result<(Vec<f32>, u32), String> {
+    let file = File::open(path).map_err(|e| format!("Failed to open file: {}", e))?;
+    let source = MediaSourceStream::new(Box::new(file), Default::default());
+
+    let mut hint = Hint::new();
+    if let Some(ext_str) = path.extension().and_then(|e| e.to_str()) {
+        hint.with_extension(ext_str);
+    }
+
+    let probe_result = get_probe()
+        .format(&hint, source, &FormatOptions::default(), &MetadataOptions::default())
+        .map_err(|e| format!("Failed to probe format: {}", e))?;
+    let mut format = probe_result.format;
+
+    let track = format.default_track().ok_or_else(|| "No default track found".to_string())?;
+    let track_id = track.id;
+    let sample_rate = track.codec_params.sample_rate.ok_or_else(|| "Unknown sample rate".to_string())?;
+    let total_secs = track.codec_params.n_frames.map(|frames| frames as f64 / sample_rate as f64);
+    let mut decoder = get_codecs()
+        .make(&track.codec_params, &DecoderOptions::default())
+        .map_err(|e| format!("Unsupported codec: {}", e))?;
+
+    let start_secs = total_secs.map_or(0.0, |total| ((total - window_secs) / 2.0).max(0.0));
+    if start_secs > 0.0 {
+        // A coarse seek is close enough; if it fails the window just starts earlier
+        let seek_to = SeekTo::Time { time: Time::from(start_secs), track_id: Some(track_id) };
+        if format.seek(SeekMode::Coarse, seek_to).is_ok() {
+            decoder.reset();
+        }
+    }
+
+    let max_frames = (window_secs * sample_rate as f64) as usize;
+    let mut mono: Vec<f32> = Vec::with_capacity(max_frames);
+    let mut sample_buf: Option<SampleBuffer<f32>> = None;
+
+    while mono.len() < max_frames {
+        let packet = match format.next_packet() {
+            Ok(packet) => packet,
+            // End of stream is reported as an unexpected EOF
+            Err(SymphoniaError::IoError(e)) if e.kind() == std::io::ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(format!("Failed to read packet: {}", e)),
+        };
+        if packet.track_id() != track_id {
+            continue;
+        }
+
+        let decoded = match decoder.decode(&packet) {
+            Ok(decoded) => decoded,
+            // Skip corrupt packets rather than failing the whole track
+            Err(SymphoniaError::DecodeError(_)) => continue,
+            Err(e) => return Err(format!("Failed to decode audio: {}", e)),
+        };
+
+        let buf = sample_buf.get_or_insert_with(|| SampleBuffer::new(decoded.capacity() as u64, *decoded.spec()));
+        let channels = decoded.spec().channels.count().max(1);
+        buf.copy_interleaved_ref(decoded);
+        mono.extend(buf.samples().chunks(channels).map(|frame| frame.iter().sum::<f32>() / channels as f32));
+    }
+    mono.truncate(max_frames);
+
+    if mono.is_empty() {
+        return Err("No audio samples decoded".to_string());
+    }
+    Ok((mono, sample_rate))
+}
+
+/// Calls `f` with the magnitude spectrum of each Hann-windowed frame of `samples`.
+fn for_each_spectrum(samples: &[f32], frame_len: usize, hop: usize, mut f: impl FnMut(&[f32])) {
+    if samples.len() < frame_len {
+        return;
+    }
+    let fft = FftPlanner::<f32>::new().plan_fft_forward(frame_len);
+    let window: Vec<f32> = (0..frame_len)
+        .map(|i| 0.5 - 0.5 * (2.0 * PI * i as f32 / frame_len as f32).cos())
+        .collect();
+    let mut buffer = vec![Complex::new(0.0f32, 0.0); frame_len];
+    let mut magnitudes = vec![0.0f32; frame_len / 2 + 1];
+
+    for start in (0..=samples.len() - frame_len).step_by(hop) {
+        for (slot, (sample, weight)) in buffer.iter_mut().zip(samples[start..start + frame_len].iter().zip(&window)) {
+            *slot = Complex::new(sample * weight, 0.0);
+        }
+        fft.process(&mut buffer);
+        for (magnitude, bin) in magnitudes.iter_mut().zip(&buffer) {
+            *magnitude = bin.norm();
+        }
+        f(&magnitudes);
+    }
+}
+
+/// Estimates the tempo in BPM, rounded to one decimal place.
+pub fn estimate_bpm(samples: &[f32], sample_rate: u32) -> Option<f32> {
+    // Onset strength: how much the log spectrum rose since the previous frame
+    let mut envelope: Vec<f32> = Vec::new();
+    let mut previous: Vec<f32> = Vec::new();
+    for_each_spectrum(samples, ONSET_FRAME_LEN, ONSET_HOP, |magnitudes| {
+        let current: Vec<f32> = magnitudes.iter().map(|m| m.ln_1p()).collect();
+        let flux = if previous.is_empty() {
+            0.0
+        } else {
+            current.iter().zip(&previous).map(|(now, before)| (now - before).max(0.0)).sum()
+        };
+        envelope.push(flux);
+        previous = current;
+    });
+
+    let frames_per_sec = sample_rate as f32 / ONSET_HOP as f32;
+    let min_lag = ((frames_per_sec * 60.0 / MAX_BPM).floor() as usize).max(1);
+    let max_lag = (frames_per_sec * 60.0 / MIN_BPM).ceil() as usize;
+    if envelope.len() < max_lag * 2 {
+        return None;
+    }
+    // Onsets rarely land on frame boundaries, so a beat period alternates between
+    // neighbouring whole-frame lags. Smoothing keeps those lags from scoring below twice the period.
+    let mut envelope = smooth(&envelope);
+    let mean = envelope.iter().sum::<f32>() / envelope.len() as f32;
+    envelope.iter_mut().for_each(|value| *value -= mean);
+
+    let autocorrelation = |lag: usize| {
+        envelope.iter().zip(&envelope[lag..]).map(|(a, b)| a * b).sum::<f32>() / (envelope.len() - lag) as f32
+    };
+    let lag_bpm = |lag: f32| 60.0 * frames_per_sec / lag;
+    let (best_lag, best_score) = (min_lag..=max_lag)
+        .map(|lag| {
+            let octaves_from_centre = (lag_bpm(lag as f32) / CENTRE_BPM).log2();
+            (lag, autocorrelation(lag) * (-0.5 * octaves_from_centre * octaves_from_centre).exp())
+        })
+        .max_by(|a, b| a.1.total_cmp(&b.1))?;
+    if best_score <= 0.0 {
+        return None;
+    }
+
+    // Interpolate between neighbouring lags for a finer tempo than whole frames allow
+    let mut lag = best_lag as f32;
+    if best_lag > min_lag && best_lag < max_lag {
+        let (before, at, after) = (autocorrelation(best_lag - 1), autocorrelation(best_lag), autocorrelation(best_lag + 1));
+        let curvature = before - 2.0 * at + after;
+        if curvature.abs() > f32::EPSILON {
+            lag += (0.5 * (before - after) / curvature).clamp(-0.5, 0.5);
+        }
+    }
+    Some((lag_bpm(lag) * 10.0).round() / 10.0)
+}
+
+/// Triangular 5-tap moving average.
+fn smooth(values: &[f32]) -> Vec<f32> {
+    const KERNEL: [f32; 5] = [1.0, 2.0, 3.0, 2.0, 1.0];
+    (0..values.len())
+        .map(|i| {
+            KERNEL.iter().enumerate()
+                .filter_map(|(k, weight)| (i + k).checked_sub(2).and_then(|j| values.get(j)).map(|v| v * weight))
+                .sum::<f32>() / 9.0
+        })
+        .collect()
+}
+
+/// Estimates the key, e.g. "C major" or "F# minor".
+pub fn estimate_key(samples: &[f32], sample_rate: u32) -> Option<String> {
+    let mut chroma = [0.0f32; 12];
+    let bin_hz = sample_rate as f32 / CHROMA_FRAME_LEN as f32;
+    for_each_spectrum(samples, CHROMA_FRAME_LEN, CHROMA_FRAME_LEN / 2, |magnitudes| {
+        for (bin, magnitude) in magnitudes.iter().enumerate() {
+            let freq = bin as f32 * bin_hz;
+            if !(CHROMA_MIN_HZ..=CHROMA_MAX_HZ).contains(&freq) {
+                continue;
+            }
+            let midi_note = 69.0 + 12.0 * (freq / 440.0).log2();
+            chroma[(midi_note.round() as i32).rem_euclid(12) as usize] += magnitude * magnitude;
+        }
+    });
+    if chroma.iter().all(|energy| *energy <= f32::EPSILON) {
+        return None;
+    }
+
+    let mut best: Option<(f32, String)> = None;
+    for tonic in 0..12 {
+        for (profile, mode) in [(&MAJOR_PROFILE, "major"), (&MINOR_PROFILE, "minor")] {
+            let rotated: Vec<f32> = (0..12).map(|pitch_class| profile[(pitch_class + 12 - tonic) % 12]).collect();
+            let score = correlation(&chroma, &rotated);
+            if best.as_ref().map_or(true, |(best_score, _)| score > *best_score) {
+                best = Some((score, format!("{} {}", PITCH_CLASSES[tonic], mode)));
+            }
+        }
+    }
+    best.map(|(_, key)| key)
+}
+
+/// Pearson correlation of two equally long series; 0 if either is constant.
+fn correlation(a: &[f32], b: &[f32]) -> f32 {
+    let mean = |values: &[f32]| values.iter().sum::<f32>() / values.len() as f32;
+    let (mean_a, mean_b) = (mean(a), mean(b));
+    let (mut covariance, mut variance_a, mut variance_b) = (0.0f32, 0.0f32, 0.0f32);
+    for (x, y) in a.iter().zip(b) {
+        covariance += (x - mean_a) * (y - mean_b);
+        variance_a += (x - mean_a) * (x - mean_a);
+        variance_b += (y - mean_b) * (y - mean_b);
+    }
+    let denominator = (variance_a * variance_b).sqrt();
+    if denominator > f32::EPSILON { covariance / denominator } else { 0.0 }
+}
+
+// --- Tauri Commands ---
+
+/// Estimates BPM and key for a local audio file without uploading it.
+#[command]
+pub async fn analyze_track_features(path: String) -> Result<TrackFeatures, CommandError> {
+    let path = PathBuf::from(path);
+    if !path.exists() {
+        return Err(CommandError::FileSystem(format!("File not found: {}", path.display())));
+    }
+    tokio::task::spawn_blocking(move || analyze_file(&path))
+        .await
+        .map_err(|e| CommandError::Unexpected(format!("Analysis task failed: {}", e)))?
+        .map_err(CommandError::Metadata)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE_RATE: u32 = 22050;
+
+    fn tones(freqs: &[f32], secs: f32) -> Vec<f32> {
+        let len = (secs * SAMPLE_RATE as f32) as usize;
+        (0..len)
+            .map(|i| {
+                let t = i as f32 / SAMPLE_RATE as f32;
+                freqs.iter().map(|f| (2.0 * PI * f * t).sin()).sum::<f32>() / freqs.len() as f32
+            })
+            .collect()
+    }
+
+    /// Short decaying 1 kHz blips, one per beat.
+    fn click_track(bpm: f32, secs: f32) -> Vec<f32> {
+        let mut samples = vec![0.0f32; (secs * SAMPLE_RATE as f32) as usize];
+        let beat_len = 60.0 / bpm * SAMPLE_RATE as f32;
+        let click_len = SAMPLE_RATE as usize / 50;
+        let mut beat = 0.0f32;
+        while (beat as usize) < samples.len() {
+            for (n, sample) in samples[beat as usize..].iter_mut().take(click_len).enumerate() {
+                let t = n as f32 / SAMPLE_RATE as f32;
+                *sample = 0.8 * (2.0 * PI * 1000.0 * t).sin() * (-t * 200.0).exp();
+            }
+            beat += beat_len;
+        }
+        samples
+    }
+
+    #[test]
+    fn test_estimate_bpm_from_click_track() {
+        let bpm = estimate_bpm(&click_track(120.0, 20.0), SAMPLE_RATE).unwrap();
+        assert!((bpm - 120.0).abs() < 3.0, "estimated {}", bpm);
+        let bpm = estimate_bpm(&click_track(90.0, 20.0), SAMPLE_RATE).unwrap();
+        assert!((bpm - 90.0).abs() < 3.0, "estimated {}", bpm);
+    }
+
+    #[test]
+    fn test_estimate_key_from_triads() {
+        // C3 under a C major triad, then A2 and A3 under an A minor triad
+        assert_eq!(estimate_key(&tones(&[130.81, 261.63, 329.63, 392.0], 5.0), SAMPLE_RATE).as_deref(), Some("C major"));
+        assert_eq!(estimate_key(&tones(&[110.0, 220.0, 261.63, 329.63], 5.0), SAMPLE_RATE).as_deref(), Some("A minor"));
+    }
+
+    #[test]
+    fn test_silence_and_short_input_give_no_estimate() {
+        assert_eq!(analyze_samples(&vec![0.0; SAMPLE_RATE as usize * 5], SAMPLE_RATE), TrackFeatures::default());
+        assert_eq!(analyze_samples(&[0.5; 100], SAMPLE_RATE), TrackFeatures::default());
+    }
+
+    #[test]
+    fn test_analyze_missing_file() {
+        assert!(analyze_file(Path::new("/nonexistent/track.wav")).is_err());
+    }
+}
@@ -0,0 +1,279 @@
+//! Broadcast metadata in WAV and AIFF files: the BWF `bext` chunk and `iXML`, where field
+//! recorders store the description, originator, project, scene and take. Neither ID3 nor
+//! symphonia reads these chunks, so they are parsed here and stored as `custom_fields`.
+//!
+//! WAV (RIFF/RF64) sizes are little-endian and AIFF (FORM) sizes big-endian; both pad
+//! odd-sized chunks to an even length. RF64 files declare the `data` size as 0xFFFFFFFF and
+//! keep the real one in the `ds64` chunk before it. Declared sizes are clamped to the file
+//! length, so a truncated or mis-sized chunk yields whatever part of it is present.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::Path;
+
+pub const BEXT_DESCRIPTION: &str = "bext_description";
+pub const BEXT_ORIGINATOR: &str = "bext_originator";
+pub const BEXT_ORIGINATION_DATE: &str = "bext_origination_date";
+pub const IXML_PROJECT: &str = "ixml_project";
+pub const IXML_SCENE: &str = "ixml_scene";
+pub const IXML_TAKE: &str = "ixml_take";
+
+/// Most of a chunk body that is read; bext is 602 bytes plus coding history and iXML a
+/// few KB, so anything past this is not metadata we use.
+const MAX_CHUNK_LEN: u64 = 1024 * 1024;
+
+/// Offsets and lengths of the fixed-width bext strings (EBU Tech 3285).
+const BEXT_FIELDS: [(&str, usize, usize); 3] = [
+    (BEXT_DESCRIPTION, 0, 256),
+    (BEXT_ORIGINATOR, 256, 32),
+    (BEXT_ORIGINATION_DATE, 320, 10),
+];
+
+/// Chunk size an RF64 file declares for chunks whose real size is in `ds64`.
+const RF64_SIZE_IN_DS64: u32 = 0xFFFF_FFFF;
+
+/// iXML elements and the custom field each is stored under.
+const IXML_FIELDS: [(&str, &str); 3] = [("PROJECT", IXML_PROJECT), ("SCENE", IXML_SCENE), ("TAKE", IXML_TAKE)];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ByteOrder {
+    Little,
+    Big,
+}
+
+/// Byte order of the chunk sizes, or None when the header is neither WAV nor AIFF.
+fn container_byte_order(header: &[u8; 12]) -> Option<ByteOrder> {
+    match (&header[0..4], &header[8..12]) {
+        (b"RIFF" | b"RF64", b"WAVE") => Some(ByteOrder::Little),
+        (b"FORM", b"AIFF" | b"AIFC") => Some(ByteOrder::Big),
+        _ => None,
+    }
+}
+
+/// The bext and iXML fields of a WAV or AIFF file, keyed by the constants above. Empty
+/// for other files and for files without those chunks. Blocking: call from a blocking task.
+pub fn read_broadcast_fields(path: &Path) -> io::Result<HashMap<String, String>> {
+    let mut file = File::open(path)?;
+    let len = file.metadata()?.len();
+    read_fields(&mut file, len)
+}
+
+fn read_fields<R: Read + Seek>(reader: &mut R, len: u64) -> io::Result<HashMap<String, String>> {
+    let mut fields = HashMap::new();
+    if len < 12 {
+        return Ok(fields);
+    }
+    let mut header = [0u8; 12];
+    reader.read_exact(&mut header)?;
+    let Some(byte_order) = container_byte_order(&header) else { return Ok(fields) };
+
+    // The 64-bit `data` size from ds64, which precedes `data` in RF64 files
+    let mut ds64_data_size = None;
+    let mut pos = 12;
+    while pos + 8 <= len && is_chunk_id_at(reader, pos, len)? {
+        let mut chunk_header = [0u8; 8];
+        reader.seek(SeekFrom::Start(pos))?;
+        reader.read_exact(&mut chunk_header)?;
+        let size_bytes = [chunk_header[4], chunk_header[5], chunk_header[6], chunk_header[7]];
+        let declared = match byte_order {
+            ByteOrder::Little => u32::from_le_bytes(size_bytes),
+            ByteOrder::Big => u32::from_be_bytes(size_bytes),
+        };
+        let body_start = pos + 8;
+        let real_size = match (&chunk_header[0..4], ds64_data_size) {
+            (b"data", Some(data_size)) if declared == RF64_SIZE_IN_DS64 => data_size,
+            _ => u64::from(declared),
+        };
+        let size = real_size.min(len - body_start);
+        match &chunk_header[0..4] {
+            b"ds64" if byte_order == ByteOrder::Little => ds64_data_size = parse_ds64(&read_body(reader, size)?),
+            b"bext" => parse_bext(&read_body(reader, size)?, &mut fields),
+            b"iXML" => parse_ixml(&read_body(reader, size)?, &mut fields),
+            _ => {}
+        }
+        pos = body_start + size;
+        // Skip the pad byte, unless the writer left it out and the next chunk starts here
+        if size % 2 == 1 && !is_chunk_id_at(reader, pos, len)? {
+            pos += 1;
+        }
+    }
+    Ok(fields)
+}
+
+/// Whether four printable ASCII characters, as chunk ids are, start at `pos`.
+fn is_chunk_id_at<R: Read + Seek>(reader: &mut R, pos: u64, len: u64) -> io::Result<bool> {
+    if pos + 4 > len {
+        return Ok(false);
+    }
+    let mut id = [0u8; 4];
+    reader.seek(SeekFrom::Start(pos))?;
+    reader.read_exact(&mut id)?;
+    Ok(id.iter().all(|byte| (0x20..=0x7e).contains(byte)))
+}
+
+/// Reads a chunk body from the current position, at most `MAX_CHUNK_LEN` bytes of it.
+fn read_body<R: Read>(reader: &mut R, size: u64) -> io::Result<Vec<u8>> {
+    let mut body = vec![0u8; size.min(MAX_CHUNK_LEN) as usize];
+    reader.read_exact(&mut body)?;
+    Ok(body)
+}
+
+/// The NUL-padded string at `start..start + width`, cut short if the body is.
+fn fixed_width_str(body: &[u8], start: usize, width: usize) -> Option<String> {
+    let field = body.get(start..(start + width).min(body.len()))?;
+    let end = field.iter().position(|&byte| byte == 0).unwrap_or(field.len());
+    let value = String::from_utf8_lossy(&field[..end]).trim().to_string();
+    Some(value).filter(|value| !value.is_empty())
+}
+
+/// The data size from a ds64 body: the 64-bit RIFF size comes first, then the data size.
+fn parse_ds64(body: &[u8]) -> Option<u64> {
+    let bytes = body.get(8..16)?;
+    Some(u64::from_le_bytes(bytes.try_into().ok()?))
+}
+
+fn parse_bext(body: &[u8], fields: &mut HashMap<String, String>) {
+    for (key, start, width) in BEXT_FIELDS {
+        if let Some(value) = fixed_width_str(body, start, width) {
+            fields.insert(key.to_string(), value);
+        }
+    }
+}
+
+fn parse_ixml(body: &[u8], fields: &mut HashMap<String, String>) {
+    let xml = String::from_utf8_lossy(body);
+    for (element, key) in IXML_FIELDS {
+        if let Some(value) = element_text(&xml, element) {
+            fields.insert(key.to_string(), value);
+        }
+    }
+}
+
+/// Text of the first `<name>` element. iXML elements are flat text without attributes,
+/// so a full XML parser isn't needed.
+fn element_text(xml: &str, name: &str) -> Option<String> {
+    let open = format!("<{}>", name);
+    let start = xml.find(&open)? + open.len();
+    let end = start + xml[start..].find(&format!("</{}>", name))?;
+    let value = xml[start..end].trim()
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&");
+    Some(value).filter(|value| !value.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chunk(id: &[u8; 4], body: &[u8], byte_order: ByteOrder, pad: bool) -> Vec<u8> {
+        let size = body.len() as u32;
+        let mut out = id.to_vec();
+        out.extend(match byte_order {
+            ByteOrder::Little => size.to_le_bytes(),
+            ByteOrder::Big => size.to_be_bytes(),
+        });
+        out.extend(body);
+        if pad && body.len() % 2 == 1 {
+            out.push(0);
+        }
+        out
+    }
+
+    fn container(magic: &[u8; 4], form: &[u8; 4], chunks: &[Vec<u8>], byte_order: ByteOrder) -> Vec<u8> {
+        let body: Vec<u8> = form.iter().copied().chain(chunks.concat()).collect();
+        chunk(magic, &body, byte_order, false)
+    }
+
+    fn bext_body(description: &str, originator: &str, date: &str) -> Vec<u8> {
+        let mut body = vec![0u8; 602];
+        body[..description.len()].copy_from_slice(description.as_bytes());
+        body[256..256 + originator.len()].copy_from_slice(originator.as_bytes());
+        body[320..320 + date.len()].copy_from_slice(date.as_bytes());
+        body
+    }
+
+    const IXML: &str = "<?xml version=\"1.0\"?><BWFXML><PROJECT>Coast &amp; Cliffs</PROJECT><SCENE>12A</SCENE><TAKE>3</TAKE><TAKE_TYPE>DEFAULT</TAKE_TYPE></BWFXML>";
+
+    fn read(bytes: &[u8]) -> HashMap<String, String> {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("take.wav");
+        std::fs::write(&path, bytes).unwrap();
+        read_broadcast_fields(&path).unwrap()
+    }
+
+    #[test]
+    fn test_reads_bext_and_ixml_from_wav_and_aiff() {
+        let le = ByteOrder::Little;
+        let wav = container(b"RIFF", b"WAVE", &[
+            chunk(b"fmt ", &[0u8; 16], le, true),
+            chunk(b"junk", b"odd", le, true),
+            chunk(b"bext", &bext_body("Surf at dawn", "Sound Devices 833", "2024-05-17"), le, true),
+            chunk(b"iXML", IXML.as_bytes(), le, true),
+        ], le);
+        let fields = read(&wav);
+        assert_eq!(fields[BEXT_DESCRIPTION], "Surf at dawn");
+        assert_eq!(fields[BEXT_ORIGINATOR], "Sound Devices 833");
+        assert_eq!(fields[BEXT_ORIGINATION_DATE], "2024-05-17");
+        assert_eq!(fields[IXML_PROJECT], "Coast & Cliffs");
+        assert_eq!(fields[IXML_SCENE], "12A");
+        assert_eq!(fields[IXML_TAKE], "3");
+
+        let be = ByteOrder::Big;
+        let aiff = container(b"FORM", b"AIFF", &[
+            chunk(b"COMM", &[0u8; 18], be, true),
+            chunk(b"iXML", b"<BWFXML><SCENE>7</SCENE></BWFXML>", be, true),
+            chunk(b"bext", &bext_body("Gulls", "", ""), be, true),
+        ], be);
+        let fields = read(&aiff);
+        assert_eq!(fields.len(), 2);
+        assert_eq!(fields[IXML_SCENE], "7");
+        assert_eq!(fields[BEXT_DESCRIPTION], "Gulls");
+
+        assert!(read(b"ID3\x04\x00\x00\x00\x00\x00\x00 not a wav").is_empty());
+    }
+
+    #[test]
+    fn test_tolerates_malformed_chunks() {
+        let le = ByteOrder::Little;
+        // A writer that left out the pad byte after an odd-sized chunk
+        let unpadded = container(b"RIFF", b"WAVE", &[
+            chunk(b"junk", b"odd", le, false),
+            chunk(b"bext", &bext_body("Wind", "", ""), le, true),
+        ], le);
+        assert_eq!(read(&unpadded)[BEXT_DESCRIPTION], "Wind");
+
+        // A last chunk declaring more bytes than the file has, and a truncated bext
+        let mut truncated = container(b"RIFF", b"WAVE", &[chunk(b"iXML", IXML.as_bytes(), le, true)], le);
+        truncated[16..20].copy_from_slice(&100_000u32.to_le_bytes());
+        assert_eq!(read(&truncated)[IXML_TAKE], "3");
+        let short_bext = container(b"RIFF", b"WAVE", &[chunk(b"bext", b"Rain\0\0", le, true)], le);
+        assert_eq!(read(&short_bext).get(BEXT_DESCRIPTION).map(String::as_str), Some("Rain"));
+    }
+
+    #[test]
+    fn test_reads_chunks_after_rf64_data() {
+        let le = ByteOrder::Little;
+        let audio = [0u8; 64];
+        let mut ds64 = Vec::new();
+        ds64.extend(0u64.to_le_bytes());
+        ds64.extend((audio.len() as u64).to_le_bytes());
+        ds64.extend(16u64.to_le_bytes());
+        ds64.extend(0u32.to_le_bytes());
+        let mut data = chunk(b"data", &audio, le, true);
+        data[4..8].copy_from_slice(&RF64_SIZE_IN_DS64.to_le_bytes());
+        let rf64 = container(b"RF64", b"WAVE", &[
+            chunk(b"ds64", &ds64, le, true),
+            chunk(b"fmt ", &[0u8; 16], le, true),
+            data,
+            chunk(b"bext", &bext_body("Thunder", "", ""), le, true),
+            chunk(b"iXML", IXML.as_bytes(), le, true),
+        ], le);
+        let fields = read(&rf64);
+        assert_eq!(fields[BEXT_DESCRIPTION], "Thunder");
+        assert_eq!(fields[IXML_SCENE], "12A");
+    }
+}
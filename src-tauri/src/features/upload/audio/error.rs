@@ -33,6 +33,12 @@ pub enum TranscodingError {
     #[error("Failed to read FFmpeg stderr: {source_message}")]
     StderrReadFailed { source_message: String },
 
+    #[error("Invalid transcoding options: {0}")]
+    InvalidOptions(String),
+
+    #[error("FFmpeg did not finish within {timeout_secs}s and was killed")]
+    TimedOut { timeout_secs: u64 },
+
     // Store IO error message as String for serialization
     #[error("An unexpected I/O error occurred: {source_message}")]
     IoError { source_message: String },
@@ -0,0 +1,98 @@
+//! Integrated loudness (EBU R128) measured with ffmpeg's `ebur128` filter, stored on
+//! tracks with the ReplayGain 2.0 gain derived from it so players can normalize at
+//! playback time. Nothing is re-encoded.
+
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use std::process::Command;
+use std::time::Duration;
+
+use super::error::TranscodingError;
+use super::transcode::run_ffmpeg;
+
+/// ReplayGain 2.0 reference level; the gain brings a track to this loudness.
+pub const REPLAYGAIN_REFERENCE_LUFS: f64 = -18.0;
+
+/// `ebur128` reports this for audio that never rises above the absolute gate, i.e. silence.
+const SILENCE_LUFS: f64 = -70.0;
+
+/// Measured loudness and the suggested gain, as stored on the track document.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Loudness {
+    pub loudness_lufs: f64,
+    pub replaygain_db: f64,
+}
+
+impl Loudness {
+    fn from_integrated(loudness_lufs: f64) -> Self {
+        let replaygain_db = ((REPLAYGAIN_REFERENCE_LUFS - loudness_lufs) * 100.0).round() / 100.0;
+        Self { loudness_lufs, replaygain_db }
+    }
+}
+
+/// The integrated loudness from the summary `ebur128` prints to stderr when it finishes:
+///
+/// ```text
+///   Integrated loudness:
+///     I:         -14.3 LUFS
+/// ```
+fn integrated_loudness(stderr: &str) -> Option<f64> {
+    let summary = &stderr[stderr.rfind("Integrated loudness:")?..];
+    let line = summary.lines().find(|line| line.trim_start().starts_with("I:"))?;
+    line.trim_start().strip_prefix("I:")?.trim().strip_suffix("LUFS")?.trim().parse().ok()
+}
+
+/// Decodes the whole file through `ebur128`. `Ok(None)` for silent files and files ffmpeg
+/// found no audio in. Blocking: call from a blocking task.
+pub fn measure_loudness(input_path: &Path, timeout: Option<Duration>) -> Result<Option<Loudness>, TranscodingError> {
+    if !input_path.exists() {
+        return Err(TranscodingError::InputFileNotFound(input_path.to_path_buf()));
+    }
+    let mut command = Command::new("ffmpeg");
+    command
+        .arg("-nostats")
+        .arg("-i")
+        .arg(input_path)
+        .arg("-vn")
+        .arg("-af")
+        .arg("ebur128=framelog=verbose") // Per-frame readings only at -v verbose, so stderr holds just the summary
+        .arg("-f")
+        .arg("null")
+        .arg("-");
+
+    let stderr_output = run_ffmpeg(command, timeout)?;
+    Ok(integrated_loudness(&stderr_output)
+        .filter(|lufs| *lufs > SILENCE_LUFS)
+        .map(Loudness::from_integrated))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SUMMARY: &str = "\
+Input #0, wav, from 'take.wav':
+  Duration: 00:03:12.00, bitrate: 1411 kb/s
+[Parsed_ebur128_0 @ 0x600003b1c000] Summary:
+
+  Integrated loudness:
+    I:         -14.3 LUFS
+    Threshold: -24.6 LUFS
+
+  Loudness range:
+    LRA:         6.0 LU
+    Threshold: -34.5 LUFS
+    LRA low:   -19.4 LUFS
+    LRA high:  -13.4 LUFS
+";
+
+    #[test]
+    fn test_integrated_loudness_from_summary() {
+        assert_eq!(integrated_loudness(SUMMARY), Some(-14.3));
+        assert_eq!(integrated_loudness("Input #0, wav, from 'empty.wav':"), None);
+
+        let loudness = Loudness::from_integrated(-14.3);
+        assert_eq!(loudness, Loudness { loudness_lufs: -14.3, replaygain_db: -3.7 });
+        assert_eq!(Loudness::from_integrated(-23.0).replaygain_db, 5.0);
+    }
+}
@@ -1,8 +1,9 @@
 use crate::features::upload::UploadItemMetadata; // Updated path
+use super::broadcast::read_broadcast_fields;
 use std::path::Path;
 use std::fs::File;
 // Removed unused Read import
-// Removed unused HashMap import
+use std::collections::HashMap;
 use serde::{Serialize, Deserialize}; // Keep for UploadItemMetadata if it derives Serialize/Deserialize
 use log::{info, error, warn};
 use id3::{Tag, TagLike};
@@ -10,7 +11,7 @@ use symphonia::core::io::MediaSourceStream;
 use symphonia::core::formats::{FormatOptions, FormatReader};
 use symphonia::core::meta::MetadataOptions;
 use symphonia::core::probe::Hint;
-use symphonia::default::get_probe;
+use symphonia::default::{get_codecs, get_probe};
 // Removed unused Uuid import
 // Removed unused chrono imports
 
@@ -37,20 +38,39 @@ pub fn extract_metadata(filePath: String) -> Result<UploadItemMetadata, String>
         track_number: None,
         duration_sec: None,
         genre: None,
-        composer: None, // Composer extraction not implemented here yet
+        composer: None,
         year: None,
         comments: None,
+        album_artist: None,
+        compilation: false,
+        custom_fields: None,
+        composers: None,
+        isrc: None,
+        catalog_number: None,
+        album_id: None,
+        codec: None,
+        bitrate: None,
+        sample_rate: None,
+        channels: None,
+        art_path: None,
     };
 
-    // --- Extract Duration using Symphonia ---
-    match extract_duration_symphonia(&filePath) {
-        Ok(duration) => {
-            metadata.duration_sec = Some(duration);
-            info!("Extracted duration (Symphonia): {}s for {}", duration, filePath);
+    // --- Extract Duration and Format using Symphonia ---
+    match probe_audio_properties(&filePath) {
+        Ok(properties) => {
+            info!("Extracted audio properties (Symphonia) for {}: {:?}", filePath, properties);
+            if properties.duration_sec.is_none() {
+                warn!("No duration found for {}", filePath);
+            }
+            metadata.duration_sec = properties.duration_sec;
+            metadata.codec = properties.codec;
+            metadata.bitrate = properties.bitrate;
+            metadata.sample_rate = properties.sample_rate;
+            metadata.channels = properties.channels;
         },
         Err(e) => {
-            warn!("Failed to extract duration using Symphonia for {}: {}", filePath, e);
-            // Continue without duration if extraction fails
+            warn!("Failed to probe {} using Symphonia: {}", filePath, e);
+            // Continue without duration and format details if probing fails
         }
     }
 
@@ -62,11 +82,28 @@ pub fn extract_metadata(filePath: String) -> Result<UploadItemMetadata, String>
             metadata.title = tag.title().map(String::from);
             metadata.artist = tag.artist().map(String::from);
             metadata.album = tag.album().map(String::from);
+            metadata.album_artist = tag.album_artist().map(String::from);
+            // iTunes compilation flag, "1" for compilations
+            metadata.compilation = tag.get("TCMP").and_then(|frame| frame.content().text()).is_some_and(|flag| flag.trim() == "1");
             metadata.track_number = tag.track();
             metadata.year = tag.year();
             metadata.genre = tag.genre().map(String::from);
             // Get the first comment if available
             metadata.comments = tag.comments().next().map(|c| c.text.clone());
+            // TCOM separates multiple composers with '/' (ID3v2.3) or NUL (ID3v2.4)
+            let composers: Vec<String> = tag.get("TCOM").and_then(|frame| frame.content().text())
+                .map(|text| text.split(['/', '\0']).map(str::trim).filter(|c| !c.is_empty()).map(String::from).collect())
+                .unwrap_or_default();
+            metadata.composer = composers.first().cloned();
+            metadata.composers = Some(composers).filter(|c| !c.is_empty());
+            metadata.isrc = tag.get("TSRC").and_then(|frame| frame.content().text())
+                .map(|isrc| isrc.trim().to_string())
+                .filter(|isrc| !isrc.is_empty());
+            // Picard and most taggers write the catalog number as TXXX:CATALOGNUMBER
+            metadata.catalog_number = tag.extended_texts()
+                .find(|text| text.description.eq_ignore_ascii_case("CATALOGNUMBER"))
+                .map(|text| text.value.trim().to_string())
+                .filter(|value| !value.is_empty());
 
             // Log missing fields
             if metadata.title.is_none() { warn!("ID3: Title missing for {}", filePath); }
@@ -87,6 +124,16 @@ pub fn extract_metadata(filePath: String) -> Result<UploadItemMetadata, String>
         }
     }
 
+    // --- Broadcast WAV/AIFF chunks (bext, iXML) ---
+    match read_broadcast_fields(path) {
+        Ok(fields) if !fields.is_empty() => {
+            info!("Read {} broadcast metadata fields for {}", fields.len(), filePath);
+            metadata.custom_fields.get_or_insert_with(HashMap::new).extend(fields);
+        }
+        Ok(_) => {}
+        Err(e) => warn!("Failed to read broadcast metadata chunks for {}: {}", filePath, e),
+    }
+
     // --- Fallback Title (if still None) ---
     if metadata.title.is_none() {
         metadata.title = Some("Unknown Title".to_string());
@@ -95,18 +142,29 @@ pub fn extract_metadata(filePath: String) -> Result<UploadItemMetadata, String>
 
     // Note: Symphonia can also extract metadata, potentially supporting more formats.
     // This could be added as a fallback or alternative if ID3 fails or for non-MP3 files.
-    // For now, we rely primarily on ID3 and Symphonia for duration.
+    // For now, we rely primarily on ID3, and on Symphonia for duration and format details.
 
     info!("Finished extracting metadata for {}: {:?}", filePath, metadata);
     Ok(metadata)
 }
 
-fn extract_duration_symphonia(filePath: &str) -> Result<f64, String> {
+/// Technical details of the default track, read from its codec parameters.
+#[derive(Debug, Default, PartialEq)]
+struct AudioProperties {
+    duration_sec: Option<f64>,
+    codec: Option<String>,
+    bitrate: Option<u32>,
+    sample_rate: Option<u32>,
+    channels: Option<u16>,
+}
+
+fn probe_audio_properties(filePath: &str) -> Result<AudioProperties, String> {
     // Open the media file
     let file = match File::open(filePath) {
         Ok(file) => file,
         Err(e) => return Err(format!("Failed to open file: {}", e)),
     };
+    let file_size = file.metadata().ok().map(|m| m.len());
     
     // Create a MediaSourceStream
     let source = MediaSourceStream::new(Box::new(file), Default::default());
@@ -143,21 +201,52 @@ fn extract_duration_symphonia(filePath: &str) -> Result<f64, String> {
         Some(track) => track,
         None => return Err("No default track found".to_string()),
     };
+    let params = &track.codec_params;
     
-    // Get the track timebase
-    let timebase = match track.codec_params.time_base {
-        Some(timebase) => timebase,
-        None => return Err("No timebase found".to_string()),
+    // Duration needs both the timebase and the frame count
+    let duration_sec = match (params.time_base, params.n_frames) {
+        (Some(timebase), Some(n_frames)) => Some(n_frames as f64 * timebase.numer as f64 / timebase.denom as f64),
+        _ => None,
     };
     
-    // Get the track duration
-    let duration = match track.codec_params.n_frames {
-        Some(n_frames) => {
-            let time = n_frames as f64 * timebase.numer as f64 / timebase.denom as f64;
-            time
-        },
-        None => return Err("No frames count found".to_string()),
+    let mut properties = AudioProperties {
+        duration_sec,
+        codec: get_codecs().get_codec(params.codec).map(|codec| codec.short_name.to_string()),
+        bitrate: None,
+        sample_rate: params.sample_rate,
+        channels: params.channels.map(|channels| channels.count() as u16),
     };
-    
-    Ok(duration)
-} 
\ No newline at end of file
+    properties.bitrate = estimate_bitrate(&properties, params.bits_per_sample, file_size);
+    Ok(properties)
+}
+
+/// Exact for PCM; otherwise the average over the file, container overhead included.
+fn estimate_bitrate(properties: &AudioProperties, bits_per_sample: Option<u32>, file_size: Option<u64>) -> Option<u32> {
+    let pcm = properties.codec.as_deref().is_some_and(|codec| codec.starts_with("pcm"));
+    if let (true, Some(sample_rate), Some(channels), Some(bits)) = (pcm, properties.sample_rate, properties.channels, bits_per_sample) {
+        return u32::try_from(u64::from(sample_rate) * u64::from(channels) * u64::from(bits)).ok();
+    }
+    let duration = properties.duration_sec.filter(|duration| *duration > 0.0)?;
+    Some((file_size? as f64 * 8.0 / duration).round() as u32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_estimate_bitrate() {
+        let pcm = AudioProperties {
+            duration_sec: Some(10.0),
+            codec: Some("pcm_s16le".to_string()),
+            sample_rate: Some(44_100),
+            channels: Some(2),
+            ..Default::default()
+        };
+        assert_eq!(estimate_bitrate(&pcm, Some(16), Some(123)), Some(1_411_200));
+
+        let mp3 = AudioProperties { duration_sec: Some(32.0), codec: Some("mp3".to_string()), ..Default::default() };
+        assert_eq!(estimate_bitrate(&mp3, None, Some(1_000_000)), Some(250_000));
+        assert_eq!(estimate_bitrate(&AudioProperties::default(), None, Some(1_000_000)), None);
+    }
+}
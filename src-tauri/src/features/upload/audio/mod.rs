@@ -1,4 +1,9 @@
 // src-tauri/src/features/upload/audio/mod.rs
+pub mod analysis;
+pub mod broadcast;
 pub mod error;
+pub mod loudness;
 pub mod metadata;
-pub mod transcode;
\ No newline at end of file
+pub mod naming;
+pub mod transcode;
+pub mod waveform;
\ No newline at end of file
@@ -0,0 +1,162 @@
+//! File names for transcodes written to a local folder, rendered from a template such
+//! as `{artist} - {title}` or `{track_number} {title}` using the input's tags.
+//!
+//! Without a template, or when a placeholder it uses has no tag value, the output is
+//! named after the input's stem as before. Within a batch, a name that is already taken
+//! gets `-1`, `-2`, ... before its extension.
+
+use log::warn;
+use std::collections::HashSet;
+use std::path::Path;
+
+use super::metadata::extract_metadata;
+use crate::features::upload::keygen::with_collision_suffix;
+use crate::features::upload::UploadItemMetadata;
+
+/// Placeholders a naming template may use.
+pub const NAMING_PLACEHOLDERS: [&str; 6] = ["{title}", "{artist}", "{album}", "{track_number}", "{year}", "{stem}"];
+
+/// Cap on the rendered name, extension excluded.
+const MAX_NAME_LEN: usize = 180;
+
+/// Checks that a template only uses known placeholders and can't leave the output folder.
+pub fn validate_naming_template(template: &str) -> Result<(), String> {
+    if template.trim().is_empty() {
+        return Err("Naming template must not be empty".to_string());
+    }
+    if template.contains('/') || template.contains('\\') || template.contains("..") {
+        return Err(format!("Naming template must not contain path separators or '..': {}", template));
+    }
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        let end = rest[start..].find('}').ok_or_else(|| format!("Unclosed placeholder in naming template: {}", template))?;
+        let placeholder = &rest[start..start + end + 1];
+        if !NAMING_PLACEHOLDERS.contains(&placeholder) {
+            return Err(format!("Unknown placeholder {} in naming template (expected one of {})", placeholder, NAMING_PLACEHOLDERS.join(", ")));
+        }
+        rest = &rest[start + end + 1..];
+    }
+    Ok(())
+}
+
+/// Replaces characters that aren't allowed in file names on common filesystems, collapses
+/// whitespace and strips leading and trailing dots so the name can't be hidden or refer
+/// to a parent directory.
+fn sanitize_file_name(name: &str) -> String {
+    let replaced: String = name.chars()
+        .map(|c| if c.is_control() || matches!(c, '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|') { '_' } else { c })
+        .collect();
+    let collapsed = replaced.split_whitespace().collect::<Vec<_>>().join(" ");
+    let mut sanitized = collapsed.trim_matches(|c: char| c == '.' || c.is_whitespace()).to_string();
+    if sanitized.len() > MAX_NAME_LEN {
+        let mut end = MAX_NAME_LEN;
+        while !sanitized.is_char_boundary(end) {
+            end -= 1;
+        }
+        sanitized.truncate(end);
+        sanitized = sanitized.trim_end_matches(|c: char| c == '.' || c.is_whitespace()).to_string();
+    }
+    sanitized
+}
+
+/// Fills `template` from `metadata`. None when a placeholder it uses has no value.
+fn render_template(template: &str, metadata: &UploadItemMetadata, stem: &str) -> Option<String> {
+    let non_blank = |value: &Option<String>| value.as_deref().map(str::trim).filter(|v| !v.is_empty()).map(String::from);
+    let values = [
+        ("{title}", non_blank(&metadata.title)),
+        ("{artist}", non_blank(&metadata.artist)),
+        ("{album}", non_blank(&metadata.album)),
+        ("{track_number}", metadata.track_number.map(|n| format!("{:02}", n))),
+        ("{year}", metadata.year.map(|year| year.to_string())),
+        ("{stem}", Some(stem.to_string())),
+    ];
+    let mut rendered = template.to_string();
+    for (placeholder, value) in values {
+        if rendered.contains(placeholder) {
+            rendered = rendered.replace(placeholder, &value?);
+        }
+    }
+    Some(rendered)
+}
+
+/// Output file name for `metadata` with `extension`: the rendered template, or the input
+/// stem when there is no template, a tag is missing, or the name sanitizes to nothing.
+pub fn output_name(template: Option<&str>, metadata: &UploadItemMetadata, stem: &str, extension: &str) -> String {
+    let rendered = template
+        .and_then(|template| render_template(template, metadata, stem))
+        .map(|name| sanitize_file_name(&name))
+        .filter(|name| !name.is_empty());
+    let name = rendered.unwrap_or_else(|| {
+        let stem = sanitize_file_name(stem);
+        if stem.is_empty() { "output".to_string() } else { stem }
+    });
+    format!("{}.{}", name, extension)
+}
+
+/// Output file name for transcoding `input_path`, reading its tags when a template is given.
+/// Blocking: call from a blocking task.
+pub fn output_name_for_file(input_path: &Path, template: Option<&str>, extension: &str) -> String {
+    let stem = input_path.file_stem().map(|s| s.to_string_lossy().into_owned()).unwrap_or_default();
+    let metadata = match template {
+        Some(_) => extract_metadata(input_path.to_string_lossy().into_owned()).unwrap_or_else(|e| {
+            warn!("Naming {} after its file name; reading tags failed: {}", input_path.display(), e);
+            UploadItemMetadata::default()
+        }),
+        None => UploadItemMetadata::default(),
+    };
+    output_name(template, &metadata, &stem, extension)
+}
+
+/// Output file names for a batch, in input order. A name already used in the batch gets
+/// the first free `-N` suffix, compared without case as file systems may ignore it.
+/// Blocking: call from a blocking task.
+pub fn unique_output_names(input_paths: &[impl AsRef<Path>], template: Option<&str>, extension: &str) -> Vec<String> {
+    let mut taken = HashSet::new();
+    input_paths
+        .iter()
+        .map(|input_path| {
+            let name = output_name_for_file(input_path.as_ref(), template, extension);
+            let name = if taken.contains(&name.to_lowercase()) {
+                (1..)
+                    .map(|n| with_collision_suffix(&name, &n.to_string()))
+                    .find(|candidate| !taken.contains(&candidate.to_lowercase()))
+                    .unwrap_or(name)
+            } else {
+                name
+            };
+            taken.insert(name.to_lowercase());
+            name
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_output_name_renders_and_falls_back() {
+        let metadata = UploadItemMetadata {
+            title: Some("Night: Drive?".to_string()),
+            artist: Some("The Band".to_string()),
+            track_number: Some(3),
+            ..Default::default()
+        };
+        assert_eq!(output_name(Some("{track_number} {artist} - {title}"), &metadata, "01 night", "aac"), "03 The Band - Night_ Drive_.aac");
+        assert_eq!(output_name(Some("{album} - {title}"), &metadata, "01 night", "aac"), "01 night.aac");
+        assert_eq!(output_name(None, &metadata, "01 night", "aac"), "01 night.aac");
+        assert_eq!(output_name(Some("{title}"), &UploadItemMetadata { title: Some("..".to_string()), ..Default::default() }, "song", "aac"), "song.aac");
+
+        assert!(validate_naming_template("{artist} - {title}").is_ok());
+        assert!(validate_naming_template("../{title}").is_err());
+        assert!(validate_naming_template("{title}/{artist}").is_err());
+        assert!(validate_naming_template("{genre}").is_err());
+        assert!(validate_naming_template("  ").is_err());
+    }
+
+    #[test]
+    fn test_batch_names_get_a_suffix_when_taken() {
+        let inputs = ["a/song.wav", "b/song.flac", "c/Song.wav", "song-1.wav", "other.wav"];
+        assert_eq!(unique_output_names(&inputs, None, "aac"), vec!["song.aac", "song-1.aac", "Song-2.aac", "song-1-1.aac", "other.aac"]);
+    }
+}
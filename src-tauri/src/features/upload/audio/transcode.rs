@@ -1,10 +1,172 @@
 use std::path::{Path, PathBuf};
-use std::process::{Command, Stdio};
+use std::process::{Child, Command, ExitStatus, Stdio};
 use std::fs;
 use std::io::Read; // Import Read trait
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use log::info;
+use serde::{Deserialize, Serialize};
 
 use super::error::TranscodingError; // Use the specific error type
 
+/// How often we poll the ffmpeg child for completion when a timeout is set.
+const WAIT_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Set when the app is force-quit; running ffmpeg processes are killed at their next poll
+/// rather than left behind once the app exits.
+static ABORT_TRANSCODES: AtomicBool = AtomicBool::new(false);
+
+/// Kills every running ffmpeg process within one `WAIT_POLL_INTERVAL`, and any started later.
+pub fn abort_running_transcodes() {
+    ABORT_TRANSCODES.store(true, Ordering::SeqCst);
+}
+
+/// Output formats the upload pipeline can transcode to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TranscodeFormat {
+    /// 256kbps AAC in an MP4 container
+    Aac,
+    /// 320kbps MP3
+    Mp3,
+    /// Lossless FLAC
+    Flac,
+}
+
+impl TranscodeFormat {
+    /// Short lowercase name, as used in R2 key templates.
+    pub fn name(self) -> &'static str {
+        match self {
+            TranscodeFormat::Aac => "aac",
+            TranscodeFormat::Mp3 => "mp3",
+            TranscodeFormat::Flac => "flac",
+        }
+    }
+
+    /// File extension for the output file.
+    pub fn extension(self) -> &'static str {
+        match self {
+            TranscodeFormat::Aac => "m4a",
+            TranscodeFormat::Mp3 => "mp3",
+            TranscodeFormat::Flac => "flac",
+        }
+    }
+
+    /// MIME type of the output file, set on the R2 object and the track. AAC is written
+    /// in an MP4 container (`.m4a`), so it is `audio/mp4`; `audio/aac` would describe a
+    /// raw ADTS stream, which players reject for these files.
+    pub fn mime_type(self) -> &'static str {
+        match self {
+            TranscodeFormat::Aac => "audio/mp4",
+            TranscodeFormat::Mp3 => "audio/mpeg",
+            TranscodeFormat::Flac => "audio/flac",
+        }
+    }
+
+    fn codec_args(self) -> &'static [&'static str] {
+        match self {
+            TranscodeFormat::Aac => &["-acodec", "aac", "-b:a", "256k"],
+            TranscodeFormat::Mp3 => &["-acodec", "libmp3lame", "-b:a", "320k"],
+            TranscodeFormat::Flac => &["-acodec", "flac", "-compression_level", "8"],
+        }
+    }
+}
+
+/// Accepted range for `TranscodingOptions::sample_rate`, in Hz.
+pub const SAMPLE_RATE_RANGE: std::ops::RangeInclusive<u32> = 8_000..=192_000;
+/// Accepted range for `TranscodingOptions::channels` (mono up to 7.1).
+pub const CHANNELS_RANGE: std::ops::RangeInclusive<u8> = 1..=8;
+/// Level below which audio counts as silence when trimming, unless set.
+pub const DEFAULT_SILENCE_THRESHOLD_DB: i32 = -50;
+/// Accepted range for `TranscodingOptions::silence_threshold_db`.
+pub const SILENCE_THRESHOLD_RANGE: std::ops::RangeInclusive<i32> = -90..=-20;
+/// Silence shorter than this is kept, so a soft first note isn't clipped.
+const MIN_TRIMMED_SILENCE_SECS: f64 = 0.1;
+
+/// Output sample rate and channel count. Unset values leave the choice to ffmpeg,
+/// which keeps the input's rate and layout where the codec allows.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TranscodingOptions {
+    /// Resample to this rate (`-ar`), e.g. 44100
+    #[serde(default)]
+    pub sample_rate: Option<u32>,
+    /// Down- or upmix to this many channels (`-ac`), e.g. 2
+    #[serde(default)]
+    pub channels: Option<u8>,
+    /// Strip leading and trailing silence; silence inside the track is kept
+    #[serde(default)]
+    pub trim_silence: bool,
+    /// Level in dBFS below which audio counts as silence, -50 when unset
+    #[serde(default)]
+    pub silence_threshold_db: Option<i32>,
+}
+
+impl TranscodingOptions {
+    pub fn validate(&self) -> Result<(), TranscodingError> {
+        if let Some(rate) = self.sample_rate.filter(|rate| !SAMPLE_RATE_RANGE.contains(rate)) {
+            return Err(TranscodingError::InvalidOptions(format!(
+                "sample rate {} Hz is outside {}-{} Hz", rate, SAMPLE_RATE_RANGE.start(), SAMPLE_RATE_RANGE.end()
+            )));
+        }
+        if let Some(channels) = self.channels.filter(|channels| !CHANNELS_RANGE.contains(channels)) {
+            return Err(TranscodingError::InvalidOptions(format!(
+                "{} channels is outside {}-{}", channels, CHANNELS_RANGE.start(), CHANNELS_RANGE.end()
+            )));
+        }
+        if let Some(threshold) = self.silence_threshold_db.filter(|threshold| !SILENCE_THRESHOLD_RANGE.contains(threshold)) {
+            return Err(TranscodingError::InvalidOptions(format!(
+                "silence threshold {} dB is outside {} to {} dB", threshold, SILENCE_THRESHOLD_RANGE.start(), SILENCE_THRESHOLD_RANGE.end()
+            )));
+        }
+        Ok(())
+    }
+
+    /// `silenceremove` only trims reliably from the start, so the trailing silence is
+    /// removed by trimming the reversed audio and reversing it back.
+    fn silence_filter(&self) -> String {
+        let threshold = self.silence_threshold_db.unwrap_or(DEFAULT_SILENCE_THRESHOLD_DB);
+        let trim_start = format!(
+            "silenceremove=start_periods=1:start_threshold={}dB:start_silence={}",
+            threshold, MIN_TRIMMED_SILENCE_SECS
+        );
+        format!("{0},areverse,{0},areverse", trim_start)
+    }
+
+    fn ffmpeg_args(&self) -> Vec<String> {
+        let mut args = Vec::new();
+        if let Some(rate) = self.sample_rate {
+            args.extend(["-ar".to_string(), rate.to_string()]);
+        }
+        if let Some(channels) = self.channels {
+            args.extend(["-ac".to_string(), channels.to_string()]);
+        }
+        if self.trim_silence {
+            args.extend(["-af".to_string(), self.silence_filter()]);
+        }
+        args
+    }
+}
+
+/// Seconds in an ffmpeg `HH:MM:SS.ss` timestamp.
+fn parse_ffmpeg_time(timestamp: &str) -> Option<f64> {
+    let mut parts = timestamp.trim().splitn(3, ':');
+    let hours: f64 = parts.next()?.parse().ok()?;
+    let minutes: f64 = parts.next()?.parse().ok()?;
+    let seconds: f64 = parts.next()?.parse().ok()?;
+    Some(hours * 3600.0 + minutes * 60.0 + seconds)
+}
+
+/// Difference between the input duration ffmpeg reports and the length it wrote, taken
+/// from its stderr output. `None` if either is missing (e.g. streams without a duration).
+fn trimmed_secs(stderr: &str) -> Option<f64> {
+    let input = stderr.split("Duration: ").nth(1)?.split(',').next().and_then(parse_ffmpeg_time)?;
+    let output = stderr.rsplit("time=").next().filter(|_| stderr.contains("time="))?
+        .split_whitespace().next().and_then(parse_ffmpeg_time)?;
+    Some((input - output).max(0.0))
+}
+
 /// Transcodes an audio file to 256kbps AAC format using the ffmpeg CLI.
 ///
 /// # Arguments
@@ -17,7 +179,20 @@ use super::error::TranscodingError; // Use the specific error type
 /// * `Ok(())` if transcoding is successful.
 /// * `Err(TranscodingError)` if any error occurs during the process.
 pub fn transcode_to_aac(input_path: &Path, output_path: &Path) -> Result<(), TranscodingError> {
+    transcode_with_timeout(input_path, output_path, TranscodeFormat::Aac, &TranscodingOptions::default(), None)
+}
+
+/// Transcodes an audio file to `format`, resampling/remixing as `options` ask. Kills the
+/// ffmpeg process if it is still running after `timeout` and returns `TranscodingError::TimedOut`.
+pub fn transcode_with_timeout(
+    input_path: &Path,
+    output_path: &Path,
+    format: TranscodeFormat,
+    options: &TranscodingOptions,
+    timeout: Option<Duration>,
+) -> Result<(), TranscodingError> {
     // --- Input Validation ---
+    options.validate()?;
     if !input_path.exists() {
         return Err(TranscodingError::InputFileNotFound(input_path.to_path_buf()));
     }
@@ -37,12 +212,63 @@ pub fn transcode_to_aac(input_path: &Path, output_path: &Path) -> Result<(), Tra
         .arg("-i") // Input file flag
         .arg(input_path)
         .arg("-vn") // Disable video recording
-        .arg("-acodec") // Audio codec flag
-        .arg("aac") // Specify AAC codec
-        .arg("-b:a") // Audio bitrate flag
-        .arg("256k") // Specify 256kbps bitrate
+        .args(format.codec_args()) // Codec and bitrate/compression for the target format
+        .args(options.ffmpeg_args()) // Sample rate and channel count, if set
         .arg("-y") // Overwrite output file if it exists
-        .arg(output_path)
+        .arg(output_path);
+
+    let stderr_output = run_ffmpeg(command, timeout)?;
+    if options.trim_silence {
+        match trimmed_secs(&stderr_output) {
+            Some(trimmed) => info!("Trimmed {:.2}s of silence from {}", trimmed, input_path.display()),
+            None => info!("Trimmed silence from {} (amount not reported by ffmpeg)", input_path.display()),
+        }
+    }
+    Ok(())
+}
+
+/// Cuts `duration_secs` of audio starting at `start_secs` from `input_path` and
+/// encodes it to 256kbps AAC, for short preview clips.
+pub fn cut_preview_clip(
+    input_path: &Path,
+    output_path: &Path,
+    start_secs: f64,
+    duration_secs: f64,
+    timeout: Option<Duration>,
+) -> Result<(), TranscodingError> {
+    if !input_path.exists() {
+        return Err(TranscodingError::InputFileNotFound(input_path.to_path_buf()));
+    }
+    if let Some(parent_dir) = output_path.parent() {
+        if !parent_dir.exists() {
+            fs::create_dir_all(parent_dir)
+                .map_err(|e| TranscodingError::output_dir_creation_failed(parent_dir.to_path_buf(), e))?;
+        }
+    }
+
+    let mut command = Command::new("ffmpeg");
+    command
+        .arg("-ss") // Seek before the input so ffmpeg skips decoding the lead-in
+        .arg(format!("{:.3}", start_secs))
+        .arg("-i")
+        .arg(input_path)
+        .arg("-t") // Clip length
+        .arg(format!("{:.3}", duration_secs))
+        .arg("-vn")
+        .arg("-acodec")
+        .arg("aac")
+        .arg("-b:a")
+        .arg("256k")
+        .arg("-y")
+        .arg(output_path);
+
+    run_ffmpeg(command, timeout).map(|_| ())
+}
+
+/// Runs a prepared ffmpeg command, enforcing `timeout` if one is given. Returns the
+/// captured stderr, which is also included in the error if ffmpeg fails.
+pub(super) fn run_ffmpeg(mut command: Command, timeout: Option<Duration>) -> Result<String, TranscodingError> {
+    command
         .stdout(Stdio::null()) // Discard stdout
         .stderr(Stdio::piped()); // Capture stderr for error reporting
 
@@ -51,18 +277,24 @@ pub fn transcode_to_aac(input_path: &Path, output_path: &Path) -> Result<(), Tra
     let mut child = command.spawn().map_err(TranscodingError::process_start_failed)?;
 
     // --- Capture Stderr ---
-    let mut stderr_output = String::new();
-    if let Some(mut stderr) = child.stderr.take() {
-        // Read stderr into the string
-        // Use the helper function from error.rs
-        stderr.read_to_string(&mut stderr_output)
-              .map_err(TranscodingError::stderr_read_failed)?;
-    }
-
+    // Drain stderr on its own thread so a hung ffmpeg can't block us on the read
+    let stderr_reader = child.stderr.take().map(|mut stderr| {
+        thread::spawn(move || {
+            let mut output = String::new();
+            stderr.read_to_string(&mut output).map(|_| output)
+        })
+    });
 
     // --- Wait for Completion and Check Status ---
-    // The `?` here uses the `From<std::io::Error>` implementation in error.rs
-    let status = child.wait()?;
+    let status = wait_with_timeout(&mut child, timeout)?;
+
+    let stderr_output = match stderr_reader {
+        Some(handle) => handle
+            .join()
+            .map_err(|_| TranscodingError::IoError { source_message: "FFmpeg stderr reader thread panicked".to_string() })?
+            .map_err(TranscodingError::stderr_read_failed)?,
+        None => String::new(),
+    };
 
     if !status.success() {
         return Err(TranscodingError::ProcessExecutionFailed {
@@ -78,7 +310,32 @@ pub fn transcode_to_aac(input_path: &Path, output_path: &Path) -> Result<(), Tra
     // but this keeps dependencies minimal for now.
     // We could refine the ProcessStartFailed mapping to specifically check for NotFound.
 
-    Ok(())
+    Ok(stderr_output)
+}
+
+/// Waits for the child to exit, killing it once `timeout` has elapsed or when transcodes
+/// are aborted.
+fn wait_with_timeout(child: &mut Child, timeout: Option<Duration>) -> Result<ExitStatus, TranscodingError> {
+    let deadline = timeout.map(|timeout| Instant::now() + timeout);
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok(status);
+        }
+        // Kill and reap the process so it doesn't linger as a zombie
+        if ABORT_TRANSCODES.load(Ordering::SeqCst) {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(TranscodingError::IoError { source_message: "FFmpeg was stopped because the app is quitting".to_string() });
+        }
+        if let (Some(deadline), Some(timeout)) = (deadline, timeout) {
+            if Instant::now() >= deadline {
+                let _ = child.kill();
+                let _ = child.wait();
+                return Err(TranscodingError::TimedOut { timeout_secs: timeout.as_secs() });
+            }
+        }
+        thread::sleep(WAIT_POLL_INTERVAL);
+    }
 }
 
 // Basic test (requires ffmpeg in PATH and a dummy input file)
@@ -126,6 +383,48 @@ mod tests {
          assert!(nested_output_dir.is_dir());
      }
 
+     #[cfg(unix)]
+     #[test]
+     fn test_wait_with_timeout_kills_hung_process() {
+         let mut child = Command::new("sleep").arg("30").spawn().unwrap();
+         let started = Instant::now();
+
+         let result = wait_with_timeout(&mut child, Some(Duration::from_millis(200)));
+
+         assert!(matches!(result, Err(TranscodingError::TimedOut { .. })));
+         assert!(started.elapsed() < Duration::from_secs(5));
+         // The child has been reaped, so there is nothing left to wait on
+         assert!(child.try_wait().unwrap().is_some());
+     }
+
+    #[test]
+    fn test_transcoding_options_args_and_validation() {
+        assert!(TranscodingOptions::default().ffmpeg_args().is_empty());
+        let stereo_44k = TranscodingOptions { sample_rate: Some(44100), channels: Some(2), ..Default::default() };
+        assert!(stereo_44k.validate().is_ok());
+        assert_eq!(stereo_44k.ffmpeg_args(), vec!["-ar", "44100", "-ac", "2"]);
+
+        assert!(TranscodingOptions { sample_rate: Some(4000), channels: None, ..Default::default() }.validate().is_err());
+        assert!(TranscodingOptions { sample_rate: Some(384000), channels: None, ..Default::default() }.validate().is_err());
+        assert!(TranscodingOptions { sample_rate: None, channels: Some(0), ..Default::default() }.validate().is_err());
+        assert!(TranscodingOptions { sample_rate: None, channels: Some(9), ..Default::default() }.validate().is_err());
+    }
+
+    #[test]
+    fn test_trim_silence_filter_and_report() {
+        let trim = TranscodingOptions { trim_silence: true, silence_threshold_db: Some(-60), ..Default::default() };
+        assert!(trim.validate().is_ok());
+        let trim_start = "silenceremove=start_periods=1:start_threshold=-60dB:start_silence=0.1";
+        assert_eq!(trim.ffmpeg_args(), vec!["-af".to_string(), format!("{0},areverse,{0},areverse", trim_start)]);
+        assert!(TranscodingOptions { silence_threshold_db: Some(-5), ..Default::default() }.validate().is_err());
+
+        let stderr = "Input #0, wav, from 'in.wav':\n  Duration: 00:03:21.50, bitrate: 1411 kb/s\n\
+            size=     512kB time=00:01:00.00 bitrate= 69.9kbits/s speed=50x\r\
+            size=    6144kB time=00:03:18.25 bitrate=253.9kbits/s speed=52x\n";
+        assert!((trimmed_secs(stderr).unwrap() - 3.25).abs() < 1e-9);
+        assert_eq!(trimmed_secs("Duration: N/A, bitrate: N/A"), None);
+    }
+
     // Add more tests:
     // - Test actual transcoding with a small, valid sample file (if feasible in test env)
     // - Test ffmpeg not found (might require manipulating PATH or mocking Command)
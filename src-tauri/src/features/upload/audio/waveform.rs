@@ -0,0 +1,160 @@
+use std::fs::File;
+use std::path::Path;
+
+use symphonia::core::audio::SampleBuffer;
+use symphonia::core::codecs::DecoderOptions;
+use symphonia::core::errors::Error as SymphoniaError;
+use symphonia::core::formats::FormatOptions;
+use symphonia::core::io::MediaSourceStream;
+use symphonia::core::meta::MetadataOptions;
+use symphonia::core::probe::Hint;
+use symphonia::default::{get_codecs, get_probe};
+
+/// Number of peaks stored per track for waveform display.
+pub const DEFAULT_WAVEFORM_PEAKS: usize = 1000;
+
+/// Frames folded into a single amplitude while decoding, to keep memory bounded
+/// for long tracks.
+const FRAMES_PER_BLOCK: usize = 256;
+
+/// Decodes an audio file and returns `num_peaks` peak amplitudes in `0.0..=1.0`,
+/// evenly spaced across the track.
+pub fn compute_peaks(path: &Path, num_peaks: usize) -> Result<Vec<f32>, String> {
+    let file = File::open(path).map_err(|e| format!("Failed to open file: {}", e))?;
+    let source = MediaSourceStream::new(Box::new(file), Default::default());
+
+    let mut hint = Hint::new();
+    if let Some(ext_str) = path.extension().and_then(|e| e.to_str()) {
+        hint.with_extension(ext_str);
+    }
+
+    let probe_result = get_probe()
+        .format(&hint, source, &FormatOptions::default(), &MetadataOptions::default())
+        .map_err(|e| format!("Failed to probe format: {}", e))?;
+    let mut format = probe_result.format;
+
+    let track = format.default_track().ok_or_else(|| "No default track found".to_string())?;
+    let track_id = track.id;
+    let mut decoder = get_codecs()
+        .make(&track.codec_params, &DecoderOptions::default())
+        .map_err(|e| format!("Unsupported codec: {}", e))?;
+
+    // --- Decode, keeping the loudest sample of each block ---
+    let mut block_maxima: Vec<f32> = Vec::new();
+    let mut block_peak = 0.0f32;
+    let mut frames_in_block = 0usize;
+    let mut sample_buf: Option<SampleBuffer<f32>> = None;
+
+    loop {
+        let packet = match format.next_packet() {
+            Ok(packet) => packet,
+            // End of stream is reported as an unexpected EOF
+            Err(SymphoniaError::IoError(e)) if e.kind() == std::io::ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(format!("Failed to read packet: {}", e)),
+        };
+        if packet.track_id() != track_id {
+            continue;
+        }
+
+        let decoded = match decoder.decode(&packet) {
+            Ok(decoded) => decoded,
+            // Skip corrupt packets rather than failing the whole track
+            Err(SymphoniaError::DecodeError(_)) => continue,
+            Err(e) => return Err(format!("Failed to decode audio: {}", e)),
+        };
+
+        let buf = sample_buf.get_or_insert_with(|| SampleBuffer::new(decoded.capacity() as u64, *decoded.spec()));
+        let channels = decoded.spec().channels.count().max(1);
+        buf.copy_interleaved_ref(decoded);
+
+        for frame in buf.samples().chunks(channels) {
+            let amplitude = frame.iter().fold(0.0f32, |peak, sample| peak.max(sample.abs()));
+            block_peak = block_peak.max(amplitude);
+            frames_in_block += 1;
+            if frames_in_block == FRAMES_PER_BLOCK {
+                block_maxima.push(block_peak);
+                block_peak = 0.0;
+                frames_in_block = 0;
+            }
+        }
+    }
+    if frames_in_block > 0 {
+        block_maxima.push(block_peak);
+    }
+
+    if block_maxima.is_empty() {
+        return Err("No audio samples decoded".to_string());
+    }
+    Ok(bucket_peaks(&block_maxima, num_peaks))
+}
+
+/// Reduces `amplitudes` to `num_peaks` values by taking the maximum of each
+/// evenly sized bucket. Shorter inputs are returned unchanged.
+fn bucket_peaks(amplitudes: &[f32], num_peaks: usize) -> Vec<f32> {
+    if num_peaks == 0 || amplitudes.len() <= num_peaks {
+        return amplitudes.iter().map(|a| a.min(1.0)).collect();
+    }
+    (0..num_peaks)
+        .map(|i| {
+            let start = i * amplitudes.len() / num_peaks;
+            let end = ((i + 1) * amplitudes.len() / num_peaks).max(start + 1);
+            amplitudes[start..end].iter().fold(0.0f32, |peak, a| peak.max(*a)).min(1.0)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use tempfile::tempdir;
+
+    /// Writes a mono 16-bit PCM WAV file containing `samples`.
+    fn write_wav(path: &Path, sample_rate: u32, samples: &[i16]) {
+        let data_len = (samples.len() * 2) as u32;
+        let mut file = File::create(path).unwrap();
+        file.write_all(b"RIFF").unwrap();
+        file.write_all(&(36 + data_len).to_le_bytes()).unwrap();
+        file.write_all(b"WAVEfmt ").unwrap();
+        file.write_all(&16u32.to_le_bytes()).unwrap();
+        file.write_all(&1u16.to_le_bytes()).unwrap(); // PCM
+        file.write_all(&1u16.to_le_bytes()).unwrap(); // mono
+        file.write_all(&sample_rate.to_le_bytes()).unwrap();
+        file.write_all(&(sample_rate * 2).to_le_bytes()).unwrap();
+        file.write_all(&2u16.to_le_bytes()).unwrap();
+        file.write_all(&16u16.to_le_bytes()).unwrap();
+        file.write_all(b"data").unwrap();
+        file.write_all(&data_len.to_le_bytes()).unwrap();
+        for sample in samples {
+            file.write_all(&sample.to_le_bytes()).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_bucket_peaks_takes_bucket_maxima() {
+        let peaks = bucket_peaks(&[0.1, 0.5, 0.2, 0.9, 0.3, 0.4], 3);
+        assert_eq!(peaks, vec![0.5, 0.9, 0.4]);
+        assert_eq!(bucket_peaks(&[0.2, 0.3], 10), vec![0.2, 0.3]);
+    }
+
+    #[test]
+    fn test_compute_peaks_from_wav() {
+        let temp_dir = tempdir().unwrap();
+        let path = temp_dir.path().join("tone.wav");
+        // One second of silence followed by one second at half volume
+        let mut samples = vec![0i16; 8000];
+        samples.extend(std::iter::repeat(i16::MAX / 2).take(8000));
+        write_wav(&path, 8000, &samples);
+
+        let peaks = compute_peaks(&path, 10).unwrap();
+
+        assert_eq!(peaks.len(), 10);
+        assert!(peaks[0] < 0.01);
+        assert!((peaks[9] - 0.5).abs() < 0.01);
+    }
+
+    #[test]
+    fn test_compute_peaks_missing_file() {
+        assert!(compute_peaks(Path::new("/nonexistent/track.wav"), 10).is_err());
+    }
+}
@@ -0,0 +1,54 @@
+//! The opt-in setting to encrypt originals before they are uploaded to R2, for labels
+//! that require masters at rest in third-party storage to be encrypted with their own key.
+//!
+//! The setting is part of the app settings, under `encryption` (see `core::settings`);
+//! the key itself lives in the credential store (see
+//! `credentials::get_or_create_originals_key`).
+
+use log::info;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::sync::Arc;
+use tauri::{command, AppHandle, State, Wry};
+
+use super::UploadState;
+use crate::core::encryption::key_id;
+use crate::core::settings::patch_settings;
+use crate::features::credentials::{get_or_create_originals_key, load_originals_key};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct EncryptionSettings {
+    pub encrypt_originals: bool,
+}
+
+/// The setting plus the id of the stored key, for display in Settings.
+#[derive(Debug, Clone, Serialize)]
+pub struct EncryptionStatus {
+    pub encrypt_originals: bool,
+    /// `None` until a key has been created
+    pub key_id: Option<String>,
+}
+
+// --- Tauri Commands ---
+
+#[command]
+pub async fn get_encryption_settings(upload_state: State<'_, Arc<UploadState>>) -> Result<EncryptionStatus, String> {
+    let settings = *upload_state.encryption.lock().await;
+    let key = load_originals_key().map_err(|e| e.to_string())?;
+    Ok(EncryptionStatus { encrypt_originals: settings.encrypt_originals, key_id: key.as_ref().map(key_id) })
+}
+
+/// Saves the setting to the settings; it applies from the next upload run. Turning
+/// encryption on creates the key if there isn't one yet.
+#[command]
+pub async fn set_encryption_settings(settings: EncryptionSettings, app_handle: AppHandle<Wry>) -> Result<EncryptionStatus, String> {
+    let key = if settings.encrypt_originals {
+        Some(get_or_create_originals_key().map_err(|e| format!("Failed to prepare the encryption key: {}", e))?)
+    } else {
+        load_originals_key().map_err(|e| e.to_string())?
+    };
+    patch_settings(&app_handle, &json!({ "encryption": settings })).await.map_err(|e| e.to_string())?;
+    info!("Encryption of originals {}.", if settings.encrypt_originals { "enabled" } else { "disabled" });
+    Ok(EncryptionStatus { encrypt_originals: settings.encrypt_originals, key_id: key.as_ref().map(key_id) })
+}
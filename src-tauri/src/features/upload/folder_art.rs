@@ -0,0 +1,145 @@
+//! Album artwork from cover images delivered next to the audio files (`cover.jpg`,
+//! `folder.png`, ...), for files whose tags carry no embedded art. Only the file's own
+//! folder is searched. Controlled by the `upload.folder_art` setting.
+
+use id3::{Tag, TagLike};
+use log::{debug, info};
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use symphonia::core::formats::FormatOptions;
+use symphonia::core::io::MediaSourceStream;
+use symphonia::core::meta::MetadataOptions;
+use symphonia::core::probe::Hint;
+use symphonia::default::get_probe;
+
+use crate::features::catalog::artwork::MAX_UPLOAD_BYTES;
+
+/// File names (without extension) taken as album artwork, ignoring case.
+const ART_FILE_STEMS: [&str; 4] = ["cover", "folder", "front", "album"];
+
+/// Extensions `upload_album_artwork` accepts.
+const ART_EXTENSIONS: [&str; 4] = ["jpg", "jpeg", "png", "webp"];
+
+fn is_art_file_name(path: &Path) -> bool {
+    let lowercase = |part: Option<&std::ffi::OsStr>| part.and_then(|p| p.to_str()).map(str::to_lowercase);
+    matches!(
+        (lowercase(path.file_stem()), lowercase(path.extension())),
+        (Some(stem), Some(extension)) if ART_FILE_STEMS.contains(&stem.as_str()) && ART_EXTENSIONS.contains(&extension.as_str())
+    )
+}
+
+/// Whether the file's tags carry a picture: ID3 `APIC` frames, or cover art Symphonia
+/// reads from other containers (FLAC, MP4).
+pub fn has_embedded_art(audio_path: &Path) -> bool {
+    if Tag::read_from_path(audio_path).is_ok_and(|tag| tag.pictures().next().is_some()) {
+        return true;
+    }
+    let Ok(file) = File::open(audio_path) else { return false };
+    let mut hint = Hint::new();
+    if let Some(extension) = audio_path.extension().and_then(|e| e.to_str()) {
+        hint.with_extension(extension);
+    }
+    let source = MediaSourceStream::new(Box::new(file), Default::default());
+    let Ok(mut probed) = get_probe().format(&hint, source, &FormatOptions::default(), &MetadataOptions::default()) else {
+        return false;
+    };
+    let in_probe = probed.metadata.get().is_some_and(|metadata| metadata.current().is_some_and(|rev| !rev.visuals().is_empty()));
+    in_probe || probed.format.metadata().current().is_some_and(|rev| !rev.visuals().is_empty())
+}
+
+/// The largest cover image, by pixel count, in the audio file's folder. Images over the
+/// artwork size limit and files that can't be read as images are skipped.
+pub fn find_folder_art(audio_path: &Path) -> Option<PathBuf> {
+    let dir = audio_path.parent()?;
+    let entries = fs::read_dir(dir).ok()?;
+    let mut candidates: Vec<(u64, PathBuf)> = entries
+        .filter_map(Result::ok)
+        .map(|entry| entry.path())
+        // The chosen path is stored as a string, so non-UTF-8 names can't be used
+        .filter(|path| path.to_str().is_some() && is_art_file_name(path))
+        .filter(|path| fs::metadata(path).is_ok_and(|meta| meta.is_file() && meta.len() <= MAX_UPLOAD_BYTES))
+        .filter_map(|path| match image::image_dimensions(&path) {
+            Ok((width, height)) => Some((u64::from(width) * u64::from(height), path)),
+            Err(e) => {
+                debug!("Skipping unreadable cover image {}: {}", path.display(), e);
+                None
+            }
+        })
+        .collect();
+    // Largest first; the name breaks ties so the pick doesn't depend on directory order
+    candidates.sort_by(|(a_pixels, a_path), (b_pixels, b_path)| b_pixels.cmp(a_pixels).then_with(|| a_path.cmp(b_path)));
+    candidates.into_iter().next().map(|(_, path)| path)
+}
+
+/// Cover image to use for the file's album, or `None` if the file has embedded art.
+/// Blocking: reads the file and its folder.
+pub fn folder_art_for(audio_path: &Path) -> Option<PathBuf> {
+    if has_embedded_art(audio_path) {
+        return None;
+    }
+    let art = find_folder_art(audio_path)?;
+    info!("Using {} as album artwork for {}", art.display(), audio_path.display());
+    Some(art)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use id3::frame::{Picture, PictureType};
+    use id3::Version;
+    use image::{DynamicImage, ImageFormat};
+    use tempfile::tempdir;
+
+    fn write_image(path: &Path, width: u32, height: u32, format: ImageFormat) {
+        DynamicImage::new_rgb8(width, height).save_with_format(path, format).unwrap();
+    }
+
+    #[test]
+    fn test_art_file_names() {
+        assert!(is_art_file_name(Path::new("/music/Cover.JPG")));
+        assert!(is_art_file_name(Path::new("folder.webp")));
+        assert!(!is_art_file_name(Path::new("cover.gif")));
+        assert!(!is_art_file_name(Path::new("back.jpg")));
+        assert!(!is_art_file_name(Path::new("cover")));
+    }
+
+    #[test]
+    fn test_largest_cover_image_in_folder_is_picked() {
+        let dir = tempdir().unwrap();
+        let audio = dir.path().join("01 track.mp3");
+        fs::write(&audio, b"not really audio").unwrap();
+        write_image(&dir.path().join("cover.jpg"), 300, 300, ImageFormat::Jpeg);
+        write_image(&dir.path().join("FOLDER.png"), 600, 600, ImageFormat::Png);
+        // Decoys: larger, but not a cover name, not an image, or in a subfolder
+        write_image(&dir.path().join("booklet.png"), 1200, 1200, ImageFormat::Png);
+        fs::write(dir.path().join("front.jpg"), b"corrupt").unwrap();
+        fs::create_dir(dir.path().join("scans")).unwrap();
+        write_image(&dir.path().join("scans").join("cover.png"), 1200, 1200, ImageFormat::Png);
+
+        assert!(!has_embedded_art(&audio));
+        assert_eq!(folder_art_for(&audio), Some(dir.path().join("FOLDER.png")));
+    }
+
+    #[test]
+    fn test_embedded_art_is_preferred() {
+        let dir = tempdir().unwrap();
+        let audio = dir.path().join("track.mp3");
+        fs::write(&audio, b"not really audio").unwrap();
+        let mut tag = Tag::new();
+        tag.add_frame(Picture { mime_type: "image/png".into(), picture_type: PictureType::CoverFront, description: String::new(), data: vec![1, 2, 3] });
+        tag.write_to_path(&audio, Version::Id3v24).unwrap();
+        write_image(&dir.path().join("cover.png"), 100, 100, ImageFormat::Png);
+
+        assert!(has_embedded_art(&audio));
+        assert_eq!(folder_art_for(&audio), None);
+    }
+
+    #[test]
+    fn test_no_cover_image() {
+        let dir = tempdir().unwrap();
+        let audio = dir.path().join("track.wav");
+        fs::write(&audio, b"RIFF").unwrap();
+        write_image(&dir.path().join("back.png"), 100, 100, ImageFormat::Png);
+        assert_eq!(find_folder_art(&audio), None);
+    }
+}
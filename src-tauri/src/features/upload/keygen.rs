@@ -0,0 +1,346 @@
+//! R2 key generation for uploaded tracks.
+//!
+//! Keys are rendered from a template such as
+//! `tracks/{format}/{album_slug}/{track_id}_{sanitized_filename}` so that two tracks
+//! with the same filename no longer overwrite each other. `{format}` is the stored
+//! format's name (`original`, `flac`, `aac`, `mp3`), which keeps the default layout
+//! of `tracks/original/...` and `tracks/aac/...` unchanged. Templates without
+//! `{track_id}` can still render a taken key; `KeyCollisionPolicy` decides what then.
+//! Except under `Overwrite`, uploads are also conditional PUTs (`If-None-Match: *`), so
+//! a key taken by another worker after the check is never overwritten either.
+
+use deunicode::deunicode;
+use log::info;
+use serde::{Deserialize, Deserializer, Serialize};
+use std::future::Future;
+use std::path::Path;
+use uuid::Uuid;
+
+use super::UploadError;
+
+pub const DEFAULT_KEY_TEMPLATE: &str = "tracks/{format}/{album_slug}/{track_id}_{sanitized_filename}";
+
+/// Placeholders a key template may use.
+pub const TEMPLATE_PLACEHOLDERS: [&str; 5] = ["{album_slug}", "{artist_slug}", "{track_id}", "{sanitized_filename}", "{format}"];
+
+/// Hard cap on rendered key length (S3 allows 1024 bytes; we stay well below).
+pub const MAX_KEY_LEN: usize = 512;
+
+/// Cap on each slugged path segment (album, artist).
+const MAX_SLUG_LEN: usize = 64;
+
+/// Cap on the sanitized filename, extension included.
+const MAX_FILENAME_LEN: usize = 120;
+
+/// How many random suffixes to try before giving up on a free key.
+const MAX_COLLISION_ATTEMPTS: u32 = 5;
+
+/// Length of the random suffix appended to a taken key.
+const COLLISION_SUFFIX_LEN: usize = 8;
+
+/// What an upload does when the rendered R2 key already holds an object.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum KeyCollisionPolicy {
+    /// Upload over the existing object
+    Overwrite,
+    /// Append a random suffix to the key
+    #[default]
+    Rename,
+    /// Fail the item and leave the existing object alone
+    Fail,
+}
+
+impl KeyCollisionPolicy {
+    /// Whether uploads must not replace an object that appeared at the key after it was
+    /// resolved.
+    pub fn put_if_absent(self) -> bool {
+        self != KeyCollisionPolicy::Overwrite
+    }
+}
+
+/// Reads a `KeyCollisionPolicy`, or the `overwrite` flag it replaced, which older
+/// callers and saved queues still send: `true` is `Overwrite`, and `false` picked a
+/// suffixed key, i.e. `Rename`.
+pub fn policy_or_legacy_overwrite<'de, D: Deserializer<'de>>(deserializer: D) -> Result<KeyCollisionPolicy, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum PolicyOrFlag {
+        Policy(KeyCollisionPolicy),
+        Overwrite(bool),
+    }
+    Ok(match PolicyOrFlag::deserialize(deserializer)? {
+        PolicyOrFlag::Policy(policy) => policy,
+        PolicyOrFlag::Overwrite(true) => KeyCollisionPolicy::Overwrite,
+        PolicyOrFlag::Overwrite(false) => KeyCollisionPolicy::Rename,
+    })
+}
+
+/// Templates used for new uploads. Existing tracks keep the keys they were stored with.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct KeyTemplates {
+    pub archive: String,
+    pub delivery: String,
+}
+
+impl Default for KeyTemplates {
+    fn default() -> Self {
+        Self {
+            archive: DEFAULT_KEY_TEMPLATE.to_string(),
+            delivery: DEFAULT_KEY_TEMPLATE.to_string(),
+        }
+    }
+}
+
+/// Values substituted into a key template.
+pub struct KeyContext<'a> {
+    pub album: &'a str,
+    pub artist: &'a str,
+    pub track_id: &'a str,
+    pub file_name: &'a str,
+    pub format: &'a str,
+}
+
+/// Lowercases, folds to ASCII, and replaces runs of spaces/punctuation with `-`.
+/// Never returns an empty string.
+pub fn slugify(input: &str, max_len: usize) -> String {
+    let mut slug = String::with_capacity(input.len());
+    for c in deunicode(input).chars() {
+        if c.is_ascii_alphanumeric() {
+            slug.push(c.to_ascii_lowercase());
+        } else if !slug.is_empty() && !slug.ends_with('-') {
+            slug.push('-');
+        }
+    }
+    slug.truncate(max_len);
+    let slug = slug.trim_end_matches('-');
+    if slug.is_empty() { "unknown".to_string() } else { slug.to_string() }
+}
+
+/// Slugifies the file stem and keeps a lowercased extension, within `max_len` characters.
+pub fn sanitize_filename(file_name: &str, max_len: usize) -> String {
+    let path = Path::new(file_name);
+    let extension = path.extension()
+        .map(|ext| ext.to_string_lossy().chars().filter(|c| c.is_ascii_alphanumeric()).collect::<String>().to_ascii_lowercase())
+        .filter(|ext| !ext.is_empty());
+    let stem = path.file_stem().map(|s| s.to_string_lossy().into_owned()).unwrap_or_default();
+
+    match extension {
+        Some(ext) => {
+            let stem_len = max_len.saturating_sub(ext.len() + 1).max(1);
+            format!("{}.{}", slugify(&stem, stem_len), ext)
+        }
+        None => slugify(&stem, max_len),
+    }
+}
+
+/// Checks that a template only uses known placeholders and identifies the track.
+pub fn validate_template(template: &str) -> Result<(), String> {
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        let end = rest[start..].find('}').ok_or_else(|| format!("Unclosed placeholder in key template: {}", template))?;
+        let placeholder = &rest[start..start + end + 1];
+        if !TEMPLATE_PLACEHOLDERS.contains(&placeholder) {
+            return Err(format!("Unknown placeholder {} in key template (expected one of {})", placeholder, TEMPLATE_PLACEHOLDERS.join(", ")));
+        }
+        rest = &rest[start + end + 1..];
+    }
+    if !template.contains("{track_id}") && !template.contains("{sanitized_filename}") {
+        return Err("Key template must include {track_id} or {sanitized_filename}".to_string());
+    }
+    if template.starts_with('/') {
+        return Err("Key template must not start with '/'".to_string());
+    }
+    Ok(())
+}
+
+/// Renders a key from `template`, shortening the filename so the key fits within `MAX_KEY_LEN`.
+pub fn render_key(template: &str, context: &KeyContext) -> String {
+    let base = template
+        .replace("{album_slug}", &slugify(context.album, MAX_SLUG_LEN))
+        .replace("{artist_slug}", &slugify(context.artist, MAX_SLUG_LEN))
+        .replace("{track_id}", context.track_id)
+        .replace("{format}", context.format);
+
+    let overhead = base.replace("{sanitized_filename}", "").len();
+    let filename_len = MAX_FILENAME_LEN.min(MAX_KEY_LEN.saturating_sub(overhead));
+    let mut key = base.replace("{sanitized_filename}", &sanitize_filename(context.file_name, filename_len));
+    if key.len() > MAX_KEY_LEN {
+        // Template literals may be non-ASCII, so cut on a char boundary
+        let cut = (0..=MAX_KEY_LEN).rev().find(|i| key.is_char_boundary(*i)).unwrap_or(0);
+        key.truncate(cut);
+    }
+    key
+}
+
+/// Inserts `-{suffix}` before the key's extension: `a/b.wav` -> `a/b-3f9c01ab.wav`.
+pub fn with_collision_suffix(key: &str, suffix: &str) -> String {
+    let name_start = key.rfind('/').map(|i| i + 1).unwrap_or(0);
+    match key[name_start..].rfind('.') {
+        Some(dot) if dot > 0 => {
+            let dot = name_start + dot;
+            format!("{}-{}{}", &key[..dot], suffix, &key[dot..])
+        }
+        _ => format!("{}-{}", key, suffix),
+    }
+}
+
+fn random_suffix() -> String {
+    let mut suffix = Uuid::new_v4().simple().to_string();
+    suffix.truncate(COLLISION_SUFFIX_LEN);
+    suffix
+}
+
+/// Returns the key to upload to: `key` if `exists` reports it free, otherwise what
+/// `policy` asks for. `Overwrite` returns `key` without checking.
+pub async fn resolve_collision<F, Fut>(key: String, policy: KeyCollisionPolicy, exists: F) -> Result<String, UploadError>
+where
+    F: Fn(String) -> Fut,
+    Fut: Future<Output = Result<bool, String>>,
+{
+    if policy == KeyCollisionPolicy::Overwrite || !exists(key.clone()).await.map_err(UploadError::R2UploadError)? {
+        return Ok(key);
+    }
+    if policy == KeyCollisionPolicy::Fail {
+        return Err(UploadError::KeyTaken(key));
+    }
+    for _ in 0..MAX_COLLISION_ATTEMPTS {
+        let candidate = with_collision_suffix(&key, &random_suffix());
+        if candidate.len() <= MAX_KEY_LEN && !exists(candidate.clone()).await.map_err(UploadError::R2UploadError)? {
+            return Ok(candidate);
+        }
+    }
+    Err(UploadError::R2UploadError(format!("No free key found for {} after {} attempts", key, MAX_COLLISION_ATTEMPTS)))
+}
+
+/// Runs `upload` against `key`. When the conditional PUT finds the key taken since it
+/// was resolved, `Rename` tries again under a fresh suffixed key; other policies keep
+/// the error. Returns the last key tried along with the outcome. Only a successful upload
+/// stored our object there: after a failure the key may hold someone else's object.
+pub async fn upload_with_policy<F, Fut>(key: String, policy: KeyCollisionPolicy, upload: F) -> (String, Result<(), UploadError>)
+where
+    F: Fn(String) -> Fut,
+    Fut: Future<Output = Result<(), UploadError>>,
+{
+    let mut key = key;
+    for _ in 0..MAX_COLLISION_ATTEMPTS {
+        match upload(key.clone()).await {
+            Err(UploadError::KeyTaken(_)) if policy == KeyCollisionPolicy::Rename => {
+                let original = key.clone();
+                key = with_collision_suffix(&key, &random_suffix());
+                info!("R2 key {} was taken during the upload; retrying as {}", original, key);
+            }
+            result => return (key, result),
+        }
+    }
+    let error = UploadError::KeyTaken(format!("{} (still taken after {} attempts)", key, MAX_COLLISION_ATTEMPTS));
+    (key, Err(error))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::features::upload::UploadOptions;
+    use std::collections::HashSet;
+
+    fn context<'a>(album: &'a str, file_name: &'a str) -> KeyContext<'a> {
+        KeyContext { album, artist: "Artist", track_id: "65f0c0ffee", file_name, format: "original" }
+    }
+
+    #[test]
+    fn test_default_template_renders_album_and_track_id() {
+        let key = render_key(DEFAULT_KEY_TEMPLATE, &context("Greatest Hits, Vol. 2", "Master.WAV"));
+        assert_eq!(key, "tracks/original/greatest-hits-vol-2/65f0c0ffee_master.wav");
+
+        let delivery = KeyContext { format: "mp3", ..context("Greatest Hits, Vol. 2", "Master.mp3") };
+        assert_eq!(render_key(DEFAULT_KEY_TEMPLATE, &delivery), "tracks/mp3/greatest-hits-vol-2/65f0c0ffee_master.mp3");
+    }
+
+    #[test]
+    fn test_unicode_filenames_are_ascii_folded() {
+        assert_eq!(sanitize_filename("Café Déjà Vu.flac", MAX_FILENAME_LEN), "cafe-deja-vu.flac");
+        assert_eq!(slugify("Björk & Sigur Rós", MAX_SLUG_LEN), "bjork-sigur-ros");
+        // Scripts without a Latin form still produce a usable, non-empty key segment
+        assert!(!sanitize_filename("音楽.mp3", MAX_FILENAME_LEN).starts_with('.'));
+        assert_eq!(sanitize_filename("!!!.wav", MAX_FILENAME_LEN), "unknown.wav");
+    }
+
+    #[test]
+    fn test_very_long_names_are_capped() {
+        let long_name = format!("{}.wav", "a".repeat(2000));
+        let sanitized = sanitize_filename(&long_name, MAX_FILENAME_LEN);
+        assert!(sanitized.len() <= MAX_FILENAME_LEN);
+        assert!(sanitized.ends_with(".wav"));
+
+        let long_album = "b".repeat(2000);
+        let key = render_key(DEFAULT_KEY_TEMPLATE, &context(&long_album, &long_name));
+        assert!(key.len() <= MAX_KEY_LEN);
+        assert!(key.ends_with(".wav"));
+    }
+
+    #[test]
+    fn test_validate_template() {
+        assert!(validate_template(DEFAULT_KEY_TEMPLATE).is_ok());
+        assert!(validate_template("tracks/{artist_slug}/{sanitized_filename}").is_ok());
+        assert!(validate_template("tracks/{album_slug}/{bogus}").is_err());
+        assert!(validate_template("tracks/{album_slug}/static.wav").is_err());
+        assert!(validate_template("tracks/{track_id").is_err());
+    }
+
+    #[test]
+    fn test_with_collision_suffix() {
+        assert_eq!(with_collision_suffix("tracks/a/master.wav", "1"), "tracks/a/master-1.wav");
+        assert_eq!(with_collision_suffix("tracks/a.b/master", "2"), "tracks/a.b/master-2");
+        assert_eq!(random_suffix().len(), COLLISION_SUFFIX_LEN);
+    }
+
+    #[tokio::test]
+    async fn test_resolve_collision_follows_policy() {
+        let taken: HashSet<String> = ["k/master.wav"].iter().map(|s| s.to_string()).collect();
+        let exists = |key: String| {
+            let found = taken.contains(&key);
+            async move { Ok(found) }
+        };
+
+        let renamed = resolve_collision("k/master.wav".to_string(), KeyCollisionPolicy::Rename, &exists).await.unwrap();
+        assert!(renamed.starts_with("k/master-") && renamed.ends_with(".wav"));
+        assert_eq!(renamed.len(), "k/master-.wav".len() + COLLISION_SUFFIX_LEN);
+        assert_eq!(resolve_collision("k/master.wav".to_string(), KeyCollisionPolicy::Overwrite, &exists).await.unwrap(), "k/master.wav");
+        assert!(matches!(
+            resolve_collision("k/master.wav".to_string(), KeyCollisionPolicy::Fail, &exists).await,
+            Err(UploadError::KeyTaken(_))
+        ));
+        for policy in [KeyCollisionPolicy::Rename, KeyCollisionPolicy::Fail] {
+            assert_eq!(resolve_collision("k/other.wav".to_string(), policy, &exists).await.unwrap(), "k/other.wav");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_upload_with_policy_renames_keys_taken_meanwhile() {
+        // Another worker stored an object at the key after it was resolved
+        let upload = |key: String| async move {
+            if key == "k/master.wav" { Err(UploadError::KeyTaken(key)) } else { Ok(()) }
+        };
+        let (key, result) = upload_with_policy("k/master.wav".to_string(), KeyCollisionPolicy::Rename, upload).await;
+        assert!(result.is_ok());
+        assert!(key.starts_with("k/master-") && key.ends_with(".wav"), "{}", key);
+
+        let (key, result) = upload_with_policy("k/master.wav".to_string(), KeyCollisionPolicy::Fail, upload).await;
+        assert_eq!(key, "k/master.wav");
+        assert!(matches!(result, Err(UploadError::KeyTaken(_))));
+
+        let (key, result) = upload_with_policy("k/free.wav".to_string(), KeyCollisionPolicy::Rename, upload).await;
+        assert_eq!((key.as_str(), result.is_ok()), ("k/free.wav", true));
+    }
+
+    #[test]
+    fn test_legacy_overwrite_flag_maps_to_a_policy() {
+        // Saved queues and schedules hold serialized `UploadOptions`
+        let parse = |json: &str| serde_json::from_str::<UploadOptions>(json).unwrap().on_key_collision;
+        assert_eq!(parse(r#"{ "overwrite": true }"#), KeyCollisionPolicy::Overwrite);
+        assert_eq!(parse(r#"{ "overwrite": false }"#), KeyCollisionPolicy::Rename);
+        assert_eq!(parse(r#"{ "on_key_collision": "fail" }"#), KeyCollisionPolicy::Fail);
+        assert_eq!(parse("{}"), KeyCollisionPolicy::Rename);
+        assert!(serde_json::from_str::<UploadOptions>(r#"{ "overwrite": "sometimes" }"#).is_err());
+    }
+}
@@ -0,0 +1,317 @@
+//! Checks every file passes before it is queued for upload: size limit, audio file
+//! type (by extension and by content), and a container symphonia can open. The size
+//! limit also guards the local transcode commands.
+//!
+//! The limits, including the upload bandwidth cap, are part of the app settings, under
+//! `limits` (see `core::settings`).
+
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File};
+use std::path::Path;
+use std::sync::Arc;
+use thiserror::Error;
+use symphonia::core::formats::FormatOptions;
+use symphonia::core::io::MediaSourceStream;
+use symphonia::core::meta::MetadataOptions;
+use symphonia::core::probe::Hint;
+use symphonia::default::get_probe;
+use serde_json::json;
+use tauri::{command, AppHandle, State, Wry};
+use ts_rs::TS;
+
+use super::{ErrorCategory, UploadError, UploadState};
+use crate::core::settings::patch_settings;
+use crate::CommandError;
+
+pub const DEFAULT_MAX_FILE_SIZE_BYTES: u64 = 2 * 1024 * 1024 * 1024;
+
+/// Extensions accepted for upload.
+pub const AUDIO_EXTENSIONS: [&str; 11] = ["wav", "wave", "aif", "aiff", "flac", "mp3", "m4a", "aac", "ogg", "oga", "opus"];
+
+const NOT_UTF8_ADVICE: &str = "is not valid UTF-8 and can't be uploaded; rename the file and select it again";
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct UploadLimits {
+    pub max_file_size_bytes: u64,
+    /// Combined rate of all uploads in MB/s; 0 means unlimited
+    pub max_upload_mb_per_sec: f64,
+}
+
+impl Default for UploadLimits {
+    fn default() -> Self {
+        Self { max_file_size_bytes: DEFAULT_MAX_FILE_SIZE_BYTES, max_upload_mb_per_sec: 0.0 }
+    }
+}
+
+/// The limits to change in `set_upload_limits`. Serialized as a settings patch, so a
+/// field that is `None` is left as it is.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct UploadLimitsUpdate {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_file_size_bytes: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_upload_mb_per_sec: Option<f64>,
+}
+
+fn validate_bandwidth_limit(mb_per_sec: f64) -> Result<(), String> {
+    if !mb_per_sec.is_finite() || mb_per_sec < 0.0 {
+        return Err(UploadError::InvalidInput("Bandwidth limit must be 0 (unlimited) or a positive number of MB/s.".to_string()).to_string());
+    }
+    Ok(())
+}
+
+impl UploadLimits {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.max_file_size_bytes == 0 {
+            return Err(UploadError::InvalidInput("Maximum file size must be greater than 0.".to_string()).to_string());
+        }
+        validate_bandwidth_limit(self.max_upload_mb_per_sec)
+    }
+}
+
+/// Why a file can't be queued for upload.
+#[derive(Debug, Clone, PartialEq, Error)]
+pub enum InputFileError {
+    /// Reading the file failed, e.g. for lack of permission; the file itself may be fine
+    #[error("Cannot read file: {0}")]
+    Unreadable(String),
+    #[error("File is {size} bytes, over the {limit} byte upload limit")]
+    TooLarge { size: u64, limit: u64 },
+    /// Not audio, or audio that can't be opened
+    #[error("{0}")]
+    Invalid(String),
+}
+
+impl InputFileError {
+    /// Category and short status message of an item rejected for this reason.
+    pub fn status(&self) -> (ErrorCategory, &'static str) {
+        match self {
+            InputFileError::Unreadable(_) => (ErrorCategory::FileAccess, "File unreadable"),
+            InputFileError::TooLarge { .. } => (ErrorCategory::CorruptInput, "File too large"),
+            InputFileError::Invalid(_) => (ErrorCategory::CorruptInput, "Invalid input file"),
+        }
+    }
+
+    /// The command error for rejecting the file at `path` for this reason.
+    pub fn into_command_error(self, path: &str) -> CommandError {
+        let message = format!("{}: {}", path, self);
+        match self {
+            InputFileError::Unreadable(_) => CommandError::FileSystem(message),
+            _ => CommandError::Validation(message),
+        }
+    }
+}
+
+/// Returns the file's size, or why it can't be read or is over `max_file_size_bytes`.
+pub fn check_file_size(path: &Path, max_file_size_bytes: u64) -> Result<u64, InputFileError> {
+    let size = fs::metadata(path).map_err(|e| InputFileError::Unreadable(e.to_string()))?.len();
+    if size > max_file_size_bytes {
+        return Err(InputFileError::TooLarge { size, limit: max_file_size_bytes });
+    }
+    Ok(size)
+}
+
+/// The path as a string. Paths reach the frontend, the queue, and MongoDB as strings,
+/// and a lossy conversion could name another file or none at all.
+pub fn utf8_path(path: &Path) -> Result<&str, CommandError> {
+    path.to_str().ok_or_else(|| CommandError::Validation(format!("The path {} {}", path.to_string_lossy(), NOT_UTF8_ADVICE)))
+}
+
+/// Files picked in the file dialog, split by whether their paths can be queued.
+#[derive(Debug, Clone, Default, Serialize, PartialEq, TS)]
+#[ts(export)]
+pub struct SelectedFiles {
+    pub paths: Vec<String>,
+    /// Names of the files whose path is not valid UTF-8, converted lossily for display;
+    /// they have to be renamed before they can be uploaded
+    pub rejected: Vec<String>,
+}
+
+/// Keeps the paths that are valid UTF-8 and collects the names of the others, so one
+/// badly named file doesn't lose the rest of the selection.
+pub fn split_selected_paths<'a>(paths: impl IntoIterator<Item = &'a Path>) -> SelectedFiles {
+    let mut selected = SelectedFiles::default();
+    for path in paths {
+        match utf8_path(path) {
+            Ok(path) => selected.paths.push(path.to_string()),
+            Err(_) => {
+                warn!("Not selecting {}: the path {}", path.to_string_lossy(), NOT_UTF8_ADVICE);
+                let name = path.file_name().unwrap_or(path.as_os_str());
+                selected.rejected.push(name.to_string_lossy().into_owned());
+            }
+        }
+    }
+    selected
+}
+
+/// Whether a path received as a string was converted lossily on its way here: it holds
+/// the replacement character and names no file.
+fn is_lossy_path(path: &Path) -> bool {
+    path.to_str().is_some_and(|path_str| path_str.contains(char::REPLACEMENT_CHARACTER)) && !path.exists()
+}
+
+/// Checks a file before it is queued, returning the reason it can't be uploaded.
+/// Reads the first bytes and probes the container, so call it off the async runtime.
+pub fn validate_input_file(path: &Path, limits: &UploadLimits) -> Result<(), InputFileError> {
+    if is_lossy_path(path) {
+        return Err(InputFileError::Invalid(format!("The file name {}", NOT_UTF8_ADVICE)));
+    }
+    check_file_size(path, limits.max_file_size_bytes)?;
+
+    let extension = path.extension().and_then(|e| e.to_str()).unwrap_or_default().to_lowercase();
+    if !AUDIO_EXTENSIONS.contains(&extension.as_str()) {
+        return Err(InputFileError::Invalid(format!("'.{}' is not a supported audio file type", extension)));
+    }
+
+    // Content that is recognized must be audio; unrecognized content is left to the probe.
+    // Some encoders write .m4a files with the generic MP4 brand, which sniffs as video.
+    if let Some(kind) = infer::get_from_path(path).map_err(|e| InputFileError::Unreadable(e.to_string()))? {
+        let generic_mp4_audio = extension == "m4a" && kind.mime_type() == "video/mp4";
+        if kind.matcher_type() != infer::MatcherType::Audio && !generic_mp4_audio {
+            return Err(InputFileError::Invalid(format!("File content is {}, not audio, despite the '.{}' extension", kind.mime_type(), extension)));
+        }
+    }
+
+    let file = File::open(path).map_err(|e| InputFileError::Unreadable(e.to_string()))?;
+    let source = MediaSourceStream::new(Box::new(file), Default::default());
+    let mut hint = Hint::new();
+    hint.with_extension(&extension);
+    let probed = get_probe()
+        .format(&hint, source, &FormatOptions::default(), &MetadataOptions::default())
+        .map_err(|e| InputFileError::Invalid(format!("Unsupported or corrupt audio container: {}", e)))?;
+    if probed.format.default_track().is_none() {
+        return Err(InputFileError::Invalid("File contains no audio track".to_string()));
+    }
+    Ok(())
+}
+
+// --- Tauri Commands ---
+
+#[command]
+pub async fn get_upload_limits(upload_state: State<'_, Arc<UploadState>>) -> Result<UploadLimits, String> {
+    Ok(*upload_state.limits.lock().await)
+}
+
+/// Saves new upload limits to the settings; they apply to items queued from now on.
+/// Fields left out keep their value. Returns the limits now in effect.
+#[command]
+pub async fn set_upload_limits(limits: UploadLimitsUpdate, app_handle: AppHandle<Wry>) -> Result<UploadLimits, String> {
+    let settings = patch_settings(&app_handle, &json!({ "limits": limits })).await.map_err(|e| e.to_string())?;
+    info!("Upload limits set to {:?}.", settings.limits);
+    Ok(settings.limits)
+}
+
+/// The combined upload rate limit in MB/s; 0 means unlimited.
+#[command]
+pub async fn get_upload_bandwidth_limit(upload_state: State<'_, Arc<UploadState>>) -> Result<f64, String> {
+    Ok(upload_state.limits.lock().await.max_upload_mb_per_sec)
+}
+
+/// Saves the upload rate limit to the settings. It applies to uploads already in flight
+/// within a chunk.
+#[command]
+pub async fn set_upload_bandwidth_limit(mb_per_sec: f64, app_handle: AppHandle<Wry>) -> Result<f64, String> {
+    validate_bandwidth_limit(mb_per_sec)?;
+    patch_settings(&app_handle, &json!({ "limits": { "max_upload_mb_per_sec": mb_per_sec } })).await.map_err(|e| e.to_string())?;
+    info!("Upload bandwidth limit set to {} MB/s.", mb_per_sec);
+    Ok(mb_per_sec)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use tempfile::tempdir;
+
+    /// A one-second silent mono 16-bit WAV file.
+    fn wav_bytes() -> Vec<u8> {
+        let (sample_rate, data_len) = (8000u32, 16000u32);
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(b"RIFF");
+        bytes.extend_from_slice(&(36 + data_len).to_le_bytes());
+        bytes.extend_from_slice(b"WAVEfmt ");
+        bytes.extend_from_slice(&16u32.to_le_bytes());
+        bytes.extend_from_slice(&1u16.to_le_bytes()); // PCM
+        bytes.extend_from_slice(&1u16.to_le_bytes()); // mono
+        bytes.extend_from_slice(&sample_rate.to_le_bytes());
+        bytes.extend_from_slice(&(sample_rate * 2).to_le_bytes());
+        bytes.extend_from_slice(&2u16.to_le_bytes());
+        bytes.extend_from_slice(&16u16.to_le_bytes());
+        bytes.extend_from_slice(b"data");
+        bytes.extend_from_slice(&data_len.to_le_bytes());
+        bytes.resize(bytes.len() + data_len as usize, 0);
+        bytes
+    }
+
+    #[test]
+    fn test_accepts_wav_and_rejects_unsupported_extension() {
+        let dir = tempdir().unwrap();
+        let wav = dir.path().join("tone.wav");
+        fs::write(&wav, wav_bytes()).unwrap();
+        assert_eq!(validate_input_file(&wav, &UploadLimits::default()), Ok(()));
+
+        let text = dir.path().join("notes.txt");
+        fs::write(&text, b"not audio").unwrap();
+        assert!(validate_input_file(&text, &UploadLimits::default()).unwrap_err().to_string().contains("not a supported audio file type"));
+    }
+
+    #[test]
+    fn test_rejects_video_renamed_to_wav() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("concert.wav");
+        let mut file = File::create(&path).unwrap();
+        // ISO base media header with the generic "isom" brand used by video files
+        file.write_all(&[0, 0, 0, 0x18]).unwrap();
+        file.write_all(b"ftypisom\0\0\x02\0isomiso2").unwrap();
+        file.write_all(&[0u8; 1024]).unwrap();
+
+        let error = validate_input_file(&path, &UploadLimits::default()).unwrap_err().to_string();
+        assert!(error.contains("video/mp4"), "{}", error);
+    }
+
+    #[test]
+    fn test_rejects_oversized_sparse_file() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("live.wav");
+        let file = File::create(&path).unwrap();
+        file.set_len(DEFAULT_MAX_FILE_SIZE_BYTES + 1).unwrap(); // Sparse, takes no disk space
+
+        let error = validate_input_file(&path, &UploadLimits::default()).unwrap_err().to_string();
+        assert!(error.contains("over the 2147483648 byte upload limit"), "{}", error);
+        assert_eq!(check_file_size(&path, DEFAULT_MAX_FILE_SIZE_BYTES + 1), Ok(DEFAULT_MAX_FILE_SIZE_BYTES + 1));
+    }
+
+    #[test]
+    fn test_unreadable_files_are_not_reported_as_too_large() {
+        let dir = tempdir().unwrap();
+        let error = check_file_size(&dir.path().join("gone.wav"), DEFAULT_MAX_FILE_SIZE_BYTES).unwrap_err();
+        assert!(matches!(error, InputFileError::Unreadable(_)), "{:?}", error);
+        assert_eq!(error.status(), (ErrorCategory::FileAccess, "File unreadable"));
+
+        let too_large = InputFileError::TooLarge { size: 2, limit: 1 };
+        assert_eq!(too_large.status(), (ErrorCategory::CorruptInput, "File too large"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_non_utf8_paths_are_rejected_with_advice() {
+        use std::ffi::OsStr;
+        use std::os::unix::ffi::OsStrExt;
+
+        let latin1 = Path::new(OsStr::from_bytes(b"/music/caf\xe9.wav"));
+        assert!(matches!(utf8_path(latin1), Err(CommandError::Validation(message)) if message.contains("rename the file")));
+        assert_eq!(utf8_path(Path::new("/music/café.wav")).unwrap(), "/music/café.wav");
+
+        // What the frontend sends back after a lossy conversion
+        let lossy = latin1.to_string_lossy().into_owned();
+        let error = validate_input_file(Path::new(&lossy), &UploadLimits::default()).unwrap_err().to_string();
+        assert!(error.contains("not valid UTF-8"), "{}", error);
+
+        let selected = split_selected_paths([Path::new("/music/a.wav"), latin1, Path::new("/music/b.wav")]);
+        assert_eq!(selected.paths, vec!["/music/a.wav", "/music/b.wav"]);
+        assert_eq!(selected.rejected, vec!["caf\u{FFFD}.wav"]);
+    }
+}
@@ -0,0 +1,87 @@
+//! Local copies of delivery files. With `keep_transcoded_dir` set, the transcoded
+//! delivery file is moved there once its track is stored instead of being deleted, at
+//! the path of its R2 key under that directory.
+
+use log::info;
+use std::fs;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+
+use super::keygen::with_collision_suffix;
+use crate::features::catalog::staging::STAGING_PREFIX;
+
+/// Path under `dir` for the delivery copy uploaded to `delivery_key`. Staged uploads
+/// land where they will be once promoted; parts of the key that could leave `dir` are
+/// dropped.
+pub fn local_copy_path(dir: &Path, delivery_key: &str) -> PathBuf {
+    let key = delivery_key.strip_prefix(STAGING_PREFIX).unwrap_or(delivery_key);
+    let mut path = dir.to_path_buf();
+    for component in Path::new(key).components() {
+        if let Component::Normal(part) = component {
+            path.push(part);
+        }
+    }
+    path
+}
+
+/// `path`, or the first of `name-1.ext`, `name-2.ext`, ... that doesn't exist yet, so
+/// earlier copies are never overwritten.
+fn free_path(path: PathBuf) -> PathBuf {
+    if !path.exists() {
+        return path;
+    }
+    let file_name = path.file_name().unwrap_or_default().to_string_lossy().into_owned();
+    (1..)
+        .map(|n| path.with_file_name(with_collision_suffix(&file_name, &n.to_string())))
+        .find(|candidate| !candidate.exists())
+        .unwrap_or(path)
+}
+
+/// Moves the transcoded file to its place under `dir` and returns where it went.
+/// Falls back to copy and delete when `dir` is on another file system. Blocking.
+pub fn keep_transcoded_file(temp_path: &Path, dir: &Path, delivery_key: &str) -> io::Result<PathBuf> {
+    let target = local_copy_path(dir, delivery_key);
+    if let Some(parent) = target.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let target = free_path(target);
+    if fs::rename(temp_path, &target).is_err() {
+        fs::copy(temp_path, &target)?;
+        fs::remove_file(temp_path)?;
+    }
+    info!("Kept transcoded file {:?} at {:?}", temp_path, target);
+    Ok(target)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_local_copy_path_follows_the_key() {
+        let dir = Path::new("/exports");
+        assert_eq!(local_copy_path(dir, "delivery/my-album/song.m4a"), dir.join("delivery/my-album/song.m4a"));
+        assert_eq!(local_copy_path(dir, "staging/delivery/song.m4a"), dir.join("delivery/song.m4a"));
+        assert_eq!(local_copy_path(dir, "/../../etc/song.m4a"), dir.join("etc/song.m4a"));
+    }
+
+    #[test]
+    fn test_kept_files_are_moved_without_overwriting() {
+        let temp = tempdir().unwrap();
+        let out = tempdir().unwrap();
+        let first = temp.path().join("transcoded_1.m4a");
+        let second = temp.path().join("transcoded_2.m4a");
+        fs::write(&first, b"first").unwrap();
+        fs::write(&second, b"second").unwrap();
+
+        let kept = keep_transcoded_file(&first, out.path(), "delivery/album/song.m4a").unwrap();
+        assert_eq!(kept, out.path().join("delivery/album/song.m4a"));
+        assert!(!first.exists());
+
+        let again = keep_transcoded_file(&second, out.path(), "delivery/album/song.m4a").unwrap();
+        assert_eq!(again, out.path().join("delivery/album/song-1.m4a"));
+        assert_eq!(fs::read(&kept).unwrap(), b"first");
+        assert_eq!(fs::read(&again).unwrap(), b"second");
+    }
+}
@@ -1,13 +1,56 @@
 // Declare submodules for the 'upload' feature
 pub mod audio;
+pub mod encryption;
+pub mod folder_art;
+pub mod keygen;
+pub mod limits;
+pub mod local_copy;
+pub mod originals;
+pub mod quarantine;
+pub mod queue;
+pub mod schedule;
+pub mod shutdown;
+pub mod temp_storage;
+pub mod throttle;
+pub mod url_ingest;
 
 // Final Corrected Imports (Attempt 3)
-use crate::features::upload::audio::transcode::transcode_to_aac; // Updated path
+use crate::features::upload::audio::transcode::{transcode_with_timeout, TranscodeFormat, TranscodingOptions}; // Updated path
 use crate::features::upload::audio::error::TranscodingError; // Updated path
-// Credentials are not directly used here; bucket name comes from R2State
+use crate::features::upload::audio::analysis::{analyze_file, TrackFeatures};
+use crate::features::upload::audio::loudness::{measure_loudness, Loudness};
+use crate::features::catalog::changes::{record_deletions, stamp, CatalogKind};
+use crate::features::catalog::genres::normalize_genres;
+use crate::features::catalog::custom_fields::validate_custom_fields;
+use crate::features::catalog::artwork::{cache_dir, invalidate_album_art_cache, store_album_artwork};
+use crate::features::catalog::storage::{id_to_string, is_duplicate_key_error, normalize_isrc};
+use crate::features::catalog::slugs::{is_slug_conflict, slug_base, unique_slug};
+use crate::features::catalog::staging::{staged_key, COLLISION_POLICY_FIELD, STAGED_STATUS};
+use crate::features::activity::{record_activity, ActivityAction, ActivityEntry};
+use crate::core::encryption::{encrypt_file, key_id, KEY_LEN};
+use crate::core::r2::{ObjectVisibility, R2Client};
+use crate::features::credentials::get_or_create_originals_key;
+use crate::core::db_config::{CatalogCollections, CatalogDatabase};
+use crate::core::settings::patch_settings;
+use crate::CommandError;
+use self::keygen::{policy_or_legacy_overwrite, render_key, resolve_collision, upload_with_policy, validate_template, KeyCollisionPolicy, KeyContext, KeyTemplates};
+use self::quarantine::{file_error, quarantine_items, Quarantine, QuarantinedItem};
+use self::queue::{PendingQueue, PendingUpload, RemoveError};
+use self::schedule::UploadSchedule;
+use self::shutdown::ShutdownState;
+use self::encryption::EncryptionSettings;
+use self::folder_art::folder_art_for;
+use self::limits::{utf8_path, validate_input_file, InputFileError, UploadLimits};
+use self::local_copy::keep_transcoded_file;
+use self::originals::{absolute_original_path, hash_file, LOCAL_ORIGINAL_LOCATION, R2_ORIGINAL_LOCATION};
+use self::temp_storage::{release_temp_files, track_temp_file};
+use self::throttle::{throttled_file_stream, BandwidthLimiter};
+// Credentials are not directly used here; bucket name comes from R2State unless a batch overrides it
 // Removed unused DbTrack import
-use aws_sdk_s3::primitives::ByteStream;
 use aws_sdk_s3::Client as S3Client;
+use aws_sdk_s3::error::SdkError;
+use aws_sdk_s3::operation::put_object::PutObjectError;
+use chrono::{DateTime, Utc};
 // Removed potentially duplicate StreamExt import
 // Removed prelude wildcard import to avoid type conflicts
 // Reverting to prelude import to resolve trait scope issues
@@ -17,17 +60,23 @@ use aws_sdk_s3::Client as S3Client;
 // StdDuration import removed as it was likely only needed for Lofty.
 use log::{error, info, warn}; // Removed unused debug import
 use mongodb::bson::{self, doc, oid::ObjectId, Document}; // Removed unused BsonDateTime import
-use mongodb::Client as MongoDbClient;
+use mongodb::error::{TRANSIENT_TRANSACTION_ERROR, UNKNOWN_TRANSACTION_COMMIT_RESULT};
+use mongodb::options::{FindOneAndUpdateOptions, FindOneOptions, ReturnDocument};
+use mongodb::{Client as MongoDbClient, ClientSession, Collection};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use serde_json::json;
+use std::collections::{HashMap, HashSet};
+use std::future::Future;
 use std::path::{Path, PathBuf};
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 // Removed unused SystemTime import
 use tauri::{command, AppHandle, Emitter, Manager, State, Wry}; // Ensure Manager and Emitter traits are imported
 use tempfile::Builder as TempFileBuilder; // Removed unused NamedTempFile import
 use thiserror::Error;
-use tokio::sync::{mpsc, Mutex};
+use ts_rs::TS;
+use tokio::sync::{oneshot, Mutex, Notify};
 use uuid::Uuid;
 
 // --- Error Enum (Consider moving to a shared error module if applicable) ---
@@ -50,6 +99,10 @@ pub enum UploadError {
     MongoDbError(String),
     #[error("Invalid input: {0}")]
     InvalidInput(String),
+    #[error("An object already exists at {0}")]
+    KeyTaken(String),
+    #[error("Operation timed out after {0}s")]
+    TimedOut(u64),
     #[error("Operation cancelled")]
     Cancelled,
     #[error("Internal error: {0}")]
@@ -58,7 +111,8 @@ pub enum UploadError {
 
 // --- Data Structures ---
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, Default, TS)]
+#[ts(export, optional_fields = nullable)]
 pub struct UploadItemMetadata {
     // Core editable fields
     pub title: Option<String>, // Made public
@@ -73,28 +127,230 @@ pub struct UploadItemMetadata {
     // Add other relevant fields here if needed (e.g., year, comments)
     pub year: Option<i32>,
     pub comments: Option<String>,
+    /// Artist credited for the whole album (TPE2/ALBUMARTIST); albums are matched on it
+    /// rather than the track artist
+    #[serde(default)]
+    pub album_artist: Option<String>,
+    /// Part of a various-artists compilation; compilations are matched on album name alone
+    #[serde(default)]
+    pub compilation: bool,
+    // Free-form tags stored on the track as `custom_fields`
+    #[serde(default)]
+    pub custom_fields: Option<HashMap<String, String>>,
+    /// All credited composers; `composer` is used when this is unset
+    #[serde(default)]
+    pub composers: Option<Vec<String>>,
+    /// International Standard Recording Code, validated before the item is queued
+    #[serde(default)]
+    pub isrc: Option<String>,
+    /// Label catalog number
+    #[serde(default)]
+    pub catalog_number: Option<String>,
+    /// Hex id of the album to file the track under, used with `AlbumMatching::ByExplicitId`
+    #[serde(default)]
+    pub album_id: Option<String>,
+    // Technical details of the source file, read by `extract_metadata`
+    /// Short codec name, e.g. "flac" or "mp3"
+    #[serde(default)]
+    pub codec: Option<String>,
+    /// Bits per second; exact for PCM, otherwise averaged over the file
+    #[serde(default)]
+    pub bitrate: Option<u32>,
+    #[serde(default)]
+    pub sample_rate: Option<u32>,
+    #[serde(default)]
+    pub channels: Option<u16>,
+    /// Local image uploaded as the album's artwork if the album has none yet. Filled in
+    /// from a cover image in the file's folder when the tags have no embedded art
+    #[serde(default)]
+    pub art_path: Option<String>,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+/// How a track finds the album it is filed under.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AlbumMatching {
+    /// Match on album name and album artist (name alone for compilations)
+    #[default]
+    ByNameArtist,
+    /// Use the `album_id` given in the metadata, creating the album if it doesn't exist
+    ByExplicitId,
+    /// Create a new album for every track
+    AlwaysNew,
+}
+
+/// What is stored as the archive copy of a track.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ArchiveFormat {
+    /// The uploaded file, byte for byte
+    #[default]
+    Original,
+    /// A lossless FLAC transcode of the uploaded file, usually smaller than WAV/AIFF
+    Flac,
+}
+
+impl ArchiveFormat {
+    /// Name used for the `{format}` key placeholder and the track's `archive_format` field.
+    pub fn name(self) -> &'static str {
+        match self {
+            ArchiveFormat::Original => "original",
+            ArchiveFormat::Flac => TranscodeFormat::Flac.name(),
+        }
+    }
+
+    /// MIME type of the archive copy of `input_path`.
+    pub fn mime_type(self, input_path: &Path) -> String {
+        match self {
+            ArchiveFormat::Original => source_mime_type(input_path),
+            ArchiveFormat::Flac => TranscodeFormat::Flac.mime_type().to_string(),
+        }
+    }
+}
+
+/// MIME type of an uploaded audio file. Common audio types are mapped explicitly so
+/// stored objects get the types players expect (`mime_guess` yields e.g. `audio/x-wav`
+/// or `audio/x-flac` for some of them); anything else falls back to `mime_guess`.
+pub fn source_mime_type(path: &Path) -> String {
+    let extension = path.extension().and_then(|e| e.to_str()).unwrap_or_default().to_lowercase();
+    let mime_type = match extension.as_str() {
+        "wav" | "wave" => "audio/wav",
+        "aif" | "aiff" => "audio/aiff",
+        "flac" => TranscodeFormat::Flac.mime_type(),
+        "mp3" => TranscodeFormat::Mp3.mime_type(),
+        "m4a" | "mp4" => TranscodeFormat::Aac.mime_type(),
+        "aac" => "audio/aac", // Raw ADTS stream
+        "ogg" | "oga" => "audio/ogg",
+        "opus" => "audio/opus",
+        _ => return mime_guess::from_path(path).first_or_octet_stream().to_string(),
+    };
+    mime_type.to_string()
+}
+
+/// Lossy format of the delivery copy used for streaming and downloads.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DeliveryFormat {
+    #[default]
+    Aac,
+    Mp3,
+}
+
+impl DeliveryFormat {
+    pub fn transcode_format(self) -> TranscodeFormat {
+        match self {
+            DeliveryFormat::Aac => TranscodeFormat::Aac,
+            DeliveryFormat::Mp3 => TranscodeFormat::Mp3,
+        }
+    }
+}
+
+/// Archive and delivery formats for an upload. Defaults to the original file plus AAC.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UploadFormats {
+    #[serde(default)]
+    pub archive: ArchiveFormat,
+    #[serde(default)]
+    pub delivery: DeliveryFormat,
+}
+
+/// Options applied to every item of a `start_upload_queue` call.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct UploadOptions {
+    /// What to do when an item's R2 key already holds an object. Also read from the
+    /// `overwrite` flag it replaced
+    #[serde(default, alias = "overwrite", deserialize_with = "policy_or_legacy_overwrite")]
+    pub on_key_collision: KeyCollisionPolicy,
+    #[serde(default)]
+    pub formats: UploadFormats,
+    /// Reject items without a title, artist, and album instead of storing placeholders
+    #[serde(default)]
+    pub require_complete_metadata: bool,
+    /// Sample rate and channels of the delivery copy; the archive is never resampled
+    #[serde(default)]
+    pub transcoding: TranscodingOptions,
+    /// Whether the uploaded objects are linked publicly or only through presigned URLs
+    #[serde(default)]
+    pub visibility: ObjectVisibility,
+    #[serde(default)]
+    pub album_matching: AlbumMatching,
+    /// Upload originals to R2; when false only the delivery copy is uploaded and the
+    /// original stays on local storage. Defaults to the `upload.upload_originals` setting
+    #[serde(default)]
+    pub upload_originals: Option<bool>,
+    /// Upload under `staging/` and keep the tracks out of listings until they are promoted
+    /// (see `catalog::staging`)
+    #[serde(default)]
+    pub staging: bool,
+    /// Keep the transcoded delivery file in this directory, at the path of its R2 key,
+    /// instead of deleting it (see `local_copy`)
+    #[serde(default)]
+    pub keep_transcoded_dir: Option<PathBuf>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, TS)]
+#[ts(export, optional_fields = nullable)]
 pub struct UploadItemInput {
     pub id: String,
     pub path: String,
     pub metadata: UploadItemMetadata,
+    /// Client-chosen key; an item whose key was already queued this session is skipped
+    #[serde(default)]
+    pub idempotency_key: Option<String>,
 }
 
-#[derive(Debug, Clone, Serialize, PartialEq)]
+/// Serialized with the variant under `kind`, e.g. `{ "kind": "Transcoding" }` or
+/// `{ "kind": "Error", "category": "Auth", "message": "..." }`.
+#[derive(Debug, Clone, Serialize, PartialEq, TS)]
+#[serde(tag = "kind")]
+#[ts(export)]
 pub enum UploadStatus {
     Pending,
+    Scheduled, // Queued, waiting for the scheduled start time
     Transcoding,
-    UploadingOriginal,
-    UploadingAAC,
+    UploadingOriginal, // Archive copy, whatever its format
+    UploadingAAC, // Delivery copy, whatever its format
     StoringMetadata,
     Complete,
+    Paused, // Waiting for the queue to be resumed
     Cancelled,
-    Error(String),
+    Removed, // Taken out of the pending queue before processing started
+    Skipped, // Its idempotency key was already queued this session
+    Error { category: ErrorCategory, message: String },
 }
 
-#[derive(Debug, Clone, Serialize)]
+impl UploadStatus {
+    pub fn error(category: ErrorCategory, message: impl Into<String>) -> Self {
+        UploadStatus::Error { category, message: message.into() }
+    }
+}
+
+/// What went wrong with a failed item, so the frontend knows whether retrying can help.
+#[derive(Debug, Clone, Copy, Serialize, PartialEq, TS)]
+#[ts(export)]
+pub enum ErrorCategory {
+    Transcode,
+    /// Network or database hiccup, or a timeout; worth retrying
+    NetworkTransient,
+    /// Missing clients or credentials R2 refused
+    Auth,
+    /// The file is missing, too large or not audio FFmpeg can decode
+    CorruptInput,
+    /// The file exists but couldn't be read, e.g. for lack of permission
+    FileAccess,
+    /// Metadata failed validation or clashes with another track
+    Metadata,
+    Unknown,
+}
+
+impl ErrorCategory {
+    pub fn is_retryable(self) -> bool {
+        matches!(self, ErrorCategory::NetworkTransient | ErrorCategory::Unknown)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, TS)]
+#[ts(export)]
 pub struct UploadProgress {
     pub item_id: Uuid,
     pub original_path: String,
@@ -102,6 +358,27 @@ pub struct UploadProgress {
     pub error_message: Option<String>,
     pub title: Option<String>,
     pub album: Option<String>,
+    /// Id of the stored track, once the item is complete
+    pub track_id: Option<String>,
+    /// Measured upload rate over the last second, while a file is being uploaded
+    pub throughput_bytes_per_sec: Option<f64>,
+    /// Where the delivery file was kept, once the item is complete and `keep_transcoded_dir` is set
+    pub local_delivery_path: Option<String>,
+}
+
+/// Overall progress of a processing run, sent as `upload://batch-progress`.
+#[derive(Debug, Clone, Serialize, PartialEq, TS)]
+#[ts(export)]
+pub struct BatchProgress {
+    #[ts(type = "number")]
+    pub completed: usize,
+    #[ts(type = "number")]
+    pub failed: usize,
+    /// Items pending when the run started
+    #[ts(type = "number")]
+    pub total: usize,
+    /// Share of the items that reached a final status, cancelled and removed ones included
+    pub percent: f64,
 }
 
 #[derive(Debug)]
@@ -109,348 +386,1752 @@ pub struct UploadQueueItem { // Make struct public
     id: Uuid,
     input_path: PathBuf,
     metadata: UploadItemMetadata,
-    temp_aac_path: Option<PathBuf>,
-    r2_original_key: Option<String>,
-    r2_aac_key: Option<String>,
+    temp_delivery_path: Option<PathBuf>,
+    // Only set when the archive copy is a transcode (or, for URL uploads, the downloaded copy);
+    // otherwise the input file is archived
+    temp_archive_path: Option<PathBuf>,
+    // Encrypted copy of the archive, uploaded in its place when originals are encrypted
+    temp_encrypted_path: Option<PathBuf>,
+    encryption_key_id: Option<String>,
+    r2_archive_key: Option<String>,
+    r2_delivery_key: Option<String>,
     db_track_id: Option<String>,
+    // What to do when the rendered R2 key already holds an object
+    on_key_collision: KeyCollisionPolicy,
+    formats: UploadFormats,
+    transcoding: TranscodingOptions,
+    // Bucket for this item's objects; the R2State bucket when None
+    bucket_override: Option<String>,
+    visibility: ObjectVisibility,
+    album_matching: AlbumMatching,
+    // Set for uploads streamed from a URL; `input_path` is then only the URL's file name
+    remote_source: Option<self::url_ingest::RemoteSource>,
+    // When false the archive phase is skipped and the original only hashed
+    upload_originals: bool,
+    // Content hash of an original that was kept locally
+    original_sha256: Option<String>,
+    // Keys go under `staging/` and the track is stored as staged
+    staging: bool,
+    // Directory the delivery file is moved to after upload; deleted when None
+    keep_transcoded_dir: Option<PathBuf>,
+    // Kept so an item saved for the next launch is queued again with it
+    idempotency_key: Option<String>,
+}
+
+/// An item's input, options and bucket override, as it was queued.
+type QueuedInput = (UploadItemInput, UploadOptions, Option<String>);
+
+impl UploadQueueItem {
+    /// The input and options the item was queued with, to queue it again. Items streamed
+    /// from a URL have no local file to queue again.
+    fn queued_input(&self) -> Option<QueuedInput> {
+        if self.remote_source.is_some() {
+            return None;
+        }
+        let input = UploadItemInput {
+            id: self.id.to_string(),
+            path: self.input_path.to_string_lossy().into_owned(),
+            metadata: self.metadata.clone(),
+            idempotency_key: self.idempotency_key.clone(),
+        };
+        let options = UploadOptions {
+            on_key_collision: self.on_key_collision,
+            formats: self.formats,
+            // Checked when the item was first queued
+            require_complete_metadata: false,
+            transcoding: self.transcoding,
+            visibility: self.visibility,
+            album_matching: self.album_matching,
+            upload_originals: Some(self.upload_originals),
+            staging: self.staging,
+            keep_transcoded_dir: self.keep_transcoded_dir.clone(),
+        };
+        Some((input, options, self.bucket_override.clone()))
+    }
 }
 
 // --- Shared State ---
 
+/// Default per-item timeout applied to the transcode and to each R2 upload.
+pub const DEFAULT_ITEM_TIMEOUT_SECS: u64 = 300;
+
+/// Items processed at once by default; one keeps uploads in queue order.
+pub const DEFAULT_UPLOAD_CONCURRENCY: usize = 1;
+pub const MAX_UPLOAD_CONCURRENCY: usize = 8;
+
 #[derive(Debug)]
 pub struct UploadState {
-    pub queue_tx: mpsc::Sender<UploadQueueItem>,
-    // Store receiver in Mutex<Option<...>> to allow taking it once
-    pub queue_rx: Arc<Mutex<Option<mpsc::Receiver<UploadQueueItem>>>>,
+    // Items waiting to be processed, in processing order
+    pub pending: Arc<PendingQueue>,
     pub is_processing: Arc<AtomicBool>,
     pub cancel_flag: Arc<AtomicBool>,
     pub progress_map: Arc<Mutex<HashMap<Uuid, UploadProgress>>>,
+    // Per-item timeout in seconds, read before each step so changes apply to the next item
+    pub item_timeout_secs: Arc<AtomicU64>,
+    // Number of items processed at once, read when a run starts
+    pub concurrency: Arc<AtomicUsize>,
+    // R2 key templates for new uploads, read per item
+    pub key_templates: Arc<Mutex<KeyTemplates>>,
+    // Set by pause_upload_queue; the worker stops at the next phase boundary
+    pub paused: Arc<AtomicBool>,
+    // Wakes a paused worker on resume or cancel
+    pub resume_notify: Arc<Notify>,
+    // Set by drain_upload_queue; workers finish their current item and take no new ones
+    pub draining: Arc<AtomicBool>,
+    // Idempotency keys of items queued this session, to drop retried submissions
+    pub idempotency_keys: Arc<Mutex<HashSet<String>>>,
+    // Checked for every item before it is queued; loaded from disk at startup
+    pub limits: Arc<Mutex<UploadLimits>>,
+    // Shared by every upload so the configured rate caps all workers together
+    pub bandwidth: Arc<BandwidthLimiter>,
+    // Signalled when the processing task exits, for upload_and_wait
+    pub finish_waiters: Arc<Mutex<Vec<oneshot::Sender<()>>>>,
+    // Transcoded files in the temp directory that queued items still need
+    pub temp_files: Arc<Mutex<HashSet<PathBuf>>>,
+    // Whether archive copies are encrypted before upload; loaded from disk at startup
+    pub encryption: Arc<Mutex<EncryptionSettings>>,
+    // Start time of scheduled items, watched by the schedule timer task
+    pub schedule: Arc<UploadSchedule>,
+    // Whether queues that don't say otherwise upload originals; from settings
+    pub upload_originals: Arc<AtomicBool>,
+    // Whether cover images in an item's folder are used as album artwork; from settings
+    pub folder_art: Arc<AtomicBool>,
+    // Progress of quitting while uploads are in flight
+    pub shutdown: Arc<ShutdownState>,
+    // Items that failed because of their source file, persisted in quarantine.json
+    pub quarantine: Arc<Quarantine>,
+}
+
+impl Default for UploadState {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl UploadState {
-    // Modify constructor to accept receiver
-    pub fn new(tx: mpsc::Sender<UploadQueueItem>, rx: mpsc::Receiver<UploadQueueItem>) -> Self {
+    pub fn new() -> Self {
         Self {
-            queue_tx: tx,
-            queue_rx: Arc::new(Mutex::new(Some(rx))), // Store receiver in Mutex<Option<...>>
+            pending: Arc::new(PendingQueue::default()),
             is_processing: Arc::new(AtomicBool::new(false)),
             cancel_flag: Arc::new(AtomicBool::new(false)),
             progress_map: Arc::new(Mutex::new(HashMap::new())),
+            item_timeout_secs: Arc::new(AtomicU64::new(DEFAULT_ITEM_TIMEOUT_SECS)),
+            concurrency: Arc::new(AtomicUsize::new(DEFAULT_UPLOAD_CONCURRENCY)),
+            key_templates: Arc::new(Mutex::new(KeyTemplates::default())),
+            paused: Arc::new(AtomicBool::new(false)),
+            resume_notify: Arc::new(Notify::new()),
+            draining: Arc::new(AtomicBool::new(false)),
+            idempotency_keys: Arc::new(Mutex::new(HashSet::new())),
+            limits: Arc::new(Mutex::new(UploadLimits::default())),
+            bandwidth: Arc::new(BandwidthLimiter::default()),
+            finish_waiters: Arc::new(Mutex::new(Vec::new())),
+            temp_files: Arc::new(Mutex::new(HashSet::new())),
+            encryption: Arc::new(Mutex::new(EncryptionSettings::default())),
+            schedule: Arc::new(UploadSchedule::default()),
+            upload_originals: Arc::new(AtomicBool::new(true)),
+            folder_art: Arc::new(AtomicBool::new(true)),
+            shutdown: Arc::new(ShutdownState::default()),
+            quarantine: Arc::new(Quarantine::default()),
+        }
+    }
+
+    pub fn queue_state(&self) -> UploadQueueState {
+        if self.paused.load(Ordering::SeqCst) {
+            UploadQueueState::Paused
+        } else if self.is_processing.load(Ordering::SeqCst) {
+            UploadQueueState::Running
+        } else {
+            UploadQueueState::Idle
+        }
+    }
+
+    /// Whether closing the app now would drop uploads in flight.
+    pub fn has_uploads_in_flight(&self) -> bool {
+        self.is_processing.load(Ordering::SeqCst)
+    }
+
+    /// The next item for a worker to process, or None once the queue is empty or
+    /// being drained.
+    fn next_item(&self) -> Option<UploadQueueItem> {
+        if self.draining.load(Ordering::SeqCst) {
+            return None;
+        }
+        self.pending.pop_next()
+    }
+
+    /// Records an idempotency key. Returns false if it was already recorded, this session
+    /// or an earlier one. Keys stay recorded after the upload finishes or fails; retry with
+    /// a new key.
+    pub async fn claim_idempotency_key(&self, key: &str) -> bool {
+        self.idempotency_keys.lock().await.insert(key.to_string())
+    }
+
+    /// Records keys claimed in earlier sessions.
+    pub async fn remember_idempotency_keys(&self, keys: impl IntoIterator<Item = String>) {
+        self.idempotency_keys.lock().await.extend(keys);
+    }
+
+    /// Waits until the queue is resumed or cancelled. Returns immediately if not paused.
+    async fn wait_while_paused(&self) {
+        loop {
+            // Register for the wakeup before checking the flags so a resume in between is not missed
+            let resumed = self.resume_notify.notified();
+            if !self.paused.load(Ordering::SeqCst) || self.cancel_flag.load(Ordering::SeqCst) {
+                return;
+            }
+            resumed.await;
         }
     }
 }
 
-// --- Tauri Commands ---
+/// Aggregate state of the upload queue, as reported by `get_upload_queue_state`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum UploadQueueState {
+    Running,
+    Paused,
+    Idle,
+}
 
-#[command]
-pub async fn start_upload_queue(
-    items: Vec<UploadItemInput>,
-    app_handle: AppHandle<Wry>,
-    upload_state: State<'_, Arc<UploadState>>,
-    r2_state: State<'_, crate::R2State>,
-    mongo_state: State<'_, crate::MongoState>,
-) -> Result<(), String> {
-    info!("Received request to upload {} items.", items.len());
+/// Final outcome of one item, as returned by `upload_and_wait`.
+#[derive(Debug, Clone, Serialize)]
+pub struct UploadResult {
+    pub item_id: Uuid,
+    pub original_path: String,
+    pub status: UploadStatus,
+    pub track_id: Option<String>,
+    /// Where the delivery file was kept, when `keep_transcoded_dir` is set
+    pub local_delivery_path: Option<String>,
+    pub error: Option<String>,
+}
+
+/// Whether an item will change status again.
+fn is_final_status(status: &UploadStatus) -> bool {
+    matches!(
+        status,
+        UploadStatus::Complete | UploadStatus::Cancelled | UploadStatus::Removed | UploadStatus::Skipped | UploadStatus::Error { .. }
+    )
+}
 
-    if r2_state.client.lock().await.is_none() { return Err(UploadError::R2ClientNotInitialized.to_string()); }
+/// Validates and queues items, then starts the processing task unless it is already
+/// running or the items are scheduled for `start_at`. Returns the ids given to the
+/// items, rejected ones included.
+#[allow(clippy::too_many_arguments)]
+async fn enqueue_items(
+    items: Vec<UploadItemInput>,
+    options: Option<UploadOptions>,
+    bucket_override: Option<String>,
+    start_at: Option<DateTime<Utc>>,
+    app_handle: &AppHandle<Wry>,
+    upload_state: &Arc<UploadState>,
+    r2_state: &crate::R2State,
+    mongo_state: &crate::MongoState,
+) -> Result<Vec<Uuid>, String> {
+    let options = options.unwrap_or_default();
+    let formats = options.formats;
+    let upload_originals = options.upload_originals.unwrap_or_else(|| upload_state.upload_originals.load(Ordering::SeqCst));
+    info!(
+        "Received request to upload {} items (archive: {}, delivery: {}).",
+        items.len(), formats.archive.name(), formats.delivery.transcode_format().name()
+    );
+
+    let r2_client = match r2_state.client.lock().await.as_ref() {
+        Some(client) => client.clone(), None => return Err(UploadError::R2ClientNotInitialized.to_string()),
+    };
     if mongo_state.client.lock().await.is_none() { return Err(UploadError::MongoDbClientNotInitialized.to_string()); }
     if items.is_empty() { return Err(UploadError::InvalidInput("No items provided for upload.".to_string()).to_string()); }
+    options.transcoding.validate().map_err(|e| UploadError::InvalidInput(e.to_string()).to_string())?;
+    if let Some(dir) = &options.keep_transcoded_dir {
+        check_keep_dir(dir).map_err(|e| UploadError::InvalidInput(e).to_string())?;
+    }
+    if upload_state.draining.load(Ordering::SeqCst) {
+        return Err(UploadError::InvalidInput("The upload queue is finishing its current uploads; no new items are accepted.".to_string()).to_string());
+    }
+    // Scheduled and immediate items share the pending queue, so they can't be mixed
+    match (start_at, upload_state.schedule.start_at()) {
+        (Some(_), _) if upload_state.is_processing.load(Ordering::SeqCst) => {
+            return Err(UploadError::InvalidInput("Uploads are running; schedule more once they have finished.".to_string()).to_string());
+        }
+        (None, Some(scheduled)) => {
+            return Err(UploadError::InvalidInput(format!(
+                "Uploads are scheduled to start at {}; schedule these items too or cancel the scheduled upload first.", scheduled
+            )).to_string());
+        }
+        _ => {}
+    }
+
+    // Fail the whole batch up front rather than every item mid-upload
+    let bucket_override = bucket_override.map(|b| b.trim().to_string());
+    if let Some(bucket) = &bucket_override {
+        if bucket.is_empty() {
+            return Err(UploadError::InvalidInput("Bucket override must not be empty.".to_string()).to_string());
+        }
+        R2Client::new(r2_client, bucket.clone()).check_bucket_access().await
+            .map_err(|e| UploadError::InvalidInput(format!("Bucket '{}' is not accessible: {}", bucket, e)).to_string())?;
+        info!("Uploading this batch to bucket '{}'.", bucket);
+    }
 
     upload_state.cancel_flag.store(false, Ordering::SeqCst);
     let mut progress_map = upload_state.progress_map.lock().await;
 
+    let mut item_ids = Vec::with_capacity(items.len());
+    // Items rejected because of their file, quarantined once the loop is done
+    let mut file_rejections = Vec::new();
+    // Saved once the loop is done, so a retry after a relaunch is refused too
+    let mut claimed_keys = Vec::new();
     for item_input in items {
         let item_id = Uuid::new_v4();
+        item_ids.push(item_id);
         let input_path = PathBuf::from(&item_input.path);
 
         if !input_path.exists() {
             warn!("Input file does not exist, skipping: {}", item_input.path);
             let progress = UploadProgress {
                 item_id, original_path: item_input.path.clone(),
-                status: UploadStatus::Error("File not found".to_string()),
+                status: UploadStatus::error(ErrorCategory::CorruptInput, "File not found"),
                 error_message: Some("Input file does not exist.".to_string()),
-                title: item_input.metadata.title.clone(), album: item_input.metadata.album.clone(),
+                title: item_input.metadata.title.clone(), album: item_input.metadata.album.clone(), track_id: None, throughput_bytes_per_sec: None, local_delivery_path: None,
             };
             if let Some(window) = app_handle.get_webview_window("main") {
                  // Clone progress before emitting
                  window.emit("upload://status-update", progress.clone()).map_err(|e| e.to_string())?;
             } else { error!("Could not find main window to emit status update."); }
+            file_rejections.push(QuarantinedItem::new(&item_input, &options, bucket_override.as_deref(), progress.error_message.clone().unwrap_or_default()));
+            progress_map.insert(item_id, progress);
+            continue;
+        }
+
+        let limits = *upload_state.limits.lock().await;
+        // The size is checked first so oversized files fail without being read
+        let validation_path = input_path.clone();
+        let validation = tokio::task::spawn_blocking(move || validate_input_file(&validation_path, &limits))
+            .await
+            .unwrap_or_else(|e| Err(InputFileError::Invalid(format!("Validation task failed: {}", e))));
+        if let Err(rejection) = validation {
+            warn!("Rejecting {}: {}", item_input.path, rejection);
+            let (category, status_message) = rejection.status();
+            let progress = UploadProgress {
+                item_id, original_path: item_input.path.clone(),
+                status: UploadStatus::error(category, status_message),
+                error_message: Some(rejection.to_string()),
+                title: item_input.metadata.title.clone(), album: item_input.metadata.album.clone(), track_id: None, throughput_bytes_per_sec: None, local_delivery_path: None,
+            };
+            if let Some(window) = app_handle.get_webview_window("main") {
+                 window.emit("upload://status-update", progress.clone()).map_err(|e| e.to_string())?;
+            } else { error!("Could not find main window to emit status update."); }
+            file_rejections.push(QuarantinedItem::new(&item_input, &options, bucket_override.as_deref(), progress.error_message.clone().unwrap_or_default()));
+            progress_map.insert(item_id, progress);
+            continue;
+        }
+
+        if options.require_complete_metadata {
+            let missing = missing_required_fields(&item_input.metadata);
+            if !missing.is_empty() {
+                let message = format!("Missing required metadata: {}", missing.join(", "));
+                warn!("Rejecting {}: {}", item_input.path, message);
+                let progress = UploadProgress {
+                    item_id, original_path: item_input.path.clone(),
+                    status: UploadStatus::error(ErrorCategory::Metadata, "Incomplete metadata"),
+                    error_message: Some(message),
+                    title: item_input.metadata.title.clone(), album: item_input.metadata.album.clone(), track_id: None, throughput_bytes_per_sec: None, local_delivery_path: None,
+                };
+                if let Some(window) = app_handle.get_webview_window("main") {
+                     window.emit("upload://status-update", progress.clone()).map_err(|e| e.to_string())?;
+                } else { error!("Could not find main window to emit status update."); }
+                progress_map.insert(item_id, progress);
+                continue;
+            }
+        }
+
+        if let Some(Err(message)) = item_input.metadata.isrc.as_deref().filter(|isrc| !isrc.trim().is_empty()).map(normalize_isrc) {
+            warn!("Rejecting {}: {}", item_input.path, message);
+            let progress = UploadProgress {
+                item_id, original_path: item_input.path.clone(),
+                status: UploadStatus::error(ErrorCategory::Metadata, "Invalid ISRC"),
+                error_message: Some(message),
+                title: item_input.metadata.title.clone(), album: item_input.metadata.album.clone(), track_id: None, throughput_bytes_per_sec: None, local_delivery_path: None,
+            };
+            if let Some(window) = app_handle.get_webview_window("main") {
+                 window.emit("upload://status-update", progress.clone()).map_err(|e| e.to_string())?;
+            } else { error!("Could not find main window to emit status update."); }
+            progress_map.insert(item_id, progress);
+            continue;
+        }
+
+        if let Some(Err(message)) = item_input.metadata.custom_fields.as_ref().map(validate_custom_fields) {
+            warn!("Rejecting {}: {}", item_input.path, message);
+            let progress = UploadProgress {
+                item_id, original_path: item_input.path.clone(),
+                status: UploadStatus::error(ErrorCategory::Metadata, "Invalid custom fields"),
+                error_message: Some(message),
+                title: item_input.metadata.title.clone(), album: item_input.metadata.album.clone(), track_id: None, throughput_bytes_per_sec: None, local_delivery_path: None,
+            };
+            if let Some(window) = app_handle.get_webview_window("main") {
+                 window.emit("upload://status-update", progress.clone()).map_err(|e| e.to_string())?;
+            } else { error!("Could not find main window to emit status update."); }
             progress_map.insert(item_id, progress);
             continue;
         }
 
-        let queue_item = UploadQueueItem {
-            id: item_id, input_path: input_path.clone(), metadata: item_input.metadata.clone(),
-            temp_aac_path: None, r2_original_key: None, r2_aac_key: None, db_track_id: None,
-        };
+        if let Err(message) = explicit_album_id(options.album_matching, &item_input.metadata) {
+            warn!("Rejecting {}: {}", item_input.path, message);
+            let progress = UploadProgress {
+                item_id, original_path: item_input.path.clone(),
+                status: UploadStatus::error(ErrorCategory::Metadata, "Invalid album ID"),
+                error_message: Some(message),
+                title: item_input.metadata.title.clone(), album: item_input.metadata.album.clone(), track_id: None, throughput_bytes_per_sec: None, local_delivery_path: None,
+            };
+            if let Some(window) = app_handle.get_webview_window("main") {
+                 window.emit("upload://status-update", progress.clone()).map_err(|e| e.to_string())?;
+            } else { error!("Could not find main window to emit status update."); }
+            progress_map.insert(item_id, progress);
+            continue;
+        }
+
+        if let Some(key) = &item_input.idempotency_key {
+            if upload_state.claim_idempotency_key(key).await {
+                claimed_keys.push(key.clone());
+            } else {
+                info!("Skipping {}: idempotency key '{}' was already queued", item_input.path, key);
+                let progress = UploadProgress {
+                    item_id, original_path: item_input.path.clone(),
+                    status: UploadStatus::Skipped,
+                    error_message: Some(format!("Duplicate submission (idempotency key '{}')", key)),
+                    title: item_input.metadata.title.clone(), album: item_input.metadata.album.clone(), track_id: None, throughput_bytes_per_sec: None, local_delivery_path: None,
+                };
+                if let Some(window) = app_handle.get_webview_window("main") {
+                     window.emit("upload://status-update", progress.clone()).map_err(|e| e.to_string())?;
+                } else { error!("Could not find main window to emit status update."); }
+                progress_map.insert(item_id, progress);
+                continue;
+            }
+        }
+
+        let queue_item = UploadQueueItem {
+            id: item_id, input_path: input_path.clone(), metadata: item_input.metadata.clone(),
+            temp_delivery_path: None, temp_archive_path: None, temp_encrypted_path: None, encryption_key_id: None, r2_archive_key: None, r2_delivery_key: None, db_track_id: None,
+            on_key_collision: options.on_key_collision, formats, transcoding: options.transcoding, bucket_override: bucket_override.clone(),
+            visibility: options.visibility, album_matching: options.album_matching, remote_source: None,
+            upload_originals, original_sha256: None, staging: options.staging, keep_transcoded_dir: options.keep_transcoded_dir.clone(),
+            idempotency_key: item_input.idempotency_key.clone(),
+        };
+
+        upload_state.pending.push(queue_item);
+        let status = if start_at.is_some() {
+            UploadStatus::Scheduled
+        } else if upload_state.paused.load(Ordering::SeqCst) {
+            UploadStatus::Paused
+        } else {
+            UploadStatus::Pending
+        };
+        let progress = UploadProgress {
+            item_id, original_path: item_input.path, status,
+            error_message: None, title: item_input.metadata.title, album: item_input.metadata.album, track_id: None, throughput_bytes_per_sec: None, local_delivery_path: None,
+        };
+        if let Some(window) = app_handle.get_webview_window("main") {
+             // Clone progress before emitting
+             window.emit("upload://status-update", progress.clone()).map_err(|e| e.to_string())?;
+        } else { error!("Could not find main window to emit status update."); }
+        progress_map.insert(item_id, progress);
+    }
+    drop(progress_map);
+    shutdown::save_idempotency_keys(app_handle, claimed_keys);
+    quarantine_items(app_handle, upload_state, file_rejections).await;
+
+    if start_at.is_none() {
+        spawn_processing(app_handle, upload_state);
+    }
+    Ok(item_ids)
+}
+
+/// Starts the processing task unless it is already running.
+fn spawn_processing(app_handle: &AppHandle<Wry>, upload_state: &Arc<UploadState>) {
+    if !upload_state.is_processing.compare_exchange(false, true, Ordering::SeqCst, Ordering::SeqCst).is_err() {
+        info!("Spawning upload processing task.");
+        let state_clone = Arc::clone(upload_state);
+        let app_handle_clone = app_handle.clone();
+
+        tauri::async_runtime::spawn(async move {
+            loop {
+                process_upload_queue(app_handle_clone.clone(), state_clone.clone()).await;
+                state_clone.is_processing.store(false, Ordering::SeqCst);
+                // Items queued after the worker saw an empty queue would otherwise be stranded
+                if state_clone.pending.is_empty()
+                    || state_clone.cancel_flag.load(Ordering::SeqCst)
+                    || state_clone.draining.load(Ordering::SeqCst)
+                    || state_clone.is_processing.compare_exchange(false, true, Ordering::SeqCst, Ordering::SeqCst).is_err()
+                {
+                    break;
+                }
+            }
+            info!("Upload processing task finished.");
+            match temp_storage::temp_dir(&app_handle_clone) {
+                Ok(dir) => release_temp_files(&state_clone, &dir).await,
+                Err(e) => warn!("{}", e),
+            }
+            for waiter in state_clone.finish_waiters.lock().await.drain(..) {
+                let _ = waiter.send(());
+            }
+            if let Some(window) = app_handle_clone.get_webview_window("main") {
+                 window.emit("upload://queue-finished", ()).unwrap_or_else(|e| {
+                     error!("Failed to emit queue-finished event: {}", e);
+                 });
+            } else { error!("Could not find main window to emit queue-finished event."); }
+        });
+    } else {
+        info!("Upload processing task already running.");
+    }
+}
+
+// --- Tauri Commands ---
+
+/// Queues items and starts processing them, or with `start_at`, schedules them as
+/// `schedule::schedule_upload_queue` does.
+#[command]
+#[allow(clippy::too_many_arguments)]
+pub async fn start_upload_queue(
+    items: Vec<UploadItemInput>,
+    options: Option<UploadOptions>,
+    bucket_override: Option<String>,
+    start_at: Option<DateTime<Utc>>,
+    app_handle: AppHandle<Wry>,
+    upload_state: State<'_, Arc<UploadState>>,
+    r2_state: State<'_, crate::R2State>,
+    mongo_state: State<'_, crate::MongoState>,
+) -> Result<(), String> {
+    match start_at {
+        Some(start_at) => schedule::schedule_items(items, options, bucket_override, start_at, &app_handle, &upload_state, &r2_state, &mongo_state).await?,
+        None => enqueue_items(items, options, bucket_override, None, &app_handle, &upload_state, &r2_state, &mongo_state).await?,
+    };
+    Ok(())
+}
+
+/// Queues items like `start_upload_queue`, then waits for every one of them to finish
+/// and returns their outcomes in input order. Progress events are still emitted. A
+/// paused queue keeps this waiting until it is resumed or cancelled.
+#[command]
+pub async fn upload_and_wait(
+    items: Vec<UploadItemInput>,
+    options: Option<UploadOptions>,
+    bucket_override: Option<String>,
+    app_handle: AppHandle<Wry>,
+    upload_state: State<'_, Arc<UploadState>>,
+    r2_state: State<'_, crate::R2State>,
+    mongo_state: State<'_, crate::MongoState>,
+) -> Result<Vec<UploadResult>, String> {
+    let item_ids = enqueue_items(items, options, bucket_override, None, &app_handle, &upload_state, &r2_state, &mongo_state).await?;
+
+    loop {
+        // Register before checking so a worker exiting in between still wakes us
+        let (finished_tx, finished_rx) = oneshot::channel();
+        upload_state.finish_waiters.lock().await.push(finished_tx);
+        let all_final = {
+            let progress_map = upload_state.progress_map.lock().await;
+            item_ids.iter().all(|id| progress_map.get(id).map_or(true, |progress| is_final_status(&progress.status)))
+        };
+        let worker_gone = !upload_state.is_processing.load(Ordering::SeqCst) && upload_state.pending.is_empty();
+        if all_final || worker_gone {
+            break;
+        }
+        let _ = finished_rx.await;
+    }
+
+    let progress_map = upload_state.progress_map.lock().await;
+    Ok(item_ids.iter().filter_map(|id| progress_map.get(id)).map(|progress| UploadResult {
+        item_id: progress.item_id,
+        original_path: progress.original_path.clone(),
+        status: progress.status.clone(),
+        track_id: progress.track_id.clone(),
+        local_delivery_path: progress.local_delivery_path.clone(),
+        error: progress.error_message.clone().or_else(|| match &progress.status {
+            UploadStatus::Error { message, .. } => Some(message.clone()),
+            _ => None,
+        }),
+    }).collect())
+}
+
+#[command]
+pub async fn cancel_upload_queue(app_handle: AppHandle<Wry>, upload_state: State<'_, Arc<UploadState>>) -> Result<(), String> {
+    info!("Received request to cancel upload queue.");
+    upload_state.cancel_flag.store(true, Ordering::SeqCst);
+    // A paused worker wakes up, sees the cancel flag, and cleans up as usual
+    if upload_state.paused.swap(false, Ordering::SeqCst) {
+        replace_status(&app_handle, &upload_state.progress_map, &UploadStatus::Paused, UploadStatus::Cancelled).await;
+    }
+    upload_state.resume_notify.notify_waiters();
+    Ok(())
+}
+
+/// Lets the items being processed finish without starting any more, then resolves once
+/// the queue is idle and emits `upload://drained`. Unlike `cancel_upload_queue`, nothing
+/// in flight is aborted; items that hadn't started are cancelled, while scheduled
+/// uploads stay scheduled for the next launch. With `exit_when_drained` the app quits
+/// afterwards; closing the window mid-batch does the same but saves the waiting items
+/// (see `shutdown`).
+#[command]
+pub async fn drain_upload_queue(
+    exit_when_drained: Option<bool>,
+    app_handle: AppHandle<Wry>,
+    upload_state: State<'_, Arc<UploadState>>,
+) -> Result<(), String> {
+    info!("Received request to drain upload queue.");
+    upload_state.draining.store(true, Ordering::SeqCst);
+    // A paused item in flight has to finish too; paused items that haven't started are cancelled below
+    upload_state.paused.store(false, Ordering::SeqCst);
+    upload_state.resume_notify.notify_waiters();
+
+    loop {
+        // Register before checking so a worker exiting in between still wakes us
+        let (finished_tx, finished_rx) = oneshot::channel();
+        upload_state.finish_waiters.lock().await.push(finished_tx);
+        if !upload_state.is_processing.load(Ordering::SeqCst) {
+            break;
+        }
+        let _ = finished_rx.await;
+    }
+
+    if upload_state.schedule.start_at().is_none() {
+        for item in upload_state.pending.drain() {
+            let original_path_str = item.input_path.to_string_lossy().to_string();
+            update_progress(&app_handle, &upload_state.progress_map, item.id, UploadStatus::Cancelled, None, &item.metadata, &original_path_str).await;
+        }
+    }
+    upload_state.draining.store(false, Ordering::SeqCst);
+    info!("Upload queue drained.");
+    if let Some(window) = app_handle.get_webview_window("main") {
+         window.emit("upload://drained", ()).unwrap_or_else(|e| {
+             error!("Failed to emit drained event: {}", e);
+         });
+    } else { error!("Could not find main window to emit drained event."); }
+
+    if exit_when_drained.unwrap_or(false) {
+        info!("Exiting after draining the upload queue.");
+        app_handle.exit(0);
+    }
+    Ok(())
+}
+
+/// Pauses the queue. The item in flight finishes its current phase, then waits;
+/// nothing is dropped or cleaned up.
+#[command]
+pub async fn pause_upload_queue(app_handle: AppHandle<Wry>, upload_state: State<'_, Arc<UploadState>>) -> Result<UploadQueueState, String> {
+    info!("Received request to pause upload queue.");
+    upload_state.paused.store(true, Ordering::SeqCst);
+    replace_status(&app_handle, &upload_state.progress_map, &UploadStatus::Pending, UploadStatus::Paused).await;
+    Ok(upload_state.queue_state())
+}
+
+#[command]
+pub async fn resume_upload_queue(app_handle: AppHandle<Wry>, upload_state: State<'_, Arc<UploadState>>) -> Result<UploadQueueState, String> {
+    info!("Received request to resume upload queue.");
+    upload_state.paused.store(false, Ordering::SeqCst);
+    upload_state.resume_notify.notify_waiters();
+    replace_status(&app_handle, &upload_state.progress_map, &UploadStatus::Paused, UploadStatus::Pending).await;
+    Ok(upload_state.queue_state())
+}
+
+#[command]
+pub async fn get_upload_queue_state(upload_state: State<'_, Arc<UploadState>>) -> Result<UploadQueueState, String> {
+    Ok(upload_state.queue_state())
+}
+
+/// Lists items that are queued but not yet being processed, in processing order.
+#[command]
+pub async fn get_pending_uploads(upload_state: State<'_, Arc<UploadState>>) -> Result<Vec<PendingUpload>, String> {
+    Ok(upload_state.pending.snapshot())
+}
+
+/// Takes a queued item out of the queue before it is processed.
+#[command]
+pub async fn remove_pending_upload(
+    item_id: Uuid,
+    app_handle: AppHandle<Wry>,
+    upload_state: State<'_, Arc<UploadState>>,
+) -> Result<(), String> {
+    let item = upload_state.pending.remove(item_id).map_err(|e| match e {
+        RemoveError::InProgress => UploadError::InvalidInput(format!(
+            "Upload {} is already being processed; cancel the queue to stop it", item_id
+        )).to_string(),
+        RemoveError::NotFound => UploadError::InvalidInput(format!("Upload {} is not in the pending queue", item_id)).to_string(),
+    })?;
+    info!("Removed pending upload {} ({})", item_id, item.input_path.display());
+    if upload_state.schedule.start_at().is_some() {
+        schedule::forget_item(&app_handle, &upload_state, item_id);
+    }
+    let original_path = item.input_path.to_string_lossy();
+    update_progress(&app_handle, &upload_state.progress_map, item_id, UploadStatus::Removed, None, &item.metadata, &original_path).await;
+    Ok(())
+}
+
+/// Moves the given pending items to the front of the queue in the given order. Items not
+/// listed keep their order behind them; ids that are no longer pending are ignored.
+#[command]
+pub async fn reorder_pending_uploads(
+    ordered_ids: Vec<Uuid>,
+    upload_state: State<'_, Arc<UploadState>>,
+) -> Result<Vec<PendingUpload>, String> {
+    upload_state.pending.reorder(&ordered_ids);
+    Ok(upload_state.pending.snapshot())
+}
+
+/// Sets the per-item timeout used for transcoding and R2 uploads, saved as
+/// `upload.item_timeout_secs` in the settings.
+#[command]
+pub async fn set_upload_timeout(timeout_secs: u64, app_handle: AppHandle<Wry>) -> Result<(), String> {
+    if timeout_secs == 0 { return Err(UploadError::InvalidInput("Timeout must be at least 1 second.".to_string()).to_string()); }
+    info!("Setting upload item timeout to {}s.", timeout_secs);
+    patch_settings(&app_handle, &json!({ "upload": { "item_timeout_secs": timeout_secs } })).await.map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+/// Sets how many items are processed at once, saved as `upload.concurrency` in the
+/// settings. Takes effect when the next run starts.
+#[command]
+pub async fn set_upload_concurrency(concurrency: usize, app_handle: AppHandle<Wry>) -> Result<(), String> {
+    if !(1..=MAX_UPLOAD_CONCURRENCY).contains(&concurrency) {
+        return Err(UploadError::InvalidInput(format!("Concurrency must be between 1 and {}.", MAX_UPLOAD_CONCURRENCY)).to_string());
+    }
+    info!("Setting upload concurrency to {}.", concurrency);
+    patch_settings(&app_handle, &json!({ "upload": { "concurrency": concurrency } })).await.map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+/// Sets the R2 key templates used for new uploads. Omitted templates are left unchanged.
+#[command]
+pub async fn set_upload_key_templates(
+    archive_template: Option<String>,
+    delivery_template: Option<String>,
+    upload_state: State<'_, Arc<UploadState>>,
+) -> Result<KeyTemplates, String> {
+    for template in archive_template.iter().chain(delivery_template.iter()) {
+        validate_template(template).map_err(|e| UploadError::InvalidInput(e).to_string())?;
+    }
+    let mut templates = upload_state.key_templates.lock().await;
+    if let Some(template) = archive_template { templates.archive = template; }
+    if let Some(template) = delivery_template { templates.delivery = template; }
+    info!("Upload key templates set to archive='{}', delivery='{}'.", templates.archive, templates.delivery);
+    Ok(templates.clone())
+}
+
+// --- Core Processing Logic ---
+
+async fn process_upload_queue(
+    app_handle: AppHandle<Wry>,
+    state: Arc<UploadState>,
+) {
+    let progress_map = Arc::clone(&state.progress_map);
+    let cancel_flag = Arc::clone(&state.cancel_flag);
+
+    // --- Get Clients from App State ---
+    let r2_state = match app_handle.try_state::<crate::R2State>() {
+         Some(state) => state, None => { error!("R2State not found."); return; }
+    };
+    let mongo_state = match app_handle.try_state::<crate::MongoState>() {
+         Some(state) => state, None => { error!("MongoState not found."); return; }
+    };
+    let r2_client_opt = r2_state.client.lock().await;
+    let mongo_client_opt = mongo_state.client.lock().await;
+    let r2_client = match r2_client_opt.as_ref() {
+        Some(client) => client, None => { error!("R2 client not initialized."); return; }
+    };
+    let mongo_client = match mongo_client_opt.as_ref() {
+        Some(client) => client, None => { error!("MongoDB client not initialized."); return; }
+    };
+    let bucket_name_opt = r2_state.bucket_name.lock().await;
+    let default_bucket_name = match bucket_name_opt.as_deref() {
+        Some(name) => name.to_string(), None => { error!("R2 bucket name not found in state."); return; }
+    };
+    drop(bucket_name_opt); // Drop lock
+    let temp_dir = match temp_storage::temp_dir(&app_handle) {
+        Ok(dir) => dir, Err(e) => { error!("{}", e); return; }
+    };
+    let use_transactions = supports_transactions(mongo_client).await;
+    if !use_transactions {
+        warn!("MongoDB deployment does not support transactions (standalone server); albums and tracks are written separately");
+    }
+    // Items fail rather than fall back to unencrypted uploads when the key can't be loaded
+    let encryption_key = if state.encryption.lock().await.encrypt_originals {
+        Some(get_or_create_originals_key().map_err(|e| e.to_string()))
+    } else {
+        None
+    };
+
+    // Items queued after this point are processed by this run but not counted in its progress
+    let batch_ids = state.pending.ids();
+    let concurrency = state.concurrency.load(Ordering::SeqCst).clamp(1, MAX_UPLOAD_CONCURRENCY);
+    info!("Processing upload queue with {} worker{}", concurrency, if concurrency == 1 { "" } else { "s" });
+    let ctx = WorkerContext {
+        app_handle: &app_handle,
+        state: &state,
+        r2_client,
+        mongo_client,
+        default_bucket_name,
+        temp_dir,
+        use_transactions,
+        encryption_key,
+        batch_ids,
+    };
+    // Workers share this task; transcoding and analysis run on blocking threads
+    let workers = (0..concurrency).map(|_| run_worker(&ctx));
+    // Track ids stored during this run, for the activity feed
+    let uploaded_track_ids: Vec<String> = futures::future::join_all(workers).await.into_iter().flatten().collect();
+
+    // A cancel stops the workers mid-queue; whatever is still waiting is cancelled too
+    if cancel_flag.load(Ordering::SeqCst) {
+        for item in state.pending.drain() {
+            let original_path_str = item.input_path.to_string_lossy().to_string();
+            update_progress(&app_handle, &progress_map, item.id, UploadStatus::Cancelled, None, &item.metadata, &original_path_str).await;
+        }
+        emit_batch_progress(&ctx).await;
+    }
+
+    if !uploaded_track_ids.is_empty() {
+        let summary = format!("Uploaded {} track{}", uploaded_track_ids.len(), if uploaded_track_ids.len() == 1 { "" } else { "s" });
+        let db = mongo_client.catalog_database();
+        record_activity(&app_handle, Some(&db), ActivityEntry::new(ActivityAction::TracksUploaded, uploaded_track_ids, summary)).await;
+    }
+} // End process_upload_queue
+
+/// What every worker of an upload run shares.
+struct WorkerContext<'a> {
+    app_handle: &'a AppHandle<Wry>,
+    state: &'a UploadState,
+    r2_client: &'a S3Client,
+    mongo_client: &'a MongoDbClient,
+    default_bucket_name: String,
+    temp_dir: PathBuf,
+    use_transactions: bool,
+    // Set when originals are encrypted; items fail rather than upload unencrypted if the key is missing
+    encryption_key: Option<Result<[u8; KEY_LEN], String>>,
+    /// Items pending when the run started, for `upload://batch-progress`
+    batch_ids: Vec<Uuid>,
+}
+
+/// How processing an item ended.
+enum ItemOutcome {
+    Completed(String),
+    Failed,
+    /// The run was cancelled; the worker stops
+    Cancelled,
+}
+
+/// Takes items off the pending queue until it is empty, drained or the run is
+/// cancelled, and returns the ids of the tracks it stored.
+async fn run_worker(ctx: &WorkerContext<'_>) -> Vec<String> {
+    let mut uploaded_track_ids = Vec::new();
+    while let Some(item) = ctx.state.next_item() {
+        let item_id = item.id;
+        let queued_input = item.queued_input();
+        let outcome = process_item(ctx, item).await;
+        ctx.state.pending.finish(item_id);
+        emit_batch_progress(ctx).await;
+        match outcome {
+            ItemOutcome::Completed(track_id) => uploaded_track_ids.push(track_id),
+            ItemOutcome::Failed => {
+                let error = ctx.state.progress_map.lock().await.get(&item_id).and_then(file_error);
+                if let (Some(error), Some((input, options, bucket_override))) = (error, queued_input) {
+                    quarantine_items(ctx.app_handle, ctx.state, vec![QuarantinedItem::new(&input, &options, bucket_override.as_deref(), error)]).await;
+                }
+            }
+            ItemOutcome::Cancelled => break,
+        }
+    }
+    uploaded_track_ids
+}
+
+/// Counts how many of `batch_ids` completed, failed or otherwise finished.
+fn batch_progress(progress_map: &HashMap<Uuid, UploadProgress>, batch_ids: &[Uuid]) -> BatchProgress {
+    let statuses: Vec<&UploadStatus> = batch_ids.iter().filter_map(|id| progress_map.get(id)).map(|progress| &progress.status).collect();
+    let finished = statuses.iter().filter(|status| is_final_status(status)).count();
+    let total = batch_ids.len();
+    BatchProgress {
+        completed: statuses.iter().filter(|status| matches!(status, UploadStatus::Complete)).count(),
+        failed: statuses.iter().filter(|status| matches!(status, UploadStatus::Error { .. })).count(),
+        total,
+        percent: if total == 0 { 100.0 } else { finished as f64 * 100.0 / total as f64 },
+    }
+}
+
+/// Emits the run's overall progress as `upload://batch-progress`.
+async fn emit_batch_progress(ctx: &WorkerContext<'_>) {
+    let progress = batch_progress(&ctx.state.progress_map.lock().await, &ctx.batch_ids);
+    if let Some(window) = ctx.app_handle.get_webview_window("main") {
+        window.emit("upload://batch-progress", progress).unwrap_or_else(|e| {
+            error!("Failed to emit batch progress: {}", e);
+        });
+    }
+}
+
+/// Transcodes, uploads and stores one item, emitting its progress. Whatever the item
+/// left in R2, MongoDB or the temp directory is cleaned up unless it completes.
+async fn process_item(ctx: &WorkerContext<'_>, mut item: UploadQueueItem) -> ItemOutcome {
+    let (app_handle, state, r2_client, mongo_client) = (ctx.app_handle, ctx.state, ctx.r2_client, ctx.mongo_client);
+    let (default_bucket_name, temp_dir, encryption_key) = (&ctx.default_bucket_name, &ctx.temp_dir, &ctx.encryption_key);
+    let (progress_map, cancel_flag) = (&state.progress_map, &state.cancel_flag);
+    let item_id = item.id;
+    let original_path_str = item.input_path.to_string_lossy().to_string();
+    info!("Processing item: {} ({})", original_path_str, item_id);
+    let mut current_status = UploadStatus::Pending;
+    // Uploads and cleanup for this item both go to its batch's bucket
+    let bucket_name = item.bucket_override.clone().unwrap_or_else(|| default_bucket_name.clone());
+
+    pause_point(&app_handle, &state, item_id, &item.metadata, &original_path_str).await;
+
+    // Check for cancellation before starting work
+    if cancel_flag.load(Ordering::SeqCst) {
+        info!("Cancellation detected before processing item {}", item_id);
+        current_status = UploadStatus::Cancelled;
+        update_progress(&app_handle, &progress_map, item_id, current_status.clone(), None, &item.metadata, &original_path_str).await;
+        return ItemOutcome::Cancelled;
+    }
+
+    // --- Transcoding ---
+    current_status = UploadStatus::Transcoding;
+    update_progress(&app_handle, &progress_map, item_id, current_status.clone(), None, &item.metadata, &original_path_str).await;
+
+    let item_timeout = Duration::from_secs(state.item_timeout_secs.load(Ordering::SeqCst));
+    let delivery_format = item.formats.delivery.transcode_format();
+    let transcoding_result = async {
+        let delivery_path = run_transcoding(&item.input_path, delivery_format, item.transcoding, item_timeout, &state, &temp_dir).await?;
+        if item.formats.archive != ArchiveFormat::Flac || !item.upload_originals {
+            return Ok((delivery_path, None));
+        }
+        match run_transcoding(&item.input_path, TranscodeFormat::Flac, TranscodingOptions::default(), item_timeout, &state, &temp_dir).await {
+            Ok(archive_path) => Ok((delivery_path, Some(archive_path))),
+            Err(e) => { cleanup_temp_file(&delivery_path); Err(e) }
+        }
+    }.await;
+
+    pause_point(&app_handle, &state, item_id, &item.metadata, &original_path_str).await;
+
+    if cancel_flag.load(Ordering::SeqCst) {
+        info!("Cancellation detected after transcoding attempt for item {}", item_id);
+        current_status = UploadStatus::Cancelled;
+        update_progress(&app_handle, &progress_map, item_id, current_status.clone(), None, &item.metadata, &original_path_str).await;
+        if let Ok((ref delivery_path, ref archive_path)) = transcoding_result {
+            cleanup_temp_file(delivery_path);
+            if let Some(path) = archive_path { cleanup_temp_file(path); }
+        }
+        return ItemOutcome::Cancelled;
+    }
+
+    match transcoding_result {
+        Ok((temp_delivery_path, temp_archive_path)) => {
+            item.temp_delivery_path = Some(temp_delivery_path);
+            item.temp_archive_path = temp_archive_path;
+        }
+        Err(e) => {
+            error!("Transcoding failed for {}: {}", original_path_str, e);
+            let category = transcoding_error_category(&e);
+            current_status = if matches!(e, TranscodingError::TimedOut { .. }) {
+                UploadStatus::error(category, "timed out")
+            } else {
+                UploadStatus::error(category, format!("Transcoding failed: {}", e))
+            };
+            update_progress(&app_handle, &progress_map, item_id, current_status.clone(), Some(e.to_string()), &item.metadata, &original_path_str).await;
+            perform_cleanup(r2_client, &bucket_name, mongo_client, &item).await;
+            return ItemOutcome::Failed;
+        }
+    };
+    // --- Analyze Tempo, Key and Loudness ---
+    let features = analyze_features(&item.input_path).await;
+    // The source is the original, which the ReplayGain backfill measures too
+    let loudness = analyze_loudness(&item.input_path, item_timeout).await;
+
+    let delivery_path_ref = item.temp_delivery_path.clone();
+    // The archive is either the FLAC transcode or the uploaded file itself
+    let archive_path = item.temp_archive_path.clone().unwrap_or_else(|| item.input_path.clone());
+
+    // --- Generate R2 Keys ---
+    // The track id is assigned up front so it can be part of the keys
+    let track_oid = ObjectId::new();
+    let track_id_hex = track_oid.to_hex();
+    let key_templates = state.key_templates.lock().await.clone();
+    let file_name = item.input_path.file_name().unwrap_or_default().to_string_lossy().into_owned();
+    let archive_file_name = match item.formats.archive {
+        ArchiveFormat::Original => file_name.clone(),
+        ArchiveFormat::Flac => Path::new(&file_name).with_extension(TranscodeFormat::Flac.extension()).to_string_lossy().into_owned(),
+    };
+    let delivery_file_name = Path::new(&file_name).with_extension(delivery_format.extension()).to_string_lossy().into_owned();
+    let album = item.metadata.album.clone().unwrap_or_else(|| "Unknown Album".to_string());
+    let artist = item.metadata.artist.clone().unwrap_or_else(|| "Unknown Artist".to_string());
+    let key_context = |file_name, format| KeyContext { album: &album, artist: &artist, track_id: &track_id_hex, file_name, format };
+    let exists_checker = R2Client::new(r2_client.clone(), bucket_name.clone());
+    let exists = |key: String| {
+        let checker = &exists_checker;
+        async move { checker.object_exists(&key).await.map_err(|e| e.to_string()) }
+    };
+    let render = |template: &str, context: &KeyContext| {
+        let key = render_key(template, context);
+        if item.staging { staged_key(&key) } else { key }
+    };
+    let key_result = async {
+        let archive_context = key_context(&archive_file_name, item.formats.archive.name());
+        let archive_key = resolve_collision(render(&key_templates.archive, &archive_context), item.on_key_collision, &exists).await?;
+        let delivery_context = key_context(&delivery_file_name, delivery_format.name());
+        let delivery_key = resolve_collision(render(&key_templates.delivery, &delivery_context), item.on_key_collision, &exists).await?;
+        if archive_key == delivery_key {
+            return Err(UploadError::InvalidInput(format!("Archive and delivery copies would share the key {}", archive_key)));
+        }
+        Ok::<_, UploadError>((archive_key, delivery_key))
+    }.await;
+    let (mut archive_key, mut delivery_key) = match key_result {
+        Ok(keys) => keys,
+        Err(e) => {
+            error!("Failed to generate R2 keys for {}: {}", original_path_str, e);
+            current_status = upload_error_status("Key generation failed", &e);
+            update_progress(&app_handle, &progress_map, item_id, current_status.clone(), Some(e.to_string()), &item.metadata, &original_path_str).await;
+            perform_cleanup(r2_client, &bucket_name, mongo_client, &item).await;
+            return ItemOutcome::Failed;
+        }
+    };
+
+    // --- Upload Archive ---
+    // An original kept locally is only hashed, so it can be matched to the track later
+    let upload_archive_res = if item.upload_originals {
+        current_status = UploadStatus::UploadingOriginal;
+        update_progress(&app_handle, &progress_map, item_id, current_status.clone(), None, &item.metadata, &original_path_str).await;
+        match encrypt_archive(&archive_path, encryption_key.as_ref(), &state, &temp_dir).await {
+            Ok(encrypted) => {
+                let (upload_path, archive_mime) = match &encrypted {
+                    Some((path, _)) => (path.clone(), ENCRYPTED_MIME_TYPE.to_string()),
+                    None => (archive_path.clone(), item.formats.archive.mime_type(&item.input_path)),
+                };
+                if let Some((path, key_id)) = encrypted {
+                    item.temp_encrypted_path = Some(path);
+                    item.encryption_key_id = Some(key_id);
+                }
+                let sent = Arc::new(AtomicU64::new(0));
+                let (sent, upload_path, bucket, archive_mime) = (&sent, &upload_path, &bucket_name, &archive_mime);
+                let if_absent = item.on_key_collision.put_if_absent();
+                let (key, result) = upload_with_policy(archive_key.clone(), item.on_key_collision, |key: String| async move {
+                    let upload = upload_file_to_r2(r2_client, &state.bandwidth, sent, upload_path, bucket, &key, archive_mime, if_absent);
+                    with_item_timeout(item_timeout, with_throughput_updates(&app_handle, &progress_map, item_id, sent, upload)).await
+                })
+                .await;
+                record_uploaded_key(&mut item.r2_archive_key, &key, &result);
+                archive_key = key;
+                result
+            }
+            Err(e) => Err(e),
+        }
+    } else {
+        hash_original(&item.input_path).await.map(|hash| item.original_sha256 = Some(hash))
+    };
+
+    pause_point(&app_handle, &state, item_id, &item.metadata, &original_path_str).await;
+
+    if cancel_flag.load(Ordering::SeqCst) {
+        info!("Cancellation detected after archive upload for item {}", item_id);
+        current_status = UploadStatus::Cancelled;
+        update_progress(&app_handle, &progress_map, item_id, current_status.clone(), None, &item.metadata, &original_path_str).await;
+        perform_cleanup(r2_client, &bucket_name, mongo_client, &item).await;
+        return ItemOutcome::Cancelled;
+    }
+
+    if let Err(e) = upload_archive_res {
+         error!("Archive upload failed for {}: {}", original_path_str, e);
+         current_status = upload_error_status("Archive upload failed", &e);
+         update_progress(&app_handle, &progress_map, item_id, current_status.clone(), Some(e.to_string()), &item.metadata, &original_path_str).await;
+         perform_cleanup(r2_client, &bucket_name, mongo_client, &item).await; // Cleanup archive R2 + temp files
+         return ItemOutcome::Failed;
+    }
+    if item.upload_originals {
+        info!("Archive upload successful for {}: {}", original_path_str, archive_key);
+    } else {
+        info!("Keeping the original of {} local; only the delivery copy is uploaded", original_path_str);
+    }
+
+    // --- Upload Delivery ---
+    if let Some(delivery_path) = delivery_path_ref.as_deref() {
+        current_status = UploadStatus::UploadingAAC;
+        update_progress(&app_handle, &progress_map, item_id, current_status.clone(), None, &item.metadata, &original_path_str).await;
+        let sent = Arc::new(AtomicU64::new(0));
+        let (sent, bucket, if_absent) = (&sent, &bucket_name, item.on_key_collision.put_if_absent());
+        let (key, upload_delivery_res) = upload_with_policy(delivery_key.clone(), item.on_key_collision, |key: String| async move {
+            let upload = upload_file_to_r2(r2_client, &state.bandwidth, sent, delivery_path, bucket, &key, delivery_format.mime_type(), if_absent);
+            with_item_timeout(item_timeout, with_throughput_updates(&app_handle, &progress_map, item_id, sent, upload)).await
+        })
+        .await;
+        record_uploaded_key(&mut item.r2_delivery_key, &key, &upload_delivery_res);
+        delivery_key = key;
+
+        pause_point(&app_handle, &state, item_id, &item.metadata, &original_path_str).await;
+
+        if cancel_flag.load(Ordering::SeqCst) {
+            info!("Cancellation detected after delivery upload for item {}", item_id);
+            current_status = UploadStatus::Cancelled;
+            update_progress(&app_handle, &progress_map, item_id, current_status.clone(), None, &item.metadata, &original_path_str).await;
+            perform_cleanup(r2_client, &bucket_name, mongo_client, &item).await;
+            return ItemOutcome::Cancelled;
+        }
+
+        if let Err(e) = upload_delivery_res {
+            error!("Delivery upload failed for {}: {}", original_path_str, e);
+            current_status = upload_error_status("Delivery upload failed", &e);
+            update_progress(&app_handle, &progress_map, item_id, current_status.clone(), Some(e.to_string()), &item.metadata, &original_path_str).await;
+            perform_cleanup(r2_client, &bucket_name, mongo_client, &item).await; // Cleanup R2 + temp files
+            return ItemOutcome::Failed;
+        }
+        info!("Delivery upload successful for {}: {}", original_path_str, delivery_key);
+    } else {
+        info!("No delivery file to upload for {}", original_path_str);
+        item.r2_delivery_key = None;
+    }
+
+    // --- Find Album Artwork ---
+    if item.metadata.art_path.is_none() && item.remote_source.is_none() && state.folder_art.load(Ordering::SeqCst) {
+        let input_path = item.input_path.clone();
+        item.metadata.art_path = tokio::task::spawn_blocking(move || folder_art_for(&input_path))
+            .await
+            .ok()
+            .flatten()
+            .and_then(|path| path.into_os_string().into_string().ok());
+    }
+
+    // --- Store Metadata ---
+    current_status = UploadStatus::StoringMetadata;
+    update_progress(&app_handle, &progress_map, item_id, current_status.clone(), None, &item.metadata, &original_path_str).await;
+    let db_result = store_track_metadata(mongo_client, ctx.use_transactions, &item, track_oid, &bucket_name, &features, loudness, item.r2_archive_key.as_deref(), item.r2_delivery_key.as_deref()).await;
+
+    if cancel_flag.load(Ordering::SeqCst) {
+        info!("Cancellation detected after DB write attempt for item {}", item_id);
+        current_status = UploadStatus::Cancelled;
+        update_progress(&app_handle, &progress_map, item_id, current_status.clone(), None, &item.metadata, &original_path_str).await;
+        if let Ok(ref track_id) = db_result { item.db_track_id = Some(track_id.clone()); } // Store ID if write succeeded
+        perform_cleanup(r2_client, &bucket_name, mongo_client, &item).await;
+        return ItemOutcome::Cancelled;
+    }
+
+    let track_id = match db_result {
+        Ok(track_id) => {
+            item.db_track_id = Some(track_id.clone()); // Store track ID
+            info!("Metadata stored successfully for {}: Track ID {}", original_path_str, track_id);
+            let local_delivery_path = keep_delivery_file(&mut item, &delivery_key).await;
+            if let Some(progress) = progress_map.lock().await.get_mut(&item_id) {
+                progress.track_id = Some(track_id.clone());
+                progress.local_delivery_path = local_delivery_path;
+            }
+            if let Some(art_path) = &item.metadata.art_path {
+                store_missing_album_art(&app_handle, mongo_client, r2_client, &bucket_name, track_oid, art_path).await;
+            }
+            update_progress(&app_handle, &progress_map, item_id, UploadStatus::Complete, None, &item.metadata, &original_path_str).await;
+            track_id
+        }
+        Err(e) => {
+             error!("Metadata storage failed for {}: {}", original_path_str, e);
+             current_status = upload_error_status("Metadata storage failed", &e);
+             update_progress(&app_handle, &progress_map, item_id, current_status.clone(), Some(e.to_string()), &item.metadata, &original_path_str).await;
+             perform_cleanup(r2_client, &bucket_name, mongo_client, &item).await; // Cleanup R2 + temp files
+             return ItemOutcome::Failed;
+        }
+    };
+
+    // --- Cleanup Temp Files ---
+    if let Some(path) = item.temp_delivery_path.take() { cleanup_temp_file(&path); }
+    if let Some(path) = item.temp_archive_path.take() { cleanup_temp_file(&path); }
+    if let Some(path) = item.temp_encrypted_path.take() { cleanup_temp_file(&path); }
+    ItemOutcome::Completed(track_id)
+}
+
+// --- Helper Functions ---
+
+/// Checks up front that `keep_transcoded_dir` can hold the delivery files, creating it
+/// if needed. Kept paths are reported as strings, so it must be valid UTF-8.
+fn check_keep_dir(dir: &Path) -> Result<(), String> {
+    utf8_path(dir).map_err(|e| e.to_string())?;
+    std::fs::create_dir_all(dir).map_err(|e| format!("Cannot use {} for transcoded files: {}", dir.display(), e))?;
+    Ok(())
+}
+
+/// Moves the item's delivery file to `keep_transcoded_dir`, if set, and returns where it
+/// went. Taken out of `temp_delivery_path` once moved; a failed move only logs, and the
+/// file is cleaned up with the other temp files.
+async fn keep_delivery_file(item: &mut UploadQueueItem, delivery_key: &str) -> Option<String> {
+    let dir = item.keep_transcoded_dir.clone()?;
+    let temp_path = item.temp_delivery_path.clone()?;
+    let key = delivery_key.to_string();
+    let kept = tokio::task::spawn_blocking(move || keep_transcoded_file(&temp_path, &dir, &key))
+        .await
+        .unwrap_or_else(|e| Err(std::io::Error::other(e.to_string())));
+    match kept {
+        Ok(path) => {
+            item.temp_delivery_path = None;
+            Some(path.to_string_lossy().into_owned())
+        }
+        Err(e) => {
+            warn!("Failed to keep the transcoded file of {} locally: {}", item.input_path.display(), e);
+            None
+        }
+    }
+}
+
+/// Uploads `art_path` as the artwork of the track's album unless the album already has
+/// artwork, which concurrent uploads to the album may have just stored. Failures are
+/// only logged; the track is stored either way.
+async fn store_missing_album_art(app_handle: &AppHandle<Wry>, mongo_client: &MongoDbClient, r2_client: &S3Client, bucket_name: &str, track_oid: ObjectId, art_path: &str) {
+    let db = mongo_client.catalog_database();
+    let options = FindOneOptions::builder().projection(doc! { "album_id": 1 }).build();
+    let album_id = match db.tracks::<Document>().find_one(doc! { "_id": track_oid }, options).await {
+        Ok(track_doc) => track_doc.and_then(|track_doc| track_doc.get("album_id").and_then(id_to_string)),
+        Err(e) => {
+            warn!("Failed to look up the album of track {}: {}", track_oid, e);
+            return;
+        }
+    };
+    let Some(album_id) = album_id else { return };
+    let r2 = R2Client::new(r2_client.clone(), bucket_name.to_string());
+    match store_album_artwork(&db, &r2, &album_id, Path::new(art_path), true, true).await {
+        Ok(_) => match cache_dir(app_handle) {
+            Ok(dir) => invalidate_album_art_cache(&dir, &album_id),
+            Err(e) => warn!("Failed to drop the cached artwork of album {}: {}", album_id, e),
+        },
+        Err(CommandError::Conflict(_)) => {} // The album has artwork already
+        Err(e) => warn!("Failed to upload {} as artwork of album {}: {}", art_path, album_id, e),
+    }
+}
+
+/// Names of the required fields (title, artist, album) that are absent or blank.
+fn missing_required_fields(metadata: &UploadItemMetadata) -> Vec<&'static str> {
+    let is_blank = |value: &Option<String>| value.as_deref().map_or(true, |v| v.trim().is_empty());
+    [("title", &metadata.title), ("artist", &metadata.artist), ("album", &metadata.album)]
+        .into_iter()
+        .filter(|(_, value)| is_blank(value))
+        .map(|(name, _)| name)
+        .collect()
+}
+
+/// Transcodes into a new file in `temp_dir`, recorded as in use until the queue stops.
+async fn run_transcoding(
+    input_path: &Path,
+    format: TranscodeFormat,
+    options: TranscodingOptions,
+    timeout: Duration,
+    state: &UploadState,
+    temp_dir: &Path,
+) -> Result<PathBuf, TranscodingError> {
+    let suffix = format!(".{}", format.extension());
+    let temp_file = TempFileBuilder::new().prefix("transcoded_").suffix(&suffix).tempfile_in(temp_dir).map_err(|e| TranscodingError::IoError { source_message: e.to_string() })?;
+    let output_path = temp_file.path().to_path_buf();
+    info!("Transcoding {:?} to {} temporary file {:?}", input_path, format.name(), output_path);
+    
+    // Using spawn_blocking to run the CPU-intensive transcoding in a separate thread pool
+    let input_path_clone = input_path.to_path_buf();
+    let output_path_clone = output_path.clone();
+    tokio::task::spawn_blocking(move || {
+        transcode_with_timeout(&input_path_clone, &output_path_clone, format, &options, Some(timeout))
+    }).await.map_err(|e| TranscodingError::IoError { 
+        source_message: format!("Task join error: {}", e) 
+    })??;
+
+    match temp_file.keep() {
+        Ok((_file, path)) => {
+            info!("Persisted temporary transcoded file: {:?}", path);
+            track_temp_file(state, temp_dir, &path).await;
+            Ok(path)
+        }
+        // Corrected IoError construction
+        Err(e) => { error!("Failed to persist temporary file {:?}: {}", output_path, e.error); let _ = std::fs::remove_file(&output_path); Err(TranscodingError::IoError { source_message: e.error.to_string() }) }
+    }
+}
+
+/// Content type of encrypted archive copies in R2.
+const ENCRYPTED_MIME_TYPE: &str = "application/octet-stream";
+
+/// SHA-256 of an original that is kept locally, computed on a blocking thread.
+async fn hash_original(input_path: &Path) -> Result<String, UploadError> {
+    let path = input_path.to_path_buf();
+    tokio::task::spawn_blocking(move || hash_file(&path))
+        .await
+        .map_err(|e| UploadError::InternalError(format!("Hashing task failed: {}", e)))?
+        .map_err(|e| UploadError::IoError(format!("Failed to hash {:?}: {}", input_path, e)))
+}
+
+/// Encrypts the archive copy into the temp directory when originals are encrypted,
+/// returning the encrypted file and the id of the key used.
+async fn encrypt_archive(
+    archive_path: &Path,
+    encryption_key: Option<&Result<[u8; KEY_LEN], String>>,
+    state: &UploadState,
+    temp_dir: &Path,
+) -> Result<Option<(PathBuf, String)>, UploadError> {
+    let key = match encryption_key {
+        None => return Ok(None),
+        Some(Err(e)) => return Err(UploadError::InternalError(format!("Originals encryption key unavailable: {}", e))),
+        Some(Ok(key)) => *key,
+    };
+    let temp_file = TempFileBuilder::new().prefix("encrypted_").suffix(".enc").tempfile_in(temp_dir)
+        .map_err(|e| UploadError::IoError(format!("Failed to create temp file: {}", e)))?;
+    let (_file, output_path) = temp_file.keep()
+        .map_err(|e| UploadError::IoError(format!("Failed to persist temp file: {}", e.error)))?;
+    track_temp_file(state, temp_dir, &output_path).await;
+
+    let (input, output) = (archive_path.to_path_buf(), output_path.clone());
+    match tokio::task::spawn_blocking(move || encrypt_file(&input, &output, &key)).await {
+        Ok(Ok(bytes)) => {
+            info!("Encrypted {:?} to {:?} ({} bytes)", archive_path, output_path, bytes);
+            Ok(Some((output_path, key_id(&key))))
+        }
+        Ok(Err(e)) => {
+            cleanup_temp_file(&output_path);
+            Err(UploadError::IoError(format!("Failed to encrypt {:?}: {}", archive_path, e)))
+        }
+        Err(e) => {
+            cleanup_temp_file(&output_path);
+            Err(UploadError::InternalError(format!("Encryption task join error: {}", e)))
+        }
+    }
+}
+
+/// Runs an upload step, failing with `UploadError::TimedOut` if it exceeds `timeout`.
+async fn with_item_timeout<F>(timeout: Duration, step: F) -> Result<(), UploadError>
+where
+    F: Future<Output = Result<(), UploadError>>,
+{
+    tokio::time::timeout(timeout, step)
+        .await
+        .unwrap_or(Err(UploadError::TimedOut(timeout.as_secs())))
+}
+
+/// Maps a failed upload step to the status shown to the frontend.
+fn upload_error_status(context: &str, err: &UploadError) -> UploadStatus {
+    match err {
+        UploadError::TimedOut(_) => UploadStatus::error(ErrorCategory::NetworkTransient, "timed out"),
+        _ => UploadStatus::error(upload_error_category(err), format!("{}: {}", context, err)),
+    }
+}
 
-        if let Err(e) = upload_state.queue_tx.send(queue_item).await {
-            error!("Failed to add item {} to upload queue: {}", item_input.path, e);
-             let progress = UploadProgress {
-                item_id, original_path: item_input.path.clone(),
-                status: UploadStatus::Error("Failed to queue".to_string()),
-                error_message: Some(format!("Failed to add item to queue: {}", e)),
-                title: item_input.metadata.title.clone(), album: item_input.metadata.album.clone(),
-            };
-            if let Some(window) = app_handle.get_webview_window("main") {
-                 // Clone progress before emitting
-                 window.emit("upload://status-update", progress.clone()).map_err(|e| e.to_string())?;
-            } else { error!("Could not find main window to emit status update."); }
-            progress_map.insert(item_id, progress);
-        } else {
-            let progress = UploadProgress {
-                item_id, original_path: item_input.path, status: UploadStatus::Pending,
-                error_message: None, title: item_input.metadata.title, album: item_input.metadata.album,
-            };
-             if let Some(window) = app_handle.get_webview_window("main") {
-                  // Clone progress before emitting
-                  window.emit("upload://status-update", progress.clone()).map_err(|e| e.to_string())?;
-             } else { error!("Could not find main window to emit status update."); }
-            progress_map.insert(item_id, progress);
-        }
+fn transcoding_error_category(err: &TranscodingError) -> ErrorCategory {
+    match err {
+        TranscodingError::InputFileNotFound(_) => ErrorCategory::CorruptInput,
+        // FFmpeg runs but can't decode the file
+        TranscodingError::ProcessExecutionFailed { .. } => ErrorCategory::CorruptInput,
+        _ => ErrorCategory::Transcode,
     }
-    drop(progress_map);
+}
 
-    if !upload_state.is_processing.compare_exchange(false, true, Ordering::SeqCst, Ordering::SeqCst).is_err() {
-        info!("Spawning upload processing task.");
-        let state_clone = Arc::clone(&upload_state);
-        let app_handle_clone = app_handle.clone();
+/// Whether an R2 error message is S3 rejecting the credentials rather than a network failure.
+fn is_auth_failure(message: &str) -> bool {
+    ["AccessDenied", "InvalidAccessKeyId", "SignatureDoesNotMatch", "403 Forbidden"]
+        .iter()
+        .any(|code| message.contains(code))
+}
 
-        tauri::async_runtime::spawn(async move {
-            let rx_option = state_clone.queue_rx.lock().await.take();
+fn upload_error_category(err: &UploadError) -> ErrorCategory {
+    match err {
+        UploadError::R2ClientNotInitialized | UploadError::MongoDbClientNotInitialized | UploadError::CredentialsError(_) => ErrorCategory::Auth,
+        UploadError::TranscodingError(e) => transcoding_error_category(e),
+        UploadError::R2UploadError(message) if is_auth_failure(message) => ErrorCategory::Auth,
+        UploadError::R2UploadError(_) | UploadError::MongoDbError(_) | UploadError::TimedOut(_) => ErrorCategory::NetworkTransient,
+        UploadError::InvalidInput(_) | UploadError::KeyTaken(_) => ErrorCategory::Metadata,
+        UploadError::IoError(_) | UploadError::Cancelled | UploadError::InternalError(_) => ErrorCategory::Unknown,
+    }
+}
 
-            if let Some(rx) = rx_option {
-                info!("Passing receiver to process_upload_queue task.");
-                process_upload_queue(app_handle_clone.clone(), state_clone.clone(), rx).await;
-            } else {
-                error!("Upload queue receiver has already been taken!");
-                state_clone.is_processing.store(false, Ordering::SeqCst);
-            }
-            state_clone.is_processing.store(false, Ordering::SeqCst);
-            info!("Upload processing task finished.");
-            if let Some(window) = app_handle_clone.get_webview_window("main") {
-                 window.emit("upload://queue-finished", ()).unwrap_or_else(|e| {
-                     error!("Failed to emit queue-finished event: {}", e);
-                 });
-            } else { error!("Could not find main window to emit queue-finished event."); }
-        });
+/// Estimates BPM and key on a blocking thread. Analysis is best effort: tracks that can't
+/// be decoded are stored without either.
+async fn analyze_features(input_path: &Path) -> TrackFeatures {
+    let path = input_path.to_path_buf();
+    match tokio::task::spawn_blocking(move || analyze_file(&path)).await {
+        Ok(Ok(features)) => {
+            info!("Analyzed {}: bpm {:?}, key {:?}", input_path.display(), features.bpm, features.musical_key);
+            features
+        }
+        Ok(Err(e)) => {
+            warn!("Skipping BPM/key analysis for {}: {}", input_path.display(), e);
+            TrackFeatures::default()
+        }
+        Err(e) => {
+            error!("BPM/key analysis task failed for {}: {}", input_path.display(), e);
+            TrackFeatures::default()
+        }
+    }
+}
+
+/// Measures integrated loudness for ReplayGain on a blocking thread. Best effort like
+/// `analyze_features`: the track is stored without it if ffmpeg can't measure the file.
+async fn analyze_loudness(input_path: &Path, timeout: Duration) -> Option<Loudness> {
+    let path = input_path.to_path_buf();
+    match tokio::task::spawn_blocking(move || measure_loudness(&path, Some(timeout))).await {
+        Ok(Ok(loudness)) => {
+            info!("Measured loudness of {}: {:?}", input_path.display(), loudness);
+            loudness
+        }
+        Ok(Err(e)) => {
+            warn!("Skipping loudness measurement for {}: {}", input_path.display(), e);
+            None
+        }
+        Err(e) => {
+            error!("Loudness measurement task failed for {}: {}", input_path.display(), e);
+            None
+        }
+    }
+}
+
+/// Maps a failed PutObject to an `UploadError`: `KeyTaken` when a conditional PUT found
+/// an object at `r2_key`.
+pub(crate) fn put_object_error(err: SdkError<PutObjectError>, r2_key: &str) -> UploadError {
+    if err.raw_response().map(|resp| resp.status().as_u16()) == Some(412) {
+        UploadError::KeyTaken(r2_key.to_string())
     } else {
-        info!("Upload processing task already running.");
+        UploadError::R2UploadError(format!("S3 PutObject failed: {}", err))
     }
-    Ok(())
 }
 
-#[command]
-pub async fn cancel_upload_queue(upload_state: State<'_, Arc<UploadState>>) -> Result<(), String> {
-    info!("Received request to cancel upload queue.");
-    upload_state.cancel_flag.store(true, Ordering::SeqCst);
+/// Uploads a file through the shared bandwidth limiter, adding the bytes sent to `sent`.
+/// With `if_absent` the PUT is conditional and fails with `KeyTaken` instead of
+/// replacing an existing object.
+pub(crate) async fn upload_file_to_r2(
+    r2_client: &S3Client,
+    limiter: &Arc<BandwidthLimiter>,
+    sent: &Arc<AtomicU64>,
+    file_path: &Path,
+    bucket_name: &str,
+    r2_key: &str,
+    mime_type: &str,
+    if_absent: bool,
+) -> Result<(), UploadError> {
+    info!("Uploading file {:?} to R2 bucket '{}' key '{}'", file_path, bucket_name, r2_key);
+    let len = tokio::fs::metadata(file_path).await.map_err(|e| UploadError::IoError(format!("Failed to read file {:?}: {}", file_path, e)))?.len();
+    let body = throttled_file_stream(file_path, len, Arc::clone(limiter), Arc::clone(sent));
+    r2_client.put_object().bucket(bucket_name).key(r2_key).content_type(mime_type).content_length(len as i64)
+        .set_if_none_match(if_absent.then(|| "*".to_string()))
+        .body(body).send().await
+        .map_err(|e| put_object_error(e, r2_key))?;
     Ok(())
 }
 
-// --- Core Processing Logic ---
+/// How often the measured throughput of a running upload is sent to the frontend.
+const THROUGHPUT_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Runs an upload step, re-emitting the item's progress every second with the rate
+/// measured from `sent`.
+async fn with_throughput_updates<F: Future>(
+    app_handle: &AppHandle<Wry>,
+    progress_map: &Arc<Mutex<HashMap<Uuid, UploadProgress>>>,
+    item_id: Uuid,
+    sent: &AtomicU64,
+    step: F,
+) -> F::Output {
+    tokio::pin!(step);
+    let mut ticker = tokio::time::interval(THROUGHPUT_INTERVAL);
+    ticker.tick().await; // The first tick completes immediately
+    let mut last = (Instant::now(), sent.load(Ordering::Relaxed));
+    loop {
+        tokio::select! {
+            output = &mut step => return output,
+            _ = ticker.tick() => {
+                let current = (Instant::now(), sent.load(Ordering::Relaxed));
+                let seconds = current.0.duration_since(last.0).as_secs_f64().max(f64::EPSILON);
+                let bytes_per_sec = current.1.saturating_sub(last.1) as f64 / seconds;
+                last = current;
+
+                let mut map = progress_map.lock().await;
+                if let Some(progress) = map.get_mut(&item_id) {
+                    progress.throughput_bytes_per_sec = Some(bytes_per_sec);
+                    if let Some(window) = app_handle.get_webview_window("main") {
+                        window.emit("upload://status-update", progress.clone()).unwrap_or_else(|e| {
+                            error!("Failed to emit status update for {}: {}", item_id, e);
+                        });
+                    }
+                }
+            }
+        }
+    }
+}
 
-async fn process_upload_queue(
-    app_handle: AppHandle<Wry>,
-    state: Arc<UploadState>,
-    mut rx: mpsc::Receiver<UploadQueueItem>,
-) {
-    let progress_map = Arc::clone(&state.progress_map);
-    let cancel_flag = Arc::clone(&state.cancel_flag);
+/// Album artist of compilations without an album artist tag.
+const VARIOUS_ARTISTS: &str = "Various Artists";
+/// Per-artist album collecting tracks without album tags.
+const SINGLES_ALBUM: &str = "[Singles]";
+
+/// The album a track is filed under.
+#[derive(Debug, Clone, PartialEq)]
+struct AlbumIdentity {
+    name: String,
+    artist: String,
+    compilation: bool,
+}
 
-    // --- Get Clients from App State ---
-    let r2_state = match app_handle.try_state::<crate::R2State>() {
-         Some(state) => state, None => { error!("R2State not found."); return; }
-    };
-    let mongo_state = match app_handle.try_state::<crate::MongoState>() {
-         Some(state) => state, None => { error!("MongoState not found."); return; }
-    };
-    let r2_client_opt = r2_state.client.lock().await;
-    let mongo_client_opt = mongo_state.client.lock().await;
-    let r2_client = match r2_client_opt.as_ref() {
-        Some(client) => client, None => { error!("R2 client not initialized."); return; }
-    };
-    let mongo_client = match mongo_client_opt.as_ref() {
-        Some(client) => client, None => { error!("MongoDB client not initialized."); return; }
-    };
-    let bucket_name_opt = r2_state.bucket_name.lock().await;
-    let bucket_name = match bucket_name_opt.as_deref() {
-        Some(name) => name.to_string(), None => { error!("R2 bucket name not found in state."); return; }
-    };
-    drop(bucket_name_opt); // Drop lock
+impl AlbumIdentity {
+    /// Compilations are matched on name alone, other albums on name and album artist.
+    /// Albums with a `grouping_key` were created for an explicit id and never match by name.
+    fn filter(&self) -> Document {
+        if self.compilation {
+            doc! { "name": &self.name, "compilation": true, "grouping_key": null }
+        } else {
+            doc! { "name": &self.name, "artist": &self.artist, "grouping_key": null }
+        }
+    }
 
-    // --- Processing Loop ---
-    while let Some(mut item) = rx.recv().await {
-        let item_id = item.id;
-        let original_path_str = item.input_path.to_string_lossy().to_string();
-        info!("Processing item: {} ({})", original_path_str, item_id);
-        let mut current_status = UploadStatus::Pending;
+    /// The key of the unique `{name, artist, grouping_key}` index. `null` also matches
+    /// albums stored before `grouping_key` and `compilation` existed, which `filter` can
+    /// miss (e.g. a "Various Artists" album without the `compilation` flag); an upsert
+    /// for such an album collides with it in the index, and this finds it instead.
+    fn index_filter(&self) -> Document {
+        doc! { "name": &self.name, "artist": &self.artist, "grouping_key": null }
+    }
+}
 
-        // Check for cancellation before starting work
-        if cancel_flag.load(Ordering::SeqCst) {
-            info!("Cancellation detected before processing item {}", item_id);
-            current_status = UploadStatus::Cancelled;
-            update_progress(&app_handle, &progress_map, item_id, current_status.clone(), None, &item.metadata, &original_path_str).await;
-            continue; // Skip to next item
+/// The album id an item must be filed under, if its matching strategy asks for one.
+fn explicit_album_id(matching: AlbumMatching, metadata: &UploadItemMetadata) -> Result<Option<ObjectId>, String> {
+    match matching {
+        AlbumMatching::ByNameArtist => Ok(None),
+        AlbumMatching::AlwaysNew => Ok(Some(ObjectId::new())),
+        AlbumMatching::ByExplicitId => {
+            let album_id = metadata.album_id.as_deref().map(str::trim).filter(|id| !id.is_empty())
+                .ok_or_else(|| "Album matching by ID needs an album_id in the metadata".to_string())?;
+            ObjectId::parse_str(album_id).map(Some).map_err(|_| format!("'{}' is not a valid album ID", album_id))
         }
+    }
+}
 
-        // --- Transcoding ---
-        current_status = UploadStatus::Transcoding;
-        update_progress(&app_handle, &progress_map, item_id, current_status.clone(), None, &item.metadata, &original_path_str).await;
+/// Decides which album a track belongs to:
+/// - the album artist tag wins over the track artist
+/// - untagged tracks go to their artist's "[Singles]" album
+/// - compilations are credited to "Various Artists" unless they have an album artist
+fn resolve_album(metadata: &UploadItemMetadata) -> AlbumIdentity {
+    let non_blank = |value: &Option<String>| value.as_deref().map(str::trim).filter(|v| !v.is_empty()).map(String::from);
+    let album_artist = non_blank(&metadata.album_artist);
+    match non_blank(&metadata.album) {
+        Some(name) if metadata.compilation => AlbumIdentity {
+            name,
+            artist: album_artist.unwrap_or_else(|| VARIOUS_ARTISTS.to_string()),
+            compilation: true,
+        },
+        Some(name) => AlbumIdentity {
+            name,
+            artist: album_artist.or_else(|| non_blank(&metadata.artist)).unwrap_or_else(|| "Unknown Artist".to_string()),
+            compilation: false,
+        },
+        // A compilation flag without an album name has nothing to group on
+        None => AlbumIdentity {
+            name: SINGLES_ALBUM.to_string(),
+            artist: album_artist.or_else(|| non_blank(&metadata.artist)).unwrap_or_else(|| "Unknown Artist".to_string()),
+            compilation: false,
+        },
+    }
+}
 
-        let transcoding_result = run_transcoding(&item.input_path).await;
+/// Composers to store on the track: the `composers` list, or the single `composer` tag.
+fn track_composers(metadata: &UploadItemMetadata) -> Vec<String> {
+    let composers = metadata.composers.clone().unwrap_or_else(|| metadata.composer.iter().cloned().collect());
+    composers.into_iter().map(|c| c.trim().to_string()).filter(|c| !c.is_empty()).collect()
+}
 
-        if cancel_flag.load(Ordering::SeqCst) {
-            info!("Cancellation detected after transcoding attempt for item {}", item_id);
-            current_status = UploadStatus::Cancelled;
-            update_progress(&app_handle, &progress_map, item_id, current_status.clone(), None, &item.metadata, &original_path_str).await;
-            if let Ok(ref temp_path) = transcoding_result { cleanup_temp_file(temp_path); }
-            break; // Stop queue processing on cancel
+/// Filter, update, and options of the upsert that finds or creates an album: by
+/// `explicit_id` when given, otherwise by name and artist. `slug` is only used if the
+/// album is created.
+fn album_upsert(album: &AlbumIdentity, explicit_id: Option<ObjectId>, year: Option<i32>, genres: &[String], slug: &str) -> (Document, Document, FindOneAndUpdateOptions) {
+    let mut fields = doc! {
+        "name": &album.name,
+        "artist": &album.artist,
+        "slug": slug,
+        "compilation": album.compilation,
+        "year": year,
+        "genres": genres,
+        "art_path": null, // Placeholder for album art
+        "date_added": bson::DateTime::now(),
+    };
+    // Only new albums change; an existing one is returned untouched
+    stamp(&mut fields);
+    let filter = match explicit_id {
+        Some(id) => {
+            // Sets the album apart in the unique {name, artist, grouping_key} index
+            fields.insert("grouping_key", id.to_hex());
+            doc! { "_id": id }
         }
+        None => album.filter(),
+    };
+    let options = FindOneAndUpdateOptions::builder().upsert(true).return_document(ReturnDocument::After).build();
+    (filter, doc! { "$setOnInsert": fields }, options)
+}
 
-        match transcoding_result {
-            Ok(temp_aac_path) => {
-                item.temp_aac_path = Some(temp_aac_path);
-            }
-            Err(e) => {
-                error!("Transcoding failed for {}: {}", original_path_str, e);
-                current_status = UploadStatus::Error(format!("Transcoding failed: {}", e));
-                update_progress(&app_handle, &progress_map, item_id, current_status.clone(), Some(e.to_string()), &item.metadata, &original_path_str).await;
-                continue; // Skip to next item
-            }
-        };
-        let aac_path_ref = item.temp_aac_path.as_ref(); // Borrow for later use
+/// The filter to retry an album upsert with after it failed on a unique index. The
+/// album the index found is matched by its index key, which covers both a concurrent
+/// upsert that won the insert race and an album stored before the identity fields.
+fn album_retry_filter(album: &AlbumIdentity, filter: Document, explicit_id: Option<ObjectId>) -> Document {
+    match explicit_id {
+        Some(_) => filter,
+        None => album.index_filter(),
+    }
+}
 
-        // --- Upload Original ---
-        current_status = UploadStatus::UploadingOriginal;
-        update_progress(&app_handle, &progress_map, item_id, current_status.clone(), None, &item.metadata, &original_path_str).await;
-        let original_mime = mime_guess::from_path(&item.input_path).first_or_octet_stream();
-        let original_key = format!("tracks/original/{}", item.input_path.file_name().unwrap_or_default().to_string_lossy());
-        let upload_orig_res = upload_file_to_r2(r2_client, &item.input_path, &bucket_name, &original_key, original_mime.as_ref(), true).await;
-        item.r2_original_key = Some(original_key.clone()); // Store key
+/// Returns the id of the album, creating it if needed. The upsert is atomic, and the
+/// unique `{name, artist, grouping_key}` index makes concurrent callers end up with the
+/// same document: an upsert that loses the insert race fails with a duplicate key error
+/// and matches the winner on retry.
+async fn resolve_album_id(
+    albums_collection: &Collection<Document>,
+    album: &AlbumIdentity,
+    explicit_id: Option<ObjectId>,
+    year: Option<i32>,
+    genres: &[String],
+    slug: &str,
+) -> Result<ObjectId, UploadError> {
+    let (filter, update, options) = album_upsert(album, explicit_id, year, genres, slug);
+    let mut result = albums_collection.find_one_and_update(filter.clone(), update.clone(), options.clone()).await;
+    if matches!(&result, Err(e) if is_duplicate_key_error(e)) {
+        let filter = album_retry_filter(album, filter, explicit_id);
+        result = albums_collection.find_one_and_update(filter, update, options).await;
+    }
+    let album_doc = result
+        .map_err(|e| UploadError::MongoDbError(format!("Album upsert failed: {}", e)))?
+        .ok_or_else(|| UploadError::MongoDbError(format!("Album upsert for '{}' returned no document", album.name)))?;
+    album_doc.get_object_id("_id").map_err(|_| UploadError::MongoDbError("Invalid album ID format".to_string()))
+}
 
-        if cancel_flag.load(Ordering::SeqCst) {
-            info!("Cancellation detected after original upload for item {}", item_id);
-            current_status = UploadStatus::Cancelled;
-            update_progress(&app_handle, &progress_map, item_id, current_status.clone(), None, &item.metadata, &original_path_str).await;
-            perform_cleanup(r2_client, &bucket_name, mongo_client, &item).await;
-            break;
-        }
+/// Whether a `hello` reply comes from a replica set member or a mongos. Standalone
+/// servers reject transactions.
+fn topology_supports_transactions(hello_reply: &Document) -> bool {
+    hello_reply.get_str("setName").is_ok() || hello_reply.get_str("msg") == Ok("isdbgrid")
+}
 
-        if let Err(e) = upload_orig_res {
-             error!("Original upload failed for {}: {}", original_path_str, e);
-             current_status = UploadStatus::Error(format!("Original upload failed: {}", e));
-             update_progress(&app_handle, &progress_map, item_id, current_status.clone(), Some(e.to_string()), &item.metadata, &original_path_str).await;
-             perform_cleanup(r2_client, &bucket_name, mongo_client, &item).await; // Cleanup original R2 + temp AAC
-             continue;
+async fn supports_transactions(mongo_client: &MongoDbClient) -> bool {
+    match mongo_client.database("admin").run_command(doc! { "hello": 1 }, None).await {
+        Ok(reply) => topology_supports_transactions(&reply),
+        Err(e) => {
+            warn!("Could not check whether MongoDB supports transactions: {}", e);
+            false
         }
-        info!("Original upload successful for {}: {}", original_path_str, original_key);
+    }
+}
 
-        // --- Upload AAC ---
-        if let Some(aac_path) = aac_path_ref {
-            current_status = UploadStatus::UploadingAAC;
-            update_progress(&app_handle, &progress_map, item_id, current_status.clone(), None, &item.metadata, &original_path_str).await;
-            let aac_mime = mime_guess::from_path::<&Path>(aac_path).first_or_octet_stream();
-            let aac_key = format!("tracks/aac/{}", aac_path.file_name().unwrap_or_default().to_string_lossy());
-            let upload_aac_res = upload_file_to_r2(r2_client, aac_path, &bucket_name, &aac_key, aac_mime.as_ref(), true).await;
-            item.r2_aac_key = Some(aac_key.clone()); // Store key
-
-            if cancel_flag.load(Ordering::SeqCst) {
-                info!("Cancellation detected after AAC upload for item {}", item_id);
-                current_status = UploadStatus::Cancelled;
-                update_progress(&app_handle, &progress_map, item_id, current_status.clone(), None, &item.metadata, &original_path_str).await;
-                perform_cleanup(r2_client, &bucket_name, mongo_client, &item).await;
-                break;
-            }
+fn track_insert_error(e: mongodb::error::Error, isrc: Option<&str>) -> UploadError {
+    match isrc {
+        // Only reached once every retry with a fresh slug lost to another upload
+        _ if is_slug_conflict(&e) => UploadError::MongoDbError(format!("Track slug was taken by a concurrent upload: {}", e)),
+        Some(isrc) if is_duplicate_key_error(&e) => UploadError::InvalidInput(format!("ISRC {} is already assigned to another track", isrc)),
+        _ => UploadError::MongoDbError(format!("Track insert failed: {}", e)),
+    }
+}
 
-            if let Err(e) = upload_aac_res {
-                error!("AAC upload failed for {}: {}", original_path_str, e);
-                current_status = UploadStatus::Error(format!("AAC upload failed: {}", e));
-                update_progress(&app_handle, &progress_map, item_id, current_status.clone(), Some(e.to_string()), &item.metadata, &original_path_str).await;
-                perform_cleanup(r2_client, &bucket_name, mongo_client, &item).await; // Cleanup R2 + temp AAC
-                continue;
+/// Gives the track the next free slug after another upload with the same title and
+/// artist took the one it was given.
+async fn refresh_track_slug(tracks_collection: &Collection<Document>, track_doc: &mut Document) -> Result<(), UploadError> {
+    let title = track_doc.get_str("title").unwrap_or_default();
+    let artist = track_doc.get_array("artists").ok().and_then(|artists| artists.first()).and_then(bson::Bson::as_str).unwrap_or_default();
+    let base = slug_base(title, artist);
+    let slug = unique_slug(tracks_collection, &base, None).await
+        .map_err(|e| UploadError::MongoDbError(format!("Slug lookup failed: {}", e)))?;
+    info!("Slug of '{}' was taken by a concurrent upload; using {}", title, slug);
+    track_doc.insert("slug", slug);
+    Ok(())
+}
+
+/// Inserts the track, retrying with a fresh slug when a concurrent upload took its slug.
+async fn insert_track(tracks_collection: &Collection<Document>, mut track_doc: Document, isrc: Option<&str>) -> Result<(), UploadError> {
+    let mut attempt = 1;
+    loop {
+        match tracks_collection.insert_one(track_doc.clone(), None).await {
+            Ok(_) => return Ok(()),
+            Err(e) if attempt < MAX_TRANSACTION_ATTEMPTS && is_slug_conflict(&e) => {
+                refresh_track_slug(tracks_collection, &mut track_doc).await?;
+                attempt += 1;
             }
-            info!("AAC upload successful for {}: {}", original_path_str, aac_key);
-        } else {
-            info!("No AAC file to upload for {}", original_path_str);
-            item.r2_aac_key = None;
+            Err(e) => return Err(track_insert_error(e, isrc)),
         }
+    }
+}
 
-        // --- Store Metadata ---
-        current_status = UploadStatus::StoringMetadata;
-        update_progress(&app_handle, &progress_map, item_id, current_status.clone(), None, &item.metadata, &original_path_str).await;
-        let db_result = store_track_metadata(mongo_client, &item, item.r2_original_key.as_deref(), item.r2_aac_key.as_deref()).await;
+const MAX_TRANSACTION_ATTEMPTS: usize = 3;
 
-        if cancel_flag.load(Ordering::SeqCst) {
-            info!("Cancellation detected after DB write attempt for item {}", item_id);
-            current_status = UploadStatus::Cancelled;
-            update_progress(&app_handle, &progress_map, item_id, current_status.clone(), None, &item.metadata, &original_path_str).await;
-            if let Ok(ref track_id) = db_result { item.db_track_id = Some(track_id.clone()); } // Store ID if write succeeded
-            perform_cleanup(r2_client, &bucket_name, mongo_client, &item).await;
-            break;
+/// Commits, retrying while the outcome of the commit is unknown (e.g. a network error).
+async fn commit_with_retry(session: &mut ClientSession) -> mongodb::error::Result<()> {
+    let mut attempt = 1;
+    loop {
+        match session.commit_transaction().await {
+            Err(e) if attempt < MAX_TRANSACTION_ATTEMPTS && e.contains_label(UNKNOWN_TRANSACTION_COMMIT_RESULT) => attempt += 1,
+            result => return result,
         }
+    }
+}
 
-        match db_result {
-            Ok(track_id) => {
-                item.db_track_id = Some(track_id.clone()); // Store track ID
-                info!("Metadata stored successfully for {}: Track ID {}", original_path_str, track_id);
-                current_status = UploadStatus::Complete;
-                update_progress(&app_handle, &progress_map, item_id, current_status.clone(), None, &item.metadata, &original_path_str).await;
-            }
+/// Finds or creates the album and inserts the track in one transaction, so a failure
+/// between the two never leaves an album behind without its track. A lost album insert
+/// race or a write conflict aborts the transaction; the next attempt matches the album
+/// the other writer created. A track slug taken by another upload is replaced with the
+/// next free one before the next attempt.
+#[allow(clippy::too_many_arguments)]
+async fn store_in_transaction(
+    mongo_client: &MongoDbClient,
+    db: &mongodb::Database,
+    album: &AlbumIdentity,
+    explicit_id: Option<ObjectId>,
+    year: Option<i32>,
+    genres: &[String],
+    album_slug: &str,
+    track_doc: &Document,
+    isrc: Option<&str>,
+) -> Result<(), UploadError> {
+    let albums_collection = db.albums::<Document>();
+    let tracks_collection = db.tracks::<Document>();
+    let transaction_error = |e: mongodb::error::Error| UploadError::MongoDbError(format!("Transaction failed: {}", e));
+    let (mut filter, update, options) = album_upsert(album, explicit_id, year, genres, album_slug);
+    let mut session = mongo_client.start_session(None).await.map_err(transaction_error)?;
+    let mut track_doc = track_doc.clone();
+
+    for attempt in 1..=MAX_TRANSACTION_ATTEMPTS {
+        let can_retry = |e: &mongodb::error::Error| attempt < MAX_TRANSACTION_ATTEMPTS && e.contains_label(TRANSIENT_TRANSACTION_ERROR);
+        session.start_transaction(None).await.map_err(transaction_error)?;
+
+        let album_doc = match albums_collection.find_one_and_update_with_session(filter.clone(), update.clone(), options.clone(), &mut session).await {
+            Ok(album_doc) => album_doc,
             Err(e) => {
-                 error!("Metadata storage failed for {}: {}", original_path_str, e);
-                 current_status = UploadStatus::Error(format!("Metadata storage failed: {}", e));
-                 update_progress(&app_handle, &progress_map, item_id, current_status.clone(), Some(e.to_string()), &item.metadata, &original_path_str).await;
-                 perform_cleanup(r2_client, &bucket_name, mongo_client, &item).await; // Cleanup R2 + temp AAC
-                 continue;
+                let _ = session.abort_transaction().await;
+                if attempt < MAX_TRANSACTION_ATTEMPTS && is_duplicate_key_error(&e) {
+                    filter = album_retry_filter(album, filter, explicit_id);
+                    continue;
+                }
+                if can_retry(&e) {
+                    continue;
+                }
+                return Err(UploadError::MongoDbError(format!("Album upsert failed: {}", e)));
             }
-        }
+        };
+        let Some(album_id) = album_doc.as_ref().and_then(|album_doc| album_doc.get_object_id("_id").ok()) else {
+            let _ = session.abort_transaction().await;
+            return Err(UploadError::MongoDbError(format!("Album upsert for '{}' returned no document", album.name)));
+        };
 
-        // --- Cleanup Temp AAC ---
-        if current_status == UploadStatus::Complete {
-            if let Some(path) = item.temp_aac_path.take() { cleanup_temp_file(&path); }
+        let mut album_track_doc = track_doc.clone();
+        album_track_doc.insert("album_id", album_id);
+        if let Err(e) = tracks_collection.insert_one_with_session(album_track_doc, None, &mut session).await {
+            let _ = session.abort_transaction().await;
+            if can_retry(&e) {
+                continue;
+            }
+            if attempt < MAX_TRANSACTION_ATTEMPTS && is_slug_conflict(&e) {
+                refresh_track_slug(&tracks_collection, &mut track_doc).await?;
+                continue;
+            }
+            return Err(track_insert_error(e, isrc));
         }
-    } // End while
-} // End process_upload_queue
-
-// --- Helper Functions ---
-
-async fn run_transcoding(input_path: &Path) -> Result<PathBuf, TranscodingError> {
-    let temp_aac_file = TempFileBuilder::new().prefix("transcoded_").suffix(".m4a").tempfile().map_err(|e| TranscodingError::IoError { source_message: e.to_string() })?;
-    let output_path = temp_aac_file.path().to_path_buf();
-    info!("Transcoding {:?} to temporary file {:?}", input_path, output_path);
-    
-    // Using spawn_blocking to run the CPU-intensive transcoding in a separate thread pool
-    let input_path_clone = input_path.to_path_buf();
-    let output_path_clone = output_path.clone();
-    tokio::task::spawn_blocking(move || {
-        transcode_to_aac(&input_path_clone, &output_path_clone)
-    }).await.map_err(|e| TranscodingError::IoError { 
-        source_message: format!("Task join error: {}", e) 
-    })??;
 
-    match temp_aac_file.keep() {
-        Ok((_file, path)) => { info!("Persisted temporary transcoded file: {:?}", path); Ok(path) }
-        // Corrected IoError construction
-        Err(e) => { error!("Failed to persist temporary file {:?}: {}", output_path, e.error); let _ = std::fs::remove_file(&output_path); Err(TranscodingError::IoError { source_message: e.error.to_string() }) }
+        match commit_with_retry(&mut session).await {
+            Ok(()) => return Ok(()),
+            Err(e) if can_retry(&e) => continue,
+            Err(e) => return Err(transaction_error(e)),
+        }
     }
+    Err(UploadError::MongoDbError(format!("Transaction for '{}' failed after {} attempts", album.name, MAX_TRANSACTION_ATTEMPTS)))
 }
 
-async fn upload_file_to_r2(r2_client: &S3Client, file_path: &Path, bucket_name: &str, r2_key: &str, mime_type: &str, _make_public: bool) -> Result<(), UploadError> {
-    info!("Uploading file {:?} to R2 bucket '{}' key '{}'", file_path, bucket_name, r2_key);
-    let body = ByteStream::from_path(file_path).await.map_err(|e| UploadError::IoError(format!("Failed to read file {:?}: {}", file_path, e)))?;
-    r2_client.put_object().bucket(bucket_name).key(r2_key).content_type(mime_type).body(body).send().await.map_err(|e| UploadError::R2UploadError(format!("S3 PutObject failed: {}", e)))?;
-    Ok(())
-}
-
+#[allow(clippy::too_many_arguments)]
 async fn store_track_metadata(
     mongo_client: &MongoDbClient,
+    use_transactions: bool,
     item: &UploadQueueItem,
-    original_r2_key: Option<&str>,
-    aac_r2_key: Option<&str>,
+    track_id: ObjectId,
+    bucket_name: &str,
+    features: &TrackFeatures,
+    loudness: Option<Loudness>,
+    archive_r2_key: Option<&str>,
+    delivery_r2_key: Option<&str>,
 ) -> Result<String, UploadError> {
-    let db = mongo_client.database("music_library");
-    let tracks_collection = db.collection::<Document>("tracks");
-    let albums_collection = db.collection::<Document>("albums");
+    let db = mongo_client.catalog_database();
+    let tracks_collection = db.tracks::<Document>();
+    let albums_collection = db.albums::<Document>();
 
     info!("Storing metadata for: {}", item.input_path.display());
 
@@ -460,83 +2141,133 @@ async fn store_track_metadata(
         item.input_path.file_stem().unwrap_or_default().to_string_lossy().into_owned() // Fallback to filename stem
     });
     let artist = item.metadata.artist.clone().unwrap_or_else(|| "Unknown Artist".to_string());
-    let album_title = item.metadata.album.clone().unwrap_or_else(|| "Unknown Album".to_string());
     let track_number = item.metadata.track_number;
     let duration_sec = item.metadata.duration_sec; // Use directly from finalized metadata
-    let genre = item.metadata.genre.clone(); // Use directly from finalized metadata
-    let composer = item.metadata.composer.clone(); // Use directly from finalized metadata
+    // Map the genre onto its canonical taxonomy name
+    let genres = match &item.metadata.genre {
+        Some(g) => normalize_genres(&db, std::slice::from_ref(g))
+            .await
+            .map_err(|e| UploadError::MongoDbError(format!("Genre normalization failed: {}", e)))?,
+        None => Vec::new(),
+    };
+    let composers = track_composers(&item.metadata);
     let year = item.metadata.year; // Use directly from finalized metadata
     let comments = item.metadata.comments.clone(); // Use directly from finalized metadata
+    let custom_fields = match &item.metadata.custom_fields {
+        Some(fields) => Some(bson::to_bson(fields).map_err(|e| UploadError::InternalError(format!("Failed to convert custom fields: {}", e)))?),
+        None => None,
+    };
 
     // --- Get Basic File Info ---
-    let file_size = match std::fs::metadata(&item.input_path) {
-         Ok(m) => m.len(),
-         Err(e) => {
+    let file_size = match (&item.remote_source, std::fs::metadata(&item.input_path)) {
+         (Some(source), _) => source.size,
+         (None, Ok(m)) => m.len(),
+         (None, Err(e)) => {
              warn!("Failed to get file size for {}: {}. Using 0.", item.input_path.display(), e);
              0 // Default to 0 if metadata fails
          }
     };
-    let mime_type = mime_guess::from_path(&item.input_path)
-        .first_or_octet_stream()
-        .to_string();
+    let original_path = match &item.remote_source {
+        Some(source) => source.url.clone(),
+        // Recorded in full so the original can be found again on local storage
+        None if !item.upload_originals => absolute_original_path(&item.input_path).to_string_lossy().into_owned(),
+        None => item.input_path.to_string_lossy().to_string(),
+    };
+    let original_location = if item.upload_originals { R2_ORIGINAL_LOCATION } else { LOCAL_ORIGINAL_LOCATION };
+    let mime_type = source_mime_type(&item.input_path);
     let file_extension = item.input_path.extension().unwrap_or_default().to_string_lossy().to_string();
 
-    // --- Find or Create Album ---
-    // Use finalized metadata for album lookup/creation
-    let album_doc = albums_collection
-        .find_one(doc! { "name": &album_title, "artist": &artist }, None)
-        .await
-        .map_err(|e| UploadError::MongoDbError(format!("Album lookup failed: {}", e)))?;
-
-    let album_id = match album_doc {
-        Some(doc) => doc.get_object_id("_id").map_err(|_| UploadError::MongoDbError("Invalid album ID format".to_string()))?,
-        None => {
-            // Create new album using finalized metadata
-            let new_album_id = ObjectId::new();
-            let new_album_doc = doc! {
-                "_id": new_album_id,
-                "name": &album_title,
-                "artist": &artist,
-                "year": year, // Use finalized year
-                "genres": if let Some(g) = &genre { vec![g.clone()] } else { Vec::<String>::new() }, // Use finalized genre
-                "art_path": null, // Placeholder for album art
-                "date_added": bson::DateTime::now(),
-            };
-            albums_collection.insert_one(new_album_doc, None).await.map_err(|e| UploadError::MongoDbError(format!("Album insert failed: {}", e)))?;
-            info!("Created new album '{}' with ID: {}", album_title, new_album_id);
-            new_album_id
-        }
-    };
+    let album = resolve_album(&item.metadata);
+    // Decided once, so a retried transaction reuses the same new album id
+    let explicit_id = explicit_album_id(item.album_matching, &item.metadata).map_err(UploadError::InvalidInput)?;
+
+    // Public URL ids; the album slug is only stored if the album is new
+    let slug_error = |e: mongodb::error::Error| UploadError::MongoDbError(format!("Slug lookup failed: {}", e));
+    let track_slug = unique_slug(&tracks_collection, &slug_base(&title, &artist), None).await.map_err(slug_error)?;
+    let album_slug = unique_slug(&albums_collection, &slug_base(&album.name, &album.artist), None).await.map_err(slug_error)?;
 
     // --- Create Track Document ---
-    let track_id = ObjectId::new();
+    // `album_id` is added once the album has been found or created
     let track_doc = doc! {
         "_id": track_id,
         "title": title,
+        "slug": track_slug,
         "filename": item.input_path.file_name().unwrap_or_default().to_string_lossy().to_string(),
         "duration": duration_sec, // Use finalized duration
         "track_number": track_number, // Use finalized track number
-        "album_id": album_id,
         "artists": vec![artist.clone()], // Assuming single artist for now from finalized metadata
-        "original_path": item.input_path.to_string_lossy().to_string(),
+        "original_path": original_path,
+        "original_location": original_location,
         "mime_type": mime_type,
         "file_size": file_size as i64, // Store as i64 for BSON compatibility
         "writers": bson::Document::new(), // Placeholder - Should this be part of finalized metadata?
         "publishers": bson::Document::new(), // Placeholder - Should this be part of finalized metadata?
-        "genre": if let Some(g) = genre { vec![g] } else { Vec::<String>::new() }, // Use finalized genre
-        "composer": composer, // Use finalized composer
+        "genre": genres, // Use normalized genre
+        "composers": composers,
         "instruments": Vec::<String>::new(), // Placeholder - Should this be part of finalized metadata?
         "mood": Vec::<String>::new(), // Placeholder - Should this be part of finalized metadata?
         "comments": comments, // Use finalized comments
+        "custom_fields": custom_fields,
+        "codec": item.metadata.codec.clone(),
+        "bitrate": item.metadata.bitrate,
+        "sample_rate": item.metadata.sample_rate,
+        "channels": item.metadata.channels.map(i32::from),
+        "bpm": features.bpm, // Estimated during upload
+        "musical_key": features.musical_key.clone(), // Estimated during upload
+        "loudness_lufs": loudness.map(|l| l.loudness_lufs), // Measured during upload
+        "replaygain_db": loudness.map(|l| l.replaygain_db),
         "date_added": bson::DateTime::now(),
         "extension": file_extension,
-        "r2_original_key": original_r2_key,
-        "r2_aac_key": aac_r2_key,
+        "r2_bucket": bucket_name,
+        "visibility": item.visibility.as_str(),
+        "r2_archive_key": archive_r2_key,
+        "archive_format": archive_r2_key.map(|_| item.formats.archive.name()),
+        "archive_mime_type": archive_r2_key.map(|_| item.formats.archive.mime_type(&item.input_path)),
+        "r2_delivery_key": delivery_r2_key,
+        "delivery_format": item.formats.delivery.transcode_format().name(),
+        "delivery_mime_type": delivery_r2_key.map(|_| item.formats.delivery.transcode_format().mime_type()),
+        // Legacy fields, still read by playback and older tooling
+        "r2_original_key": archive_r2_key.filter(|_| item.formats.archive == ArchiveFormat::Original),
+        "r2_aac_key": delivery_r2_key.filter(|_| item.formats.delivery == DeliveryFormat::Aac),
         // Add other fields as needed based on finalized metadata
     };
 
-    // --- Insert Track ---
-    tracks_collection.insert_one(track_doc, None).await.map_err(|e| UploadError::MongoDbError(format!("Track insert failed: {}", e)))?;
+    // Left out when unset: the unique ISRC index is sparse, so a stored null would collide
+    let mut track_doc = track_doc;
+    let isrc = item.metadata.isrc.as_deref().map(str::trim).filter(|isrc| !isrc.is_empty())
+        .map(normalize_isrc).transpose().map_err(UploadError::InvalidInput)?;
+    if let Some(isrc) = &isrc {
+        track_doc.insert("isrc", isrc);
+    }
+    if let Some(hash) = &item.original_sha256 {
+        track_doc.insert("original_sha256", hash);
+    }
+    if let Some(catalog_number) = item.metadata.catalog_number.as_deref().map(str::trim).filter(|number| !number.is_empty()) {
+        track_doc.insert("catalog_number", catalog_number);
+    }
+    // Only the archive copy is encrypted; delivery copies stay playable
+    if let Some(key_id) = &item.encryption_key_id {
+        track_doc.insert("encrypted", true);
+        track_doc.insert("encryption_key_id", key_id);
+    }
+    if item.staging {
+        track_doc.insert("status", STAGED_STATUS);
+        // Promotion resolves a taken final key with the same policy
+        if let Ok(policy) = mongodb::bson::to_bson(&item.on_key_collision) {
+            track_doc.insert(COLLISION_POLICY_FIELD, policy);
+        }
+    }
+
+    stamp(&mut track_doc);
+
+    // --- Find or Create Album and Insert Track ---
+    if use_transactions {
+        store_in_transaction(mongo_client, &db, &album, explicit_id, year, &genres, &album_slug, &track_doc, isrc.as_deref()).await?;
+    } else {
+        let album_id = resolve_album_id(&albums_collection, &album, explicit_id, year, &genres, &album_slug).await?;
+        track_doc.insert("album_id", album_id);
+        insert_track(&tracks_collection, track_doc, isrc.as_deref()).await?;
+    }
     info!("Stored track metadata for '{}' with ID: {}", item.input_path.display(), track_id);
 
     Ok(track_id.to_hex())
@@ -552,10 +2283,14 @@ async fn update_progress(app_handle: &AppHandle<Wry>, progress_map: &Arc<Mutex<H
         error_message: None,
         title: metadata.title.clone(),
         album: metadata.album.clone(),
+        track_id: None,
+        throughput_bytes_per_sec: None,
+        local_delivery_path: None,
     });
 
     progress.status = status;
     progress.error_message = error_message;
+    progress.throughput_bytes_per_sec = None; // Set by `with_throughput_updates` while a transfer runs
 
     // Emit update event - Clone progress before emitting
     if let Some(window) = app_handle.get_webview_window("main") {
@@ -566,6 +2301,30 @@ async fn update_progress(app_handle: &AppHandle<Wry>, progress_map: &Arc<Mutex<H
     } else { error!("Could not find main window to emit status update for {}.", item_id); }
 }
 
+/// Called between phases: if the queue is paused, reports the item as Paused and waits.
+async fn pause_point(app_handle: &AppHandle<Wry>, state: &UploadState, item_id: Uuid, metadata: &UploadItemMetadata, original_path: &str) {
+    if !state.paused.load(Ordering::SeqCst) || state.cancel_flag.load(Ordering::SeqCst) {
+        return;
+    }
+    info!("Upload queue paused at item {}", item_id);
+    update_progress(app_handle, &state.progress_map, item_id, UploadStatus::Paused, None, metadata, original_path).await;
+    state.wait_while_paused().await;
+    info!("Upload queue continuing with item {}", item_id);
+}
+
+/// Moves every item with status `from` to `to`, emitting an update for each.
+async fn replace_status(app_handle: &AppHandle<Wry>, progress_map: &Arc<Mutex<HashMap<Uuid, UploadProgress>>>, from: &UploadStatus, to: UploadStatus) {
+    let mut map = progress_map.lock().await;
+    for progress in map.values_mut().filter(|progress| &progress.status == from) {
+        progress.status = to.clone();
+        if let Some(window) = app_handle.get_webview_window("main") {
+            window.emit("upload://status-update", progress.clone()).unwrap_or_else(|e| {
+                error!("Failed to emit status update for {}: {}", progress.item_id, e);
+            });
+        }
+    }
+}
+
 fn cleanup_temp_file(path: &Path) {
     if let Err(e) = std::fs::remove_file(path) {
         warn!("Failed to clean up temporary file {:?}: {}", path, e);
@@ -589,12 +2348,13 @@ async fn delete_mongodb_track(mongo_client: &MongoDbClient, track_id_hex: &str)
     info!("Attempting to delete MongoDB track: {}", track_id_hex);
     match ObjectId::parse_str(track_id_hex) {
         Ok(oid) => {
-            let db = mongo_client.database("music_library");
-            let tracks_collection = db.collection::<Document>("tracks");
+            let db = mongo_client.catalog_database();
+            let tracks_collection = db.tracks::<Document>();
             if let Err(e) = tracks_collection.delete_one(doc! { "_id": oid }, None).await {
                 error!("Failed to delete MongoDB track {}: {}", track_id_hex, e);
             } else {
                 info!("Successfully deleted MongoDB track: {}", track_id_hex);
+                record_deletions(&db, CatalogKind::Track, vec![track_id_hex.to_string()]).await;
             }
         }
         Err(e) => {
@@ -603,10 +2363,264 @@ async fn delete_mongodb_track(mongo_client: &MongoDbClient, track_id_hex: &str)
     }
 }
 
+/// Records `key` as uploaded by this item, so `perform_cleanup` deletes it, only when the
+/// upload succeeded. After a failed one the key may hold an object that was already there,
+/// e.g. the one an `Overwrite` upload was about to replace.
+fn record_uploaded_key(slot: &mut Option<String>, key: &str, result: &Result<(), UploadError>) {
+    if result.is_ok() {
+        *slot = Some(key.to_string());
+    }
+}
+
 async fn perform_cleanup(r2_client: &S3Client, bucket_name: &str, mongo_client: &MongoDbClient, item: &UploadQueueItem) {
     warn!("Performing cleanup for failed/cancelled item: {}", item.id);
-    if let Some(path) = &item.temp_aac_path { cleanup_temp_file(path); }
-    if let Some(key) = &item.r2_original_key { delete_r2_object(r2_client, bucket_name, key).await; }
-    if let Some(key) = &item.r2_aac_key { delete_r2_object(r2_client, bucket_name, key).await; }
+    if let Some(path) = &item.temp_delivery_path { cleanup_temp_file(path); }
+    if let Some(path) = &item.temp_archive_path { cleanup_temp_file(path); }
+    if let Some(path) = &item.temp_encrypted_path { cleanup_temp_file(path); }
+    if let Some(key) = &item.r2_archive_key { delete_r2_object(r2_client, bucket_name, key).await; }
+    if let Some(key) = &item.r2_delivery_key { delete_r2_object(r2_client, bucket_name, key).await; }
     if let Some(id) = &item.db_track_id { delete_mongodb_track(mongo_client, id).await; }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn metadata(title: Option<&str>, artist: Option<&str>, album: Option<&str>) -> UploadItemMetadata {
+        UploadItemMetadata {
+            title: title.map(String::from), artist: artist.map(String::from), album: album.map(String::from),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_topology_supports_transactions() {
+        assert!(topology_supports_transactions(&doc! { "isWritablePrimary": true, "setName": "atlas-abc-shard-0" }));
+        assert!(topology_supports_transactions(&doc! { "isWritablePrimary": true, "msg": "isdbgrid" }));
+        assert!(!topology_supports_transactions(&doc! { "isWritablePrimary": true }));
+    }
+
+    #[tokio::test]
+    async fn test_failed_overwrite_leaves_the_existing_object() {
+        let mut bucket = HashSet::from(["k/master.wav".to_string()]);
+        let upload = |_key: String| async move { Err(UploadError::TimedOut(60)) };
+        let (key, result) = upload_with_policy("k/master.wav".to_string(), KeyCollisionPolicy::Overwrite, upload).await;
+        let mut r2_archive_key = None;
+        record_uploaded_key(&mut r2_archive_key, &key, &result);
+        // What perform_cleanup would delete
+        if let Some(key) = &r2_archive_key {
+            bucket.remove(key);
+        }
+        assert!(bucket.contains("k/master.wav"));
+
+        let (key, result) = upload_with_policy("k/master.wav".to_string(), KeyCollisionPolicy::Overwrite, |_key: String| async move { Ok(()) }).await;
+        record_uploaded_key(&mut r2_archive_key, &key, &result);
+        assert_eq!(r2_archive_key.as_deref(), Some("k/master.wav"));
+    }
+
+    #[test]
+    fn test_batch_progress() {
+        let ids: Vec<Uuid> = (0..4).map(|_| Uuid::new_v4()).collect();
+        let statuses = [UploadStatus::Complete, UploadStatus::error(ErrorCategory::Transcode, "bad"), UploadStatus::Cancelled, UploadStatus::Transcoding];
+        let progress_map: HashMap<Uuid, UploadProgress> = ids.iter().zip(statuses)
+            .map(|(id, status)| (*id, UploadProgress {
+                item_id: *id, original_path: String::new(), status, error_message: None,
+                title: None, album: None, track_id: None, throughput_bytes_per_sec: None, local_delivery_path: None,
+            }))
+            .collect();
+        assert_eq!(batch_progress(&progress_map, &ids), BatchProgress { completed: 1, failed: 1, total: 4, percent: 75.0 });
+        assert_eq!(batch_progress(&progress_map, &ids[..2]).percent, 100.0);
+        assert_eq!(batch_progress(&progress_map, &[]).total, 0);
+    }
+
+    #[test]
+    fn test_upload_status_is_tagged_by_kind() {
+        assert_eq!(serde_json::to_value(UploadStatus::Pending).unwrap(), serde_json::json!({ "kind": "Pending" }));
+        assert_eq!(
+            serde_json::to_value(UploadStatus::error(ErrorCategory::Auth, "R2 refused the key")).unwrap(),
+            serde_json::json!({ "kind": "Error", "category": "Auth", "message": "R2 refused the key" })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_wait_while_paused_until_resumed() {
+        let state = Arc::new(UploadState::new());
+        state.wait_while_paused().await; // Not paused: returns immediately
+
+        state.paused.store(true, Ordering::SeqCst);
+        assert_eq!(state.queue_state(), UploadQueueState::Paused);
+        let waiter = tokio::spawn({
+            let state = Arc::clone(&state);
+            async move { state.wait_while_paused().await }
+        });
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert!(!waiter.is_finished());
+
+        state.paused.store(false, Ordering::SeqCst);
+        state.resume_notify.notify_waiters();
+        tokio::time::timeout(Duration::from_secs(1), waiter).await.expect("worker should resume").unwrap();
+        assert_eq!(state.queue_state(), UploadQueueState::Idle);
+    }
+
+    #[tokio::test]
+    async fn test_cancel_wakes_paused_worker() {
+        let state = Arc::new(UploadState::new());
+        state.paused.store(true, Ordering::SeqCst);
+        let waiter = tokio::spawn({
+            let state = Arc::clone(&state);
+            async move { state.wait_while_paused().await }
+        });
+        tokio::time::sleep(Duration::from_millis(20)).await;
+
+        state.cancel_flag.store(true, Ordering::SeqCst);
+        state.resume_notify.notify_waiters();
+        tokio::time::timeout(Duration::from_secs(1), waiter).await.expect("cancel should wake the worker").unwrap();
+    }
+
+    #[test]
+    fn test_draining_stops_handing_out_items() {
+        let state = UploadState::new();
+        state.pending.push(UploadQueueItem {
+            id: Uuid::new_v4(), input_path: PathBuf::from("/music/take.wav"), metadata: UploadItemMetadata::default(),
+            temp_delivery_path: None, temp_archive_path: None, temp_encrypted_path: None, encryption_key_id: None,
+            r2_archive_key: None, r2_delivery_key: None, db_track_id: None,
+            on_key_collision: Default::default(), formats: UploadFormats::default(), transcoding: Default::default(), bucket_override: None,
+            visibility: Default::default(), album_matching: Default::default(), remote_source: None,
+            upload_originals: true, original_sha256: None, staging: false, keep_transcoded_dir: None, idempotency_key: None,
+        });
+
+        state.draining.store(true, Ordering::SeqCst);
+        assert!(state.next_item().is_none());
+        assert_eq!(state.pending.len(), 1);
+        state.draining.store(false, Ordering::SeqCst);
+        assert!(state.next_item().is_some());
+    }
+
+    #[test]
+    fn test_final_statuses() {
+        assert!(is_final_status(&UploadStatus::Complete));
+        assert!(is_final_status(&UploadStatus::error(ErrorCategory::NetworkTransient, "timed out")));
+        assert!(!is_final_status(&UploadStatus::Paused));
+        assert!(!is_final_status(&UploadStatus::StoringMetadata));
+    }
+
+    #[tokio::test]
+    async fn test_idempotency_keys_are_claimed_once() {
+        let state = UploadState::new();
+        assert!(state.claim_idempotency_key("batch-1/item-1").await);
+        assert!(state.claim_idempotency_key("batch-1/item-2").await);
+        assert!(!state.claim_idempotency_key("batch-1/item-1").await);
+    }
+
+    #[test]
+    fn test_source_and_archive_mime_types() {
+        assert_eq!(source_mime_type(Path::new("/music/Track.WAV")), "audio/wav");
+        assert_eq!(source_mime_type(Path::new("song.m4a")), "audio/mp4");
+        assert_eq!(source_mime_type(Path::new("song.flac")), "audio/flac");
+        assert_eq!(source_mime_type(Path::new("notes.txt")), "text/plain");
+        assert_eq!(ArchiveFormat::Flac.mime_type(Path::new("song.wav")), "audio/flac");
+        assert_eq!(ArchiveFormat::Original.mime_type(Path::new("song.aiff")), "audio/aiff");
+    }
+
+    #[test]
+    fn test_upload_error_category() {
+        let category = |err: UploadError| upload_error_category(&err);
+        assert_eq!(category(UploadError::R2UploadError("S3 PutObject failed: dispatch failure".to_string())), ErrorCategory::NetworkTransient);
+        assert_eq!(category(UploadError::R2UploadError("S3 PutObject failed: SignatureDoesNotMatch".to_string())), ErrorCategory::Auth);
+        assert_eq!(category(UploadError::InvalidInput("ISRC taken".to_string())), ErrorCategory::Metadata);
+        assert_eq!(
+            category(UploadError::TranscodingError(TranscodingError::ProcessExecutionFailed { status: Some(1), stderr: String::new() })),
+            ErrorCategory::CorruptInput
+        );
+        assert!(ErrorCategory::NetworkTransient.is_retryable());
+        assert!(!ErrorCategory::CorruptInput.is_retryable());
+        assert!(!ErrorCategory::Auth.is_retryable());
+    }
+
+    #[test]
+    fn test_explicit_album_id() {
+        let oid = ObjectId::new();
+        let with_id = |album_id: Option<&str>| UploadItemMetadata { album_id: album_id.map(String::from), ..Default::default() };
+        assert_eq!(explicit_album_id(AlbumMatching::ByNameArtist, &with_id(Some(&oid.to_hex()))), Ok(None));
+        assert_eq!(explicit_album_id(AlbumMatching::ByExplicitId, &with_id(Some(&oid.to_hex()))), Ok(Some(oid)));
+        assert!(explicit_album_id(AlbumMatching::ByExplicitId, &with_id(None)).is_err());
+        assert!(explicit_album_id(AlbumMatching::ByExplicitId, &with_id(Some("album-1"))).is_err());
+        assert!(matches!(explicit_album_id(AlbumMatching::AlwaysNew, &with_id(None)), Ok(Some(_))));
+
+        let album = AlbumIdentity { name: "Album".into(), artist: "Artist".into(), compilation: false };
+        let (filter, update, _) = album_upsert(&album, Some(oid), None, &[], "album-artist");
+        assert_eq!(filter, doc! { "_id": oid });
+        assert_eq!(update.get_document("$setOnInsert").unwrap().get_str("grouping_key"), Ok(oid.to_hex().as_str()));
+    }
+
+    #[test]
+    fn test_missing_required_fields() {
+        assert!(missing_required_fields(&metadata(Some("Song"), Some("Artist"), Some("Album"))).is_empty());
+        assert_eq!(missing_required_fields(&metadata(Some("Song"), Some("  "), None)), vec!["artist", "album"]);
+        assert_eq!(missing_required_fields(&metadata(None, None, None)), vec!["title", "artist", "album"]);
+    }
+
+    #[test]
+    fn test_resolve_album() {
+        let identity = |name: &str, artist: &str, compilation| AlbumIdentity { name: name.into(), artist: artist.into(), compilation };
+        let with = |album_artist: Option<&str>, compilation, base: UploadItemMetadata| UploadItemMetadata {
+            album_artist: album_artist.map(String::from), compilation, ..base
+        };
+        let cases = [
+            // Tagged albums match on album artist, falling back to the track artist
+            (metadata(None, Some("Artist"), Some("Album")), identity("Album", "Artist", false)),
+            (with(Some("Band"), false, metadata(None, Some("Band feat. Guest"), Some("Album"))), identity("Album", "Band", false)),
+            (with(Some("  "), false, metadata(None, Some("Artist"), Some(" Album "))), identity("Album", "Artist", false)),
+            // Untagged tracks go to the artist's singles album
+            (metadata(None, Some("Artist"), None), identity("[Singles]", "Artist", false)),
+            (metadata(None, None, Some(" ")), identity("[Singles]", "Unknown Artist", false)),
+            (with(Some("Band"), true, metadata(None, Some("Artist"), None)), identity("[Singles]", "Band", false)),
+            // Compilations ignore the track artist
+            (with(None, true, metadata(None, Some("Artist"), Some("Hits"))), identity("Hits", "Various Artists", true)),
+            (with(Some("DJ Mix"), true, metadata(None, Some("Artist"), Some("Hits"))), identity("Hits", "DJ Mix", true)),
+        ];
+        for (input, expected) in cases {
+            assert_eq!(resolve_album(&input), expected, "input: {:?}", input);
+        }
+        assert_eq!(identity("Hits", "Various Artists", true).filter(), doc! { "name": "Hits", "compilation": true, "grouping_key": null });
+        assert_eq!(identity("Album", "Artist", false).filter(), doc! { "name": "Album", "artist": "Artist", "grouping_key": null });
+        // A compilation that collided with an album stored without the `compilation` flag
+        let hits = identity("Hits", "Various Artists", true);
+        assert_eq!(album_retry_filter(&hits, hits.filter(), None), doc! { "name": "Hits", "artist": "Various Artists", "grouping_key": null });
+        let oid = ObjectId::new();
+        assert_eq!(album_retry_filter(&hits, doc! { "_id": oid }, Some(oid)), doc! { "_id": oid });
+    }
+
+    // Run with `MONGODB_TEST_URI=mongodb://... cargo test -- --ignored`
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    #[ignore = "requires a MongoDB server (MONGODB_TEST_URI)"]
+    async fn test_concurrent_tracks_for_new_album_create_one_album() {
+        let uri = std::env::var("MONGODB_TEST_URI").expect("MONGODB_TEST_URI is not set");
+        let client = MongoDbClient::with_uri_str(&uri).await.unwrap();
+        let db = client.database(&format!("album_race_test_{}", Uuid::new_v4().simple()));
+        crate::features::catalog::migrations::run_migrations(&db, &crate::features::catalog::album_names::AlbumNameCache::default()).await.unwrap();
+        let album = resolve_album(&metadata(None, Some("New Artist"), Some("New Album")));
+
+        let mut tasks = Vec::new();
+        for n in 0..10 {
+            let (db, album) = (db.clone(), album.clone());
+            tasks.push(tokio::spawn(async move {
+                let album_id = resolve_album_id(&db.albums(), &album, None, Some(2024), &[], "new-artist-new-album").await.unwrap();
+                db.tracks::<Document>()
+                    .insert_one(doc! { "title": format!("Track {}", n), "album_id": album_id }, None)
+                    .await
+                    .unwrap();
+                album_id
+            }));
+        }
+        let mut album_ids = HashSet::new();
+        for task in tasks {
+            album_ids.insert(task.await.unwrap());
+        }
+
+        let album_count = db.albums::<Document>().count_documents(None, None).await.unwrap();
+        db.drop(None).await.unwrap();
+        assert_eq!(album_count, 1);
+        assert_eq!(album_ids.len(), 1, "every track points at the same album");
+    }
+}
@@ -0,0 +1,71 @@
+//! Originals kept out of R2. With `upload_originals` off only the delivery copy is
+//! uploaded, and the track stores no archive key. It records `original_location: "local"`,
+//! the absolute path of the file it was made from and a SHA-256 of that file instead, so
+//! the original can be found on local storage later and checked to be the same file.
+
+use mongodb::bson::Document;
+use sha2::{Digest, Sha256};
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+
+/// `original_location` of tracks whose original was kept locally.
+pub const LOCAL_ORIGINAL_LOCATION: &str = "local";
+
+/// `original_location` of tracks whose original was uploaded.
+pub const R2_ORIGINAL_LOCATION: &str = "r2";
+
+/// Hex SHA-256 of the file's content. Blocking: call from a blocking task.
+pub fn hash_file(path: &Path) -> io::Result<String> {
+    let mut file = File::open(path)?;
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0u8; 1024 * 1024];
+    loop {
+        let read = file.read(&mut buf)?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buf[..read]);
+    }
+    Ok(hasher.finalize().iter().map(|byte| format!("{:02x}", byte)).collect())
+}
+
+/// The path to record for a local original: absolute with links resolved, or as given
+/// if the file can't be resolved.
+pub fn absolute_original_path(path: &Path) -> PathBuf {
+    std::fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf())
+}
+
+/// Whether the track's original was never uploaded.
+pub fn is_local_original(track_doc: &Document) -> bool {
+    track_doc.get_str("original_location").ok() == Some(LOCAL_ORIGINAL_LOCATION)
+}
+
+/// Where a local-only original was when it was uploaded, for error messages.
+pub fn local_original_description(track_doc: &Document) -> String {
+    match track_doc.get_str("original_path").ok().filter(|path| !path.is_empty()) {
+        Some(path) => format!("kept on local storage at {}", path),
+        None => "kept on local storage".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mongodb::bson::doc;
+
+    #[test]
+    fn test_hash_file_and_local_original_detection() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("take.wav");
+        std::fs::write(&path, b"abc").unwrap();
+        assert_eq!(hash_file(&path).unwrap(), "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad");
+        assert!(absolute_original_path(&path).is_absolute());
+
+        let local = doc! { "original_location": "local", "original_path": "/nas/take.wav", "r2_original_key": null };
+        assert!(is_local_original(&local));
+        assert_eq!(local_original_description(&local), "kept on local storage at /nas/take.wav");
+        assert!(!is_local_original(&doc! { "original_location": "r2" }));
+        assert!(!is_local_original(&doc! { "r2_original_key": "tracks/original/a.wav" }));
+    }
+}
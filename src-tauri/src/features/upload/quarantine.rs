@@ -0,0 +1,364 @@
+//! Items that failed because of their source file, such as a truncated WAV or a zero-byte
+//! bounce, kept in `quarantine.json` so the user knows which files to export again. An
+//! item is quarantined when it is rejected as it is queued or fails with
+//! `ErrorCategory::CorruptInput`; a later failure of the same file replaces its entry.
+//!
+//! `requeue_quarantined_items` checks each file again before queueing it, so a file that
+//! is still broken stays quarantined with the new error. Entries are only removed once
+//! their items are queued. Every change emits `quarantine://updated` with the full list.
+
+use chrono::{DateTime, Utc};
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tauri::{command, AppHandle, Emitter, Manager, State, Wry};
+use tokio::sync::Mutex;
+use uuid::Uuid;
+
+use super::limits::{validate_input_file, InputFileError, UploadLimits};
+use super::{enqueue_items, ErrorCategory, UploadItemInput, UploadItemMetadata, UploadOptions, UploadProgress, UploadState, UploadStatus};
+use crate::core::json_file::write_json_atomically;
+
+const QUARANTINE_FILE_NAME: &str = "quarantine.json";
+
+/// A file that failed to upload and has to be fixed before it is queued again.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct QuarantinedItem {
+    pub id: String,
+    pub path: String,
+    pub error: String,
+    /// When the file last failed
+    pub quarantined_at: DateTime<Utc>,
+    /// Metadata the item was queued with, used again when it is re-queued
+    pub metadata: UploadItemMetadata,
+    pub options: UploadOptions,
+    pub bucket_override: Option<String>,
+}
+
+impl QuarantinedItem {
+    pub fn new(input: &UploadItemInput, options: &UploadOptions, bucket_override: Option<&str>, error: impl Into<String>) -> Self {
+        Self {
+            id: Uuid::new_v4().to_string(),
+            path: input.path.clone(),
+            error: error.into(),
+            quarantined_at: Utc::now(),
+            metadata: input.metadata.clone(),
+            options: UploadOptions {
+                // Checked when the item was first queued
+                require_complete_metadata: false,
+                ..options.clone()
+            },
+            bucket_override: bucket_override.map(str::to_string),
+        }
+    }
+
+    fn input(&self) -> UploadItemInput {
+        UploadItemInput { id: self.id.clone(), path: self.path.clone(), metadata: self.metadata.clone(), idempotency_key: None }
+    }
+}
+
+/// Result of `requeue_quarantined_items`.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct RequeueResult {
+    /// Upload ids of the items queued again, for following their progress
+    pub item_ids: Vec<Uuid>,
+    /// Items that failed again and stay quarantined
+    pub failed: Vec<QuarantinedItem>,
+}
+
+/// The quarantined items, read from disk on first use. Holding the lock also keeps
+/// writers of the file apart.
+#[derive(Debug, Default)]
+pub struct Quarantine {
+    items: Mutex<Option<Vec<QuarantinedItem>>>,
+    // Held by a re-queue from reading its entries to removing them, so two re-queues
+    // can't queue the same entry
+    requeueing: Mutex<()>,
+}
+
+impl Quarantine {
+    /// Applies `change` to the items and writes them back to `path`.
+    async fn update<R>(&self, path: &Path, change: impl FnOnce(&mut Vec<QuarantinedItem>) -> R) -> Result<(R, Vec<QuarantinedItem>), String> {
+        let mut items = self.items.lock().await;
+        let items = items.get_or_insert_with(|| load_quarantine_file(path));
+        let result = change(items);
+        save_quarantine_file(path, items)?;
+        Ok((result, items.clone()))
+    }
+
+    async fn list(&self, path: &Path) -> Vec<QuarantinedItem> {
+        self.items.lock().await.get_or_insert_with(|| load_quarantine_file(path)).clone()
+    }
+}
+
+fn quarantine_path(app_handle: &AppHandle<Wry>) -> Result<PathBuf, String> {
+    let dir = app_handle.path().app_data_dir()
+        .map_err(|e| format!("Failed to resolve app data directory: {}", e))?;
+    Ok(dir.join(QUARANTINE_FILE_NAME))
+}
+
+fn load_quarantine_file(path: &Path) -> Vec<QuarantinedItem> {
+    match fs::read_to_string(path) {
+        Ok(json) => serde_json::from_str(&json).unwrap_or_else(|e| {
+            warn!("Invalid quarantine file {:?}, starting empty: {}", path, e);
+            Vec::new()
+        }),
+        Err(_) => Vec::new(), // Nothing quarantined yet
+    }
+}
+
+fn save_quarantine_file(path: &Path, items: &[QuarantinedItem]) -> Result<(), String> {
+    write_json_atomically(path, items).map_err(|e| format!("Failed to write {:?}: {}", path, e))
+}
+
+/// Adds the entries, replacing the entry of a file that is already quarantined but
+/// keeping its id.
+fn add_entries(items: &mut Vec<QuarantinedItem>, entries: Vec<QuarantinedItem>) {
+    for entry in entries {
+        match items.iter_mut().find(|item| item.path == entry.path) {
+            Some(existing) => *existing = QuarantinedItem { id: existing.id.clone(), ..entry },
+            None => items.push(entry),
+        }
+    }
+}
+
+/// Removes the entries of items that were queued again. An entry quarantined again in
+/// the meantime, e.g. because its file was rejected as it was queued, is kept.
+fn remove_requeued(items: &mut Vec<QuarantinedItem>, requeued: &[QuarantinedItem]) {
+    items.retain(|item| !requeued.iter().any(|queued| queued.id == item.id && queued.quarantined_at == item.quarantined_at));
+}
+
+/// Checks the files of `entries` again. Returns the entries whose file passes and the
+/// ones that still fail, carrying the new error.
+async fn validate_entries(entries: Vec<QuarantinedItem>, limits: UploadLimits) -> (Vec<QuarantinedItem>, Vec<QuarantinedItem>) {
+    let mut valid = Vec::new();
+    let mut failed = Vec::new();
+    for mut item in entries {
+        let validation_path = PathBuf::from(&item.path);
+        let validation = tokio::task::spawn_blocking(move || validate_input_file(&validation_path, &limits))
+            .await
+            .unwrap_or_else(|e| Err(InputFileError::Invalid(format!("Validation task failed: {}", e))));
+        match validation.map_err(|e| e.to_string()) {
+            Ok(()) => valid.push(item),
+            Err(message) => {
+                warn!("{} is still unusable: {}", item.path, message);
+                item.error = message;
+                item.quarantined_at = Utc::now();
+                failed.push(item);
+            }
+        }
+    }
+    (valid, failed)
+}
+
+/// Removes and returns the items with the given ids, in quarantine order.
+fn take_entries(items: &mut Vec<QuarantinedItem>, ids: &[String]) -> Vec<QuarantinedItem> {
+    let (taken, kept): (Vec<_>, Vec<_>) = std::mem::take(items).into_iter().partition(|item| ids.contains(&item.id));
+    *items = kept;
+    taken
+}
+
+/// The error to quarantine an item for, if it failed because of its file.
+pub fn file_error(progress: &UploadProgress) -> Option<String> {
+    match &progress.status {
+        UploadStatus::Error { category: ErrorCategory::CorruptInput, message } => {
+            Some(progress.error_message.clone().unwrap_or_else(|| message.clone()))
+        }
+        _ => None,
+    }
+}
+
+fn emit_updated(app_handle: &AppHandle<Wry>, items: &[QuarantinedItem]) {
+    if let Err(e) = app_handle.emit("quarantine://updated", items) {
+        error!("Failed to emit quarantine update: {}", e);
+    }
+}
+
+async fn update_and_emit<R>(
+    app_handle: &AppHandle<Wry>,
+    upload_state: &UploadState,
+    change: impl FnOnce(&mut Vec<QuarantinedItem>) -> R,
+) -> Result<(R, Vec<QuarantinedItem>), String> {
+    let path = quarantine_path(app_handle)?;
+    let (result, items) = upload_state.quarantine.update(&path, change).await?;
+    emit_updated(app_handle, &items);
+    Ok((result, items))
+}
+
+/// Records failed items. Best effort: a failure is logged and the items are only lost
+/// from the quarantine list.
+pub async fn quarantine_items(app_handle: &AppHandle<Wry>, upload_state: &UploadState, entries: Vec<QuarantinedItem>) {
+    if entries.is_empty() {
+        return;
+    }
+    let count = entries.len();
+    match update_and_emit(app_handle, upload_state, |items| add_entries(items, entries)).await {
+        Ok(_) => info!("Quarantined {} item(s) with unusable source files", count),
+        Err(e) => error!("Failed to quarantine {} item(s): {}", count, e),
+    }
+}
+
+// --- Tauri Commands ---
+
+#[command]
+pub async fn list_quarantined_items(
+    app_handle: AppHandle<Wry>,
+    upload_state: State<'_, Arc<UploadState>>,
+) -> Result<Vec<QuarantinedItem>, String> {
+    let path = quarantine_path(&app_handle)?;
+    Ok(upload_state.quarantine.list(&path).await)
+}
+
+/// Checks the files of the given items again and queues the ones that pass with the
+/// metadata and options they failed with. Items whose file still fails stay quarantined
+/// with the new error.
+#[command]
+pub async fn requeue_quarantined_items(
+    ids: Vec<String>,
+    app_handle: AppHandle<Wry>,
+    upload_state: State<'_, Arc<UploadState>>,
+    r2_state: State<'_, crate::R2State>,
+    mongo_state: State<'_, crate::MongoState>,
+) -> Result<RequeueResult, String> {
+    let path = quarantine_path(&app_handle)?;
+    let _requeueing = upload_state.quarantine.requeueing.lock().await;
+    let entries: Vec<QuarantinedItem> = upload_state.quarantine.list(&path).await.into_iter()
+        .filter(|item| ids.contains(&item.id))
+        .collect();
+    if entries.is_empty() {
+        return Err(format!("None of the {} item(s) are quarantined", ids.len()));
+    }
+
+    let limits = *upload_state.limits.lock().await;
+    let (valid, failed) = validate_entries(entries, limits).await;
+    let mut result = RequeueResult { failed, ..Default::default() };
+
+    // Queued in runs of items with the same options, as `start_upload_queue` takes one set per call
+    let mut requeued = Vec::new();
+    let mut remaining = valid.into_iter().peekable();
+    while let Some(first) = remaining.next() {
+        let same_options = |item: &QuarantinedItem| {
+            item.bucket_override == first.bucket_override
+                && serde_json::to_value(&item.options).ok() == serde_json::to_value(&first.options).ok()
+        };
+        let mut batch = vec![first.clone()];
+        while let Some(item) = remaining.next_if(same_options) {
+            batch.push(item);
+        }
+        let inputs = batch.iter().map(QuarantinedItem::input).collect();
+        match enqueue_items(inputs, Some(first.options.clone()), first.bucket_override.clone(), None, &app_handle, &upload_state, &r2_state, &mongo_state).await {
+            Ok(item_ids) => {
+                result.item_ids.extend(item_ids);
+                requeued.extend(batch);
+            }
+            Err(e) => {
+                warn!("Failed to queue {} quarantined item(s) again: {}", batch.len(), e);
+                result.failed.extend(batch.into_iter().map(|item| QuarantinedItem { error: e.clone(), quarantined_at: Utc::now(), ..item }));
+            }
+        }
+    }
+
+    let failed = result.failed.clone();
+    update_and_emit(&app_handle, &upload_state, |items| {
+        remove_requeued(items, &requeued);
+        add_entries(items, failed);
+    }).await?;
+    info!("Queued {} quarantined item(s) again; {} still failing", result.item_ids.len(), result.failed.len());
+    Ok(result)
+}
+
+/// Forgets the given items without queueing them. Returns the items still quarantined.
+#[command]
+pub async fn dismiss_quarantined_items(
+    ids: Vec<String>,
+    app_handle: AppHandle<Wry>,
+    upload_state: State<'_, Arc<UploadState>>,
+) -> Result<Vec<QuarantinedItem>, String> {
+    let (dismissed, items) = update_and_emit(&app_handle, &upload_state, |items| take_entries(items, &ids)).await?;
+    info!("Dismissed {} quarantined item(s)", dismissed.len());
+    Ok(items)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(path: &str, error: &str) -> QuarantinedItem {
+        let input = UploadItemInput { id: String::new(), path: path.to_string(), metadata: UploadItemMetadata::default(), idempotency_key: None };
+        QuarantinedItem::new(&input, &UploadOptions { require_complete_metadata: true, ..Default::default() }, None, error)
+    }
+
+    #[tokio::test]
+    async fn test_entries_are_replaced_per_file_and_persisted() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(QUARANTINE_FILE_NAME);
+        let quarantine = Quarantine::default();
+
+        let (_, items) = quarantine.update(&path, |items| add_entries(items, vec![entry("/mix/a.wav", "truncated"), entry("/mix/b.wav", "empty")])).await.unwrap();
+        assert_eq!(items.len(), 2);
+        assert!(!items[0].options.require_complete_metadata);
+        let first_id = items[0].id.clone();
+
+        let (_, items) = quarantine.update(&path, |items| add_entries(items, vec![entry("/mix/a.wav", "still truncated")])).await.unwrap();
+        assert_eq!(items.len(), 2);
+        assert_eq!((items[0].id.as_str(), items[0].error.as_str()), (first_id.as_str(), "still truncated"));
+
+        // A fresh instance reads what was written
+        let (taken, items) = Quarantine::default().update(&path, |items| take_entries(items, &[first_id.clone()])).await.unwrap();
+        assert_eq!(taken.len(), 1);
+        assert_eq!(items.iter().map(|item| item.path.as_str()).collect::<Vec<_>>(), vec!["/mix/b.wav"]);
+        assert_eq!(load_quarantine_file(&path).len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_still_broken_files_stay_quarantined_with_the_new_error() {
+        let dir = tempfile::tempdir().unwrap();
+        let empty = dir.path().join("bounce.wav");
+        fs::write(&empty, b"").unwrap();
+        let missing = dir.path().join("gone.wav");
+        let (mut bounce, mut gone) = (entry(&empty.to_string_lossy(), "File is empty"), entry(&missing.to_string_lossy(), "File not found"));
+        let earlier = Utc::now() - chrono::Duration::hours(1);
+        (bounce.quarantined_at, gone.quarantined_at) = (earlier, earlier);
+        let mut items = vec![bounce.clone(), gone.clone()];
+
+        let (valid, failed) = validate_entries(items.clone(), UploadLimits::default()).await;
+        assert!(valid.is_empty());
+        assert_eq!(failed.iter().map(|item| item.id.as_str()).collect::<Vec<_>>(), vec![bounce.id.as_str(), gone.id.as_str()]);
+        assert!(failed.iter().all(|item| item.quarantined_at > earlier));
+
+        // Nothing was queued, so both entries stay, carrying the new errors
+        add_entries(&mut items, failed.clone());
+        assert_eq!(items.len(), 2);
+        assert_eq!(items[0].id, bounce.id);
+        assert_eq!(items[0].error, failed[0].error);
+        assert_ne!(items[1].error, "File not found");
+    }
+
+    #[test]
+    fn test_requeued_entries_are_removed_unless_quarantined_again() {
+        let (queued, requarantined, untouched) = (entry("/mix/a.wav", "truncated"), entry("/mix/b.wav", "empty"), entry("/mix/c.wav", "empty"));
+        let mut items = vec![queued.clone(), requarantined.clone(), untouched.clone()];
+        // b's file was rejected again while it was being queued
+        add_entries(&mut items, vec![QuarantinedItem { quarantined_at: Utc::now() + chrono::Duration::seconds(1), ..entry("/mix/b.wav", "still empty") }]);
+
+        remove_requeued(&mut items, &[queued, requarantined.clone()]);
+        assert_eq!(items.iter().map(|item| item.path.as_str()).collect::<Vec<_>>(), vec!["/mix/b.wav", "/mix/c.wav"]);
+        assert_eq!((items[0].id.as_str(), items[0].error.as_str()), (requarantined.id.as_str(), "still empty"));
+    }
+
+    #[test]
+    fn test_only_file_errors_are_quarantined() {
+        let progress = |status: UploadStatus, error_message: Option<&str>| UploadProgress {
+            item_id: Uuid::new_v4(), original_path: "/mix/a.wav".to_string(), status,
+            error_message: error_message.map(str::to_string), title: None, album: None, track_id: None, throughput_bytes_per_sec: None, local_delivery_path: None,
+        };
+        let corrupt = progress(UploadStatus::error(ErrorCategory::CorruptInput, "Invalid input file"), Some("File is empty"));
+        assert_eq!(file_error(&corrupt).as_deref(), Some("File is empty"));
+        let corrupt = progress(UploadStatus::error(ErrorCategory::CorruptInput, "Transcoding failed"), None);
+        assert_eq!(file_error(&corrupt).as_deref(), Some("Transcoding failed"));
+        assert_eq!(file_error(&progress(UploadStatus::error(ErrorCategory::NetworkTransient, "timed out"), None)), None);
+        assert_eq!(file_error(&progress(UploadStatus::Complete, None)), None);
+    }
+}
@@ -0,0 +1,246 @@
+//! Pending upload items, kept in a deque so they can be listed, reordered, and removed
+//! before a worker picks them up.
+//!
+//! The pending items and the items being processed live behind one lock, and
+//! the lock is never held across an await, so workers and commands cannot deadlock.
+
+use serde::Serialize;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::Mutex;
+use uuid::Uuid;
+
+use super::{QueuedInput, UploadQueueItem};
+
+/// A queued item as listed by `get_pending_uploads`.
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct PendingUpload {
+    pub item_id: Uuid,
+    pub path: String,
+    /// `None` if the file can no longer be read
+    pub size_bytes: Option<u64>,
+}
+
+/// Why an item could not be removed from the pending queue.
+#[derive(Debug, Clone, PartialEq)]
+pub enum RemoveError {
+    /// The item is being processed and must be cancelled instead
+    InProgress,
+    NotFound,
+}
+
+#[derive(Debug, Default)]
+struct QueueInner {
+    items: VecDeque<UploadQueueItem>,
+    // Items being processed, with what they were queued with; `None` for URL uploads
+    in_progress: HashMap<Uuid, Option<QueuedInput>>,
+}
+
+#[derive(Debug, Default)]
+pub struct PendingQueue {
+    inner: Mutex<QueueInner>,
+}
+
+impl PendingQueue {
+    fn lock(&self) -> std::sync::MutexGuard<'_, QueueInner> {
+        self.inner.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    pub fn push(&self, item: UploadQueueItem) {
+        self.lock().items.push_back(item);
+    }
+
+    /// Takes the next item and marks it as being processed until `finish` is called.
+    pub fn pop_next(&self) -> Option<UploadQueueItem> {
+        let mut inner = self.lock();
+        let next = inner.items.pop_front()?;
+        inner.in_progress.insert(next.id, next.queued_input());
+        Some(next)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lock().items.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.lock().items.len()
+    }
+
+    /// Ids of the pending items, in queue order.
+    pub fn ids(&self) -> Vec<Uuid> {
+        self.lock().items.iter().map(|item| item.id).collect()
+    }
+
+    pub fn in_progress(&self) -> HashSet<Uuid> {
+        self.lock().in_progress.keys().copied().collect()
+    }
+
+    /// What the items being processed were queued with, to queue them again. Items
+    /// streamed from a URL are left out.
+    pub fn in_progress_inputs(&self) -> Vec<QueuedInput> {
+        self.lock().in_progress.values().flatten().cloned().collect()
+    }
+
+    pub fn snapshot(&self) -> Vec<PendingUpload> {
+        self.lock().items.iter()
+            .map(|item| PendingUpload {
+                item_id: item.id,
+                path: item.input_path.to_string_lossy().into_owned(),
+                size_bytes: std::fs::metadata(&item.input_path).ok().map(|m| m.len()),
+            })
+            .collect()
+    }
+
+    /// Removes a pending item. Items being processed cannot be removed.
+    pub fn remove(&self, item_id: Uuid) -> Result<UploadQueueItem, RemoveError> {
+        let mut inner = self.lock();
+        if let Some(index) = inner.items.iter().position(|item| item.id == item_id) {
+            return Ok(inner.items.remove(index).expect("index is in bounds"));
+        }
+        if inner.in_progress.contains_key(&item_id) { Err(RemoveError::InProgress) } else { Err(RemoveError::NotFound) }
+    }
+
+    /// Moves the listed items to the front in the given order. Pending items not listed
+    /// keep their relative order behind them; listed ids that are no longer pending
+    /// (e.g. picked up by a worker in the meantime) are ignored.
+    pub fn reorder(&self, ordered_ids: &[Uuid]) {
+        let mut inner = self.lock();
+        let mut remaining: Vec<Option<UploadQueueItem>> = inner.items.drain(..).map(Some).collect();
+        let mut reordered = VecDeque::with_capacity(remaining.len());
+        let mut seen = HashSet::new();
+        for id in ordered_ids.iter().filter(|id| seen.insert(**id)) {
+            if let Some(slot) = remaining.iter_mut().find(|slot| slot.as_ref().is_some_and(|item| item.id == *id)) {
+                reordered.extend(slot.take());
+            }
+        }
+        reordered.extend(remaining.into_iter().flatten());
+        inner.items = reordered;
+    }
+
+    /// Removes and returns all pending items.
+    pub fn drain(&self) -> Vec<UploadQueueItem> {
+        self.lock().items.drain(..).collect()
+    }
+
+    /// Clears an item a worker is done with.
+    pub fn finish(&self, item_id: Uuid) {
+        self.lock().in_progress.remove(&item_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{UploadFormats, UploadItemMetadata};
+    use super::*;
+    use std::path::PathBuf;
+    use std::sync::Arc;
+
+    fn item(name: &str) -> UploadQueueItem {
+        UploadQueueItem {
+            id: Uuid::new_v4(),
+            input_path: PathBuf::from(name),
+            metadata: UploadItemMetadata::default(),
+            temp_delivery_path: None, temp_archive_path: None, temp_encrypted_path: None, encryption_key_id: None,
+            r2_archive_key: None, r2_delivery_key: None, db_track_id: None,
+            on_key_collision: Default::default(), formats: UploadFormats::default(), transcoding: Default::default(), bucket_override: None,
+            visibility: Default::default(), album_matching: Default::default(), remote_source: None,
+            upload_originals: true, original_sha256: None, staging: false, keep_transcoded_dir: None, idempotency_key: None,
+        }
+    }
+
+    fn paths(queue: &PendingQueue) -> Vec<String> {
+        queue.snapshot().into_iter().map(|p| p.path).collect()
+    }
+
+    #[test]
+    fn test_reorder_moves_listed_items_first() {
+        let queue = PendingQueue::default();
+        let items: Vec<UploadQueueItem> = ["a", "b", "c", "d"].iter().map(|n| item(n)).collect();
+        let ids: Vec<Uuid> = items.iter().map(|i| i.id).collect();
+        items.into_iter().for_each(|i| queue.push(i));
+
+        queue.reorder(&[ids[2], Uuid::new_v4(), ids[0], ids[2]]);
+        assert_eq!(paths(&queue), vec!["c", "a", "b", "d"]);
+    }
+
+    #[test]
+    fn test_remove_pending_and_in_progress() {
+        let queue = PendingQueue::default();
+        let (first, second) = (item("first"), item("second"));
+        let (first_id, second_id) = (first.id, second.id);
+        queue.push(first);
+        queue.push(second);
+
+        assert_eq!(queue.pop_next().map(|i| i.id), Some(first_id));
+        assert_eq!(queue.remove(first_id).unwrap_err(), RemoveError::InProgress);
+        assert_eq!(queue.remove(second_id).map(|i| i.id), Ok(second_id));
+        assert_eq!(queue.remove(second_id).unwrap_err(), RemoveError::NotFound);
+
+        queue.finish(first_id);
+        assert_eq!(queue.remove(first_id).unwrap_err(), RemoveError::NotFound);
+    }
+
+    #[test]
+    fn test_several_items_in_progress() {
+        let queue = PendingQueue::default();
+        let (first, second) = (item("first"), item("second"));
+        let (first_id, second_id) = (first.id, second.id);
+        queue.push(first);
+        queue.push(second);
+
+        queue.pop_next();
+        queue.pop_next();
+        assert_eq!(queue.in_progress(), HashSet::from([first_id, second_id]));
+        queue.finish(second_id);
+        assert_eq!(queue.remove(first_id).unwrap_err(), RemoveError::InProgress);
+        assert_eq!(queue.remove(second_id).unwrap_err(), RemoveError::NotFound);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_concurrent_workers_and_reorders_handle_each_item_once() {
+        let queue = Arc::new(PendingQueue::default());
+        let mut ids = Vec::new();
+        for n in 0..500 {
+            let queued = item(&format!("file-{}", n));
+            ids.push(queued.id);
+            queue.push(queued);
+        }
+
+        let mut workers = Vec::new();
+        for _ in 0..3 {
+            let queue = Arc::clone(&queue);
+            workers.push(tokio::spawn(async move {
+                let mut taken = Vec::new();
+                while let Some(next) = queue.pop_next() {
+                    taken.push(next.id);
+                    tokio::task::yield_now().await;
+                }
+                taken
+            }));
+        }
+        let editor = {
+            let queue = Arc::clone(&queue);
+            let ids = ids.clone();
+            tokio::spawn(async move {
+                let mut removed = Vec::new();
+                for (round, chunk) in ids.chunks(25).enumerate() {
+                    let reversed: Vec<Uuid> = chunk.iter().rev().copied().collect();
+                    queue.reorder(&reversed);
+                    if let Ok(gone) = queue.remove(chunk[round % chunk.len()]) {
+                        removed.push(gone.id);
+                    }
+                    tokio::task::yield_now().await;
+                }
+                removed
+            })
+        };
+
+        let mut handled = editor.await.unwrap();
+        for worker in workers {
+            handled.extend(worker.await.unwrap());
+        }
+        handled.sort();
+        ids.sort();
+        assert_eq!(handled, ids, "every item is processed or removed exactly once");
+        assert!(queue.is_empty());
+    }
+}
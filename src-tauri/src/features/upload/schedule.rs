@@ -0,0 +1,370 @@
+//! Uploads queued now and started at a set time, e.g. overnight when the uplink is idle.
+//!
+//! Scheduled items are validated and wait in the pending queue as `UploadStatus::Scheduled`
+//! while the timer task spawned at startup waits for the start time. The inputs are also
+//! written to `scheduled_upload.json`, so a schedule survives a restart; if the app was
+//! closed across the start time, the items are queued again and started on the next launch.
+
+use chrono::{DateTime, Utc};
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::PathBuf;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::Duration;
+use tauri::{command, AppHandle, Emitter, Manager, State, Wry};
+use tokio::sync::Notify;
+use uuid::Uuid;
+
+use super::{enqueue_items, replace_status, spawn_processing, UploadError, UploadItemInput, UploadOptions, UploadState, UploadStatus};
+use crate::core::json_file::write_json_atomically;
+
+const SCHEDULE_FILE_NAME: &str = "scheduled_upload.json";
+
+/// Longest single wait, so a clock change or system sleep delays the start by at most this much.
+const MAX_TIMER_SLEEP: Duration = Duration::from_secs(60);
+
+/// The start time of the scheduled items, shared by the commands and the timer task.
+#[derive(Debug, Default)]
+pub struct UploadSchedule {
+    start_at: std::sync::Mutex<Option<DateTime<Utc>>>,
+    // Wakes the timer when the start time is set or cleared
+    changed: Notify,
+}
+
+impl UploadSchedule {
+    fn lock(&self) -> std::sync::MutexGuard<'_, Option<DateTime<Utc>>> {
+        self.start_at.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    pub fn start_at(&self) -> Option<DateTime<Utc>> {
+        *self.lock()
+    }
+
+    fn set(&self, start_at: DateTime<Utc>) {
+        *self.lock() = Some(start_at);
+        self.changed.notify_one();
+    }
+
+    /// Clears the schedule, returning the start time it had.
+    fn clear(&self) -> Option<DateTime<Utc>> {
+        let previous = self.lock().take();
+        self.changed.notify_one();
+        previous
+    }
+
+    /// Clears the schedule if it is still set to `start_at`, so a cancel racing the timer wins.
+    fn clear_if(&self, start_at: DateTime<Utc>) -> bool {
+        let mut current = self.lock();
+        if *current == Some(start_at) {
+            *current = None;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct ScheduledItem {
+    item_id: Uuid,
+    input: UploadItemInput,
+}
+
+/// The accepted items of one scheduling call, with the options they were queued with.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct ScheduledBatch {
+    items: Vec<ScheduledItem>,
+    options: Option<UploadOptions>,
+    bucket_override: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct ScheduleFile {
+    start_at: Option<DateTime<Utc>>,
+    batches: Vec<ScheduledBatch>,
+}
+
+impl ScheduleFile {
+    /// Drops a removed item, and its batch once empty. Returns false if it wasn't scheduled.
+    fn forget(&mut self, item_id: Uuid) -> bool {
+        let mut found = false;
+        for batch in &mut self.batches {
+            let before = batch.items.len();
+            batch.items.retain(|item| item.item_id != item_id);
+            found |= batch.items.len() != before;
+        }
+        self.batches.retain(|batch| !batch.items.is_empty());
+        if self.batches.is_empty() {
+            self.start_at = None;
+        }
+        found
+    }
+}
+
+/// Payload of `upload://queue-started`.
+#[derive(Debug, Clone, Serialize)]
+pub struct QueueStarted {
+    pub scheduled_for: DateTime<Utc>,
+    pub item_count: usize,
+}
+
+/// Payload of `upload://schedule-deferred`, sent when the app was closed at the start time.
+#[derive(Debug, Clone, Serialize)]
+pub struct ScheduleDeferred {
+    pub scheduled_for: DateTime<Utc>,
+    pub item_count: usize,
+}
+
+fn schedule_path(app_handle: &AppHandle<Wry>) -> Result<PathBuf, String> {
+    let dir = app_handle.path().app_data_dir()
+        .map_err(|e| format!("Failed to resolve app data directory: {}", e))?;
+    Ok(dir.join(SCHEDULE_FILE_NAME))
+}
+
+fn load_schedule_file(app_handle: &AppHandle<Wry>) -> ScheduleFile {
+    let path = match schedule_path(app_handle) {
+        Ok(path) => path,
+        Err(e) => {
+            warn!("{}", e);
+            return ScheduleFile::default();
+        }
+    };
+    match fs::read_to_string(&path) {
+        Ok(json) => serde_json::from_str(&json).unwrap_or_else(|e| {
+            warn!("Invalid scheduled upload in {:?}, ignoring it: {}", path, e);
+            ScheduleFile::default()
+        }),
+        Err(_) => ScheduleFile::default(), // Nothing scheduled
+    }
+}
+
+/// Writes the schedule, or removes the file once nothing is scheduled.
+fn save_schedule_file(app_handle: &AppHandle<Wry>, file: &ScheduleFile) -> Result<(), String> {
+    let path = schedule_path(app_handle)?;
+    if file.batches.is_empty() {
+        return match fs::remove_file(&path) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(format!("Failed to remove {:?}: {}", path, e)),
+            _ => Ok(()),
+        };
+    }
+    write_json_atomically(&path, file).map_err(|e| format!("Failed to write {:?}: {}", path, e))
+}
+
+fn clear_schedule_file(app_handle: &AppHandle<Wry>) {
+    if let Err(e) = save_schedule_file(app_handle, &ScheduleFile::default()) {
+        warn!("Failed to clear the scheduled upload: {}", e);
+    }
+}
+
+/// Validates and queues items to start at `start_at`, and records them on disk. Scheduling
+/// again moves the start of everything scheduled to the new time.
+#[allow(clippy::too_many_arguments)]
+pub(super) async fn schedule_items(
+    items: Vec<UploadItemInput>,
+    options: Option<UploadOptions>,
+    bucket_override: Option<String>,
+    start_at: DateTime<Utc>,
+    app_handle: &AppHandle<Wry>,
+    upload_state: &Arc<UploadState>,
+    r2_state: &crate::R2State,
+    mongo_state: &crate::MongoState,
+) -> Result<Vec<Uuid>, String> {
+    if start_at <= Utc::now() {
+        return Err(UploadError::InvalidInput(format!("Scheduled start time {} is in the past.", start_at)).to_string());
+    }
+    let inputs = items.clone();
+    let item_ids = enqueue_items(items, options.clone(), bucket_override.clone(), Some(start_at), app_handle, upload_state, r2_state, mongo_state).await?;
+
+    // Rejected items got an id too; only the queued ones are restored after a restart
+    let progress_map = upload_state.progress_map.lock().await;
+    let accepted: Vec<ScheduledItem> = item_ids.iter().zip(inputs)
+        .filter(|(id, _)| progress_map.get(id).is_some_and(|progress| progress.status == UploadStatus::Scheduled))
+        .map(|(id, input)| ScheduledItem { item_id: *id, input })
+        .collect();
+    drop(progress_map);
+
+    let mut file = load_schedule_file(app_handle);
+    if !accepted.is_empty() {
+        file.batches.push(ScheduledBatch { items: accepted, options, bucket_override });
+    }
+    if file.batches.is_empty() {
+        return Ok(item_ids);
+    }
+    file.start_at = Some(start_at);
+    if let Err(e) = save_schedule_file(app_handle, &file) {
+        warn!("Scheduled upload will not survive a restart: {}", e);
+    }
+    upload_state.schedule.set(start_at);
+    info!("Upload of {} queued item(s) scheduled for {}.", upload_state.pending.len(), start_at);
+    Ok(item_ids)
+}
+
+/// Drops a removed item from the saved schedule; clears the schedule once it is empty.
+pub(super) fn forget_item(app_handle: &AppHandle<Wry>, upload_state: &UploadState, item_id: Uuid) {
+    let mut file = load_schedule_file(app_handle);
+    if !file.forget(item_id) {
+        return;
+    }
+    if file.batches.is_empty() {
+        upload_state.schedule.clear();
+        info!("Last scheduled upload removed; the schedule is cleared.");
+    }
+    if let Err(e) = save_schedule_file(app_handle, &file) {
+        warn!("Failed to update the scheduled upload: {}", e);
+    }
+}
+
+/// Moves the scheduled items to the pending queue and starts processing them.
+async fn start_scheduled(app_handle: &AppHandle<Wry>, upload_state: &Arc<UploadState>, start_at: DateTime<Utc>) {
+    if !upload_state.schedule.clear_if(start_at) {
+        return;
+    }
+    clear_schedule_file(app_handle);
+    let status = if upload_state.paused.load(Ordering::SeqCst) { UploadStatus::Paused } else { UploadStatus::Pending };
+    replace_status(app_handle, &upload_state.progress_map, &UploadStatus::Scheduled, status).await;
+    let item_count = upload_state.pending.len();
+    info!("Starting {} scheduled upload(s) (scheduled for {}).", item_count, start_at);
+    if let Some(window) = app_handle.get_webview_window("main") {
+        window.emit("upload://queue-started", QueueStarted { scheduled_for: start_at, item_count }).unwrap_or_else(|e| {
+            error!("Failed to emit queue-started event: {}", e);
+        });
+    }
+    // A cancel of an earlier run must not stop the scheduled one
+    upload_state.cancel_flag.store(false, Ordering::SeqCst);
+    spawn_processing(app_handle, upload_state);
+}
+
+/// Waits for the scheduled start time and starts the queue. Runs for the life of the app.
+pub async fn run_schedule_timer(app_handle: AppHandle<Wry>, upload_state: Arc<UploadState>) {
+    loop {
+        // Register before reading so a change in between still wakes us
+        let changed = upload_state.schedule.changed.notified();
+        let Some(start_at) = upload_state.schedule.start_at() else {
+            changed.await;
+            continue;
+        };
+        let remaining = (start_at - Utc::now()).to_std().unwrap_or(Duration::ZERO);
+        if remaining.is_zero() {
+            start_scheduled(&app_handle, &upload_state, start_at).await;
+            continue;
+        }
+        tokio::select! {
+            _ = changed => {}
+            _ = tokio::time::sleep(remaining.min(MAX_TIMER_SLEEP)) => {}
+        }
+    }
+}
+
+/// Queues the saved schedule again after a restart. If its start time passed while the
+/// app was closed, the items start now and `upload://schedule-deferred` is emitted.
+pub async fn restore_scheduled_upload(app_handle: &AppHandle<Wry>, upload_state: &Arc<UploadState>) {
+    let file = load_schedule_file(app_handle);
+    let Some(start_at) = file.start_at else { return };
+    let r2_state = app_handle.state::<crate::R2State>();
+    let mongo_state = app_handle.state::<crate::MongoState>();
+    if r2_state.client.lock().await.is_none() || mongo_state.client.lock().await.is_none() {
+        warn!("Clients are not initialized; the upload scheduled for {} stays saved for the next launch.", start_at);
+        return;
+    }
+
+    // The items are queued under new ids, and persisted again if still in the future
+    clear_schedule_file(app_handle);
+    let deferred = start_at <= Utc::now();
+    let mut item_count = 0;
+    for batch in file.batches {
+        let inputs = batch.items.into_iter().map(|item| item.input).collect();
+        let result = if deferred {
+            enqueue_items(inputs, batch.options, batch.bucket_override, None, app_handle, upload_state, &r2_state, &mongo_state).await
+        } else {
+            schedule_items(inputs, batch.options, batch.bucket_override, start_at, app_handle, upload_state, &r2_state, &mongo_state).await
+        };
+        match result {
+            Ok(item_ids) => item_count += item_ids.len(),
+            Err(e) => warn!("Failed to restore scheduled uploads: {}", e),
+        }
+    }
+
+    if deferred {
+        warn!("The app was closed at the scheduled upload time {}; starting {} upload(s) now.", start_at, item_count);
+        if let Some(window) = app_handle.get_webview_window("main") {
+            window.emit("upload://schedule-deferred", ScheduleDeferred { scheduled_for: start_at, item_count }).unwrap_or_else(|e| {
+                error!("Failed to emit schedule-deferred event: {}", e);
+            });
+        }
+    } else {
+        info!("Restored {} upload(s) scheduled for {}.", item_count, start_at);
+    }
+}
+
+// --- Tauri Commands ---
+
+/// Validates and queues items now and starts them at `start_at`. Items show as
+/// `Scheduled` until then and can be removed like any pending item.
+#[command]
+#[allow(clippy::too_many_arguments)]
+pub async fn schedule_upload_queue(
+    items: Vec<UploadItemInput>,
+    options: Option<UploadOptions>,
+    bucket_override: Option<String>,
+    start_at: DateTime<Utc>,
+    app_handle: AppHandle<Wry>,
+    upload_state: State<'_, Arc<UploadState>>,
+    r2_state: State<'_, crate::R2State>,
+    mongo_state: State<'_, crate::MongoState>,
+) -> Result<(), String> {
+    schedule_items(items, options, bucket_override, start_at, &app_handle, &upload_state, &r2_state, &mongo_state).await?;
+    Ok(())
+}
+
+/// Clears the scheduled start. The items stay in the pending queue, where they can be
+/// removed, and are processed with the next `start_upload_queue` call.
+#[command]
+pub async fn cancel_scheduled_upload(app_handle: AppHandle<Wry>, upload_state: State<'_, Arc<UploadState>>) -> Result<(), String> {
+    let Some(start_at) = upload_state.schedule.clear() else {
+        return Err(UploadError::InvalidInput("No upload is scheduled.".to_string()).to_string());
+    };
+    clear_schedule_file(&app_handle);
+    let status = if upload_state.paused.load(Ordering::SeqCst) { UploadStatus::Paused } else { UploadStatus::Pending };
+    replace_status(&app_handle, &upload_state.progress_map, &UploadStatus::Scheduled, status).await;
+    info!("Cancelled the upload scheduled for {}.", start_at);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scheduled_item(item_id: Uuid) -> ScheduledItem {
+        ScheduledItem {
+            item_id,
+            input: UploadItemInput {
+                id: item_id.to_string(),
+                path: format!("/music/{}.wav", item_id),
+                metadata: Default::default(),
+                idempotency_key: None,
+            },
+        }
+    }
+
+    #[test]
+    fn test_forget_drops_empty_batches_and_start_time() {
+        let (first, second, third) = (Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4());
+        let mut file = ScheduleFile {
+            start_at: Some(Utc::now()),
+            batches: vec![
+                ScheduledBatch { items: vec![scheduled_item(first)], options: None, bucket_override: None },
+                ScheduledBatch { items: vec![scheduled_item(second), scheduled_item(third)], options: None, bucket_override: None },
+            ],
+        };
+
+        assert!(file.forget(first));
+        assert_eq!(file.batches.len(), 1);
+        assert!(!file.forget(first));
+        assert!(file.forget(second));
+        assert!(file.start_at.is_some());
+        assert!(file.forget(third));
+        assert!(file.batches.is_empty() && file.start_at.is_none());
+    }
+}
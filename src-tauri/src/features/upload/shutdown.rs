@@ -0,0 +1,490 @@
+//! Quitting while uploads are running. Closing the main window or quitting the app first
+//! lets the items in flight finish: the queue stops handing out items, as when it is
+//! drained, the items still waiting are written to `interrupted_upload.json`, and the app
+//! exits once the workers are done. `shutdown://pending` tells the UI what is being finished.
+//!
+//! A second close request, `force_quit`, or the workers taking longer than
+//! `GRACEFUL_SHUTDOWN_TIMEOUT` exits straight away, killing any running ffmpeg. The items
+//! still in flight are then saved too, with their idempotency keys. Uploads are single
+//! PUTs, so an interrupted one leaves nothing behind in R2; leftover temp files are swept
+//! at the next launch. The saved items are queued again, paused, on the next launch by
+//! `restore_interrupted_uploads`.
+//!
+//! The same file keeps the idempotency keys claimed so far, written as they are claimed,
+//! so a request retried after a relaunch is still refused.
+
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tauri::{command, AppHandle, Emitter, Manager, State, Wry};
+use tokio::sync::oneshot;
+
+use super::audio::transcode::abort_running_transcodes;
+use super::{enqueue_items, QueuedInput, UploadItemInput, UploadOptions, UploadState};
+use crate::core::json_file::write_json_atomically;
+
+const INTERRUPTED_FILE_NAME: &str = "interrupted_upload.json";
+
+/// Most idempotency keys kept in the file; the oldest are dropped first.
+const MAX_SAVED_IDEMPOTENCY_KEYS: usize = 10_000;
+
+/// Serializes the read-modify-write of the file between enqueues and shutdown.
+static FILE_LOCK: Mutex<()> = Mutex::new(());
+
+/// Longest the items in flight are waited for before the app quits anyway.
+pub const GRACEFUL_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// Time given to running ffmpeg processes to be killed before a forced exit; a few of
+/// their poll intervals.
+const FORCE_EXIT_GRACE: Duration = Duration::from_millis(300);
+
+/// Where the app is in quitting, shared by the close handlers and `force_quit`.
+#[derive(Debug, Default)]
+pub struct ShutdownState {
+    // A shutdown is waiting for the items in flight
+    started: AtomicBool,
+    // The waiting items have been written to disk
+    saved: AtomicBool,
+    // A forced exit is under way
+    forcing: AtomicBool,
+    // Exit requests are let through from now on
+    exit_allowed: AtomicBool,
+}
+
+/// What to do with a request to close the main window or quit the app.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExitDecision {
+    Exit,
+    /// Keep running; the app exits on its own once the shutdown is done
+    Wait,
+}
+
+/// Payload of `shutdown://pending`.
+#[derive(Debug, Clone, Serialize)]
+pub struct ShutdownPending {
+    /// Items being finished before the app exits
+    pub in_flight: usize,
+    /// Waiting items saved for the next launch
+    pub saved: usize,
+    pub timeout_secs: u64,
+}
+
+/// Payload of `upload://restored`.
+#[derive(Debug, Clone, Serialize)]
+pub struct UploadsRestored {
+    pub item_count: usize,
+}
+
+/// Waiting items that were queued with the same options.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct InterruptedBatch {
+    items: Vec<UploadItemInput>,
+    options: UploadOptions,
+    bucket_override: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct InterruptedFile {
+    batches: Vec<InterruptedBatch>,
+    /// Idempotency keys claimed in earlier sessions, oldest first
+    #[serde(default)]
+    idempotency_keys: Vec<String>,
+}
+
+impl InterruptedFile {
+    fn item_count(&self) -> usize {
+        self.batches.iter().map(|batch| batch.items.len()).sum()
+    }
+
+    fn item_idempotency_keys(&self) -> HashSet<String> {
+        self.batches.iter().flat_map(|batch| &batch.items).filter_map(|item| item.idempotency_key.clone()).collect()
+    }
+
+    /// Keys to refuse from the start of a session. Those of saved items are left out;
+    /// they are claimed again when the items are restored.
+    fn seen_idempotency_keys(&self) -> Vec<String> {
+        let item_keys = self.item_idempotency_keys();
+        self.idempotency_keys.iter().filter(|key| !item_keys.contains(*key)).cloned().collect()
+    }
+}
+
+fn interrupted_path(app_handle: &AppHandle<Wry>) -> Result<PathBuf, String> {
+    let dir = app_handle.path().app_data_dir()
+        .map_err(|e| format!("Failed to resolve app data directory: {}", e))?;
+    Ok(dir.join(INTERRUPTED_FILE_NAME))
+}
+
+fn load_interrupted_file(path: &Path) -> InterruptedFile {
+    match fs::read_to_string(path) {
+        Ok(json) => serde_json::from_str(&json).unwrap_or_else(|e| {
+            warn!("Invalid interrupted uploads in {:?}, ignoring them: {}", path, e);
+            InterruptedFile::default()
+        }),
+        Err(_) => InterruptedFile::default(), // Nothing was interrupted
+    }
+}
+
+/// Writes the file, adding to any batches and keys saved before.
+fn save_interrupted_file(path: &Path, mut file: InterruptedFile) -> Result<(), String> {
+    if file.batches.is_empty() && file.idempotency_keys.is_empty() {
+        return Ok(());
+    }
+    let _guard = FILE_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let mut saved = load_interrupted_file(path);
+    saved.batches.append(&mut file.batches);
+    for key in file.idempotency_keys {
+        if !saved.idempotency_keys.contains(&key) {
+            saved.idempotency_keys.push(key);
+        }
+    }
+    let excess = saved.idempotency_keys.len().saturating_sub(MAX_SAVED_IDEMPOTENCY_KEYS);
+    saved.idempotency_keys.drain(..excess);
+    write_json_atomically(path, &saved).map_err(|e| format!("Failed to write {:?}: {}", path, e))
+}
+
+/// Takes the saved batches out of the file, keeping the idempotency keys.
+fn take_interrupted_batches(path: &Path) -> Result<Vec<InterruptedBatch>, String> {
+    let _guard = FILE_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let mut saved = load_interrupted_file(path);
+    let batches = std::mem::take(&mut saved.batches);
+    let result = if saved.idempotency_keys.is_empty() {
+        fs::remove_file(path).map_err(|e| format!("Failed to remove {:?}: {}", path, e))
+    } else {
+        write_json_atomically(path, &saved).map_err(|e| format!("Failed to write {:?}: {}", path, e))
+    };
+    result.map(|()| batches)
+}
+
+/// Groups items into batches, starting a new batch whenever the options change.
+fn interrupted_batches(items: impl IntoIterator<Item = QueuedInput>) -> InterruptedFile {
+    let mut batches: Vec<InterruptedBatch> = Vec::new();
+    for (input, options, bucket_override) in items {
+        let same_options = |batch: &InterruptedBatch| {
+            batch.bucket_override == bucket_override
+                && serde_json::to_value(&batch.options).ok() == serde_json::to_value(&options).ok()
+        };
+        match batches.last_mut() {
+            Some(batch) if same_options(batch) => batch.items.push(input),
+            _ => batches.push(InterruptedBatch { items: vec![input], options, bucket_override }),
+        }
+    }
+    InterruptedFile { batches, idempotency_keys: Vec::new() }
+}
+
+/// Adds newly claimed idempotency keys to the file.
+pub fn save_idempotency_keys(app_handle: &AppHandle<Wry>, keys: Vec<String>) {
+    let file = InterruptedFile { batches: Vec::new(), idempotency_keys: keys };
+    if let Err(e) = interrupted_path(app_handle).and_then(|path| save_interrupted_file(&path, file)) {
+        error!("Failed to save idempotency keys: {}", e);
+    }
+}
+
+/// Refuses the idempotency keys claimed in earlier sessions. Runs at launch, before
+/// anything can be queued.
+pub async fn load_idempotency_keys(app_handle: &AppHandle<Wry>, upload_state: &UploadState) {
+    let path = match interrupted_path(app_handle) {
+        Ok(path) => path,
+        Err(e) => {
+            warn!("{}", e);
+            return;
+        }
+    };
+    let keys = load_interrupted_file(&path).seen_idempotency_keys();
+    if !keys.is_empty() {
+        info!("Loaded {} idempotency key(s) from earlier sessions.", keys.len());
+        upload_state.remember_idempotency_keys(keys).await;
+    }
+}
+
+/// Stops workers from taking new items and lets a paused item in flight carry on.
+fn stop_taking_items(upload_state: &UploadState) {
+    upload_state.draining.store(true, Ordering::SeqCst);
+    upload_state.paused.store(false, Ordering::SeqCst);
+    upload_state.resume_notify.notify_waiters();
+}
+
+/// Takes the waiting items out of the queue and writes them to `path`, once per shutdown.
+/// Returns how many were saved. Scheduled items are left alone; the schedule file already
+/// has them.
+fn save_waiting_items(upload_state: &UploadState, path: &Path) -> Result<usize, String> {
+    if upload_state.shutdown.saved.swap(true, Ordering::SeqCst) || upload_state.schedule.start_at().is_some() {
+        return Ok(0);
+    }
+    let items = upload_state.pending.drain().into_iter().filter_map(|item| {
+        let input = item.queued_input();
+        if input.is_none() {
+            warn!("Not saving {} for the next launch; it is streamed from a URL", item.input_path.display());
+        }
+        input
+    });
+    let file = interrupted_batches(items);
+    let item_count = file.item_count();
+    save_interrupted_file(path, file)?;
+    Ok(item_count)
+}
+
+/// Writes the items still in flight to `path`, keeping their idempotency keys, for an
+/// exit that does not wait for them. An item that completes in the moment before the
+/// exit is queued again too; the next launch starts paused, so the user sees it first.
+fn save_in_flight_items(upload_state: &UploadState, path: &Path) -> Result<usize, String> {
+    let file = interrupted_batches(upload_state.pending.in_progress_inputs());
+    let item_count = file.item_count();
+    save_interrupted_file(path, file)?;
+    Ok(item_count)
+}
+
+/// Waits up to `limit` for the processing task to exit. Returns false on timeout.
+async fn wait_for_workers(upload_state: &UploadState, limit: Duration) -> bool {
+    tokio::time::timeout(limit, async {
+        loop {
+            // Register before checking so a worker exiting in between still wakes us
+            let (finished_tx, finished_rx) = oneshot::channel();
+            upload_state.finish_waiters.lock().await.push(finished_tx);
+            if !upload_state.is_processing.load(Ordering::SeqCst) {
+                return;
+            }
+            let _ = finished_rx.await;
+        }
+    }).await.is_ok()
+}
+
+fn save_for_next_launch(app_handle: &AppHandle<Wry>, upload_state: &UploadState) -> usize {
+    let result = interrupted_path(app_handle).and_then(|path| save_waiting_items(upload_state, &path));
+    result.unwrap_or_else(|e| {
+        error!("Failed to save the waiting uploads for the next launch: {}", e);
+        0
+    })
+}
+
+/// Saves the waiting and in-flight items, kills running transcodes and exits shortly
+/// after, without waiting for anything else.
+fn force_exit(app_handle: &AppHandle<Wry>, upload_state: &Arc<UploadState>) {
+    if upload_state.shutdown.forcing.swap(true, Ordering::SeqCst) {
+        return;
+    }
+    stop_taking_items(upload_state);
+    save_for_next_launch(app_handle, upload_state);
+    let in_flight = interrupted_path(app_handle).and_then(|path| save_in_flight_items(upload_state, &path));
+    match in_flight {
+        Ok(saved) => info!("Saved {} upload(s) in flight for the next launch.", saved),
+        Err(e) => error!("Failed to save the uploads in flight for the next launch: {}", e),
+    }
+    abort_running_transcodes();
+    let app_handle = app_handle.clone();
+    let upload_state = Arc::clone(upload_state);
+    tauri::async_runtime::spawn(async move {
+        tokio::time::sleep(FORCE_EXIT_GRACE).await;
+        info!("Exiting without waiting for the uploads in flight.");
+        upload_state.shutdown.exit_allowed.store(true, Ordering::SeqCst);
+        app_handle.exit(0);
+    });
+}
+
+/// Decides on a request to close the main window or quit. With uploads in flight the
+/// first request starts a graceful shutdown and a second one forces the exit.
+pub fn handle_exit_request(app_handle: &AppHandle<Wry>, upload_state: &Arc<UploadState>) -> ExitDecision {
+    let shutdown = &upload_state.shutdown;
+    if shutdown.exit_allowed.load(Ordering::SeqCst) {
+        return ExitDecision::Exit;
+    }
+    if shutdown.started.load(Ordering::SeqCst) {
+        info!("Quit requested again while finishing uploads; quitting now.");
+        force_exit(app_handle, upload_state);
+        return ExitDecision::Wait;
+    }
+    if !upload_state.has_uploads_in_flight() {
+        return ExitDecision::Exit;
+    }
+
+    shutdown.started.store(true, Ordering::SeqCst);
+    stop_taking_items(upload_state);
+    let saved = save_for_next_launch(app_handle, upload_state);
+    let in_flight = upload_state.pending.in_progress().len();
+    info!("Quit requested with {} upload(s) in flight; finishing them first ({} saved for the next launch).", in_flight, saved);
+    let pending = ShutdownPending { in_flight, saved, timeout_secs: GRACEFUL_SHUTDOWN_TIMEOUT.as_secs() };
+    if let Err(e) = app_handle.emit("shutdown://pending", pending) {
+        error!("Failed to emit shutdown pending event: {}", e);
+    }
+
+    let app_handle = app_handle.clone();
+    let upload_state = Arc::clone(upload_state);
+    tauri::async_runtime::spawn(async move {
+        if wait_for_workers(&upload_state, GRACEFUL_SHUTDOWN_TIMEOUT).await {
+            info!("Uploads in flight finished; exiting.");
+            upload_state.shutdown.exit_allowed.store(true, Ordering::SeqCst);
+            app_handle.exit(0);
+        } else {
+            warn!("Uploads in flight did not finish within {}s.", GRACEFUL_SHUTDOWN_TIMEOUT.as_secs());
+            force_exit(&app_handle, &upload_state);
+        }
+    });
+    ExitDecision::Wait
+}
+
+/// Quits now, even with uploads in flight. The waiting items and the ones in flight are
+/// saved for the next launch.
+#[command]
+pub async fn force_quit(app_handle: AppHandle<Wry>, upload_state: State<'_, Arc<UploadState>>) -> Result<(), String> {
+    info!("Received request to force quit.");
+    force_exit(&app_handle, &upload_state);
+    Ok(())
+}
+
+/// Queues the uploads saved by the last shutdown again, paused so the user decides when
+/// they continue, and emits `upload://restored`. The file is kept while the clients are
+/// down or a schedule is pending, for a later launch.
+pub async fn restore_interrupted_uploads(app_handle: &AppHandle<Wry>, upload_state: &Arc<UploadState>) {
+    let path = match interrupted_path(app_handle) {
+        Ok(path) => path,
+        Err(e) => {
+            warn!("{}", e);
+            return;
+        }
+    };
+    let file = load_interrupted_file(&path);
+    if file.batches.is_empty() {
+        return;
+    }
+    let r2_state = app_handle.state::<crate::R2State>();
+    let mongo_state = app_handle.state::<crate::MongoState>();
+    let clients_ready = r2_state.client.lock().await.is_some() && mongo_state.client.lock().await.is_some();
+    if !clients_ready || upload_state.schedule.start_at().is_some() {
+        if clients_ready {
+            warn!("An upload is scheduled; interrupted uploads stay saved until it has run.");
+        } else {
+            warn!("Clients are not initialized; interrupted uploads stay saved for the next launch.");
+        }
+        // Not queued this session, so their keys are refused like the others
+        upload_state.remember_idempotency_keys(file.item_idempotency_keys()).await;
+        return;
+    }
+
+    let batches = match take_interrupted_batches(&path) {
+        Ok(batches) => batches,
+        Err(e) => {
+            warn!("{}", e);
+            file.batches
+        }
+    };
+    upload_state.paused.store(true, Ordering::SeqCst);
+    let mut item_count = 0;
+    for batch in batches {
+        match enqueue_items(batch.items, Some(batch.options), batch.bucket_override, None, app_handle, upload_state, &r2_state, &mongo_state).await {
+            Ok(item_ids) => item_count += item_ids.len(),
+            Err(e) => warn!("Failed to restore interrupted uploads: {}", e),
+        }
+    }
+    info!("Restored {} upload(s) interrupted by the last shutdown; the queue is paused.", item_count);
+    if let Err(e) = app_handle.emit("upload://restored", UploadsRestored { item_count }) {
+        error!("Failed to emit restored event: {}", e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{UploadFormats, UploadItemMetadata, UploadQueueItem};
+    use super::*;
+    use uuid::Uuid;
+
+    fn item(path: &str, bucket_override: Option<&str>) -> UploadQueueItem {
+        UploadQueueItem {
+            id: Uuid::new_v4(), input_path: PathBuf::from(path), metadata: UploadItemMetadata::default(),
+            temp_delivery_path: None, temp_archive_path: None, temp_encrypted_path: None, encryption_key_id: None,
+            r2_archive_key: None, r2_delivery_key: None, db_track_id: None,
+            on_key_collision: Default::default(), formats: UploadFormats::default(), transcoding: Default::default(),
+            bucket_override: bucket_override.map(str::to_string),
+            visibility: Default::default(), album_matching: Default::default(), remote_source: None,
+            upload_originals: true, original_sha256: None, staging: false, keep_transcoded_dir: None, idempotency_key: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_waiting_items_are_saved_before_workers_finish() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(INTERRUPTED_FILE_NAME);
+        let state = Arc::new(UploadState::new());
+        state.pending.push(item("/music/a.wav", None));
+        state.pending.push(item("/music/b.wav", None));
+        state.pending.push(item("/music/c.wav", Some("archive")));
+        // A worker with an item in flight, finishing once it sees the queue stop
+        state.is_processing.store(true, Ordering::SeqCst);
+        let worker = tokio::spawn({
+            let state = Arc::clone(&state);
+            async move {
+                while !state.draining.load(Ordering::SeqCst) {
+                    tokio::time::sleep(Duration::from_millis(5)).await;
+                }
+                tokio::time::sleep(Duration::from_millis(50)).await;
+                state.is_processing.store(false, Ordering::SeqCst);
+                for waiter in state.finish_waiters.lock().await.drain(..) {
+                    let _ = waiter.send(());
+                }
+            }
+        });
+
+        stop_taking_items(&state);
+        assert_eq!(save_waiting_items(&state, &path).unwrap(), 3);
+        assert!(state.is_processing.load(Ordering::SeqCst), "saved while the worker is still running");
+        let saved = load_interrupted_file(&path);
+        assert_eq!(saved.batches.len(), 2);
+        assert_eq!(saved.batches[0].items.len(), 2);
+        assert_eq!(saved.batches[1].bucket_override.as_deref(), Some("archive"));
+        assert!(state.pending.is_empty());
+        assert_eq!(save_waiting_items(&state, &path).unwrap(), 0, "saved once per shutdown");
+
+        assert!(wait_for_workers(&state, Duration::from_secs(2)).await);
+        worker.await.unwrap();
+    }
+
+    #[test]
+    fn test_in_flight_items_are_saved_with_their_keys() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(INTERRUPTED_FILE_NAME);
+        let state = UploadState::new();
+        state.pending.push(UploadQueueItem { idempotency_key: Some("batch-1/item-1".to_string()), ..item("/music/a.wav", None) });
+        state.pending.push(item("/music/b.wav", None));
+        let in_flight = state.pending.pop_next().unwrap();
+
+        assert_eq!(save_waiting_items(&state, &path).unwrap(), 1);
+        assert_eq!(save_in_flight_items(&state, &path).unwrap(), 1);
+        let saved = load_interrupted_file(&path);
+        assert_eq!(saved.item_count(), 2);
+        let restored = &saved.batches[1].items[0];
+        assert_eq!(restored.id, in_flight.id.to_string());
+        assert_eq!(restored.idempotency_key.as_deref(), Some("batch-1/item-1"));
+    }
+
+    #[test]
+    fn test_idempotency_keys_survive_restores() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(INTERRUPTED_FILE_NAME);
+        let keys = |names: &[&str]| names.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        save_interrupted_file(&path, InterruptedFile { batches: Vec::new(), idempotency_keys: keys(&["k1", "k2"]) }).unwrap();
+        let state = UploadState::new();
+        state.pending.push(UploadQueueItem { idempotency_key: Some("k2".to_string()), ..item("/music/a.wav", None) });
+        assert_eq!(save_waiting_items(&state, &path).unwrap(), 1);
+        save_interrupted_file(&path, InterruptedFile { batches: Vec::new(), idempotency_keys: keys(&["k2", "k3"]) }).unwrap();
+
+        let saved = load_interrupted_file(&path);
+        assert_eq!(saved.idempotency_keys, keys(&["k1", "k2", "k3"]));
+        // k2 belongs to a saved item and is claimed again when it is restored
+        assert_eq!(saved.seen_idempotency_keys(), keys(&["k1", "k3"]));
+
+        assert_eq!(take_interrupted_batches(&path).unwrap().len(), 1);
+        let remaining = load_interrupted_file(&path);
+        assert!(remaining.batches.is_empty());
+        assert_eq!(remaining.seen_idempotency_keys(), keys(&["k1", "k2", "k3"]));
+    }
+
+    #[tokio::test]
+    async fn test_wait_for_workers_gives_up_after_limit() {
+        let state = UploadState::new();
+        state.is_processing.store(true, Ordering::SeqCst);
+        assert!(!wait_for_workers(&state, Duration::from_millis(50)).await);
+    }
+}
@@ -0,0 +1,217 @@
+//! Scratch space for transcoded files waiting to be uploaded, in
+//! `{app_cache_dir}/transcode-tmp/`.
+//!
+//! The files the upload queue is working on are listed in `in_flight.json` in that
+//! directory, so a crash leaves a record of them. At startup every file that isn't listed,
+//! and every file older than a day, is removed. Files the running queue still uses are
+//! never removed.
+
+use log::{info, warn};
+use serde::Serialize;
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use tauri::{command, AppHandle, Manager, State, Wry};
+
+use super::UploadState;
+use crate::core::json_file::write_json_atomically;
+
+pub const TEMP_DIR_NAME: &str = "transcode-tmp";
+
+/// Files listed in the in-flight record are kept this long after their last change.
+pub const STALE_AFTER: Duration = Duration::from_secs(24 * 60 * 60);
+
+const IN_FLIGHT_FILE_NAME: &str = "in_flight.json";
+
+/// Prefix of transcoded files written to the OS temp directory by earlier versions.
+const LEGACY_TEMP_PREFIX: &str = "transcoded_";
+
+#[derive(Debug, Serialize, Clone, Default, PartialEq)]
+pub struct TempStorageUsage {
+    pub file_count: u64,
+    pub total_bytes: u64,
+    /// Bytes held by files the upload queue is still using
+    pub in_use_bytes: u64,
+}
+
+#[derive(Debug, Serialize, Clone, Default, PartialEq)]
+pub struct TempSweepResult {
+    pub files_removed: u64,
+    pub bytes_freed: u64,
+}
+
+/// The scratch directory, created if needed.
+pub fn temp_dir(app_handle: &AppHandle<Wry>) -> Result<PathBuf, String> {
+    let dir = app_handle.path().app_cache_dir()
+        .map_err(|e| format!("Failed to resolve app cache directory: {}", e))?
+        .join(TEMP_DIR_NAME);
+    fs::create_dir_all(&dir).map_err(|e| format!("Failed to create {:?}: {}", dir, e))?;
+    Ok(dir)
+}
+
+fn read_in_flight(dir: &Path) -> HashSet<PathBuf> {
+    let path = dir.join(IN_FLIGHT_FILE_NAME);
+    match fs::read_to_string(&path) {
+        Ok(json) => serde_json::from_str(&json).unwrap_or_else(|e| {
+            warn!("Invalid in-flight temp file record {:?}, ignoring it: {}", path, e);
+            HashSet::new()
+        }),
+        Err(_) => HashSet::new(), // Nothing in flight
+    }
+}
+
+/// Writes the in-flight record; a failure is only logged.
+fn write_in_flight(dir: &Path, paths: &HashSet<PathBuf>) {
+    let path = dir.join(IN_FLIGHT_FILE_NAME);
+    if let Err(e) = write_json_atomically(&path, paths) {
+        warn!("Failed to record in-flight temp files in {:?}: {}", path, e);
+    }
+}
+
+/// Records a temp file the upload queue has created.
+pub async fn track_temp_file(state: &UploadState, dir: &Path, path: &Path) {
+    let mut temp_files = state.temp_files.lock().await;
+    temp_files.insert(path.to_path_buf());
+    write_in_flight(dir, &temp_files);
+}
+
+/// Forgets every tracked temp file, once the upload queue has stopped. The files
+/// themselves are removed as each item finishes.
+pub async fn release_temp_files(state: &UploadState, dir: &Path) {
+    let mut temp_files = state.temp_files.lock().await;
+    temp_files.clear();
+    write_in_flight(dir, &temp_files);
+}
+
+/// Whether a file found in the scratch directory should be removed.
+fn should_remove(path: &Path, age: Duration, in_use: &HashSet<PathBuf>, in_flight: &HashSet<PathBuf>) -> bool {
+    if in_use.contains(path) {
+        return false;
+    }
+    !in_flight.contains(path) || age > STALE_AFTER
+}
+
+/// Files in `dir` other than the in-flight record, with their size and age.
+fn list_files(dir: &Path) -> Vec<(PathBuf, u64, Duration)> {
+    let Ok(entries) = fs::read_dir(dir) else { return Vec::new() };
+    let now = SystemTime::now();
+    entries.flatten()
+        .filter(|entry| entry.file_name() != IN_FLIGHT_FILE_NAME)
+        .filter_map(|entry| {
+            let metadata = entry.metadata().ok().filter(|metadata| metadata.is_file())?;
+            let age = metadata.modified().ok().and_then(|modified| now.duration_since(modified).ok()).unwrap_or_default();
+            Some((entry.path(), metadata.len(), age))
+        })
+        .collect()
+}
+
+/// Removes the files in `dir` that `remove` selects.
+fn sweep(dir: &Path, remove: impl Fn(&Path, Duration) -> bool) -> TempSweepResult {
+    let mut result = TempSweepResult::default();
+    for (path, size, age) in list_files(dir) {
+        if !remove(&path, age) {
+            continue;
+        }
+        match fs::remove_file(&path) {
+            Ok(()) => {
+                result.files_removed += 1;
+                result.bytes_freed += size;
+            }
+            Err(e) => warn!("Failed to remove temp file {:?}: {}", path, e),
+        }
+    }
+    result
+}
+
+/// Startup cleanup, called from `setup()`: removes files left by a previous run that
+/// aren't in flight or are stale, and transcodes earlier versions left in the OS temp
+/// directory.
+pub async fn sweep_temp_storage(app_handle: &AppHandle<Wry>, state: &UploadState) -> Result<TempSweepResult, String> {
+    let dir = temp_dir(app_handle)?;
+    let mut temp_files = state.temp_files.lock().await;
+    let in_flight = read_in_flight(&dir);
+    let mut result = sweep(&dir, |path, age| should_remove(path, age, &temp_files, &in_flight));
+
+    // Keep only the records of files that are still there
+    temp_files.extend(in_flight.into_iter().filter(|path| path.exists()));
+    write_in_flight(&dir, &temp_files);
+    drop(temp_files);
+
+    let legacy = sweep(&std::env::temp_dir(), |path, age| {
+        age > STALE_AFTER && path.file_name().is_some_and(|name| name.to_string_lossy().starts_with(LEGACY_TEMP_PREFIX))
+    });
+    result.files_removed += legacy.files_removed;
+    result.bytes_freed += legacy.bytes_freed;
+    if result.files_removed > 0 {
+        info!("Removed {} stale temp files ({} bytes)", result.files_removed, result.bytes_freed);
+    }
+    Ok(result)
+}
+
+// --- Tauri Commands ---
+
+/// Disk space used by transcoded files waiting to be uploaded.
+#[command]
+pub async fn get_temp_storage_usage(
+    app_handle: AppHandle<Wry>,
+    upload_state: State<'_, Arc<UploadState>>,
+) -> Result<TempStorageUsage, String> {
+    let dir = temp_dir(&app_handle)?;
+    let temp_files = upload_state.temp_files.lock().await;
+    let mut usage = TempStorageUsage::default();
+    for (path, size, _) in list_files(&dir) {
+        usage.file_count += 1;
+        usage.total_bytes += size;
+        if temp_files.contains(&path) {
+            usage.in_use_bytes += size;
+        }
+    }
+    Ok(usage)
+}
+
+/// Removes every temp file the upload queue isn't using right now.
+#[command]
+pub async fn clear_temp_storage(
+    app_handle: AppHandle<Wry>,
+    upload_state: State<'_, Arc<UploadState>>,
+) -> Result<TempSweepResult, String> {
+    let dir = temp_dir(&app_handle)?;
+    let mut temp_files = upload_state.temp_files.lock().await;
+    let result = sweep(&dir, |path, _| !temp_files.contains(path));
+    temp_files.retain(|path| path.exists());
+    write_in_flight(&dir, &temp_files);
+    info!("Cleared temp storage: {} files, {} bytes", result.files_removed, result.bytes_freed);
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_should_remove() {
+        let (live, listed, orphan) = (PathBuf::from("/t/live.m4a"), PathBuf::from("/t/listed.m4a"), PathBuf::from("/t/orphan.m4a"));
+        let in_use = HashSet::from([live.clone()]);
+        let in_flight = HashSet::from([live.clone(), listed.clone()]);
+        let (fresh, old) = (Duration::from_secs(60), STALE_AFTER + Duration::from_secs(1));
+
+        assert!(!should_remove(&live, old, &in_use, &in_flight));
+        assert!(!should_remove(&listed, fresh, &in_use, &in_flight));
+        assert!(should_remove(&listed, old, &in_use, &in_flight));
+        assert!(should_remove(&orphan, fresh, &in_use, &in_flight));
+    }
+
+    #[test]
+    fn test_sweep_skips_in_flight_record() {
+        let dir = tempdir().unwrap();
+        fs::write(dir.path().join("transcoded_a.m4a"), [0u8; 10]).unwrap();
+        write_in_flight(dir.path(), &HashSet::new());
+
+        let result = sweep(dir.path(), |_, _| true);
+        assert_eq!(result, TempSweepResult { files_removed: 1, bytes_freed: 10 });
+        assert!(dir.path().join(IN_FLIGHT_FILE_NAME).exists());
+    }
+}
@@ -0,0 +1,352 @@
+//! Caps the upload bandwidth used by all workers together, so the app doesn't saturate
+//! an office uplink.
+//!
+//! Every upload body draws from one token bucket. A chunk is read from disk, the
+//! bucket is charged for it, and the body waits as long as the bucket is in debt
+//! before handing the chunk to the HTTP client. The rate is read on every chunk, and
+//! changing it wakes the bodies that are waiting so they rescale their wait to the new
+//! rate; in-flight transfers pick up the change right away.
+
+use aws_sdk_s3::primitives::{ByteStream, SdkBody};
+use bytes::Bytes;
+use http_body::{Body, Frame, SizeHint};
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::task::{ready, Context, Poll, Waker};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncRead, ReadBuf};
+
+/// Bytes in the MB of the MB/s setting.
+pub const BYTES_PER_MB: f64 = 1_000_000.0;
+
+/// Bytes read from disk and charged to the bucket at a time.
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// Seconds of transfer the bucket can save up while idle.
+const BURST_SECS: f64 = 1.0;
+
+pub trait Clock: Send + Sync {
+    fn now(&self) -> Instant;
+}
+
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+/// A token bucket shared by all uploads. A rate of 0 means unlimited.
+pub struct BandwidthLimiter {
+    bytes_per_sec: AtomicU64,
+    // Bumped on every rate change, so a waiting body can tell its wait is stale
+    generation: AtomicU64,
+    bucket: Mutex<Bucket>,
+    // Bodies waiting on the bucket, by body id; woken when the rate changes
+    sleepers: Mutex<HashMap<u64, Waker>>,
+    next_body_id: AtomicU64,
+    clock: Box<dyn Clock>,
+}
+
+impl std::fmt::Debug for BandwidthLimiter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("BandwidthLimiter").field("bytes_per_sec", &self.bytes_per_sec).finish()
+    }
+}
+
+impl Default for BandwidthLimiter {
+    fn default() -> Self {
+        Self::with_clock(Box::new(SystemClock))
+    }
+}
+
+impl BandwidthLimiter {
+    pub fn with_clock(clock: Box<dyn Clock>) -> Self {
+        let now = clock.now();
+        Self {
+            bytes_per_sec: AtomicU64::new(0),
+            generation: AtomicU64::new(0),
+            bucket: Mutex::new(Bucket { tokens: f64::MAX, updated: now }),
+            sleepers: Mutex::new(HashMap::new()),
+            next_body_id: AtomicU64::new(0),
+            clock,
+        }
+    }
+
+    /// Sets the rate and wakes the bodies waiting on the old one.
+    pub fn set_limit_mb_per_sec(&self, mb_per_sec: f64) {
+        let rate = (mb_per_sec.max(0.0) * BYTES_PER_MB).round() as u64;
+        if self.bytes_per_sec.swap(rate, Ordering::SeqCst) == rate {
+            return;
+        }
+        self.generation.fetch_add(1, Ordering::SeqCst);
+        let sleepers = std::mem::take(&mut *self.sleepers());
+        for waker in sleepers.into_values() {
+            waker.wake();
+        }
+    }
+
+    /// The rate in bytes per second, and the generation it belongs to.
+    fn rate(&self) -> (u64, u64) {
+        let generation = self.generation.load(Ordering::SeqCst);
+        (self.bytes_per_sec.load(Ordering::SeqCst), generation)
+    }
+
+    fn sleepers(&self) -> MutexGuard<'_, HashMap<u64, Waker>> {
+        self.sleepers.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn register_sleeper(&self, body_id: u64, waker: &Waker) {
+        self.sleepers().insert(body_id, waker.clone());
+    }
+
+    fn remove_sleeper(&self, body_id: u64) {
+        self.sleepers().remove(&body_id);
+    }
+
+    /// Charges `bytes` to the bucket and returns how long to wait before sending them.
+    pub fn reserve(&self, bytes: usize) -> Duration {
+        let rate = self.bytes_per_sec.load(Ordering::SeqCst) as f64;
+        let mut bucket = self.bucket.lock().unwrap_or_else(|e| e.into_inner());
+        let now = self.clock.now();
+        let elapsed = now.saturating_duration_since(bucket.updated).as_secs_f64();
+        bucket.updated = now;
+        if rate == 0.0 {
+            bucket.tokens = f64::MAX;
+            return Duration::ZERO;
+        }
+        bucket.tokens = (bucket.tokens + elapsed * rate).min(rate * BURST_SECS) - bytes as f64;
+        if bucket.tokens >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-bucket.tokens / rate)
+        }
+    }
+}
+
+/// A chunk's wait on the bucket, with the rate it was worked out at.
+struct Delay {
+    sleep: Pin<Box<tokio::time::Sleep>>,
+    bytes_per_sec: u64,
+    generation: u64,
+}
+
+impl Delay {
+    /// Scales the time left to `bytes_per_sec`: the chunk still owes the same number of
+    /// bytes, paid off at the new rate.
+    fn rescale(&mut self, bytes_per_sec: u64, generation: u64) {
+        let now = tokio::time::Instant::now();
+        let left = self.sleep.deadline().saturating_duration_since(now);
+        let wait = if bytes_per_sec == 0 { Duration::ZERO } else { left.mul_f64(self.bytes_per_sec as f64 / bytes_per_sec as f64) };
+        self.sleep.as_mut().reset(now + wait);
+        self.bytes_per_sec = bytes_per_sec;
+        self.generation = generation;
+    }
+}
+
+/// Streams a file to the HTTP client through the limiter, counting the bytes sent.
+struct ThrottledFileBody {
+    id: u64,
+    file: Option<tokio::fs::File>,
+    open_error: Option<std::io::Error>,
+    remaining: u64,
+    limiter: Arc<BandwidthLimiter>,
+    sent: Arc<AtomicU64>,
+    buffer: Box<[u8]>,
+    // A chunk read from disk, held back until the bucket allows it
+    pending: Option<Bytes>,
+    delay: Option<Delay>,
+}
+
+impl ThrottledFileBody {
+    fn open(path: &Path, len: u64, limiter: Arc<BandwidthLimiter>, sent: Arc<AtomicU64>) -> Self {
+        let (file, open_error) = match std::fs::File::open(path) {
+            Ok(file) => (Some(tokio::fs::File::from_std(file)), None),
+            Err(e) => (None, Some(e)),
+        };
+        let id = limiter.next_body_id.fetch_add(1, Ordering::Relaxed);
+        Self { id, file, open_error, remaining: len, limiter, sent, buffer: vec![0u8; CHUNK_SIZE].into_boxed_slice(), pending: None, delay: None }
+    }
+}
+
+impl Drop for ThrottledFileBody {
+    fn drop(&mut self) {
+        if self.delay.is_some() {
+            self.limiter.remove_sleeper(self.id);
+        }
+    }
+}
+
+impl Body for ThrottledFileBody {
+    type Data = Bytes;
+    type Error = std::io::Error;
+
+    fn poll_frame(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Result<Frame<Bytes>, std::io::Error>>> {
+        let this = self.get_mut();
+        if let Some(e) = this.open_error.take() {
+            return Poll::Ready(Some(Err(e)));
+        }
+        loop {
+            if let Some(delay) = this.delay.as_mut() {
+                // Registered before the rate is read, so a change after the read still wakes us
+                this.limiter.register_sleeper(this.id, cx.waker());
+                let (rate, generation) = this.limiter.rate();
+                if generation != delay.generation {
+                    delay.rescale(rate, generation);
+                }
+                ready!(delay.sleep.as_mut().poll(cx));
+                this.limiter.remove_sleeper(this.id);
+                this.delay = None;
+            }
+            if let Some(chunk) = this.pending.take() {
+                this.sent.fetch_add(chunk.len() as u64, Ordering::Relaxed);
+                return Poll::Ready(Some(Ok(Frame::data(chunk))));
+            }
+            if this.remaining == 0 {
+                this.file = None;
+                return Poll::Ready(None);
+            }
+            let Some(file) = this.file.as_mut() else {
+                return Poll::Ready(None);
+            };
+            let want = this.buffer.len().min(usize::try_from(this.remaining).unwrap_or(usize::MAX));
+            let mut read_buf = ReadBuf::new(&mut this.buffer[..want]);
+            if let Err(e) = ready!(Pin::new(file).poll_read(cx, &mut read_buf)) {
+                return Poll::Ready(Some(Err(e)));
+            }
+            let read = read_buf.filled().len();
+            if read == 0 {
+                return Poll::Ready(Some(Err(std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "File shrank during upload"))));
+            }
+            this.remaining -= read as u64;
+            this.pending = Some(Bytes::copy_from_slice(read_buf.filled()));
+            let (_, generation) = this.limiter.rate();
+            let wait = this.limiter.reserve(read);
+            if !wait.is_zero() {
+                let bytes_per_sec = this.limiter.bytes_per_sec.load(Ordering::SeqCst);
+                this.delay = Some(Delay { sleep: Box::pin(tokio::time::sleep(wait)), bytes_per_sec, generation });
+            }
+        }
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.open_error.is_none() && self.pending.is_none() && self.remaining == 0
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        SizeHint::with_exact(self.remaining + self.pending.as_ref().map_or(0, |chunk| chunk.len() as u64))
+    }
+}
+
+/// A retryable request body for the file at `path`, limited by `limiter`. Bytes sent
+/// are added to `sent`, including bytes of attempts that are retried.
+pub fn throttled_file_stream(path: &Path, len: u64, limiter: Arc<BandwidthLimiter>, sent: Arc<AtomicU64>) -> ByteStream {
+    let path: PathBuf = path.to_path_buf();
+    let body = SdkBody::retryable(move || {
+        SdkBody::from_body_1_x(ThrottledFileBody::open(&path, len, Arc::clone(&limiter), Arc::clone(&sent)))
+    });
+    ByteStream::new(body)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct FakeClock(Arc<Mutex<Instant>>);
+
+    impl Clock for FakeClock {
+        fn now(&self) -> Instant {
+            *self.0.lock().unwrap()
+        }
+    }
+
+    fn fake_limiter() -> (BandwidthLimiter, Arc<Mutex<Instant>>) {
+        let now = Arc::new(Mutex::new(Instant::now()));
+        (BandwidthLimiter::with_clock(Box::new(FakeClock(Arc::clone(&now)))), now)
+    }
+
+    /// Sends `total` bytes in chunks from `workers` interleaved senders, sleeping on the
+    /// fake clock as told, and returns the simulated seconds taken.
+    fn simulate(limiter: &BandwidthLimiter, now: &Mutex<Instant>, total: usize, workers: usize) -> f64 {
+        let start = *now.lock().unwrap();
+        let mut ready_at = vec![start; workers];
+        let mut sent = 0;
+        while sent < total {
+            // The worker that can send next goes first
+            let (worker, at) = ready_at.iter().copied().enumerate().min_by_key(|(_, at)| *at).unwrap();
+            let current = at.max(*now.lock().unwrap());
+            *now.lock().unwrap() = current;
+            ready_at[worker] = current + limiter.reserve(CHUNK_SIZE);
+            sent += CHUNK_SIZE;
+        }
+        let end = ready_at.into_iter().max().unwrap();
+        end.duration_since(start).as_secs_f64()
+    }
+
+    #[test]
+    fn test_bucket_enforces_aggregate_rate() {
+        let (limiter, now) = fake_limiter();
+        limiter.set_limit_mb_per_sec(2.0);
+        let total = 40 * CHUNK_SIZE * 8; // About 21 MB
+
+        // Everything beyond the one second burst is sent at the configured rate, however many workers share it
+        let expected = (total as f64 - 2.0 * BYTES_PER_MB * BURST_SECS) / (2.0 * BYTES_PER_MB);
+        let elapsed = simulate(&limiter, &now, total, 3);
+        assert!((elapsed - expected).abs() / expected < 0.02, "took {}s, expected about {}s", elapsed, expected);
+
+        // A lower limit applies from the next chunk; an idle bucket only saves up one second at the new rate
+        limiter.set_limit_mb_per_sec(0.5);
+        *now.lock().unwrap() += Duration::from_secs(10);
+        let elapsed = simulate(&limiter, &now, total / 4, 2);
+        let expected = (total as f64 / 4.0 - 0.5 * BYTES_PER_MB * BURST_SECS) / (0.5 * BYTES_PER_MB);
+        assert!((elapsed - expected).abs() / expected < 0.02, "took {}s, expected about {}s", elapsed, expected);
+    }
+
+    #[tokio::test]
+    async fn test_raising_the_limit_wakes_a_waiting_body() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("track.wav");
+        std::fs::write(&path, vec![0u8; 2 * CHUNK_SIZE]).unwrap();
+
+        // At 1 KB/s the first chunk alone would wait over a minute
+        let limiter = Arc::new(BandwidthLimiter::default());
+        limiter.set_limit_mb_per_sec(0.001);
+        let sent = Arc::new(AtomicU64::new(0));
+        let mut body = ThrottledFileBody::open(&path, 2 * CHUNK_SIZE as u64, Arc::clone(&limiter), Arc::clone(&sent));
+        let read_all = async move {
+            while let Some(frame) = std::future::poll_fn(|cx| Pin::new(&mut body).poll_frame(cx)).await {
+                frame.unwrap();
+            }
+        };
+        let raise = async {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            limiter.set_limit_mb_per_sec(0.0);
+        };
+        let (read, ()) = tokio::join!(tokio::time::timeout(Duration::from_secs(5), read_all), raise);
+        assert!(read.is_ok(), "the waiting body wasn't woken by the new limit");
+        assert_eq!(sent.load(Ordering::Relaxed), 2 * CHUNK_SIZE as u64);
+        assert!(limiter.sleepers().is_empty());
+    }
+
+    #[test]
+    fn test_zero_rate_is_unlimited() {
+        let (limiter, _now) = fake_limiter();
+        assert_eq!(limiter.reserve(100 * CHUNK_SIZE), Duration::ZERO);
+        limiter.set_limit_mb_per_sec(1.0);
+        // Switching from unlimited starts with a full one second burst
+        assert_eq!(limiter.reserve(CHUNK_SIZE), Duration::ZERO);
+        limiter.set_limit_mb_per_sec(0.0);
+        assert_eq!(limiter.reserve(100 * CHUNK_SIZE), Duration::ZERO);
+    }
+}
@@ -0,0 +1,353 @@
+//! Uploads a file that already lives at an HTTP(S) URL without downloading it first:
+//! the response body is streamed straight into the archive `put_object`, through the
+//! same bandwidth limiter as queued uploads.
+//!
+//! Transcoding needs a local file, so when a delivery copy is requested the body is also
+//! written to the scratch directory as it passes. The server must send a Content-Length,
+//! as R2 needs the object size up front.
+
+use bytes::Bytes;
+use futures::Stream;
+use http_body::{Body, Frame, SizeHint};
+use log::{error, info};
+use mongodb::bson::oid::ObjectId;
+use std::future::Future;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::task::{ready, Context, Poll};
+use std::time::Duration;
+use aws_sdk_s3::primitives::{ByteStream, SdkBody};
+use tauri::{command, AppHandle, Manager, State, Wry};
+use tempfile::Builder as TempFileBuilder;
+use url::Url;
+use uuid::Uuid;
+
+use super::keygen::{render_key, resolve_collision, upload_with_policy, KeyCollisionPolicy, KeyContext};
+use super::temp_storage::{temp_dir, track_temp_file};
+use super::throttle::BandwidthLimiter;
+use super::{
+    analyze_features, cleanup_temp_file, perform_cleanup, put_object_error, run_transcoding, store_track_metadata, supports_transactions,
+    update_progress, upload_file_to_r2, upload_error_status, with_item_timeout, with_throughput_updates, AlbumMatching,
+    ArchiveFormat, DeliveryFormat, UploadError, UploadFormats, UploadItemMetadata, UploadQueueItem, UploadState, UploadStatus,
+};
+use crate::core::r2::{ObjectVisibility, R2Client};
+use crate::core::timing::TimeoutSettings;
+use crate::features::activity::{record_activity, ActivityAction, ActivityEntry};
+use crate::features::catalog::custom_fields::validate_custom_fields;
+use crate::features::upload::audio::analysis::TrackFeatures;
+use crate::features::upload::audio::transcode::TranscodingOptions;
+use crate::core::db_config::CatalogDatabase;
+
+/// Where an upload's source came from when it isn't a local file.
+#[derive(Debug, Clone)]
+pub struct RemoteSource {
+    pub url: String,
+    /// Content-Length of the response
+    pub size: u64,
+}
+
+/// Accepts absolute http and https URLs with a host.
+pub fn validate_source_url(url: &str) -> Result<Url, String> {
+    let parsed = Url::parse(url.trim()).map_err(|e| format!("Invalid URL '{}': {}", url, e))?;
+    if !matches!(parsed.scheme(), "http" | "https") {
+        return Err(format!("Only http and https URLs can be uploaded, got '{}'", parsed.scheme()));
+    }
+    if parsed.host_str().unwrap_or_default().is_empty() {
+        return Err(format!("URL '{}' has no host", url));
+    }
+    Ok(parsed)
+}
+
+/// The size to upload: the response must declare it, and it must fit the upload limit.
+fn check_content_length(content_length: Option<u64>, max_file_size_bytes: u64) -> Result<u64, String> {
+    match content_length {
+        None => Err("The server did not send a Content-Length; the file can't be streamed to R2".to_string()),
+        Some(0) => Err("The server sent an empty file".to_string()),
+        Some(len) if len > max_file_size_bytes => Err(format!(
+            "File is {} bytes, larger than the {} byte upload limit", len, max_file_size_bytes
+        )),
+        Some(len) => Ok(len),
+    }
+}
+
+/// File name for keys and the track's `filename`: the last path segment of the URL.
+fn source_file_name(url: &Url) -> String {
+    url.path_segments()
+        .and_then(|segments| segments.filter(|segment| !segment.is_empty()).last())
+        .map(String::from)
+        .unwrap_or_else(|| "download".to_string())
+}
+
+type ChunkStream = Pin<Box<dyn Stream<Item = reqwest::Result<Bytes>> + Send>>;
+
+/// Passes a download on to the HTTP client through the limiter, optionally copying it to
+/// a local file, and fails if the server sends more or less than it announced.
+struct RemoteBody {
+    // Behind a mutex only so the body is Sync; polling has exclusive access anyway
+    stream: std::sync::Mutex<ChunkStream>,
+    remaining: u64,
+    limiter: Arc<BandwidthLimiter>,
+    sent: Arc<AtomicU64>,
+    copy: Option<std::fs::File>,
+    // A received chunk, held back until the bucket allows it
+    pending: Option<Bytes>,
+    delay: Option<Pin<Box<tokio::time::Sleep>>>,
+}
+
+impl Body for RemoteBody {
+    type Data = Bytes;
+    type Error = std::io::Error;
+
+    fn poll_frame(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Result<Frame<Bytes>, std::io::Error>>> {
+        let this = self.get_mut();
+        loop {
+            if let Some(delay) = this.delay.as_mut() {
+                ready!(delay.as_mut().poll(cx));
+                this.delay = None;
+            }
+            if let Some(chunk) = this.pending.take() {
+                this.sent.fetch_add(chunk.len() as u64, Ordering::Relaxed);
+                return Poll::Ready(Some(Ok(Frame::data(chunk))));
+            }
+            let stream = this.stream.get_mut().unwrap_or_else(|e| e.into_inner());
+            let chunk = match ready!(stream.as_mut().poll_next(cx)) {
+                Some(Ok(chunk)) => chunk,
+                Some(Err(e)) => return Poll::Ready(Some(Err(std::io::Error::other(e)))),
+                None if this.remaining > 0 => {
+                    return Poll::Ready(Some(Err(std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "Download ended before its Content-Length"))));
+                }
+                None => return Poll::Ready(None),
+            };
+            if chunk.len() as u64 > this.remaining {
+                return Poll::Ready(Some(Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "Download is longer than its Content-Length"))));
+            }
+            this.remaining -= chunk.len() as u64;
+            // One chunk to local disk; small enough not to hold up the runtime
+            if let Some(copy) = this.copy.as_mut() {
+                if let Err(e) = copy.write_all(&chunk) {
+                    return Poll::Ready(Some(Err(e)));
+                }
+            }
+            let wait = this.limiter.reserve(chunk.len());
+            if !wait.is_zero() {
+                this.delay = Some(Box::pin(tokio::time::sleep(wait)));
+            }
+            this.pending = Some(chunk);
+        }
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.pending.is_none() && self.remaining == 0
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        SizeHint::with_exact(self.remaining + self.pending.as_ref().map_or(0, |chunk| chunk.len() as u64))
+    }
+}
+
+// --- Tauri Commands ---
+
+/// Uploads the file at `url` as a new track with `metadata` and returns the track id.
+/// The original is streamed into R2 unchanged; with `delivery_format` a delivery copy is
+/// transcoded and uploaded too. Progress is reported like queued items, keyed by the URL.
+#[command]
+pub async fn upload_from_url(
+    url: String,
+    metadata: UploadItemMetadata,
+    delivery_format: Option<DeliveryFormat>,
+    visibility: Option<ObjectVisibility>,
+    app_handle: AppHandle<Wry>,
+    upload_state: State<'_, Arc<UploadState>>,
+    timeout_settings: State<'_, TimeoutSettings>,
+) -> Result<String, String> {
+    let source_url = validate_source_url(&url).map_err(|e| UploadError::InvalidInput(e).to_string())?;
+    if let Some(fields) = &metadata.custom_fields {
+        validate_custom_fields(fields).map_err(|e| UploadError::InvalidInput(e).to_string())?;
+    }
+    if upload_state.encryption.lock().await.encrypt_originals {
+        return Err(UploadError::InvalidInput(
+            "Originals are encrypted before upload, which needs a local copy; download the file and upload it from disk".to_string(),
+        ).to_string());
+    }
+
+    let r2_state = app_handle.state::<crate::R2State>();
+    let r2_client = r2_state.client.lock().await.clone().ok_or_else(|| UploadError::R2ClientNotInitialized.to_string())?;
+    let bucket_name = r2_state.bucket_name.lock().await.clone().ok_or_else(|| UploadError::R2ClientNotInitialized.to_string())?;
+    let mongo_client = app_handle.state::<crate::MongoState>().client.lock().await.clone()
+        .ok_or_else(|| UploadError::MongoDbClientNotInitialized.to_string())?;
+    let scratch_dir = temp_dir(&app_handle)?;
+    let item_timeout = Duration::from_secs(upload_state.item_timeout_secs.load(Ordering::SeqCst));
+
+    let http = reqwest::Client::builder()
+        .connect_timeout(timeout_settings.get().connect())
+        .build()
+        .map_err(|e| UploadError::InternalError(format!("Failed to create HTTP client: {}", e)).to_string())?;
+    let response = http.get(source_url.clone()).send().await
+        .and_then(|response| response.error_for_status())
+        .map_err(|e| UploadError::InvalidInput(format!("Failed to fetch {}: {}", url, e)).to_string())?;
+    let max_file_size_bytes = upload_state.limits.lock().await.max_file_size_bytes;
+    let size = check_content_length(response.content_length(), max_file_size_bytes).map_err(|e| UploadError::InvalidInput(e).to_string())?;
+    let file_name = source_file_name(&source_url);
+    info!("Uploading {} ({} bytes) from URL", url, size);
+
+    let mut item = UploadQueueItem {
+        id: Uuid::new_v4(), input_path: PathBuf::from(&file_name), metadata,
+        temp_delivery_path: None, temp_archive_path: None, temp_encrypted_path: None, encryption_key_id: None,
+        r2_archive_key: None, r2_delivery_key: None, db_track_id: None,
+        on_key_collision: KeyCollisionPolicy::default(),
+        formats: UploadFormats { archive: ArchiveFormat::Original, delivery: delivery_format.unwrap_or_default() },
+        transcoding: TranscodingOptions::default(), bucket_override: None,
+        visibility: visibility.unwrap_or_default(), album_matching: AlbumMatching::default(),
+        remote_source: Some(RemoteSource { url: url.clone(), size }),
+        upload_originals: true, original_sha256: None, staging: false, keep_transcoded_dir: None, idempotency_key: None,
+    };
+    let state: &UploadState = &upload_state;
+    let progress_map = &state.progress_map;
+
+    let result = async {
+        let track_oid = ObjectId::new();
+        let track_id_hex = track_oid.to_hex();
+        let key_templates = state.key_templates.lock().await.clone();
+        let album = item.metadata.album.clone().unwrap_or_else(|| "Unknown Album".to_string());
+        let artist = item.metadata.artist.clone().unwrap_or_else(|| "Unknown Artist".to_string());
+        let key_context = |file_name, format| KeyContext { album: &album, artist: &artist, track_id: &track_id_hex, file_name, format };
+        let exists_checker = R2Client::new(r2_client.clone(), bucket_name.clone());
+        let exists = |key: String| {
+            let checker = &exists_checker;
+            async move { checker.object_exists(&key).await.map_err(|e| e.to_string()) }
+        };
+        let archive_key = resolve_collision(render_key(&key_templates.archive, &key_context(&file_name, ArchiveFormat::Original.name())), item.on_key_collision, &exists).await?;
+        let delivery = delivery_format.map(DeliveryFormat::transcode_format);
+        let delivery_key = match delivery {
+            Some(format) => {
+                let delivery_file_name = Path::new(&file_name).with_extension(format.extension()).to_string_lossy().into_owned();
+                let key = resolve_collision(render_key(&key_templates.delivery, &key_context(&delivery_file_name, format.name())), item.on_key_collision, &exists).await?;
+                if key == archive_key {
+                    return Err(UploadError::InvalidInput(format!("Archive and delivery copies would share the key {}", archive_key)));
+                }
+                Some(key)
+            }
+            None => None,
+        };
+
+        // --- Stream Archive ---
+        update_progress(&app_handle, progress_map, item.id, UploadStatus::UploadingOriginal, None, &item.metadata, &url).await;
+        let copy = match delivery {
+            Some(_) => {
+                let temp_file = TempFileBuilder::new().prefix("download_").tempfile_in(&scratch_dir)
+                    .map_err(|e| UploadError::IoError(format!("Failed to create download copy: {}", e)))?;
+                let (file, path) = temp_file.keep().map_err(|e| UploadError::IoError(format!("Failed to keep download copy: {}", e)))?;
+                track_temp_file(state, &scratch_dir, &path).await;
+                // Removed with the item's other temp files
+                item.temp_archive_path = Some(path);
+                Some(file)
+            }
+            None => None,
+        };
+        let sent = Arc::new(AtomicU64::new(0));
+        let body = RemoteBody {
+            stream: std::sync::Mutex::new(Box::pin(response.bytes_stream())),
+            remaining: size,
+            limiter: Arc::clone(&state.bandwidth),
+            sent: Arc::clone(&sent),
+            copy,
+            pending: None,
+            delay: None,
+        };
+        let mime_type = item.formats.archive.mime_type(&item.input_path);
+        let if_absent = item.on_key_collision.put_if_absent();
+        let upload = async {
+            r2_client.put_object().bucket(&bucket_name).key(&archive_key).content_type(mime_type).content_length(size as i64)
+                .set_if_none_match(if_absent.then(|| "*".to_string()))
+                .body(ByteStream::new(SdkBody::from_body_1_x(body))).send().await
+                .map(|_| ())
+                .map_err(|e| put_object_error(e, &archive_key))
+        };
+        // The body is read once, so a key taken meanwhile fails the upload even under
+        // `Rename`; that key holds someone else's object and is not ours to clean up
+        let streamed = with_item_timeout(item_timeout, with_throughput_updates(&app_handle, progress_map, item.id, &sent, upload)).await;
+        if !matches!(streamed, Err(UploadError::KeyTaken(_))) {
+            item.r2_archive_key = Some(archive_key.clone());
+        }
+        streamed?;
+        info!("Streamed {} to R2 key {}", url, archive_key);
+
+        // --- Transcode and Upload Delivery ---
+        let mut features = TrackFeatures::default();
+        if let (Some(format), Some(delivery_key), Some(local_copy)) = (delivery, &delivery_key, item.temp_archive_path.clone()) {
+            update_progress(&app_handle, progress_map, item.id, UploadStatus::Transcoding, None, &item.metadata, &url).await;
+            let delivery_path = run_transcoding(&local_copy, format, item.transcoding, item_timeout, state, &scratch_dir).await?;
+            item.temp_delivery_path = Some(delivery_path.clone());
+            features = analyze_features(&local_copy).await;
+
+            update_progress(&app_handle, progress_map, item.id, UploadStatus::UploadingAAC, None, &item.metadata, &url).await;
+            let sent = Arc::new(AtomicU64::new(0));
+            let (sent, r2_client, bucket_name, delivery_path) = (&sent, &r2_client, &bucket_name, &delivery_path);
+            let (app_handle, item_id, if_absent) = (&app_handle, item.id, item.on_key_collision.put_if_absent());
+            let (key, uploaded) = upload_with_policy(delivery_key.clone(), item.on_key_collision, |key: String| async move {
+                let upload = upload_file_to_r2(r2_client, &state.bandwidth, sent, delivery_path, bucket_name, &key, format.mime_type(), if_absent);
+                with_item_timeout(item_timeout, with_throughput_updates(app_handle, progress_map, item_id, sent, upload)).await
+            })
+            .await;
+            if !matches!(uploaded, Err(UploadError::KeyTaken(_))) {
+                item.r2_delivery_key = Some(key);
+            }
+            uploaded?;
+        }
+
+        // --- Store Metadata ---
+        update_progress(&app_handle, progress_map, item.id, UploadStatus::StoringMetadata, None, &item.metadata, &url).await;
+        let use_transactions = supports_transactions(&mongo_client).await;
+        let track_id = store_track_metadata(
+            &mongo_client, use_transactions, &item, track_oid, &bucket_name, &features,
+            item.r2_archive_key.as_deref(), item.r2_delivery_key.as_deref(),
+        ).await?;
+        item.db_track_id = Some(track_id.clone());
+        Ok::<_, UploadError>(track_id)
+    }.await;
+
+    match result {
+        Ok(track_id) => {
+            if let Some(progress) = progress_map.lock().await.get_mut(&item.id) {
+                progress.track_id = Some(track_id.clone());
+            }
+            update_progress(&app_handle, progress_map, item.id, UploadStatus::Complete, None, &item.metadata, &url).await;
+            if let Some(path) = item.temp_delivery_path.take() { cleanup_temp_file(&path); }
+            if let Some(path) = item.temp_archive_path.take() { cleanup_temp_file(&path); }
+            let db = mongo_client.catalog_database();
+            record_activity(&app_handle, Some(&db), ActivityEntry::new(ActivityAction::TracksUploaded, vec![track_id.clone()], "Uploaded 1 track from a URL".to_string())).await;
+            Ok(track_id)
+        }
+        Err(e) => {
+            error!("Upload from {} failed: {}", url, e);
+            update_progress(&app_handle, progress_map, item.id, upload_error_status("Upload from URL failed", &e), Some(e.to_string()), &item.metadata, &url).await;
+            perform_cleanup(&r2_client, &bucket_name, &mongo_client, &item).await;
+            Err(e.to_string())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_source_url_and_length_checks() {
+        assert!(validate_source_url("https://cdn.example.com/masters/take%201.wav").is_ok());
+        assert!(validate_source_url("ftp://example.com/take.wav").is_err());
+        assert!(validate_source_url("file:///etc/passwd").is_err());
+        assert!(validate_source_url("not a url").is_err());
+
+        let url = validate_source_url("https://cdn.example.com/masters/take.wav?sig=abc").unwrap();
+        assert_eq!(source_file_name(&url), "take.wav");
+        assert_eq!(source_file_name(&validate_source_url("https://example.com/").unwrap()), "download");
+
+        assert_eq!(check_content_length(Some(10), 100), Ok(10));
+        assert!(check_content_length(None, 100).is_err());
+        assert!(check_content_length(Some(0), 100).is_err());
+        assert!(check_content_length(Some(101), 100).is_err());
+    }
+}
@@ -59,12 +59,29 @@ pub struct MongoState {
     // Make fields public if they need to be accessed directly from outside the lib crate,
     // otherwise keep them private and provide methods. Keeping private for now.
     pub client: Mutex<Option<mongodb::Client>>, // Make field public
+    pub init_lock: core::client_init::InitLock<CommandError>, // Held while a client is being created
+}
+
+impl MongoState {
+    /// The catalog database of the current client, named by `core::db_config`.
+    pub async fn database(&self) -> Result<mongodb::Database, CommandError> {
+        let client_lock = self.client.lock().await;
+        let client = client_lock.as_ref()
+            .ok_or_else(|| CommandError::Configuration("MongoDB client not initialized".to_string()))?;
+        Ok(core::db_config::CatalogDatabase::catalog_database(client))
+    }
+
+    /// The configured tracks and albums collections of the current client.
+    pub async fn collections(&self) -> Result<(mongodb::Collection<mongodb::bson::Document>, mongodb::Collection<mongodb::bson::Document>), CommandError> {
+        Ok(core::db_config::CatalogCollections::catalog_collections(&self.database().await?))
+    }
 }
 
 /// R2 client state
 pub struct R2State {
     pub client: Mutex<Option<aws_sdk_s3::Client>>, // Make field public
     pub bucket_name: Mutex<Option<String>>, // Make field public
+    pub init_lock: core::client_init::InitLock<CommandError>, // Held while a client is being created
 }
 
 // Re-export CredentialsError for easier access from main.rs
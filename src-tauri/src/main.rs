@@ -6,13 +6,12 @@ use tokio::sync::Mutex;
 use log::{error, info, warn};
 use tauri_plugin_dialog::{DialogExt};
 use std::fs;
-use std::path::{Path, PathBuf};
+use std::path::PathBuf;
 use std::sync::Arc;
 // keyring::Entry moved to credentials.rs
 use tauri::{
     command, AppHandle, State, Manager, Emitter,
 };
-use tokio::sync::mpsc;
 
 // Import modules
 // mod audio; // Moved to features::upload
@@ -42,9 +41,16 @@ fn test_extract_metadata(filePath: String) -> Result<serde_json::Value, String>
 pub use app_lib::error::CommandError;
 pub use app_lib::core;
 use app_lib::{MongoState, R2State}; // Use items from the library crate
+use app_lib::core::database::{classify_connection_error, connection_error, ConnectionFailure};
+use app_lib::core::timing::{with_timeout, TimeoutSettings};
+use app_lib::core::client_init::init_client;
 use app_lib::features::upload::audio::transcode; // Import transcode module
+use app_lib::features::upload::audio::naming::{output_name_for_file, unique_output_names, validate_naming_template};
+use app_lib::features::upload::limits::{check_file_size, split_selected_paths, SelectedFiles};
+use app_lib::core::db_config::{self, CatalogDatabase, DatabaseConfig};
+use app_lib::core::settings::SettingsState;
 use app_lib::features::upload::{ // Corrected path to use app_lib
-    start_upload_queue, cancel_upload_queue, UploadState, UploadQueueItem,
+    start_upload_queue, cancel_upload_queue, UploadState,
 };
 use app_lib::features::credentials::{ // Corrected path to use app_lib
     store_r2_credentials,
@@ -73,16 +79,17 @@ struct TranscodingResult {
 // --- Client Initialization ---
 
 /// Initializes the R2 client and stores it in state if successful.
+/// Concurrent calls wait for the one in flight and reuse its client; `force` replaces an
+/// existing client, e.g. after the credentials changed.
 #[command]
-async fn init_r2_client(r2_state: State<'_, R2State>) -> Result<bool, CommandError> {
-    {
-        let lock = r2_state.client.lock().await;
-        if lock.is_some() {
-            info!("R2 client already initialized, reusing existing client");
-            return Ok(true);
-        }
-    }
+async fn init_r2_client(force: Option<bool>, r2_state: State<'_, R2State>, timeout_settings: State<'_, TimeoutSettings>) -> Result<bool, CommandError> {
+    let r2_state = &*r2_state;
+    init_client(&r2_state.client, &r2_state.init_lock, force.unwrap_or(false), || connect_r2_client(r2_state, &timeout_settings)).await?;
+    Ok(true)
+}
 
+/// Creates and tests an R2 client, storing the bucket name on success.
+async fn connect_r2_client(r2_state: &R2State, timeout_settings: &TimeoutSettings) -> Result<aws_sdk_s3::Client, CommandError> {
     let credentials = get_r2_credentials_proxy().await.map_err(|e| {
         if matches!(e, CommandError::Configuration(_)) {
             CommandError::Configuration("R2 credentials not set. Please configure credentials in Settings.".to_string())
@@ -94,61 +101,78 @@ async fn init_r2_client(r2_state: State<'_, R2State>) -> Result<bool, CommandErr
     info!("Creating new R2 client with account ID: {} and access key: {}",
         credentials.account_id, credentials.access_key_id);
 
-    let endpoint = if !credentials.endpoint.is_empty() {
-        credentials.endpoint.clone()
-    } else {
-        format!("https://{}.r2.cloudflarestorage.com", credentials.account_id)
+    let credentials = core::r2::R2Credentials {
+        account_id: credentials.account_id,
+        bucket_name: credentials.bucket_name,
+        access_key_id: credentials.access_key_id,
+        secret_access_key: credentials.secret_access_key,
+        endpoint: credentials.endpoint,
     };
-
-    let aws_creds = aws_sdk_s3::config::Credentials::new(
-        &credentials.access_key_id, &credentials.secret_access_key, None, None, "r2-credentials"
-    );
-
-    let config = aws_config::defaults(aws_config::BehaviorVersion::latest())
-        .region(aws_sdk_s3::config::Region::new("auto"))
-        .endpoint_url(&endpoint)
-        .credentials_provider(aws_creds)
-        .load().await;
-
-     let s3_config = aws_sdk_s3::config::Builder::from(&config).force_path_style(true).build();
-    let client = aws_sdk_s3::Client::from_conf(s3_config);
-
-    info!("Testing R2 connection (list_buckets)");
-    client.list_buckets().send().await.map_err(|e| {
-        error!("R2 connection test failed (list_buckets): {}", e);
-        CommandError::Storage(format!("R2 connection test failed: {}", e))
-    })?;
-
-    info!("Testing R2 bucket access: {}", credentials.bucket_name);
-     client.list_objects_v2().bucket(&credentials.bucket_name).max_keys(1).send().await
-         .map_err(|e| {
-             error!("R2 bucket access test failed (list_objects_v2): {}", e);
-             CommandError::Storage(format!(
-                 "R2 credentials seem valid but couldn't access bucket '{}': {}",
-                 credentials.bucket_name, e
-             ))
-         })?;
+    let client = with_timeout("r2_client_init", timeout_settings.get().connect(), core::r2::connect_and_verify(&credentials)).await?;
 
     info!("R2 connection and bucket access successful.");
-    let mut client_lock = r2_state.client.lock().await;
-    *client_lock = Some(client);
     let mut bucket_lock = r2_state.bucket_name.lock().await;
     *bucket_lock = Some(credentials.bucket_name);
-    info!("Stored R2 client and bucket name in state.");
-    Ok(true)
+    info!("Storing R2 client and bucket name in state.");
+    Ok(client)
 }
 
-/// Initializes the MongoDB client and stores it in state if successful.
+/// Checks R2 credentials entered in the settings form before they are saved, with the
+/// same checks `init_r2_client` runs. Neither the keychain nor `R2State` is touched.
 #[command]
-async fn init_mongo_client(mongo_state: State<'_, MongoState>) -> Result<bool, CommandError> {
-    {
-        let lock = mongo_state.client.lock().await;
-        if lock.is_some() {
-            info!("MongoDB client already initialized, reusing existing client");
-            return Ok(true);
+async fn validate_r2_credentials(
+    account_id: String,
+    bucket_name: String,
+    access_key_id: String,
+    secret_access_key: String,
+    endpoint: Option<String>,
+    timeout_settings: State<'_, TimeoutSettings>,
+) -> Result<bool, CommandError> {
+    let credentials = core::r2::R2Credentials {
+        account_id: account_id.trim().to_string(),
+        bucket_name: bucket_name.trim().to_string(),
+        access_key_id: access_key_id.trim().to_string(),
+        secret_access_key: secret_access_key.trim().to_string(),
+        endpoint: endpoint.unwrap_or_default(),
+    };
+    for (field, value) in [
+        ("Account ID", &credentials.account_id),
+        ("Bucket name", &credentials.bucket_name),
+        ("Access key ID", &credentials.access_key_id),
+        ("Secret access key", &credentials.secret_access_key),
+    ] {
+        if value.is_empty() {
+            return Err(CommandError::Validation(format!("{} is required", field)));
         }
     }
 
+    info!("Validating R2 credentials for account {} and bucket {}", credentials.account_id, credentials.bucket_name);
+    with_timeout("r2_credentials_check", timeout_settings.get().connect(), core::r2::connect_and_verify(&credentials)).await?;
+    Ok(true)
+}
+
+/// Initializes the MongoDB client and stores it in state if successful.
+/// Concurrent calls wait for the one in flight and reuse its client; `force` replaces an
+/// existing client, e.g. after the connection string or the database settings changed.
+/// Waits for the settings to load, so the first connection uses the configured names.
+#[command]
+async fn init_mongo_client(
+    force: Option<bool>,
+    app_handle: AppHandle,
+    mongo_state: State<'_, MongoState>,
+    settings_state: State<'_, SettingsState>,
+    album_cache: State<'_, features::catalog::album_names::AlbumNameCache>,
+) -> Result<bool, CommandError> {
+    let db_config = settings_state.loaded().await.database;
+    let force = force.unwrap_or(false);
+    init_client(&mongo_state.client, &mongo_state.init_lock, force, || connect_mongo_client(db_config, &album_cache)).await?;
+    core::live_sync::on_mongo_client_ready(&app_handle, force);
+    Ok(true)
+}
+
+/// Creates and tests a MongoDB client, switches to the configured database and
+/// collection names and runs the migrations against them.
+async fn connect_mongo_client(db_config: DatabaseConfig, album_cache: &features::catalog::album_names::AlbumNameCache) -> Result<mongodb::Client, CommandError> {
     let connection_string = get_mongo_credentials_proxy().await.map_err(|e| {
         if matches!(e, CommandError::Configuration(_)) {
             CommandError::Configuration("MongoDB credentials not set. Please configure credentials in Settings.".to_string())
@@ -160,29 +184,73 @@ async fn init_mongo_client(mongo_state: State<'_, MongoState>) -> Result<bool, C
     let client_instance = create_mongodb_client(connection_string).await?;
 
     info!("MongoDB client created and connection tested successfully.");
-    let mut lock = mongo_state.client.lock().await;
-    *lock = Some(client_instance);
-    info!("Stored MongoDB client in state.");
-    Ok(true)
+    info!(
+        "Using database '{}' (tracks: '{}', albums: '{}', cursor batch size {})",
+        db_config.database_name, db_config.tracks_collection, db_config.albums_collection, db_config.cursor_batch_size
+    );
+    db_config::activate(db_config);
+    // A failed migration leaves the catalog usable, just without the fix or index
+    if let Err(e) = features::catalog::migrations::run_migrations(&client_instance.catalog_database(), album_cache).await {
+        warn!("Database migrations failed: {}", e);
+    }
+    info!("Storing MongoDB client in state.");
+    Ok(client_instance)
 }
 
 /// Helper to create and test MongoDB client
 async fn create_mongodb_client(connection_string: String) -> Result<mongodb::Client, CommandError> {
     info!("Attempting to connect to MongoDB...");
+    // Parsing a mongodb+srv:// string already resolves the SRV record
     let client_options = mongodb::options::ClientOptions::parse(&connection_string)
         .await
-        .map_err(|e| CommandError::Configuration(format!("Failed to parse MongoDB connection string: {}", e)))?;
+        .map_err(|e| match classify_connection_error(&e) {
+            Some(ConnectionFailure::DnsSrv) => connection_error(&e),
+            _ => CommandError::Configuration(format!("Failed to parse MongoDB connection string: {}", e)),
+        })?;
 
     let client = mongodb::Client::with_options(client_options)
         .map_err(|e| CommandError::Configuration(format!("Failed to create MongoDB client: {}", e)))?;
 
     // Test connection by listing database names
-    client.list_database_names(None, None).await
-        .map_err(|e| CommandError::Database(format!("Failed to connect to MongoDB: {}", e)))?;
+    client.list_database_names(None, None).await.map_err(|e| connection_error(&e))?;
 
     Ok(client)
 }
 
+fn emit_keychain_access_denied(app_handle: &AppHandle) {
+    if let Some(denied) = features::credentials::KeychainAccessDenied::current() {
+        warn!("Keychain access denied; not asking again for {} seconds.", denied.retry_after_secs);
+        let _ = app_handle.emit("credentials://access-denied", denied);
+    }
+}
+
+/// Asks for keychain access again after the user denied it, without waiting for the
+/// cooldown, and initializes whichever clients aren't up yet. Emits
+/// `credentials://access-denied` again if the prompt is denied once more.
+#[command]
+async fn retry_keychain_access(
+    app_handle: AppHandle,
+    mongo_state: State<'_, MongoState>,
+    r2_state: State<'_, R2State>,
+    timeout_settings: State<'_, TimeoutSettings>,
+    settings_state: State<'_, SettingsState>,
+    album_cache: State<'_, features::catalog::album_names::AlbumNameCache>,
+) -> Result<bool, CommandError> {
+    info!("Retrying keychain access.");
+    features::credentials::backend::clear_access_denial();
+    let result = async {
+        init_mongo_client(None, app_handle.clone(), mongo_state, settings_state, album_cache).await?;
+        let _ = app_handle.emit("mongo-init-success", ());
+        init_r2_client(None, r2_state, timeout_settings).await?;
+        let _ = app_handle.emit("r2-init-success", ());
+        Ok(true)
+    }.await;
+    if let Err(CommandError::KeychainAccessDenied(_)) = &result {
+        emit_keychain_access_denied(&app_handle);
+    }
+    result
+}
+
 // --- Connection Testing Commands ---
 
 /// Test MongoDB connection using stored credentials
@@ -199,8 +267,7 @@ async fn test_mongo_connection(_mongo_state: State<'_, MongoState>) -> Result<bo
 
     let client = create_mongodb_client(connection_string).await?;
 
-    client.list_database_names(None, None).await
-        .map_err(|e| CommandError::Database(format!("MongoDB connection test failed: {}", e)))?;
+    client.list_database_names(None, None).await.map_err(|e| connection_error(&e))?;
 
     info!("MongoDB connection test successful.");
     Ok(true)
@@ -208,9 +275,9 @@ async fn test_mongo_connection(_mongo_state: State<'_, MongoState>) -> Result<bo
 
 /// Test R2 connection using stored credentials
 #[command]
-async fn test_r2_connection(r2_state: State<'_, R2State>) -> Result<bool, CommandError> {
+async fn test_r2_connection(r2_state: State<'_, R2State>, timeout_settings: State<'_, TimeoutSettings>) -> Result<bool, CommandError> {
     info!("Testing R2 connection...");
-    init_r2_client(r2_state).await
+    init_r2_client(None, r2_state, timeout_settings).await
 }
 
 // --- Audio Processing Commands ---
@@ -252,9 +319,10 @@ async fn extract_audio_metadata_batch(file_paths: Vec<String>) -> Result<Vec<ser
     Ok(results)
 }
 
-/// Open file dialog and return selected file paths
+/// Opens the file dialog and returns the picked paths, with the names of files that
+/// have to be renamed before they can be uploaded
 #[command]
-async fn select_audio_files(app_handle: tauri::AppHandle) -> Result<Vec<String>, CommandError> {
+async fn select_audio_files(app_handle: tauri::AppHandle) -> Result<SelectedFiles, CommandError> {
     use std::sync::{mpsc, Arc as StdArc, Mutex as StdMutex};
     use tauri_plugin_dialog::FilePath;
 
@@ -266,19 +334,18 @@ async fn select_audio_files(app_handle: tauri::AppHandle) -> Result<Vec<String>,
         let sender = tx_clone.lock().unwrap();
         match paths_option {
             Some(paths) => {
-                let path_strings: Vec<String> = paths.into_iter()
-                    .filter_map(|fp| fp.as_path().map(|p| p.to_string_lossy().into_owned()))
-                    .collect();
-                let _ = sender.send(Ok(path_strings));
+                // A lossy conversion would queue a path that names no file, so non-UTF-8
+                // names are left out and reported for the user to rename.
+                let _ = sender.send(split_selected_paths(paths.iter().filter_map(|fp| fp.as_path())));
             }
             None => { // User cancelled
-                let _ = sender.send(Ok(Vec::new()));
+                let _ = sender.send(SelectedFiles::default());
             }
         }
     });
 
     rx.recv()
-        .map_err(|e| CommandError::Unexpected(format!("Failed to receive file paths from dialog channel: {}", e)))?
+        .map_err(|e| CommandError::Unexpected(format!("Failed to receive file paths from dialog channel: {}", e)))
 }
 
 /// Get file stats (size, modified date)
@@ -296,22 +363,29 @@ async fn get_file_stats(path: String) -> Result<serde_json::Value, CommandError>
 }
 
 /// Transcode a single audio file to AAC
+/// `name_template` (e.g. `{artist} - {title}`) names the output from the file's tags;
+/// without one, or when a tag is missing, the input's stem is used.
+/// Files over the upload size limit are rejected before transcoding.
 #[command(rename_all = "camelCase")]
 async fn transcode_audio_file(
     input_path_str: String,
     output_dir_str: String,
+    name_template: Option<String>,
+    upload_state: State<'_, Arc<UploadState>>,
 ) -> Result<TranscodingResult, CommandError> {
     info!("Transcoding {} to AAC in directory {}", input_path_str, output_dir_str);
 
     let input_path = PathBuf::from(&input_path_str);
     let output_dir = PathBuf::from(&output_dir_str);
 
-    let file_name = input_path.file_name()
-        .ok_or_else(|| CommandError::Validation(format!("Invalid input file path: {}", input_path_str)))?
-        .to_string_lossy();
-    let stem = Path::new(&*file_name).file_stem().map(|s| s.to_string_lossy()).unwrap_or_default();
-    let output_file_name = format!("{}.aac", stem);
-    let output_path = output_dir.join(output_file_name);
+    if input_path.file_name().is_none() {
+        return Err(CommandError::Validation(format!("Invalid input file path: {}", input_path_str)));
+    }
+    if let Some(template) = &name_template {
+        validate_naming_template(template).map_err(CommandError::Validation)?;
+    }
+    let max_file_size_bytes = upload_state.limits.lock().await.max_file_size_bytes;
+    check_file_size(&input_path, max_file_size_bytes).map_err(|e| e.into_command_error(&input_path_str))?;
 
      if !output_dir.exists() {
          fs::create_dir_all(&output_dir).map_err(|e| {
@@ -319,16 +393,17 @@ async fn transcode_audio_file(
          })?;
      }
 
-    let output_path_clone = output_path.clone();
     let join_handle = tokio::task::spawn_blocking(move || {
-        transcode::transcode_to_aac(&input_path, &output_path_clone) // Use imported module
+        // Reads tags when a template is set, so it runs off the async runtime too
+        let output_path = output_dir.join(output_name_for_file(&input_path, name_template.as_deref(), "aac"));
+        transcode::transcode_to_aac(&input_path, &output_path).map(|()| output_path) // Use imported module
     });
 
-    // Await the join handle to get the Result<(), TranscodingError>
+    // Await the join handle to get the Result<PathBuf, TranscodingError>
     match join_handle.await {
         Ok(transcoding_result) => {
             match transcoding_result {
-                Ok(()) => { // transcode_to_aac succeeded
+                Ok(output_path) => { // transcode_to_aac succeeded
                     Ok(TranscodingResult { output_path: output_path.to_string_lossy().into_owned() })
                 },
                 Err(transcoding_err) => { // transcode_to_aac failed
@@ -344,12 +419,22 @@ async fn transcode_audio_file(
 
 
 /// Transcode multiple audio files to AAC
+/// Outputs are named by `name_template` as in `transcode_audio_file`; inputs that would
+/// get the same name are told apart with a `-N` suffix. Files over the upload size limit
+/// fail without being transcoded.
 #[command]
 async fn transcode_audio_batch(
     file_paths: Vec<String>,
     outputDirStr: String,  // Renamed directly
+    name_template: Option<String>,
+    upload_state: State<'_, Arc<UploadState>>,
 ) -> Result<Vec<TranscodingResult>, CommandError> {
     info!("Starting batch transcoding for {} files to {}", file_paths.len(), &outputDirStr);
+    if let Some(template) = &name_template {
+        validate_naming_template(template).map_err(CommandError::Validation)?;
+    }
+
+    let max_file_size_bytes = upload_state.limits.lock().await.max_file_size_bytes;
 
     let output_dir = PathBuf::from(&outputDirStr);
     if let Err(e) = fs::create_dir_all(&output_dir) {
@@ -357,31 +442,35 @@ async fn transcode_audio_batch(
         error!("{}", err); return Err(err);
     }
 
+    // Named before any task starts, so two inputs never write the same file
+    let output_names = {
+        let file_paths = file_paths.clone();
+        tokio::task::spawn_blocking(move || unique_output_names(&file_paths, name_template.as_deref(), "aac"))
+            .await
+            .map_err(|e| CommandError::Unexpected(format!("Task join error while naming outputs: {}", e)))?
+    };
+
     let mut tasks = Vec::new();
-    for input_path_str in file_paths {
-        let current_output_dir = output_dir.clone();
+    for (input_path_str, output_name) in file_paths.into_iter().zip(output_names) {
+        let output_path = output_dir.join(output_name);
         let input_path_str_clone = input_path_str.clone();
 
         tasks.push(tokio::spawn(async move {
             let input_path = PathBuf::from(&input_path_str_clone);
-            let file_name = match input_path.file_name() {
-                Some(name) => name.to_string_lossy(),
-                None => return Err(CommandError::Validation(format!("Invalid input file path: {}", input_path_str_clone))),
-            };
-            let stem = Path::new(&*file_name).file_stem().map(|s| s.to_string_lossy()).unwrap_or_default();
-            let output_file_name = format!("{}.aac", stem);
-            let output_path = current_output_dir.join(output_file_name);
-            let output_path_clone = output_path.clone();
+            if input_path.file_name().is_none() {
+                return Err(CommandError::Validation(format!("Invalid input file path: {}", input_path_str_clone)));
+            }
+            check_file_size(&input_path, max_file_size_bytes).map_err(|e| e.into_command_error(&input_path_str_clone))?;
 
             let join_handle = tokio::task::spawn_blocking(move || {
-                transcode::transcode_to_aac(&input_path, &output_path_clone) // Use imported module
+                transcode::transcode_to_aac(&input_path, &output_path).map(|()| output_path) // Use imported module
             });
 
-            // Await the join handle to get the Result<(), TranscodingError>
+            // Await the join handle to get the Result<PathBuf, TranscodingError>
             match join_handle.await {
                 Ok(transcoding_result) => {
                     match transcoding_result {
-                        Ok(()) => { // transcode_to_aac succeeded
+                        Ok(output_path) => { // transcode_to_aac succeeded
                             Ok(TranscodingResult { output_path: output_path.to_string_lossy().into_owned() })
                         },
                         Err(transcoding_err) => { // transcode_to_aac failed
@@ -440,6 +529,15 @@ fn ping() -> String {
 
 // Add proxies for credential commands to adapt the error types
 
+/// Keeps a denied keychain prompt distinguishable so callers don't report it as missing
+/// credentials; everything else is a configuration problem.
+fn credentials_error(context: &str, e: app_lib::CredentialsError) -> CommandError {
+    match e {
+        app_lib::CredentialsError::AccessDenied(message) => CommandError::KeychainAccessDenied(message),
+        e => CommandError::Configuration(format!("{}: {}", context, e)),
+    }
+}
+
 // R2 credentials proxy
 #[command]
 async fn store_r2_credentials_proxy(
@@ -451,55 +549,94 @@ async fn store_r2_credentials_proxy(
 ) -> Result<bool, CommandError> {
     features::credentials::store_r2_credentials(
         account_id, bucket_name, access_key_id, secret_access_key, endpoint
-    ).await.map_err(|e| CommandError::Configuration(format!("Failed to store R2 credentials: {}", e)))
+    ).await.map_err(|e| credentials_error("Failed to store R2 credentials", e))
 }
 
 // MongoDB credentials proxy
 #[command]
 async fn store_mongo_credentials_proxy(connection_string: String) -> Result<bool, CommandError> {
     features::credentials::store_mongo_credentials(connection_string)
-        .await.map_err(|e| CommandError::Configuration(format!("Failed to store MongoDB credentials: {}", e)))
+        .await.map_err(|e| credentials_error("Failed to store MongoDB credentials", e))
 }
 
 #[command]
 async fn get_r2_credentials_proxy() -> Result<features::credentials::R2Credentials, CommandError> {
     features::credentials::get_r2_credentials()
-        .await.map_err(|e| CommandError::Configuration(format!("Failed to get R2 credentials: {}", e)))
+        .await.map_err(|e| credentials_error("Failed to get R2 credentials", e))
 }
 
 #[command]
 async fn get_mongo_credentials_proxy() -> Result<String, CommandError> {
     features::credentials::get_mongo_credentials()
-        .await.map_err(|e| CommandError::Configuration(format!("Failed to get MongoDB credentials: {}", e)))
+        .await.map_err(|e| credentials_error("Failed to get MongoDB credentials", e))
 }
 
 #[command]
 async fn has_credentials_proxy(credential_type: String) -> Result<bool, CommandError> {
     features::credentials::has_credentials(credential_type)
-        .await.map_err(|e| CommandError::Configuration(format!("Failed to check credentials: {}", e)))
+        .await.map_err(|e| credentials_error("Failed to check credentials", e))
+}
+
+#[command]
+async fn get_credentials_status() -> Result<features::credentials::CredentialsStatus, CommandError> {
+    features::credentials::get_credentials_status()
+        .await.map_err(|e| credentials_error("Failed to get credentials status", e))
 }
 
 #[command]
 async fn delete_credentials_proxy(credential_type: String) -> Result<(), CommandError> {
     features::credentials::delete_credentials(credential_type)
-        .await.map_err(|e| CommandError::Configuration(format!("Failed to delete credentials: {}", e)))
+        .await.map_err(|e| credentials_error("Failed to delete credentials", e))
+}
+
+#[command]
+async fn get_credential_backend_info_proxy() -> Result<features::credentials::CredentialBackendInfo, CommandError> {
+    features::credentials::get_credential_backend_info()
+        .await.map_err(|e| credentials_error("Failed to get credential backend info", e))
+}
+
+#[command]
+async fn unlock_credential_store_proxy(passphrase: String) -> Result<features::credentials::CredentialBackendInfo, CommandError> {
+    features::credentials::unlock_credential_store(passphrase)
+        .await.map_err(|e| CommandError::Keychain(format!("Failed to unlock credential store: {}", e)))
+}
+
+#[command]
+async fn migrate_file_credentials_to_keychain_proxy(
+    passphrase: Option<String>,
+    delete_file: Option<bool>,
+) -> Result<features::credentials::CredentialMigrationReport, CommandError> {
+    features::credentials::migrate_file_credentials_to_keychain(passphrase, delete_file.unwrap_or(false))
+        .await.map_err(|e| credentials_error("Failed to migrate credentials to the keychain", e))
 }
 
 // --- Main Application Setup ---
 fn main() {
-    // Setup logging
-    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
+    // Setup logging to stderr and the log file
+    core::logging::init_logging();
     info!("Starting Music Library Manager application");
 
-    // Create channel for upload queue
-    let (upload_tx, upload_rx) = mpsc::channel::<UploadQueueItem>(100);
-
     // Initialize Tauri application
     tauri::Builder::default()
         .plugin(tauri_plugin_dialog::init())
-        .manage(MongoState { client: Mutex::new(None) })
-        .manage(R2State { client: Mutex::new(None), bucket_name: Mutex::new(None) })
-        .manage(Arc::new(UploadState::new(upload_tx, upload_rx))) // Wrap state in Arc
+        .manage(MongoState { client: Mutex::new(None), init_lock: Default::default() })
+        .manage(R2State { client: Mutex::new(None), bucket_name: Mutex::new(None), init_lock: Default::default() })
+        .manage(Arc::new(UploadState::new())) // Wrap state in Arc
+        .manage(features::catalog::waveforms::WaveformState::default())
+        .manage(features::catalog::album_names::AlbumNameCache::default())
+        .manage(features::catalog::artwork::AlbumArtManifest::default())
+        .manage(core::presign::PresignedUrlCache::default())
+        .manage(core::presign::PublicUrlBase::default())
+        .manage(TimeoutSettings::default())
+        .manage(core::settings::SettingsState::default())
+        .manage(features::catalog::stream::StreamSourceCache::default())
+        .manage(core::jobs::JobManager::default())
+        .manage(core::live_sync::LiveSyncState::default())
+        .manage(features::devtools::DangerousOperationTokens::default())
+        .register_asynchronous_uri_scheme_protocol(
+            features::catalog::stream::STREAM_SCHEME,
+            features::catalog::stream::handle_stream_request,
+        )
         .invoke_handler(tauri::generate_handler![
             // Credential Commands (now from credentials module)
             // Credential Commands (now from features::credentials module)
@@ -511,11 +648,21 @@ fn main() {
             // features::credentials::delete_credentials,
             // Client Init & Test Commands
             init_r2_client,
+            validate_r2_credentials,
             init_mongo_client,
             test_mongo_connection,
             test_r2_connection,
+            core::database::benchmark_mongo_connections,
+            core::logging::get_log_file_path,
+            core::timing::get_command_timings,
+            core::timing::get_command_timeouts,
+            core::timing::set_command_timeouts,
+            core::settings::get_settings,
+            core::settings::update_settings,
+            core::live_sync::get_sync_status,
             // Audio/File Commands
             features::upload::audio::metadata::extract_metadata, // Updated path
+            features::upload::audio::analysis::analyze_track_features,
             extract_audio_metadata_batch,
             select_audio_files,
             get_file_stats,
@@ -523,13 +670,134 @@ fn main() {
             transcode_audio_batch,
             // MongoDB Commands
             features::catalog::storage::mongodb::fetch_all_tracks,
+            features::catalog::storage::mongodb::search_catalog,
+            features::catalog::storage::mongodb::fetch_album_summaries,
             features::catalog::storage::mongodb::update_track_metadata, // <-- Added update_track_metadata
+            features::catalog::storage::mongodb::get_tracks_by_ids,
+            // Source Relinking Commands
+            features::catalog::relink::relink_tracks,
+            features::catalog::relink::relink_tracks_by_prefix,
+            features::catalog::relink::find_missing_sources,
+            // Album Artwork Commands
+            features::catalog::artwork::get_album_art,
+            features::catalog::artwork::invalidate_album_art,
+            features::catalog::artwork::prefetch_album_art,
+            features::catalog::artwork::upload_album_artwork,
+            // Genre Taxonomy Commands
+            features::catalog::genres::list_genres,
+            features::catalog::genres::rename_genre,
+            features::catalog::genres::merge_genres,
+            features::catalog::vocabulary::get_vocabulary,
+            features::catalog::vocabulary::set_vocabulary,
+            features::catalog::suggestions::suggest_writers,
+            features::catalog::suggestions::suggest_publishers,
+            features::catalog::suggestions::suggest_genres,
+            features::catalog::suggestions::suggest_metadata,
+            features::catalog::distinct::get_distinct_values,
+            features::catalog::export::export_original,
+            features::catalog::export::export_album_originals,
+            features::catalog::backup::backup_catalog,
+            features::catalog::backup::restore_catalog,
+            core::jobs::start_job,
+            core::jobs::list_jobs,
+            core::jobs::get_job,
+            core::jobs::cancel_job,
+            features::catalog::notes::get_track_notes,
+            features::catalog::notes::add_track_note,
+            features::catalog::notes::edit_track_note,
+            features::catalog::notes::delete_track_note,
+            // Preview Clip Commands
+            features::catalog::preview::generate_preview_clip,
+            // Integrity Check Commands
+            features::catalog::integrity::verify_catalog_integrity,
+            features::catalog::reencode::reencode_catalog_previews,
+            features::catalog::replaygain::compute_replaygain,
+            features::catalog::changes::fetch_tracks_updated_since,
+            // Waveform Commands
+            features::catalog::waveforms::regenerate_waveforms,
+            features::catalog::waveforms::cancel_waveform_regeneration,
+            // Storage Cleanup Commands
+            features::catalog::transcoded::purge_transcoded_copies,
+            features::catalog::r2_keys::move_r2_object,
+            features::catalog::stats::get_track_stats,
+            features::catalog::stats::top_tracks,
+            features::catalog::slugs::find_track_by_slug,
+            features::catalog::slugs::find_album_by_slug,
+            // Trash Commands
+            features::catalog::delete_preview::preview_delete,
+            features::catalog::album_merge::merge_duplicate_albums,
+            features::catalog::trash::trash_tracks,
+            features::catalog::trash::restore_tracks,
+            features::catalog::trash::list_trashed_tracks,
+            features::catalog::trash::empty_trash,
+            features::catalog::staging::list_staged_tracks,
+            features::catalog::staging::promote_tracks,
+            features::catalog::staging::reject_staged_tracks,
+            // Metadata Re-extraction Commands
+            features::catalog::reextract::reextract_metadata,
+            // Streaming Commands
+            features::catalog::stream::get_track_stream_uri,
+            // Playlist Commands
+            features::catalog::playlists::create_playlist,
+            features::catalog::playlists::list_playlists,
+            features::catalog::playlists::get_playlist,
+            features::catalog::playlists::add_tracks_to_playlist,
+            features::catalog::playlists::remove_tracks_from_playlist,
+            features::catalog::playlists::reorder_playlist,
+            features::catalog::playlists::delete_playlist,
+            // Activity Feed Commands
+            features::activity::get_activity_log,
+            // R2 Commands
+            core::r2::get_storage_usage,
+            core::presign::get_presigned_url,
+            core::presign::clear_url_cache,
+            core::presign::get_track_url,
+            core::presign::set_public_base_url,
             // Upload Queue Commands
             // Upload Queue Commands (from features::upload)
             features::upload::start_upload_queue,
+            features::upload::upload_and_wait,
+            features::upload::url_ingest::upload_from_url,
             features::upload::cancel_upload_queue,
+            features::upload::drain_upload_queue,
+            features::upload::shutdown::force_quit,
+            features::upload::schedule::schedule_upload_queue,
+            features::upload::schedule::cancel_scheduled_upload,
+            features::upload::pause_upload_queue,
+            features::upload::resume_upload_queue,
+            features::upload::get_upload_queue_state,
+            features::upload::get_pending_uploads,
+            features::upload::remove_pending_upload,
+            features::upload::reorder_pending_uploads,
+            features::upload::quarantine::list_quarantined_items,
+            features::upload::quarantine::requeue_quarantined_items,
+            features::upload::quarantine::dismiss_quarantined_items,
+            features::upload::set_upload_timeout,
+            features::upload::set_upload_concurrency,
+            features::upload::set_upload_key_templates,
+            features::upload::limits::get_upload_limits,
+            features::upload::limits::set_upload_limits,
+            features::upload::limits::get_upload_bandwidth_limit,
+            features::upload::limits::set_upload_bandwidth_limit,
+            features::upload::encryption::get_encryption_settings,
+            features::upload::encryption::set_encryption_settings,
+            features::upload::temp_storage::get_temp_storage_usage,
+            features::upload::temp_storage::clear_temp_storage,
+            // Onboarding Commands
+            features::onboarding::get_setup_status,
+            features::onboarding::complete_setup_step,
             // Debug Commands
             debug_mongo_state,
+            // QA helpers, never registered in release builds
+            #[cfg(debug_assertions)]
+            features::devtools::request_dangerous_operation,
+            #[cfg(debug_assertions)]
+            features::devtools::seed_test_data,
+            #[cfg(debug_assertions)]
+            features::devtools::clear_test_data,
+            #[cfg(debug_assertions)]
+            features::devtools::test_mongodb_collections,
+            features::catalog::album_names::debug_album_name_cache,
             ping, // Add the new ping command here
             // New proxies
             store_r2_credentials_proxy,
@@ -537,7 +805,12 @@ fn main() {
             get_r2_credentials_proxy,
             get_mongo_credentials_proxy,
             has_credentials_proxy,
+            get_credentials_status,
             delete_credentials_proxy,
+            get_credential_backend_info_proxy,
+            unlock_credential_store_proxy,
+            migrate_file_credentials_to_keychain_proxy,
+            retry_keychain_access,
             // New test command
             test_extract_metadata,
             extract_metadata_wrapper,
@@ -547,37 +820,108 @@ fn main() {
             store_mongo_credentials_wrapper,
             store_r2_credentials_wrapper,
         ])
+        .on_window_event(|window, event| {
+            // Closing mid-batch finishes the uploads in flight first; a second close quits now
+            if let tauri::WindowEvent::CloseRequested { api, .. } = event {
+                let upload_state = window.state::<Arc<UploadState>>();
+                if window.label() == "main" {
+                    let decision = features::upload::shutdown::handle_exit_request(window.app_handle(), &upload_state);
+                    if decision == features::upload::shutdown::ExitDecision::Wait {
+                        api.prevent_close();
+                    }
+                }
+            }
+        })
         .setup(|app| {
             info!("Application setup started");
             let app_handle = app.handle().clone();
-            
+            // Before the frontend loads, so no command sees the defaults
+            core::settings::init_settings(&app_handle);
+
             // Use tauri's async_runtime instead of tokio::spawn directly
             tauri::async_runtime::spawn(async move {
+                let upload_state: State<Arc<UploadState>> = app_handle.state();
+                // Before the clients are up, as nothing can be queued until then
+                features::upload::shutdown::load_idempotency_keys(&app_handle, &upload_state).await;
+                if let Err(e) = features::upload::temp_storage::sweep_temp_storage(&app_handle, &upload_state).await {
+                    warn!("Failed to clean up temp storage: {}", e);
+                }
+
                 let mongo_state: State<MongoState> = app_handle.state();
                 let r2_state: State<R2State> = app_handle.state();
+                // Before setup has been completed, missing credentials are expected rather than errors
+                let mut onboarding = features::onboarding::load_onboarding_state(&app_handle);
+                let mut setup_required = false;
+                // A denied keychain prompt is reported once below rather than as two init failures
+                let mut keychain_denied = false;
 
                 info!("Attempting background initialization of MongoDB client...");
-                if let Err(e) = init_mongo_client(mongo_state).await {
+                if let Err(e) = init_mongo_client(None, app_handle.clone(), mongo_state, app_handle.state(), app_handle.state()).await {
                     warn!("Background MongoDB initialization failed: {}", e);
-                    let _ = app_handle.emit("mongo-init-failed", e.to_string());
+                    if matches!(e, CommandError::KeychainAccessDenied(_)) {
+                        keychain_denied = true;
+                    } else if onboarding.onboarding_completed {
+                        let _ = app_handle.emit("mongo-init-failed", e.to_string());
+                    } else {
+                        setup_required = true;
+                    }
                 } else {
                      info!("Background MongoDB initialization successful.");
                      let _ = app_handle.emit("mongo-init-success", ());
                 }
 
                 info!("Attempting background initialization of R2 client...");
-                 if let Err(e) = init_r2_client(r2_state).await {
+                 if let Err(e) = init_r2_client(None, r2_state, app_handle.state()).await {
                      warn!("Background R2 initialization failed: {}", e);
-                     let _ = app_handle.emit("r2-init-failed", e.to_string());
+                     if matches!(e, CommandError::KeychainAccessDenied(_)) {
+                         keychain_denied = true;
+                     } else if onboarding.onboarding_completed {
+                         let _ = app_handle.emit("r2-init-failed", e.to_string());
+                     } else {
+                         setup_required = true;
+                     }
                  } else {
                      info!("Background R2 initialization successful.");
                      let _ = app_handle.emit("r2-init-success", ());
                  }
+
+                // Needs the clients, so runs once they are up; a failure keeps the schedule on disk
+                let upload_state = Arc::clone(upload_state.inner());
+                features::upload::schedule::restore_scheduled_upload(&app_handle, &upload_state).await;
+                features::upload::shutdown::restore_interrupted_uploads(&app_handle, &upload_state).await;
+                tauri::async_runtime::spawn(features::upload::schedule::run_schedule_timer(app_handle.clone(), upload_state));
+
+                if keychain_denied {
+                    emit_keychain_access_denied(&app_handle);
+                } else if setup_required {
+                    info!("Setup has not been completed; asking the frontend to show the setup wizard.");
+                    let _ = app_handle.emit("setup-required", ());
+                } else if !onboarding.onboarding_completed {
+                    // Both clients came up, e.g. an install from before the setup wizard existed
+                    onboarding.onboarding_completed = true;
+                    if let Err(e) = features::onboarding::save_onboarding_state(&app_handle, &onboarding) {
+                        warn!("Failed to record completed setup: {}", e);
+                    }
+                }
             });
             Ok(())
         })
-        .run(tauri::generate_context!())
-        .expect("error while running tauri application");
+        .build(tauri::generate_context!())
+        .expect("error while building tauri application")
+        .run(|app_handle, event| {
+            match event {
+                // Quitting from the menu or dock skips the window close handler
+                tauri::RunEvent::ExitRequested { api, .. } => {
+                    let upload_state = app_handle.state::<Arc<UploadState>>();
+                    let decision = features::upload::shutdown::handle_exit_request(app_handle, &upload_state);
+                    if decision == features::upload::shutdown::ExitDecision::Wait {
+                        api.prevent_exit();
+                    }
+                }
+                tauri::RunEvent::Exit => app_handle.state::<core::live_sync::LiveSyncState>().stop(),
+                _ => {}
+            }
+        });
 
     info!("Application finished");
 }
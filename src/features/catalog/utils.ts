@@ -1,4 +1,5 @@
 import { showSuccessToast, showErrorToast } from '$lib/stores/notifications';
+import type { SelectedFiles } from '$lib/bindings/SelectedFiles';
 
 // Define the type signature for the safeInvoke function locally
 type SafeInvokeFn = <T>(cmd: string, args?: Record<string, unknown>) => Promise<T | null>;
@@ -16,11 +17,13 @@ export async function replaceTrackAudioWorkflow(safeInvoke: SafeInvokeFn, trackI
     let replacementFilePath: string | null = null;
     try {
         console.log("Attempting to select replacement audio file...");
-        // Assuming 'select_audio_files' returns string[] or null
-        const selectedPaths = await safeInvoke<string[]>('select_audio_files');
-        if (selectedPaths && selectedPaths.length > 0) {
-            replacementFilePath = selectedPaths[0];
+        const selection = await safeInvoke<SelectedFiles>('select_audio_files');
+        if (selection && selection.paths.length > 0) {
+            replacementFilePath = selection.paths[0];
             console.log('Replacement file selected:', replacementFilePath);
+        } else if (selection && selection.rejected.length > 0) {
+            showErrorToast(`${selection.rejected[0]} has a name that isn't valid UTF-8; rename it and select it again.`);
+            return false;
         } else {
             console.log('No replacement file selected.');
             showErrorToast('File selection cancelled or failed.');
@@ -0,0 +1,14 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+/**
+ * Overall progress of a processing run, sent as `upload://batch-progress`.
+ */
+export type BatchProgress = { completed: number, failed: number, 
+/**
+ * Items pending when the run started
+ */
+total: number, 
+/**
+ * Share of the items that reached a final status, cancelled and removed ones included
+ */
+percent: number, };
@@ -0,0 +1,15 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+/**
+ * An operation on documents of one collection.
+ */
+export type CatalogChange = { 
+/**
+ * `tracks` or `albums`
+ */
+collection: string, 
+/**
+ * `insert`, `update`, `replace` or `delete`; `refresh`, without ids, when the
+ * affected documents aren't known and everything shown should be reloaded
+ */
+operation: string, ids: Array<string>, };
@@ -0,0 +1,7 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { CatalogChange } from "./CatalogChange";
+
+/**
+ * Payload of `catalog://changed`.
+ */
+export type CatalogChanged = { changes: Array<CatalogChange>, };
@@ -0,0 +1,7 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+/**
+ * Standard error structure for Tauri command results. Serialized as
+ * `{ "code": "Validation", "message": "..." }`, the shape `safeInvoke` reads.
+ */
+export type CommandError = { "code": "Database", "message": string } | { "code": "Storage", "message": string } | { "code": "FileSystem", "message": string } | { "code": "Transcoding", "message": string } | { "code": "Metadata", "message": string } | { "code": "Validation", "message": string } | { "code": "Configuration", "message": string } | { "code": "Keychain", "message": string } | { "code": "KeychainAccessDenied", "message": string } | { "code": "NotFound", "message": string } | { "code": "OriginalNotUploaded", "message": string } | { "code": "Conflict", "message": string } | { "code": "OperationFailed", "message": string } | { "code": "Unexpected", "message": string };
@@ -0,0 +1,15 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { BackendKind } from "./BackendKind";
+
+/**
+ * Which backend holds credentials, for display in Settings.
+ */
+export type CredentialBackendInfo = { backend: BackendKind, 
+/**
+ * Human-readable description of where secrets are stored
+ */
+location: string, 
+/**
+ * True when the encrypted file is in use and no passphrase has been entered yet
+ */
+locked: boolean, };
@@ -0,0 +1,27 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+/**
+ * What `migrate_file_credentials_to_keychain` did, by credential name (`mongo`, `r2`,
+ * `originals_key`).
+ */
+export type CredentialMigrationReport = { 
+/**
+ * Whether there was an encrypted credentials file
+ */
+file_found: boolean, 
+/**
+ * Whether there was a plaintext `dev_credentials.json`
+ */
+dev_file_found: boolean, 
+/**
+ * Copied into the keychain
+ */
+migrated: Array<string>, 
+/**
+ * Already in the keychain with the same value
+ */
+already_in_keychain: Array<string>, 
+/**
+ * In the keychain with a different value, which was kept
+ */
+conflicts: Array<string>, file_deleted: boolean, dev_file_deleted: boolean, };
@@ -0,0 +1,7 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+/**
+ * What is configured, without the R2 secret key or the Mongo connection string, so
+ * the settings screen can show the current setup.
+ */
+export type CredentialsStatus = { mongo_configured: boolean, r2_configured: boolean, r2_account_id: string | null, r2_bucket_name: string | null, r2_endpoint: string | null, };
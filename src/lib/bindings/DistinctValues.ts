@@ -0,0 +1,15 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { JsonValue } from "./serde_json/JsonValue";
+
+/**
+ * Result of `get_distinct_values`.
+ */
+export type DistinctValues = { 
+/**
+ * Numbers ascending, then text ignoring case
+ */
+values: Array<JsonValue>, 
+/**
+ * More values exist than were returned
+ */
+truncated: boolean, };
@@ -0,0 +1,6 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+/**
+ * What went wrong with a failed item, so the frontend knows whether retrying can help.
+ */
+export type ErrorCategory = "Transcode" | "NetworkTransient" | "Auth" | "CorruptInput" | "FileAccess" | "Metadata" | "Unknown";
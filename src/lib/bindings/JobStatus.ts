@@ -0,0 +1,17 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { JobKind } from "./JobKind";
+import type { JobState } from "./JobState";
+import type { JsonValue } from "./serde_json/JsonValue";
+
+/**
+ * Payload of `job://progress` and the result of `get_job`.
+ */
+export type JobStatus = { id: string, kind: JobKind, state: JobState, 
+/**
+ * Units of work done out of `total`, e.g. tracks checked or files exported
+ */
+done: number, total: number, message: string | null, 
+/**
+ * What the job returned, once completed
+ */
+result: JsonValue | null, error: string | null, started_at: string, finished_at: string | null, };
@@ -0,0 +1,6 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+/**
+ * R2 credentials structure
+ */
+export type R2Credentials = { account_id: string, bucket_name: string, access_key_id: string, secret_access_key: string, endpoint: string, };
@@ -0,0 +1,6 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+/**
+ * A track that could not be re-encoded.
+ */
+export type ReencodeFailure = { track_id: string, error: string, };
@@ -0,0 +1,19 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { ReencodeFailure } from "./ReencodeFailure";
+
+/**
+ * Result of a finished `PreviewReencode` job.
+ */
+export type ReencodeReport = { 
+/**
+ * Tracks with an original and a separate preview to re-encode from it
+ */
+total_tracks: number, reencoded: number, 
+/**
+ * Tracks finished by an earlier, interrupted run
+ */
+resumed: number, 
+/**
+ * Tracks whose original is missing from R2
+ */
+skipped_missing_original: Array<string>, failed: Array<ReencodeFailure>, };
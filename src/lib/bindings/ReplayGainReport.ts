@@ -0,0 +1,11 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { ReplayGainFailure } from "./ReplayGainFailure";
+
+/**
+ * Result of a finished `ReplayGain` job.
+ */
+export type ReplayGainReport = { total_tracks: number, measured: number, 
+/**
+ * Tracks that are silent or had no audio ffmpeg could measure; left unchanged
+ */
+unmeasurable: Array<string>, failed: Array<ReplayGainFailure>, };
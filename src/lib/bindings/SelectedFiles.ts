@@ -0,0 +1,11 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+/**
+ * Files picked in the file dialog, split by whether their paths can be queued.
+ */
+export type SelectedFiles = { paths: Array<string>, 
+/**
+ * Names of the files whose path is not valid UTF-8, converted lossily for display;
+ * they have to be renamed before they can be uploaded
+ */
+rejected: Array<string>, };
@@ -0,0 +1,15 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { SyncMode } from "./SyncMode";
+
+/**
+ * Result of `get_sync_status`.
+ */
+export type SyncStatus = { mode: SyncMode, 
+/**
+ * Why sync is degraded or unavailable
+ */
+reason: string | null, 
+/**
+ * When `mode` last changed
+ */
+since: string, };
@@ -0,0 +1,25 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { TrackWithAlbum } from "./TrackWithAlbum";
+
+/**
+ * Result of `fetch_tracks_updated_since`.
+ */
+export type TrackDelta = { 
+/**
+ * Tracks added or changed since `since`, as track listings return them
+ */
+tracks: Array<TrackWithAlbum>, 
+/**
+ * Tracks deleted, moved to the trash or staged for review since `since`, all of
+ * which track listings leave out
+ */
+deleted_ids: Array<string>, 
+/**
+ * Pass as `since` on the next call
+ */
+as_of: string, 
+/**
+ * `since` is older than the tombstones are kept, so nothing else is filled in and the
+ * client should reload everything
+ */
+full_refresh: boolean, };
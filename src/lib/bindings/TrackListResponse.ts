@@ -0,0 +1,4 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { TrackWithAlbum } from "./TrackWithAlbum";
+
+export type TrackListResponse = { success: boolean, message: string | null, tracks: Array<TrackWithAlbum>, total_count: number, };
@@ -0,0 +1,3 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+export type TrackWithAlbum = { id: string, title: string, album_id: string, album_name: string, track_number: number | null, filename: string, duration: number | null, writers: Array<string>, writer_percentages: { [key in string]?: number } | null, publishers: Array<string>, publisher_percentages: { [key in string]?: number } | null, composers: Array<string> | null, genre: Array<string> | null, path: string, waveform_data: Array<number> | null, comments: string | null, custom_fields: { [key in string]?: string } | null, bpm: number | null, musical_key: string | null, loudness_lufs: number | null, replaygain_db: number | null, isrc: string | null, catalog_number: string | null, slug: string | null, };
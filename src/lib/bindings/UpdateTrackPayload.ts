@@ -0,0 +1,3 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+export type UpdateTrackPayload = { title?: string | null, genre?: Array<string> | null, writers?: Array<string> | null, writer_percentages?: { [key in string]?: number } | null, publishers?: Array<string> | null, publisher_percentages?: { [key in string]?: number } | null, instruments?: Array<string> | null, mood?: Array<string> | null, comments?: string | null, custom_fields?: { [key in string]?: string } | null, bpm?: number | null, musical_key?: string | null, composers?: Array<string> | null, isrc?: string | null, catalog_number?: string | null, regenerate_slug: boolean, sync_object_metadata: boolean, };
@@ -0,0 +1,8 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { UploadItemMetadata } from "./UploadItemMetadata";
+
+export type UploadItemInput = { id: string, path: string, metadata: UploadItemMetadata, 
+/**
+ * Client-chosen key; an item whose key was already queued this session is skipped
+ */
+idempotency_key?: string | null, };
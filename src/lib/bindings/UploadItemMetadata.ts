@@ -0,0 +1,41 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+export type UploadItemMetadata = { title?: string | null, artist?: string | null, album?: string | null, track_number?: number | null, duration_sec?: number | null, genre?: string | null, composer?: string | null, year?: number | null, comments?: string | null, 
+/**
+ * Artist credited for the whole album (TPE2/ALBUMARTIST); albums are matched on it
+ * rather than the track artist
+ */
+album_artist?: string | null, 
+/**
+ * Part of a various-artists compilation; compilations are matched on album name alone
+ */
+compilation: boolean, custom_fields?: { [key in string]?: string } | null, 
+/**
+ * All credited composers; `composer` is used when this is unset
+ */
+composers?: Array<string> | null, 
+/**
+ * International Standard Recording Code, validated before the item is queued
+ */
+isrc?: string | null, 
+/**
+ * Label catalog number
+ */
+catalog_number?: string | null, 
+/**
+ * Hex id of the album to file the track under, used with `AlbumMatching::ByExplicitId`
+ */
+album_id?: string | null, 
+/**
+ * Short codec name, e.g. "flac" or "mp3"
+ */
+codec?: string | null, 
+/**
+ * Bits per second; exact for PCM, otherwise averaged over the file
+ */
+bitrate?: number | null, sample_rate?: number | null, channels?: number | null, 
+/**
+ * Local image uploaded as the album's artwork if the album has none yet. Filled in
+ * from a cover image in the file's folder when the tags have no embedded art
+ */
+art_path?: string | null, };
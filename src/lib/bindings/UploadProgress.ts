@@ -0,0 +1,16 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { UploadStatus } from "./UploadStatus";
+
+export type UploadProgress = { item_id: string, original_path: string, status: UploadStatus, error_message: string | null, title: string | null, album: string | null, 
+/**
+ * Id of the stored track, once the item is complete
+ */
+track_id: string | null, 
+/**
+ * Measured upload rate over the last second, while a file is being uploaded
+ */
+throughput_bytes_per_sec: number | null, 
+/**
+ * Where the delivery file was kept, once the item is complete and `keep_transcoded_dir` is set
+ */
+local_delivery_path: string | null, };
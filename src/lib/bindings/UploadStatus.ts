@@ -0,0 +1,8 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { ErrorCategory } from "./ErrorCategory";
+
+/**
+ * Serialized with the variant under `kind`, e.g. `{ "kind": "Transcoding" }` or
+ * `{ "kind": "Error", "category": "Auth", "message": "..." }`.
+ */
+export type UploadStatus = { "kind": "Pending" } | { "kind": "Scheduled" } | { "kind": "Transcoding" } | { "kind": "UploadingOriginal" } | { "kind": "UploadingAAC" } | { "kind": "StoringMetadata" } | { "kind": "Complete" } | { "kind": "Paused" } | { "kind": "Cancelled" } | { "kind": "Removed" } | { "kind": "Skipped" } | { "kind": "Error", category: ErrorCategory, message: string, };
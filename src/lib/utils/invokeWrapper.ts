@@ -1,12 +1,8 @@
 import { invoke } from '@tauri-apps/api/core';
 import { showErrorToast } from '$lib/stores/notifications';
-
-// Interface matching the Rust CommandError structure (simplified)
+// Commands fail with the Rust CommandError, serialized as { code, message }.
 // We mainly care about the message for display purposes.
-interface CommandError {
-  code?: string; // e.g., Database, Storage, Validation
-  message: string;
-}
+import type { CommandError } from '$lib/bindings/CommandError';
 
 /**
  * Wraps the Tauri invoke call to provide consistent error handling.
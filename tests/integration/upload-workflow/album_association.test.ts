@@ -38,7 +38,8 @@ describe('Track-to-Album Association in Upload Workflow', () => {
     createTestFilesIfNeeded();
     
     // Clean any existing test data
-    await invoke('clear_test_data').catch(e => console.log('Clear test data failed, may be first run', e));
+    const confirmationToken = await invoke('request_dangerous_operation', { operation: 'clear_test_data' });
+    await invoke('clear_test_data', { confirmationToken }).catch(e => console.log('Clear test data failed, may be first run', e));
   });
   
   test('should create album from initial upload batch', async () => {